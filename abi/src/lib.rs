@@ -0,0 +1,288 @@
+#![no_std]
+
+//! The user/kernel system call ABI
+//! Everything a user program relies on when it traps into the kernel: call
+//! numbers, error codes, well-known descriptors, the user address-space
+//! layout, and the layout of any structure passed by pointer. The kernel and
+//! Rust user programs both depend on this crate; assembly programs include
+//! `user/abi.inc`, which the tests below keep in step with it.
+//!
+//! Calling convention: the number goes in rax and arguments in rdi, rsi,
+//! rdx. The result comes back in rax: a value, or a negated errno.
+//!
+//! Compatibility rules: existing numbers, error codes, and structure layouts
+//! never change meaning. Adding a call or error code bumps `ABI_VERSION`;
+//! anything that would break an existing program needs a new call number
+//! instead.
+
+/// Revision of this interface, raised whenever something is added
+pub const ABI_VERSION: u32 = 5;
+
+// Call numbers follow the Linux x86_64 ABI so tiny ports need no renumbering
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+/// Open a file: (path, path length, O_* flags) -> fd
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+/// Move a file's cursor: (fd, offset, SEEK_*) -> new offset
+pub const SYS_LSEEK: u64 = 8;
+/// Map zeroed memory: (address hint or 0, length, PROT_*) -> address
+/// Mappings are always anonymous and private; Linux's flags, fd and offset
+/// arguments are ignored.
+pub const SYS_MMAP: u64 = 9;
+/// Unmap whole pages: (address, length) -> 0
+pub const SYS_MUNMAP: u64 = 11;
+/// Move the end of the heap: (new end, or 0 to ask) -> the end now
+pub const SYS_BRK: u64 = 12;
+/// Make `new` name what `old` does, closing it first: (old, new) -> new
+pub const SYS_DUP2: u64 = 33;
+/// Start a copy of the calling program: () -> the copy's id, and 0 in the copy
+/// The copy runs to completion before the call returns in the caller.
+pub const SYS_FORK: u64 = 57;
+pub const SYS_EXIT: u64 = 60;
+
+// Calls of wflos's own start at 1000, clear of Linux's numbers
+
+/// Create a message port: (name, name length) -> port id
+pub const SYS_PORT_CREATE: u64 = 1000;
+/// Find a port by name: (name, name length) -> port id
+pub const SYS_PORT_LOOKUP: u64 = 1001;
+/// Queue a message, waiting while the port is full: (port, buf, len) -> 0
+pub const SYS_PORT_SEND: u64 = 1002;
+/// Take the oldest message, waiting while the port is empty:
+/// (port, buf, len) -> message length
+pub const SYS_PORT_RECEIVE: u64 = 1003;
+/// Destroy a port the program created: (port) -> 0
+pub const SYS_PORT_DESTROY: u64 = 1004;
+
+// Error codes, returned negated (Linux values)
+
+pub const EPERM: i64 = 1;
+pub const ENOENT: i64 = 2;
+pub const EIO: i64 = 5;
+pub const EBADF: i64 = 9;
+pub const EAGAIN: i64 = 11;
+pub const ENOMEM: i64 = 12;
+pub const EFAULT: i64 = 14;
+pub const EBUSY: i64 = 16;
+pub const EEXIST: i64 = 17;
+pub const ENOTDIR: i64 = 20;
+pub const EISDIR: i64 = 21;
+pub const EINVAL: i64 = 22;
+pub const EMFILE: i64 = 24;
+pub const ENOSPC: i64 = 28;
+pub const ESPIPE: i64 = 29;
+pub const EROFS: i64 = 30;
+pub const ENOSYS: i64 = 38;
+pub const ENOTEMPTY: i64 = 39;
+pub const EMSGSIZE: i64 = 90;
+
+// Files
+
+/// `open` access modes, in the low two bits of its flags
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+/// Create the file if it doesn't exist
+pub const O_CREAT: u64 = 0o100;
+/// Empty the file first (when opened for writing)
+pub const O_TRUNC: u64 = 0o1000;
+/// Write at the end of the file each time
+pub const O_APPEND: u64 = 0o2000;
+
+/// `lseek` offsets count from the start, the cursor, or the end
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// Longest path `open` takes, in bytes; paths are absolute
+pub const PATH_MAX: u64 = 256;
+
+// Memory
+
+/// `mmap` protection: readable (always, if mapped at all), writable, executable
+pub const PROT_NONE: u64 = 0;
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+// Message ports
+
+/// Longest message a port carries, in bytes
+pub const PORT_MESSAGE_MAX: u64 = 256;
+/// Longest port name, in bytes
+pub const PORT_NAME_MAX: u64 = 32;
+
+/// Largest errno; results in -MAX_ERRNO..=-1 are errors, as on Linux
+pub const MAX_ERRNO: i64 = 4095;
+
+// File descriptors open in every process
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+// User address-space layout
+
+/// Load address of flat binaries (entry point is the first byte)
+pub const USER_CODE_BASE: u64 = 0x40_0000;
+/// Top of the initial user stack (grows down)
+pub const USER_STACK_TOP: u64 = 0x7FFF_FFFF_F000;
+/// Where `mmap` places mappings without a usable hint; the heap grows from
+/// the end of the program up to here
+pub const USER_MMAP_BASE: u64 = 0x1000_0000_0000;
+/// First address above the canonical lower (user) half
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Encode `errno` as a system call result
+pub const fn error(errno: i64) -> u64 {
+    (-errno) as u64
+}
+
+/// Split a system call result into its value or errno
+pub const fn decode(result: u64) -> Result<u64, i64> {
+    let signed = result as i64;
+    if signed < 0 && signed >= -MAX_ERRNO {
+        Err(-signed)
+    } else {
+        Ok(result)
+    }
+}
+
+/// Pin a structure's size and alignment at compile time
+/// Every `#[repr(C)]` type passed across the boundary gets one, so a field
+/// change that moves the layout fails the build instead of a user program.
+#[macro_export]
+macro_rules! assert_layout {
+    ($ty:ty, size = $size:expr, align = $align:expr) => {
+        const _: () = assert!(core::mem::size_of::<$ty>() == $size);
+        const _: () = assert!(core::mem::align_of::<$ty>() == $align);
+    };
+}
+
+// Published values; changing any of these breaks existing programs
+const _: () = assert!(SYS_READ == 0 && SYS_WRITE == 1 && SYS_EXIT == 60);
+const _: () = assert!(EBADF == 9 && EFAULT == 14 && ENOSYS == 38);
+const _: () = assert!(STDIN == 0 && STDOUT == 1 && STDERR == 2);
+const _: () = assert!(USER_CODE_BASE == 0x40_0000);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The NASM mirror of this crate
+    static ABI_INC: &str = include_str!("../../user/abi.inc");
+
+    /// Value of `name equ value` in abi.inc
+    fn inc_value(name: &str) -> Option<u64> {
+        ABI_INC.lines().find_map(|line| {
+            let line = line.split(';').next()?;
+            let mut words = line.split_whitespace();
+            if words.next()? != name || words.next()? != "equ" {
+                return None;
+            }
+            let value = words.next()?;
+            match value.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+                None => value.parse().ok(),
+            }
+        })
+    }
+
+    #[test]
+    fn test_error_round_trip() {
+        assert_eq!(decode(error(EBADF)), Err(EBADF));
+        assert_eq!(decode(error(MAX_ERRNO)), Err(MAX_ERRNO));
+        assert_eq!(decode(42), Ok(42));
+        assert_eq!(decode(0), Ok(0));
+    }
+
+    #[test]
+    fn test_large_results_are_not_errors() {
+        // Only the top 4095 values are errno; addresses below stay values
+        assert_eq!(decode(error(MAX_ERRNO + 1)), Ok(error(MAX_ERRNO + 1)));
+        assert_eq!(decode(USER_SPACE_END), Ok(USER_SPACE_END));
+    }
+
+    #[test]
+    fn test_assembly_include_matches() {
+        let expected = [
+            ("ABI_VERSION", ABI_VERSION as u64),
+            ("SYS_READ", SYS_READ),
+            ("SYS_WRITE", SYS_WRITE),
+            ("SYS_OPEN", SYS_OPEN),
+            ("SYS_CLOSE", SYS_CLOSE),
+            ("SYS_LSEEK", SYS_LSEEK),
+            ("SYS_DUP2", SYS_DUP2),
+            ("SYS_MMAP", SYS_MMAP),
+            ("SYS_MUNMAP", SYS_MUNMAP),
+            ("SYS_BRK", SYS_BRK),
+            ("PROT_NONE", PROT_NONE),
+            ("PROT_READ", PROT_READ),
+            ("PROT_WRITE", PROT_WRITE),
+            ("PROT_EXEC", PROT_EXEC),
+            ("USER_MMAP_BASE", USER_MMAP_BASE),
+            ("SYS_FORK", SYS_FORK),
+            ("SYS_EXIT", SYS_EXIT),
+            ("EBADF", EBADF as u64),
+            ("EFAULT", EFAULT as u64),
+            ("ENOSYS", ENOSYS as u64),
+            ("EAGAIN", EAGAIN as u64),
+            ("ENOMEM", ENOMEM as u64),
+            ("EPERM", EPERM as u64),
+            ("ENOENT", ENOENT as u64),
+            ("EEXIST", EEXIST as u64),
+            ("EINVAL", EINVAL as u64),
+            ("ENOSPC", ENOSPC as u64),
+            ("EMSGSIZE", EMSGSIZE as u64),
+            ("EIO", EIO as u64),
+            ("EBUSY", EBUSY as u64),
+            ("ENOTDIR", ENOTDIR as u64),
+            ("EISDIR", EISDIR as u64),
+            ("EMFILE", EMFILE as u64),
+            ("ESPIPE", ESPIPE as u64),
+            ("EROFS", EROFS as u64),
+            ("ENOTEMPTY", ENOTEMPTY as u64),
+            ("O_RDONLY", O_RDONLY),
+            ("O_WRONLY", O_WRONLY),
+            ("O_RDWR", O_RDWR),
+            ("O_CREAT", O_CREAT),
+            ("O_TRUNC", O_TRUNC),
+            ("O_APPEND", O_APPEND),
+            ("SEEK_SET", SEEK_SET),
+            ("SEEK_CUR", SEEK_CUR),
+            ("SEEK_END", SEEK_END),
+            ("PATH_MAX", PATH_MAX),
+            ("SYS_PORT_CREATE", SYS_PORT_CREATE),
+            ("SYS_PORT_LOOKUP", SYS_PORT_LOOKUP),
+            ("SYS_PORT_SEND", SYS_PORT_SEND),
+            ("SYS_PORT_RECEIVE", SYS_PORT_RECEIVE),
+            ("SYS_PORT_DESTROY", SYS_PORT_DESTROY),
+            ("PORT_MESSAGE_MAX", PORT_MESSAGE_MAX),
+            ("PORT_NAME_MAX", PORT_NAME_MAX),
+            ("STDIN", STDIN),
+            ("STDOUT", STDOUT),
+            ("STDERR", STDERR),
+            ("USER_CODE_BASE", USER_CODE_BASE),
+            ("USER_STACK_TOP", USER_STACK_TOP),
+        ];
+        for (name, value) in expected {
+            assert_eq!(inc_value(name), Some(value), "abi.inc: {}", name);
+        }
+    }
+
+    #[repr(C)]
+    struct Example {
+        ptr: u64,
+        len: u32,
+    }
+
+    assert_layout!(Example, size = 16, align = 8);
+
+    #[test]
+    fn test_assert_layout_compiles() {
+        let example = Example { ptr: 0, len: 0 };
+        assert_eq!(example.ptr + example.len as u64, 0);
+    }
+}
@@ -0,0 +1,46 @@
+#!/bin/sh
+# Cargo runner for the kernel's test build (`make test-integration`)
+# Boots the test binary cargo passes in from a scratch ISO, with serial on
+# stdout and QEMU's isa-debug-exit device for the kernel to report through
+# (see kernel/src/testing.rs), and exits 0 only if every test passed.
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+LIMINE="$ROOT/build_limine"
+# Generous for TCG on an ARM64 host
+TIMEOUT=${TEST_TIMEOUT:-300}
+
+if [ ! -d "$LIMINE" ]; then
+	echo "$0: build_limine is missing, run 'make limine-utility' first" >&2
+	exit 1
+fi
+
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+mkdir -p "$WORK/iso_root/boot/limine" "$WORK/iso_root/EFI/BOOT"
+cp "$1" "$WORK/iso_root/boot/kernel"
+cp "$ROOT/limine.conf" "$WORK/iso_root/boot/limine/"
+cp "$ROOT"/user/*.bin "$WORK/iso_root/boot/"
+cp "$LIMINE/limine-bios.sys" "$LIMINE/limine-bios-cd.bin" "$LIMINE/limine-uefi-cd.bin" "$WORK/iso_root/boot/limine/"
+cp "$LIMINE/BOOTX64.EFI" "$LIMINE/BOOTIA32.EFI" "$WORK/iso_root/EFI/BOOT/"
+xorriso -as mkisofs -b boot/limine/limine-bios-cd.bin \
+	-no-emul-boot -boot-load-size 4 -boot-info-table \
+	--efi-boot boot/limine/limine-uefi-cd.bin \
+	-efi-boot-part --efi-boot-image --protective-msdos-label \
+	"$WORK/iso_root" -o "$WORK/test.iso" 2>/dev/null || exit 1
+"$LIMINE/limine" bios-install "$WORK/test.iso" 2>/dev/null || exit 1
+
+timeout "$TIMEOUT" qemu-system-x86_64 -cdrom "$WORK/test.iso" \
+	-serial stdio \
+	-display none \
+	-no-reboot \
+	-m 256M \
+	-device isa-debug-exit,iobase=0xf4,iosize=0x04
+STATUS=$?
+
+# The device makes QEMU exit with (code << 1) | 1
+case $STATUS in
+	33) exit 0 ;;
+	35) exit 1 ;;
+	124) echo "$0: no result after ${TIMEOUT}s" >&2; exit 1 ;;
+	*) echo "$0: QEMU exited with status $STATUS before the tests finished" >&2; exit 1 ;;
+esac
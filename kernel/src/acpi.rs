@@ -0,0 +1,45 @@
+//! ACPI tables
+//! Finds tables through the RSDP that Limine passes; `shared::acpi` decodes
+//! them. The firmware keeps tables in ACPI reclaimable or NVS memory, which
+//! the frame allocator never hands out, so they are read in place through
+//! the HHDM for as long as the kernel runs.
+
+use crate::bootinfo;
+use crate::memory::{self, PhysAddr};
+use shared::acpi::{self, Rsdp};
+
+/// Larger than any table firmware ships; guards against a corrupt length
+const MAX_TABLE_LEN: usize = 1 << 20;
+
+/// `len` bytes of physical memory at `phys`
+fn physical(phys: u64, len: usize) -> &'static [u8] {
+    let virt = memory::phys_to_virt(PhysAddr::new(phys));
+    unsafe { core::slice::from_raw_parts(virt.as_ptr::<u8>(), len) }
+}
+
+fn rsdp() -> Option<Rsdp> {
+    Rsdp::parse(physical(bootinfo::get().rsdp?, Rsdp::MAX_LEN))
+}
+
+/// The table at physical address `phys`, if its length and checksum are sound
+pub fn table_at(phys: u64) -> Option<&'static [u8]> {
+    if phys == 0 {
+        return None;
+    }
+    let (_, length) = acpi::header(physical(phys, acpi::HEADER_LEN))?;
+    if !(acpi::HEADER_LEN..=MAX_TABLE_LEN).contains(&length) {
+        return None;
+    }
+    let table = physical(phys, length);
+    acpi::valid(table).then_some(table)
+}
+
+/// The first table the root table lists with `signature`, e.g. `b"FACP"`
+pub fn find(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp = rsdp()?;
+    let root = match rsdp.xsdt {
+        0 => table_at(rsdp.rsdt as u64)?,
+        xsdt => table_at(xsdt)?,
+    };
+    acpi::entries(root, rsdp.xsdt != 0).filter_map(table_at).find(|table| &table[..4] == signature)
+}
@@ -1 +1,180 @@
+//! Architecture abstraction
+//! Code outside `arch` reaches the hardware through these traits, implemented
+//! by the backend for the target architecture. `Arch` names that backend, so
+//! portable code calls e.g. `Arch::wait_for_interrupt()` with the trait in scope
+//! and never uses inline assembly or a backend module directly.
+//!
+//! Boot-time setup (descriptor tables, interrupt routing, AP startup) stays
+//! backend-specific and is driven from `main`.
+
+#[cfg(target_arch = "x86_64")]
 pub mod x86_64;
+
+#[cfg(target_arch = "x86_64")]
+pub type Arch = x86_64::X86_64;
+#[cfg(target_arch = "x86_64")]
+pub const MAX_CPUS: usize = x86_64::smp::MAX_CPUS;
+
+use crate::memory::{PhysAddr, VirtAddr};
+use shared::backtrace::FrameLayout;
+use shared::mmio::Volatile;
+
+/// Control of the current CPU
+pub trait Cpu {
+    /// True if maskable interrupts are enabled on this CPU
+    fn interrupts_enabled() -> bool;
+    fn enable_interrupts();
+    fn disable_interrupts();
+
+    /// Sleep until the next interrupt arrives
+    fn wait_for_interrupt();
+
+    /// Enable interrupts and sleep until one arrives, with no gap between the
+    /// two for an interrupt to slip through and leave the CPU asleep
+    fn enable_interrupts_and_wait();
+
+    /// Index of this CPU, 0 for the boot CPU
+    fn cpu_id() -> usize;
+
+    /// How stack frames link together, for walking them
+    const FRAME_LAYOUT: FrameLayout;
+
+    /// Frame pointer of the calling function
+    fn frame_pointer() -> u64;
+
+    /// A random word from the CPU's own generator, if it has one with a
+    /// value ready
+    fn hardware_random() -> Option<u64>;
+
+    /// Run `f` with interrupts disabled, restoring the previous state afterwards
+    fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+        let were_enabled = Self::interrupts_enabled();
+        if were_enabled {
+            Self::disable_interrupts();
+        }
+        let result = f();
+        if were_enabled {
+            Self::enable_interrupts();
+        }
+        result
+    }
+
+    /// Stop this CPU for good
+    fn halt() -> ! {
+        loop {
+            Self::disable_interrupts();
+            Self::wait_for_interrupt();
+        }
+    }
+}
+
+/// Routing of device interrupt lines
+pub trait InterruptController {
+    fn enable_irq(irq: u8);
+    #[allow(dead_code)]
+    fn disable_irq(irq: u8);
+    /// Acknowledge `irq` so the line can fire again
+    fn end_of_interrupt(irq: u8);
+}
+
+/// The periodic system tick, delivered to `time::handle_tick`
+pub trait Timer {
+    /// Start ticking at `hz`
+    fn start_timer(hz: u32);
+    /// Nanoseconds since the current tick fired, as finely as the timer counts
+    /// Called first thing in the tick handler, this is the interrupt latency.
+    fn nanos_since_tick() -> u64;
+    /// Ticks the interrupt being handled stands for: 1, or more once
+    /// `set_tick_period` stretched the tick; called before `nanos_since_tick`
+    fn ticks_elapsed() -> u64;
+    /// Make the tick interrupts after the next one come every `ticks` ticks
+    /// (1 for the periodic tick); returns the period actually in effect, which
+    /// hardware limits can keep shorter or unchanged
+    fn set_tick_period(ticks: u64) -> u64;
+    /// Acknowledge the tick so the next one can fire
+    fn end_of_tick();
+    /// Free-running cycle counter, calibrated against the tick by `time`
+    /// unless `cycles_hz` knows its frequency
+    fn read_cycles() -> u64;
+    /// The cycle counter's frequency, if the hardware states it
+    fn cycles_hz() -> Option<u64>;
+    /// True if the cycle counter keeps a constant rate, even in idle states
+    fn cycles_invariant() -> bool;
+}
+
+/// Address translation
+pub trait Mmu {
+    /// Root of the active page table
+    fn active_table() -> PhysAddr;
+
+    /// Switch to another page table (also flushes non-global TLB entries)
+    ///
+    /// # Safety
+    /// The table must map the currently executing code, stack, and HHDM.
+    unsafe fn switch_table(root: PhysAddr);
+
+    /// Drop any cached translation of the page holding `virt` on this CPU,
+    /// after its entry in the active table changed
+    fn flush_page(virt: VirtAddr);
+
+    /// True if the hardware enforces non-executable mappings
+    fn nx_enabled() -> bool;
+}
+
+/// Transfer between kernel and user mode
+pub trait ContextSwitch {
+    /// Run user code at `entry` on `user_stack` in the active address space
+    /// Returns once the program calls `exit_user`, with the code it passed.
+    ///
+    /// # Safety
+    /// `entry` and `user_stack` must be mapped user-accessible, and only one
+    /// user context may be active at a time.
+    unsafe fn enter_user(entry: VirtAddr, user_stack: VirtAddr) -> i64;
+
+    /// Abandon the running user program, making `enter_user` return `code`
+    ///
+    /// # Safety
+    /// Must be called from a system call made by the program `enter_user` started.
+    unsafe fn exit_user(code: i64) -> !;
+}
+
+/// Device register access
+/// Port I/O only exists on some architectures; elsewhere the backend maps
+/// the legacy port space (PCI I/O BARs) onto MMIO.
+pub trait PortIo {
+    /// # Safety
+    /// Port access can have arbitrary side effects on devices.
+    unsafe fn inb(port: u16) -> u8;
+    /// # Safety
+    /// See `inb`.
+    unsafe fn outb(port: u16, value: u8);
+    /// # Safety
+    /// See `inb`.
+    unsafe fn inw(port: u16) -> u16;
+    /// # Safety
+    /// See `inb`.
+    unsafe fn outw(port: u16, value: u16);
+    /// # Safety
+    /// See `inb`.
+    unsafe fn inl(port: u16) -> u32;
+    /// # Safety
+    /// See `inb`.
+    unsafe fn outl(port: u16, value: u32);
+
+    /// Read a memory-mapped device register
+    ///
+    /// # Safety
+    /// `addr` must be a mapped, suitably aligned device register.
+    #[allow(dead_code)]
+    unsafe fn mmio_read<T: Copy>(addr: VirtAddr) -> T {
+        Volatile::from_ptr(addr.as_mut_ptr::<T>()).read()
+    }
+
+    /// Write a memory-mapped device register
+    ///
+    /// # Safety
+    /// See `mmio_read`.
+    unsafe fn mmio_write<T: Copy>(addr: VirtAddr, value: T) {
+        Volatile::from_ptr(addr.as_mut_ptr::<T>()).write(value)
+    }
+}
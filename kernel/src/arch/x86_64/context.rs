@@ -0,0 +1,188 @@
+//! Ring 3 entry and exit
+//! `enter_user` saves the kernel's callee-saved registers on its own stack and
+//! `iretq`s to user mode; the `exit` syscall later unwinds to that frame with
+//! `resume_kernel`, so `enter_user` appears to return the exit code.
+//! `enter_fork` does the same for a forked copy of a program, starting it
+//! from the registers of the parent's system call.
+//! `run_on_stack` moves the boot CPU off the stack Limine started it on.
+
+use super::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+use super::{fpu, smp};
+use super::syscall::SyscallFrame;
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Room left below `enter_fork`'s stack pointer for what `enter_frame`
+/// pushes before the child's kernel entries start
+const FORK_STACK_GAP: u64 = 256;
+
+// Kernel stack pointer saved by `enter_user`, restored by `exit_user`
+static KERNEL_RESUME_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// Run user code until it exits, returning its exit code
+pub unsafe fn enter(entry: u64, user_rsp: u64) -> i64 {
+    fpu::start_program();
+    enter_user(entry, user_rsp, KERNEL_RESUME_RSP.as_ptr())
+}
+
+/// Resume the kernel after `enter`, which returns `code`
+pub unsafe fn exit(code: i64) -> ! {
+    resume_kernel(KERNEL_RESUME_RSP.load(Ordering::Relaxed), code)
+}
+
+/// Run a copy of the program that made the system call `frame` was saved
+/// from, seeing the call return 0, until it exits; returns its exit code
+/// The copy enters the kernel on the stack below this call, leaving the
+/// parent's system call intact above it, and its `exit` unwinds to here
+/// rather than to the parent's `enter`.
+pub unsafe fn enter_fork(frame: &SyscallFrame) -> i64 {
+    let mut child = frame.clone();
+    child.rax = 0;
+
+    let cpu = smp::current();
+    let parent_stack = cpu.kernel_stack();
+    let parent_resume = KERNEL_RESUME_RSP.load(Ordering::Relaxed);
+    let rsp: u64;
+    core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    cpu.set_kernel_stack(((rsp - FORK_STACK_GAP) & !0xF) as usize);
+
+    fpu::enter_child();
+    let code = enter_frame(&child, KERNEL_RESUME_RSP.as_ptr());
+    fpu::resume_parent();
+
+    KERNEL_RESUME_RSP.store(parent_resume, Ordering::Relaxed);
+    cpu.set_kernel_stack(parent_stack);
+    code
+}
+
+/// Save callee-saved state, then `iretq` to ring 3
+/// Returns (through `resume_kernel`) the process exit code.
+#[unsafe(naked)]
+unsafe extern "C" fn enter_user(_entry: u64, _user_rsp: u64, _saved_rsp: *mut u64) -> i64 {
+    core::arch::naked_asm!(
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdx], rsp",
+        // Kernel GS base moves to KERNEL_GS_BASE while in user mode
+        "swapgs",
+        "push {user_ss}",
+        "push rsi",
+        "push 0x202", // RFLAGS: IF set
+        "push {user_cs}",
+        "push rdi",
+        // Don't leak kernel register contents into user mode
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+        user_ss = const USER_DATA_SELECTOR,
+        user_cs = const USER_CODE_SELECTOR,
+    );
+}
+
+/// Save callee-saved state like `enter_user`, then `iretq` to ring 3 with
+/// every register as `frame` holds it
+#[unsafe(naked)]
+unsafe extern "C" fn enter_frame(_frame: *const SyscallFrame, _saved_rsp: *mut u64) -> i64 {
+    core::arch::naked_asm!(
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rsi], rsp",
+        "swapgs",
+        "push {user_ss}",
+        "push qword ptr [rdi + {rsp}]",
+        "push qword ptr [rdi + {rflags}]",
+        "push {user_cs}",
+        "push qword ptr [rdi + {rip}]",
+        "mov rax, [rdi + {rax}]",
+        "mov rbx, [rdi + {rbx}]",
+        "mov rbp, [rdi + {rbp}]",
+        "mov r12, [rdi + {r12}]",
+        "mov r13, [rdi + {r13}]",
+        "mov r14, [rdi + {r14}]",
+        "mov r15, [rdi + {r15}]",
+        "mov rsi, [rdi + {rsi}]",
+        "mov rdx, [rdi + {rdx}]",
+        "mov r10, [rdi + {r10}]",
+        "mov r8, [rdi + {r8}]",
+        "mov r9, [rdi + {r9}]",
+        // What sysretq would have left in rcx and r11
+        "mov rcx, [rdi + {rip}]",
+        "mov r11, [rdi + {rflags}]",
+        "mov rdi, [rdi + {rdi}]",
+        "iretq",
+        user_ss = const USER_DATA_SELECTOR,
+        user_cs = const USER_CODE_SELECTOR,
+        rax = const offset_of!(SyscallFrame, rax),
+        rbx = const offset_of!(SyscallFrame, rbx),
+        rbp = const offset_of!(SyscallFrame, rbp),
+        r12 = const offset_of!(SyscallFrame, r12),
+        r13 = const offset_of!(SyscallFrame, r13),
+        r14 = const offset_of!(SyscallFrame, r14),
+        r15 = const offset_of!(SyscallFrame, r15),
+        rdi = const offset_of!(SyscallFrame, rdi),
+        rsi = const offset_of!(SyscallFrame, rsi),
+        rdx = const offset_of!(SyscallFrame, rdx),
+        r10 = const offset_of!(SyscallFrame, r10),
+        r8 = const offset_of!(SyscallFrame, r8),
+        r9 = const offset_of!(SyscallFrame, r9),
+        rip = const offset_of!(SyscallFrame, rip),
+        rflags = const offset_of!(SyscallFrame, rflags),
+        rsp = const offset_of!(SyscallFrame, rsp),
+    );
+}
+
+/// Unwind to the frame saved by `enter_user`, making it return `code`
+#[unsafe(naked)]
+unsafe extern "C" fn resume_kernel(_saved_rsp: u64, _code: i64) -> ! {
+    core::arch::naked_asm!(
+        "mov rsp, rdi",
+        "mov rax, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+    );
+}
+
+/// Continue on the stack ending at `top` by calling `entry`, abandoning the current stack
+///
+/// # Safety
+/// `top` must be the 16-byte aligned top of a mapped, otherwise unused stack.
+pub unsafe fn run_on_stack(top: u64, entry: extern "C" fn() -> !) -> ! {
+    switch_stack(top, entry)
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stack(_top: u64, _entry: extern "C" fn() -> !) -> ! {
+    core::arch::naked_asm!(
+        "mov rsp, rdi",
+        // End the frame pointer chain for backtraces
+        "xor ebp, ebp",
+        "call rsi",
+        "ud2",
+    );
+}
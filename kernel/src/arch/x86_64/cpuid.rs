@@ -0,0 +1,35 @@
+//! CPUID instruction
+//! Decoding lives in `shared::cpuid`; this only executes the instruction.
+
+use core::arch::asm;
+use shared::cpuid::{CpuFeatures, CpuInfo};
+
+/// CPUID leaf `leaf`, subleaf `subleaf`, returning [EAX, EBX, ECX, EDX]
+pub fn cpuid(leaf: u32, subleaf: u32) -> [u32; 4] {
+    let (eax, ecx, edx): (u32, u32, u32);
+    let rbx: u64;
+    unsafe {
+        // LLVM reserves rbx, which CPUID clobbers
+        asm!(
+            "mov {saved:r}, rbx",
+            "cpuid",
+            "xchg {saved:r}, rbx",
+            saved = out(reg) rbx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    [eax, rbx as u32, ecx, edx]
+}
+
+/// Identify the processor this code runs on
+pub fn info() -> CpuInfo {
+    CpuInfo::read(cpuid)
+}
+
+/// The features of this processor the kernel checks before using
+pub fn features() -> CpuFeatures {
+    info().cpu_features()
+}
@@ -0,0 +1,159 @@
+//! x87/SSE state of user programs
+//! The kernel is built soft-float and never touches these registers, so
+//! interrupts and system calls need not save them; only a switch between
+//! programs does. `init` lets SSE run (CR4.OSFXSR) on each CPU, and every
+//! program starts from the power-on state. AVX stays off (no XSAVE), so the
+//! 512-byte FXSAVE area holds all of it.
+//!
+//! A forked child inherits its parent's registers, so fork saves nothing up
+//! front: it sets CR0.TS, and only if the child then uses the FPU does the
+//! #NM that raises save the registers for the parents waiting on it. A
+//! parent whose state was saved gets it back when its child exits.
+
+use super::cpuid;
+use crate::process::fork::MAX_DEPTH;
+use crate::sync::spinlock::Spinlock;
+use crate::warn;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// CR0: x87 emulation (set would #NM every FPU instruction)
+const CR0_EM: u64 = 1 << 2;
+/// CR0: WAIT/FWAIT honour TS too
+const CR0_MP: u64 = 1 << 1;
+/// CR0: task switched, FPU use raises #NM
+const CR0_TS: u64 = 1 << 3;
+/// CR4: FXSAVE/FXRSTOR and SSE instructions allowed
+const CR4_OSFXSR: u64 = 1 << 9;
+/// CR4: unmasked SIMD exceptions raise #XM instead of #UD
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+
+/// An FXSAVE image
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    /// The state after FNINIT, with every SIMD exception masked
+    const INITIAL: FpuState = {
+        let mut bytes = [0u8; 512];
+        // FCW 0x037F
+        bytes[0] = 0x7F;
+        bytes[1] = 0x03;
+        // MXCSR 0x1F80
+        bytes[24] = 0x80;
+        bytes[25] = 0x1F;
+        FpuState(bytes)
+    };
+
+    fn save(&mut self) {
+        unsafe { asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags)) };
+    }
+
+    fn restore(&self) {
+        unsafe { asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags)) };
+    }
+}
+
+/// Fork depth of the running program; the one the shell started is 0
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+/// Depth of the program the registers hold the state of: the running one,
+/// or a parent whose state it inherited and has not used since
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+/// State of each parent waiting on a child that used the FPU, by depth
+static SAVED: Spinlock<[FpuState; MAX_DEPTH]> = Spinlock::new([const { FpuState::INITIAL }; MAX_DEPTH]);
+
+fn set_ts(on: bool) {
+    unsafe {
+        let mut cr0: u64;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        cr0 = if on { cr0 | CR0_TS } else { cr0 & !CR0_TS };
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+    }
+}
+
+/// Allow SSE on this CPU
+pub fn init() {
+    if !cpuid::features().sse {
+        warn!("fpu: no SSE, user programs cannot use it");
+        return;
+    }
+    unsafe {
+        let (cr0, cr4): (u64, u64);
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        asm!("mov cr0, {}", in(reg) (cr0 & !(CR0_EM | CR0_TS)) | CR0_MP, options(nostack, preserves_flags));
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        asm!("mov cr4, {}", in(reg) cr4 | CR4_OSFXSR | CR4_OSXMMEXCPT, options(nostack, preserves_flags));
+    }
+}
+
+/// Give a program the shell is starting the initial state
+pub fn start_program() {
+    CURRENT.store(0, Ordering::Relaxed);
+    LIVE.store(0, Ordering::Relaxed);
+    set_ts(false);
+    FpuState::INITIAL.restore();
+}
+
+/// A forked child is about to run, with its parent's registers
+pub fn enter_child() {
+    CURRENT.fetch_add(1, Ordering::Relaxed);
+    set_ts(true);
+}
+
+/// The running child has exited and its parent carries on
+pub fn resume_parent() {
+    let parent = CURRENT.fetch_sub(1, Ordering::Relaxed) - 1;
+    let live = LIVE.load(Ordering::Relaxed);
+    if live > parent {
+        set_ts(false);
+        SAVED.lock()[parent].restore();
+        LIVE.store(parent, Ordering::Relaxed);
+    } else if live == parent {
+        set_ts(false);
+    }
+    // Otherwise the registers still hold a grandparent's state, which the
+    // parent shares until it uses them; TS stays set to catch that
+}
+
+/// #NM from user mode: the running program used the FPU after a fork
+/// Saves the registers for every waiting parent sharing them; the program
+/// itself carries on with them.
+pub fn device_not_available() {
+    set_ts(false);
+    let current = CURRENT.load(Ordering::Relaxed);
+    let live = LIVE.load(Ordering::Relaxed);
+    if live < current {
+        let mut saved = SAVED.lock();
+        let (first, rest) = saved[live..current].split_first_mut().expect("live < current");
+        first.save();
+        for state in rest {
+            *state = first.clone();
+        }
+        LIVE.store(current, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catch_exception;
+    use crate::testing::Caught;
+
+    #[test_case]
+    fn test_task_switched_traps_fpu_use() {
+        set_ts(true);
+        let caught = unsafe { catch_exception!("fnop") };
+        set_ts(false);
+        assert_eq!(caught, Some(Caught { vector: 7, error_code: None }));
+        assert_eq!(unsafe { catch_exception!("fnop") }, None);
+    }
+
+    #[test_case]
+    fn test_sse_enabled() {
+        // #UD unless CR4.OSFXSR is set
+        let mut mxcsr = 0u32;
+        assert_eq!(unsafe { catch_exception!("stmxcsr [{mxcsr}]", mxcsr = in(reg) &mut mxcsr) }, None);
+        assert_eq!(mxcsr, 0x1F80);
+    }
+}
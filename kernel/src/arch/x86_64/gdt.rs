@@ -1,7 +1,10 @@
 //! Global Descriptor Table (GDT) for x86_64
 //! Required for long mode, defines code and data segments
 
+use super::smp::MAX_CPUS;
+use crate::sync::spinlock::Spinlock;
 use core::arch::asm;
+use core::cell::UnsafeCell;
 
 #[repr(C, packed)]
 struct GdtDescriptor {
@@ -42,6 +45,57 @@ impl GdtEntry {
             base_high: 0,
         }
     }
+
+    /// Lower half of a 16-byte 64-bit TSS descriptor
+    fn tss_low(base: u64, limit: u32) -> Self {
+        GdtEntry {
+            limit_low: limit as u16,
+            base_low: base as u16,
+            base_mid: (base >> 16) as u8,
+            access: PRESENT | TSS_AVAILABLE,
+            granularity: ((limit >> 16) & 0x0F) as u8,
+            base_high: (base >> 24) as u8,
+        }
+    }
+
+    /// Upper half of a TSS descriptor: bits 32-63 of the base, rest reserved
+    fn tss_high(base: u64) -> Self {
+        GdtEntry {
+            limit_low: (base >> 32) as u16,
+            base_low: (base >> 48) as u16,
+            base_mid: 0,
+            access: 0,
+            granularity: 0,
+            base_high: 0,
+        }
+    }
+}
+
+/// 64-bit Task State Segment: supplies the stacks used on privilege changes
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    rsp: [u64; 3],
+    reserved_2: u64,
+    ist: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    const fn new() -> Self {
+        TaskStateSegment {
+            reserved_1: 0,
+            rsp: [0; 3],
+            reserved_2: 0,
+            ist: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            // No I/O permission bitmap: offset points past the segment limit
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
 }
 
 // GDT access bits
@@ -51,17 +105,22 @@ const DPL_3: u8 = 3 << 5;
 const DESCRIPTOR_TYPE: u8 = 1 << 4;
 const EXECUTABLE: u8 = 1 << 3;
 const RW: u8 = 1 << 1;
+const TSS_AVAILABLE: u8 = 0x9; // System descriptor type: available 64-bit TSS
 
 // GDT flags
 const GRANULARITY: u8 = 1 << 7;
 const LONG_MODE: u8 = 1 << 5;
 
-const GDT_ENTRY_COUNT: usize = 9;
+const GDT_ENTRY_COUNT: usize = 11;
 
 // Segment selectors (byte offsets into GDT)
 pub const KERNEL_CODE_SELECTOR: u16 = 0x28;
-#[allow(dead_code)]
 pub const KERNEL_DATA_SELECTOR: u16 = 0x30;
+#[allow(dead_code)]
+pub const USER_DATA_SELECTOR: u16 = 0x38 | 3;
+#[allow(dead_code)]
+pub const USER_CODE_SELECTOR: u16 = 0x40 | 3;
+pub const TSS_SELECTOR: u16 = 0x48;
 
 pub struct Gdt {
     table: [GdtEntry; GDT_ENTRY_COUNT],
@@ -70,6 +129,8 @@ pub struct Gdt {
 impl Gdt {
     /// Layout matches Limine bootloader's GDT selector assignments:
     ///   0x28 = 64-bit kernel code, 0x30 = 64-bit kernel data
+    /// User data precedes user code because `sysret` derives SS and CS as
+    /// STAR base + 8 and + 16 respectively.
     pub const fn new() -> Self {
         Gdt {
             table: [
@@ -86,20 +147,31 @@ impl Gdt {
                     PRESENT | DPL_0 | DESCRIPTOR_TYPE | RW,
                     GRANULARITY,
                 ),
-                GdtEntry::new(    // 0x38: User code segment (64-bit)
-                    PRESENT | DPL_3 | DESCRIPTOR_TYPE | EXECUTABLE | RW,
-                    GRANULARITY | LONG_MODE,
-                ),
-                GdtEntry::new(    // 0x40: User data segment (64-bit)
+                GdtEntry::new(    // 0x38: User data segment (64-bit)
                     PRESENT | DPL_3 | DESCRIPTOR_TYPE | RW,
                     GRANULARITY,
                 ),
+                GdtEntry::new(    // 0x40: User code segment (64-bit)
+                    PRESENT | DPL_3 | DESCRIPTOR_TYPE | EXECUTABLE | RW,
+                    GRANULARITY | LONG_MODE,
+                ),
+                GdtEntry::null(), // 0x48: TSS (low), filled in per CPU
+                GdtEntry::null(), // 0x50: TSS (high)
             ],
         }
     }
 
-    pub fn load(&'static self) {
-        use crate::serial_println;
+    fn set_tss(&mut self, tss: &TaskStateSegment) {
+        let base = tss as *const TaskStateSegment as u64;
+        let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u32;
+        let index = TSS_SELECTOR as usize / 8;
+        self.table[index] = GdtEntry::tss_low(base, limit);
+        self.table[index + 1] = GdtEntry::tss_high(base);
+    }
+
+    /// Load this table; it must live in a static since the CPU keeps its address
+    fn load(&self) {
+        use crate::debug;
 
         let gdt_size = (core::mem::size_of::<[GdtEntry; GDT_ENTRY_COUNT]>() - 1) as u16;
         let gdt_offset = self.table.as_ptr() as u64;
@@ -109,22 +181,76 @@ impl Gdt {
             offset: gdt_offset,
         };
 
-        serial_println!("  GDT descriptor: size={}, offset={:#x}", gdt_size, gdt_offset);
+        debug!("  GDT descriptor: size={}, offset={:#x}", gdt_size, gdt_offset);
 
         unsafe {
-            serial_println!("  Loading GDT...");
+            debug!("  Loading GDT...");
             asm!(
                 "lgdt [{}]",
                 in(reg) &descriptor,
                 options(nostack, preserves_flags)
             );
-            serial_println!("  GDT loaded (Limine selectors 0x28/0x30 preserved)");
+            debug!("  GDT loaded (Limine selectors 0x28/0x30 preserved)");
+
+            asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
         }
     }
 }
 
-static GDT: Gdt = Gdt::new();
+/// IST slot (1-based, as encoded in IDT entries) used by the double fault handler
+pub const DOUBLE_FAULT_IST_INDEX: u8 = 1;
+const IST_STACK_SIZE: usize = 8192;
+
+// Statically allocated so a double fault works even before the frame
+// allocator exists; UnsafeCell keeps it out of read-only sections
+#[repr(C, align(16))]
+struct IstStack(UnsafeCell<[u8; IST_STACK_SIZE]>);
+
+unsafe impl Sync for IstStack {}
 
+impl IstStack {
+    const fn new() -> Self {
+        IstStack(UnsafeCell::new([0; IST_STACK_SIZE]))
+    }
+
+    fn top(&self) -> u64 {
+        self.0.get() as u64 + IST_STACK_SIZE as u64
+    }
+}
+
+static DOUBLE_FAULT_STACKS: [IstStack; MAX_CPUS] = [const { IstStack::new() }; MAX_CPUS];
+
+// One GDT and TSS per CPU: each CPU needs its own ring-0 stack pointers
+static GDTS: [Spinlock<Gdt>; MAX_CPUS] = [const { Spinlock::new(Gdt::new()) }; MAX_CPUS];
+static TSS: [Spinlock<TaskStateSegment>; MAX_CPUS] =
+    [const { Spinlock::new(TaskStateSegment::new()) }; MAX_CPUS];
+
+fn load_for_cpu(cpu: usize) {
+    let mut tss = TSS[cpu].lock();
+    let mut ist = tss.ist;
+    ist[DOUBLE_FAULT_IST_INDEX as usize - 1] = DOUBLE_FAULT_STACKS[cpu].top();
+    tss.ist = ist;
+
+    let mut gdt = GDTS[cpu].lock();
+    gdt.set_tss(&tss);
+    gdt.load();
+}
+
+/// Load the bootstrap processor's GDT and TSS
 pub fn init() {
-    GDT.load();
+    load_for_cpu(0);
+}
+
+/// Load the GDT and TSS belonging to application processor `cpu`
+pub fn init_ap(cpu: usize) {
+    load_for_cpu(cpu);
+}
+
+/// Set the stack the CPU switches to on interrupts arriving from ring 3
+pub fn set_privilege_stack(cpu: usize, top: usize) {
+    let mut tss = TSS[cpu].lock();
+    // Copy out and back: the TSS is packed, so fields can't be borrowed in place
+    let mut rsp = tss.rsp;
+    rsp[0] = top as u64;
+    tss.rsp = rsp;
 }
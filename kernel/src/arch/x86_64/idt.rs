@@ -1,6 +1,8 @@
 //! Interrupt Descriptor Table (IDT) for x86_64
 //! Handles CPU exceptions and hardware interrupts
 
+use super::pic;
+use crate::sync::once::Lazy;
 use core::arch::asm;
 
 #[repr(C, packed)]
@@ -35,10 +37,15 @@ impl IdtEntry {
     }
 
     pub const fn new(handler: usize) -> Self {
+        Self::with_ist(handler, 0)
+    }
+
+    /// Entry that switches to Interrupt Stack Table slot `ist` (1-7) on delivery
+    pub const fn with_ist(handler: usize, ist: u8) -> Self {
         IdtEntry {
             offset_low: (handler & 0xFFFF) as u16,
             selector: crate::arch::x86_64::gdt::KERNEL_CODE_SELECTOR,
-            ist: 0,
+            ist,
             type_attr: 0x8E, // Present, DPL=0, Interrupt Gate
             offset_mid: ((handler >> 16) & 0xFFFF) as u16,
             offset_high: ((handler >> 32) & 0xFFFFFFFF) as u32,
@@ -64,6 +71,10 @@ impl Idt {
         self.entries[index as usize] = IdtEntry::new(handler);
     }
 
+    pub fn set_handler_with_ist(&mut self, index: u8, handler: usize, ist: u8) {
+        self.entries[index as usize] = IdtEntry::with_ist(handler, ist);
+    }
+
     pub fn load(&'static self) {
         let descriptor = IdtDescriptor {
             size: (core::mem::size_of::<[IdtEntry; IDT_ENTRIES]>() - 1) as u16,
@@ -84,11 +95,52 @@ impl Idt {
 // Must save ALL general-purpose registers: both caller-saved (rax, rcx, rdx, rsi, rdi, r8-r11)
 // and callee-saved (rbx, rbp, r12-r15) since interrupt handlers call Rust functions that
 // freely use callee-saved registers, corrupting the interrupted code's state.
+// Entries from ring 3 also swap to the kernel GS base (checked via the saved CS,
+// which sits one slot higher when the CPU pushed an error code).
+// Every stub first counts the interrupt in `interrupts::VECTOR_COUNTS`.
+// Handlers are called as `(regs: &SavedRegisters, error_code: u64, frame:
+// &mut InterruptStackFrame, vector: u64)` and may ignore trailing arguments;
+// the error code is 0 for vectors without one. Changes to the frame (the
+// `rip` to resume at) take effect on `iretq`.
+// The CPU aligns the stack to 16 bytes before pushing its frame, so the
+// error code leaves it 8 bytes off from the other vectors; those stubs pad
+// around the call to keep the ABI's alignment.
 macro_rules! exception_wrapper {
-    ($name:ident, $handler_name:ident) => {
+    ($name:ident, $handler_name:ident, vector = $vector:literal) => {
+        exception_wrapper!($name, $handler_name, $vector, cs_offset = 8, "xor esi, esi", frame = 120, "", "", "");
+    };
+    ($name:ident, $handler_name:ident, vector = $vector:literal, error_code) => {
+        exception_wrapper!(
+            $name,
+            $handler_name,
+            $vector,
+            cs_offset = 16,
+            "mov rsi, [rsp + 120]",
+            frame = 128,
+            "sub rsp, 8",
+            "add rsp, 8",
+            "add rsp, 8"
+        );
+    };
+    (
+        $name:ident,
+        $handler_name:ident,
+        $vector:literal,
+        cs_offset = $cs:literal,
+        $load_error:literal,
+        frame = $frame:literal,
+        $pad:literal,
+        $unpad:literal,
+        $drop_error:literal
+    ) => {
         #[unsafe(naked)]
         pub extern "C" fn $name() {
             core::arch::naked_asm!(
+                concat!("lock inc qword ptr [rip + {1} + ", $vector, " * 8]"),
+                concat!("test qword ptr [rsp + ", $cs, "], 3"),
+                "jz 2f",
+                "swapgs",
+                "2:",
                 "push rax",
                 "push rcx",
                 "push rdx",
@@ -104,7 +156,13 @@ macro_rules! exception_wrapper {
                 "push r13",
                 "push r14",
                 "push r15",
+                "mov rdi, rsp",
+                $load_error,
+                concat!("lea rdx, [rsp + ", $frame, "]"),
+                concat!("mov ecx, ", $vector),
+                $pad,
                 "call {0}",
+                $unpad,
                 "pop r15",
                 "pop r14",
                 "pop r13",
@@ -120,41 +178,172 @@ macro_rules! exception_wrapper {
                 "pop rdx",
                 "pop rcx",
                 "pop rax",
+                $drop_error,
+                "test qword ptr [rsp + 8], 3",
+                "jz 3f",
+                "swapgs",
+                "3:",
                 "iretq",
                 sym crate::arch::x86_64::interrupts::$handler_name,
+                sym crate::arch::x86_64::interrupts::VECTOR_COUNTS,
             );
         }
     };
 }
 
-exception_wrapper!(divide_by_zero_wrapper, divide_by_zero_handler);
-exception_wrapper!(debug_wrapper, debug_handler);
-exception_wrapper!(invalid_opcode_wrapper, invalid_opcode_handler);
-exception_wrapper!(breakpoint_wrapper, breakpoint_handler);
-exception_wrapper!(page_fault_wrapper, page_fault_handler);
-exception_wrapper!(general_protection_fault_wrapper, general_protection_fault_handler);
-exception_wrapper!(double_fault_wrapper, double_fault_handler);
-exception_wrapper!(keyboard_wrapper, keyboard_interrupt_handler);
+// One stub per architectural exception. Vectors 15, 22-27 and 31 are
+// reserved and never raised; 8 (double fault) has its own stub below.
+exception_wrapper!(divide_error_wrapper, exception_handler, vector = 0);
+exception_wrapper!(debug_wrapper, debug_handler, vector = 1);
+exception_wrapper!(nmi_wrapper, exception_handler, vector = 2);
+exception_wrapper!(breakpoint_wrapper, breakpoint_handler, vector = 3);
+exception_wrapper!(overflow_wrapper, exception_handler, vector = 4);
+exception_wrapper!(bound_range_wrapper, exception_handler, vector = 5);
+exception_wrapper!(invalid_opcode_wrapper, exception_handler, vector = 6);
+exception_wrapper!(device_not_available_wrapper, device_not_available_handler, vector = 7);
+exception_wrapper!(coprocessor_segment_wrapper, exception_handler, vector = 9);
+exception_wrapper!(invalid_tss_wrapper, exception_handler, vector = 10, error_code);
+exception_wrapper!(segment_not_present_wrapper, exception_handler, vector = 11, error_code);
+exception_wrapper!(stack_segment_wrapper, exception_handler, vector = 12, error_code);
+exception_wrapper!(general_protection_fault_wrapper, exception_handler, vector = 13, error_code);
+exception_wrapper!(page_fault_wrapper, page_fault_handler, vector = 14, error_code);
+exception_wrapper!(x87_floating_point_wrapper, exception_handler, vector = 16);
+exception_wrapper!(alignment_check_wrapper, exception_handler, vector = 17, error_code);
+exception_wrapper!(machine_check_wrapper, exception_handler, vector = 18);
+exception_wrapper!(simd_floating_point_wrapper, exception_handler, vector = 19);
+exception_wrapper!(virtualization_wrapper, exception_handler, vector = 20);
+exception_wrapper!(control_protection_wrapper, exception_handler, vector = 21, error_code);
+exception_wrapper!(hypervisor_injection_wrapper, exception_handler, vector = 28);
+exception_wrapper!(vmm_communication_wrapper, exception_handler, vector = 29, error_code);
+exception_wrapper!(security_wrapper, exception_handler, vector = 30, error_code);
 
-static mut IDT: Idt = Idt::new();
+const EXCEPTION_STUBS: [(u8, extern "C" fn()); 23] = [
+    (0, divide_error_wrapper),
+    (1, debug_wrapper),
+    (2, nmi_wrapper),
+    (3, breakpoint_wrapper),
+    (4, overflow_wrapper),
+    (5, bound_range_wrapper),
+    (6, invalid_opcode_wrapper),
+    (7, device_not_available_wrapper),
+    (9, coprocessor_segment_wrapper),
+    (10, invalid_tss_wrapper),
+    (11, segment_not_present_wrapper),
+    (12, stack_segment_wrapper),
+    (13, general_protection_fault_wrapper),
+    (14, page_fault_wrapper),
+    (16, x87_floating_point_wrapper),
+    (17, alignment_check_wrapper),
+    (18, machine_check_wrapper),
+    (19, simd_floating_point_wrapper),
+    (20, virtualization_wrapper),
+    (21, control_protection_wrapper),
+    (28, hypervisor_injection_wrapper),
+    (29, vmm_communication_wrapper),
+    (30, security_wrapper),
+];
 
-pub fn init() {
-    unsafe {
-        let idt = &mut *core::ptr::addr_of_mut!(IDT);
-
-        // Install exception handlers
-        idt.set_handler(0, divide_by_zero_wrapper as *const () as usize);
-        idt.set_handler(1, debug_wrapper as *const () as usize);
-        idt.set_handler(3, breakpoint_wrapper as *const () as usize);
-        idt.set_handler(6, invalid_opcode_wrapper as *const () as usize);
-        idt.set_handler(8, double_fault_wrapper as *const () as usize);
-        idt.set_handler(13, general_protection_fault_wrapper as *const () as usize);
-        idt.set_handler(14, page_fault_wrapper as *const () as usize);
-
-        // Install IRQ handlers (remapped to 32+)
-        idt.set_handler(33, keyboard_wrapper as *const () as usize); // IRQ1 -> vector 33
-
-        // Load IDT
-        (&*core::ptr::addr_of!(IDT)).load();
+// The double fault stub does no register saving and no swapgs: the handler
+// never returns, and it must not depend on anything that may be broken
+// (the interrupted stack, GS, locks). It runs on its own IST stack.
+#[unsafe(naked)]
+pub extern "C" fn double_fault_wrapper() {
+    core::arch::naked_asm!(
+        "lock inc qword ptr [rip + {1} + 8 * 8]",
+        "mov rdi, rsp",
+        "and rsp, -16",
+        "call {0}",
+        "2:",
+        "cli",
+        "hlt",
+        "jmp 2b",
+        sym crate::arch::x86_64::interrupts::double_fault_handler,
+        sym crate::arch::x86_64::interrupts::VECTOR_COUNTS,
+    );
+}
+
+// The LAPIC's spurious vector only needs counting: it gets no EOI, and
+// touches no registers that would need saving
+#[unsafe(naked)]
+pub extern "C" fn lapic_spurious_wrapper() {
+    core::arch::naked_asm!(
+        "lock inc qword ptr [rip + {0} + {1} * 8]",
+        "iretq",
+        sym crate::arch::x86_64::interrupts::VECTOR_COUNTS,
+        const super::lapic::SPURIOUS_VECTOR as usize,
+    );
+}
+exception_wrapper!(timer_wrapper, timer_interrupt_handler, vector = 32);
+exception_wrapper!(watch_timer_wrapper, watch_timer_handler, vector = 240);
+
+// One stub per PIC line but the tick's (IRQ 0), all handing the IRQ to
+// `crate::interrupts::dispatch`; drivers register there, not here
+exception_wrapper!(irq1_wrapper, irq_handler, vector = 33);
+exception_wrapper!(irq2_wrapper, irq_handler, vector = 34);
+exception_wrapper!(irq3_wrapper, irq_handler, vector = 35);
+exception_wrapper!(irq4_wrapper, irq_handler, vector = 36);
+exception_wrapper!(irq5_wrapper, irq_handler, vector = 37);
+exception_wrapper!(irq6_wrapper, irq_handler, vector = 38);
+exception_wrapper!(irq7_wrapper, irq_handler, vector = 39);
+exception_wrapper!(irq8_wrapper, irq_handler, vector = 40);
+exception_wrapper!(irq9_wrapper, irq_handler, vector = 41);
+exception_wrapper!(irq10_wrapper, irq_handler, vector = 42);
+exception_wrapper!(irq11_wrapper, irq_handler, vector = 43);
+exception_wrapper!(irq12_wrapper, irq_handler, vector = 44);
+exception_wrapper!(irq13_wrapper, irq_handler, vector = 45);
+exception_wrapper!(irq14_wrapper, irq_handler, vector = 46);
+exception_wrapper!(irq15_wrapper, irq_handler, vector = 47);
+
+const IRQ_STUBS: [(u8, extern "C" fn()); 15] = [
+    (1, irq1_wrapper),
+    (2, irq2_wrapper),
+    (3, irq3_wrapper),
+    (4, irq4_wrapper),
+    (5, irq5_wrapper),
+    (6, irq6_wrapper),
+    (7, irq7_wrapper),
+    (8, irq8_wrapper),
+    (9, irq9_wrapper),
+    (10, irq10_wrapper),
+    (11, irq11_wrapper),
+    (12, irq12_wrapper),
+    (13, irq13_wrapper),
+    (14, irq14_wrapper),
+    (15, irq15_wrapper),
+];
+
+/// Built on first use, by the boot CPU's `init`; the APs share it
+static IDT: Lazy<Idt> = Lazy::new(build);
+
+fn build() -> Idt {
+    let mut idt = Idt::new();
+
+    // Install exception handlers
+    for (vector, stub) in EXCEPTION_STUBS {
+        idt.set_handler(vector, stub as *const () as usize);
+    }
+    idt.set_handler_with_ist(
+        8,
+        double_fault_wrapper as *const () as usize,
+        crate::arch::x86_64::gdt::DOUBLE_FAULT_IST_INDEX,
+    );
+
+    // Install IRQ handlers (remapped to 32+)
+    idt.set_handler(pic::VECTOR_BASE, timer_wrapper as *const () as usize); // IRQ0 -> vector 32
+    for (irq, stub) in IRQ_STUBS {
+        idt.set_handler(pic::VECTOR_BASE + irq, stub as *const () as usize);
     }
+    idt.set_handler(super::smp::WATCH_VECTOR, watch_timer_wrapper as *const () as usize); // LAPIC timer of the watch CPU
+    idt.set_handler(super::lapic::SPURIOUS_VECTOR, lapic_spurious_wrapper as *const () as usize);
+    idt
+}
+
+/// Build the IDT and load it on the boot CPU
+pub fn init() {
+    IDT.load();
+}
+
+/// Load the (shared, already populated) IDT on the calling CPU
+pub fn load() {
+    IDT.load();
 }
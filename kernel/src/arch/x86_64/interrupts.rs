@@ -1,47 +1,209 @@
 //! Exception and interrupt handlers for x86_64
 
-use crate::{println, serial_println};
-use crate::drivers;
+use crate::arch::Cpu;
+use crate::{backtrace, drivers, symbols, watchdog};
+use crate::{boot_println, error, println, warn};
+use crate::memory::{kstack, VirtAddr};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use shared::backtrace::Demangled;
+use shared::page_fault::PageFaultError;
 
-#[no_mangle]
-pub extern "C" fn divide_by_zero_handler() {
-    serial_println!("EXCEPTION: Divide by Zero");
-    println!("EXCEPTION: Divide by Zero");
-    loop {
-        unsafe {
-            core::arch::asm!("hlt");
+/// General-purpose registers in the order the exception wrappers push them
+#[repr(C)]
+pub struct SavedRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rax: u64,
+}
+
+/// Pushed by the CPU on every exception, above the error code if there is one
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl InterruptStackFrame {
+    /// The exception interrupted ring 3
+    pub fn in_user_mode(&self) -> bool {
+        self.cs & 3 != 0
+    }
+}
+
+/// Exception names by vector
+const EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-Maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection Fault (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point Exception (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point Exception (#XM)",
+    "Virtualization Exception (#VE)",
+    "Control Protection Exception (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception (#HV)",
+    "VMM Communication Exception (#VC)",
+    "Security Exception (#SX)",
+    "Reserved",
+];
+
+const NMI_VECTOR: u64 = 2;
+
+/// Times each vector was delivered, counted by its stub in `idt` before
+/// anything else runs
+pub static VECTOR_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// IRQ 7 and 15 deliveries the PIC raised for no device (see `pic::spurious`)
+static PIC_SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// What a vector is used for
+#[derive(Clone, Copy)]
+pub enum VectorUse {
+    Exception(&'static str),
+    Irq(u8),
+    WatchTimer,
+    Spurious,
+    Unused,
+}
+
+impl VectorUse {
+    pub fn of(vector: u8) -> Self {
+        let irqs = super::pic::VECTOR_BASE..super::pic::VECTOR_BASE + 16;
+        if let Some(&name) = EXCEPTION_NAMES.get(vector as usize) {
+            VectorUse::Exception(name)
+        } else if irqs.contains(&vector) {
+            VectorUse::Irq(vector - super::pic::VECTOR_BASE)
+        } else if vector == super::smp::WATCH_VECTOR {
+            VectorUse::WatchTimer
+        } else if vector == super::lapic::SPURIOUS_VECTOR {
+            VectorUse::Spurious
+        } else {
+            VectorUse::Unused
+        }
+    }
+}
+
+impl fmt::Display for VectorUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VectorUse::Exception(name) => f.write_str(name),
+            VectorUse::Irq(0) => f.write_str("IRQ 0 (timer tick)"),
+            VectorUse::Irq(irq) => write!(f, "IRQ {}", irq),
+            VectorUse::WatchTimer => f.write_str("Watchdog LAPIC timer"),
+            VectorUse::Spurious => f.write_str("LAPIC spurious"),
+            VectorUse::Unused => f.write_str("Unused"),
         }
     }
 }
 
+/// Vectors delivered at least once, with their counts
+pub fn vector_counts() -> impl Iterator<Item = (u8, u64)> {
+    (0..=255).map(|vector| (vector, VECTOR_COUNTS[vector as usize].load(Ordering::Relaxed))).filter(|&(_, count)| count > 0)
+}
+
+/// Spurious interrupts, from the PIC (as IRQ 7 or 15) and from the LAPIC
+pub fn spurious_counts() -> (u64, u64) {
+    let lapic = VECTOR_COUNTS[super::lapic::SPURIOUS_VECTOR as usize].load(Ordering::Relaxed);
+    (PIC_SPURIOUS.load(Ordering::Relaxed), lapic)
+}
+
+/// Vectors whose exceptions push an error code, as a bit mask
+const ERROR_CODE_VECTORS: u32 = 1 << 8 | 1 << 10 | 1 << 11 | 1 << 12 | 1 << 13 | 1 << 14 | 1 << 17 | 1 << 21 | 1 << 29 | 1 << 30;
+
+/// Any exception without a handler of its own
 #[no_mangle]
-pub extern "C" fn debug_handler() {
-    serial_println!("EXCEPTION: Debug");
-    println!("EXCEPTION: Debug");
+pub extern "C" fn exception_handler(
+    regs: &SavedRegisters,
+    error_code: u64,
+    frame: &mut InterruptStackFrame,
+    vector: u64,
+) {
+    let name = EXCEPTION_NAMES.get(vector as usize).copied().unwrap_or("Unknown");
+    let error_code = (ERROR_CODE_VECTORS & 1 << vector != 0).then_some(error_code);
+    #[cfg(test)]
+    if crate::testing::catch(vector, error_code, frame) {
+        return;
+    }
+    if vector == NMI_VECTOR && watchdog::nmi_sent() {
+        let what = format_args!("CPU stuck with interrupts disabled for {} ms", watchdog::TIMEOUT_MS);
+        watchdog_bite(what, regs, frame);
+    }
+    fatal(format_args!("{}", name), regs, error_code, frame);
 }
 
+/// A program's first FPU or SSE instruction after a fork (see `fpu`)
 #[no_mangle]
-pub extern "C" fn invalid_opcode_handler() {
-    serial_println!("EXCEPTION: Invalid Opcode (#UD)");
-    println!("EXCEPTION: Invalid Opcode (#UD)");
-    loop {
-        unsafe {
-            core::arch::asm!("hlt");
-        }
+pub extern "C" fn device_not_available_handler(
+    regs: &SavedRegisters,
+    error_code: u64,
+    frame: &mut InterruptStackFrame,
+    vector: u64,
+) {
+    if frame.in_user_mode() {
+        super::fpu::device_not_available();
+        return;
     }
+    exception_handler(regs, error_code, frame, vector);
 }
 
 #[no_mangle]
-pub extern "C" fn breakpoint_handler() {
-    serial_println!("EXCEPTION: Breakpoint");
-    println!("EXCEPTION: Breakpoint");
+pub extern "C" fn debug_handler() {
+    warn!("EXCEPTION: Debug");
+    println!("EXCEPTION: Debug");
 }
 
+/// `int3` prints where it was hit and carries on
 #[no_mangle]
-pub extern "C" fn page_fault_handler() {
-    serial_println!("EXCEPTION: Page Fault");
-    println!("EXCEPTION: Page Fault");
+pub extern "C" fn breakpoint_handler(regs: &SavedRegisters, _: u64, frame: &mut InterruptStackFrame) {
+    #[cfg(test)]
+    if crate::testing::catch(3, None, frame) {
+        return;
+    }
+    warn!("EXCEPTION: Breakpoint at {:#x}", frame.rip);
+    println!("EXCEPTION: Breakpoint");
+    print_registers(regs, frame);
+    if !frame.in_user_mode() {
+        backtrace::print(Some(frame.rip), regs.rbp);
+    }
+}
 
+#[no_mangle]
+pub extern "C" fn page_fault_handler(regs: &SavedRegisters, error_code: u64, frame: &mut InterruptStackFrame) {
     // Read CR2 register for faulting address
     let faulting_address: u64;
     unsafe {
@@ -52,20 +214,55 @@ pub extern "C" fn page_fault_handler() {
         );
     }
 
-    serial_println!("  Faulting address: {:#x}", faulting_address);
-    println!("  Faulting address: {:#x}", faulting_address);
+    // A page of a demand-paged program that has not been read in yet
+    let error = PageFaultError(error_code);
+    if !error.present() && crate::process::demand::handle_fault(VirtAddr::new(faulting_address)) {
+        return;
+    }
 
-    loop {
-        unsafe {
-            core::arch::asm!("hlt");
+    // A write to a page shared with a forked program, by the program or by
+    // the kernel on its behalf
+    if error.present()
+        && error.write()
+        && faulting_address < abi::USER_SPACE_END
+        && crate::process::demand::handle_write_fault(VirtAddr::new(faulting_address))
+    {
+        return;
+    }
+
+    #[cfg(test)]
+    if crate::testing::catch(14, Some(error_code), frame) {
+        return;
+    }
+
+    // A kernel access that ran off the bottom of a guarded stack
+    if !error.present() && !frame.in_user_mode() {
+        if let Some(stack) = kstack::guard_owner(faulting_address) {
+            let what = format_args!("Kernel stack overflow in the {} (at {:#x})", stack, faulting_address);
+            fatal(what, regs, Some(error_code), frame);
         }
     }
+
+    fatal(format_args!("Page Fault at {:#x}: {}", faulting_address, error), regs, Some(error_code), frame);
 }
 
-#[no_mangle]
-pub extern "C" fn general_protection_fault_handler() {
-    serial_println!("EXCEPTION: General Protection Fault");
-    println!("EXCEPTION: General Protection Fault");
+/// Report an exception the kernel cannot recover from, with the registers
+/// and (for faults in the kernel) a backtrace, and halt this CPU
+/// A user program that faults is ended instead, as if it had called exit(-1).
+fn fatal(what: fmt::Arguments, regs: &SavedRegisters, error_code: Option<u64>, frame: &InterruptStackFrame) -> ! {
+    error!("EXCEPTION: {} (rip {:#x})", what, frame.rip);
+    println!("EXCEPTION: {}", what);
+    if let Some(code) = error_code {
+        boot_println!("  Error code {:#x}", code);
+    }
+    print_location(frame);
+    print_registers(regs, frame);
+    if frame.in_user_mode() {
+        boot_println!("  (in user mode, ending the program)");
+        crate::process::exit_from_trap(-1);
+    }
+    backtrace::print(Some(frame.rip), regs.rbp);
+    watchdog::stop();
 
     loop {
         unsafe {
@@ -74,19 +271,195 @@ pub extern "C" fn general_protection_fault_handler() {
     }
 }
 
+/// The watchdog caught this CPU stuck at `frame`: report where, and reset
+fn watchdog_bite(what: fmt::Arguments, regs: &SavedRegisters, frame: &InterruptStackFrame) -> ! {
+    watchdog::stop();
+    boot_println!("WATCHDOG: {}", what);
+    print_location(frame);
+    print_registers(regs, frame);
+    backtrace::print(Some(frame.rip), regs.rbp);
+    boot_println!("Resetting");
+    crate::power::reboot()
+}
+
+/// The kernel function `frame` interrupted, if it interrupted the kernel
+fn print_location(frame: &InterruptStackFrame) {
+    if let Some((name, offset)) = symbols::lookup(frame.rip).filter(|_| !frame.in_user_mode()) {
+        boot_println!("  In {}+{:#x}", Demangled(name), offset);
+    }
+}
+
+fn print_registers(regs: &SavedRegisters, frame: &InterruptStackFrame) {
+    boot_println!("  RIP {:016x}  RSP {:016x}  RFLAGS {:016x}", frame.rip, frame.rsp, frame.rflags);
+    boot_println!("  CS {:04x}  SS {:04x}", frame.cs, frame.ss);
+    boot_println!("  RAX {:016x}  RBX {:016x}  RCX {:016x}  RDX {:016x}", regs.rax, regs.rbx, regs.rcx, regs.rdx);
+    boot_println!("  RSI {:016x}  RDI {:016x}  RBP {:016x}  R8  {:016x}", regs.rsi, regs.rdi, regs.rbp, regs.r8);
+    boot_println!("  R9  {:016x}  R10 {:016x}  R11 {:016x}  R12 {:016x}", regs.r9, regs.r10, regs.r11, regs.r12);
+    boot_println!("  R13 {:016x}  R14 {:016x}  R15 {:016x}", regs.r13, regs.r14, regs.r15);
+    print_control_registers();
+}
+
+/// Print CR0, CR2 (last page fault address), CR3 (page table) and CR4
+pub fn print_control_registers() {
+    let (cr0, cr2, cr3, cr4): (u64, u64, u64, u64);
+    unsafe {
+        core::arch::asm!(
+            "mov {}, cr0",
+            "mov {}, cr2",
+            "mov {}, cr3",
+            "mov {}, cr4",
+            out(reg) cr0,
+            out(reg) cr2,
+            out(reg) cr3,
+            out(reg) cr4,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    boot_println!("  CR0 {:016x}  CR2 {:016x}  CR3 {:016x}  CR4 {:016x}", cr0, cr2, cr3, cr4);
+}
+
+/// Frame pushed by the CPU for a double fault (error code is always zero)
+#[repr(C)]
+pub struct DoubleFaultFrame {
+    error_code: u64,
+    frame: InterruptStackFrame,
+}
+
+/// Double fault handler: runs on its IST stack and takes no locks
+/// Output goes to the raw serial path with hand-rolled hex formatting so that
+/// neither a held console lock nor a corrupted formatting path can re-fault.
 #[no_mangle]
-pub extern "C" fn double_fault_handler() {
-    serial_println!("EXCEPTION: Double Fault");
-    println!("EXCEPTION: Double Fault");
+pub extern "C" fn double_fault_handler(frame: &DoubleFaultFrame) -> ! {
+    use drivers::serial::write_raw;
+
+    write_raw(b"\n*** EXCEPTION: Double Fault ***\n");
+    let DoubleFaultFrame { frame, .. } = frame;
+    write_raw_field(b"  RIP:    ", frame.rip);
+    write_raw_field(b"  CS:     ", frame.cs);
+    write_raw_field(b"  RFLAGS: ", frame.rflags);
+    write_raw_field(b"  RSP:    ", frame.rsp);
+    write_raw_field(b"  SS:     ", frame.ss);
+
+    // A push onto a stack guard page faults, and delivering that page fault
+    // pushes onto the same guard page, so overflows usually end up here
+    let cr2: u64;
+    unsafe {
+        core::arch::asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    }
+    if let Some(stack) = kstack::guard_owner(cr2) {
+        write_raw(b"  Kernel stack overflow in the ");
+        write_raw(stack.name.as_bytes());
+        write_raw(b" stack\n");
+        write_raw_field(b"  CPU:    ", stack.cpu as u64);
+        write_raw_field(b"  CR2:    ", cr2);
+    }
+    write_raw(b"System halted.\n");
+    watchdog::stop();
 
     loop {
         unsafe {
-            core::arch::asm!("hlt");
+            core::arch::asm!("cli; hlt", options(nomem, nostack));
         }
     }
 }
 
+/// Write "<label>0x<16 hex digits>\n" without core::fmt
+fn write_raw_field(label: &[u8], value: u64) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut digits = [0u8; 19];
+    digits[0] = b'0';
+    digits[1] = b'x';
+    for i in 0..16 {
+        digits[2 + i] = HEX[((value >> (60 - i * 4)) & 0xF) as usize];
+    }
+    digits[18] = b'\n';
+
+    drivers::serial::write_raw(label);
+    drivers::serial::write_raw(&digits);
+}
+
 #[no_mangle]
-pub extern "C" fn keyboard_interrupt_handler() {
-    drivers::keyboard::handle_interrupt();
+pub extern "C" fn timer_interrupt_handler(regs: &SavedRegisters, _: u64, frame: &InterruptStackFrame) {
+    crate::time::handle_tick();
+    if watchdog::tick(frame.in_user_mode()) {
+        watchdog_bite(format_args!("no progress for {} ms", watchdog::TIMEOUT_MS), regs, frame);
+    }
+    // A program that never makes a syscall still gets ended on request
+    if frame.in_user_mode() && crate::process::kill_pending() {
+        crate::process::exit_from_trap(crate::process::OOM_EXIT_CODE);
+    }
+    run_deferred_work(frame);
+}
+
+/// Run queued `workqueue` items on the way back to user mode, where no
+/// kernel lock can be held; from the kernel they wait for `time::idle`
+fn run_deferred_work(frame: &InterruptStackFrame) {
+    if frame.in_user_mode() && crate::workqueue::pending() {
+        super::X86_64::enable_interrupts();
+        crate::workqueue::run_pending();
+        super::X86_64::disable_interrupts();
+    }
+}
+
+/// The watch CPU's timer (see `watchdog`): NMI the boot CPU if its tick stopped
+#[no_mangle]
+pub extern "C" fn watch_timer_handler() {
+    if watchdog::watch() {
+        super::lapic::send_nmi(super::smp::lapic_id(0));
+    }
+    super::lapic::end_of_interrupt();
+}
+
+/// Any PIC line but the tick's: run the handlers drivers registered on it
+#[no_mangle]
+pub extern "C" fn irq_handler(_: &SavedRegisters, _: u64, frame: &InterruptStackFrame, vector: u64) {
+    let irq = (vector - super::pic::VECTOR_BASE as u64) as u8;
+    if super::pic::spurious(irq) {
+        PIC_SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    crate::interrupts::dispatch(irq);
+    run_deferred_work(frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catch_exception;
+    use crate::testing::Caught;
+    use shared::page_fault::PageFaultError;
+
+    /// In the kernel's read-only segment
+    static READ_ONLY: u64 = 0;
+
+    #[test_case]
+    fn test_divide_error() {
+        let caught = unsafe {
+            catch_exception!("div {divisor}", divisor = in(reg) 0u64, inout("rax") 1u64 => _, inout("rdx") 0u64 => _)
+        };
+        assert_eq!(caught, Some(Caught { vector: 0, error_code: None }));
+    }
+
+    #[test_case]
+    fn test_breakpoint() {
+        assert_eq!(unsafe { catch_exception!("int3") }, Some(Caught { vector: 3, error_code: None }));
+    }
+
+    #[test_case]
+    fn test_invalid_opcode() {
+        assert_eq!(unsafe { catch_exception!("ud2") }, Some(Caught { vector: 6, error_code: None }));
+    }
+
+    #[test_case]
+    fn test_write_to_read_only_page() {
+        let caught = unsafe { catch_exception!("mov qword ptr [{addr}], 1", addr = in(reg) &READ_ONLY) };
+        let error = caught.and_then(|caught| caught.error_code).map(PageFaultError);
+        assert_eq!(caught.map(|caught| caught.vector), Some(14));
+        assert!(error.is_some_and(|error| error.present() && error.write() && !error.user()));
+        assert_eq!(READ_ONLY, 0);
+    }
+
+    #[test_case]
+    fn test_no_exception() {
+        assert_eq!(unsafe { catch_exception!("nop") }, None);
+    }
 }
@@ -0,0 +1,77 @@
+//! Local APIC
+//! Only what the watchdog needs so far: a CPU's own periodic timer, and NMIs
+//! to another CPU, plus a known spurious vector on every CPU. Device
+//! interrupts still come through the PIC. Limine
+//! leaves every LAPIC in xAPIC mode, with its registers memory-mapped at
+//! the address in IA32_APIC_BASE (below 4 GiB, so inside the HHDM).
+
+use super::msr;
+use crate::memory::{self, PhysAddr};
+use shared::mmio::{ReadWrite, WriteOnly};
+use shared::register_block;
+
+const BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+register_block! {
+    /// The xAPIC registers this driver uses
+    struct Lapic {
+        0xb0 => eoi: WriteOnly<u32>,
+        0xf0 => spurious: ReadWrite<u32>,
+        0x300 => icr_low: ReadWrite<u32>,
+        0x310 => icr_high: ReadWrite<u32>,
+        0x320 => lvt_timer: ReadWrite<u32>,
+        0x380 => timer_initial: ReadWrite<u32>,
+        0x3e0 => timer_divide: ReadWrite<u32>,
+    }
+}
+
+/// Spurious vector register: APIC software enable
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+/// Delivered for an interrupt withdrawn before the CPU took it; needs no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_ASSERT: u32 = 1 << 14;
+/// Set until the last IPI has been accepted
+const ICR_PENDING: u32 = 1 << 12;
+
+/// The calling CPU's LAPIC
+fn lapic() -> Lapic {
+    let base = unsafe { msr::read(msr::IA32_APIC_BASE) } & BASE_ADDR_MASK;
+    unsafe { Lapic::new(memory::phys_to_virt(PhysAddr::new(base))) }
+}
+
+/// Deliver the calling CPU's spurious interrupts on `SPURIOUS_VECTOR`, whose
+/// stub ignores them; whatever vector the firmware left there may have no
+/// IDT entry, or belong to a handler that would acknowledge them. The APIC
+/// stays enabled or disabled as it was.
+pub fn init_spurious_vector() {
+    lapic().spurious().modify(|value| value & !0xff | SPURIOUS_VECTOR as u32);
+}
+
+/// Interrupt the calling CPU on `vector` every `count` timer ticks
+/// The timer counts the bus clock divided by 16, at whatever rate that is.
+pub fn start_periodic_timer(vector: u8, count: u32) {
+    let lapic = lapic();
+    lapic.spurious().modify(|value| value | SPURIOUS_ENABLE);
+    lapic.timer_divide().write(TIMER_DIVIDE_BY_16);
+    lapic.lvt_timer().write(vector as u32 | TIMER_PERIODIC);
+    lapic.timer_initial().write(count);
+}
+
+/// Acknowledge the LAPIC interrupt being handled (its timer's)
+pub fn end_of_interrupt() {
+    lapic().eoi().write(0);
+}
+
+/// Send a non-maskable interrupt to the CPU with `lapic_id`
+pub fn send_nmi(lapic_id: u32) {
+    let lapic = lapic();
+    while lapic.icr_low().read() & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+    lapic.icr_high().write(lapic_id << 24);
+    // Writing the low half sends it
+    lapic.icr_low().write(ICR_DELIVERY_NMI | ICR_ASSERT);
+}
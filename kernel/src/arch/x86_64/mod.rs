@@ -1,4 +1,226 @@
+//! x86_64 backend
+//! `X86_64` implements the `arch` traits on top of the modules below. Those
+//! modules also carry the boot-time setup `main` performs directly.
+
+pub mod context;
+pub mod cpuid;
+pub mod fpu;
 pub mod gdt;
 pub mod idt;
 pub mod interrupts;
+pub mod lapic;
+pub mod msr;
 pub mod pic;
+pub mod pit;
+pub mod port;
+pub mod rdrand;
+pub mod smp;
+pub mod syscall;
+pub mod tsc;
+
+use super::{ContextSwitch, Cpu, InterruptController, Mmu, PortIo, Timer};
+use crate::memory::{PhysAddr, VirtAddr};
+use core::arch::asm;
+use shared::backtrace::FrameLayout;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+const RFLAGS_IF: u64 = 1 << 9;
+const EFER_NXE: u64 = 1 << 11;
+const CR3_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+const TIMER_IRQ: u8 = 0;
+
+/// PIT input clock cycles per tick
+static PIT_DIVISOR: AtomicU16 = AtomicU16::new(0);
+/// Ticks per PIT period: the one counting now, and the one loaded at the next reload
+static PERIOD_RUNNING: AtomicU64 = AtomicU64::new(1);
+static PERIOD_PROGRAMMED: AtomicU64 = AtomicU64::new(1);
+/// PIT cycles (about 100 us) before a reload in which the count is left alone
+const RELOAD_GUARD: u16 = 120;
+
+pub struct X86_64;
+
+/// Turn on no-execute page protection on this CPU, if it has it
+/// Limine usually has already; the kernel's W^X mappings need it either way.
+pub fn enable_nx() {
+    if cpuid::features().nx {
+        unsafe { msr::write(msr::IA32_EFER, msr::read(msr::IA32_EFER) | EFER_NXE) };
+    }
+}
+
+impl Cpu for X86_64 {
+    fn interrupts_enabled() -> bool {
+        let rflags: u64;
+        unsafe {
+            asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+        }
+        rflags & RFLAGS_IF != 0
+    }
+
+    fn enable_interrupts() {
+        unsafe { asm!("sti", options(nomem, nostack)) };
+    }
+
+    fn disable_interrupts() {
+        unsafe { asm!("cli", options(nomem, nostack)) };
+    }
+
+    fn wait_for_interrupt() {
+        unsafe { asm!("hlt", options(nomem, nostack)) };
+    }
+
+    fn enable_interrupts_and_wait() {
+        // sti only takes effect after the next instruction, so nothing lands before the hlt
+        unsafe { asm!("sti", "hlt", options(nomem, nostack)) };
+    }
+
+    fn cpu_id() -> usize {
+        smp::cpu_id()
+    }
+
+    const FRAME_LAYOUT: FrameLayout = FrameLayout::X86_64;
+
+    #[inline(always)]
+    fn frame_pointer() -> u64 {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        rbp
+    }
+
+    fn hardware_random() -> Option<u64> {
+        rdrand::read()
+    }
+}
+
+impl InterruptController for X86_64 {
+    fn enable_irq(irq: u8) {
+        pic::enable_irq(irq);
+    }
+
+    fn disable_irq(irq: u8) {
+        pic::disable_irq(irq);
+    }
+
+    fn end_of_interrupt(irq: u8) {
+        pic::send_eoi(irq);
+    }
+}
+
+impl Timer for X86_64 {
+    fn start_timer(hz: u32) {
+        PIT_DIVISOR.store(pit::init(hz), Ordering::Relaxed);
+        pic::enable_irq(TIMER_IRQ);
+    }
+
+    fn nanos_since_tick() -> u64 {
+        // The count runs down from the reload value starting when IRQ 0 fires
+        let reload = PIT_DIVISOR.load(Ordering::Relaxed) as u64 * PERIOD_RUNNING.load(Ordering::Relaxed);
+        let elapsed = reload.saturating_sub(pit::read_count() as u64);
+        pit::cycles_to_nanos(elapsed)
+    }
+
+    fn ticks_elapsed() -> u64 {
+        // The PIT switched to the programmed count at the reload that raised this IRQ
+        PERIOD_RUNNING.swap(PERIOD_PROGRAMMED.load(Ordering::Relaxed), Ordering::Relaxed)
+    }
+
+    fn set_tick_period(ticks: u64) -> u64 {
+        let divisor = PIT_DIVISOR.load(Ordering::Relaxed) as u64;
+        if divisor == 0 {
+            return 1;
+        }
+        // The 16-bit count caps a period near 55 ms
+        let ticks = ticks.clamp(1, u16::MAX as u64 / divisor);
+        Self::without_interrupts(|| {
+            let programmed = PERIOD_PROGRAMMED.load(Ordering::Relaxed);
+            if programmed == ticks {
+                return ticks;
+            }
+            // Rewriting the count right at a reload, or with the reload's IRQ
+            // still pending, would leave `ticks_elapsed` unsure which count
+            // the current period uses: keep the old one and let a later call
+            // try again
+            if pic::irq_pending(TIMER_IRQ) || pit::read_count() < RELOAD_GUARD {
+                return programmed;
+            }
+            pit::set_reload((divisor * ticks) as u16);
+            PERIOD_PROGRAMMED.store(ticks, Ordering::Relaxed);
+            ticks
+        })
+    }
+
+    fn end_of_tick() {
+        pic::send_eoi(TIMER_IRQ);
+    }
+
+    fn read_cycles() -> u64 {
+        tsc::read()
+    }
+
+    fn cycles_hz() -> Option<u64> {
+        tsc::stated_hz()
+    }
+
+    fn cycles_invariant() -> bool {
+        tsc::invariant()
+    }
+}
+
+impl Mmu for X86_64 {
+    fn active_table() -> PhysAddr {
+        let cr3: u64;
+        unsafe {
+            asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        }
+        PhysAddr::new(cr3 & CR3_ADDR_MASK)
+    }
+
+    unsafe fn switch_table(root: PhysAddr) {
+        asm!("mov cr3, {}", in(reg) root.as_u64(), options(nostack, preserves_flags));
+    }
+
+    fn flush_page(virt: VirtAddr) {
+        unsafe {
+            asm!("invlpg [{}]", in(reg) virt.as_u64(), options(nostack, preserves_flags));
+        }
+    }
+
+    fn nx_enabled() -> bool {
+        unsafe { msr::read(msr::IA32_EFER) & EFER_NXE != 0 }
+    }
+}
+
+impl ContextSwitch for X86_64 {
+    unsafe fn enter_user(entry: VirtAddr, user_stack: VirtAddr) -> i64 {
+        context::enter(entry.as_u64(), user_stack.as_u64())
+    }
+
+    unsafe fn exit_user(code: i64) -> ! {
+        context::exit(code)
+    }
+}
+
+impl PortIo for X86_64 {
+    unsafe fn inb(port: u16) -> u8 {
+        port::inb(port)
+    }
+
+    unsafe fn outb(port: u16, value: u8) {
+        port::outb(port, value)
+    }
+
+    unsafe fn inw(port: u16) -> u16 {
+        port::inw(port)
+    }
+
+    unsafe fn outw(port: u16, value: u16) {
+        port::outw(port, value)
+    }
+
+    unsafe fn inl(port: u16) -> u32 {
+        port::inl(port)
+    }
+
+    unsafe fn outl(port: u16, value: u32) {
+        port::outl(port, value)
+    }
+}
@@ -0,0 +1,44 @@
+//! Model-specific register (MSR) access
+
+use core::arch::asm;
+
+pub const IA32_APIC_BASE: u32 = 0x1B;
+pub const IA32_EFER: u32 = 0xC000_0080;
+pub const IA32_STAR: u32 = 0xC000_0081;
+pub const IA32_LSTAR: u32 = 0xC000_0082;
+pub const IA32_FMASK: u32 = 0xC000_0084;
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+#[allow(dead_code)]
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// Read a model-specific register
+///
+/// # Safety
+/// `msr` must name a register that exists on this CPU.
+pub unsafe fn read(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags)
+    );
+    ((high as u64) << 32) | low as u64
+}
+
+/// Write a model-specific register
+///
+/// # Safety
+/// `msr` must exist and `value` must be valid for it; many MSRs change
+/// fundamental CPU behavior.
+pub unsafe fn write(msr: u32, value: u64) {
+    asm!(
+        "wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32,
+        options(nostack, preserves_flags)
+    );
+}
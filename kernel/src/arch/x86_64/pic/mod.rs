@@ -1,7 +1,7 @@
 //! PIC (Programmable Interrupt Controller) configuration
 //! Remaps IRQs to avoid conflicts with CPU exceptions
 
-use core::arch::asm;
+use super::port::{inb, outb};
 
 const PIC1_COMMAND: u16 = 0x20;
 const PIC1_DATA: u16 = 0x21;
@@ -12,6 +12,13 @@ const ICW1_INIT: u8 = 0x11;
 const ICW4_8086: u8 = 0x01;
 
 const PIC_EOI: u8 = 0x20;
+// OCW3: the next command port read returns the Interrupt Request Register
+const OCW3_READ_IRR: u8 = 0x0A;
+// OCW3: the next command port read returns the In-Service Register
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// Vector of IRQ 0; IRQ n arrives at `VECTOR_BASE + n`
+pub const VECTOR_BASE: u8 = 32;
 
 /// Remap PIC interrupts to avoid conflicts with CPU exceptions
 /// CPU exceptions use vectors 0-31, so we remap PIC to 32-47
@@ -28,9 +35,9 @@ pub fn init() {
         io_wait();
 
         // Set vector offsets
-        outb(PIC1_DATA, 32); // Master PIC starts at 32
+        outb(PIC1_DATA, VECTOR_BASE); // Master PIC starts at 32
         io_wait();
-        outb(PIC2_DATA, 40); // Slave PIC starts at 40
+        outb(PIC2_DATA, VECTOR_BASE + 8); // Slave PIC starts at 40
         io_wait();
 
         // Configure cascade
@@ -76,6 +83,37 @@ pub fn send_eoi(irq: u8) {
     }
 }
 
+/// True if `irq` has been raised but not yet delivered to the CPU
+pub fn irq_pending(irq: u8) -> bool {
+    let port = if irq < 8 { PIC1_COMMAND } else { PIC2_COMMAND };
+    let irr = unsafe {
+        outb(port, OCW3_READ_IRR);
+        inb(port)
+    };
+    irr & (1 << (irq % 8)) != 0
+}
+
+/// True if `irq` is a spurious IRQ 7 or 15: a request withdrawn before the
+/// CPU took it, which must not be acknowledged (but IRQ 15's cascade through
+/// the master must, so that is done here)
+pub fn spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+    let port = if irq < 8 { PIC1_COMMAND } else { PIC2_COMMAND };
+    let isr = unsafe {
+        outb(port, OCW3_READ_ISR);
+        inb(port)
+    };
+    if isr & (1 << (irq % 8)) != 0 {
+        return false;
+    }
+    if irq == 15 {
+        unsafe { outb(PIC1_COMMAND, PIC_EOI) };
+    }
+    true
+}
+
 #[allow(dead_code)]
 /// Disable all IRQs
 pub fn disable_all() {
@@ -85,28 +123,6 @@ pub fn disable_all() {
     }
 }
 
-#[inline]
-unsafe fn outb(port: u16, value: u8) {
-    asm!(
-        "out dx, al",
-        in("dx") port,
-        in("al") value,
-        options(nomem, nostack, preserves_flags)
-    );
-}
-
-#[inline]
-unsafe fn inb(port: u16) -> u8 {
-    let value: u8;
-    asm!(
-        "in al, dx",
-        out("al") value,
-        in("dx") port,
-        options(nomem, nostack, preserves_flags)
-    );
-    value
-}
-
 #[inline]
 fn io_wait() {
     unsafe {
@@ -0,0 +1,51 @@
+//! PIT (8254 Programmable Interval Timer)
+//! Channel 0 runs as a rate generator and drives IRQ 0, the system tick.
+
+use super::port::{inb, outb};
+
+/// Input clock of the PIT in Hz
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+// Channel 0, lobyte/hibyte access, mode 2 (rate generator), binary counting
+const CMD_CHANNEL0_RATE: u8 = 0b0011_0100;
+// Channel 0, counter latch
+const CMD_CHANNEL0_LATCH: u8 = 0b0000_0000;
+
+/// Program channel 0 to fire at `hz`, returning the reload value used
+pub fn init(hz: u32) -> u16 {
+    let divisor = (PIT_FREQUENCY / hz).clamp(1, u16::MAX as u32) as u16;
+    unsafe {
+        outb(COMMAND, CMD_CHANNEL0_RATE);
+        outb(CHANNEL0_DATA, divisor as u8);
+        outb(CHANNEL0_DATA, (divisor >> 8) as u8);
+    }
+    divisor
+}
+
+/// Change channel 0's reload value without restarting it
+/// In rate generator mode the new count is loaded when the current period ends.
+pub fn set_reload(count: u16) {
+    unsafe {
+        outb(CHANNEL0_DATA, count as u8);
+        outb(CHANNEL0_DATA, (count >> 8) as u8);
+    }
+}
+
+/// Current channel 0 count; it runs down from the reload value to 1
+/// Callers must keep interrupts disabled so the two-byte read isn't split.
+pub fn read_count() -> u16 {
+    unsafe {
+        outb(COMMAND, CMD_CHANNEL0_LATCH);
+        let low = inb(CHANNEL0_DATA) as u16;
+        let high = inb(CHANNEL0_DATA) as u16;
+        (high << 8) | low
+    }
+}
+
+/// Convert PIT input clock cycles to nanoseconds (one cycle is about 838 ns)
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    cycles * 1_000_000_000 / PIT_FREQUENCY as u64
+}
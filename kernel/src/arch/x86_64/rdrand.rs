@@ -0,0 +1,45 @@
+//! RDSEED and RDRAND
+//! RDSEED reads the CPU's entropy source directly; RDRAND reads a generator
+//! the CPU reseeds from it. Both report with the carry flag whether a value
+//! was ready, and run dry when drawn on faster than they refill, so each is
+//! retried a few times before giving up.
+
+use super::cpuid;
+use crate::sync::once::Lazy;
+use core::arch::asm;
+use shared::cpuid::CpuFeatures;
+
+const RETRIES: usize = 10;
+
+static FEATURES: Lazy<CpuFeatures> = Lazy::new(cpuid::features);
+
+/// A random word, from RDSEED if it has one to give, else RDRAND
+pub fn read() -> Option<u64> {
+    if FEATURES.rdseed {
+        if let Some(value) = retry(rdseed) {
+            return Some(value);
+        }
+    }
+    if FEATURES.rdrand {
+        return retry(rdrand);
+    }
+    None
+}
+
+fn retry(read: fn() -> Option<u64>) -> Option<u64> {
+    (0..RETRIES).find_map(|_| read())
+}
+
+fn rdseed() -> Option<u64> {
+    let value: u64;
+    let ready: u8;
+    unsafe { asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) ready, options(nomem, nostack)) };
+    (ready != 0).then_some(value)
+}
+
+fn rdrand() -> Option<u64> {
+    let value: u64;
+    let ready: u8;
+    unsafe { asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ready, options(nomem, nostack)) };
+    (ready != 0).then_some(value)
+}
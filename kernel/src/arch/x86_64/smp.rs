@@ -0,0 +1,242 @@
+//! Symmetric multiprocessing (SMP) bring-up
+//! Starts application processors (APs) via the Limine SMP request, gives each
+//! its own GDT and kernel stack, and parks them until a scheduler exists.
+//! The first AP meanwhile watches the boot CPU for hangs (see `watchdog`).
+
+use super::{fpu, gdt, idt, lapic, msr};
+use crate::limine::{self, LimineSmpInfo};
+use crate::memory::kstack;
+use crate::{info, warn};
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+pub const MAX_CPUS: usize = 16;
+
+const AP_STACK_PAGES: usize = 4; // 16KB kernel stack per AP
+
+/// The CPU that watches the boot CPU for hangs (see `watchdog`), and the
+/// vector and period of its LAPIC timer: about a tenth of a second at
+/// QEMU's 1 GHz bus clock, a second or so on hardware
+const WATCH_CPU: usize = 1;
+pub const WATCH_VECTOR: u8 = 0xf0;
+const WATCH_TIMER_COUNT: u32 = 10_000_000;
+
+/// Per-CPU data block, reachable through the GS base of its CPU
+#[repr(C)]
+pub struct PerCpu {
+    self_ptr: AtomicUsize, // Must stay at offset 0 (read via gs:[0])
+    cpu_id: AtomicUsize,
+    kernel_rsp: AtomicUsize,
+    user_rsp: AtomicUsize,
+    lapic_id: AtomicU32,
+    online: AtomicBool,
+}
+
+/// Field offsets for assembly that addresses per-CPU data through GS
+pub const PERCPU_KERNEL_RSP: usize = offset_of!(PerCpu, kernel_rsp);
+pub const PERCPU_USER_RSP: usize = offset_of!(PerCpu, user_rsp);
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            self_ptr: AtomicUsize::new(0),
+            cpu_id: AtomicUsize::new(0),
+            kernel_rsp: AtomicUsize::new(0),
+            user_rsp: AtomicUsize::new(0),
+            lapic_id: AtomicU32::new(0),
+            online: AtomicBool::new(false),
+        }
+    }
+
+    pub fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Relaxed)
+    }
+
+    pub fn lapic_id(&self) -> u32 {
+        self.lapic_id.load(Ordering::Relaxed)
+    }
+
+    /// Top of the stack used on entry from ring 3
+    pub fn kernel_stack(&self) -> usize {
+        self.kernel_rsp.load(Ordering::Relaxed)
+    }
+
+    /// Stack used on entry from ring 3 (syscall stub and TSS.RSP0)
+    pub fn set_kernel_stack(&self, top: usize) {
+        self.kernel_rsp.store(top, Ordering::Relaxed);
+        gdt::set_privilege_stack(self.cpu_id(), top);
+    }
+}
+
+static CPUS: [PerCpu; MAX_CPUS] = [const { PerCpu::new() }; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+static ONLINE_COUNT: AtomicUsize = AtomicUsize::new(1);
+static PERCPU_READY: AtomicBool = AtomicBool::new(false);
+/// APs Limine started that were never given work: they still spin in its
+/// code, on its stacks, watching its SMP response
+static PARKED_IN_LIMINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Point the calling CPU's GS base at its per-CPU block
+fn install_percpu(cpu: usize, lapic_id: u32) {
+    let percpu = &CPUS[cpu];
+    percpu.self_ptr.store(percpu as *const PerCpu as usize, Ordering::Relaxed);
+    percpu.cpu_id.store(cpu, Ordering::Relaxed);
+    percpu.lapic_id.store(lapic_id, Ordering::Relaxed);
+    percpu.online.store(true, Ordering::Release);
+    unsafe { msr::write(msr::IA32_GS_BASE, percpu as *const PerCpu as u64) };
+}
+
+/// Per-CPU data of the calling CPU
+pub fn current() -> &'static PerCpu {
+    if !PERCPU_READY.load(Ordering::Acquire) {
+        return &CPUS[0];
+    }
+    let ptr: usize;
+    unsafe {
+        core::arch::asm!(
+            "mov {}, gs:[{}]",
+            out(reg) ptr,
+            const offset_of!(PerCpu, self_ptr),
+            options(nostack, preserves_flags, readonly)
+        );
+        &*(ptr as *const PerCpu)
+    }
+}
+
+/// Local APIC ID of CPU `cpu`
+pub fn lapic_id(cpu: usize) -> u32 {
+    CPUS[cpu].lapic_id()
+}
+
+/// Logical index (0 = BSP) of the calling CPU
+pub fn cpu_id() -> usize {
+    current().cpu_id()
+}
+
+/// Number of CPUs reported by the bootloader (capped at MAX_CPUS)
+#[allow(dead_code)]
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Relaxed)
+}
+
+/// Number of CPUs that have finished bring-up
+pub fn online_count() -> usize {
+    ONLINE_COUNT.load(Ordering::Acquire)
+}
+
+/// Number of APs still waiting in Limine's code for a `goto_address`
+/// Their memory is bootloader-reclaimable, so it must not be reused.
+pub fn parked_in_limine() -> usize {
+    PARKED_IN_LIMINE.load(Ordering::Acquire)
+}
+
+/// Set up per-CPU state for the bootstrap processor and start all APs
+pub fn init() {
+    lapic::init_spurious_vector();
+    let response = match limine::SMP_REQUEST.get_response() {
+        Some(response) => response,
+        None => {
+            warn!("  No SMP response from Limine, running on BSP only");
+            install_percpu(0, 0);
+            PERCPU_READY.store(true, Ordering::Release);
+            return;
+        }
+    };
+
+    install_percpu(0, response.bsp_lapic_id);
+    PERCPU_READY.store(true, Ordering::Release);
+
+    // The count includes the BSP
+    let reported = response.cpu_count as usize;
+    if !cfg!(feature = "smp") {
+        info!("  SMP support not built in, running on BSP only");
+        PARKED_IN_LIMINE.store(reported.saturating_sub(1), Ordering::Release);
+        return;
+    }
+
+    if reported > MAX_CPUS {
+        warn!("  {} CPUs reported, only starting {}", reported, MAX_CPUS);
+    }
+
+    // The BSP always takes index 0; APs are numbered in bootloader order
+    let mut next_id = 1;
+    for i in 0..reported {
+        let info = unsafe { &**response.cpus.add(i) };
+        if info.lapic_id == response.bsp_lapic_id {
+            continue;
+        }
+        if next_id >= MAX_CPUS {
+            break;
+        }
+
+        let stack_top = match kstack::allocate("kernel", next_id, AP_STACK_PAGES) {
+            Some(top) => top.as_usize(),
+            None => {
+                warn!("  Out of memory for AP stacks, stopping at {} CPUs", next_id);
+                break;
+            }
+        };
+
+        CPUS[next_id].lapic_id.store(info.lapic_id, Ordering::Relaxed);
+        CPUS[next_id].cpu_id.store(next_id, Ordering::Relaxed);
+        info.extra_argument.store(stack_top as u64, Ordering::Relaxed);
+        info.goto_address.store(ap_entry as *const () as u64, Ordering::SeqCst);
+
+        next_id += 1;
+    }
+    CPU_COUNT.store(next_id, Ordering::Relaxed);
+    PARKED_IN_LIMINE.store(reported.saturating_sub(next_id), Ordering::Release);
+
+    // Wait for every started AP to report in
+    while online_count() < next_id {
+        core::hint::spin_loop();
+    }
+
+    info!("  {} CPU(s) online", online_count());
+}
+
+/// AP entry point: switch from Limine's stack to our own before touching Rust code
+#[unsafe(naked)]
+extern "C" fn ap_entry(_info: *const LimineSmpInfo) -> ! {
+    core::arch::naked_asm!(
+        "mov rsp, [rdi + {stack}]",
+        "xor rbp, rbp",
+        "call {main}",
+        "2:",
+        "hlt",
+        "jmp 2b",
+        stack = const offset_of!(LimineSmpInfo, extra_argument),
+        main = sym ap_main,
+    );
+}
+
+extern "C" fn ap_main(info: &'static LimineSmpInfo) -> ! {
+    let cpu = CPUS
+        .iter()
+        .position(|percpu| percpu.lapic_id.load(Ordering::Relaxed) == info.lapic_id)
+        .unwrap_or(0);
+
+    gdt::init_ap(cpu);
+    idt::load();
+    lapic::init_spurious_vector();
+    super::enable_nx();
+    fpu::init();
+    install_percpu(cpu, info.lapic_id);
+
+    info!("  CPU {} online (LAPIC ID {})", cpu, info.lapic_id);
+    ONLINE_COUNT.fetch_add(1, Ordering::AcqRel);
+    if cpu == WATCH_CPU {
+        lapic::start_periodic_timer(WATCH_VECTOR, WATCH_TIMER_COUNT);
+    }
+
+    park()
+}
+
+/// Idle loop for APs until the scheduler can hand them work
+fn park() -> ! {
+    loop {
+        unsafe {
+            core::arch::asm!("sti; hlt", options(nomem, nostack));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! `syscall`/`sysret` entry path
+//! Programs the STAR/LSTAR/FMASK MSRs and provides the assembly stub that
+//! swaps to the per-CPU kernel stack before calling the Rust dispatcher
+
+use super::gdt::{KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR};
+use super::{msr, smp};
+
+const EFER_SCE: u64 = 1 << 0; // System Call Extensions
+
+// RFLAGS bits cleared on entry: TF, IF, DF, NT, AC
+// A program can set NT with popf; left set, the kernel's next iretq would
+// try a task return and fault.
+const SYSCALL_RFLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 14) | (1 << 18);
+
+/// User register state saved by the entry stub, lowest address first
+/// Arguments follow the System V syscall convention: number in rax,
+/// arguments in rdi, rsi, rdx, r10, r8, r9; result returned in rax. The
+/// callee-saved registers are kept too, so `fork` can start a copy of the
+/// caller from here.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct SyscallFrame {
+    pub rbx: u64,
+    pub rbp: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub rip: u64,    // Saved from rcx by the CPU
+    pub rflags: u64, // Saved from r11 by the CPU
+    pub rsp: u64,
+}
+
+/// Enable `syscall` on the calling CPU, entering the kernel on `stack_top`
+pub fn init(stack_top: usize) {
+    smp::current().set_kernel_stack(stack_top);
+
+    // sysret loads CS = base + 16 and SS = base + 8 (both with RPL 3), which
+    // with base 0x30 selects user code 0x40 and user data 0x38
+    let sysret_base = KERNEL_DATA_SELECTOR as u64;
+    let star = (sysret_base << 48) | ((KERNEL_CODE_SELECTOR as u64) << 32);
+
+    unsafe {
+        msr::write(msr::IA32_STAR, star);
+        msr::write(msr::IA32_LSTAR, syscall_entry as *const () as u64);
+        msr::write(msr::IA32_FMASK, SYSCALL_RFLAGS_MASK);
+        let efer = msr::read(msr::IA32_EFER);
+        msr::write(msr::IA32_EFER, efer | EFER_SCE);
+    }
+}
+
+#[unsafe(naked)]
+extern "C" fn syscall_entry() {
+    core::arch::naked_asm!(
+        // Switch to kernel GS and the per-CPU kernel stack
+        "swapgs",
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{kernel_rsp}]",
+        // Build a SyscallFrame (pushed in reverse field order)
+        "push qword ptr gs:[{user_rsp}]",
+        "push r11",
+        "push rcx",
+        "push r9",
+        "push r8",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rax",
+        "push r15",
+        "push r14",
+        "push r13",
+        "push r12",
+        "push rbp",
+        "push rbx",
+        // Handlers may block waiting for interrupts, so run with IF set
+        "sti",
+        "mov rdi, rsp",
+        "call {dispatch}",
+        "cli",
+        "pop rbx",
+        "pop rbp",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
+        "pop rax",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop r10",
+        "pop r8",
+        "pop r9",
+        "pop rcx",
+        "pop r11",
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        user_rsp = const smp::PERCPU_USER_RSP,
+        kernel_rsp = const smp::PERCPU_KERNEL_RSP,
+        dispatch = sym crate::syscall::dispatch,
+    );
+}
@@ -0,0 +1,26 @@
+//! Time Stamp Counter
+//! Only an invariant TSC ticks at a constant rate through P-state changes
+//! and keeps running in deep C-states; older parts stop or slow it.
+//! Newer CPUs and most hypervisors state its frequency in CPUID; otherwise
+//! `time` measures it against the PIT tick.
+
+use core::arch::asm;
+
+pub fn read() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// TSC frequency in Hz, if CPUID states it
+pub fn stated_hz() -> Option<u64> {
+    super::cpuid::info().tsc_hz()
+}
+
+/// True if CPUID advertises an invariant TSC
+pub fn invariant() -> bool {
+    super::cpuid::features().invariant_tsc
+}
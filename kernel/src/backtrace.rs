@@ -0,0 +1,58 @@
+//! Kernel stack traces for panics and fatal exceptions
+//! Follows the frame pointer chain (see `shared::backtrace`), reading only
+//! kernel-half words the active page table maps, and names each address from
+//! the kernel's own symbol table (`symbols`). Output goes through
+//! `boot_println!`, which takes no locks.
+
+use crate::arch::{Arch, Cpu, Mmu};
+use crate::memory::{paging, VirtAddr};
+use crate::{boot_println, symbols};
+use shared::backtrace::{Demangled, Frames};
+
+/// Lowest kernel address; frames in user memory are never followed
+const KERNEL_HALF_BASE: u64 = 0xffff_8000_0000_0000;
+
+fn read_kernel_word(addr: u64) -> Option<u64> {
+    let virt = VirtAddr::try_new(addr)?;
+    if addr < KERNEL_HALF_BASE || !addr.is_multiple_of(8) || !paging::is_mapped(Arch::active_table(), virt) {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_volatile(addr as *const u64) })
+}
+
+/// Print the call stack above frame pointer `fp`, starting with `pc` (where
+/// execution stopped) if it is known
+pub fn print(pc: Option<u64>, fp: u64) {
+    if symbols::table().is_some() {
+        boot_println!("Backtrace:");
+    } else {
+        boot_println!("Backtrace (no kernel symbol table):");
+    }
+
+    let mut index = 0;
+    if let Some(pc) = pc {
+        print_frame(index, pc, pc);
+        index += 1;
+    }
+    for return_addr in Frames::new(fp, Arch::FRAME_LAYOUT, read_kernel_word) {
+        // The call may be the last instruction of its function, so look up
+        // the byte before the return address
+        print_frame(index, return_addr, return_addr - 1);
+        index += 1;
+    }
+}
+
+/// Print the call stack of the caller
+#[inline(always)]
+pub fn print_here() {
+    print(None, Arch::frame_pointer());
+}
+
+fn print_frame(index: usize, addr: u64, lookup: u64) {
+    match symbols::lookup(lookup) {
+        Some((name, offset)) => {
+            boot_println!("  #{:<2} {:#018x} {}+{:#x}", index, addr, Demangled(name), offset + (addr - lookup))
+        }
+        None => boot_println!("  #{:<2} {:#018x}", index, addr),
+    }
+}
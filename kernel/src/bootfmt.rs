@@ -0,0 +1,30 @@
+//! Allocation-free, lock-safe formatted output for early boot and panics
+//! Formats into a fixed stack buffer first (truncating on overflow), then
+//! writes the bytes to the console's serial port without locking and to the display only if its
+//! lock is free (`console::emit`)
+
+use crate::drivers::console;
+use core::fmt;
+use shared::bootfmt::{self, BootBuffer};
+
+const LINE_CAPACITY: usize = 256;
+
+#[macro_export]
+macro_rules! boot_print {
+    ($($arg:tt)*) => ($crate::bootfmt::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! boot_println {
+    () => ($crate::boot_print!("\n"));
+    ($($arg:tt)*) => ($crate::boot_print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let buffer: BootBuffer<LINE_CAPACITY> = bootfmt::format(args);
+    console::emit(buffer.as_str());
+    if buffer.is_truncated() {
+        console::emit("...\n");
+    }
+}
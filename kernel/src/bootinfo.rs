@@ -0,0 +1,206 @@
+//! What the bootloader told the kernel, in memory the kernel owns
+//! Limine's responses live in bootloader-reclaimable memory and hold HHDM
+//! pointers into more of it. `init` copies everything the kernel needs past
+//! boot (the memory map, the framebuffer, modules, the kernel file and its
+//! command line, where the kernel was loaded, the HHDM offset and the RSDP)
+//! into a static `BootInfo`
+//! first thing in `_start`, so nothing reads Limine's structures afterwards
+//! and that memory can be reclaimed (`memory::bootmem`). File contents stay
+//! where Limine loaded them: the kernel-and-modules entries are never freed.
+//! Only SMP startup and the Limine terminal still use their responses.
+
+use crate::boot_println;
+use crate::limine;
+use crate::sync::once::Once;
+use shared::data_structures::array_string::ArrayString;
+use shared::data_structures::array_vec::ArrayVec;
+
+/// Memory map entries kept; firmware rarely reports more than a few dozen
+pub const MAX_MEMMAP_ENTRIES: usize = 128;
+pub const MAX_MODULES: usize = 16;
+/// Longest module path kept, in bytes
+const MAX_PATH: usize = 128;
+/// Longest kernel command line kept, in bytes
+const MAX_CMDLINE: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct MemoryMapEntry {
+    pub base: u64,
+    pub length: u64,
+    /// One of the `limine::LIMINE_MEMMAP_*` types
+    pub entry_type: u64,
+}
+
+impl MemoryMapEntry {
+    pub fn end(&self) -> u64 {
+        self.base + self.length
+    }
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct FramebufferInfo {
+    /// HHDM address of the first pixel
+    pub address: u64,
+    pub width: u64,
+    pub height: u64,
+    pub pitch: u64,
+    pub bpp: u16,
+}
+
+/// A file Limine loaded alongside the kernel
+#[derive(Clone, Copy)]
+pub struct Module {
+    path: ArrayString<MAX_PATH>,
+    /// HHDM address of the contents
+    address: u64,
+    pub size: u64,
+}
+
+impl Module {
+    /// Path as given in limine.conf (e.g. "/boot/hello.bin")
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Last component of the path ("hello.bin")
+    pub fn name(&self) -> &str {
+        self.path().rsplit('/').next().unwrap_or("")
+    }
+
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.address as *const u8, self.size as usize) }
+    }
+
+    /// The contents, for a module the kernel takes over (a disk image)
+    #[allow(dead_code)]
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.address as *mut u8
+    }
+}
+
+pub struct BootInfo {
+    pub hhdm_offset: u64,
+    /// Where the kernel was loaded, physical and virtual (0 if not reported)
+    pub kernel_physical_base: u64,
+    pub kernel_virtual_base: u64,
+    memory_map: ArrayVec<MemoryMapEntry, MAX_MEMMAP_ENTRIES>,
+    pub framebuffer: Option<FramebufferInfo>,
+    modules: ArrayVec<Module, MAX_MODULES>,
+    /// The kernel's own ELF file
+    pub kernel_file: Option<&'static [u8]>,
+    cmdline: ArrayString<MAX_CMDLINE>,
+    /// Physical address of the ACPI RSDP
+    pub rsdp: Option<u64>,
+}
+
+impl BootInfo {
+    pub fn memory_map(&self) -> &[MemoryMapEntry] {
+        &self.memory_map
+    }
+
+    /// Kernel command line (see `cmdline`)
+    pub fn cmdline(&self) -> &str {
+        &self.cmdline
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules
+    }
+
+    /// The module with path `name`, or whose file name is `name`
+    pub fn module(&self, name: &str) -> Option<&Module> {
+        self.modules().iter().find(|module| module.path() == name || module.name() == name)
+    }
+}
+
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+/// Copy the Limine responses; must run before anything else reads them
+pub fn init() {
+    BOOT_INFO.call_once(copy);
+}
+
+/// The boot information `init` copied
+pub fn get() -> &'static BootInfo {
+    BOOT_INFO.get().expect("bootinfo::init not called")
+}
+
+fn copy() -> BootInfo {
+    let hhdm_offset = limine::HHDM_REQUEST.get_response().expect("Limine HHDM request failed").offset;
+    let mut info = BootInfo {
+        hhdm_offset,
+        kernel_physical_base: 0,
+        kernel_virtual_base: 0,
+        memory_map: ArrayVec::new(),
+        framebuffer: None,
+        modules: ArrayVec::new(),
+        kernel_file: None,
+        cmdline: ArrayString::new(),
+        rsdp: None,
+    };
+
+    if let Some(kernel) = limine::KERNEL_ADDRESS_REQUEST.get_response() {
+        info.kernel_physical_base = kernel.physical_base;
+        info.kernel_virtual_base = kernel.virtual_base;
+    }
+
+    if let Some(memmap) = limine::MEMMAP_REQUEST.get_response() {
+        for entry in memmap.entries() {
+            let entry = MemoryMapEntry { base: entry.base, length: entry.length, entry_type: entry.entry_type };
+            if info.memory_map.push(entry).is_err() {
+                boot_println!("bootinfo: kept {} of {} memory map entries", MAX_MEMMAP_ENTRIES, memmap.entry_count);
+                break;
+            }
+        }
+    }
+
+    if let Some(response) = limine::FRAMEBUFFER_REQUEST.get_response().filter(|response| response.framebuffer_count > 0) {
+        let fb = unsafe { &**response.framebuffers };
+        info.framebuffer = Some(FramebufferInfo {
+            address: fb.address as u64,
+            width: fb.width,
+            height: fb.height,
+            pitch: fb.pitch,
+            bpp: fb.bpp,
+        });
+    }
+
+    if let Some(response) = limine::MODULE_REQUEST.get_response() {
+        for file in response.modules() {
+            let Ok(path) = ArrayString::from(file.path()) else {
+                boot_println!("bootinfo: module path too long, skipped: {}", file.path());
+                continue;
+            };
+            if info.modules.push(Module { path, address: file.address as u64, size: file.size }).is_err() {
+                boot_println!("bootinfo: kept {} of {} modules", MAX_MODULES, response.module_count);
+                break;
+            }
+        }
+    }
+
+    if let Some(file) = limine::KERNEL_FILE_REQUEST.get_response().and_then(|response| response.file()) {
+        info.kernel_file = Some(file.data());
+        let cmdline = file.cmdline();
+        // Cut at an option boundary rather than mid-value
+        let kept = if cmdline.len() > MAX_CMDLINE {
+            let end = cmdline.as_bytes()[..=MAX_CMDLINE].iter().rposition(|&byte| byte == b' ').unwrap_or(0);
+            &cmdline[..end]
+        } else {
+            cmdline
+        };
+        if kept.len() < cmdline.len() {
+            boot_println!("bootinfo: command line cut to {} of {} bytes", kept.len(), cmdline.len());
+        }
+        info.cmdline = ArrayString::from(kept).unwrap_or_default();
+    }
+
+    // An HHDM address before base revision 3, physical from it on
+    info.rsdp = limine::RSDP_REQUEST
+        .get_response()
+        .map(|response| response.address)
+        .filter(|&address| address != 0)
+        .map(|address| address.checked_sub(hhdm_offset).unwrap_or(address));
+
+    info
+}
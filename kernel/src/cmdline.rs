@@ -0,0 +1,72 @@
+//! Kernel command line
+//! Options come from the `cmdline:` line of the boot entry in limine.conf
+//! (`make run CMDLINE="..."`), which Limine passes with the kernel file.
+//! `init` applies the ones that shape the whole kernel; drivers read theirs
+//! with `flag` and `get` as they start. Parsing is in `shared::cmdline`.
+//!
+//! - `loglevel=LEVEL`: least important message echoed (trace .. error)
+//! - `console=all|SINK,...`: console sinks to write to (serial, vga, framebuffer)
+//! - `no-framebuffer`: use the Limine terminal or VGA text mode instead
+//! - `ps2-translate`: keep the PS/2 controller translating to scan code set 1
+//! - `keymap=NAME`: keyboard layout (us, de, fr, dvorak)
+//! - `com1=SETTINGS` .. `com4=`: set up a serial port (`115200` or `9600,7e1`)
+//! - `console-port=comN`, `log-port=comN`: serial port for the console and the log
+
+use crate::bootinfo;
+use crate::drivers::console::{self, Sinks};
+use crate::log::{self, Level};
+use crate::{info, warn};
+use shared::cmdline::Cmdline;
+
+/// Options something reads; any other is reported at boot
+const KNOWN: [&str; 11] = [
+    "loglevel",
+    "console",
+    "no-framebuffer",
+    "ps2-translate",
+    "keymap",
+    "com1",
+    "com2",
+    "com3",
+    "com4",
+    "console-port",
+    "log-port",
+];
+
+pub fn cmdline() -> Cmdline<'static> {
+    Cmdline::new(bootinfo::get().cmdline())
+}
+
+/// Value of `key=value`, `Some("")` for a bare `key`
+pub fn get(key: &str) -> Option<&'static str> {
+    cmdline().get(key)
+}
+
+pub fn flag(name: &str) -> bool {
+    cmdline().flag(name)
+}
+
+/// Apply the kernel-wide options; needs `bootinfo`
+pub fn init() {
+    let cmdline = cmdline();
+    if cmdline.as_str().is_empty() {
+        return;
+    }
+    info!("Command line: {}", cmdline.as_str());
+    for (key, _) in cmdline.options().filter(|(key, _)| !KNOWN.contains(key)) {
+        warn!("cmdline: unknown option '{}'", key);
+    }
+
+    if let Some(name) = cmdline.get("loglevel") {
+        match Level::parse(name) {
+            Some(level) => log::set_echo_level(level),
+            None => warn!("cmdline: unknown log level '{}'", name),
+        }
+    }
+    if let Some(name) = cmdline.get("console") {
+        match Sinks::parse(name) {
+            Some(sinks) => console::select(sinks),
+            None => warn!("cmdline: unknown console '{}'", name),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Kernel console
+//! Output goes to sinks: COM1, the VGA text buffer or the framebuffer. Each
+//! driver registers its sink once the device works (the display is one or
+//! the other, never both), and the console writes everything, `print!`,
+//! the kernel log echo and `boot_println!` alike, to every registered sink
+//! that is also selected. All are selected unless `console=` on the kernel
+//! command line names some. Input comes from the PS/2 keyboard or COM1, so
+//! the shell works on a screen or headless over a serial line
+//! (`qemu -nographic`).
+//! Text colors are set on every sink at once, as ANSI sequences.
+//! Console hotkeys on the keyboard are handled as input is read: Alt+F1 to
+//! Alt+F4 switch virtual terminals, and Shift+PageUp and Shift+PageDown
+//! scroll the one on show through its scrollback.
+
+use super::{keyboard, serial, vga};
+use super::vga::{Color, Sgr};
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use shared::keyboard::{KeyCode, KeyEvent};
+
+/// A device console output can go to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sink {
+    /// COM1
+    Serial,
+    /// VGA text mode
+    Vga,
+    /// The framebuffer, drawn by the kernel or by the Limine terminal
+    Framebuffer,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 3] = [Sink::Serial, Sink::Vga, Sink::Framebuffer];
+
+    /// Name as used by `console=`
+    pub fn name(self) -> &'static str {
+        match self {
+            Sink::Serial => "serial",
+            Sink::Vga => "vga",
+            Sink::Framebuffer => "framebuffer",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of sinks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sinks(u8);
+
+impl Sinks {
+    pub const NONE: Sinks = Sinks(0);
+    pub const ALL: Sinks = Sinks(0b111);
+
+    /// Sinks from a `console=` value: `all`, or names separated by commas
+    pub fn parse(list: &str) -> Option<Sinks> {
+        if list == "all" {
+            return Some(Sinks::ALL);
+        }
+        let mut sinks = Sinks::NONE;
+        for name in list.split(',') {
+            let sink = Sink::ALL.into_iter().find(|sink| sink.name() == name || (name == "fb" && *sink == Sink::Framebuffer))?;
+            sinks.0 |= sink.bit();
+        }
+        Some(sinks)
+    }
+
+    pub fn contains(self, sink: Sink) -> bool {
+        self.0 & sink.bit() != 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Sink> {
+        Sink::ALL.into_iter().filter(move |&sink| self.contains(sink))
+    }
+}
+
+/// Names separated by commas, or "none"
+impl fmt::Display for Sinks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Sinks::NONE {
+            return f.write_str("none");
+        }
+        for (i, sink) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(sink.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// Sinks whose device is ready
+static REGISTERED: AtomicU8 = AtomicU8::new(0);
+
+/// Sinks picked with `console=`
+static SELECTED: AtomicU8 = AtomicU8::new(Sinks::ALL.0);
+
+/// Start writing to `sink` (if selected); called by its driver once the
+/// device is ready
+pub fn register(sink: Sink) {
+    REGISTERED.fetch_or(sink.bit(), Ordering::Relaxed);
+}
+
+/// Write only to `sinks` from now on
+pub fn select(sinks: Sinks) {
+    SELECTED.store(sinks.0, Ordering::Relaxed);
+}
+
+/// Whether `select` leaves `sink` in, ready or not
+pub fn selected(sink: Sink) -> bool {
+    Sinks(SELECTED.load(Ordering::Relaxed)).contains(sink)
+}
+
+/// Sinks output goes to: registered and selected
+pub fn active() -> Sinks {
+    Sinks(REGISTERED.load(Ordering::Relaxed) & SELECTED.load(Ordering::Relaxed))
+}
+
+pub fn enabled(sink: Sink) -> bool {
+    active().contains(sink)
+}
+
+/// Next key from either input device, blocking until there is one
+pub fn read_key() -> KeyEvent {
+    crate::input::wait_for(try_read_key)
+}
+
+/// Next key from either input device, if one is buffered
+/// Keyboard hotkeys never get here: the keyboard driver acts on them as it
+/// decodes keys.
+pub fn try_read_key() -> Option<KeyEvent> {
+    keyboard::try_read_key().or_else(serial::read_key)
+}
+
+/// Act on `key` if it is a console hotkey; false if it is ordinary input
+pub fn hotkey(key: &KeyEvent) -> bool {
+    let modifiers = key.modifiers;
+    if !key.pressed || modifiers.ctrl {
+        return false;
+    }
+    match key.code {
+        KeyCode::F(n) if modifiers.alt => vga::switch_terminal(usize::from(n) - 1),
+        KeyCode::PageUp if modifiers.shift => vga::scroll_terminal(1),
+        KeyCode::PageDown if modifiers.shift => vga::scroll_terminal(-1),
+        _ => false,
+    }
+}
+
+/// True if either input device has buffered input for `read_key`, or keys
+/// have been injected
+pub fn input_pending() -> bool {
+    keyboard::pending() || serial::stats().buffered > 0 || crate::input::pending()
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::drivers::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    for sink in active().iter() {
+        match sink {
+            Sink::Serial => serial::_print(args),
+            Sink::Vga | Sink::Framebuffer => vga::_print(args),
+        }
+    }
+}
+
+/// Write already-formatted `text` to the active sinks without waiting for
+/// a lock, for `boot_println!`: COM1 is written unlocked, and the display
+/// skipped if busy
+pub fn emit(text: &str) {
+    for sink in active().iter() {
+        match sink {
+            Sink::Serial => serial::write_raw(text.as_bytes()),
+            Sink::Vga | Sink::Framebuffer => {
+                vga::try_write_str(text);
+            }
+        }
+    }
+}
+
+/// Colors for console text from now on
+pub fn set_color(foreground: Color, background: Color) {
+    // Also kept with no display, for `color` to report
+    vga::set_color(foreground, background);
+    if enabled(Sink::Serial) {
+        serial::_print(format_args!("{}", Sgr(foreground, background)));
+    }
+}
+
+/// Current foreground and background colors
+pub fn color() -> (Color, Color) {
+    vga::color()
+}
+
+/// Console colors for as long as the guard lives; the previous ones come
+/// back when it is dropped
+pub struct ColorGuard {
+    previous: (Color, Color),
+}
+
+impl ColorGuard {
+    pub fn new(foreground: Color, background: Color) -> Self {
+        let previous = color();
+        set_color(foreground, background);
+        ColorGuard { previous }
+    }
+
+    /// Change only the foreground color
+    pub fn foreground(foreground: Color) -> Self {
+        Self::new(foreground, color().1)
+    }
+}
+
+impl Drop for ColorGuard {
+    fn drop(&mut self) {
+        set_color(self.previous.0, self.previous.1);
+    }
+}
+
+/// `fmt::Write` handle on the console, for code that takes a writer
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        _print(format_args!("{}", s));
+        Ok(())
+    }
+}
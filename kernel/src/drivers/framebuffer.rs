@@ -0,0 +1,96 @@
+//! Framebuffer console and graphics (the `gui` feature)
+//! The Limine framebuffer as a `shared::fbterm` pixel surface, on which the
+//! console draws glyphs in the built-in font or a PSF font given as the boot
+//! module `font`, and `vga::with_framebuffer` hands `shared::graphics`.
+//! Without the feature, the `Framebuffer` console sink uses the Limine
+//! terminal instead.
+
+use crate::bootinfo::FramebufferInfo;
+use crate::sync::once::Once;
+use crate::{info, warn};
+use core::ptr;
+use shared::fbterm::{Font, FontScreen, Psf, Surface, Terminal};
+use shared::mmio::Volatile;
+
+/// File name of the boot module holding a PSF font for the framebuffer
+const FONT_MODULE: &str = "font";
+
+/// The font from the `font` module, once parsed
+static FONT: Once<Psf<'static>> = Once::new();
+
+/// The Limine framebuffer as a pixel surface (32 bpp only; other depths draw nothing)
+pub struct Framebuffer {
+    address: *mut u8,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    bpp: u16,
+}
+
+impl Framebuffer {
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        unsafe { self.address.add(y * self.pitch + x * 4) as *mut u32 }
+    }
+}
+
+impl Surface for Framebuffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if self.bpp == 32 && x < self.width && y < self.height {
+            unsafe { Volatile::from_ptr(self.pixel_ptr(x, y)).write(color) }
+        }
+    }
+
+    fn scroll_up(&mut self, lines: usize, color: u32) {
+        if self.bpp != 32 {
+            return;
+        }
+        let lines = lines.min(self.height);
+        // One memmove of every row kept, padding included: far fewer
+        // accesses to framebuffer memory than moving pixel by pixel
+        unsafe {
+            ptr::copy(self.address.add(lines * self.pitch), self.address, (self.height - lines) * self.pitch);
+        }
+        self.fill_rect(0, self.height - lines, self.width, lines, color);
+    }
+}
+
+/// A terminal drawing on the framebuffer `fb`
+pub fn terminal(fb: &FramebufferInfo) -> Terminal<FontScreen<'static, Framebuffer>> {
+    info!("Using framebuffer: {}x{}, bpp={}", fb.width, fb.height, fb.bpp);
+    let framebuffer = Framebuffer {
+        address: fb.address as *mut u8,
+        width: fb.width as usize,
+        height: fb.height as usize,
+        pitch: fb.pitch as usize,
+        bpp: fb.bpp,
+    };
+    Terminal::new(FontScreen::new(framebuffer, load_font()))
+}
+
+/// The font in the `font` module, or the built-in one if there is none or
+/// it is no good
+fn load_font() -> Font<'static> {
+    let Some(module) = crate::bootinfo::get().module(FONT_MODULE) else {
+        return Font::Builtin;
+    };
+    match Psf::parse(module.data()) {
+        Ok(psf) => {
+            let psf = FONT.call_once(|| psf);
+            let (width, height) = psf.size();
+            info!("Font: {}, {}x{}, {} glyphs", module.path(), width, height, psf.glyph_count());
+            Font::Psf(psf)
+        }
+        Err(e) => {
+            warn!("{}: {}; using the built-in font", module.path(), e);
+            Font::Builtin
+        }
+    }
+}
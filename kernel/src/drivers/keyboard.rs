@@ -1,150 +1,434 @@
 //! PS/2 Keyboard driver
-//! Handles scan codes from PS/2 keyboard controller
+//! `init` self-tests the controller and its keyboard port, then turns the
+//! controller's translation off and has the keyboard send scan code set 2,
+//! which `Decoder::feed_set2` reads. If any of that fails, or the
+//! `ps2-translate` option asks for it, translation stays on and the
+//! keyboard's bytes arrive as set 1, as firmware usually leaves them.
+//! Characters are decoded with the layout `set_keymap` picks (`keymap=` at
+//! boot, `setkeymap` in the shell).
+//! The IRQ handler buffers raw scan codes in a lock-free queue and leaves
+//! decoding them, which tracks modifier state, to deferred work
+//! (`workqueue`). That work also acts on console hotkeys, so they take
+//! effect even while nothing reads input; other keys wait in a queue of
+//! events for `read_key`. Scan codes injected through `input` are decoded
+//! the same way, after the buffered ones, when a reader asks for a key.
+//! Commands to the keyboard itself (`send_command`) are answered through
+//! the same IRQ: while one is outstanding the handler takes its ACK or
+//! Resend instead of buffering it. The lock LEDs follow the decoder's
+//! state, updated by deferred work whenever a lock key changes it.
 
-use crate::arch::x86_64::pic;
+use crate::arch::{Arch, Cpu, PortIo};
+use super::console;
 use crate::sync::spinlock::Spinlock;
+use crate::workqueue::{self, Work};
+use crate::{cmdline, info, input, interrupts, rand, warn};
 use shared::data_structures::ring_buffer::RingBuffer;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use shared::data_structures::spsc::SpscQueue;
+use shared::keyboard::{Decoder, KeyEvent, Typematic};
+use shared::keymap::{self, Keymap};
 
+const KEYBOARD_IRQ: u8 = 1;
 const PS2_DATA_PORT: u16 = 0x60;
 const PS2_STATUS_PORT: u16 = 0x64;
-#[allow(dead_code)]
 const PS2_COMMAND_PORT: u16 = 0x64;
+/// Status: a byte is waiting in the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status: the controller has not yet taken the last byte written to it
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Command: read the configuration byte / write the one that follows
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xa7;
+/// Command: self-test, answered with `SELF_TEST_PASSED`
+const CMD_SELF_TEST: u8 = 0xaa;
+/// Command: test the keyboard port, answered with `PORT_TEST_PASSED`
+const CMD_TEST_PORT1: u8 = 0xab;
+const CMD_DISABLE_PORT1: u8 = 0xad;
+const CMD_ENABLE_PORT1: u8 = 0xae;
+/// Command: pulse output line 0, wired to the CPU reset line
+const CMD_PULSE_RESET: u8 = 0xfe;
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+/// Configuration: the keyboard port raises IRQ 1, the mouse port IRQ 12
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+/// Configuration: translate the keyboard's set 2 into set 1
+const CONFIG_TRANSLATE: u8 = 1 << 6;
+/// Bytes read and dropped at most when emptying the controller
+const FLUSH_LIMIT: usize = 16;
+/// Status reads before writing a command anyway
+const COMMAND_POLLS: usize = 100_000;
+/// Keyboard command: set the lock LEDs to the byte that follows
+const KBD_SET_LEDS: u8 = 0xED;
+/// Keyboard command: switch to the scan code set that follows
+const KBD_SET_SCAN_CODES: u8 = 0xF0;
+/// Keyboard command: set the repeat delay and rate to the byte that follows
+const KBD_SET_TYPEMATIC: u8 = 0xF3;
+/// Keyboard reply: the last byte was taken
+const KBD_ACK: u8 = 0xFA;
+/// Keyboard reply: send the last byte again
+const KBD_RESEND: u8 = 0xFE;
+/// Times a byte is sent before giving up on it
+const KBD_ATTEMPTS: usize = 3;
+
+// Large enough to absorb a paste or a stalled reader (e.g. a user program
+// hogging the CPU); one slot is always kept free by the queue
+const BUFFER_SIZE: usize = 1024;
+
+/// Pushed to only by the IRQ handler, popped only with `DECODER` held
+static KEYBOARD_BUFFER: SpscQueue<u8, BUFFER_SIZE> = SpscQueue::new();
+
+/// Held while decoding, never by the IRQ handler
+static DECODER: Spinlock<Decoder> = Spinlock::new(Decoder::new());
+
+/// Decoded key events, filled with `DECODER` held
+static EVENTS: Spinlock<RingBuffer<KeyEvent, EVENT_QUEUE_SIZE>> = Spinlock::new(RingBuffer::new());
+const EVENT_QUEUE_SIZE: usize = 64;
 
-const BUFFER_SIZE: usize = 256;
+static DECODE: Work = Work::new(decode);
 
-static KEYBOARD_BUFFER: Spinlock<RingBuffer<u8, BUFFER_SIZE>> =
-    Spinlock::new(RingBuffer::new());
+/// Set the keyboard's bytes arrive in, chosen by `init`
+static SCAN_CODE_SET: AtomicU8 = AtomicU8::new(1);
+
+/// Held while a command is talking to the keyboard
+static COMMAND: Spinlock<()> = Spinlock::new(());
+/// Set while a command byte waits for its reply, which the IRQ handler
+/// stores in `REPLY`
+static AWAITING_REPLY: AtomicBool = AtomicBool::new(false);
+static REPLY: AtomicU8 = AtomicU8::new(0);
+
+static UPDATE_LEDS: Work = Work::new(update_leds);
+/// `leds()` bits last sent, or `LEDS_UNKNOWN`
+static LEDS: AtomicU8 = AtomicU8::new(LEDS_UNKNOWN);
+const LEDS_UNKNOWN: u8 = 0xFF;
+/// Last typematic byte sent
+static TYPEMATIC: AtomicU8 = AtomicU8::new(Typematic::DEFAULT.0);
+
+static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+/// Keyboard IRQ and buffer counters
+pub struct KeyboardStats {
+    pub interrupts: u64,
+    pub dropped: u64,
+    pub buffered: usize,
+    pub high_water: usize,
+    pub capacity: usize,
+    pub scan_code_set: u8,
+}
 
 /// Initialize PS/2 keyboard
 pub fn init() {
-    // Enable keyboard IRQ (IRQ1)
-    pic::enable_irq(1);
+    match configure_controller(!cmdline::flag("ps2-translate")) {
+        Ok(2) => info!("Keyboard: scan code set 2"),
+        Ok(set) => info!("Keyboard: scan code set {} (translated)", set),
+        Err(e) => warn!("Keyboard: {}; assuming translated set 1", e),
+    }
+    if let Some(name) = cmdline::get("keymap") {
+        match keymap::by_name(name) {
+            Some(keymap) => set_keymap(keymap),
+            None => warn!("cmdline: unknown keymap '{}'", name),
+        }
+    }
+    if let Err(e) = interrupts::register_irq(KEYBOARD_IRQ, handle_interrupt) {
+        warn!("Keyboard: {}", e);
+    }
 
-    // Flush keyboard buffer
-    unsafe {
-        while (inb(PS2_STATUS_PORT) & 1) != 0 {
-            inb(PS2_DATA_PORT);
+    // Replies need the IRQ, which is not on yet; the LEDs are set (all off,
+    // whatever the firmware left) once the kernel first goes idle
+    workqueue::schedule(&UPDATE_LEDS);
+}
+
+/// Self-test the controller and the keyboard port, then switch the keyboard
+/// to set 2 with translation off if `untranslated`; returns the set its
+/// bytes will arrive in
+/// Runs before IRQ 1 is enabled, so replies are polled for. Whatever fails,
+/// the keyboard port is left enabled, raising IRQ 1, and translated if the
+/// keyboard is not known to be in set 2.
+fn configure_controller(untranslated: bool) -> Result<u8, &'static str> {
+    // Nothing the keyboard (or a mouse) sends may get in between the replies
+    controller_command(CMD_DISABLE_PORT1);
+    controller_command(CMD_DISABLE_PORT2);
+    flush_output();
+    controller_command(CMD_READ_CONFIG);
+    let Some(firmware) = read_polled() else {
+        controller_command(CMD_ENABLE_PORT1);
+        return Err("No PS/2 controller");
+    };
+    let quiet = firmware & !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ | CONFIG_TRANSLATE);
+    let result = test_controller(quiet).and_then(|()| {
+        controller_command(CMD_ENABLE_PORT1);
+        if !untranslated {
+            return Ok(1);
         }
+        send_polled(KBD_SET_SCAN_CODES)?;
+        send_polled(2)?;
+        Ok(2)
+    });
+    // A keyboard that refused set 2 is still in it: keyboards start there
+    let translate = if result == Ok(2) { 0 } else { CONFIG_TRANSLATE };
+    write_config(quiet | CONFIG_PORT1_IRQ | translate);
+    controller_command(CMD_ENABLE_PORT1);
+    flush_output();
+    if let Ok(set) = result {
+        SCAN_CODE_SET.store(set, Ordering::Relaxed);
     }
+    result
 }
 
-/// Handle keyboard interrupt (called from IRQ handler)
-pub fn handle_interrupt() {
-    unsafe {
-        let scan_code = inb(PS2_DATA_PORT);
+/// Run the controller's self-test and keyboard port test, leaving it with
+/// configuration `config`
+fn test_controller(config: u8) -> Result<(), &'static str> {
+    write_config(config);
+    controller_command(CMD_SELF_TEST);
+    if read_polled() != Some(SELF_TEST_PASSED) {
+        return Err("PS/2 controller failed its self-test");
+    }
+    // The self-test resets the configuration on some controllers
+    write_config(config);
+    controller_command(CMD_TEST_PORT1);
+    if read_polled() != Some(PORT_TEST_PASSED) {
+        return Err("PS/2 keyboard port failed its test");
+    }
+    Ok(())
+}
 
-        // Add to buffer
-        KEYBOARD_BUFFER.lock().push(scan_code);
+/// Wait, up to `COMMAND_POLLS` status reads, for the controller to take the
+/// last byte written to it
+fn wait_input_empty() {
+    for _ in 0..COMMAND_POLLS {
+        if unsafe { Arch::inb(PS2_STATUS_PORT) } & STATUS_INPUT_FULL == 0 {
+            break;
+        }
+    }
+}
 
-        // Send EOI
-        pic::send_eoi(1);
+/// Next byte from the controller, if one comes within `COMMAND_POLLS`
+/// status reads; only while IRQ 1 is off, or the handler takes it first
+fn read_polled() -> Option<u8> {
+    for _ in 0..COMMAND_POLLS {
+        if unsafe { Arch::inb(PS2_STATUS_PORT) } & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { Arch::inb(PS2_DATA_PORT) });
+        }
     }
+    None
 }
 
-/// Read a scan code from the buffer
-/// Disables interrupts while holding the lock to prevent deadlock with the
-/// keyboard IRQ handler, which also acquires KEYBOARD_BUFFER.
-pub fn read_scancode() -> Option<u8> {
-    unsafe { core::arch::asm!("cli", options(nostack, preserves_flags)); }
-    let result = KEYBOARD_BUFFER.lock().pop();
-    unsafe { core::arch::asm!("sti", options(nostack, preserves_flags)); }
-    result
+/// Drop whatever the controller has waiting
+fn flush_output() {
+    for _ in 0..FLUSH_LIMIT {
+        unsafe {
+            if Arch::inb(PS2_STATUS_PORT) & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            Arch::inb(PS2_DATA_PORT);
+        }
+    }
+}
+
+fn controller_command(command: u8) {
+    wait_input_empty();
+    unsafe { Arch::outb(PS2_COMMAND_PORT, command) };
+}
+
+fn write_config(config: u8) {
+    controller_command(CMD_WRITE_CONFIG);
+    write_data(config);
+}
+
+/// Reset the machine through the controller, the way PCs always could
+/// Returns if the controller is missing or ignores the command.
+pub fn pulse_reset() {
+    controller_command(CMD_PULSE_RESET);
+}
+
+/// Write `byte` to the keyboard once the controller can take it
+fn write_data(byte: u8) {
+    wait_input_empty();
+    unsafe { Arch::outb(PS2_DATA_PORT, byte) };
 }
 
-/// Read a key (blocking)
-pub fn read_key() -> Option<char> {
-    while let Some(scan_code) = read_scancode() {
-        if let Some(key) = scancode_to_ascii(scan_code) {
-            return Some(key);
+/// Send one byte to the keyboard and poll for its ACK, resending as asked;
+/// `send_command` for before IRQ 1 is enabled
+fn send_polled(byte: u8) -> Result<(), &'static str> {
+    for _ in 0..KBD_ATTEMPTS {
+        write_data(byte);
+        match read_polled() {
+            Some(KBD_ACK) => return Ok(()),
+            Some(KBD_RESEND) => {}
+            Some(_) => return Err("Keyboard refused a command"),
+            None => return Err("Keyboard did not answer"),
         }
     }
-    None
+    Err("Keyboard kept asking for a resend")
 }
 
-/// Convert scan code to ASCII (US keyboard layout, Set 1)
-/// Only handles key press events (not release)
-fn scancode_to_ascii(scan_code: u8) -> Option<char> {
-    // Ignore key release events (bit 7 set)
-    if scan_code & 0x80 != 0 {
-        return None;
-    }
-
-    match scan_code {
-        0x01 => Some('\x1B'), // ESC
-        0x02 => Some('1'),
-        0x03 => Some('2'),
-        0x04 => Some('3'),
-        0x05 => Some('4'),
-        0x06 => Some('5'),
-        0x07 => Some('6'),
-        0x08 => Some('7'),
-        0x09 => Some('8'),
-        0x0A => Some('9'),
-        0x0B => Some('0'),
-        0x0C => Some('-'),
-        0x0D => Some('='),
-        0x0E => Some('\x08'), // Backspace
-        0x0F => Some('\t'),   // Tab
-        0x10 => Some('q'),
-        0x11 => Some('w'),
-        0x12 => Some('e'),
-        0x13 => Some('r'),
-        0x14 => Some('t'),
-        0x15 => Some('y'),
-        0x16 => Some('u'),
-        0x17 => Some('i'),
-        0x18 => Some('o'),
-        0x19 => Some('p'),
-        0x1A => Some('['),
-        0x1B => Some(']'),
-        0x1C => Some('\n'), // Enter
-        0x1E => Some('a'),
-        0x1F => Some('s'),
-        0x20 => Some('d'),
-        0x21 => Some('f'),
-        0x22 => Some('g'),
-        0x23 => Some('h'),
-        0x24 => Some('j'),
-        0x25 => Some('k'),
-        0x26 => Some('l'),
-        0x27 => Some(';'),
-        0x28 => Some('\''),
-        0x29 => Some('`'),
-        0x2B => Some('\\'),
-        0x2C => Some('z'),
-        0x2D => Some('x'),
-        0x2E => Some('c'),
-        0x2F => Some('v'),
-        0x30 => Some('b'),
-        0x31 => Some('n'),
-        0x32 => Some('m'),
-        0x33 => Some(','),
-        0x34 => Some('.'),
-        0x35 => Some('/'),
-        0x39 => Some(' '), // Space
-        _ => None,          // Unsupported key
+/// Send a command and its arguments to the keyboard, each byte once it has
+/// acknowledged the one before
+/// A byte the keyboard asks for again is resent up to `KBD_ATTEMPTS` times.
+/// Waits for the IRQ handler to see each reply, so interrupts must be on;
+/// IRQ 1 can be handled on another CPU.
+pub fn send_command(bytes: &[u8]) -> Result<(), &'static str> {
+    if !Arch::interrupts_enabled() {
+        return Err("Keyboard commands need interrupts enabled");
     }
+    let _command = COMMAND.lock();
+    for &byte in bytes {
+        let mut acknowledged = false;
+        for _ in 0..KBD_ATTEMPTS {
+            REPLY.store(0, Ordering::Relaxed);
+            AWAITING_REPLY.store(true, Ordering::Release);
+            write_data(byte);
+            let mut reply = 0;
+            // Status reads pace the wait, as they do in `write_data`
+            for _ in 0..COMMAND_POLLS {
+                reply = REPLY.load(Ordering::Acquire);
+                if reply != 0 {
+                    break;
+                }
+                unsafe { Arch::inb(PS2_STATUS_PORT) };
+            }
+            AWAITING_REPLY.store(false, Ordering::Release);
+            if reply == KBD_ACK {
+                acknowledged = true;
+                break;
+            }
+            if reply == 0 {
+                return Err("Keyboard did not answer");
+            }
+        }
+        if !acknowledged {
+            return Err("Keyboard kept asking for a resend");
+        }
+    }
+    Ok(())
+}
+
+/// Set how soon and how fast held keys repeat
+pub fn set_typematic(typematic: Typematic) -> Result<(), &'static str> {
+    send_command(&[KBD_SET_TYPEMATIC, typematic.0])?;
+    TYPEMATIC.store(typematic.0, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The repeat setting last sent (the keyboard's default until then)
+pub fn typematic() -> Typematic {
+    Typematic(TYPEMATIC.load(Ordering::Relaxed))
+}
+
+/// Decode keys typed from now on with `keymap`
+pub fn set_keymap(keymap: &'static Keymap) {
+    DECODER.lock().set_keymap(keymap);
 }
 
+/// The layout keys are decoded with
+pub fn keymap() -> &'static Keymap {
+    DECODER.lock().keymap()
+}
+
+/// Bring the lock LEDs in line with the decoder's locks
+fn update_leds() {
+    let leds = DECODER.lock().modifiers().leds();
+    if LEDS.swap(leds, Ordering::Relaxed) == leds {
+        return;
+    }
+    if let Err(e) = send_command(&[KBD_SET_LEDS, leds]) {
+        // Try again on the next change
+        LEDS.store(LEDS_UNKNOWN, Ordering::Relaxed);
+        warn!("Keyboard LEDs: {}", e);
+    }
+}
+
+/// IRQ 1: buffer the scan code the controller has ready, or take the reply
+/// to a command
+fn handle_interrupt() -> bool {
+    unsafe {
+        // The byte must be read even when there is no room for it, or the
+        // controller never raises another IRQ
+        let scan_code = Arch::inb(PS2_DATA_PORT);
+        INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+        if AWAITING_REPLY.load(Ordering::Acquire) && matches!(scan_code, KBD_ACK | KBD_RESEND) {
+            REPLY.store(scan_code, Ordering::Release);
+            return true;
+        }
+        rand::add_event(scan_code as u64);
+
+        // IRQ 1 is handled on one CPU at a time: the queue's one producer
+        if KEYBOARD_BUFFER.push(scan_code) {
+            HIGH_WATER.fetch_max(KEYBOARD_BUFFER.len(), Ordering::Relaxed);
+        } else {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    workqueue::schedule(&DECODE);
+    true
+}
+
+/// Snapshot of the keyboard counters
+pub fn stats() -> KeyboardStats {
+    KeyboardStats {
+        interrupts: INTERRUPTS.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+        buffered: KEYBOARD_BUFFER.len(),
+        high_water: HIGH_WATER.load(Ordering::Relaxed),
+        capacity: KEYBOARD_BUFFER.capacity(),
+        scan_code_set: SCAN_CODE_SET.load(Ordering::Relaxed),
+    }
+}
+
+/// Next key event, blocking until one is typed (or injected)
 #[allow(dead_code)]
-#[inline]
-unsafe fn outb(port: u16, value: u8) {
-    core::arch::asm!(
-        "out dx, al",
-        in("dx") port,
-        in("al") value,
-        options(nomem, nostack, preserves_flags)
-    );
-}
-
-#[inline]
-unsafe fn inb(port: u16) -> u8 {
-    let value: u8;
-    core::arch::asm!(
-        "in al, dx",
-        out("al") value,
-        in("dx") port,
-        options(nomem, nostack, preserves_flags)
-    );
-    value
+pub fn read_key() -> KeyEvent {
+    input::wait_for(try_read_key)
+}
+
+/// Next key event, if a complete one is buffered
+pub fn try_read_key() -> Option<KeyEvent> {
+    if let Some(event) = EVENTS.lock().pop() {
+        return Some(event);
+    }
+    decode();
+    EVENTS.lock().pop()
+}
+
+/// True if scan codes or decoded keys are waiting
+pub fn pending() -> bool {
+    !KEYBOARD_BUFFER.is_empty() || !EVENTS.lock().is_empty()
+}
+
+/// Decode buffered scan codes, then injected ones, until the event queue is
+/// full; runs as deferred work after each IRQ and when a reader finds no key
+fn decode() {
+    // Holding the decoder makes this the scan code queue's one consumer
+    let mut decoder = DECODER.lock();
+    let leds = decoder.modifiers().leds();
+    let set2 = SCAN_CODE_SET.load(Ordering::Relaxed) == 2;
+    let mut decoded = false;
+    while !EVENTS.lock().is_full() {
+        // Injected scan codes are always set 1
+        let event = if let Some(scan_code) = unsafe { KEYBOARD_BUFFER.pop() } {
+            if set2 {
+                decoder.feed_set2(scan_code)
+            } else {
+                decoder.feed(scan_code)
+            }
+        } else if let Some(scan_code) = input::next_scancode() {
+            decoder.feed(scan_code)
+        } else {
+            break;
+        };
+        if let Some(event) = event.filter(|event| !console::hotkey(event)) {
+            EVENTS.lock().push(event);
+            decoded = true;
+        }
+    }
+    if decoder.modifiers().leds() != leds {
+        workqueue::schedule(&UPDATE_LEDS);
+    }
+    drop(decoder);
+    if decoded {
+        input::wake_readers();
+    }
 }
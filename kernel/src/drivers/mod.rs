@@ -1,3 +1,11 @@
+pub mod console;
 pub mod vga;
+#[cfg(feature = "gui")]
+pub mod framebuffer;
+pub mod vt;
 pub mod serial;
 pub mod keyboard;
+pub mod pci;
+pub mod rtc;
+#[cfg(feature = "net")]
+pub mod virtio;
@@ -0,0 +1,188 @@
+//! PCI configuration space
+//! Devices are found by brute-force enumeration through the legacy 0xCF8/0xCFC
+//! configuration mechanism, which every PC chipset and QEMU machine supports.
+// Only the network driver configures devices so far; without it only lspci and hwinfo remain
+#![cfg_attr(not(feature = "net"), allow(dead_code))]
+
+use crate::arch::{Arch, PortIo};
+use crate::sync::spinlock::Spinlock;
+use alloc::vec::Vec;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const VENDOR_NONE: u16 = 0xFFFF;
+
+// Configuration space offsets
+const OFFSET_VENDOR_ID: u8 = 0x00;
+const OFFSET_COMMAND: u8 = 0x04;
+const OFFSET_CLASS: u8 = 0x08;
+const OFFSET_HEADER_TYPE: u8 = 0x0E;
+const OFFSET_BAR0: u8 = 0x10;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+const BAR_IO_SPACE: u32 = 1 << 0;
+
+/// One function of a PCI device
+#[derive(Clone, Copy, Debug)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+}
+
+/// Where a function sits: (bus, device, function)
+type PciAddress = (u8, u8, u8);
+
+/// Devices a driver has claimed, with the driver's name
+static BOUND: Spinlock<Vec<(PciAddress, &'static str)>> = Spinlock::new(Vec::new());
+
+/// Where a base address register points
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bar {
+    Io(u16),
+    Memory(u64),
+}
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    (1 << 31)
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xFC)
+}
+
+fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    unsafe {
+        Arch::outl(CONFIG_ADDRESS, config_address(bus, device, function, offset));
+        Arch::inl(CONFIG_DATA)
+    }
+}
+
+fn write_config(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    unsafe {
+        Arch::outl(CONFIG_ADDRESS, config_address(bus, device, function, offset));
+        Arch::outl(CONFIG_DATA, value);
+    }
+}
+
+impl PciDevice {
+    fn probe(bus: u8, device: u8, function: u8) -> Option<Self> {
+        let id = read_config(bus, device, function, OFFSET_VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == VENDOR_NONE {
+            return None;
+        }
+        Some(PciDevice { bus, device, function, vendor_id, device_id: (id >> 16) as u16 })
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(offset);
+        let new = (old & !(0xFFFF << shift)) | (value as u32) << shift;
+        write_config(self.bus, self.device, self.function, offset, new);
+    }
+
+    /// Base class and subclass codes
+    pub fn class(&self) -> (u8, u8) {
+        let code = self.read_u32(OFFSET_CLASS);
+        ((code >> 24) as u8, (code >> 16) as u8)
+    }
+
+    /// What kind of device this is, from its class codes
+    pub fn class_name(&self) -> &'static str {
+        match self.class() {
+            (0x01, 0x01) => "IDE controller",
+            (0x01, 0x06) => "SATA controller",
+            (0x01, 0x08) => "NVMe controller",
+            (0x01, _) => "Storage controller",
+            (0x02, 0x00) => "Ethernet controller",
+            (0x02, _) => "Network controller",
+            (0x03, 0x00) => "VGA controller",
+            (0x03, _) => "Display controller",
+            (0x04, _) => "Multimedia device",
+            (0x06, 0x00) => "Host bridge",
+            (0x06, 0x01) => "ISA bridge",
+            (0x06, 0x04) => "PCI bridge",
+            (0x06, _) => "Bridge",
+            (0x08, _) => "System peripheral",
+            (0x0C, 0x03) => "USB controller",
+            (0x0C, 0x05) => "SMBus controller",
+            (0x0C, _) => "Serial bus controller",
+            _ => "Other device",
+        }
+    }
+
+    /// Record that `driver` has taken charge of this device
+    pub fn bind(&self, driver: &'static str) {
+        BOUND.lock().push(((self.bus, self.device, self.function), driver));
+    }
+
+    /// The driver bound to this device, if any
+    pub fn driver(&self) -> Option<&'static str> {
+        let address = (self.bus, self.device, self.function);
+        BOUND.lock().iter().find(|(bound, _)| *bound == address).map(|&(_, driver)| driver)
+    }
+
+    /// Decode base address register `index` (0-5)
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        if index > 5 {
+            return None;
+        }
+        let offset = OFFSET_BAR0 + index * 4;
+        let low = self.read_u32(offset);
+        if low & BAR_IO_SPACE != 0 {
+            return Some(Bar::Io((low & 0xFFFC) as u16));
+        }
+        let base = (low & 0xFFFF_FFF0) as u64;
+        // Type 0b10 in bits 1-2 marks a 64-bit BAR that continues in the next one
+        if (low >> 1) & 0b11 == 0b10 && index < 5 {
+            let high = self.read_u32(offset + 4) as u64;
+            return Some(Bar::Memory(base | high << 32));
+        }
+        Some(Bar::Memory(base))
+    }
+
+    /// Let the device decode its BARs and master the bus for DMA
+    pub fn enable(&self) {
+        let command = self.read_u16(OFFSET_COMMAND);
+        self.write_u16(OFFSET_COMMAND, command | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER);
+    }
+}
+
+/// Every present function on every bus
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255u8).flat_map(|bus| {
+        (0..32u8).flat_map(move |device| {
+            // Only look past function 0 on multi-function devices
+            let functions = match PciDevice::probe(bus, device, 0) {
+                Some(dev) if dev.read_u8(OFFSET_HEADER_TYPE) & HEADER_MULTI_FUNCTION != 0 => 8,
+                Some(_) => 1,
+                None => 0,
+            };
+            (0..functions).filter_map(move |function| PciDevice::probe(bus, device, function))
+        })
+    })
+}
+
+/// First function with the given vendor and device ID
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    devices().find(|dev| dev.vendor_id == vendor_id && dev.device_id == device_id)
+}
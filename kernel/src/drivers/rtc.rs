@@ -0,0 +1,77 @@
+//! CMOS real-time clock
+//! The battery-backed clock keeps the date and time while the machine is off.
+//! Its registers are read through an index port and a data port. While the
+//! clock updates itself, once a second, reads can return a mix of the old and
+//! new time, so `read` waits for the update-in-progress flag to clear and
+//! reads every register twice, retrying until both reads agree.
+//! Decoding (BCD, 12-hour time) is in `shared::datetime`.
+
+use crate::arch::{Arch, PortIo};
+use shared::datetime::{DateTime, RtcRegisters, STATUS_B_BINARY};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECOND: u8 = 0x00;
+const REG_MINUTE: u8 = 0x02;
+const REG_HOUR: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+/// Not standard, but where QEMU and most PCs keep it (ACPI names the register)
+const REG_CENTURY: u8 = 0x32;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+
+/// Reads tried before giving up on getting the same time twice
+const MAX_ATTEMPTS: usize = 8;
+/// Polls of the update flag; an update takes under 2 ms
+const MAX_UPDATE_POLLS: usize = 100_000;
+
+fn read_register(register: u8) -> u8 {
+    // Bit 7 of the index would mask NMIs; leave it clear
+    unsafe {
+        Arch::outb(CMOS_INDEX, register);
+        Arch::inb(CMOS_DATA)
+    }
+}
+
+fn read_registers() -> RtcRegisters {
+    for _ in 0..MAX_UPDATE_POLLS {
+        if read_register(REG_STATUS_A) & STATUS_A_UPDATING == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    // Other firmware leaves something else in the century register
+    let century = read_register(REG_CENTURY);
+    let centuries: [u8; 3] = if status_b & STATUS_B_BINARY != 0 { [19, 20, 21] } else { [0x19, 0x20, 0x21] };
+    RtcRegisters {
+        second: read_register(REG_SECOND),
+        minute: read_register(REG_MINUTE),
+        hour: read_register(REG_HOUR),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: centuries.contains(&century).then_some(century),
+        status_b,
+    }
+}
+
+/// The date and time the clock holds (UTC on a PC set up for it, which is
+/// what QEMU provides), or None if it cannot be read
+pub fn read() -> Option<DateTime> {
+    let mut previous = read_registers();
+    for _ in 0..MAX_ATTEMPTS {
+        let current = read_registers();
+        if current == previous {
+            return current.decode();
+        }
+        previous = current;
+    }
+    None
+}
@@ -1,136 +1,469 @@
-//! Serial port driver for COM1 (0x3F8)
-//! Used for debugging output in QEMU
+//! Serial port driver for COM1 to COM4
+//! COM1 is set up at 38400 8N1 first thing at boot, for early output.
+//! `configure` then applies the command line: `comN=SETTINGS` sets up a port
+//! (`shared::serial` parses the settings), and `console-port=` and
+//! `log-port=` pick the port each `Role` writes to; both start on COM1.
+//! The console's port carries console output, as the `Serial` sink of
+//! `console`, and once `enable_interrupts` has run, console input:
+//! received bytes are collected by its IRQ handler into a lock-free queue.
+//! From then on the console's and the log's ports also transmit from
+//! interrupts: writers queue bytes in a software ring and return, and each
+//! THR-empty interrupt refills the 16-byte FIFO from it, so heavy logging
+//! costs no more than copying until the ring fills; then the writer sends
+//! what is queued itself to make room. Before that, and on other ports,
+//! transmit is polled. `write_raw` (panics) sends what is queued first, so
+//! its output is never reordered behind buffered text.
 
+use crate::arch::{Arch, PortIo};
+use crate::interrupts::{self, IrqHandler};
+use crate::sync::console_lock::ConsoleLock;
+use crate::sync::irq_spinlock::IrqSafeSpinlock;
 use crate::sync::spinlock::Spinlock;
+use crate::{cmdline, info, warn};
 use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use shared::bootfmt::{self, BootBuffer};
+use shared::data_structures::ring_buffer::RingBuffer;
+use shared::data_structures::spsc::SpscQueue;
+use shared::keyboard::{KeyEvent, TerminalDecoder};
+use shared::serial::{port_index, LineSettings, PORTS};
 
-const COM1_PORT: u16 = 0x3F8;
+const PORT_COUNT: usize = PORTS.len();
+/// Command line options setting up each port
+const PORT_OPTIONS: [&str; PORT_COUNT] = ["com1", "com2", "com3", "com4"];
+
+const REG_INTERRUPT_ENABLE: u16 = 1;
+const REG_FIFO_CONTROL: u16 = 2;
+const REG_LINE_CONTROL: u16 = 3;
+const REG_MODEM_CONTROL: u16 = 4;
+const REG_LINE_STATUS: u16 = 5;
+const IER_RX_AVAILABLE: u8 = 0x01;
+const IER_TX_EMPTY: u8 = 0x02;
+const LCR_DIVISOR_LATCH: u8 = 0x80;
+/// Enable and clear both FIFOs, receive interrupt at 14 bytes
+const FCR_ENABLE_CLEAR_14: u8 = 0xC7;
+/// DTR, RTS and OUT2, which gates the IRQ line
+const MCR_IRQ_ENABLED: u8 = 0x0B;
+/// Loopback, with RTS, OUT1 and OUT2
+const MCR_LOOPBACK: u8 = 0x1E;
+/// DTR, RTS, OUT1 and OUT2
+const MCR_NORMAL: u8 = 0x0F;
+const LOOPBACK_TEST_BYTE: u8 = 0xAE;
+const LSR_DATA_READY: u8 = 0x01;
+/// Transmit holding register empty: with the FIFO on, the whole FIFO is empty
+const LSR_TX_EMPTY: u8 = 0x20;
+/// Bytes the 16550's transmit FIFO takes at once
+const TX_FIFO_SIZE: usize = 16;
+
+// One slot is always kept free by the queues
+const RX_BUFFER_SIZE: usize = 256;
+const TX_BUFFER_SIZE: usize = 4096;
+
+/// Pushed to only by the IRQ handler, popped only with `DECODER` held
+static RX_BUFFER: SpscQueue<u8, RX_BUFFER_SIZE> = SpscQueue::new();
+static RX_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static RX_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Only used by readers, never by the IRQ handler
+static DECODER: Spinlock<TerminalDecoder> = Spinlock::new(TerminalDecoder::new());
+
+/// Bytes waiting for each port's THR-empty interrupt
+static TX_BUFFERS: [IrqSafeSpinlock<RingBuffer<u8, TX_BUFFER_SIZE>>; PORT_COUNT] =
+    [const { IrqSafeSpinlock::new(RingBuffer::new()) }; PORT_COUNT];
+/// Ports whose transmit goes through `TX_BUFFERS`
+static TX_BUFFERED: [AtomicBool; PORT_COUNT] = [const { AtomicBool::new(false) }; PORT_COUNT];
+static TX_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+/// Times a writer found a ring full and sent from it to make room
+static TX_WAITS: AtomicU64 = AtomicU64::new(0);
+
+/// What a port is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// `print!` output, shell input and panics
+    Console,
+    /// Kernel log echo
+    Log,
+}
+
+impl Role {
+    const ALL: [Role; 2] = [Role::Console, Role::Log];
+
+    /// Command line option choosing the role's port
+    fn option(self) -> &'static str {
+        match self {
+            Role::Console => "console-port",
+            Role::Log => "log-port",
+        }
+    }
+}
+
+/// Port index of each role, COM1 until `configure`
+static ROLE_PORTS: [AtomicU8; Role::ALL.len()] = [AtomicU8::new(0), AtomicU8::new(0)];
+
+fn port_of(role: Role) -> usize {
+    ROLE_PORTS[role as usize].load(Ordering::Relaxed) as usize
+}
+
+/// Serial counters, and the console's port
+pub struct SerialStats {
+    /// COM1 is 1
+    pub port: usize,
+    pub irq: u8,
+    pub settings: Option<LineSettings>,
+    /// Receive interrupts that brought data
+    pub interrupts: u64,
+    pub dropped: u64,
+    pub buffered: usize,
+    pub capacity: usize,
+    /// THR-empty interrupts that refilled a FIFO, on any port
+    pub tx_interrupts: u64,
+    pub tx_waits: u64,
+    /// Bytes queued on the console's port
+    pub tx_queued: usize,
+    pub tx_capacity: usize,
+}
 
 pub struct Serial {
-    initialized: bool,
+    /// How each port that answered was set up
+    settings: [Option<LineSettings>; PORT_COUNT],
 }
 
 impl Serial {
     const fn new() -> Self {
-        Serial {
-            initialized: false,
-        }
+        Serial { settings: [None; PORT_COUNT] }
     }
 
-    pub fn init(&mut self) {
+    /// Program port `index` with `line` and check that a UART answers
+    fn init(&mut self, index: usize, line: LineSettings) -> bool {
+        let (base, _) = PORTS[index];
+        let [divisor_low, divisor_high] = line.divisor().to_le_bytes();
         unsafe {
             // Disable interrupts
-            outb(COM1_PORT + 1, 0x00);
+            Arch::outb(base + REG_INTERRUPT_ENABLE, 0x00);
 
-            // Enable DLAB (set baud rate divisor)
-            outb(COM1_PORT + 3, 0x80);
+            // Set the baud rate divisor through the divisor latch
+            Arch::outb(base + REG_LINE_CONTROL, LCR_DIVISOR_LATCH);
+            Arch::outb(base, divisor_low);
+            Arch::outb(base + 1, divisor_high);
 
-            // Set divisor to 3 (38400 baud)
-            outb(COM1_PORT, 0x03);
-            outb(COM1_PORT + 1, 0x00);
+            // Data bits, parity and stop bits
+            Arch::outb(base + REG_LINE_CONTROL, line.line_control());
 
-            // 8 bits, no parity, one stop bit
-            outb(COM1_PORT + 3, 0x03);
+            Arch::outb(base + REG_FIFO_CONTROL, FCR_ENABLE_CLEAR_14);
+            Arch::outb(base + REG_MODEM_CONTROL, MCR_IRQ_ENABLED);
+
+            // Test the chip: a byte sent in loopback mode must come back
+            Arch::outb(base + REG_MODEM_CONTROL, MCR_LOOPBACK);
+            Arch::outb(base, LOOPBACK_TEST_BYTE);
+            if Arch::inb(base) != LOOPBACK_TEST_BYTE {
+                return false;
+            }
+
+            // Set to normal operation mode
+            Arch::outb(base + REG_MODEM_CONTROL, MCR_NORMAL);
+        }
+        self.settings[index] = Some(line);
+        READY[index].store(true, Ordering::Release);
+        true
+    }
 
-            // Enable FIFO, clear with 14-byte threshold
-            outb(COM1_PORT + 2, 0xC7);
+    /// The port `role` writes to, if it answered
+    fn port(&self, role: Role) -> Option<usize> {
+        let index = port_of(role);
+        self.settings[index].map(|_| index)
+    }
 
-            // IRQs enabled, RTS/DSR set
-            outb(COM1_PORT + 4, 0x0B);
+    fn write_to(&mut self, role: Role, args: fmt::Arguments) {
+        if let Some(index) = self.port(role) {
+            let _ = fmt::write(&mut PortWriter(index), args);
+        }
+    }
+}
 
-            // Set in loopback mode, test the serial chip
-            outb(COM1_PORT + 4, 0x1E);
+/// `fmt::Write` onto one port, with the `SERIAL` lock held
+struct PortWriter(usize);
 
-            // Test serial chip (send byte 0xAE and check if serial returns same byte)
-            outb(COM1_PORT, 0xAE);
+impl fmt::Write for PortWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        transmit(self.0, s.as_bytes());
+        Ok(())
+    }
+}
 
-            // Check if serial is faulty
-            if inb(COM1_PORT) != 0xAE {
-                return;
-            }
+static SERIAL: ConsoleLock<Serial> = ConsoleLock::new(Serial::new());
 
-            // Set to normal operation mode
-            outb(COM1_PORT + 4, 0x0F);
+// Mirrors `Serial::settings` for the lock-free raw path
+static READY: [AtomicBool; PORT_COUNT] = [const { AtomicBool::new(false) }; PORT_COUNT];
 
-            self.initialized = true;
+/// Set up COM1 and, if it answers, register it as a console sink
+pub fn init() {
+    if let Ok(mut serial) = SERIAL.lock_or_reentered() {
+        if serial.init(0, LineSettings::DEFAULT) {
+            super::console::register(super::console::Sink::Serial);
         }
     }
+}
 
-    fn is_transmit_empty(&self) -> bool {
-        unsafe { (inb(COM1_PORT + 5) & 0x20) != 0 }
+/// Apply the serial options of the command line; needs `cmdline`
+pub fn configure() {
+    for (index, option) in PORT_OPTIONS.into_iter().enumerate() {
+        let Some(text) = cmdline::get(option) else {
+            continue;
+        };
+        match LineSettings::parse(text) {
+            Ok(line) => match SERIAL.lock_or_reentered().map(|mut serial| serial.init(index, line)) {
+                Ok(true) => info!("Serial COM{}: {}", index + 1, line),
+                _ => warn!("Serial COM{}: no UART answers", index + 1),
+            },
+            Err(e) => warn!("cmdline: {}={}: {}", option, text, e),
+        }
     }
 
-    pub fn write_byte(&mut self, byte: u8) {
-        if !self.initialized {
-            return;
+    for role in Role::ALL {
+        let Some(name) = cmdline::get(role.option()) else {
+            continue;
+        };
+        let Some(index) = port_index(name) else {
+            warn!("cmdline: unknown serial port '{}'", name);
+            continue;
+        };
+        // A port no `comN=` set up gets the default settings
+        let ready = SERIAL.lock_or_reentered().is_ok_and(|mut serial| {
+            serial.settings[index].is_some() || serial.init(index, LineSettings::DEFAULT)
+        });
+        if !ready {
+            warn!("Serial COM{}: no UART answers, {} stays on COM{}", index + 1, role.option(), port_of(role) + 1);
+            continue;
+        }
+        ROLE_PORTS[role as usize].store(index as u8, Ordering::Relaxed);
+        if role == Role::Console {
+            super::console::register(super::console::Sink::Serial);
         }
+    }
+}
 
-        // Wait for transmit buffer to be empty
-        while !self.is_transmit_empty() {
-            core::hint::spin_loop();
+/// Write to the console's port alone; everything else goes through `console`
+pub fn _print(args: fmt::Arguments) {
+    print(Role::Console, args);
+}
+
+/// Write to the port `role` uses
+pub fn print(role: Role, args: fmt::Arguments) {
+    match SERIAL.lock_or_reentered() {
+        Ok(mut serial) => serial.write_to(role, args),
+        // We interrupted our own print: emit without the lock
+        Err(_) => {
+            let text: BootBuffer<256> = bootfmt::format(args);
+            write_raw_to(port_of(role), text.as_str().as_bytes());
         }
+    }
+}
 
-        unsafe {
-            outb(COM1_PORT, byte);
+/// Whether the kernel log goes to a port of its own rather than the console's
+pub fn log_has_own_port() -> bool {
+    let index = port_of(Role::Log);
+    index != port_of(Role::Console) && READY[index].load(Ordering::Acquire)
+}
+
+/// Write bytes straight to the console's port without taking the SERIAL lock
+/// Only for paths that must not block on a lock that may be held by the code
+/// they interrupted (panics, early boot); output may interleave with `_print`.
+pub fn write_raw(bytes: &[u8]) {
+    write_raw_to(port_of(Role::Console), bytes);
+}
+
+fn write_raw_to(index: usize, bytes: &[u8]) {
+    if !READY[index].load(Ordering::Acquire) {
+        return;
+    }
+
+    // Queued text goes first; if the code we interrupted holds the ring,
+    // ours may come out ahead of it rather than deadlock
+    let queue = TX_BUFFERS[index].try_lock();
+    if let Some(mut queue) = queue {
+        while let Some(byte) = queue.pop() {
+            send_polled(index, byte);
         }
     }
+    for_each_byte(bytes, |byte| send_polled(index, byte));
+}
 
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            self.write_byte(byte);
+/// Call `f` with each byte of `bytes`, newlines as CR LF so raw-mode
+/// terminals (`qemu -nographic`) return to the first column
+fn for_each_byte(bytes: &[u8], mut f: impl FnMut(u8)) {
+    for &byte in bytes {
+        if byte == b'\n' {
+            f(b'\r');
         }
+        f(byte);
     }
 }
 
-impl fmt::Write for Serial {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_string(s);
-        Ok(())
+/// Send `bytes` on port `index`, queued if its transmit is buffered
+fn transmit(index: usize, bytes: &[u8]) {
+    if !TX_BUFFERED[index].load(Ordering::Acquire) {
+        for_each_byte(bytes, |byte| send_polled(index, byte));
+        return;
     }
+    let (base, _) = PORTS[index];
+    let mut queue = TX_BUFFERS[index].lock();
+    for_each_byte(bytes, |byte| {
+        while !queue.push(byte) {
+            TX_WAITS.fetch_add(1, Ordering::Relaxed);
+            wait_tx_empty(base);
+            fill_fifo(base, &mut queue);
+        }
+    });
+    // Start an idle transmitter; its interrupts keep it going
+    if unsafe { Arch::inb(base + REG_LINE_STATUS) } & LSR_TX_EMPTY != 0 {
+        fill_fifo(base, &mut queue);
+    }
+    set_tx_interrupt(base, !queue.is_empty());
 }
 
-static SERIAL: Spinlock<Serial> = Spinlock::new(Serial::new());
+/// Send one byte, waiting for room in the transmitter
+fn send_polled(index: usize, byte: u8) {
+    let (base, _) = PORTS[index];
+    wait_tx_empty(base);
+    unsafe { Arch::outb(base, byte) };
+}
 
-pub fn init() {
-    SERIAL.lock().init();
+fn wait_tx_empty(base: u16) {
+    while unsafe { Arch::inb(base + REG_LINE_STATUS) } & LSR_TX_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
 }
 
-#[macro_export]
-macro_rules! serial_print {
-    ($($arg:tt)*) => ($crate::drivers::serial::_print(format_args!($($arg)*)));
+/// Move up to a FIFO's worth of queued bytes into an empty FIFO
+fn fill_fifo(base: u16, queue: &mut RingBuffer<u8, TX_BUFFER_SIZE>) {
+    for _ in 0..TX_FIFO_SIZE {
+        match queue.pop() {
+            Some(byte) => unsafe { Arch::outb(base, byte) },
+            None => break,
+        }
+    }
 }
 
-#[macro_export]
-macro_rules! serial_println {
-    () => ($crate::serial_print!("\n"));
-    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+/// Turn the THR-empty interrupt on or off, leaving the receive one alone
+fn set_tx_interrupt(base: u16, on: bool) {
+    unsafe {
+        let enabled = Arch::inb(base + REG_INTERRUPT_ENABLE);
+        let wanted = if on { enabled | IER_TX_EMPTY } else { enabled & !IER_TX_EMPTY };
+        if wanted != enabled {
+            Arch::outb(base + REG_INTERRUPT_ENABLE, wanted);
+        }
+    }
 }
 
-#[doc(hidden)]
-pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    SERIAL.lock().write_fmt(args).unwrap();
-}
-
-// x86_64 I/O port operations
-#[inline]
-unsafe fn outb(port: u16, value: u8) {
-    core::arch::asm!(
-        "out dx, al",
-        in("dx") port,
-        in("al") value,
-        options(nomem, nostack, preserves_flags)
-    );
-}
-
-#[inline]
-unsafe fn inb(port: u16) -> u8 {
-    let value: u8;
-    core::arch::asm!(
-        "in al, dx",
-        out("al") value,
-        in("dx") port,
-        options(nomem, nostack, preserves_flags)
-    );
-    value
+/// Handler for each IRQ line a port uses (COM1 and COM3 share 4, COM2 and
+/// COM4 share 3)
+const HANDLERS: [(u8, IrqHandler); 2] = [(3, handle_irq3), (4, handle_irq4)];
+
+fn handle_irq3() -> bool {
+    handle_interrupt(3)
+}
+
+fn handle_irq4() -> bool {
+    handle_interrupt(4)
+}
+
+/// Take input on the console's port, and transmit from interrupts there and
+/// on the log's port (needs the PIC set up)
+pub fn enable_interrupts() {
+    let console = port_of(Role::Console);
+    let mut registered = [false; HANDLERS.len()];
+    for index in [console, port_of(Role::Log)] {
+        if !READY[index].load(Ordering::Acquire) || TX_BUFFERED[index].load(Ordering::Relaxed) {
+            continue;
+        }
+        let (base, irq) = PORTS[index];
+        let Some(slot) = HANDLERS.iter().position(|&(line, _)| line == irq) else {
+            continue;
+        };
+        if !registered[slot] {
+            if let Err(e) = interrupts::register_irq(irq, HANDLERS[slot].1) {
+                warn!("Serial COM{}: {}", index + 1, e);
+                continue;
+            }
+            registered[slot] = true;
+        }
+        TX_BUFFERED[index].store(true, Ordering::Release);
+        if index == console {
+            unsafe { Arch::outb(base + REG_INTERRUPT_ENABLE, IER_RX_AVAILABLE) };
+        }
+    }
+}
+
+/// Serve the ports on line `irq`: move bytes received on the console's
+/// port into the queue and refill transmit FIFOs; false if none needed it
+fn handle_interrupt(irq: u8) -> bool {
+    let mut handled = false;
+    let console = port_of(Role::Console);
+    // The console's port is buffered once `enable_interrupts` turned its
+    // receive interrupt on
+    if PORTS[console].1 == irq && TX_BUFFERED[console].load(Ordering::Acquire) {
+        let (base, _) = PORTS[console];
+        let mut received = false;
+        // Drain the FIFO; the IRQ stays asserted while data is waiting
+        while unsafe { Arch::inb(base + REG_LINE_STATUS) } & LSR_DATA_READY != 0 {
+            received = true;
+            let byte = unsafe { Arch::inb(base) };
+            // Only this line's handler receives, on one CPU at a time: the
+            // queue's one producer
+            if !unsafe { RX_BUFFER.push(byte) } {
+                RX_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if received {
+            RX_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+            crate::input::wake_readers();
+            handled = true;
+        }
+    }
+
+    for (index, &(base, line)) in PORTS.iter().enumerate() {
+        if line != irq || !TX_BUFFERED[index].load(Ordering::Acquire) {
+            continue;
+        }
+        let mut queue = TX_BUFFERS[index].lock();
+        let armed = unsafe { Arch::inb(base + REG_INTERRUPT_ENABLE) } & IER_TX_EMPTY != 0;
+        if armed && unsafe { Arch::inb(base + REG_LINE_STATUS) } & LSR_TX_EMPTY != 0 {
+            TX_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+            fill_fifo(base, &mut queue);
+            set_tx_interrupt(base, !queue.is_empty());
+            handled = true;
+        }
+    }
+    handled
+}
+
+/// Next key typed on the serial terminal
+/// Escape sequences are decoded into navigation keys; see `TerminalDecoder`.
+pub fn read_key() -> Option<KeyEvent> {
+    // Holding the decoder makes this the queue's one consumer
+    let mut decoder = DECODER.lock();
+    while let Some(byte) = unsafe { RX_BUFFER.pop() } {
+        if let Some(event) = decoder.feed(byte) {
+            return Some(event);
+        }
+    }
+    None
+}
+
+/// Snapshot of the receive counters
+pub fn stats() -> SerialStats {
+    let index = port_of(Role::Console);
+    SerialStats {
+        port: index + 1,
+        irq: PORTS[index].1,
+        settings: SERIAL.lock_or_reentered().ok().and_then(|serial| serial.settings[index]),
+        interrupts: RX_INTERRUPTS.load(Ordering::Relaxed),
+        dropped: RX_DROPPED.load(Ordering::Relaxed),
+        buffered: RX_BUFFER.len(),
+        capacity: RX_BUFFER.capacity(),
+        tx_interrupts: TX_INTERRUPTS.load(Ordering::Relaxed),
+        tx_waits: TX_WAITS.load(Ordering::Relaxed),
+        tx_queued: TX_BUFFERS[index].lock().len(),
+        tx_capacity: TX_BUFFER_SIZE - 1,
+    }
 }
@@ -1,34 +1,44 @@
 //! VGA text mode driver
 //! Physical address: 0xB8000
 //! Access through Limine's Higher-Half Direct Map (HHDM)
-
-use crate::sync::spinlock::Spinlock;
-use crate::serial_println;
-use core::fmt;
-use core::ptr;
+//! With a Limine framebuffer and the `gui` feature, text is drawn as glyphs
+//! instead (`framebuffer`). Either way a `shared::fbterm` terminal handles
+//! control characters and escape sequences. Text is UTF-8: the framebuffer
+//! font covers Latin-1, and text mode shows what code page 437 has, '?' for
+//! the rest.
+//! `set_color` picks the colors of text written from then on, whichever
+//! backend draws it. `with_framebuffer` gives graphics (`shared::graphics`)
+//! the same framebuffer.
+//! Once `start_terminals` runs, the display is shared by virtual terminals
+//! (`vt`): printed text goes to the shell's, and `write_log` to the log's.
+//! The display is the `Framebuffer` console sink or the `Vga` one, and is
+//! left alone if `console=` selects neither.
+
+use super::console::{self, Sink};
+#[cfg(feature = "gui")]
+use super::framebuffer::{self, Framebuffer};
+use super::vt::{self, Terminals};
+use crate::memory::PhysAddr;
+use crate::sync::console_lock::ConsoleLock;
+use crate::sync::once::Once;
+use crate::{info, warn};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "gui")]
+use shared::fbterm::{FontScreen, Surface};
+use shared::fbterm::{font, Cell, Screen, Terminal};
+use shared::mmio::Volatile;
 
 const VGA_WIDTH: usize = 80;
 const VGA_HEIGHT: usize = 25;
-const VGA_BUFFER_PHYSICAL: usize = 0xB8000;
-
-// Framebuffer text mode constants
-const CHAR_WIDTH: usize = 8;
-const CHAR_HEIGHT: usize = 16;
-
-// Simple 8x16 bitmap font (subset of printable ASCII)
-// Each character is 16 bytes (1 bit per pixel, 8 pixels wide, 16 pixels tall)
-fn get_char_bitmap(c: u8) -> &'static [u8; 16] {
-    // Minimal bitmap font for common ASCII characters
-    static FONT_DATA: [[u8; 16]; 128] = include!("vga_font.rs");
-
-    if (c as usize) < 128 {
-        &FONT_DATA[c as usize]
-    } else {
-        &FONT_DATA[0] // Default to null character
-    }
-}
+const VGA_BUFFER_PHYSICAL: PhysAddr = PhysAddr::new(0xB8000);
+
+/// Foreground and background until `set_color` says otherwise
+pub const DEFAULT_COLORS: (Color, Color) = (Color::White, Color::Black);
+
+/// Current colors as a `ColorCode`
+static COLOR: AtomicU8 = AtomicU8::new(ColorCode::new(DEFAULT_COLORS.0, DEFAULT_COLORS.1).0);
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Color {
@@ -50,6 +60,57 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    /// In VGA order, so indexed by the value of each
+    const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::Pink,
+        Color::Yellow,
+        Color::White,
+    ];
+
+    /// Index of the same color in the ANSI palette
+    fn ansi(self) -> u8 {
+        swap_red_blue(self as u8)
+    }
+}
+
+/// Convert a color index between the VGA and ANSI palettes, which swap the
+/// red and blue bits
+fn swap_red_blue(index: u8) -> u8 {
+    (index & 0b1010) | (index & 1) << 2 | (index >> 2) & 1
+}
+
+/// ANSI escape sequence (SGR) selecting a foreground and background color
+/// The default colors reset the terminal instead, so one with defaults of
+/// its own (a serial terminal) goes back to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sgr(pub Color, pub Color);
+
+impl fmt::Display for Sgr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if (self.0, self.1) == DEFAULT_COLORS {
+            return f.write_str("\x1b[0m");
+        }
+        let (fg, bg) = (self.0.ansi(), self.1.ansi());
+        let fg = if fg < 8 { 30 + fg } else { 90 + fg - 8 };
+        let bg = if bg < 8 { 40 + bg } else { 100 + bg - 8 };
+        write!(f, "\x1b[{};{}m", fg, bg)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct ColorCode(u8);
@@ -67,365 +128,323 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-impl ScreenChar {
-    fn write(&mut self, value: ScreenChar) {
-        unsafe {
-            ptr::write_volatile(self, value);
-        }
-    }
-
-    fn read(&self) -> ScreenChar {
-        unsafe { ptr::read_volatile(self) }
-    }
-}
-
 struct Buffer {
-    chars: [[ScreenChar; VGA_WIDTH]; VGA_HEIGHT],
-}
-
-// Framebuffer info
-struct FramebufferInfo {
-    address: *mut u8,
-    width: usize,
-    height: usize,
-    pitch: usize,
-    bpp: u16,
+    chars: [[Volatile<ScreenChar>; VGA_WIDTH]; VGA_HEIGHT],
 }
 
-pub struct VgaBuffer {
-    buffer: *mut Buffer,
-    column_position: usize,
-    row_position: usize,
-    color_code: ColorCode,
-    // Limine terminal for fallback
-    limine_terminal: Option<*const crate::limine::LimineTerminal>,
-    limine_write: Option<extern "C" fn(*const crate::limine::LimineTerminal, *const u8, u64)>,
-    // Framebuffer for graphics mode
-    framebuffer: Option<FramebufferInfo>,
+/// Where text goes, chosen once at `init`
+enum VgaBuffer {
+    /// Limine framebuffer, drawn by `shared::fbterm`
+    #[cfg(feature = "gui")]
+    Framebuffer(Terminal<FontScreen<'static, Framebuffer>>),
+    /// Limine terminal, for when there is no framebuffer
+    Limine {
+        terminal: *const crate::limine::LimineTerminal,
+        write: extern "C" fn(*const crate::limine::LimineTerminal, *const u8, u64),
+    },
+    /// Direct VGA text buffer
+    Text(Terminal<TextScreen>),
 }
 
 unsafe impl Send for VgaBuffer {}
 
 impl VgaBuffer {
-    const fn new_uninit() -> Self {
-        VgaBuffer {
-            buffer: ptr::null_mut(),
-            column_position: 0,
-            row_position: 0,
-            color_code: ColorCode::new(Color::White, Color::Black),
-            limine_terminal: None,
-            limine_write: None,
-            framebuffer: None,
-        }
-    }
-
-    pub fn init(&mut self, hhdm_offset: u64) {
-        // Try to use Limine framebuffer first
-        if let Some(fb_response) = crate::limine::FRAMEBUFFER_REQUEST.get_response() {
-            if fb_response.framebuffer_count > 0 {
-                let fb = unsafe { &**fb_response.framebuffers };
-                self.framebuffer = Some(FramebufferInfo {
-                    address: fb.address,
-                    width: fb.width as usize,
-                    height: fb.height as usize,
-                    pitch: fb.pitch as usize,
-                    bpp: fb.bpp,
-                });
-                self.column_position = 0;
-                self.row_position = 0;
-                serial_println!("Using framebuffer: {}x{}, bpp={}", fb.width, fb.height, fb.bpp);
-                return;
+    /// The first display the selected sinks allow; None if there is none
+    fn new(hhdm_offset: u64) -> Option<Self> {
+        if console::selected(Sink::Framebuffer) {
+            // Try to use Limine framebuffer first, unless the command line says not to
+            #[cfg(feature = "gui")]
+            if let Some(fb) = crate::bootinfo::get().framebuffer.filter(|_| !crate::cmdline::flag("no-framebuffer")) {
+                return Some(VgaBuffer::Framebuffer(framebuffer::terminal(&fb)));
             }
-        }
 
-        // Try to use Limine terminal
-        if let Some(term_response) = crate::limine::TERMINAL_REQUEST.get_response() {
-            if term_response.terminal_count > 0 && term_response.write.is_some() {
-                let terminal = unsafe { *term_response.terminals };
-                self.limine_terminal = Some(terminal);
-                self.limine_write = term_response.write;
-                serial_println!("Using Limine terminal for VGA output");
-                return;
+            // Try to use Limine terminal
+            if let Some(term_response) = crate::limine::TERMINAL_REQUEST.get_response() {
+                if let (true, Some(write)) = (term_response.terminal_count > 0, term_response.write) {
+                    info!("Using Limine terminal for VGA output");
+                    return Some(VgaBuffer::Limine { terminal: unsafe { *term_response.terminals }, write });
+                }
             }
         }
 
+        if !console::selected(Sink::Vga) {
+            return None;
+        }
         // Fallback to direct VGA buffer access
-        let vga_virtual = hhdm_offset + VGA_BUFFER_PHYSICAL as u64;
-        self.buffer = vga_virtual as *mut Buffer;
-        self.column_position = 0;
-        self.row_position = 0;
-        self.color_code = ColorCode::new(Color::White, Color::Black);
-        serial_println!("Using direct VGA buffer: phys={:#x}, virt={:#x}", VGA_BUFFER_PHYSICAL, vga_virtual);
+        let vga_virtual = VGA_BUFFER_PHYSICAL.to_virt(hhdm_offset);
+        info!("Using direct VGA buffer: phys={:#x}, virt={:#x}", VGA_BUFFER_PHYSICAL, vga_virtual);
+        Some(VgaBuffer::Text(Terminal::new(TextScreen { buffer: unsafe { &*vga_virtual.as_ptr::<Buffer>() } })))
     }
 
-    fn scroll_fb(&mut self) {
-        if let Some(ref fb) = self.framebuffer {
-            // Copy each row up by one character height (16 pixels)
-            for row in 1..VGA_HEIGHT {
-                for pixel_row in 0..CHAR_HEIGHT {
-                    let src_y = row * CHAR_HEIGHT + pixel_row;
-                    let dst_y = (row - 1) * CHAR_HEIGHT + pixel_row;
-
-                    if src_y < fb.height && dst_y < fb.height {
-                        for x in 0..fb.width {
-                            let src_offset = src_y * fb.pitch + x * (fb.bpp as usize / 8);
-                            let dst_offset = dst_y * fb.pitch + x * (fb.bpp as usize / 8);
-
-                            unsafe {
-                                if fb.bpp == 32 {
-                                    let src_ptr = fb.address.add(src_offset) as *const u32;
-                                    let dst_ptr = fb.address.add(dst_offset) as *mut u32;
-                                    let pixel = ptr::read_volatile(src_ptr);
-                                    ptr::write_volatile(dst_ptr, pixel);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-
-            // Clear the last row
-            for pixel_row in 0..CHAR_HEIGHT {
-                let y = (VGA_HEIGHT - 1) * CHAR_HEIGHT + pixel_row;
-                if y < fb.height {
-                    for x in 0..fb.width {
-                        let offset = y * fb.pitch + x * (fb.bpp as usize / 8);
-                        unsafe {
-                            if fb.bpp == 32 {
-                                let pixel_ptr = fb.address.add(offset) as *mut u32;
-                                ptr::write_volatile(pixel_ptr, 0x00000000); // Black
-                            }
-                        }
-                    }
-                }
-            }
+    fn sink(&self) -> Sink {
+        match self {
+            #[cfg(feature = "gui")]
+            VgaBuffer::Framebuffer(_) => Sink::Framebuffer,
+            VgaBuffer::Limine { .. } => Sink::Framebuffer,
+            VgaBuffer::Text(_) => Sink::Vga,
         }
     }
 
-    fn draw_char_fb(&mut self, c: u8, x: usize, y: usize) {
-        if let Some(ref fb) = self.framebuffer {
-            let bitmap = get_char_bitmap(c);
-
-            for row in 0..CHAR_HEIGHT {
-                let bits = bitmap[row];
-                for col in 0..CHAR_WIDTH {
-                    let pixel_on = (bits & (0x80 >> col)) != 0;
-                    let pixel_x = x * CHAR_WIDTH + col;
-                    let pixel_y = y * CHAR_HEIGHT + row;
-
-                    if pixel_x < fb.width && pixel_y < fb.height {
-                        let offset = pixel_y * fb.pitch + pixel_x * (fb.bpp as usize / 8);
-                        unsafe {
-                            let pixel_ptr = fb.address.add(offset);
-                            // Write white (0xFFFFFF) or black (0x000000)
-                            if fb.bpp == 32 {
-                                let color: u32 = if pixel_on { 0xFFFFFFFF } else { 0x00000000 };
-                                ptr::write_volatile(pixel_ptr as *mut u32, color);
-                            }
-                        }
-                    }
-                }
-            }
+    /// The display as character cells, for virtual terminals to share
+    /// None for the Limine terminal, which keeps its cells to itself.
+    fn screen(&mut self) -> Option<&mut dyn Screen> {
+        match self {
+            #[cfg(feature = "gui")]
+            VgaBuffer::Framebuffer(terminal) => Some(terminal.screen_mut()),
+            VgaBuffer::Limine { .. } => None,
+            VgaBuffer::Text(terminal) => Some(terminal.screen_mut()),
         }
     }
 
-    pub fn write_byte(&mut self, byte: u8) {
-        // Use framebuffer if available
-        if self.framebuffer.is_some() {
-            match byte {
-                b'\n' => {
-                    self.column_position = 0;
-                    self.row_position += 1;
-                    if self.row_position >= VGA_HEIGHT {
-                        self.scroll_fb();
-                        self.row_position = VGA_HEIGHT - 1;
-                    }
-                }
-                b'\x08' => {
-                    // Backspace - move back and clear character
-                    if self.column_position > 0 {
-                        self.column_position -= 1;
-                        self.draw_char_fb(b' ', self.column_position, self.row_position);
-                    }
-                }
-                byte => {
-                    if self.column_position >= VGA_WIDTH {
-                        self.column_position = 0;
-                        self.row_position += 1;
-                        if self.row_position >= VGA_HEIGHT {
-                            self.scroll_fb();
-                            self.row_position = VGA_HEIGHT - 1;
-                        }
-                    }
-                    self.draw_char_fb(byte, self.column_position, self.row_position);
-                    self.column_position += 1;
-                }
-            }
-            return;
-        }
-
-        // Use Limine terminal if available
-        if let (Some(terminal), Some(write_fn)) = (self.limine_terminal, self.limine_write) {
-            let bytes = [byte];
-            write_fn(terminal, bytes.as_ptr(), 1);
-            return;
+    pub fn write_string(&mut self, s: &str) {
+        match self {
+            // Both terminals interpret control and escape sequences themselves
+            #[cfg(feature = "gui")]
+            VgaBuffer::Framebuffer(terminal) => terminal.write_str(s),
+            VgaBuffer::Limine { terminal, write } => write(*terminal, s.as_ptr(), s.len() as u64),
+            VgaBuffer::Text(terminal) => terminal.write_str(s),
         }
+    }
 
-        // Fallback to direct VGA buffer
-        if self.buffer.is_null() {
-            return;
+    pub fn clear(&mut self) {
+        match self {
+            #[cfg(feature = "gui")]
+            VgaBuffer::Framebuffer(terminal) => terminal.clear(),
+            // Limine terminal handles clearing itself, given the ANSI sequence
+            VgaBuffer::Limine { terminal, write } => {
+                let clear_seq = b"\x1B[2J\x1B[H"; // Clear screen + move cursor to home
+                write(*terminal, clear_seq.as_ptr(), clear_seq.len() as u64);
+            }
+            VgaBuffer::Text(terminal) => terminal.clear(),
         }
+    }
+}
 
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= VGA_WIDTH {
-                    self.new_line();
-                }
-
-                let row = self.row_position;
-                let col = self.column_position;
+impl fmt::Write for VgaBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
 
-                let color_code = self.color_code;
-                let buffer = unsafe { &mut *self.buffer };
+/// The VGA text buffer as a screen of cells
+struct TextScreen {
+    buffer: &'static Buffer,
+}
 
-                buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
+impl TextScreen {
+    fn blank(bg: u8) -> ScreenChar {
+        ScreenChar { ascii_character: b' ', color_code: ColorCode(swap_red_blue(bg & 0xF) << 4) }
+    }
+}
 
-                self.column_position += 1;
-            }
-        }
+impl Screen for TextScreen {
+    fn dimensions(&self) -> (usize, usize) {
+        (VGA_WIDTH, VGA_HEIGHT)
     }
 
-    pub fn write_string(&mut self, s: &str) {
-        // Use Limine terminal if available (more efficient)
-        if let (Some(terminal), Some(write_fn)) = (self.limine_terminal, self.limine_write) {
-            write_fn(terminal, s.as_ptr(), s.len() as u64);
-            return;
+    fn put_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        if col < VGA_WIDTH && row < VGA_HEIGHT {
+            let (fg, bg) = (swap_red_blue(cell.fg & 0xF), swap_red_blue(cell.bg & 0xF));
+            self.buffer.chars[row][col].write(ScreenChar {
+                ascii_character: font::to_cp437(cell.ch).unwrap_or(b'?'),
+                color_code: ColorCode(bg << 4 | fg),
+            });
         }
+    }
 
-        // Fallback to direct VGA buffer
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                _ => self.write_byte(0xfe), // Replacement character
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize, bg: u8) {
+        if row < VGA_HEIGHT {
+            for col in from..to.min(VGA_WIDTH) {
+                self.buffer.chars[row][col].write(Self::blank(bg));
             }
         }
     }
 
-    fn new_line(&mut self) {
-        if self.row_position < VGA_HEIGHT - 1 {
-            self.row_position += 1;
-        } else {
-            self.scroll_up();
+    fn scroll_line(&mut self, bg: u8) {
+        for row in 1..VGA_HEIGHT {
+            for col in 0..VGA_WIDTH {
+                let character = self.buffer.chars[row][col].read();
+                self.buffer.chars[row - 1][col].write(character);
+            }
         }
-        self.column_position = 0;
+        self.erase_cells(VGA_HEIGHT - 1, 0, VGA_WIDTH, bg);
     }
+}
 
-    fn scroll_up(&mut self) {
-        if self.buffer.is_null() {
-            return;
-        }
-
-        let buffer = unsafe { &mut *self.buffer };
+/// The display, and the virtual terminals on it once they start
+struct Console {
+    display: VgaBuffer,
+    terminals: Option<Terminals>,
+}
 
-        for row in 1..VGA_HEIGHT {
-            for col in 0..VGA_WIDTH {
-                let character = buffer.chars[row][col].read();
-                buffer.chars[row - 1][col].write(character);
+impl Console {
+    /// Write to terminal `vt`, or before there are terminals, straight to
+    /// the display (dropping what is meant for any terminal but the shell's)
+    fn write(&mut self, vt: usize, args: fmt::Arguments) {
+        match (self.terminals.as_mut(), self.display.screen()) {
+            (Some(terminals), Some(display)) => {
+                let _ = terminals.terminal_mut(vt).write_fmt(args);
+                terminals.render(vt, display);
+            }
+            _ if vt == vt::SHELL => {
+                let _ = self.display.write_fmt(args);
             }
+            _ => {}
         }
-
-        self.clear_row(VGA_HEIGHT - 1);
     }
 
-    fn clear_row(&mut self, row: usize) {
-        if self.buffer.is_null() {
-            return;
+    fn clear(&mut self) {
+        match (self.terminals.as_mut(), self.display.screen()) {
+            (Some(terminals), Some(display)) => {
+                terminals.terminal_mut(vt::SHELL).clear();
+                terminals.render(vt::SHELL, display);
+            }
+            _ => self.display.clear(),
         }
+    }
+}
 
-        let buffer = unsafe { &mut *self.buffer };
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
+/// Empty until `init`, and for good if there is no display to use; output
+/// meanwhile only reaches serial
+static CONSOLE: Once<ConsoleLock<Console>> = Once::new();
 
-        for col in 0..VGA_WIDTH {
-            buffer.chars[row][col].write(blank);
-        }
+/// Pick the display and register it as a console sink; needs `cmdline`
+pub fn init(hhdm_offset: u64) {
+    if !console::selected(Sink::Vga) && !console::selected(Sink::Framebuffer) {
+        return;
     }
+    let Some(display) = VgaBuffer::new(hhdm_offset) else {
+        warn!("No display for the selected console");
+        return;
+    };
+    let sink = display.sink();
+    CONSOLE.call_once(|| ConsoleLock::new(Console { display, terminals: None }));
+    console::register(sink);
+}
 
-    pub fn clear(&mut self) {
-        // Clear framebuffer if available
-        if let Some(ref fb) = self.framebuffer {
-            // Clear entire framebuffer to black
-            for y in 0..fb.height {
-                for x in 0..fb.width {
-                    let offset = y * fb.pitch + x * (fb.bpp as usize / 8);
-                    unsafe {
-                        if fb.bpp == 32 {
-                            let pixel_ptr = fb.address.add(offset) as *mut u32;
-                            ptr::write_volatile(pixel_ptr, 0x00000000); // Black
-                        }
-                    }
-                }
-            }
-            self.column_position = 0;
-            self.row_position = 0;
-            return;
-        }
+/// Split the display into virtual terminals, showing the shell's (blank)
+/// Needs the frame allocator for their scrollback.
+pub fn start_terminals() -> Result<(), &'static str> {
+    let mut console = CONSOLE.get().ok_or("no display")?.lock_or_reentered().map_err(|_| "console busy")?;
+    if console.terminals.is_some() {
+        return Ok(());
+    }
+    let display = console.display.screen().ok_or("the Limine terminal cannot be shared")?;
+    let (cols, rows) = display.dimensions();
+    let mut terminals = Terminals::new(cols, rows)?;
+    display.clear(0);
+    terminals.switch(vt::SHELL, display);
+    console.terminals = Some(terminals);
+    Ok(())
+}
 
-        // Limine terminal handles clearing internally, just reset position
-        if self.limine_terminal.is_some() {
-            // Send ANSI clear screen sequence
-            if let (Some(terminal), Some(write_fn)) = (self.limine_terminal, self.limine_write) {
-                let clear_seq = b"\x1B[2J\x1B[H"; // Clear screen + move cursor to home
-                write_fn(terminal, clear_seq.as_ptr(), clear_seq.len() as u64);
-            }
-            return;
-        }
+/// Show virtual terminal `vt` (counting from 0); false if there is none
+pub fn switch_terminal(vt: usize) -> bool {
+    let Some(Ok(mut console)) = CONSOLE.get().map(ConsoleLock::lock_or_reentered) else {
+        return false;
+    };
+    let Console { display, terminals } = &mut *console;
+    match (terminals.as_mut(), display.screen()) {
+        (Some(terminals), Some(display)) => terminals.switch(vt, display),
+        _ => false,
+    }
+}
 
-        // Fallback to direct VGA buffer
-        for row in 0..VGA_HEIGHT {
-            self.clear_row(row);
+/// Scroll the virtual terminal on show `halves` half screens back into its
+/// scrollback (negative: forward); false if there are no terminals
+pub fn scroll_terminal(halves: isize) -> bool {
+    let Some(Ok(mut console)) = CONSOLE.get().map(ConsoleLock::lock_or_reentered) else {
+        return false;
+    };
+    let Console { display, terminals } = &mut *console;
+    match (terminals.as_mut(), display.screen()) {
+        (Some(terminals), Some(display)) => {
+            let lines = halves * (terminals.rows() / 2).max(1) as isize;
+            terminals.scroll_view(lines, display);
+            true
         }
-        self.column_position = 0;
-        self.row_position = 0;
+        _ => false,
     }
 }
 
-impl fmt::Write for VgaBuffer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_string(s);
-        Ok(())
+/// Whether output goes through the Limine terminal, whose code and state
+/// live in bootloader-reclaimable memory
+pub fn uses_limine_terminal() -> bool {
+    CONSOLE
+        .get()
+        .and_then(|console| console.lock_or_reentered().ok())
+        .is_some_and(|console| matches!(console.display, VgaBuffer::Limine { .. }))
+}
+
+pub fn clear_screen() {
+    if let Some(Ok(mut console)) = CONSOLE.get().map(ConsoleLock::lock_or_reentered) {
+        console.clear();
     }
 }
 
-static VGA_WRITER: Spinlock<VgaBuffer> = Spinlock::new(VgaBuffer::new_uninit());
+/// Colors for text written from now on
+pub fn set_color(foreground: Color, background: Color) {
+    COLOR.store(ColorCode::new(foreground, background).0, Ordering::Relaxed);
+    if let Some(Ok(mut console)) = CONSOLE.get().map(ConsoleLock::lock_or_reentered) {
+        // Every backend understands SGR sequences
+        console.write(vt::SHELL, format_args!("{}", Sgr(foreground, background)));
+    }
+}
 
-pub fn init(hhdm_offset: u64) {
-    VGA_WRITER.lock().init(hhdm_offset);
+/// Current foreground and background colors
+pub fn color() -> (Color, Color) {
+    let code = COLOR.load(Ordering::Relaxed);
+    (Color::ALL[(code & 0xF) as usize], Color::ALL[(code >> 4) as usize])
 }
 
-pub fn clear_screen() {
-    VGA_WRITER.lock().clear();
+/// Run `f` on the framebuffer, if the console draws on one
+/// The console lock is held meanwhile, so text waits until the drawing is
+/// done; text written afterwards may cover it or scroll it away, as may
+/// switching virtual terminals.
+#[cfg(feature = "gui")]
+#[allow(dead_code)]
+pub fn with_framebuffer<R>(f: impl FnOnce(&mut dyn Surface) -> R) -> Option<R> {
+    let mut console = CONSOLE.get()?.lock_or_reentered().ok()?;
+    match &mut console.display {
+        VgaBuffer::Framebuffer(terminal) => Some(f(terminal.screen_mut().surface_mut())),
+        _ => None,
+    }
 }
 
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => ($crate::drivers::vga::_print(format_args!($($arg)*)));
+/// Write a string only if the console lock is free; returns false if skipped
+pub fn try_write_str(s: &str) -> bool {
+    match CONSOLE.get().and_then(ConsoleLock::try_lock) {
+        Some(mut console) => {
+            console.write(vt::SHELL, format_args!("{}", s));
+            true
+        }
+        None => false,
+    }
 }
 
-#[macro_export]
-macro_rules! println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+/// Run `f` on the log's virtual terminal and show the result
+/// Never waits for the console, so it is safe wherever messages are logged;
+/// false if skipped because the console is busy or there are no terminals.
+pub fn try_write_log(f: impl FnOnce(&mut dyn fmt::Write)) -> bool {
+    let Some(mut console) = CONSOLE.get().and_then(ConsoleLock::try_lock) else {
+        return false;
+    };
+    let Console { display, terminals } = &mut *console;
+    match (terminals.as_mut(), display.screen()) {
+        (Some(terminals), Some(display)) => {
+            f(terminals.terminal_mut(vt::LOG));
+            terminals.render(vt::LOG, display);
+            true
+        }
+        _ => false,
+    }
 }
 
-#[doc(hidden)]
+/// Write to the screen; `print!` reaches it through `console`
 pub fn _print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    VGA_WRITER.lock().write_fmt(args).unwrap();
+    // Re-entered from an exception mid-print: skip, the serial copy still goes out
+    if let Some(Ok(mut console)) = CONSOLE.get().map(ConsoleLock::lock_or_reentered) {
+        console.write(vt::SHELL, args);
+    }
 }
@@ -0,0 +1,254 @@
+//! Virtio devices over the legacy PCI transport
+//! Legacy (0.9.5) virtio exposes its registers in an I/O BAR and places each
+//! virtqueue in one physically contiguous, page-aligned block whose frame
+//! number is written to the device. QEMU's virtio-*-pci devices offer it by
+//! default on the i440fx machine.
+
+pub mod net;
+
+use crate::arch::{Arch, PortIo};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::frame_allocator::Zone;
+use crate::memory::{PhysAddr, VirtAddr};
+use core::sync::atomic::{fence, Ordering};
+use core::mem;
+use shared::mmio::Volatile;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+
+// Legacy register offsets in the I/O BAR
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_DEVICE_STATUS: u16 = 0x12;
+/// Device-specific configuration (when MSI-X is disabled)
+const REG_DEVICE_CONFIG: u16 = 0x14;
+
+// Device status bits
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Device may lay out headers and data in any descriptor split
+pub const VIRTIO_F_ANY_LAYOUT: u32 = 1 << 27;
+
+const QUEUE_ALIGN: usize = 4096;
+
+/// Register window of a legacy virtio device
+#[derive(Clone, Copy)]
+pub struct Transport {
+    io_base: u16,
+}
+
+impl Transport {
+    pub fn new(io_base: u16) -> Self {
+        Transport { io_base }
+    }
+
+    /// Reset the device and announce that a driver has found it
+    pub fn begin_init(&self) {
+        self.set_status(0);
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+    }
+
+    /// Accept the subset of `wanted` the device offers, returning it
+    pub fn negotiate(&self, wanted: u32) -> u32 {
+        let offered = unsafe { Arch::inl(self.io_base + REG_DEVICE_FEATURES) };
+        let accepted = offered & wanted;
+        unsafe { Arch::outl(self.io_base + REG_GUEST_FEATURES, accepted) };
+        accepted
+    }
+
+    pub fn finish_init(&self) {
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+    }
+
+    pub fn fail(&self) {
+        self.set_status(STATUS_FAILED);
+    }
+
+    fn set_status(&self, status: u8) {
+        unsafe { Arch::outb(self.io_base + REG_DEVICE_STATUS, status) };
+    }
+
+    pub fn config_u8(&self, offset: u16) -> u8 {
+        unsafe { Arch::inb(self.io_base + REG_DEVICE_CONFIG + offset) }
+    }
+
+    fn notify(&self, queue: u16) {
+        unsafe { Arch::outw(self.io_base + REG_QUEUE_NOTIFY, queue) };
+    }
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A split virtqueue in the legacy memory layout
+pub struct Virtqueue {
+    transport: Transport,
+    index: u16,
+    size: u16,
+    /// Holds the descriptor table and rings, until the queue is dropped
+    #[allow(dead_code)]
+    memory: DmaBuffer,
+    desc: VirtAddr,
+    avail: VirtAddr,
+    used: VirtAddr,
+    free_head: u16,
+    free_count: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    /// Allocate queue `index` at the size the device asks for and hand it over
+    pub fn new(transport: Transport, index: u16) -> Result<Self, &'static str> {
+        let size = unsafe {
+            Arch::outw(transport.io_base + REG_QUEUE_SELECT, index);
+            Arch::inw(transport.io_base + REG_QUEUE_SIZE)
+        };
+        if size == 0 {
+            return Err("Virtqueue not available");
+        }
+
+        let n = size as usize;
+        let avail_offset = n * mem::size_of::<Descriptor>();
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(QUEUE_ALIGN);
+        let bytes = used_offset + (6 + n * mem::size_of::<UsedElement>()).next_multiple_of(QUEUE_ALIGN);
+
+        // The legacy transport takes a 32-bit frame number, which reaches
+        // far past any memory we have
+        let memory = DmaBuffer::new(bytes, Zone::High).map_err(|_| "Out of memory for virtqueue")?;
+        let base = memory.virt();
+
+        // Chain every descriptor into the free list
+        let desc = base.as_mut_ptr::<Descriptor>();
+        for i in 0..size {
+            unsafe { (*desc.add(i as usize)).next = i.wrapping_add(1) };
+        }
+
+        unsafe { Arch::outl(transport.io_base + REG_QUEUE_PFN, (memory.phys().as_u64() / QUEUE_ALIGN as u64) as u32) };
+
+        Ok(Virtqueue {
+            transport,
+            index,
+            size,
+            memory,
+            desc: base,
+            avail: base + avail_offset,
+            used: base + used_offset,
+            free_head: 0,
+            free_count: size,
+            last_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Post a chain of buffers as (physical address, length, device-writable)
+    /// Returns the head descriptor, which `pop_used` reports back on completion.
+    pub fn push(&mut self, buffers: &[(PhysAddr, u32, bool)]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free_count as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut index = head;
+        for (i, &(addr, len, writable)) in buffers.iter().enumerate() {
+            let desc = self.descriptor(index);
+            let mut flags = if writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            unsafe {
+                (*desc).addr = addr.as_u64();
+                (*desc).len = len;
+                (*desc).flags = flags;
+            }
+            if i + 1 < buffers.len() {
+                index = unsafe { (*desc).next };
+            }
+        }
+        self.free_head = unsafe { (*self.descriptor(index)).next };
+        self.free_count -= buffers.len() as u16;
+
+        // Publish the chain in the available ring, then bump its index
+        let (avail_idx, ring) = unsafe {
+            let avail = self.avail.as_mut_ptr::<u16>();
+            (Volatile::from_ptr(avail.add(1)), avail.add(2))
+        };
+        let idx = avail_idx.read();
+        unsafe { Volatile::from_ptr(ring.add((idx % self.size) as usize)).write(head) };
+        fence(Ordering::SeqCst);
+        avail_idx.write(idx.wrapping_add(1));
+        Some(head)
+    }
+
+    /// Tell the device new buffers are available
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        self.transport.notify(self.index);
+    }
+
+    /// Take the next chain the device finished with, as (head, bytes written),
+    /// returning its descriptors to the free list
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { Volatile::from_ptr(self.used.as_mut_ptr::<u16>().add(1)).read() };
+        if used_idx == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        let slot = (self.last_used % self.size) as usize;
+        let element = unsafe { Volatile::from_ptr((self.used + 4usize).as_mut_ptr::<UsedElement>().add(slot)).read() };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = element.id as u16;
+        let mut tail = head;
+        let mut count = 1;
+        while unsafe { (*self.descriptor(tail)).flags } & DESC_F_NEXT != 0 {
+            tail = unsafe { (*self.descriptor(tail)).next };
+            count += 1;
+        }
+        unsafe { (*self.descriptor(tail)).next = self.free_head };
+        self.free_head = head;
+        self.free_count += count;
+
+        Some((head, element.len))
+    }
+
+    fn descriptor(&self, index: u16) -> *mut Descriptor {
+        unsafe { self.desc.as_mut_ptr::<Descriptor>().add(index as usize) }
+    }
+}
+
+impl Drop for Virtqueue {
+    fn drop(&mut self) {
+        // Detach the queue from the device before its memory is freed
+        unsafe {
+            Arch::outw(self.transport.io_base + REG_QUEUE_SELECT, self.index);
+            Arch::outl(self.transport.io_base + REG_QUEUE_PFN, 0);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! virtio-net driver
+//! One receive and one transmit queue over fixed DMA buffers. There is no IRQ
+//! routing yet, so received frames are collected by `poll()` from the shell's
+//! idle loop and handed to the registered callback.
+
+use super::{Transport, Virtqueue, VIRTIO_F_ANY_LAYOUT, VIRTIO_VENDOR_ID};
+use crate::drivers::pci::{self, Bar};
+use crate::memory::dma::DmaBuffer;
+use crate::memory::frame_allocator::Zone;
+use crate::memory::PhysAddr;
+use crate::sync::spinlock::Spinlock;
+use crate::{info, println};
+
+/// Transitional virtio-net PCI device ID (legacy interface)
+const DEVICE_ID_LEGACY_NET: u16 = 0x1000;
+
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// Legacy `virtio_net_hdr` without mergeable receive buffers
+const NET_HDR_LEN: usize = 10;
+/// Largest Ethernet frame without the FCS
+pub const MAX_FRAME_LEN: usize = 1514;
+
+const BUFFER_SIZE: usize = 2048;
+const RX_BUFFERS: usize = 16;
+const TX_BUFFERS: usize = 16;
+
+pub type ReceiveCallback = fn(&[u8]);
+
+struct VirtioNet {
+    rx: Virtqueue,
+    tx: Virtqueue,
+    /// RX buffers first, then TX buffers
+    buffers: DmaBuffer,
+    mac: [u8; 6],
+    /// Descriptor head posted for each RX buffer
+    rx_heads: [u16; RX_BUFFERS],
+    /// Descriptor head of each TX buffer in flight, None if the buffer is free
+    tx_heads: [Option<u16>; TX_BUFFERS],
+}
+
+static NIC: Spinlock<Option<VirtioNet>> = Spinlock::new(None);
+static RX_CALLBACK: Spinlock<Option<ReceiveCallback>> = Spinlock::new(None);
+
+impl VirtioNet {
+    fn buffer(&self, index: usize) -> PhysAddr {
+        self.buffers.phys_at(index * BUFFER_SIZE)
+    }
+
+    /// Contents of buffer `index`, header included
+    fn buffer_mut(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..][..BUFFER_SIZE]
+    }
+
+    fn post_rx(&mut self, index: usize) -> Result<(), &'static str> {
+        let buffer = (self.buffer(index), BUFFER_SIZE as u32, true);
+        self.rx_heads[index] = self.rx.push(&[buffer]).ok_or("RX queue full")?;
+        Ok(())
+    }
+
+    /// Copy the next received frame into `frame`, returning its length
+    fn receive(&mut self, frame: &mut [u8; MAX_FRAME_LEN]) -> Option<usize> {
+        loop {
+            let (head, written) = self.rx.pop_used()?;
+            let index = self.rx_heads.iter().position(|&h| h == head)?;
+
+            let len = (written as usize).saturating_sub(NET_HDR_LEN).min(MAX_FRAME_LEN);
+            frame[..len].copy_from_slice(&self.buffer_mut(index)[NET_HDR_LEN..][..len]);
+
+            // Hand the buffer straight back; there are always as many descriptors as buffers
+            let _ = self.post_rx(index);
+            self.rx.notify();
+            if len > 0 {
+                return Some(len);
+            }
+        }
+    }
+
+    /// Mark TX buffers the device has finished sending as free again
+    fn reclaim_tx(&mut self) {
+        while let Some((head, _)) = self.tx.pop_used() {
+            if let Some(slot) = self.tx_heads.iter_mut().find(|h| **h == Some(head)) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), &'static str> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err("Frame too large");
+        }
+        self.reclaim_tx();
+        let slot = self.tx_heads.iter().position(|h| h.is_none()).ok_or("Transmit queue full")?;
+
+        let dest = self.buffer_mut(RX_BUFFERS + slot);
+        // Zeroed header: no checksum offload, no segmentation
+        dest[..NET_HDR_LEN].fill(0);
+        dest[NET_HDR_LEN..][..frame.len()].copy_from_slice(frame);
+
+        let buffer = (self.buffer(RX_BUFFERS + slot), (NET_HDR_LEN + frame.len()) as u32, false);
+        self.tx_heads[slot] = Some(self.tx.push(&[buffer]).ok_or("Transmit queue full")?);
+        self.tx.notify();
+        Ok(())
+    }
+}
+
+/// Find a virtio-net device and bring it up
+pub fn init() -> Result<(), &'static str> {
+    let device = pci::find(VIRTIO_VENDOR_ID, DEVICE_ID_LEGACY_NET).ok_or("No virtio-net device")?;
+    let io_base = match device.bar(0) {
+        Some(Bar::Io(port)) => port,
+        _ => return Err("virtio-net BAR0 is not an I/O BAR"),
+    };
+    device.enable();
+
+    let transport = Transport::new(io_base);
+    transport.begin_init();
+    let features = transport.negotiate(VIRTIO_NET_F_MAC | VIRTIO_F_ANY_LAYOUT);
+
+    let nic = setup(transport, features);
+    let nic = match nic {
+        Ok(nic) => nic,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    transport.finish_init();
+
+    let mac = nic.mac;
+    println!(
+        "virtio-net: MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    info!("  virtio-net at {:02x}:{:02x}.{} io {:#x}", device.bus, device.device, device.function, io_base);
+    device.bind("virtio-net");
+    *NIC.lock() = Some(nic);
+    Ok(())
+}
+
+fn setup(transport: Transport, features: u32) -> Result<VirtioNet, &'static str> {
+    let rx = Virtqueue::new(transport, RX_QUEUE)?;
+    let tx = Virtqueue::new(transport, TX_QUEUE)?;
+    if (rx.size() as usize) < RX_BUFFERS || (tx.size() as usize) < TX_BUFFERS {
+        return Err("virtio-net queues too small");
+    }
+
+    let buffers = DmaBuffer::new((RX_BUFFERS + TX_BUFFERS) * BUFFER_SIZE, Zone::High)
+        .map_err(|_| "Out of memory for network buffers")?;
+
+    // Without the MAC feature the device has no fixed address; use a locally administered one
+    let mut mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    if features & VIRTIO_NET_F_MAC != 0 {
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = transport.config_u8(i as u16);
+        }
+    }
+
+    let mut nic = VirtioNet {
+        rx,
+        tx,
+        buffers,
+        mac,
+        rx_heads: [0; RX_BUFFERS],
+        tx_heads: [None; TX_BUFFERS],
+    };
+    for index in 0..RX_BUFFERS {
+        nic.post_rx(index)?;
+    }
+    nic.rx.notify();
+    Ok(nic)
+}
+
+/// Register the function that receives every incoming Ethernet frame
+pub fn set_receive_callback(callback: ReceiveCallback) {
+    *RX_CALLBACK.lock() = Some(callback);
+}
+
+/// Deliver any received frames to the callback (frames are dropped without one)
+pub fn poll() {
+    let callback = *RX_CALLBACK.lock();
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    loop {
+        // Release the device before the callback runs, since it may transmit
+        let len = match NIC.lock().as_mut() {
+            Some(nic) => match nic.receive(&mut frame) {
+                Some(len) => len,
+                None => return,
+            },
+            None => return,
+        };
+        if let Some(callback) = callback {
+            callback(&frame[..len]);
+        }
+    }
+}
+
+/// Queue one Ethernet frame (destination MAC onwards, no FCS) for sending
+pub fn transmit(frame: &[u8]) -> Result<(), &'static str> {
+    match NIC.lock().as_mut() {
+        Some(nic) => nic.transmit(frame),
+        None => Err("No network device"),
+    }
+}
+
+/// MAC address of the network device, if there is one
+pub fn mac_address() -> Option<[u8; 6]> {
+    NIC.lock().as_ref().map(|nic| nic.mac)
+}
@@ -0,0 +1,90 @@
+//! Virtual terminals
+//! Several text terminals share the display, one shown at a time. Each keeps
+//! its own cells, cursor, colors and scrollback in a `TextGrid` and goes on
+//! taking output while hidden; switching to one redraws the display from its
+//! grid. The shell writes to `SHELL` and the kernel log to `LOG`.
+//! The grids live in frames from the frame allocator, so the terminals only
+//! start once it is up (`vga::start_terminals`).
+
+use crate::memory::frame_allocator::{self, FrameOwner, FRAME_SIZE};
+use crate::memory::phys_to_virt;
+use shared::fbterm::{Cell, Screen, Terminal, TextGrid};
+
+/// Terminals there are, switched with Alt+F1 to Alt+F<COUNT>
+pub const COUNT: usize = 4;
+pub const SHELL: usize = 0;
+pub const LOG: usize = 1;
+
+/// Lines each terminal keeps above its screen
+const SCROLLBACK_LINES: usize = 500;
+
+pub struct Terminals {
+    terminals: [Terminal<TextGrid<'static>>; COUNT],
+    /// The one on the display
+    active: usize,
+}
+
+impl Terminals {
+    /// `COUNT` blank terminals of `cols` x `rows` cells, `SHELL` on show
+    pub fn new(cols: usize, rows: usize) -> Result<Self, &'static str> {
+        if cols == 0 || rows == 0 {
+            return Err("display has no room for text");
+        }
+        let per_terminal = cols * (rows + SCROLLBACK_LINES);
+        let bytes = per_terminal * COUNT * core::mem::size_of::<Cell>();
+        let frames = bytes.div_ceil(FRAME_SIZE);
+        let phys = frame_allocator::allocate_contiguous_frames(frames, FrameOwner::Console)
+            .ok_or("out of memory for terminal scrollback")?;
+
+        // Kept for as long as the kernel runs; a char is not valid for any
+        // bytes, so every cell is written before the slice is made
+        let base = phys_to_virt(phys).as_mut_ptr::<Cell>();
+        let cells: &'static mut [Cell] = unsafe {
+            for index in 0..per_terminal * COUNT {
+                base.add(index).write(Cell::BLANK);
+            }
+            core::slice::from_raw_parts_mut(base, per_terminal * COUNT)
+        };
+        let mut grids = cells.chunks_exact_mut(per_terminal);
+        let terminals = core::array::from_fn(|_| {
+            let grid = grids.next().and_then(|cells| TextGrid::new(cells, cols, rows));
+            Terminal::new(grid.expect("grid sized for the screen"))
+        });
+        Ok(Terminals { terminals, active: SHELL })
+    }
+
+    /// Rows of text on each terminal's screen
+    pub fn rows(&self) -> usize {
+        self.terminals[SHELL].size().1
+    }
+
+    /// Terminal `vt`, to write to; `render` shows what was written
+    pub fn terminal_mut(&mut self, vt: usize) -> &mut Terminal<TextGrid<'static>> {
+        &mut self.terminals[vt]
+    }
+
+    /// Bring `display` up to date with terminal `vt`, if it is the one shown
+    pub fn render(&mut self, vt: usize, display: &mut dyn Screen) {
+        if vt == self.active {
+            self.terminals[vt].screen_mut().render(display);
+        }
+    }
+
+    /// Show terminal `vt`; false if there is no such terminal
+    pub fn switch(&mut self, vt: usize, display: &mut dyn Screen) -> bool {
+        if vt >= COUNT {
+            return false;
+        }
+        self.active = vt;
+        self.terminals[vt].screen_mut().redraw_all();
+        self.render(vt, display);
+        true
+    }
+
+    /// Scroll the shown terminal's view `lines` back into its scrollback
+    /// (negative: forward); its next output returns it to the live screen
+    pub fn scroll_view(&mut self, lines: isize, display: &mut dyn Screen) {
+        self.terminals[self.active].screen_mut().scroll_view(lines);
+        self.render(self.active, display);
+    }
+}
@@ -0,0 +1,12 @@
+//! Build-time feature flags
+//! Cargo features decide which subsystems are compiled in; this table lets
+//! the running kernel report them.
+
+/// Every optional subsystem as (cargo feature, description, compiled in)
+pub const FEATURES: &[(&str, &str, bool)] = &[
+    ("net", "Network drivers and protocol stack", cfg!(feature = "net")),
+    ("storage", "Block devices and FAT32", cfg!(feature = "storage")),
+    ("gui", "Framebuffer console and graphics", cfg!(feature = "gui")),
+    ("smp", "Application processor startup", cfg!(feature = "smp")),
+    ("debugging", "Frame ownership tags, object counts, automatic page table checks", cfg!(feature = "debugging")),
+];
@@ -0,0 +1,40 @@
+//! Disk images
+//! Mounts FAT32 images handed over as boot modules, each through its own
+//! sector cache.
+
+use super::{fat32, vfs};
+use crate::bootinfo::{self, Module};
+use crate::storage::cache::SectorCache;
+use crate::sync::kref::{KRef, ObjectKind};
+use crate::storage::ramdisk::RamDisk;
+use crate::{error, println};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
+/// Blocks cached per FAT32 volume (8 KB with 512-byte sectors)
+const FAT_CACHE_BLOCKS: usize = 16;
+
+/// Mount every boot module holding FAT32 at /disk, /disk1, ...
+pub fn mount_fat_modules() {
+    let mut disks = 0;
+    for module in bootinfo::get().modules().iter().filter(|module| fat32::probe(module.data())) {
+        let target = if disks == 0 { String::from("/disk") } else { format!("/disk{}", disks) };
+        match mount_fat_module(module, &target) {
+            Ok(()) => {
+                println!("FAT32: {} mounted at {}", module.path(), target);
+                disks += 1;
+            }
+            Err(e) => error!("  Failed to mount {}: {}", module.path(), e),
+        }
+    }
+}
+
+fn mount_fat_module(module: &Module, target: &str) -> Result<(), &'static str> {
+    // The module is ours alone once loaded, so writes can go straight to it
+    let disk = unsafe { RamDisk::from_raw(module.as_mut_ptr(), module.size as usize) };
+    let cache = SectorCache::new(Box::new(disk), FAT_CACHE_BLOCKS);
+    let fs = fat32::Fat32::new(Box::new(cache))?;
+    vfs::mkdir(target).map_err(|e| e.as_str())?;
+    vfs::mount(target, KRef::new(ObjectKind::FileSystem, fs)).map_err(|e| e.as_str())
+}
@@ -0,0 +1,836 @@
+//! FAT32 filesystem
+//! Read/write FAT32 with long file names on top of any `BlockDevice`.
+//! There is no RTC yet, so every timestamp written is 2000-01-01, and the
+//! FSInfo free-cluster count is invalidated on the first allocation so other
+//! systems recount it instead of trusting a stale value.
+
+use super::vfs::{DirEntry, FileSystem, FsError, FsResult, Inode, InodeKind};
+use crate::storage::block::BlockDevice;
+use crate::sync::kref::{KRef, KWeak, ObjectKind};
+use crate::sync::spinlock::Spinlock;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use shared::bytes::le;
+use shared::fat::{
+    self, BiosParameterBlock, ShortEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_LONG_NAME,
+    ATTR_VOLUME_ID, CLUSTER_END, CLUSTER_FREE, DIR_ENTRY_SIZE, ENTRY_DELETED, ENTRY_END,
+    FAT_ENTRY_MASK, FIRST_DATA_CLUSTER, LFN_CHARS_PER_ENTRY, LFN_MAX_CHARS,
+};
+
+const FIXED_DATE: u16 = (20 << 9) | (1 << 5) | 1; // 2000-01-01 in FAT date format
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FREE_COUNT_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// Where a file's short directory entry lives
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct EntryLocation {
+    dir_cluster: u32,
+    index: u32,
+}
+
+/// A parsed directory entry with its long name resolved
+struct FoundEntry {
+    name: String,
+    short: ShortEntry,
+    /// Index of the short entry
+    index: u32,
+    /// Index of the first LFN entry (or the short entry if there is none)
+    first_index: u32,
+}
+
+/// Mutable volume state; every disk access happens with this locked
+struct VolumeState {
+    device: Box<dyn BlockDevice>,
+    bpb: BiosParameterBlock,
+    blocks_per_sector: u64,
+    sector: Vec<u8>,
+    next_free: u32,
+    fs_info_invalidated: bool,
+}
+
+struct Volume {
+    state: Spinlock<VolumeState>,
+    root_cluster: u32,
+    inodes: Spinlock<BTreeMap<EntryLocation, KWeak<FatInode>>>,
+}
+
+impl VolumeState {
+    fn load_sector(&mut self, sector: u32) -> FsResult<()> {
+        let lba = sector as u64 * self.blocks_per_sector;
+        self.device.read_blocks(lba, &mut self.sector).map_err(|_| FsError::Io)
+    }
+
+    fn store_sector(&mut self, sector: u32) -> FsResult<()> {
+        let lba = sector as u64 * self.blocks_per_sector;
+        self.device.write_blocks(lba, &self.sector).map_err(|_| FsError::Io)
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= FIRST_DATA_CLUSTER && cluster < self.bpb.cluster_count() + FIRST_DATA_CLUSTER
+    }
+
+    fn fat_get(&mut self, cluster: u32) -> FsResult<u32> {
+        let (sector, offset) = self.bpb.fat_entry_location(cluster, 0);
+        self.load_sector(sector)?;
+        Ok(le::read_u32(&self.sector, offset) & FAT_ENTRY_MASK)
+    }
+
+    /// Set a FAT entry in every FAT copy, keeping the reserved top 4 bits
+    fn fat_set(&mut self, cluster: u32, value: u32) -> FsResult<()> {
+        for copy in 0..self.bpb.fat_count {
+            let (sector, offset) = self.bpb.fat_entry_location(cluster, copy);
+            self.load_sector(sector)?;
+            let old = le::read_u32(&self.sector, offset);
+            le::write_u32(&mut self.sector, offset, (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK));
+            self.store_sector(sector)?;
+        }
+        Ok(())
+    }
+
+    /// Cluster following `cluster` in its chain, or None at the end
+    fn chain_next(&mut self, cluster: u32) -> FsResult<Option<u32>> {
+        let next = self.fat_get(cluster)?;
+        if fat::is_end_of_chain(next) {
+            Ok(None)
+        } else if self.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FsError::Io) // Free, bad, or out-of-range cluster inside a chain
+        }
+    }
+
+    fn chain_length(&mut self, first: u32) -> FsResult<usize> {
+        if first == 0 {
+            return Ok(0);
+        }
+        let limit = self.bpb.cluster_count() as usize;
+        let mut count = 1;
+        let mut cluster = first;
+        while let Some(next) = self.chain_next(cluster)? {
+            cluster = next;
+            count += 1;
+            if count > limit {
+                return Err(FsError::Io); // Loop in the chain
+            }
+        }
+        Ok(count)
+    }
+
+    fn invalidate_fs_info(&mut self) -> FsResult<()> {
+        if self.fs_info_invalidated {
+            return Ok(());
+        }
+        self.fs_info_invalidated = true;
+
+        let sector = self.bpb.fs_info_sector as u32;
+        if sector == 0 || sector == 0xFFFF {
+            return Ok(());
+        }
+        self.load_sector(sector)?;
+        let lead = le::read_u32(&self.sector, 0);
+        let strukt = le::read_u32(&self.sector, 484);
+        if lead == FSINFO_LEAD_SIGNATURE && strukt == FSINFO_STRUCT_SIGNATURE {
+            le::write_u32(&mut self.sector, FSINFO_FREE_COUNT, FREE_COUNT_UNKNOWN);
+            self.store_sector(sector)?;
+        }
+        Ok(())
+    }
+
+    /// Allocate a zeroed cluster and append it to the chain ending at `prev`
+    fn allocate_cluster(&mut self, prev: Option<u32>) -> FsResult<u32> {
+        self.invalidate_fs_info()?;
+
+        let count = self.bpb.cluster_count();
+        let start = self.next_free.max(FIRST_DATA_CLUSTER);
+        let mut found = None;
+        for i in 0..count {
+            let cluster = FIRST_DATA_CLUSTER + (start - FIRST_DATA_CLUSTER + i) % count;
+            if self.fat_get(cluster)? == CLUSTER_FREE {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FsError::NoSpace)?;
+
+        self.fat_set(cluster, CLUSTER_END)?;
+        if let Some(prev) = prev {
+            self.fat_set(prev, cluster)?;
+        }
+        self.next_free = cluster + 1;
+
+        self.sector.fill(0);
+        let first_sector = self.bpb.cluster_to_sector(cluster);
+        for s in 0..self.bpb.sectors_per_cluster as u32 {
+            self.store_sector(first_sector + s)?;
+        }
+        Ok(cluster)
+    }
+
+    fn free_chain(&mut self, first: u32) -> FsResult<()> {
+        if first == 0 {
+            return Ok(());
+        }
+        self.invalidate_fs_info()?;
+
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            cluster = self.chain_next(current)?;
+            self.fat_set(current, CLUSTER_FREE)?;
+        }
+        self.next_free = self.next_free.min(first);
+        Ok(())
+    }
+
+    /// Grow the chain starting at `*first` so it covers at least `len` bytes
+    fn ensure_capacity(&mut self, first: &mut u32, len: usize) -> FsResult<()> {
+        let needed = len.div_ceil(self.bpb.cluster_size());
+        if needed == 0 {
+            return Ok(());
+        }
+        if *first == 0 {
+            *first = self.allocate_cluster(None)?;
+        }
+
+        let mut last = *first;
+        let mut count = 1;
+        while let Some(next) = self.chain_next(last)? {
+            last = next;
+            count += 1;
+        }
+        while count < needed {
+            last = self.allocate_cluster(Some(last))?;
+            count += 1;
+        }
+        Ok(())
+    }
+
+    /// Cut the chain at `*first` down to `len` bytes, freeing the rest
+    fn shrink_chain(&mut self, first: &mut u32, len: usize) -> FsResult<()> {
+        let keep = len.div_ceil(self.bpb.cluster_size());
+        if keep == 0 {
+            self.free_chain(*first)?;
+            *first = 0;
+            return Ok(());
+        }
+
+        let mut last = *first;
+        for _ in 1..keep {
+            last = self.chain_next(last)?.ok_or(FsError::Io)?;
+        }
+        if let Some(rest) = self.chain_next(last)? {
+            self.fat_set(last, CLUSTER_END)?;
+            self.free_chain(rest)?;
+        }
+        Ok(())
+    }
+
+    /// Visit the sectors backing bytes [offset, offset + len) of a chain
+    /// `op` gets the sector, the offset within it, and the matching range of
+    /// the caller's buffer. The chain must already be long enough.
+    fn chain_io(
+        &mut self,
+        first: u32,
+        offset: usize,
+        len: usize,
+        mut op: impl FnMut(&mut Self, u32, usize, core::ops::Range<usize>) -> FsResult<()>,
+    ) -> FsResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let cluster_size = self.bpb.cluster_size();
+        let sector_size = self.bpb.bytes_per_sector as usize;
+
+        let mut cluster = first;
+        for _ in 0..offset / cluster_size {
+            cluster = self.chain_next(cluster)?.ok_or(FsError::Io)?;
+        }
+
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let in_cluster = pos % cluster_size;
+            let sector = self.bpb.cluster_to_sector(cluster) + (in_cluster / sector_size) as u32;
+            let in_sector = in_cluster % sector_size;
+            let chunk = (sector_size - in_sector).min(end - pos);
+
+            op(self, sector, in_sector, pos - offset..pos - offset + chunk)?;
+
+            pos += chunk;
+            if pos < end && pos.is_multiple_of(cluster_size) {
+                cluster = self.chain_next(cluster)?.ok_or(FsError::Io)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_chain(&mut self, first: u32, offset: usize, buf: &mut [u8]) -> FsResult<()> {
+        self.chain_io(first, offset, buf.len(), |vol, sector, in_sector, range| {
+            vol.load_sector(sector)?;
+            let len = range.len();
+            buf[range].copy_from_slice(&vol.sector[in_sector..in_sector + len]);
+            Ok(())
+        })
+    }
+
+    fn write_chain(&mut self, first: u32, offset: usize, data: &[u8]) -> FsResult<()> {
+        let sector_size = self.bpb.bytes_per_sector as usize;
+        self.chain_io(first, offset, data.len(), |vol, sector, in_sector, range| {
+            // Partial sectors need a read-modify-write
+            if range.len() != sector_size {
+                vol.load_sector(sector)?;
+            }
+            let len = range.len();
+            vol.sector[in_sector..in_sector + len].copy_from_slice(&data[range]);
+            vol.store_sector(sector)
+        })
+    }
+
+    fn zero_chain(&mut self, first: u32, offset: usize, len: usize) -> FsResult<()> {
+        let zeros = [0u8; 512];
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(zeros.len());
+            self.write_chain(first, offset + done, &zeros[..chunk])?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Read a whole directory's entries
+    fn read_directory(&mut self, first: u32) -> FsResult<Vec<u8>> {
+        let len = self.chain_length(first)? * self.bpb.cluster_size();
+        let mut raw = vec![0u8; len];
+        self.read_chain(first, 0, &mut raw)?;
+        Ok(raw)
+    }
+
+    fn update_entry(&mut self, location: EntryLocation, first_cluster: u32, size: u32) -> FsResult<()> {
+        let offset = location.index as usize * DIR_ENTRY_SIZE;
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        self.read_chain(location.dir_cluster, offset, &mut raw)?;
+        ShortEntry::patch(&mut raw, first_cluster, size);
+        self.write_chain(location.dir_cluster, offset, &raw)
+    }
+}
+
+/// Accumulates LFN entries until the short entry they belong to
+struct LongName {
+    units: [u16; LFN_MAX_CHARS + LFN_CHARS_PER_ENTRY],
+    expected: u8,
+    checksum: u8,
+    first_index: u32,
+    active: bool,
+}
+
+impl LongName {
+    fn new() -> Self {
+        LongName {
+            units: [0xFFFF; LFN_MAX_CHARS + LFN_CHARS_PER_ENTRY],
+            expected: 0,
+            checksum: 0,
+            first_index: 0,
+            active: false,
+        }
+    }
+
+    fn push(&mut self, raw: &[u8], index: u32) {
+        let (sequence, last) = fat::lfn_sequence(raw);
+        if sequence == 0 || sequence as usize * LFN_CHARS_PER_ENTRY > self.units.len() {
+            self.active = false;
+            return;
+        }
+
+        if last {
+            self.units.fill(0xFFFF);
+            self.checksum = fat::lfn_entry_checksum(raw);
+            self.first_index = index;
+            self.active = true;
+        } else if !self.active
+            || sequence != self.expected
+            || fat::lfn_entry_checksum(raw) != self.checksum
+        {
+            self.active = false;
+            return;
+        }
+
+        let start = (sequence as usize - 1) * LFN_CHARS_PER_ENTRY;
+        self.units[start..start + LFN_CHARS_PER_ENTRY].copy_from_slice(&fat::lfn_chars(raw));
+        self.expected = sequence - 1;
+    }
+
+    /// The long name, if a complete sequence matching `short` was collected
+    fn take(&mut self, short: &ShortEntry) -> Option<String> {
+        let complete = self.active && self.expected == 0 && self.checksum == fat::lfn_checksum(&short.name);
+        self.active = false;
+        if !complete {
+            return None;
+        }
+        let units = self.units.iter().copied().take_while(|&u| u != 0 && u != 0xFFFF);
+        Some(char::decode_utf16(units).map(|c| c.unwrap_or('?')).collect())
+    }
+}
+
+/// Parse raw directory contents into named entries (skipping "." and "..")
+fn parse_directory(raw: &[u8]) -> Vec<FoundEntry> {
+    let mut entries = Vec::new();
+    let mut long_name = LongName::new();
+
+    for (index, entry) in raw.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        let index = index as u32;
+        match entry[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED => {
+                long_name.active = false;
+                continue;
+            }
+            _ => {}
+        }
+
+        if entry[11] & 0x3F == ATTR_LONG_NAME {
+            long_name.push(entry, index);
+            continue;
+        }
+
+        let short = ShortEntry::parse(entry);
+        let lfn_start = long_name.first_index;
+        let lfn = long_name.take(&short);
+        if short.attr & ATTR_VOLUME_ID != 0 || short.is_dot_entry() {
+            continue;
+        }
+
+        let (name, first_index) = match lfn {
+            Some(name) => (name, lfn_start),
+            None => {
+                let mut buf = [0u8; 12];
+                (String::from(short.display_name(&mut buf)), index)
+            }
+        };
+        entries.push(FoundEntry {
+            name,
+            short,
+            index,
+            first_index,
+        });
+    }
+    entries
+}
+
+/// First index of `count` consecutive free entries (may run past the end)
+fn find_free_slots(raw: &[u8], count: usize) -> usize {
+    let mut run_start = 0;
+    let mut run_len = 0;
+    for (index, entry) in raw.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        if entry[0] == ENTRY_END {
+            // Everything from here on is unused
+            return if run_len > 0 { run_start } else { index };
+        }
+        if entry[0] == ENTRY_DELETED {
+            if run_len == 0 {
+                run_start = index;
+            }
+            run_len += 1;
+            if run_len == count {
+                return run_start;
+            }
+        } else {
+            run_len = 0;
+        }
+    }
+    raw.len() / DIR_ENTRY_SIZE
+}
+
+fn names_match(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+struct FileState {
+    first_cluster: u32,
+    size: u32,
+    unlinked: bool,
+}
+
+pub struct FatInode {
+    volume: KRef<Volume>,
+    kind: InodeKind,
+    /// None for the root directory, which has no directory entry
+    location: Option<EntryLocation>,
+    state: Spinlock<FileState>,
+}
+
+impl FatInode {
+    /// The cached inode for an entry, creating it on first use
+    fn for_entry(volume: &KRef<Volume>, dir_cluster: u32, found: &FoundEntry) -> KRef<FatInode> {
+        let location = EntryLocation {
+            dir_cluster,
+            index: found.index,
+        };
+        let mut inodes = volume.inodes.lock();
+        if let Some(inode) = inodes.get(&location).and_then(KWeak::upgrade) {
+            return inode;
+        }
+
+        let kind = if found.short.is_directory() {
+            InodeKind::Directory
+        } else {
+            InodeKind::File
+        };
+        let inode = KRef::new(
+            ObjectKind::Inode,
+            FatInode {
+                volume: volume.clone(),
+                kind,
+                location: Some(location),
+                state: Spinlock::new(FileState {
+                    first_cluster: found.short.first_cluster,
+                    size: if kind == InodeKind::File { found.short.size } else { 0 },
+                    unlinked: false,
+                }),
+            },
+        );
+        inodes.retain(|_, weak| weak.is_alive());
+        inodes.insert(location, KRef::downgrade(&inode));
+        inode
+    }
+
+    /// First cluster of this directory (".." entries use 0 for the root)
+    fn dir_cluster(&self) -> FsResult<u32> {
+        if self.kind != InodeKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let state = self.state.lock();
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+        Ok(state.first_cluster)
+    }
+
+    fn find(&self, vol: &mut VolumeState, dir: u32, name: &str) -> FsResult<Option<FoundEntry>> {
+        let raw = vol.read_directory(dir)?;
+        Ok(parse_directory(&raw)
+            .into_iter()
+            .find(|entry| names_match(&entry.name, name)))
+    }
+}
+
+impl Inode for FatInode {
+    fn kind(&self) -> InodeKind {
+        self.kind
+    }
+
+    fn size(&self) -> usize {
+        self.state.lock().size as usize
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let state = self.state.lock();
+        let size = state.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let count = buf.len().min(size - offset);
+        self.volume
+            .state
+            .lock()
+            .read_chain(state.first_cluster, offset, &mut buf[..count])?;
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let mut state = self.state.lock();
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+        let end = offset.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if end > u32::MAX as usize {
+            return Err(FsError::NoSpace); // FAT file sizes are 32-bit
+        }
+
+        let mut vol = self.volume.state.lock();
+        let mut first = state.first_cluster;
+        let result = vol.ensure_capacity(&mut first, end);
+        state.first_cluster = first;
+        result?;
+
+        // Fill any gap between the old end of file and the write position
+        let size = state.size as usize;
+        if offset > size {
+            vol.zero_chain(first, size, offset - size)?;
+        }
+        vol.write_chain(first, offset, buf)?;
+
+        state.size = state.size.max(end as u32);
+        if let Some(location) = self.location {
+            vol.update_entry(location, state.first_cluster, state.size)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: usize) -> FsResult<()> {
+        if self.kind == InodeKind::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        let mut state = self.state.lock();
+        if state.unlinked {
+            return Err(FsError::NotFound);
+        }
+        if size > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+
+        let mut vol = self.volume.state.lock();
+        let mut first = state.first_cluster;
+        let old_size = state.size as usize;
+        let result = if size < old_size {
+            vol.shrink_chain(&mut first, size)
+        } else {
+            vol.ensure_capacity(&mut first, size)
+                .and_then(|_| vol.zero_chain(first, old_size, size - old_size))
+        };
+        state.first_cluster = first;
+        result?;
+
+        state.size = size as u32;
+        if let Some(location) = self.location {
+            vol.update_entry(location, state.first_cluster, state.size)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<KRef<dyn Inode>> {
+        let dir = self.dir_cluster()?;
+        let found = {
+            let mut vol = self.volume.state.lock();
+            self.find(&mut vol, dir, name)?.ok_or(FsError::NotFound)?
+        };
+        Ok(FatInode::for_entry(&self.volume, dir, &found))
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let dir = self.dir_cluster()?;
+        let raw = self.volume.state.lock().read_directory(dir)?;
+
+        Ok(parse_directory(&raw)
+            .into_iter()
+            .map(|entry| {
+                // Prefer the live size of open files over the on-disk entry
+                let inode = FatInode::for_entry(&self.volume, dir, &entry);
+                DirEntry {
+                    name: entry.name,
+                    kind: inode.kind,
+                    size: inode.size(),
+                }
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> FsResult<KRef<dyn Inode>> {
+        let dir = self.dir_cluster()?;
+        let units: Vec<u16> = name.encode_utf16().collect();
+        if units.is_empty() || units.len() > LFN_MAX_CHARS {
+            return Err(FsError::InvalidPath);
+        }
+
+        let mut vol = self.volume.state.lock();
+        let raw = vol.read_directory(dir)?;
+        let existing = parse_directory(&raw);
+        if existing.iter().any(|entry| names_match(&entry.name, name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let short_taken = |short: &[u8; 11]| existing.iter().any(|entry| &entry.short.name == short);
+
+        // Plain upper-case 8.3 names are stored as-is; anything else gets an
+        // LFN sequence plus a unique "~N" alias
+        let (short_name, lfn_count) = match fat::exact_short_name(name) {
+            Some(short) if !short_taken(&short) => (short, 0),
+            _ => {
+                let alias = (1..=999_999)
+                    .map(|n| fat::short_alias(name, n))
+                    .find(|alias| !short_taken(alias))
+                    .ok_or(FsError::NoSpace)?;
+                (alias, fat::lfn_entries_needed(units.len()))
+            }
+        };
+
+        // Find room for the entries, growing the directory if needed
+        let slots = lfn_count + 1;
+        let start = find_free_slots(&raw, slots);
+        let mut dir_first = dir;
+        vol.ensure_capacity(&mut dir_first, (start + slots) * DIR_ENTRY_SIZE)?;
+
+        let first_cluster = match kind {
+            InodeKind::File => 0,
+            InodeKind::Directory => {
+                let cluster = vol.allocate_cluster(None)?;
+                let parent = if dir == self.volume.root_cluster { 0 } else { dir };
+                let mut dots = [0u8; DIR_ENTRY_SIZE * 2];
+                let dot = ShortEntry { name: *b".          ", attr: ATTR_DIRECTORY, first_cluster: cluster, size: 0 };
+                let dotdot = ShortEntry { name: *b"..         ", attr: ATTR_DIRECTORY, first_cluster: parent, size: 0 };
+                dot.encode(&mut dots[..DIR_ENTRY_SIZE], FIXED_DATE);
+                dotdot.encode(&mut dots[DIR_ENTRY_SIZE..], FIXED_DATE);
+                vol.write_chain(cluster, 0, &dots)?;
+                cluster
+            }
+        };
+
+        let short = ShortEntry {
+            name: short_name,
+            attr: if kind == InodeKind::Directory { ATTR_DIRECTORY } else { ATTR_ARCHIVE },
+            first_cluster,
+            size: 0,
+        };
+
+        // LFN entries are stored last piece first, directly before the short entry
+        let mut entries = vec![0u8; slots * DIR_ENTRY_SIZE];
+        let checksum = fat::lfn_checksum(&short_name);
+        for k in 0..lfn_count {
+            let sequence = lfn_count - k;
+            let mut chars = [0xFFFF; LFN_CHARS_PER_ENTRY];
+            let piece = &units[(sequence - 1) * LFN_CHARS_PER_ENTRY..];
+            for (i, slot) in chars.iter_mut().enumerate() {
+                match i.cmp(&piece.len()) {
+                    core::cmp::Ordering::Less => *slot = piece[i],
+                    core::cmp::Ordering::Equal => *slot = 0,
+                    core::cmp::Ordering::Greater => {}
+                }
+            }
+            let raw_entry = &mut entries[k * DIR_ENTRY_SIZE..(k + 1) * DIR_ENTRY_SIZE];
+            fat::encode_lfn(raw_entry, sequence as u8, k == 0, checksum, &chars);
+        }
+        short.encode(&mut entries[lfn_count * DIR_ENTRY_SIZE..], FIXED_DATE);
+        vol.write_chain(dir, start * DIR_ENTRY_SIZE, &entries)?;
+        drop(vol);
+
+        let found = FoundEntry {
+            name: String::from(name),
+            short,
+            index: (start + lfn_count) as u32,
+            first_index: start as u32,
+        };
+        Ok(FatInode::for_entry(&self.volume, dir, &found))
+    }
+
+    fn remove(&self, name: &str) -> FsResult<()> {
+        let dir = self.dir_cluster()?;
+
+        let location = {
+            let mut vol = self.volume.state.lock();
+            let found = self.find(&mut vol, dir, name)?.ok_or(FsError::NotFound)?;
+            if found.short.is_directory() {
+                let contents = vol.read_directory(found.short.first_cluster)?;
+                if !parse_directory(&contents).is_empty() {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+            }
+
+            for index in found.first_index..=found.index {
+                vol.write_chain(dir, index as usize * DIR_ENTRY_SIZE, &[ENTRY_DELETED])?;
+            }
+            vol.free_chain(found.short.first_cluster)?;
+
+            EntryLocation {
+                dir_cluster: dir,
+                index: found.index,
+            }
+        };
+
+        // Inodes still held open can't touch the freed clusters any more
+        let inode = self.volume.inodes.lock().remove(&location).and_then(|weak| weak.upgrade());
+        if let Some(inode) = inode {
+            let mut state = inode.state.lock();
+            state.unlinked = true;
+            state.first_cluster = 0;
+            state.size = 0;
+        }
+        Ok(())
+    }
+}
+
+pub struct Fat32 {
+    root: KRef<FatInode>,
+}
+
+impl Fat32 {
+    /// Mount the FAT32 volume on `device`
+    pub fn new(mut device: Box<dyn BlockDevice>) -> Result<Self, &'static str> {
+        let block_size = device.block_size();
+        let mut boot = vec![0u8; block_size.max(512)];
+        device.read_blocks(0, &mut boot[..block_size])?;
+        let bpb = BiosParameterBlock::parse(&boot)?;
+
+        let sector_size = bpb.bytes_per_sector as usize;
+        if !sector_size.is_multiple_of(block_size) {
+            return Err("FAT sector size is not a multiple of the device block size");
+        }
+        let blocks_per_sector = (sector_size / block_size) as u64;
+        if bpb.total_sectors as u64 * blocks_per_sector > device.block_count() {
+            return Err("Volume is larger than the device");
+        }
+
+        let root_cluster = bpb.root_cluster;
+        let volume = KRef::new(
+            ObjectKind::Volume,
+            Volume {
+                state: Spinlock::new(VolumeState {
+                    device,
+                    bpb,
+                    blocks_per_sector,
+                    sector: vec![0; sector_size],
+                    next_free: FIRST_DATA_CLUSTER,
+                    fs_info_invalidated: false,
+                }),
+                root_cluster,
+                inodes: Spinlock::new(BTreeMap::new()),
+            },
+        );
+
+        let root = KRef::new(
+            ObjectKind::Inode,
+            FatInode {
+                volume,
+                kind: InodeKind::Directory,
+                location: None,
+                state: Spinlock::new(FileState {
+                    first_cluster: root_cluster,
+                    size: 0,
+                    unlinked: false,
+                }),
+            },
+        );
+        Ok(Fat32 { root })
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> KRef<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.root.volume.state.lock().device.sync().map_err(|_| FsError::Io)
+    }
+
+    fn reclaim(&self) -> usize {
+        self.root.volume.state.try_lock().map_or(0, |mut vol| vol.device.reclaim())
+    }
+}
+
+/// True if `image` starts with a FAT32 boot sector
+pub fn probe(image: &[u8]) -> bool {
+    BiosParameterBlock::parse(image).is_ok()
+}
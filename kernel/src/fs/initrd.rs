@@ -0,0 +1,79 @@
+//! Initial ramdisk
+//! A boot module named `initrd`, a cpio (newc) or tar (ustar) archive, is
+//! unpacked into the root ramfs at boot, before any disk is mounted, so
+//! programs and configuration files reach the kernel without trusting a disk
+//! driver. The archive is read in place (`shared::archive`); only the files
+//! are copied, onto the heap like every other ramfs file.
+
+use super::vfs::{self, FsError, FsResult, OpenFlags};
+use crate::{bootinfo, error, info, warn};
+use alloc::format;
+use shared::archive::{Archive, EntryKind};
+
+/// File name of the boot module to unpack
+const MODULE_NAME: &str = "initrd";
+
+/// Unpack the `initrd` module into / if there is one
+pub fn unpack() {
+    let Some(module) = bootinfo::get().module(MODULE_NAME) else {
+        return;
+    };
+    let archive = match Archive::parse(module.data()) {
+        Ok(archive) => archive,
+        Err(e) => {
+            error!("  {}: {}", module.path(), e);
+            return;
+        }
+    };
+
+    let (mut files, mut directories) = (0, 0);
+    for entry in archive.entries() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("  {}: {}, stopped unpacking", module.path(), e);
+                break;
+            }
+        };
+        let path = format!("/{}", entry.path);
+        if entry.path.split('/').any(|component| component == "..") {
+            warn!("  initrd: skipped {}, which leads out of the archive", path);
+            continue;
+        }
+        let result = match entry.kind {
+            EntryKind::Directory => make_dirs(&path).map(|()| directories += 1),
+            EntryKind::File => write_file(&path, entry.data).map(|()| files += 1),
+            EntryKind::Other => {
+                warn!("  initrd: skipped {}, not a file or directory", path);
+                continue;
+            }
+        };
+        if let Err(e) = result {
+            error!("  initrd: {}: {}", path, e.as_str());
+        }
+    }
+    info!("  {} unpacked: {} files, {} directories", module.path(), files, directories);
+}
+
+/// Create directory `path` and any missing parents
+fn make_dirs(path: &str) -> FsResult<()> {
+    let ends = path.match_indices('/').skip(1).map(|(end, _)| end).chain([path.len()]);
+    for end in ends {
+        match vfs::mkdir(&path[..end]) {
+            Ok(()) | Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Create (or replace) file `path` holding `data`, and its parents
+fn write_file(path: &str, data: &[u8]) -> FsResult<()> {
+    // Archives needn't list a directory before the files in it
+    if let Some((parent, _)) = path.rsplit_once('/').filter(|(parent, _)| !parent.is_empty()) {
+        make_dirs(parent)?;
+    }
+    let mut file = vfs::open(path, OpenFlags::WRITE)?;
+    vfs::write(&mut file, data)?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Filesystems
+//! The VFS layer plus the concrete filesystems that plug into it
+
+#[cfg(feature = "storage")]
+mod disks;
+#[cfg(feature = "storage")]
+pub mod fat32;
+mod initrd;
+pub mod ramfs;
+pub mod vfs;
+
+use crate::sync::kref::{KRef, ObjectKind};
+use crate::{bootinfo, error, info};
+
+/// Mount the root filesystem, unpack the initrd into it, and mount any
+/// FAT32 disk images; needs the heap
+pub fn init() {
+    if let Err(e) = vfs::mount("/", KRef::new(ObjectKind::FileSystem, ramfs::RamFs::new())) {
+        error!("  Failed to mount ramfs: {}", e.as_str());
+        return;
+    }
+    info!("  ramfs mounted at /");
+    initrd::unpack();
+    // Given on their own, these override the initrd's
+    install_module("rc", "/etc/rc");
+    install_module("keys", "/etc/keys");
+
+    #[cfg(feature = "storage")]
+    disks::mount_fat_modules();
+}
+
+/// Copy the boot module called `name` to `path` in /etc, where the shell
+/// looks for its startup script (`rc`) and key replay (`keys`)
+fn install_module(name: &str, path: &str) {
+    let module = match bootinfo::get().module(name) {
+        Some(module) => module,
+        None => return,
+    };
+    // The other module may have created /etc already
+    let result = match vfs::mkdir("/etc") {
+        Err(vfs::FsError::AlreadyExists) => Ok(()),
+        result => result,
+    };
+    let result = result
+        .and_then(|()| vfs::open(path, vfs::OpenFlags::WRITE))
+        .and_then(|mut file| vfs::write(&mut file, module.data()));
+    match result {
+        Ok(_) => info!("  {} installed as {}", module.path(), path),
+        Err(e) => error!("  Failed to install {}: {}", path, e.as_str()),
+    }
+}
@@ -0,0 +1,175 @@
+//! In-memory filesystem (ramfs)
+//! Files and directories live entirely on the kernel heap and vanish on reboot.
+
+use super::vfs::{DirEntry, FileSystem, FsError, FsResult, Inode, InodeKind};
+use crate::sync::kref::{KRef, ObjectKind};
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+enum RamData {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, KRef<RamInode>>),
+}
+
+pub struct RamInode {
+    data: Spinlock<RamData>,
+}
+
+impl RamInode {
+    fn new(kind: InodeKind) -> KRef<Self> {
+        let data = match kind {
+            InodeKind::File => RamData::File(Vec::new()),
+            InodeKind::Directory => RamData::Directory(BTreeMap::new()),
+        };
+        KRef::new(
+            ObjectKind::Inode,
+            RamInode {
+                data: Spinlock::new(data),
+            },
+        )
+    }
+}
+
+impl Inode for RamInode {
+    fn kind(&self) -> InodeKind {
+        match *self.data.lock() {
+            RamData::File(_) => InodeKind::File,
+            RamData::Directory(_) => InodeKind::Directory,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match &*self.data.lock() {
+            RamData::File(bytes) => bytes.len(),
+            RamData::Directory(entries) => entries.len(),
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        match &*self.data.lock() {
+            RamData::File(bytes) => {
+                if offset >= bytes.len() {
+                    return Ok(0);
+                }
+                let count = buf.len().min(bytes.len() - offset);
+                buf[..count].copy_from_slice(&bytes[offset..offset + count]);
+                Ok(count)
+            }
+            RamData::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        match &mut *self.data.lock() {
+            RamData::File(bytes) => {
+                let end = offset.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+                if bytes.try_reserve(end.saturating_sub(bytes.len())).is_err() {
+                    return Err(FsError::NoSpace);
+                }
+                // Writing past the end leaves a zero-filled gap, as on other filesystems
+                if end > bytes.len() {
+                    bytes.resize(end, 0);
+                }
+                bytes[offset..end].copy_from_slice(buf);
+                Ok(buf.len())
+            }
+            RamData::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn truncate(&self, size: usize) -> FsResult<()> {
+        match &mut *self.data.lock() {
+            RamData::File(bytes) => {
+                bytes.resize(size, 0);
+                bytes.shrink_to_fit();
+                Ok(())
+            }
+            RamData::Directory(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<KRef<dyn Inode>> {
+        match &*self.data.lock() {
+            RamData::Directory(entries) => entries
+                .get(name)
+                .map(|inode| inode.clone() as KRef<dyn Inode>)
+                .ok_or(FsError::NotFound),
+            RamData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        // Clone the children out first so their locks are never taken under ours
+        let children: Vec<(String, KRef<RamInode>)> = match &*self.data.lock() {
+            RamData::Directory(entries) => entries
+                .iter()
+                .map(|(name, inode)| (name.clone(), inode.clone()))
+                .collect(),
+            RamData::File(_) => return Err(FsError::NotADirectory),
+        };
+
+        Ok(children
+            .into_iter()
+            .map(|(name, inode)| DirEntry {
+                name,
+                kind: inode.kind(),
+                size: inode.size(),
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: InodeKind) -> FsResult<KRef<dyn Inode>> {
+        match &mut *self.data.lock() {
+            RamData::Directory(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::AlreadyExists);
+                }
+                let inode = RamInode::new(kind);
+                entries.insert(String::from(name), inode.clone());
+                Ok(inode)
+            }
+            RamData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn remove(&self, name: &str) -> FsResult<()> {
+        match &mut *self.data.lock() {
+            RamData::Directory(entries) => {
+                let child = entries.get(name).ok_or(FsError::NotFound)?;
+                if let RamData::Directory(grandchildren) = &*child.data.lock() {
+                    if !grandchildren.is_empty() {
+                        return Err(FsError::DirectoryNotEmpty);
+                    }
+                }
+                // Open files keep their own KRef, so the data lives until they close
+                entries.remove(name);
+                Ok(())
+            }
+            RamData::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+}
+
+pub struct RamFs {
+    root: KRef<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Self {
+        RamFs {
+            root: RamInode::new(InodeKind::Directory),
+        }
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> KRef<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -0,0 +1,380 @@
+//! Virtual File System (VFS) layer
+//! Filesystems implement `FileSystem` and `Inode` and are attached to the
+//! mount table; callers only ever go through the path-based API below.
+
+use crate::sync::kref::KRef;
+use crate::sync::rwlock::RwLock;
+use alloc::string::String;
+use alloc::vec::Vec;
+use shared::path;
+
+/// Errors returned by VFS and filesystem operations
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidPath,
+    NotMounted,
+    ReadOnly,
+    Unsupported,
+    NoSpace,
+    Busy,
+    Io,
+}
+
+impl FsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsError::NotFound => "No such file or directory",
+            FsError::AlreadyExists => "File exists",
+            FsError::NotADirectory => "Not a directory",
+            FsError::IsADirectory => "Is a directory",
+            FsError::DirectoryNotEmpty => "Directory not empty",
+            FsError::InvalidPath => "Invalid path",
+            FsError::NotMounted => "No filesystem mounted",
+            FsError::ReadOnly => "Read-only filesystem",
+            FsError::Unsupported => "Operation not supported",
+            FsError::NoSpace => "No space left on device",
+            FsError::Busy => "Device or resource busy",
+            FsError::Io => "Input/output error",
+        }
+    }
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeKind {
+    File,
+    Directory,
+}
+
+/// One entry returned by `readdir`
+pub struct DirEntry {
+    pub name: String,
+    pub kind: InodeKind,
+    pub size: usize,
+}
+
+/// A file or directory inside some filesystem
+/// Directory operations default to `NotADirectory` and data operations to
+/// `IsADirectory`, so each implementation only overrides what applies to it.
+pub trait Inode: Send + Sync {
+    fn kind(&self) -> InodeKind;
+
+    fn size(&self) -> usize;
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn truncate(&self, _size: usize) -> FsResult<()> {
+        Err(FsError::IsADirectory)
+    }
+
+    fn lookup(&self, _name: &str) -> FsResult<KRef<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _kind: InodeKind) -> FsResult<KRef<dyn Inode>> {
+        Err(FsError::NotADirectory)
+    }
+
+    fn remove(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotADirectory)
+    }
+}
+
+/// A mountable filesystem instance
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> KRef<dyn Inode>;
+
+    /// Flush cached data to the backing device
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Free cached data that can be read back, returning the bytes freed
+    /// Called when the heap runs out: must not allocate or wait on a lock.
+    fn reclaim(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    pub truncate: bool,
+    pub append: bool,
+}
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags {
+        read: true,
+        write: false,
+        create: false,
+        truncate: false,
+        append: false,
+    };
+
+    pub const WRITE: OpenFlags = OpenFlags {
+        read: false,
+        write: true,
+        create: true,
+        truncate: true,
+        append: false,
+    };
+
+    pub const APPEND: OpenFlags = OpenFlags {
+        read: false,
+        write: true,
+        create: true,
+        truncate: false,
+        append: true,
+    };
+}
+
+/// An open file: an inode plus a cursor
+pub struct File {
+    inode: KRef<dyn Inode>,
+    offset: usize,
+    flags: OpenFlags,
+}
+
+impl File {
+    pub fn read(&mut self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags.read {
+            return Err(FsError::Unsupported);
+        }
+        let count = self.inode.read_at(self.offset, buf)?;
+        self.offset += count;
+        Ok(count)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags.write {
+            return Err(FsError::Unsupported);
+        }
+        if self.flags.append {
+            self.offset = self.inode.size();
+        }
+        let count = self.inode.write_at(self.offset, buf)?;
+        self.offset += count;
+        Ok(count)
+    }
+
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn size(&self) -> usize {
+        self.inode.size()
+    }
+}
+
+struct Mount {
+    path: String,
+    fs: KRef<dyn FileSystem>,
+}
+
+/// Read on every path lookup, written only by `mount`
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Attach `fs` at the absolute directory `target`
+pub fn mount(target: &str, fs: KRef<dyn FileSystem>) -> FsResult<()> {
+    let target = normalize(target)?;
+
+    let mut mounts = MOUNTS.write();
+    if mounts.iter().any(|m| m.path == target) {
+        return Err(FsError::Busy);
+    }
+    if target != "/" {
+        // The mount point must be an existing directory of the parent filesystem
+        drop(mounts);
+        if resolve_in_mounts(&target)?.kind() != InodeKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        mounts = MOUNTS.write();
+    }
+    mounts.push(Mount { path: target, fs });
+    Ok(())
+}
+
+/// List mount points as (path, filesystem name)
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS
+        .read()
+        .iter()
+        .map(|m| (m.path.clone(), m.fs.name()))
+        .collect()
+}
+
+/// Flush every mounted filesystem, returning the first error after trying all
+pub fn sync() -> FsResult<()> {
+    let filesystems: Vec<KRef<dyn FileSystem>> = MOUNTS.read().iter().map(|m| m.fs.clone()).collect();
+    let mut result = Ok(());
+    for fs in filesystems {
+        if let (Err(e), Ok(())) = (fs.sync(), &result) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Let every mounted filesystem free what it caches; returns the bytes freed
+/// Skips everything if the mount table is busy, since this runs inside the
+/// allocator.
+pub fn reclaim() -> usize {
+    match MOUNTS.try_read() {
+        Some(mounts) => mounts.iter().map(|m| m.fs.reclaim()).sum(),
+        None => 0,
+    }
+}
+
+/// Canonical absolute form of `path_str`: no ".", "..", or repeated slashes
+fn normalize(path_str: &str) -> FsResult<String> {
+    if !path::is_absolute(path_str) {
+        return Err(FsError::InvalidPath);
+    }
+
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path::components(path_str) {
+        if component == ".." {
+            parts.pop();
+        } else {
+            parts.push(component);
+        }
+    }
+
+    let mut normalized = String::new();
+    for part in &parts {
+        normalized.push('/');
+        normalized.push_str(part);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// True if `path` is `mount_point` or lies below it
+fn is_under(path: &str, mount_point: &str) -> bool {
+    mount_point == "/"
+        || path == mount_point
+        || (path.starts_with(mount_point) && path.as_bytes()[mount_point.len()] == b'/')
+}
+
+/// Walk a normalized path from the root of the filesystem mounted closest to it
+fn resolve_in_mounts(normalized: &str) -> FsResult<KRef<dyn Inode>> {
+    let (root, rest) = {
+        let mounts = MOUNTS.read();
+        let mount = mounts
+            .iter()
+            .filter(|m| is_under(normalized, &m.path))
+            .max_by_key(|m| m.path.len())
+            .ok_or(FsError::NotMounted)?;
+        let rest = if mount.path == "/" {
+            normalized
+        } else {
+            &normalized[mount.path.len()..]
+        };
+        (mount.fs.root(), String::from(rest))
+    };
+
+    let mut inode = root;
+    for component in path::components(&rest) {
+        inode = inode.lookup(component)?;
+    }
+    Ok(inode)
+}
+
+/// Resolve an absolute path to its inode
+pub fn resolve(path_str: &str) -> FsResult<KRef<dyn Inode>> {
+    resolve_in_mounts(&normalize(path_str)?)
+}
+
+/// Resolve the parent directory of `path_str` and validate the final name
+fn resolve_parent(path_str: &str) -> FsResult<(KRef<dyn Inode>, String)> {
+    let normalized = normalize(path_str)?;
+    let (parent, name) = path::split_parent(&normalized).ok_or(FsError::InvalidPath)?;
+    if !path::is_valid_name(name) {
+        return Err(FsError::InvalidPath);
+    }
+    Ok((resolve_in_mounts(parent)?, String::from(name)))
+}
+
+/// Open a file, creating or truncating it as `flags` request
+pub fn open(path_str: &str, flags: OpenFlags) -> FsResult<File> {
+    let inode = match resolve(path_str) {
+        Ok(inode) => inode,
+        Err(FsError::NotFound) if flags.create => {
+            let (parent, name) = resolve_parent(path_str)?;
+            parent.create(&name, InodeKind::File)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    if inode.kind() == InodeKind::Directory && flags.write {
+        return Err(FsError::IsADirectory);
+    }
+    if flags.truncate && flags.write {
+        inode.truncate(0)?;
+    }
+
+    Ok(File {
+        inode,
+        offset: 0,
+        flags,
+    })
+}
+
+/// Read from an open file at its cursor
+pub fn read(file: &mut File, buf: &mut [u8]) -> FsResult<usize> {
+    file.read(buf)
+}
+
+/// Write to an open file at its cursor
+pub fn write(file: &mut File, buf: &[u8]) -> FsResult<usize> {
+    file.write(buf)
+}
+
+/// List the entries of a directory
+pub fn readdir(path_str: &str) -> FsResult<Vec<DirEntry>> {
+    resolve(path_str)?.readdir()
+}
+
+/// Create an empty directory
+pub fn mkdir(path_str: &str) -> FsResult<()> {
+    let (parent, name) = resolve_parent(path_str)?;
+    parent.create(&name, InodeKind::Directory).map(|_| ())
+}
+
+/// Remove a file or an empty directory
+pub fn remove(path_str: &str) -> FsResult<()> {
+    let normalized = normalize(path_str)?;
+    if MOUNTS.read().iter().any(|m| m.path == normalized) {
+        return Err(FsError::Busy);
+    }
+    let (parent, name) = resolve_parent(&normalized)?;
+    parent.remove(&name)
+}
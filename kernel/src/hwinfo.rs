@@ -0,0 +1,170 @@
+//! Boot hardware summary
+//! Once the drivers have probed, `collect` gathers what the kernel found into
+//! one report: the CPU and its features, memory size and layout, the display
+//! mode, PCI devices and the drivers bound to them, and storage and network
+//! devices. It is printed at the end of boot and kept for `hwinfo`, so a
+//! shared boot log or a running system can answer "what is this machine?".
+
+use crate::arch::x86_64::{cpuid, smp};
+use crate::drivers::pci::{self, PciDevice};
+use crate::fs::vfs;
+use crate::{bootinfo, limine};
+use crate::sync::mutex::Mutex;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use shared::cpuid::CpuInfo;
+
+/// Memory map entry types `limine::memmap_type_name` knows, plus one for unknown types
+const MEMMAP_TYPES: usize = 9;
+/// Width the feature list wraps at, after the label column
+const WRAP_WIDTH: usize = 64;
+const LABEL_WIDTH: usize = 11;
+
+pub struct HardwareSummary {
+    cpu: CpuInfo,
+    cpus_online: usize,
+    /// Bytes of each memory map entry type
+    memory: [u64; MEMMAP_TYPES],
+    memmap_entries: usize,
+    /// Width, height, and bits per pixel of the Limine framebuffer
+    framebuffer: Option<(u64, u64, u16)>,
+    pci: Vec<(PciDevice, &'static str, Option<&'static str>)>,
+    /// Mount points and their filesystems
+    mounts: Vec<(String, &'static str)>,
+    mac: Option<[u8; 6]>,
+}
+
+/// `with_summary` formats the report while holding it
+static SUMMARY: Mutex<Option<HardwareSummary>> = Mutex::new(None);
+
+/// Gather the summary, print it, and keep it for `hwinfo`; needs the heap
+pub fn collect() {
+    let mut memory = [0; MEMMAP_TYPES];
+    let memory_map = bootinfo::get().memory_map();
+    for entry in memory_map {
+        memory[(entry.entry_type as usize).min(MEMMAP_TYPES - 1)] += entry.length;
+    }
+    let memmap_entries = memory_map.len();
+
+    let framebuffer = bootinfo::get().framebuffer.map(|fb| (fb.width, fb.height, fb.bpp));
+
+    #[cfg(feature = "net")]
+    let mac = crate::drivers::virtio::net::mac_address();
+    #[cfg(not(feature = "net"))]
+    let mac = None;
+
+    let summary = HardwareSummary {
+        cpu: cpuid::info(),
+        cpus_online: smp::online_count(),
+        memory,
+        memmap_entries,
+        framebuffer,
+        pci: pci::devices().map(|dev| (dev, dev.class_name(), dev.driver())).collect(),
+        mounts: vfs::mounts(),
+        mac,
+    };
+    crate::print!("{}", summary);
+    *SUMMARY.lock() = Some(summary);
+}
+
+/// Run `f` on the summary taken at boot, if there is one
+pub fn with_summary<R>(f: impl FnOnce(&HardwareSummary) -> R) -> Option<R> {
+    SUMMARY.lock().as_ref().map(f)
+}
+
+/// Start a line of the report: the label, or blank space under the previous one
+fn label(f: &mut fmt::Formatter, label: &str) -> fmt::Result {
+    write!(f, "  {:<1$}", label, LABEL_WIDTH)
+}
+
+impl fmt::Display for HardwareSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Hardware summary:")?;
+
+        label(f, "CPU:")?;
+        writeln!(f, "{}", self.cpu.brand().unwrap_or("(no brand string)"))?;
+        label(f, "")?;
+        writeln!(
+            f,
+            "{} family {} model {} stepping {}, {} online",
+            self.cpu.vendor(),
+            self.cpu.family(),
+            self.cpu.model(),
+            self.cpu.stepping(),
+            self.cpus_online
+        )?;
+
+        // Feature names, wrapped under their label
+        label(f, "Features:")?;
+        let mut column = 0;
+        for feature in self.cpu.features() {
+            if column > 0 && column + 1 + feature.len() > WRAP_WIDTH {
+                writeln!(f)?;
+                label(f, "")?;
+                column = 0;
+            }
+            if column > 0 {
+                f.write_str(" ")?;
+                column += 1;
+            }
+            f.write_str(feature)?;
+            column += feature.len();
+        }
+        writeln!(f)?;
+
+        let usable = self.memory[limine::LIMINE_MEMMAP_USABLE as usize];
+        let total: u64 = self.memory.iter().sum();
+        label(f, "Memory:")?;
+        writeln!(f, "{} MB usable of {} MB in {} regions", usable >> 20, total >> 20, self.memmap_entries)?;
+        for (entry_type, &bytes) in self.memory.iter().enumerate() {
+            if bytes > 0 {
+                label(f, "")?;
+                writeln!(f, "{:<24} {} KB", limine::memmap_type_name(entry_type as u64), bytes / 1024)?;
+            }
+        }
+
+        label(f, "Display:")?;
+        match self.framebuffer {
+            Some((width, height, bpp)) => writeln!(f, "framebuffer {}x{}, {} bpp", width, height, bpp)?,
+            None => writeln!(f, "text mode")?,
+        }
+
+        label(f, "PCI:")?;
+        if self.pci.is_empty() {
+            writeln!(f, "no devices")?;
+        }
+        for (i, (dev, class, driver)) in self.pci.iter().enumerate() {
+            if i > 0 {
+                label(f, "")?;
+            }
+            write!(f, "{:02x}:{:02x}.{} {:04x}:{:04x} {}", dev.bus, dev.device, dev.function, dev.vendor_id, dev.device_id, class)?;
+            match driver {
+                Some(driver) => writeln!(f, " [{}]", driver)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        label(f, "Storage:")?;
+        if self.mounts.is_empty() {
+            writeln!(f, "nothing mounted")?;
+        }
+        for (i, (path, fs)) in self.mounts.iter().enumerate() {
+            if i > 0 {
+                label(f, "")?;
+            }
+            writeln!(f, "{} ({})", path, fs)?;
+        }
+
+        label(f, "Network:")?;
+        match self.mac {
+            Some(mac) => writeln!(
+                f,
+                "virtio-net, MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+            ),
+            None if cfg!(feature = "net") => writeln!(f, "no device"),
+            None => writeln!(f, "not built in"),
+        }
+    }
+}
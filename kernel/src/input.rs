@@ -0,0 +1,43 @@
+//! Injected keyboard input, and waiting for input
+//! `inject` queues scan codes (set 1) that the keyboard driver hands out once
+//! the hardware buffer is empty, so they are decoded and delivered exactly as
+//! typed keys are. `replay` plays key scripts (`shared::replay`) through it,
+//! which lets shell and editor behaviour be tested under QEMU with nobody at
+//! the keyboard.
+//! Readers that have nothing else to do block in `wait_for` until input
+//! arrives; the keyboard and serial IRQ handlers call `wake_readers`.
+
+use crate::sync::spinlock::Spinlock;
+use crate::sync::wait_queue::WaitQueue;
+use alloc::collections::VecDeque;
+
+static READERS: WaitQueue = WaitQueue::new();
+
+/// Only touched outside interrupt context: by `inject` and keyboard readers
+static QUEUE: Spinlock<VecDeque<u8>> = Spinlock::new(VecDeque::new());
+
+/// Queue `bytes` behind any scan codes injected earlier
+pub fn inject(bytes: &[u8]) {
+    QUEUE.lock().extend(bytes);
+    wake_readers();
+}
+
+/// Next injected scan code, for the keyboard driver
+pub fn next_scancode() -> Option<u8> {
+    QUEUE.lock().pop_front()
+}
+
+/// True while injected scan codes are waiting to be read
+pub fn pending() -> bool {
+    !QUEUE.lock().is_empty()
+}
+
+/// Block until `read` returns input; it is tried again whenever input arrives
+pub fn wait_for<R>(read: impl FnMut() -> Option<R>) -> R {
+    READERS.wait_until(read)
+}
+
+/// Tell blocked readers that input arrived; called from IRQ handlers
+pub fn wake_readers() {
+    READERS.wake_all();
+}
@@ -0,0 +1,128 @@
+//! Device interrupt lines
+//! A driver takes a line with `register_irq(irq, handler)` rather than
+//! installing a stub of its own: the first handler on a line unmasks it, and
+//! `unregister_irq` masks it again once the last one is gone. Up to
+//! `MAX_SHARED` handlers share a line. Each is called on every interrupt and
+//! returns true if its device raised it; an interrupt no handler claims is
+//! counted as unclaimed in `stats`.
+//! The architecture's IRQ stubs call `dispatch`, which runs the handlers and
+//! then acknowledges the line, so handlers never acknowledge it themselves.
+//! The system tick has its own stub and does not come through here.
+
+use crate::arch::{Arch, InterruptController};
+use crate::sync::spinlock::Spinlock;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+/// Lines drivers can register on (the two PICs' 16 on x86_64)
+pub const IRQ_LINES: usize = 16;
+/// Most handlers on one line
+pub const MAX_SHARED: usize = 4;
+
+/// Runs in interrupt context; true if its device raised the interrupt
+pub type IrqHandler = fn() -> bool;
+
+struct Line {
+    /// `IrqHandler`s, null where a slot is free
+    handlers: [AtomicPtr<()>; MAX_SHARED],
+    interrupts: AtomicU64,
+    unclaimed: AtomicU64,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Line {
+            handlers: [const { AtomicPtr::new(ptr::null_mut()) }; MAX_SHARED],
+            interrupts: AtomicU64::new(0),
+            unclaimed: AtomicU64::new(0),
+        }
+    }
+
+    fn handlers(&self) -> impl Iterator<Item = IrqHandler> + '_ {
+        self.handlers.iter().filter_map(|slot| {
+            let handler = slot.load(Ordering::Acquire);
+            // Only `register_irq` fills a slot, always with an `IrqHandler`
+            (!handler.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), IrqHandler>(handler) })
+        })
+    }
+}
+
+static LINES: [Line; IRQ_LINES] = [const { Line::new() }; IRQ_LINES];
+
+/// Held while handlers come and go, so masking follows the handler count;
+/// `dispatch` never takes it
+static REGISTRY: Spinlock<()> = Spinlock::new(());
+
+fn line(irq: u8) -> Result<&'static Line, &'static str> {
+    LINES.get(irq as usize).ok_or("No such IRQ line")
+}
+
+/// Call `handler` on every interrupt on line `irq`, unmasking the line if it
+/// is the first
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), &'static str> {
+    let line = line(irq)?;
+    let _registry = REGISTRY.lock();
+    let first = line.handlers().next().is_none();
+    let slot = line
+        .handlers
+        .iter()
+        .find(|slot| slot.load(Ordering::Relaxed).is_null())
+        .ok_or("IRQ line has no free handler slot")?;
+    slot.store(handler as *mut (), Ordering::Release);
+    if first {
+        Arch::enable_irq(irq);
+    }
+    Ok(())
+}
+
+/// Stop calling `handler` for line `irq`, masking the line if it was the last
+#[allow(dead_code)]
+pub fn unregister_irq(irq: u8, handler: IrqHandler) -> Result<(), &'static str> {
+    let line = line(irq)?;
+    let _registry = REGISTRY.lock();
+    let slot = line
+        .handlers
+        .iter()
+        .find(|slot| slot.load(Ordering::Relaxed) == handler as *mut ())
+        .ok_or("Handler not registered on the IRQ line")?;
+    slot.store(ptr::null_mut(), Ordering::Release);
+    if line.handlers().next().is_none() {
+        Arch::disable_irq(irq);
+    }
+    Ok(())
+}
+
+/// Run the handlers registered on `irq` and acknowledge it; called by the
+/// architecture's IRQ stubs
+pub fn dispatch(irq: u8) {
+    if let Ok(line) = line(irq) {
+        line.interrupts.fetch_add(1, Ordering::Relaxed);
+        // Every handler runs: more than one device may be waiting
+        let claimed = line.handlers().fold(false, |claimed, handler| handler() | claimed);
+        if !claimed {
+            line.unclaimed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Arch::end_of_interrupt(irq);
+}
+
+/// Counters of one line
+pub struct IrqStats {
+    pub irq: u8,
+    pub handlers: usize,
+    pub interrupts: u64,
+    pub unclaimed: u64,
+}
+
+/// Lines that have a handler or have fired
+pub fn stats() -> impl Iterator<Item = IrqStats> {
+    LINES.iter().zip(0..).filter_map(|(line, irq)| {
+        let stats = IrqStats {
+            irq,
+            handlers: line.handlers().count(),
+            interrupts: line.interrupts.load(Ordering::Relaxed),
+            unclaimed: line.unclaimed.load(Ordering::Relaxed),
+        };
+        (stats.handlers > 0 || stats.interrupts > 0).then_some(stats)
+    })
+}
@@ -0,0 +1,92 @@
+//! Message ports between user programs and kernel services
+//! The ports (`shared::ipc`) sit behind one lock, which keeps interrupts off
+//! so a driver can post to a port from its handler with `try_send`. Every
+//! change wakes a single `WaitQueue`, and callers of `send` and `receive`
+//! wait on it while their port is full or empty: without a scheduler they
+//! all recheck on any change, which is cheap at this size.
+//! Ports a user program creates are destroyed when it exits.
+
+use crate::sync::irq_spinlock::IrqSafeSpinlock;
+use crate::sync::wait_queue::WaitQueue;
+use alloc::vec::Vec;
+use shared::ipc::{Owner, PortError, PortId, PortInfo, PortTable, MAX_PORTS};
+
+const _: () = assert!(shared::ipc::MAX_MESSAGE as u64 == abi::PORT_MESSAGE_MAX);
+const _: () = assert!(shared::ipc::MAX_NAME as u64 == abi::PORT_NAME_MAX);
+
+static PORTS: IrqSafeSpinlock<PortTable> = IrqSafeSpinlock::new(PortTable::new());
+/// Woken by every send, receive and destroy
+static CHANGED: WaitQueue = WaitQueue::new();
+
+pub fn create(name: &str, owner: Owner) -> Result<PortId, PortError> {
+    PORTS.lock().create(name, owner)
+}
+
+pub fn lookup(name: &str) -> Result<PortId, PortError> {
+    PORTS.lock().lookup(name)
+}
+
+pub fn owner(port: PortId) -> Result<Owner, PortError> {
+    PORTS.lock().owner(port)
+}
+
+/// Destroy `port`; anyone waiting on it gets `NotFound`
+pub fn destroy(port: PortId) -> Result<(), PortError> {
+    let result = PORTS.lock().destroy(port);
+    CHANGED.wake_all();
+    result
+}
+
+/// Destroy every port `owner` created (a user program that has exited)
+pub fn release(owner: Owner) -> usize {
+    let count = PORTS.lock().destroy_owned_by(owner);
+    if count > 0 {
+        CHANGED.wake_all();
+    }
+    count
+}
+
+/// Queue `message` on `port` if there is room now; never blocks
+pub fn try_send(port: PortId, message: &[u8]) -> Result<(), PortError> {
+    PORTS.lock().send(port, message)?;
+    CHANGED.wake_all();
+    Ok(())
+}
+
+/// Take the oldest message on `port` into `buffer` if there is one now,
+/// returning its length; never blocks
+pub fn try_receive(port: PortId, buffer: &mut [u8]) -> Result<usize, PortError> {
+    let len = PORTS.lock().receive(port, buffer)?;
+    CHANGED.wake_all();
+    Ok(len)
+}
+
+/// Retry `attempt` until it stops failing with `WouldBlock`
+/// It runs again after each change to any port, with interrupts enabled.
+pub fn blocking<R>(mut attempt: impl FnMut() -> Result<R, PortError>) -> Result<R, PortError> {
+    CHANGED.wait_until(|| match attempt() {
+        Err(PortError::WouldBlock) => None,
+        result => Some(result),
+    })
+}
+
+/// Queue `message` on `port`, waiting while it is full
+#[allow(dead_code)]
+pub fn send(port: PortId, message: &[u8]) -> Result<(), PortError> {
+    blocking(|| try_send(port, message))
+}
+
+/// Take the oldest message on `port` into `buffer`, waiting while it is
+/// empty, and return its length
+#[allow(dead_code)]
+pub fn receive(port: PortId, buffer: &mut [u8]) -> Result<usize, PortError> {
+    blocking(|| try_receive(port, buffer))
+}
+
+/// Every port, copied out of the table
+pub fn ports() -> Vec<PortInfo> {
+    // Room for all of them first, so nothing is allocated under the lock
+    let mut ports = Vec::with_capacity(MAX_PORTS);
+    ports.extend(PORTS.lock().ports());
+    ports
+}
@@ -2,6 +2,7 @@
 //! Documentation: https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md
 
 use core::ptr;
+use core::sync::atomic::AtomicU64;
 
 // Limine protocol magic numbers
 const LIMINE_COMMON_MAGIC: [u64; 2] = [0xc7b1dd30df4c8b88, 0x0a82e883a194f07b];
@@ -92,21 +93,36 @@ pub struct LimineMemoryMapEntry {
     pub entry_type: u64,
 }
 
+impl LimineMemoryMapResponse {
+    pub fn entries(&self) -> impl Iterator<Item = &'static LimineMemoryMapEntry> {
+        let entries = self.entries;
+        (0..self.entry_count as usize).map(move |i| unsafe { &**entries.add(i) })
+    }
+}
+
+/// Human-readable name of a memory map entry type
+pub fn memmap_type_name(entry_type: u64) -> &'static str {
+    match entry_type {
+        LIMINE_MEMMAP_USABLE => "Usable",
+        LIMINE_MEMMAP_RESERVED => "Reserved",
+        LIMINE_MEMMAP_ACPI_RECLAIMABLE => "ACPI reclaimable",
+        LIMINE_MEMMAP_ACPI_NVS => "ACPI NVS",
+        LIMINE_MEMMAP_BAD_MEMORY => "Bad memory",
+        LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE => "Bootloader reclaimable",
+        LIMINE_MEMMAP_KERNEL_AND_MODULES => "Kernel and modules",
+        LIMINE_MEMMAP_FRAMEBUFFER => "Framebuffer",
+        _ => "Unknown",
+    }
+}
+
 #[allow(dead_code)]
 pub const LIMINE_MEMMAP_USABLE: u64 = 0;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_RESERVED: u64 = 1;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_ACPI_RECLAIMABLE: u64 = 2;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_ACPI_NVS: u64 = 3;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_BAD_MEMORY: u64 = 4;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE: u64 = 5;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_KERNEL_AND_MODULES: u64 = 6;
-#[allow(dead_code)]
 pub const LIMINE_MEMMAP_FRAMEBUFFER: u64 = 7;
 
 #[used]
@@ -127,6 +143,161 @@ pub struct LimineKernelAddressResponse {
 pub static KERNEL_ADDRESS_REQUEST: LimineRequest<LimineKernelAddressResponse> =
     LimineRequest::new(0x71ba76863cc55f63, 0xb2644a48c516a487);
 
+// Module Request - files loaded alongside the kernel (module_path in limine.conf)
+#[repr(C)]
+pub struct LimineModuleResponse {
+    pub revision: u64,
+    pub module_count: u64,
+    pub modules: *const *const LimineFile,
+}
+
+#[repr(C)]
+pub struct LimineFile {
+    pub revision: u64,
+    pub address: *mut u8,
+    pub size: u64,
+    pub path: *const u8,
+    pub cmdline: *const u8,
+    pub media_type: u32,
+    pub unused: u32,
+    pub tftp_ip: u32,
+    pub tftp_port: u32,
+    pub partition_index: u32,
+    pub mbr_disk_id: u32,
+    pub gpt_disk_uuid: [u8; 16],
+    pub gpt_part_uuid: [u8; 16],
+    pub part_uuid: [u8; 16],
+}
+
+impl LimineFile {
+    /// Path of the file as given in limine.conf (e.g. "/boot/hello.bin")
+    pub fn path(&self) -> &'static str {
+        c_str(self.path)
+    }
+
+    /// Command line given with the file (`cmdline:` in limine.conf, for the kernel)
+    pub fn cmdline(&self) -> &'static str {
+        c_str(self.cmdline)
+    }
+
+    /// File contents as loaded by Limine
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.address, self.size as usize) }
+    }
+}
+
+impl LimineModuleResponse {
+    pub fn modules(&self) -> impl Iterator<Item = &'static LimineFile> {
+        let modules = self.modules;
+        (0..self.module_count as usize).map(move |i| unsafe { &**modules.add(i) })
+    }
+}
+
+/// Borrow a NUL-terminated bootloader string (non-UTF-8 yields "")
+fn c_str(ptr: *const u8) -> &'static str {
+    if ptr.is_null() {
+        return "";
+    }
+    unsafe {
+        core::ffi::CStr::from_ptr(ptr as *const core::ffi::c_char)
+            .to_str()
+            .unwrap_or("")
+    }
+}
+
+#[used]
+#[link_section = ".limine_reqs"]
+pub static MODULE_REQUEST: LimineRequest<LimineModuleResponse> =
+    LimineRequest::new(0x3e7e279702be32af, 0xca1c4f3bd1280cee);
+
+// Kernel File Request - the kernel's own ELF file, for its symbol table
+#[repr(C)]
+pub struct LimineKernelFileResponse {
+    pub revision: u64,
+    pub kernel_file: *const LimineFile,
+}
+
+impl LimineKernelFileResponse {
+    pub fn file(&self) -> Option<&'static LimineFile> {
+        if self.kernel_file.is_null() {
+            None
+        } else {
+            Some(unsafe { &*self.kernel_file })
+        }
+    }
+}
+
+#[used]
+#[link_section = ".limine_reqs"]
+pub static KERNEL_FILE_REQUEST: LimineRequest<LimineKernelFileResponse> =
+    LimineRequest::new(0xad97e90e83f1ed67, 0x31eb5d1c5ff23b69);
+
+// RSDP Request - the ACPI root pointer
+#[repr(C)]
+pub struct LimineRsdpResponse {
+    pub revision: u64,
+    /// An HHDM address before base revision 3, physical from it on
+    pub address: u64,
+}
+
+#[used]
+#[link_section = ".limine_reqs"]
+pub static RSDP_REQUEST: LimineRequest<LimineRsdpResponse> =
+    LimineRequest::new(0xc5e77b6b397e7b43, 0x27637845accdcf3c);
+
+// SMP (multiprocessor) Request
+#[repr(C)]
+pub struct LimineSmpRequest {
+    id: [u64; 4],
+    revision: u64,
+    response: *const LimineSmpResponse,
+    flags: u64,
+}
+
+unsafe impl Sync for LimineSmpRequest {}
+
+impl LimineSmpRequest {
+    pub const fn new() -> Self {
+        LimineSmpRequest {
+            id: [LIMINE_COMMON_MAGIC[0], LIMINE_COMMON_MAGIC[1], 0x95a67b819a1b857e, 0xa0b61b723b6a73e0],
+            revision: 0,
+            response: ptr::null(),
+            flags: 0, // xAPIC mode (bit 0 would request x2APIC)
+        }
+    }
+
+    pub fn get_response(&self) -> Option<&'static LimineSmpResponse> {
+        if self.response.is_null() {
+            None
+        } else {
+            Some(unsafe { &*self.response })
+        }
+    }
+}
+
+#[repr(C)]
+pub struct LimineSmpResponse {
+    pub revision: u64,
+    pub flags: u32,
+    pub bsp_lapic_id: u32,
+    pub cpu_count: u64,
+    pub cpus: *const *const LimineSmpInfo,
+}
+
+#[repr(C)]
+pub struct LimineSmpInfo {
+    pub processor_id: u32,
+    pub lapic_id: u32,
+    pub reserved: u64,
+    /// Writing an entry point here atomically releases the AP from Limine's wait loop
+    pub goto_address: AtomicU64,
+    pub extra_argument: AtomicU64,
+}
+
+#[used]
+#[link_section = ".limine_reqs"]
+pub static SMP_REQUEST: LimineSmpRequest = LimineSmpRequest::new();
+
 // Terminal Request (for text output via Limine)
 type LimineTerminalCallback = extern "C" fn(*const LimineTerminal, u64, u64, u64, u64);
 
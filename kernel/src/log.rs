@@ -0,0 +1,163 @@
+//! Kernel log
+//! `info!`, `warn!` and friends stamp a message with the monotonic clock,
+//! keep it in an in-memory ring (`shared::log`) that `dmesg` reads back, and
+//! echo it to serial unless it is below `SERIAL_LEVEL`. The echo goes to the
+//! port `log-port=` picks, or with the console's, unless `console=` leaves
+//! serial out. Formatting goes through
+//! a stack buffer, so logging works before the heap exists.
+//! Messages from `SCREEN_LEVEL` up are also shown on the log's virtual
+//! terminal (Alt+F2), replayed from the ring once the terminals start.
+//! `loglevel=` on the kernel command line sets both levels.
+//! Warnings are shown in yellow and errors in red, on the serial echo, the
+//! log terminal and in `dmesg`.
+
+use crate::arch::{Arch, Cpu};
+use crate::drivers::vga::{self, Color, Sgr, DEFAULT_COLORS};
+use crate::drivers::console::{self, Sink};
+use crate::drivers::serial::{self, Role};
+use crate::sync::spinlock::Spinlock;
+use crate::time;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use shared::bootfmt::{self, BootBuffer};
+use shared::log::{LogRing, Timestamp, TEXT_CAPACITY};
+
+pub use shared::log::Level;
+
+/// Messages kept for `dmesg`
+const LOG_RECORDS: usize = 256;
+
+/// Least important level still echoed to the serial port
+static SERIAL_LEVEL: AtomicU8 = AtomicU8::new(Level::Debug as u8);
+
+/// Least important level shown on the log terminal
+static SCREEN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+static KMSG: Spinlock<LogRing<LOG_RECORDS>> = Spinlock::new(LogRing::new());
+
+/// Number of the next message for the log terminal
+static SCREEN_SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ($crate::log::_log($level, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+fn threshold(setting: &AtomicU8) -> Level {
+    Level::ALL[setting.load(Ordering::Relaxed) as usize]
+}
+
+/// Echo messages from `level` up, on serial and the log terminal alike
+/// Every message is still kept for `dmesg`.
+pub fn set_echo_level(level: Level) {
+    SERIAL_LEVEL.store(level as u8, Ordering::Relaxed);
+    SCREEN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, args: fmt::Arguments) {
+    if level >= threshold(&SERIAL_LEVEL) && (serial::log_has_own_port() || console::enabled(Sink::Serial)) {
+        match color(level) {
+            Some(color) => {
+                let (fg, bg) = console::color();
+                serial::print(Role::Log, format_args!("{}{}{}\n", Sgr(color, bg), args, Sgr(fg, bg)));
+            }
+            None => serial::print(Role::Log, format_args!("{}\n", args)),
+        }
+    }
+
+    let text: BootBuffer<TEXT_CAPACITY> = bootfmt::format(args);
+    let timestamp = time::monotonic_ns();
+    // Messages are also logged from interrupt handlers
+    Arch::without_interrupts(|| KMSG.lock().push(level, timestamp, text.as_str()));
+    echo_to_terminal();
+}
+
+/// Show the messages logged since the last call on the log terminal
+/// Skipped while the console is busy (this CPU may be in the middle of
+/// printing); the messages then wait for the next one logged.
+fn echo_to_terminal() {
+    vga::try_write_log(|out| {
+        let (first, end) = bounds();
+        let screen_level = threshold(&SCREEN_LEVEL);
+        for seq in SCREEN_SEQ.load(Ordering::Relaxed).max(first)..end {
+            match message(seq) {
+                Some(message) if message.level >= screen_level => {
+                    let (fg, bg) = (color(message.level).unwrap_or(DEFAULT_COLORS.0), DEFAULT_COLORS.1);
+                    let _ = writeln!(
+                        out,
+                        "{}{} {:<5} {}{}",
+                        Sgr(fg, bg),
+                        Timestamp(message.timestamp_ns),
+                        message.level.as_str(),
+                        message.text.as_str(),
+                        Sgr(DEFAULT_COLORS.0, DEFAULT_COLORS.1)
+                    );
+                }
+                _ => {}
+            }
+        }
+        SCREEN_SEQ.store(end, Ordering::Relaxed);
+    });
+}
+
+/// Text color for messages at `level`, where it differs from the console's
+pub fn color(level: Level) -> Option<Color> {
+    match level {
+        Level::Warn => Some(Color::Yellow),
+        Level::Error => Some(Color::LightRed),
+        _ => None,
+    }
+}
+
+/// One record copied out of the log
+pub struct Message {
+    pub level: Level,
+    pub timestamp_ns: u64,
+    pub text: BootBuffer<TEXT_CAPACITY>,
+}
+
+/// Message number `seq` (counting from boot), if it is still in the log
+/// Copied out one at a time so readers never hold the log while printing.
+pub fn message(seq: u64) -> Option<Message> {
+    Arch::without_interrupts(|| {
+        KMSG.lock().get(seq).map(|record| Message {
+            level: record.level,
+            timestamp_ns: record.timestamp_ns,
+            text: bootfmt::format(format_args!("{}", record.text)),
+        })
+    })
+}
+
+/// Numbers of the oldest message still held and of the next to be logged
+pub fn bounds() -> (u64, u64) {
+    Arch::without_interrupts(|| {
+        let log = KMSG.lock();
+        (log.first(), log.end())
+    })
+}
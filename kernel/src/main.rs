@@ -1,43 +1,140 @@
 #![no_std]
 #![no_main]
 #![feature(alloc_error_handler)]
+#![feature(coerce_unsized, unsize)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::testing::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 extern crate alloc;
 
+mod acpi;
 mod arch;
+mod backtrace;
+mod bootfmt;
+mod bootinfo;
+mod cmdline;
 mod drivers;
+mod features;
+mod fs;
+mod hwinfo;
+mod input;
+mod interrupts;
+mod ipc;
 mod limine;
+mod log;
 mod memory;
+#[cfg(feature = "net")]
+mod net;
+mod power;
+mod process;
+mod rand;
 mod shell;
+#[cfg(feature = "storage")]
+mod storage;
+mod symbols;
 mod sync;
+mod syscall;
+#[cfg(test)]
+mod testing;
+mod time;
+mod watchdog;
+mod workqueue;
 
+use arch::{Arch, Cpu, Mmu};
 use core::panic::PanicInfo;
 
+const SYSCALL_STACK_PAGES: usize = 4; // 16KB kernel stack for syscalls on the BSP
+const SHELL_STACK_PAGES: usize = 16; // 64KB, as much as Limine's boot stack
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("KERNEL PANIC: {}", info);
+    // Never use println! here: the panic may have happened with a console lock held
+    boot_println!("KERNEL PANIC: {}", info);
+    arch::x86_64::interrupts::print_control_registers();
+    backtrace::print_here();
+    watchdog::stop();
     loop {
         core::hint::spin_loop();
     }
 }
 
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panic(info)
+}
+
 #[no_mangle]
 extern "C" fn _start() -> ! {
     // Initialize serial port first for early debugging
     drivers::serial::init();
-    serial_println!("Serial port initialized");
+    boot_println!("Serial port initialized");
 
-    // Get HHDM offset from Limine
-    let hhdm_offset = limine::HHDM_REQUEST
-        .get_response()
-        .expect("Limine HHDM request failed")
-        .offset;
+    // Nothing reads Limine's responses past this point (but SMP startup and
+    // the Limine terminal), so their memory can be reclaimed later
+    bootinfo::init();
+    let hhdm_offset = bootinfo::get().hhdm_offset;
+    cmdline::init();
+    drivers::serial::configure();
 
-    serial_println!("HHDM offset: {:#x}", hhdm_offset);
+    boot_println!("HHDM offset: {:#x}", hhdm_offset);
+    memory::set_hhdm_offset(hhdm_offset);
+    boot_println!("Kernel slide: {:#x}", symbols::slide());
 
     // Initialize VGA driver
     drivers::vga::init(hhdm_offset);
 
+    info!("VGA initialized");
+    info!("Console output: {}", drivers::console::active());
+    info!("wflos - Rust Microkernel OS");
+    info!("Version 0.4.0 (Phase 4: Command-Line Interface)");
+
+    // Initialize GDT
+    info!("Initializing GDT...");
+    arch::x86_64::gdt::init();
+    info!("GDT loaded");
+
+    // Initialize IDT
+    info!("Initializing IDT...");
+    arch::x86_64::idt::init();
+    info!("IDT loaded");
+    arch::x86_64::enable_nx();
+    arch::x86_64::fpu::init();
+
+    // Initialize PIC
+    info!("Initializing PIC...");
+    arch::x86_64::pic::init();
+    info!("PIC initialized and remapped");
+
+    // Initialize frame allocator (before interrupts and heap)
+    let memory_map = bootinfo::get().memory_map();
+    let memory_total = if !memory_map.is_empty() {
+        info!("Initializing frame allocator...");
+        memory::frame_allocator::init(memory_map, hhdm_offset);
+
+        let (total, used, free) = memory::frame_allocator::stats();
+        info!("Frame allocator: {} total, {} used, {} free", total, used, free);
+        let reserved = memory::frame_allocator::reserved_frames();
+        if reserved > 0 {
+            warn!("Frame allocator: {} usable frames overlap other memory map entries, left reserved", reserved);
+        }
+        Some(total)
+    } else {
+        None
+    };
+
+    // Kernel mappings are W^X from here on
+    memory::protect::init();
+
+    // Virtual terminals keep their scrollback in frames, so the screen
+    // output starts here
+    match drivers::vga::start_terminals() {
+        Ok(()) => info!("Virtual terminals started: Alt+F1 to Alt+F{}", drivers::vt::COUNT),
+        Err(e) => warn!("No virtual terminals: {}", e),
+    }
+
     // Clear screen
     drivers::vga::clear_screen();
 
@@ -54,95 +151,113 @@ extern "C" fn _start() -> ! {
     println!("Booting kernel...");
     println!();
 
-    serial_println!("VGA initialized");
-    serial_println!("wflos - Rust Microkernel OS");
-    serial_println!("Version 0.4.0 (Phase 4: Command-Line Interface)");
-
-    // Initialize GDT
-    serial_println!("Initializing GDT...");
-    arch::x86_64::gdt::init();
-    serial_println!("GDT loaded");
-
-    // Initialize IDT
-    serial_println!("Initializing IDT...");
-    arch::x86_64::idt::init();
-    serial_println!("IDT loaded");
-
-    // Initialize PIC
-    serial_println!("Initializing PIC...");
-    arch::x86_64::pic::init();
-    serial_println!("PIC initialized and remapped");
-
-    // Initialize frame allocator (before interrupts and heap)
-    if let Some(memmap_response) = limine::MEMMAP_REQUEST.get_response() {
-        let entry_count = memmap_response.entry_count as usize;
-
-        // Can't use Vec yet (heap not initialized), build array manually
-        // Use a dummy reference that will be overwritten for each valid entry
-        let dummy = unsafe { &**memmap_response.entries };
-        let mut map_slice: [&limine::LimineMemoryMapEntry; 64] = [dummy; 64];
-        let mut map_count = 0;
-
-        for (i, slot) in map_slice.iter_mut().enumerate().take(entry_count.min(64)) {
-            let entry = unsafe { &**memmap_response.entries.add(i) };
-            *slot = entry;
-            map_count += 1;
-        }
-
-        let initialized_slice = &map_slice[..map_count];
-
-        serial_println!("Initializing frame allocator...");
-        memory::frame_allocator::init(initialized_slice, hhdm_offset);
-
-        let (total, used, free) = memory::frame_allocator::stats();
-        serial_println!("Frame allocator: {} total, {} used, {} free", total, used, free);
+    if let Some(total) = memory_total {
         println!("Memory: {} KB total", (total * 4096) / 1024);
     }
 
     // Initialize heap allocator (before interrupts)
-    serial_println!("Initializing heap allocator...");
+    info!("Initializing heap allocator...");
     match memory::heap::init(hhdm_offset) {
         Ok(()) => {
-            serial_println!("Heap allocator initialized");
+            info!("Heap allocator initialized");
             println!("Heap: 64 KB initialized");
             memory::heap::verify_heap();
+
+            info!("Mounting root filesystem...");
+            fs::init();
         }
         Err(e) => {
-            serial_println!("Heap allocator failed: {}", e);
+            error!("Heap allocator failed: {}", e);
             println!("Heap: FAILED ({})", e);
         }
     }
+    memory::pagecheck::debug_check(Arch::active_table(), "boot");
+
+    // Start application processors (needs frames for their stacks)
+    info!("Starting application processors...");
+    arch::x86_64::smp::init();
+    println!("CPUs: {} online", arch::x86_64::smp::online_count());
+
+    // Enable the syscall instruction on the BSP
+    info!("Initializing syscall interface...");
+    match memory::kstack::allocate("syscall", 0, SYSCALL_STACK_PAGES) {
+        Some(stack_top) => {
+            arch::x86_64::syscall::init(stack_top.as_usize());
+            info!("Syscall interface ready");
+        }
+        None => warn!("Syscall interface disabled: no frames for kernel stack"),
+    }
 
     // Initialize keyboard
-    serial_println!("Initializing keyboard...");
+    info!("Initializing keyboard...");
     drivers::keyboard::init();
-    serial_println!("Keyboard initialized");
+    info!("Keyboard initialized");
+    drivers::serial::enable_interrupts();
+    info!("Serial console input and buffered output enabled");
 
-    // Enable interrupts (after all initialization is complete)
-    serial_println!("Enabling interrupts...");
-    unsafe {
-        core::arch::asm!("sti");
+    #[cfg(feature = "net")]
+    {
+        info!("Initializing network device...");
+        match drivers::virtio::net::init() {
+            Ok(()) => net::init(),
+            Err(e) => warn!("  {}", e),
+        }
     }
-    serial_println!("Interrupts enabled");
+
+    // Every driver has probed by now
+    hwinfo::collect();
+
+    // Start the system tick
+    info!("Starting PIT at {} Hz...", time::TICK_HZ);
+    time::init();
+    rand::init();
+    watchdog::start();
+
+    // Enable interrupts (after all initialization is complete)
+    info!("Enabling interrupts...");
+    Arch::enable_interrupts();
+    info!("Interrupts enabled");
 
     println!();
     println!("Phase 5 complete: Heap allocator operational");
     println!();
 
-    serial_println!("\n=== Phase 5 Complete ===");
-    serial_println!("  - GDT initialized and loaded");
-    serial_println!("  - IDT initialized with exception handlers");
+    info!("=== Phase 5 Complete ===");
+    info!("  - GDT initialized and loaded");
+    info!("  - IDT initialized with exception handlers");
     let (total, _used, _free) = memory::frame_allocator::stats();
-    serial_println!("  - Frame allocator operational ({} frames available)", total);
-    serial_println!("  - Heap allocator initialized (64 KB)");
-    serial_println!("  - PIC remapped (IRQs at vectors 32-47)");
-    serial_println!("  - Keyboard driver ready (IRQ1)");
-    serial_println!("  - Interrupts enabled");
-    serial_println!("  - Shell ready for commands");
-    serial_println!("========================\n");
+    info!("  - Frame allocator operational ({} frames available)", total);
+    info!("  - Heap allocator initialized (64 KB)");
+    info!("  - PIC remapped (IRQs at vectors 32-47)");
+    info!("  - Keyboard driver ready (IRQ1)");
+    info!("  - Interrupts enabled");
+    info!("  - Shell ready for commands");
+    info!("========================");
+
+    // Everything is up: a test build runs its tests here instead of the shell
+    #[cfg(test)]
+    test_main();
 
     // Keyboard is ready - launch shell
-    serial_println!("Launching shell...");
+    info!("Launching shell...");
+
+    // Limine's boot stack has nothing below it to catch an overflow, so the
+    // shell gets a guarded one if there are frames for it
+    match memory::kstack::allocate("shell", 0, SHELL_STACK_PAGES) {
+        Some(stack_top) => unsafe { arch::x86_64::context::run_on_stack(stack_top.as_u64(), shell_main) },
+        None => {
+            warn!("No frames for a guarded shell stack, staying on the boot stack");
+            shell_main()
+        }
+    }
+}
+
+extern "C" fn shell_main() -> ! {
+    // Off the boot stack, what Limine no longer needs can be reused
+    match memory::bootmem::reclaim() {
+        Ok(frames) => info!("Reclaimed {} KB of bootloader memory", frames * 4),
+        Err(e) => info!("Bootloader memory not reclaimed: {}", e),
+    }
 
     // Run the shell REPL (never returns)
     shell::run();
@@ -0,0 +1,89 @@
+//! Reclaiming the memory Limine booted the kernel with
+//! Bootloader-reclaimable entries hold Limine's page tables (still the
+//! kernel's own), its response structures, the boot stack and the Limine
+//! terminal. The responses were copied out at boot (`bootinfo`), so
+//! `reclaim` hands all the rest to the frame allocator but the table frames
+//! reachable from the active root, which stay in place as frames owned by
+//! `FrameOwner::Boot`. The boot stack, the Limine terminal and the code and
+//! stacks of APs Limine still holds parked can't be told apart from the
+//! rest, so it refuses while any of them is in use.
+
+use super::frame_allocator;
+use super::paging::{self, HUGE_PAGE, PRESENT};
+use super::{hhdm_offset, PhysAddr};
+use crate::arch::x86_64::smp;
+use crate::arch::{Arch, Mmu};
+use crate::bootinfo;
+use crate::drivers::vga;
+use crate::limine::LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE;
+use shared::data_structures::array_vec::ArrayVec;
+
+/// Most frames of bootloader memory that can be kept
+const MAX_KEPT: usize = 256;
+
+/// Frames of bootloader memory that must not be freed
+struct Kept {
+    frames: ArrayVec<u64, MAX_KEPT>,
+    overflowed: bool,
+}
+
+impl Kept {
+    fn add(&mut self, frame: u64) {
+        if !in_boot_memory(frame) || self.frames.contains(&frame) {
+            return;
+        }
+        if self.frames.push(frame).is_err() {
+            self.overflowed = true;
+        }
+    }
+
+    /// Every table below the one at `table`, which sits at `level` (4 = PML4)
+    fn add_tables(&mut self, table: PhysAddr, level: u32) {
+        self.add(table.as_u64());
+        if level == 1 {
+            return;
+        }
+        for &entry in paging::table_entries(table).iter() {
+            if entry & PRESENT != 0 && (level == 4 || entry & HUGE_PAGE == 0) {
+                self.add_tables(paging::entry_addr(entry), level - 1);
+            }
+        }
+    }
+}
+
+/// Whether physical address `phys` lies in bootloader-reclaimable memory
+fn in_boot_memory(phys: u64) -> bool {
+    bootinfo::get()
+        .memory_map()
+        .iter()
+        .any(|entry| entry.entry_type == LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE && (entry.base..entry.end()).contains(&phys))
+}
+
+/// Give bootloader-reclaimable memory to the frame allocator, keeping what
+/// is still in use; returns the frames freed (0 on later calls)
+pub fn reclaim() -> Result<usize, &'static str> {
+    let memory_map = bootinfo::get().memory_map();
+    if vga::uses_limine_terminal() {
+        return Err("The Limine terminal is in use");
+    }
+    // Without the `smp` feature, past MAX_CPUS, or out of memory for their
+    // stacks, APs are left spinning in Limine's code
+    if smp::parked_in_limine() > 0 {
+        return Err("Application processors are still parked by Limine");
+    }
+    let here = 0u8;
+    let stack = core::ptr::addr_of!(here) as u64;
+    if stack.checked_sub(hhdm_offset()).is_some_and(in_boot_memory) {
+        return Err("Still running on the boot stack");
+    }
+
+    let mut kept = Kept { frames: ArrayVec::new(), overflowed: false };
+    kept.add_tables(Arch::active_table(), 4);
+    if kept.overflowed {
+        return Err("Too many bootloader frames still in use");
+    }
+
+    let frames = &mut kept.frames;
+    frames.sort_unstable();
+    Ok(frame_allocator::reclaim(memory_map, &|frame| frames.binary_search(&frame.as_u64()).is_ok()))
+}
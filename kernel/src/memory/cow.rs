@@ -0,0 +1,44 @@
+//! Frames shared copy-on-write between address spaces
+//! `fork` maps each user frame into the child as well as the parent, so a
+//! frame can have several owners. Only frames with more than one are
+//! recorded, with how many extra mappings they have; a frame absent from the
+//! table has exactly one owner and is freed when that owner lets it go.
+
+use super::PhysAddr;
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::BTreeMap;
+
+/// Extra mappings of each shared frame, keyed by physical address
+static SHARED: Spinlock<BTreeMap<u64, usize>> = Spinlock::new(BTreeMap::new());
+
+/// Note one more mapping of the frame at `phys`
+pub fn share(phys: PhysAddr) {
+    *SHARED.lock().entry(phys.as_u64()).or_insert(0) += 1;
+}
+
+/// Whether the frame at `phys` is mapped more than once
+pub fn is_shared(phys: PhysAddr) -> bool {
+    SHARED.lock().contains_key(&phys.as_u64())
+}
+
+/// Drop one mapping of the frame at `phys`; true if it was the last, so the
+/// caller now owns the frame outright
+pub fn release(phys: PhysAddr) -> bool {
+    let mut shared = SHARED.lock();
+    match shared.get_mut(&phys.as_u64()) {
+        None => true,
+        Some(1) => {
+            shared.remove(&phys.as_u64());
+            false
+        }
+        Some(extra) => {
+            *extra -= 1;
+            false
+        }
+    }
+}
+
+/// Frames currently shared
+pub fn shared_frames() -> usize {
+    SHARED.lock().len()
+}
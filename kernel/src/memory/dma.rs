@@ -0,0 +1,85 @@
+//! DMA buffers
+//! A `DmaBuffer` is a zeroed, physically contiguous run of frames that a
+//! device can be pointed at, with both addresses a driver needs: the physical
+//! one to program into the device and the HHDM virtual one to fill and read
+//! the buffer through. The buffer owns its frames and returns them when
+//! dropped, so a driver that fails halfway through setup does not leak them;
+//! a driver must stop the device using a buffer before dropping it.
+//! The HHDM maps RAM write-back, which is right for DMA on x86: devices snoop
+//! the CPU caches, so no flushing is needed. Ordering still matters, and
+//! drivers fence between writing a buffer and telling the device about it.
+//! Buffers are limited to `MAX_LEN` bytes (one buddy block); devices that can
+//! only address low memory ask for a buffer below a zone's limit.
+
+#![cfg_attr(not(feature = "net"), allow(dead_code))]
+
+use super::frame_allocator::{self, FrameOwner, Zone, FRAME_SIZE};
+use super::{phys_to_virt, PhysAddr, VirtAddr};
+use core::{ptr, slice};
+use shared::buddy::MAX_BLOCK;
+
+/// Largest buffer: 4 MB
+pub const MAX_LEN: usize = MAX_BLOCK * FRAME_SIZE;
+
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    /// Requested length; the frames behind it may hold more
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocate a zeroed buffer of `len` bytes lying entirely below the limit
+    /// of `zone` (`Zone::High` for devices that reach all of memory)
+    pub fn new(len: usize, zone: Zone) -> Result<Self, &'static str> {
+        if len == 0 || len > MAX_LEN {
+            return Err("DMA buffer size out of range");
+        }
+        let phys = frame_allocator::allocate_contiguous_frames_in(len.div_ceil(FRAME_SIZE), zone, FrameOwner::Dma)
+            .ok_or("Out of memory for DMA buffer")?;
+        let virt = phys_to_virt(phys);
+        unsafe { ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, len) };
+        Ok(DmaBuffer { phys, virt, len })
+    }
+
+    /// Address to program into the device
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Physical address `offset` bytes into the buffer
+    pub fn phys_at(&self, offset: usize) -> PhysAddr {
+        assert!(offset < self.len, "offset past the end of a DMA buffer");
+        self.phys + offset
+    }
+
+    /// Address the kernel reaches the buffer through
+    pub fn virt(&self) -> VirtAddr {
+        self.virt
+    }
+
+    /// Length in bytes, as requested
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// The buffer's contents; the device may change them at any time it owns
+    /// part of the buffer, so read what it wrote only once it says so
+    #[allow(dead_code)]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virt.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virt.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        for frame in 0..self.len.div_ceil(FRAME_SIZE) {
+            frame_allocator::deallocate_frame(self.phys + frame * FRAME_SIZE);
+        }
+    }
+}
@@ -1,184 +1,394 @@
-//! Physical frame allocator using bitmap
+//! Physical frame allocator
 //! Manages 4KB physical memory frames
 //! Properly handles non-contiguous memory regions from the bootloader memory map
-
-use crate::limine::{LimineMemoryMapEntry, LIMINE_MEMMAP_USABLE};
+//! Free frames are found through a buddy allocator (`shared::buddy`), so
+//! single frames and contiguous runs take O(log n); a bitmap
+//! (`shared::data_structures::bitmap`) records which frames are in use, so
+//! a frame freed twice is caught before it reaches the buddy lists.
+//! Memory is divided into zones by physical address, each with its own buddy
+//! lists, so devices that can only reach low memory can be given frames
+//! there (`allocate_frame_in`). Other allocations take the highest zone
+//! first, which leaves the scarce low zones for them.
+//! Usable memory map entries are managed from boot, less any pages another
+//! entry overlaps. Bootloader-reclaimable memory joins them later (`reclaim`,
+//! driven by `memory::bootmem`), once Limine's structures no longer need it.
+
+use super::{oom, PhysAddr};
+use crate::bootinfo::MemoryMapEntry;
+use crate::limine::{LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE, LIMINE_MEMMAP_USABLE};
 use crate::sync::spinlock::Spinlock;
+use shared::buddy::{Buddy, MAX_BLOCK};
+use shared::data_structures::bitmap::{self, Bitmap};
+use shared::memrange;
 
-const FRAME_SIZE: usize = 4096;
+pub const FRAME_SIZE: usize = 4096;
 const MAX_FRAMES: usize = 262144; // Support up to 1GB of RAM (256K frames)
-const BITMAP_SIZE: usize = MAX_FRAMES / 8; // 32KB bitmap
 const MAX_REGIONS: usize = 64;
 
+/// Subsystem a frame was allocated for, recorded per frame with the `debugging` feature
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum FrameOwner {
+    Heap,
+    PageTable,
+    Stack,
+    Dma,
+    User,
+    ZeroPool,
+    Slab,
+    Console,
+    /// Bootloader-reclaimable frames Limine's structures still occupy
+    Boot,
+}
+
+impl FrameOwner {
+    pub const ALL: [FrameOwner; 9] = [
+        FrameOwner::Heap,
+        FrameOwner::PageTable,
+        FrameOwner::Stack,
+        FrameOwner::Dma,
+        FrameOwner::User,
+        FrameOwner::ZeroPool,
+        FrameOwner::Slab,
+        FrameOwner::Console,
+        FrameOwner::Boot,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameOwner::Heap => "heap",
+            FrameOwner::PageTable => "page tables",
+            FrameOwner::Stack => "stacks",
+            FrameOwner::Dma => "DMA",
+            FrameOwner::User => "user pages",
+            FrameOwner::ZeroPool => "zero pool",
+            FrameOwner::Slab => "slabs",
+            FrameOwner::Console => "console",
+            FrameOwner::Boot => "bootloader",
+        }
+    }
+}
+
+const OWNER_COUNT: usize = FrameOwner::ALL.len();
+
+/// Physical memory zones, from the lowest; each holds the frames below its
+/// limit that no lower zone holds
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Zone {
+    /// Below 1MB, e.g. for real-mode trampolines
+    Low,
+    /// Below 16MB, reachable by ISA DMA
+    Isa,
+    /// Below 4GB, reachable by 32-bit DMA
+    Dma32,
+    High,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 4] = [Zone::Low, Zone::Isa, Zone::Dma32, Zone::High];
+
+    /// First physical address past the zone
+    pub fn limit(self) -> u64 {
+        match self {
+            Zone::Low => 0x10_0000,
+            Zone::Isa => 0x100_0000,
+            Zone::Dma32 => 0x1_0000_0000,
+            Zone::High => u64::MAX,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Zone::Low => "low (<1MB)",
+            Zone::Isa => "ISA DMA (<16MB)",
+            Zone::Dma32 => "DMA32 (<4GB)",
+            Zone::High => "high",
+        }
+    }
+
+    /// The zone holding the frame at `addr`
+    fn of(addr: PhysAddr) -> Zone {
+        Zone::ALL.into_iter().find(|zone| addr.as_u64() < zone.limit()).unwrap_or(Zone::High)
+    }
+
+    /// This zone and the ones below it, highest first: where a frame that
+    /// must lie below this zone's limit can come from
+    fn and_below(self) -> impl Iterator<Item = Zone> {
+        Zone::ALL.into_iter().rev().filter(move |&zone| zone <= self)
+    }
+}
+
+const ZONE_COUNT: usize = Zone::ALL.len();
+/// Tags hold the owner plus one, so an allocator with nothing tagged is all
+/// zeros and its per-frame arrays take no space in the kernel image
+#[cfg(feature = "debugging")]
+const TAG_NONE: u8 = 0;
+
 #[derive(Clone, Copy)]
 struct MemoryRegion {
-    base: usize,
+    base: PhysAddr,
     frame_count: usize,
+    /// Frame index of `base`; regions start on a `MAX_BLOCK` boundary so
+    /// buddy blocks never span two of them
+    first_index: usize,
+    /// Memory map entries are split at zone limits, so a region is in one zone
+    zone: Zone,
 }
 
 impl MemoryRegion {
     const fn empty() -> Self {
-        MemoryRegion { base: 0, frame_count: 0 }
+        MemoryRegion { base: PhysAddr::zero(), frame_count: 0, first_index: 0, zone: Zone::Low }
+    }
+
+    fn indices(&self) -> core::ops::Range<usize> {
+        self.first_index..self.first_index + self.frame_count
+    }
+
+    fn end(&self) -> PhysAddr {
+        self.base + self.frame_count * FRAME_SIZE
     }
 }
 
 pub struct FrameAllocator {
-    bitmap: [u8; BITMAP_SIZE],
+    /// Frames in use, 32KB for `MAX_FRAMES`
+    bitmap: Bitmap<{ bitmap::words(MAX_FRAMES) }>,
+    /// One pool per zone
+    buddy: Buddy<MAX_FRAMES, ZONE_COUNT>,
+    zone_frames: [usize; ZONE_COUNT],
     total_frames: usize,
     used_frames: usize,
     regions: [MemoryRegion; MAX_REGIONS],
     region_count: usize,
+    /// First frame index no region has taken
+    next_index: usize,
+    /// Usable frames left out because another entry overlaps them
+    reserved_frames: usize,
+    /// Whether bootloader-reclaimable memory has been added
+    reclaimed: bool,
     hhdm_offset: u64,
+    #[cfg(feature = "debugging")]
+    tags: [u8; MAX_FRAMES],
+    #[cfg(feature = "debugging")]
+    owner_frames: [usize; OWNER_COUNT],
 }
 
 impl FrameAllocator {
     pub const fn new() -> Self {
         FrameAllocator {
-            bitmap: [0; BITMAP_SIZE],
+            bitmap: Bitmap::new(),
+            buddy: Buddy::new(),
+            zone_frames: [0; ZONE_COUNT],
             total_frames: 0,
             used_frames: 0,
             regions: [MemoryRegion::empty(); MAX_REGIONS],
             region_count: 0,
+            next_index: 0,
+            reserved_frames: 0,
+            reclaimed: false,
             hhdm_offset: 0,
+            #[cfg(feature = "debugging")]
+            tags: [TAG_NONE; MAX_FRAMES],
+            #[cfg(feature = "debugging")]
+            owner_frames: [0; OWNER_COUNT],
         }
     }
 
     /// Initialize allocator with memory map from Limine
-    pub fn init(&mut self, memory_map: &[&LimineMemoryMapEntry], hhdm_offset: u64) {
+    /// Only usable entries are managed, less any pages another entry overlaps
+    /// (firmware may report a framebuffer or reserved hole on top of usable
+    /// memory); those are counted as reserved and never handed out.
+    /// Frames past `MAX_FRAMES` indices, or past `MAX_REGIONS` regions, are
+    /// left unmanaged.
+    pub fn init(&mut self, memory_map: &[MemoryMapEntry], hhdm_offset: u64) {
         self.hhdm_offset = hhdm_offset;
 
-        for entry in memory_map {
-            if entry.entry_type == LIMINE_MEMMAP_USABLE && self.region_count < MAX_REGIONS {
-                let frames = (entry.length as usize) / FRAME_SIZE;
-                self.regions[self.region_count] = MemoryRegion {
-                    base: entry.base as usize,
-                    frame_count: frames,
-                };
-                self.region_count += 1;
-                self.total_frames += frames;
-            }
+        let others = memory_map
+            .iter()
+            .filter(|entry| entry.entry_type != LIMINE_MEMMAP_USABLE)
+            .map(|entry| (entry.base, entry.end()));
+        for entry in memory_map.iter().filter(|entry| entry.entry_type == LIMINE_MEMMAP_USABLE) {
+            let (start, end) = (entry.base, entry.end());
+            self.reserved_frames += memrange::overlap(start, end, others.clone(), FRAME_SIZE as u64) as usize / FRAME_SIZE;
+            memrange::subtract(start, end, others.clone(), FRAME_SIZE as u64, |base, end| {
+                self.add_range(base, end, &|_| false)
+            });
         }
-
-        // Mark all frames as free initially (bitmap already zeroed)
     }
 
-    #[allow(dead_code)]
-    /// Convert a bitmap frame index to a physical address by walking regions
-    fn frame_index_to_phys(&self, index: usize) -> Option<usize> {
-        let mut offset = 0;
-        for i in 0..self.region_count {
-            let region = &self.regions[i];
-            if index < offset + region.frame_count {
-                let frame_in_region = index - offset;
-                return Some(region.base + frame_in_region * FRAME_SIZE);
-            }
-            offset += region.frame_count;
+    /// Manage the bootloader-reclaimable entries too, except the frames
+    /// `keep` picks, which stay used by `FrameOwner::Boot`; returns how many
+    /// frames were freed. Only the first call adds anything.
+    pub fn reclaim(&mut self, memory_map: &[MemoryMapEntry], keep: &dyn Fn(PhysAddr) -> bool) -> usize {
+        if core::mem::replace(&mut self.reclaimed, true) {
+            return 0;
         }
-        None
+        let (total, used) = (self.total_frames, self.used_frames);
+        let others = memory_map
+            .iter()
+            .filter(|entry| entry.entry_type != LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE)
+            .map(|entry| (entry.base, entry.end()));
+        for entry in memory_map.iter().filter(|entry| entry.entry_type == LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE) {
+            memrange::subtract(entry.base, entry.end(), others.clone(), FRAME_SIZE as u64, |base, end| {
+                self.add_range(base, end, keep)
+            });
+        }
+        (self.total_frames - total) - (self.used_frames - used)
     }
 
-    #[allow(dead_code)]
-    /// Convert a physical address to a bitmap frame index
-    fn phys_to_frame_index(&self, phys_addr: usize) -> Option<usize> {
-        let mut offset = 0;
-        for i in 0..self.region_count {
-            let region = &self.regions[i];
-            let region_end = region.base + region.frame_count * FRAME_SIZE;
-            if phys_addr >= region.base && phys_addr < region_end {
-                let frame_in_region = (phys_addr - region.base) / FRAME_SIZE;
-                return Some(offset + frame_in_region);
+    /// Manage the frames of [base, end), one region per zone it reaches
+    /// into; frames `keep` picks start out used, the rest free
+    fn add_range(&mut self, mut base: u64, end: u64, keep: &dyn Fn(PhysAddr) -> bool) {
+        while base < end && self.region_count < MAX_REGIONS {
+            let zone = Zone::of(PhysAddr::new(base));
+            let piece_end = end.min(zone.limit());
+            let first_index = self.next_index.next_multiple_of(MAX_BLOCK);
+            let frames = ((piece_end - base) as usize / FRAME_SIZE).min(MAX_FRAMES.saturating_sub(first_index));
+            if frames == 0 {
+                break;
+            }
+            self.regions[self.region_count] = MemoryRegion { base: PhysAddr::new(base), frame_count: frames, first_index, zone };
+            self.region_count += 1;
+            self.total_frames += frames;
+            self.zone_frames[zone as usize] += frames;
+            self.next_index = first_index + frames;
+
+            // Free the runs between kept frames
+            let mut run_start = 0;
+            for frame in 0..=frames {
+                if frame == frames || keep(PhysAddr::new(base) + frame * FRAME_SIZE) {
+                    self.buddy.free_range(zone as usize, first_index + run_start, frame - run_start);
+                    if frame < frames {
+                        self.mark_used(first_index + frame, 1, FrameOwner::Boot);
+                    }
+                    run_start = frame + 1;
+                }
             }
-            offset += region.frame_count;
+            base = piece_end;
         }
-        None
     }
 
-    #[allow(dead_code)]
-    /// Allocate a single frame, returns physical address
-    pub fn allocate_frame(&mut self) -> Option<usize> {
-        // Find first free frame
-        for frame_index in 0..self.total_frames {
-            let byte_index = frame_index / 8;
-            let bit_index = frame_index % 8;
+    /// Convert a bitmap frame index to a physical address
+    fn frame_index_to_phys(&self, index: usize) -> Option<PhysAddr> {
+        let region = self.regions[..self.region_count].iter().find(|region| region.indices().contains(&index))?;
+        Some(region.base + (index - region.first_index) * FRAME_SIZE)
+    }
 
-            if self.bitmap[byte_index] & (1 << bit_index) == 0 {
-                // Frame is free, mark as used
-                self.bitmap[byte_index] |= 1 << bit_index;
-                self.used_frames += 1;
+    /// Convert a physical address to a bitmap frame index
+    fn phys_to_frame_index(&self, phys_addr: PhysAddr) -> Option<usize> {
+        let region = self.region_of(phys_addr)?;
+        Some(region.first_index + (phys_addr - region.base) as usize / FRAME_SIZE)
+    }
 
-                // Convert bitmap index to physical address via region walk
-                return self.frame_index_to_phys(frame_index);
-            }
-        }
+    fn region_of(&self, phys_addr: PhysAddr) -> Option<&MemoryRegion> {
+        self.regions[..self.region_count]
+            .iter()
+            .find(|region| phys_addr >= region.base && phys_addr < region.end())
+    }
 
-        None // Out of memory
+    /// Mark frames [index, index + count) used and record their owner
+    fn mark_used(&mut self, index: usize, count: usize, owner: FrameOwner) {
+        self.bitmap.set_range(index..index + count);
+        self.used_frames += count;
+        self.tag_frames(index, count, owner);
     }
 
-    /// Allocate N contiguous physical frames from a single region.
-    /// Returns the physical address of the first frame.
-    pub fn allocate_contiguous_frames(&mut self, count: usize) -> Option<usize> {
-        if count == 0 {
-            return None;
+    /// Record `owner` for frames [index, index + count)
+    #[cfg(feature = "debugging")]
+    fn tag_frames(&mut self, index: usize, count: usize, owner: FrameOwner) {
+        self.tags[index..index + count].fill(owner as u8 + 1);
+        self.owner_frames[owner as usize] += count;
+    }
+
+    #[cfg(not(feature = "debugging"))]
+    fn tag_frames(&mut self, _index: usize, _count: usize, _owner: FrameOwner) {}
+
+    #[cfg(feature = "debugging")]
+    fn untag_frame(&mut self, index: usize) {
+        let tag = core::mem::replace(&mut self.tags[index], TAG_NONE);
+        if let Some(count) = self.owner_frames.get_mut((tag as usize).wrapping_sub(1)) {
+            *count -= 1;
         }
+    }
 
-        let mut region_start_index = 0;
-
-        for r in 0..self.region_count {
-            let region = &self.regions[r];
-
-            if region.frame_count >= count {
-                // Search within this region for `count` consecutive free frames
-                let mut run_start = 0;
-                let mut run_len = 0;
-
-                for f in 0..region.frame_count {
-                    let frame_index = region_start_index + f;
-                    let byte_index = frame_index / 8;
-                    let bit_index = frame_index % 8;
-
-                    if self.bitmap[byte_index] & (1 << bit_index) == 0 {
-                        if run_len == 0 {
-                            run_start = f;
-                        }
-                        run_len += 1;
-                        if run_len == count {
-                            // Found enough contiguous frames — mark them all used
-                            let base_frame_index = region_start_index + run_start;
-                            for i in 0..count {
-                                let idx = base_frame_index + i;
-                                self.bitmap[idx / 8] |= 1 << (idx % 8);
-                            }
-                            self.used_frames += count;
-                            return Some(region.base + run_start * FRAME_SIZE);
-                        }
-                    } else {
-                        run_len = 0;
-                    }
-                }
-            }
+    #[cfg(not(feature = "debugging"))]
+    fn untag_frame(&mut self, _index: usize) {}
 
-            region_start_index += region.frame_count;
+    /// Hand an allocated frame over to a different owner
+    #[cfg(feature = "debugging")]
+    pub fn set_owner(&mut self, phys_addr: PhysAddr, owner: FrameOwner) {
+        if let Some(index) = self.phys_to_frame_index(phys_addr) {
+            if self.tags[index] != TAG_NONE {
+                self.untag_frame(index);
+                self.tag_frames(index, 1, owner);
+            }
         }
+    }
+
+    #[cfg(not(feature = "debugging"))]
+    pub fn set_owner(&mut self, _phys_addr: PhysAddr, _owner: FrameOwner) {}
 
-        None // Could not find enough contiguous frames
+    /// Frames currently held by each owner (`debugging` feature only)
+    pub fn owner_frames(&self) -> Option<[usize; OWNER_COUNT]> {
+        #[cfg(feature = "debugging")]
+        return Some(self.owner_frames);
+        #[cfg(not(feature = "debugging"))]
+        return None;
+    }
+
+    /// Allocate a single frame below the limit of `zone`, from the highest
+    /// zone that has one; returns its physical address
+    pub fn allocate_frame_in(&mut self, zone: Zone, owner: FrameOwner) -> Option<PhysAddr> {
+        let index = zone.and_below().find_map(|zone| self.buddy.alloc(zone as usize, 0))?;
+        self.mark_used(index, 1, owner);
+        self.frame_index_to_phys(index)
+    }
+
+    /// Allocate N contiguous physical frames from a single region below the
+    /// limit of `zone`, N at most `MAX_BLOCK` (1024).
+    /// Returns the physical address of the first frame. The frames may be
+    /// freed one at a time.
+    pub fn allocate_contiguous_frames_in(&mut self, count: usize, zone: Zone, owner: FrameOwner) -> Option<PhysAddr> {
+        let index = zone.and_below().find_map(|zone| self.buddy.alloc_run(zone as usize, count))?;
+        self.mark_used(index, count, owner);
+        self.frame_index_to_phys(index)
     }
 
-    #[allow(dead_code)]
     /// Deallocate a frame, returns it to the free pool
-    pub fn deallocate_frame(&mut self, phys_addr: usize) {
-        let frame_index = match self.phys_to_frame_index(phys_addr) {
-            Some(idx) => idx,
+    pub fn deallocate_frame(&mut self, phys_addr: PhysAddr) {
+        let (frame_index, zone) = match self.region_of(phys_addr) {
+            Some(region) => (region.first_index + (phys_addr - region.base) as usize / FRAME_SIZE, region.zone),
             None => return, // Address doesn't belong to any known region
         };
 
-        let byte_index = frame_index / 8;
-        let bit_index = frame_index % 8;
-
-        // Mark as free
-        if self.bitmap[byte_index] & (1 << bit_index) != 0 {
-            self.bitmap[byte_index] &= !(1 << bit_index);
+        // Freeing a free frame would corrupt the buddy lists
+        if self.bitmap.get(frame_index) {
+            self.bitmap.clear(frame_index);
             self.used_frames -= 1;
+            self.untag_frame(frame_index);
+            self.buddy.free(zone as usize, frame_index, 0);
+        }
+    }
+
+    /// Whether the frame at `phys_addr` is allocated, or None if it isn't managed here
+    pub fn is_allocated(&self, phys_addr: PhysAddr) -> Option<bool> {
+        let index = self.phys_to_frame_index(phys_addr)?;
+        Some(self.bitmap.get(index))
+    }
+
+    /// Frames managed and frames in use within the memory map entry
+    /// [base, end), or None if none of it is managed
+    pub fn range_usage(&self, base: PhysAddr, end: PhysAddr) -> Option<(usize, usize)> {
+        let (mut frames, mut used) = (0, 0);
+        for region in self.regions[..self.region_count].iter().filter(|region| region.base >= base && region.end() <= end) {
+            frames += region.frame_count;
+            used += self.bitmap.count_ones(region.indices());
         }
+        (frames > 0).then_some((frames, used))
+    }
+
+    /// Frames managed and frames free in `zone`
+    pub fn zone_usage(&self, zone: Zone) -> (usize, usize) {
+        (self.zone_frames[zone as usize], self.buddy.free_count(zone as usize))
     }
 
     pub fn total_frames(&self) -> usize {
@@ -192,29 +402,112 @@ impl FrameAllocator {
     pub fn free_frames(&self) -> usize {
         self.total_frames - self.used_frames
     }
+
+    pub fn reserved_frames(&self) -> usize {
+        self.reserved_frames
+    }
 }
 
 static FRAME_ALLOCATOR: Spinlock<FrameAllocator> = Spinlock::new(FrameAllocator::new());
 
-pub fn init(memory_map: &[&LimineMemoryMapEntry], hhdm_offset: u64) {
+pub fn init(memory_map: &[MemoryMapEntry], hhdm_offset: u64) {
     FRAME_ALLOCATOR.lock().init(memory_map, hhdm_offset);
 }
 
-#[allow(dead_code)]
-pub fn allocate_frame() -> Option<usize> {
-    FRAME_ALLOCATOR.lock().allocate_frame()
+/// Allocate a frame, reclaiming memory first if none is free (see `oom`)
+pub fn allocate_frame(owner: FrameOwner) -> Option<PhysAddr> {
+    allocate_frame_in(Zone::High, owner)
+}
+
+/// Allocate a frame below the limit of `zone`, reclaiming memory first if
+/// none is free there
+pub fn allocate_frame_in(zone: Zone, owner: FrameOwner) -> Option<PhysAddr> {
+    // The lock must be released before reclaiming, which frees frames
+    let frame = FRAME_ALLOCATOR.lock().allocate_frame_in(zone, owner);
+    frame.or_else(|| oom::reclaim_frames().then(|| FRAME_ALLOCATOR.lock().allocate_frame_in(zone, owner)).flatten())
+}
+
+/// Allocate a frame only if one is free, for allocations that are optional
+pub fn try_allocate_frame(owner: FrameOwner) -> Option<PhysAddr> {
+    FRAME_ALLOCATOR.lock().allocate_frame_in(Zone::High, owner)
+}
+
+pub fn allocate_contiguous_frames(count: usize, owner: FrameOwner) -> Option<PhysAddr> {
+    allocate_contiguous_frames_in(count, Zone::High, owner)
 }
 
-pub fn allocate_contiguous_frames(count: usize) -> Option<usize> {
-    FRAME_ALLOCATOR.lock().allocate_contiguous_frames(count)
+/// Allocate `count` contiguous frames below the limit of `zone`
+pub fn allocate_contiguous_frames_in(count: usize, zone: Zone, owner: FrameOwner) -> Option<PhysAddr> {
+    let frames = FRAME_ALLOCATOR.lock().allocate_contiguous_frames_in(count, zone, owner);
+    frames.or_else(|| {
+        oom::reclaim_frames()
+            .then(|| FRAME_ALLOCATOR.lock().allocate_contiguous_frames_in(count, zone, owner))
+            .flatten()
+    })
 }
 
-#[allow(dead_code)]
-pub fn deallocate_frame(phys_addr: usize) {
+pub fn deallocate_frame(phys_addr: PhysAddr) {
     FRAME_ALLOCATOR.lock().deallocate_frame(phys_addr);
 }
 
+pub fn set_owner(phys_addr: PhysAddr, owner: FrameOwner) {
+    FRAME_ALLOCATOR.lock().set_owner(phys_addr, owner);
+}
+
+pub fn is_allocated(phys_addr: PhysAddr) -> Option<bool> {
+    FRAME_ALLOCATOR.lock().is_allocated(phys_addr)
+}
+
+/// Manage bootloader-reclaimable memory, keeping the frames `keep` picks;
+/// see `memory::bootmem` for when that is safe. Returns the frames freed.
+pub fn reclaim(memory_map: &[MemoryMapEntry], keep: &dyn Fn(PhysAddr) -> bool) -> usize {
+    FRAME_ALLOCATOR.lock().reclaim(memory_map, keep)
+}
+
+/// (frames managed, frames used) within the memory map entry [base, end)
+pub fn range_usage(base: PhysAddr, end: PhysAddr) -> Option<(usize, usize)> {
+    FRAME_ALLOCATOR.lock().range_usage(base, end)
+}
+
+/// Usable frames left unmanaged because another memory map entry overlaps them
+pub fn reserved_frames() -> usize {
+    FRAME_ALLOCATOR.lock().reserved_frames()
+}
+
+/// (zone, frames managed, frames free) for every zone
+pub fn zone_stats() -> [(Zone, usize, usize); ZONE_COUNT] {
+    let allocator = FRAME_ALLOCATOR.lock();
+    Zone::ALL.map(|zone| {
+        let (frames, free) = allocator.zone_usage(zone);
+        (zone, frames, free)
+    })
+}
+
+/// Frames held per owner as (owner, frames); None without the `debugging` feature
+pub fn owner_stats() -> Option<[(FrameOwner, usize); OWNER_COUNT]> {
+    let frames = FRAME_ALLOCATOR.lock().owner_frames()?;
+    let mut stats = [(FrameOwner::Heap, 0); OWNER_COUNT];
+    for (slot, (&owner, &count)) in stats.iter_mut().zip(FrameOwner::ALL.iter().zip(frames.iter())) {
+        *slot = (owner, count);
+    }
+    Some(stats)
+}
+
 pub fn stats() -> (usize, usize, usize) {
     let allocator = FRAME_ALLOCATOR.lock();
     (allocator.total_frames(), allocator.used_frames(), allocator.free_frames())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_allocate_and_free() {
+        let frame = allocate_frame(FrameOwner::Stack).expect("no free frame");
+        assert!(frame.as_u64().is_multiple_of(FRAME_SIZE as u64));
+        assert_eq!(is_allocated(frame), Some(true));
+        deallocate_frame(frame);
+        assert_eq!(is_allocated(frame), Some(false));
+    }
+}
@@ -1,63 +1,101 @@
 //! Kernel heap allocator
 //! Provides dynamic memory allocation (Box, Vec, String, etc.)
+//! Small blocks come from slab caches through per-CPU magazines; larger
+//! ones from a fixed 64KB region.
 
-use crate::memory::frame_allocator;
-use linked_list_allocator::LockedHeap;
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::magazine::{MagazineHeap, MagazineStats};
+use crate::memory::oom;
+use core::alloc::{GlobalAlloc, Layout};
+
+/// The magazine heap, with one retry after reclaiming when it runs dry
+struct KernelHeap(MagazineHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let block = self.0.alloc(layout);
+        if block.is_null() && oom::reclaim_heap() {
+            return self.0.alloc(layout);
+        }
+        block
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap(MagazineHeap::new());
 
 const HEAP_SIZE: usize = 64 * 1024; // 64KB heap
 const HEAP_FRAMES: usize = HEAP_SIZE.div_ceil(4096); // 16 frames
 
 pub fn init(hhdm_offset: u64) -> Result<(), &'static str> {
-    use crate::serial_println;
+    use crate::debug;
 
-    serial_println!("  Allocating {} contiguous frames for heap...", HEAP_FRAMES);
+    debug!("  Allocating {} contiguous frames for heap...", HEAP_FRAMES);
 
     // Allocate contiguous frames in a single region
-    let heap_phys = frame_allocator::allocate_contiguous_frames(HEAP_FRAMES)
+    let heap_phys = frame_allocator::allocate_contiguous_frames(HEAP_FRAMES, FrameOwner::Heap)
         .ok_or("Failed to allocate contiguous heap frames")?;
 
-    serial_println!("  Heap physical base: {:#x}", heap_phys);
+    debug!("  Heap physical base: {:#x}", heap_phys);
 
     // Calculate virtual address using HHDM (all physical memory mapped here)
-    let heap_start_virt = (hhdm_offset as usize) + heap_phys;
-    serial_println!("  Heap virtual address: {:#x}", heap_start_virt);
+    let heap_start_virt = heap_phys.to_virt(hhdm_offset);
+    debug!("  Heap virtual address: {:#x}", heap_start_virt);
 
     // Initialize the allocator
     unsafe {
-        ALLOCATOR.lock().init(heap_start_virt as *mut u8, HEAP_SIZE);
+        ALLOCATOR.0.init(heap_start_virt.as_mut_ptr(), HEAP_SIZE);
     }
 
-    serial_println!("  Allocator initialized ({} KB)", HEAP_SIZE / 1024);
+    debug!("  Allocator initialized ({} KB)", HEAP_SIZE / 1024);
     Ok(())
 }
 
 /// Verify heap works by performing a test allocation
 pub fn verify_heap() {
-    use crate::serial_println;
+    use crate::{debug, error};
     use alloc::boxed::Box;
 
     let test_val = Box::new(0xDEAD_BEEFu64);
     if *test_val == 0xDEAD_BEEF {
-        serial_println!("  Heap verification passed (Box<u64> = {:#x})", *test_val);
+        debug!("  Heap verification passed (Box<u64> = {:#x})", *test_val);
     } else {
-        serial_println!("  Heap verification FAILED: unexpected value {:#x}", *test_val);
+        error!("  Heap verification FAILED: unexpected value {:#x}", *test_val);
     }
     // Box is dropped here, returning memory to the allocator
 }
 
 /// Return heap statistics: (total_bytes, used_bytes, free_bytes)
 pub fn stats() -> Option<(usize, usize, usize)> {
-    let allocator = ALLOCATOR.lock();
-    let free = allocator.free();
+    let free = ALLOCATOR.0.free_bytes();
     let total = HEAP_SIZE;
     let used = total - free;
     Some((total, used, free))
 }
 
+/// Return per-CPU magazine cache and heap lock statistics
+pub fn magazine_stats() -> MagazineStats {
+    ALLOCATOR.0.stats()
+}
+
+/// Move blocks cached per CPU back into the slab caches; returns the bytes moved
+pub fn drain_magazines() -> usize {
+    ALLOCATOR.0.drain_magazines()
+}
+
+/// Reached only once reclaiming has failed too (see `oom`)
 #[alloc_error_handler]
-fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
-    panic!("Allocation error: {:?}", layout);
+fn alloc_error_handler(layout: Layout) -> ! {
+    let free = ALLOCATOR.0.free_bytes();
+    panic!(
+        "Out of memory: kernel heap cannot fit {} bytes (align {}), {} of {} bytes free",
+        layout.size(),
+        layout.align(),
+        free,
+        HEAP_SIZE
+    );
 }
@@ -0,0 +1,90 @@
+//! Kernel stacks with guard pages
+//! Every kernel stack the kernel switches to gets a slot in its own area of
+//! the kernel half (PML4 entry 509). The stack is mapped at the top of the
+//! slot and the rest of the slot is left unmapped, so running off the bottom
+//! of a stack faults instead of overwriting whatever memory lies below it.
+//! The fault handlers ask `guard_owner` whether a fault address falls in such
+//! a gap, and report it as an overflow of that stack.
+//! Stacks live for as long as the kernel runs; there is no free.
+
+use super::frame_allocator::{self, FrameOwner};
+use super::paging::{self, PAGE_SIZE, WRITABLE};
+use super::{PhysAddr, VirtAddr};
+use crate::sync::spinlock::Spinlock;
+use core::fmt;
+
+/// Start of the stack area: PML4 entry 509, sign-extended
+const AREA_START: usize = 0xffff_fe80_0000_0000;
+/// Pages per slot; a stack may use all but one of them
+const SLOT_PAGES: usize = 32;
+const SLOT_SIZE: usize = SLOT_PAGES * PAGE_SIZE;
+const MAX_STACKS: usize = 32;
+
+/// Who a kernel stack belongs to, for overflow reports
+#[derive(Clone, Copy)]
+pub struct KernelStack {
+    /// What runs on the stack ("shell", "syscall", ...)
+    pub name: &'static str,
+    pub cpu: usize,
+    pub pages: usize,
+}
+
+impl fmt::Display for KernelStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} stack of CPU {}", self.name, self.cpu)
+    }
+}
+
+static STACKS: Spinlock<[Option<KernelStack>; MAX_STACKS]> = Spinlock::new([None; MAX_STACKS]);
+
+/// Map a `pages`-page stack below an unmapped guard gap and return its top
+/// Stacks must be allocated before the first user address space is created
+/// (see `paging::map_kernel_page`), which in practice means during boot.
+pub fn allocate(name: &'static str, cpu: usize, pages: usize) -> Option<VirtAddr> {
+    if pages == 0 || pages >= SLOT_PAGES {
+        return None;
+    }
+
+    let mut stacks = STACKS.lock();
+    let slot = stacks.iter().position(Option::is_none)?;
+
+    // Take every frame before mapping any, so a shortage leaves nothing behind
+    let mut frames = [PhysAddr::new(0); SLOT_PAGES];
+    for taken in 0..pages {
+        let Some(phys) = frame_allocator::allocate_frame(FrameOwner::Stack) else {
+            frames[..taken].iter().for_each(|&phys| frame_allocator::deallocate_frame(phys));
+            return None;
+        };
+        frames[taken] = phys;
+    }
+
+    let top = AREA_START + (slot + 1) * SLOT_SIZE;
+    let bottom = top - pages * PAGE_SIZE;
+    for (i, &phys) in frames[..pages].iter().enumerate() {
+        let virt = VirtAddr::new((bottom + i * PAGE_SIZE) as u64);
+        // Only fails for want of a page table frame; the slot stays taken so
+        // the pages already mapped are never handed out twice
+        if paging::map_kernel_page(virt, phys, WRITABLE).is_err() {
+            frames[i..pages].iter().for_each(|&phys| frame_allocator::deallocate_frame(phys));
+            stacks[slot] = Some(KernelStack { name, cpu, pages: 0 });
+            return None;
+        }
+    }
+
+    stacks[slot] = Some(KernelStack { name, cpu, pages });
+    Some(VirtAddr::new(top as u64))
+}
+
+/// The stack whose guard gap contains `addr`, if any
+/// Called from fault handlers, so it only try-locks; a fault taken while
+/// `allocate` holds the lock goes unattributed.
+pub fn guard_owner(addr: u64) -> Option<KernelStack> {
+    let offset = (addr as usize).checked_sub(AREA_START)?;
+    let slot = offset / SLOT_SIZE;
+    if slot >= MAX_STACKS {
+        return None;
+    }
+    let stack = STACKS.try_lock()?[slot]?;
+    let in_gap = offset % SLOT_SIZE < (SLOT_PAGES - stack.pages) * PAGE_SIZE;
+    in_gap.then_some(stack)
+}
@@ -0,0 +1,259 @@
+//! Per-CPU magazine caches in front of the slab caches
+//! Small allocations are served from a per-CPU stack of free blocks, so a CPU
+//! only takes the lock of a size class's slab cache (see `slab`) when its
+//! magazine runs empty or full, and then moves a whole batch of blocks under
+//! a single acquisition. Larger allocations go to the global heap.
+
+use super::slab::SIZE_CACHES;
+use crate::arch::{Arch, Cpu, MAX_CPUS};
+use crate::sync::spinlock::Spinlock;
+use core::alloc::{GlobalAlloc, Layout};
+use core::iter;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicU64, Ordering};
+use linked_list_allocator::{Heap, LockedHeap};
+
+pub const SIZE_CLASSES: [usize; 6] = [16, 32, 64, 128, 256, 512];
+pub const CLASS_COUNT: usize = SIZE_CLASSES.len();
+const CLASS_ALIGN: usize = 16;
+const MAGAZINE_CAPACITY: usize = 32;
+const BATCH_SIZE: usize = MAGAZINE_CAPACITY / 2;
+
+/// Map a layout to its size class, or None if it must go to the global heap
+/// Slab objects are 16-byte aligned, so stricter alignments go there too.
+fn size_class(layout: Layout) -> Option<usize> {
+    if layout.align() > CLASS_ALIGN {
+        return None;
+    }
+    SIZE_CLASSES.iter().position(|&size| layout.size() <= size)
+}
+
+struct Magazine {
+    blocks: [*mut u8; MAGAZINE_CAPACITY],
+    count: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Magazine {
+            blocks: [ptr::null_mut(); MAGAZINE_CAPACITY],
+            count: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        if self.count == 0 {
+            return None;
+        }
+        self.count -= 1;
+        Some(self.blocks[self.count])
+    }
+
+    fn push(&mut self, block: *mut u8) -> bool {
+        if self.count == MAGAZINE_CAPACITY {
+            return false;
+        }
+        self.blocks[self.count] = block;
+        self.count += 1;
+        true
+    }
+}
+
+struct CpuCache {
+    magazines: [Magazine; CLASS_COUNT],
+}
+
+// Blocks in a magazine are owned exclusively by the cache holding them
+unsafe impl Send for CpuCache {}
+
+impl CpuCache {
+    const fn new() -> Self {
+        CpuCache {
+            magazines: [const { Magazine::new() }; CLASS_COUNT],
+        }
+    }
+}
+
+struct CpuCounters {
+    hits: AtomicU64,
+    refills: AtomicU64,
+    flushes: AtomicU64,
+}
+
+impl CpuCounters {
+    const fn new() -> Self {
+        CpuCounters {
+            hits: AtomicU64::new(0),
+            refills: AtomicU64::new(0),
+            flushes: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Aggregated magazine and heap lock counters
+pub struct MagazineStats {
+    pub hits: u64,
+    pub refills: u64,
+    pub flushes: u64,
+    pub cached_bytes: usize,
+    pub lock_acquisitions: u64,
+    pub lock_contended: u64,
+}
+
+pub struct MagazineHeap {
+    heap: LockedHeap,
+    caches: [Spinlock<CpuCache>; MAX_CPUS],
+    counters: [CpuCounters; MAX_CPUS],
+    lock_acquisitions: AtomicU64,
+    lock_contended: AtomicU64,
+}
+
+impl MagazineHeap {
+    pub const fn new() -> Self {
+        MagazineHeap {
+            heap: LockedHeap::empty(),
+            caches: [const { Spinlock::new(CpuCache::new()) }; MAX_CPUS],
+            counters: [const { CpuCounters::new() }; MAX_CPUS],
+            lock_acquisitions: AtomicU64::new(0),
+            lock_contended: AtomicU64::new(0),
+        }
+    }
+
+    /// Hand the backing memory region to the global heap
+    ///
+    /// # Safety
+    /// The region must be valid, unused, and mapped for the kernel's lifetime.
+    pub unsafe fn init(&self, start: *mut u8, size: usize) {
+        self.heap.lock().init(start, size);
+    }
+
+    /// Bytes currently free in the global heap, which holds only allocations
+    /// too large for a size class
+    pub fn free_bytes(&self) -> usize {
+        self.with_heap(|heap| heap.free())
+    }
+
+    /// Run `f` with the global heap lock held, recording contention
+    fn with_heap<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        let mut heap = match self.heap.try_lock() {
+            Some(guard) => guard,
+            None => {
+                self.lock_contended.fetch_add(1, Ordering::Relaxed);
+                self.heap.lock()
+            }
+        };
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        f(&mut heap)
+    }
+
+    fn refill(&self, magazine: &mut Magazine, class: usize) {
+        SIZE_CACHES[class].alloc_batch(BATCH_SIZE, &mut |block| {
+            magazine.push(block);
+        });
+    }
+
+    fn flush(&self, magazine: &mut Magazine, class: usize) {
+        SIZE_CACHES[class].free_batch(iter::from_fn(|| magazine.pop()).take(BATCH_SIZE));
+    }
+
+    /// Return every block held in the per-CPU magazines to the slab caches,
+    /// so their slabs can empty and be given back; returns the bytes moved
+    /// Magazines in use at the moment are skipped.
+    pub fn drain_magazines(&self) -> usize {
+        let mut bytes = 0;
+        for cache in &self.caches {
+            let Some(mut cache) = cache.try_lock() else {
+                continue;
+            };
+            for (class, magazine) in cache.magazines.iter_mut().enumerate() {
+                bytes += magazine.count * SIZE_CLASSES[class];
+                SIZE_CACHES[class].free_batch(iter::from_fn(|| magazine.pop()));
+            }
+        }
+        bytes
+    }
+
+    pub fn stats(&self) -> MagazineStats {
+        let mut stats = MagazineStats {
+            hits: 0,
+            refills: 0,
+            flushes: 0,
+            cached_bytes: 0,
+            lock_acquisitions: self.lock_acquisitions.load(Ordering::Relaxed),
+            lock_contended: self.lock_contended.load(Ordering::Relaxed),
+        };
+
+        for cpu in 0..MAX_CPUS {
+            let counters = &self.counters[cpu];
+            stats.hits += counters.hits.load(Ordering::Relaxed);
+            stats.refills += counters.refills.load(Ordering::Relaxed);
+            stats.flushes += counters.flushes.load(Ordering::Relaxed);
+
+            if let Some(cache) = self.caches[cpu].try_lock() {
+                for (class, magazine) in cache.magazines.iter().enumerate() {
+                    stats.cached_bytes += magazine.count * SIZE_CLASSES[class];
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+unsafe impl GlobalAlloc for MagazineHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let class = match size_class(layout) {
+            Some(class) => class,
+            None => {
+                return self.with_heap(|heap| {
+                    heap.allocate_first_fit(layout)
+                        .map_or(ptr::null_mut(), |block| block.as_ptr())
+                });
+            }
+        };
+
+        let cpu = Arch::cpu_id();
+
+        // A busy cache means we interrupted this CPU's own allocator path;
+        // go to the slab cache instead of spinning on ourselves.
+        if let Some(mut cache) = self.caches[cpu].try_lock() {
+            let magazine = &mut cache.magazines[class];
+            if magazine.count == 0 {
+                self.counters[cpu].refills.fetch_add(1, Ordering::Relaxed);
+                self.refill(magazine, class);
+            } else {
+                self.counters[cpu].hits.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(block) = magazine.pop() {
+                return block;
+            }
+        }
+
+        SIZE_CACHES[class].alloc()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let class = match size_class(layout) {
+            Some(class) => class,
+            None => {
+                self.with_heap(|heap| heap.deallocate(NonNull::new_unchecked(ptr), layout));
+                return;
+            }
+        };
+
+        let cpu = Arch::cpu_id();
+
+        if let Some(mut cache) = self.caches[cpu].try_lock() {
+            let magazine = &mut cache.magazines[class];
+            if magazine.count == MAGAZINE_CAPACITY {
+                self.counters[cpu].flushes.fetch_add(1, Ordering::Relaxed);
+                self.flush(magazine, class);
+            }
+            if magazine.push(ptr) {
+                return;
+            }
+        }
+
+        SIZE_CACHES[class].free(ptr);
+    }
+}
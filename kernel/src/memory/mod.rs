@@ -1,2 +1,33 @@
+pub mod bootmem;
+pub mod cow;
+pub mod dma;
 pub mod frame_allocator;
 pub mod heap;
+pub mod kstack;
+pub mod magazine;
+pub mod oom;
+pub mod pagecheck;
+pub mod paging;
+pub mod protect;
+pub mod slab;
+pub mod zero_pool;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub use shared::addr::{PhysAddr, VirtAddr};
+
+// Offset of Limine's Higher-Half Direct Map, recorded once at boot
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_hhdm_offset(offset: u64) {
+    HHDM_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+pub fn hhdm_offset() -> u64 {
+    HHDM_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Virtual address through which physical address `phys` is accessible
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    phys.to_virt(hhdm_offset())
+}
@@ -0,0 +1,78 @@
+//! Out-of-memory policy
+//! Neither allocator gives up on its first miss. The heap first takes back
+//! the blocks parked in per-CPU magazines and the clean blocks held by disk
+//! sector caches; the frame allocator first empties the pre-zeroed pool and
+//! takes back empty slabs.
+//! If frames are still short while a user program runs, that program (the
+//! only one, until there is a scheduler) is ended at its next entry into the
+//! kernel so its pages come back. The heap only ever serves the kernel, so
+//! when reclaiming fails there the allocation error handler panics.
+
+use super::{heap, slab, zero_pool};
+use crate::fs::vfs;
+use crate::{process, warn};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Set while a reclaim pass runs, so a nested or concurrent miss does not
+/// start another one
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+static HEAP_RECLAIMS: AtomicU64 = AtomicU64::new(0);
+static FRAME_RECLAIMS: AtomicU64 = AtomicU64::new(0);
+static KILLS: AtomicU64 = AtomicU64::new(0);
+
+/// How often each step of the policy has run
+pub struct OomStats {
+    pub heap_reclaims: u64,
+    pub frame_reclaims: u64,
+    pub kills: u64,
+}
+
+/// Run `reclaim` unless another reclaim pass is already under way
+fn exclusive(reclaim: impl FnOnce() -> bool) -> bool {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    let freed = reclaim();
+    RECLAIMING.store(false, Ordering::Release);
+    freed
+}
+
+/// The heap could not satisfy an allocation: free what caches hold
+/// Returns true if anything came back, so the allocation is worth retrying.
+/// Runs inside the allocator, so it must not allocate and only try-locks.
+pub fn reclaim_heap() -> bool {
+    exclusive(|| {
+        HEAP_RECLAIMS.fetch_add(1, Ordering::Relaxed);
+        let magazines = heap::drain_magazines();
+        let caches = vfs::reclaim();
+        magazines + caches > 0
+    })
+}
+
+/// The frame allocator is out of frames: empty the zeroed pool and the empty
+/// slabs, and if neither gave anything back, have the running program ended
+/// Returns true if frames came back, so the allocation is worth retrying.
+pub fn reclaim_frames() -> bool {
+    exclusive(|| {
+        FRAME_RECLAIMS.fetch_add(1, Ordering::Relaxed);
+        // Runs inside a slab cache growing, so only empty slabs are taken;
+        // draining the magazines could wait on that cache's lock
+        if zero_pool::drain() + slab::shrink() > 0 {
+            return true;
+        }
+        if process::request_kill() {
+            KILLS.fetch_add(1, Ordering::Relaxed);
+            warn!("oom: out of frames, ending the running program");
+        }
+        false
+    })
+}
+
+pub fn stats() -> OomStats {
+    OomStats {
+        heap_reclaims: HEAP_RECLAIMS.load(Ordering::Relaxed),
+        frame_reclaims: FRAME_RECLAIMS.load(Ordering::Relaxed),
+        kills: KILLS.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,157 @@
+//! Page table consistency checker
+//! Walks a PML4 and checks the invariants the rest of the kernel relies on:
+//! no page is both writable and executable, the HHDM maps every usable frame
+//! to itself, and no table entry points at a frame the allocator has freed.
+//! With the `debugging` feature it also runs automatically after building a user address space.
+
+use super::frame_allocator;
+use super::paging::{self, HUGE_PAGE, KERNEL_HALF_START, NO_EXECUTE, PRESENT, WRITABLE};
+use super::{hhdm_offset, PhysAddr, VirtAddr};
+use crate::arch::{Arch, Mmu};
+use crate::{bootinfo, limine};
+
+const PAGE_SIZE: u64 = paging::PAGE_SIZE as u64;
+const LOWER_HALF_END: u64 = (KERNEL_HALF_START as u64) << 39;
+
+/// How many times a problem was seen, and the first address it was seen at
+#[derive(Clone, Copy, Default)]
+pub struct Finding {
+    pub count: usize,
+    pub first: Option<u64>,
+}
+
+impl Finding {
+    fn record(&mut self, addr: u64) {
+        if self.first.is_none() {
+            self.first = Some(addr);
+        }
+        self.count += 1;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PageCheckReport {
+    /// Page tables visited below the PML4
+    pub tables: usize,
+    /// Leaf mappings of any size
+    pub mappings: usize,
+    /// False if EFER.NXE is off, in which case W^X cannot hold and isn't checked
+    pub nx_enforced: bool,
+    /// Leaf mappings that are writable and executable (virtual addresses)
+    pub writable_executable: Finding,
+    /// Tables or user pages whose frame is free in the allocator (virtual addresses)
+    pub dangling: Finding,
+    /// Usable frames the HHDM doesn't map to themselves (physical addresses)
+    pub hhdm_missing: Finding,
+}
+
+impl PageCheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.writable_executable.count == 0 && self.dangling.count == 0 && self.hhdm_missing.count == 0
+    }
+}
+
+/// Check every invariant against the page tables rooted at `pml4`
+pub fn check(pml4: PhysAddr) -> PageCheckReport {
+    let mut report = PageCheckReport {
+        nx_enforced: Arch::nx_enabled(),
+        ..Default::default()
+    };
+    walk(pml4, 4, 0, true, true, &mut report);
+    check_hhdm(pml4, &mut report);
+    report
+}
+
+/// Visit the table at `level` (4 = PML4 .. 1 = PT) mapping from `base`;
+/// `writable`/`executable` are what the levels above still allow
+fn walk(table: PhysAddr, level: u32, base: u64, writable: bool, executable: bool, report: &mut PageCheckReport) {
+    let shift = 12 + 9 * (level - 1);
+    for (index, &entry) in paging::table_entries(table).iter().enumerate() {
+        if entry & PRESENT == 0 {
+            continue;
+        }
+        let virt = VirtAddr::new_truncate(base | ((index as u64) << shift)).as_u64();
+        let writable = writable && entry & WRITABLE != 0;
+        let executable = executable && entry & NO_EXECUTE == 0;
+        let target = paging::entry_addr(entry);
+
+        let is_leaf = level == 1 || (level <= 3 && entry & HUGE_PAGE != 0);
+        if is_leaf {
+            report.mappings += 1;
+            if report.nx_enforced && writable && executable {
+                report.writable_executable.record(virt);
+            }
+            // The HHDM maps free frames by design; only user pages must be owned
+            if virt < LOWER_HALF_END && frame_allocator::is_allocated(target) == Some(false) {
+                report.dangling.record(virt);
+            }
+        } else {
+            report.tables += 1;
+            if frame_allocator::is_allocated(target) == Some(false) {
+                report.dangling.record(virt);
+                continue; // Don't read a table that may have been reused
+            }
+            walk(target, level - 1, virt, writable, executable, report);
+        }
+    }
+}
+
+/// Frame and page size that `virt` translates to under `pml4`
+fn translate(pml4: PhysAddr, virt: VirtAddr) -> Option<(PhysAddr, u64)> {
+    let indices = virt.page_table_indices();
+    let mut table = pml4;
+    for (depth, &index) in indices.iter().enumerate() {
+        let entry = paging::table_entries(table)[index];
+        if entry & PRESENT == 0 {
+            return None;
+        }
+        let level = 4 - depth as u32;
+        if level == 1 || (level <= 3 && entry & HUGE_PAGE != 0) {
+            let page_size = 1u64 << (12 + 9 * (level - 1));
+            let offset = virt.as_u64() & (page_size - 1);
+            return Some((paging::entry_addr(entry).align_down(page_size) + offset, page_size));
+        }
+        table = paging::entry_addr(entry);
+    }
+    None
+}
+
+fn check_hhdm(pml4: PhysAddr, report: &mut PageCheckReport) {
+    let hhdm = hhdm_offset();
+
+    for entry in bootinfo::get().memory_map().iter().filter(|e| e.entry_type == limine::LIMINE_MEMMAP_USABLE) {
+        let mut phys = PhysAddr::new(entry.base).align_up(PAGE_SIZE);
+        let end = PhysAddr::new(entry.base + entry.length);
+        while phys < end {
+            let step = match translate(pml4, phys.to_virt(hhdm)) {
+                Some((mapped, page_size)) if mapped == phys => {
+                    // Skip the rest of a huge page in one go
+                    page_size - (phys.as_u64() & (page_size - 1))
+                }
+                _ => {
+                    report.hhdm_missing.record(phys.as_u64());
+                    PAGE_SIZE
+                }
+            };
+            phys = phys + step;
+        }
+    }
+}
+
+/// Check the tables at `pml4` and log any violations to serial (`debugging` feature only)
+#[cfg(feature = "debugging")]
+pub fn debug_check(pml4: PhysAddr, context: &str) {
+    let report = check(pml4);
+    if !report.is_clean() {
+        crate::warn!(
+            "pagecheck ({}): {} W+X, {} dangling, {} HHDM gaps",
+            context,
+            report.writable_executable.count,
+            report.dangling.count,
+            report.hhdm_missing.count
+        );
+    }
+}
+
+#[cfg(not(feature = "debugging"))]
+pub fn debug_check(_pml4: PhysAddr, _context: &str) {}
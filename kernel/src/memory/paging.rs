@@ -0,0 +1,365 @@
+//! x86_64 4-level page tables
+//! Tables are reached through the HHDM; intermediate tables come from the
+//! frame allocator. The kernel half (PML4 entries 256-511) of every address
+//! space is shared with the tables Limine set up.
+//! A forked address space shares the user frames of the one it came from,
+//! read-only, until either writes to them (`fork`, `copy_on_write`).
+
+use crate::arch::{Arch, Mmu};
+use crate::memory::cow;
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::{phys_to_virt, PhysAddr, VirtAddr};
+
+pub const PAGE_SIZE: usize = 4096;
+
+// Page table entry flags
+pub const PRESENT: u64 = 1 << 0;
+pub const WRITABLE: u64 = 1 << 1;
+pub const USER: u64 = 1 << 2;
+#[allow(dead_code)]
+pub const WRITE_THROUGH: u64 = 1 << 3;
+#[allow(dead_code)]
+pub const NO_CACHE: u64 = 1 << 4;
+pub const HUGE_PAGE: u64 = 1 << 7;
+/// Ignored by the MMU: a writable page made read-only while it is shared
+pub const COPY_ON_WRITE: u64 = 1 << 9;
+pub const NO_EXECUTE: u64 = 1 << 63;
+
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+const ENTRY_COUNT: usize = 512;
+pub const KERNEL_HALF_START: usize = 256;
+
+#[repr(C, align(4096))]
+pub struct PageTable {
+    entries: [u64; ENTRY_COUNT],
+}
+
+fn table_at(phys: PhysAddr) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(phys).as_mut_ptr::<PageTable>() }
+}
+
+/// Physical address stored in a table entry
+pub fn entry_addr(entry: u64) -> PhysAddr {
+    PhysAddr::new(entry & ADDR_MASK)
+}
+
+/// Read-only view of the page table in frame `phys`
+pub fn table_entries(phys: PhysAddr) -> &'static [u64; ENTRY_COUNT] {
+    &table_at(phys).entries
+}
+
+/// True if `virt` is mapped in the tables rooted at `root` (any page size)
+pub fn is_mapped(root: PhysAddr, virt: VirtAddr) -> bool {
+    let mut table = table_at(root);
+    for (level, &index) in virt.page_table_indices().iter().enumerate() {
+        let entry = table.entries[index];
+        if entry & PRESENT == 0 {
+            return false;
+        }
+        // Levels 1 and 2 may map 1 GB and 2 MB pages directly
+        if level == 3 || entry & HUGE_PAGE != 0 {
+            return true;
+        }
+        table = table_at(entry_addr(entry));
+    }
+    false
+}
+
+/// `NO_EXECUTE` if the CPU enforces it, otherwise 0: with EFER.NXE off
+/// the bit is reserved, and setting it faults
+pub fn no_execute() -> u64 {
+    if Arch::nx_enabled() {
+        NO_EXECUTE
+    } else {
+        0
+    }
+}
+
+/// Map the 4KB page at `virt` in the kernel half of the active tables
+/// The kernel only maps data at run time, so the page is never executable.
+/// A new PML4 entry only reaches address spaces created after it, so new
+/// kernel areas must be mapped before the first user program runs.
+pub fn map_kernel_page(virt: VirtAddr, phys: PhysAddr, flags: u64) -> Result<(), &'static str> {
+    if virt.page_table_indices()[0] < KERNEL_HALF_START {
+        return Err("Not a kernel address");
+    }
+    map_in(Arch::active_table(), virt, phys, flags | no_execute())
+}
+
+/// Replace the flags of the kernel-half 4KB mapping at `virt` in the active
+/// tables (shared by every address space)
+pub fn set_kernel_flags(virt: VirtAddr, flags: u64) -> Result<(), &'static str> {
+    if virt.page_table_indices()[0] < KERNEL_HALF_START {
+        return Err("Not a kernel address");
+    }
+    let entry = leaf_entry_in(Arch::active_table(), virt).ok_or("Not mapped by a 4KB page")?;
+    if *entry & PRESENT == 0 {
+        return Err("Page not mapped");
+    }
+    *entry = (*entry & ADDR_MASK) | flags | PRESENT;
+    Arch::flush_page(virt);
+    Ok(())
+}
+
+/// Add `flags` (such as `NO_EXECUTE`) to entry `index` of the active PML4,
+/// restricting all 512 GB it maps; false if the entry is empty
+/// The TLB is not flushed.
+pub fn restrict_top_level(index: usize, flags: u64) -> bool {
+    let pml4 = table_at(Arch::active_table());
+    if pml4.entries[index] & PRESENT == 0 {
+        return false;
+    }
+    pml4.entries[index] |= flags;
+    true
+}
+
+/// The level-1 entry for `virt` under `root`; None if a level above is
+/// empty or maps a huge page
+fn leaf_entry_in(root: PhysAddr, virt: VirtAddr) -> Option<&'static mut u64> {
+    let idx = virt.page_table_indices();
+    let mut table = table_at(root);
+    for &index in &idx[..3] {
+        let entry = table.entries[index];
+        if entry & PRESENT == 0 || entry & HUGE_PAGE != 0 {
+            return None;
+        }
+        table = table_at(entry_addr(entry));
+    }
+    Some(&mut table.entries[idx[3]])
+}
+
+fn map_in(root: PhysAddr, virt: VirtAddr, phys: PhysAddr, flags: u64) -> Result<(), &'static str> {
+    if !virt.is_aligned(PAGE_SIZE as u64) || !phys.is_aligned(PAGE_SIZE as u64) {
+        return Err("Unaligned mapping");
+    }
+
+    let idx = virt.page_table_indices();
+    let mut table = table_at(root);
+
+    // Intermediate entries carry USER/WRITABLE so the leaf decides access
+    for &index in &idx[..3] {
+        let entry = table.entries[index];
+        let next_phys = if entry & PRESENT != 0 {
+            if entry & HUGE_PAGE != 0 {
+                return Err("Address already covered by a huge page");
+            }
+            entry_addr(entry)
+        } else {
+            let phys = allocate_table().ok_or("Out of frames for page tables")?;
+            table.entries[index] = phys.as_u64() | PRESENT | WRITABLE | (flags & USER);
+            phys
+        };
+        table = table_at(next_phys);
+    }
+
+    if table.entries[idx[3]] & PRESENT != 0 {
+        return Err("Page already mapped");
+    }
+    table.entries[idx[3]] = phys.as_u64() | flags | PRESENT;
+    Ok(())
+}
+
+fn allocate_table() -> Option<PhysAddr> {
+    let phys = frame_allocator::allocate_frame(FrameOwner::PageTable)?;
+    let table = table_at(phys);
+    table.entries.fill(0);
+    Some(phys)
+}
+
+/// A user address space: private lower half, shared kernel upper half
+pub struct AddressSpace {
+    pml4_phys: PhysAddr,
+}
+
+impl AddressSpace {
+    /// Create an empty user address space sharing the active kernel mappings
+    pub fn new_user() -> Option<Self> {
+        let pml4_phys = allocate_table()?;
+        let new = table_at(pml4_phys);
+        let active = table_at(Arch::active_table());
+        new.entries[KERNEL_HALF_START..].copy_from_slice(&active.entries[KERNEL_HALF_START..]);
+        Some(AddressSpace { pml4_phys })
+    }
+
+    pub fn pml4_phys(&self) -> PhysAddr {
+        self.pml4_phys
+    }
+
+    /// Map the 4KB page at `virt` to the frame at `phys`
+    pub fn map_page(&mut self, virt: VirtAddr, phys: PhysAddr, flags: u64) -> Result<(), &'static str> {
+        map_in(self.pml4_phys, virt, phys, flags)
+    }
+
+    /// Walk to the leaf entry for `virt`, returning (frame, flags) if mapped
+    pub fn lookup(&self, virt: VirtAddr) -> Option<(PhysAddr, u64)> {
+        let entry = *self.leaf_entry(virt)?;
+        if entry & PRESENT == 0 {
+            return None;
+        }
+        Some((entry_addr(entry), entry & !ADDR_MASK))
+    }
+
+    /// Replace the flags of an existing 4KB mapping
+    pub fn set_flags(&mut self, virt: VirtAddr, flags: u64) -> Result<(), &'static str> {
+        let entry = self.leaf_entry(virt).ok_or("Page not mapped")?;
+        if *entry & PRESENT == 0 {
+            return Err("Page not mapped");
+        }
+        *entry = (*entry & ADDR_MASK) | flags | PRESENT;
+        Ok(())
+    }
+
+    fn leaf_entry(&self, virt: VirtAddr) -> Option<&'static mut u64> {
+        leaf_entry_in(self.pml4_phys, virt)
+    }
+
+    /// A copy of this address space sharing all of its user pages
+    /// Writable pages turn read-only and `COPY_ON_WRITE` in both spaces, and
+    /// the first write to one in either gets a private copy. This space's
+    /// TLB entries must be flushed before it runs again (switching tables
+    /// does it).
+    pub fn fork(&mut self) -> Result<AddressSpace, &'static str> {
+        let mut child = AddressSpace::new_user().ok_or("Out of memory for page tables")?;
+        let pml4 = table_at(self.pml4_phys);
+        for index in 0..KERNEL_HALF_START {
+            let entry = pml4.entries[index];
+            if entry & PRESENT == 0 {
+                continue;
+            }
+            if let Err(e) = share_table(entry_addr(entry), 3, (index as u64) << 39, &mut child) {
+                child.destroy();
+                return Err(e);
+            }
+        }
+        Ok(child)
+    }
+
+    /// Give the page holding `virt` back its write access if it lost it to
+    /// `fork`, copying it first while another space still shares it
+    /// Returns false if the page is not copy-on-write.
+    pub fn copy_on_write(&mut self, virt: VirtAddr) -> Result<bool, &'static str> {
+        let page = virt.align_down(PAGE_SIZE as u64);
+        let Some(entry) = self.leaf_entry(page) else {
+            return Ok(false);
+        };
+        if *entry & PRESENT == 0 || *entry & COPY_ON_WRITE == 0 {
+            return Ok(false);
+        }
+
+        let shared = entry_addr(*entry);
+        let frame = if cow::is_shared(shared) {
+            let copy = frame_allocator::allocate_frame(FrameOwner::User).ok_or("Out of memory for a page copy")?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    phys_to_virt(shared).as_ptr::<u8>(),
+                    phys_to_virt(copy).as_mut_ptr::<u8>(),
+                    PAGE_SIZE,
+                );
+            }
+            cow::release(shared);
+            copy
+        } else {
+            // Everyone else has copied it or gone; it is ours to write
+            shared
+        };
+        *entry = frame.as_u64() | (*entry & !ADDR_MASK & !COPY_ON_WRITE) | WRITABLE;
+        if Arch::active_table() == self.pml4_phys {
+            Arch::flush_page(page);
+        }
+        Ok(true)
+    }
+
+    /// Unmap every page in `start..end` (user addresses), freeing frames no
+    /// forked space still shares; returns how many pages were mapped
+    /// The emptied tables stay until the space is destroyed.
+    pub fn unmap_range(&mut self, start: VirtAddr, end: VirtAddr) -> usize {
+        let unmapped = unmap_in(self.pml4_phys, 4, 0, start.as_u64(), end.as_u64());
+        if unmapped > 0 && Arch::active_table() == self.pml4_phys {
+            // Reloading the table drops every stale translation at once
+            unsafe { Arch::switch_table(self.pml4_phys) };
+        }
+        unmapped
+    }
+
+    /// Release every user-half table and every frame mapped through them
+    pub fn destroy(self) {
+        let pml4 = table_at(self.pml4_phys);
+        for index in 0..KERNEL_HALF_START {
+            let entry = pml4.entries[index];
+            if entry & PRESENT != 0 {
+                free_table(entry_addr(entry), 3);
+            }
+        }
+        frame_allocator::deallocate_frame(self.pml4_phys);
+    }
+}
+
+/// Map every page under the table at `level` (3 = PDPT .. 1 = PT), which
+/// covers user addresses from `base`, into `child` too
+fn share_table(phys: PhysAddr, level: usize, base: u64, child: &mut AddressSpace) -> Result<(), &'static str> {
+    let table = table_at(phys);
+    for (index, entry) in table.entries.iter_mut().enumerate() {
+        if *entry & PRESENT == 0 {
+            continue;
+        }
+        let virt = base | (index as u64) << (12 + 9 * (level - 1));
+        if level == 1 {
+            if *entry & WRITABLE != 0 {
+                *entry = (*entry & !WRITABLE) | COPY_ON_WRITE;
+            }
+            child.map_page(VirtAddr::new(virt), entry_addr(*entry), *entry & !ADDR_MASK)?;
+            cow::share(entry_addr(*entry));
+        } else if *entry & HUGE_PAGE != 0 {
+            return Err("Huge user pages cannot be shared");
+        } else {
+            share_table(entry_addr(*entry), level - 1, virt, child)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unmap the pages in `start..end` under the table at `level` (4 = PML4 ..
+/// 1 = PT), which covers addresses from `base`
+fn unmap_in(phys: PhysAddr, level: usize, base: u64, start: u64, end: u64) -> usize {
+    let span = 1u64 << (12 + 9 * (level - 1));
+    let entries = if level == 4 { KERNEL_HALF_START } else { ENTRY_COUNT };
+    let table = table_at(phys);
+    let mut unmapped = 0;
+    for (index, entry) in table.entries[..entries].iter_mut().enumerate() {
+        let from = base + index as u64 * span;
+        if *entry & PRESENT == 0 || from + span <= start || end <= from {
+            continue;
+        }
+        if level == 1 {
+            let frame = entry_addr(*entry);
+            *entry = 0;
+            if cow::release(frame) {
+                frame_allocator::deallocate_frame(frame);
+            }
+            unmapped += 1;
+        } else if *entry & HUGE_PAGE == 0 {
+            unmapped += unmap_in(entry_addr(*entry), level - 1, from, start, end);
+        }
+    }
+    unmapped
+}
+
+/// Free a table at `level` (3 = PDPT .. 1 = PT) and everything below it
+/// Frames still shared with a forked address space stay with it.
+fn free_table(phys: PhysAddr, level: usize) {
+    let table = table_at(phys);
+    for &entry in table.entries.iter() {
+        if entry & PRESENT == 0 {
+            continue;
+        }
+        let target = entry_addr(entry);
+        if level == 1 {
+            if cow::release(target) {
+                frame_allocator::deallocate_frame(target);
+            }
+        } else if entry & HUGE_PAGE == 0 {
+            free_table(target, level - 1);
+        }
+    }
+    frame_allocator::deallocate_frame(phys);
+}
@@ -0,0 +1,86 @@
+//! W^X for the kernel's own mappings
+//! Limine maps the kernel image from its ELF segments and all of memory
+//! through the HHDM. Rather than trust its choice of permissions, `init`
+//! sets them at boot: text read and execute, rodata read-only, data and bss
+//! read and write, and the HHDM no-execute, which covers every frame the
+//! frame allocator, heap and slabs hand out. Kernel pages mapped later
+//! (`paging::map_kernel_page`) are no-execute too.
+//! This must run before the first user address space copies the kernel half.
+
+use super::paging::{self, PAGE_SIZE, WRITABLE};
+use super::{hhdm_offset, VirtAddr};
+use crate::arch::{Arch, Mmu};
+use crate::{bootinfo, info, warn};
+
+extern "C" {
+    // Section bounds from kernel/linker.ld
+    static __text_start: u8;
+    static __rodata_start: u8;
+    static __data_start: u8;
+    static __kernel_end: u8;
+}
+
+const PAGE: u64 = PAGE_SIZE as u64;
+/// Limine maps at least the first 4 GB into the HHDM, whatever the memory map says
+const HHDM_MIN_SIZE: u64 = 4 << 30;
+/// Bytes one PML4 entry maps
+const PML4_ENTRY_SPAN: u64 = 1 << 39;
+
+/// Set the kernel image and HHDM permissions in the active tables
+pub fn init() {
+    let nx = paging::no_execute();
+    if nx == 0 {
+        warn!("protect: CPU has no NX, kernel data stays executable");
+    }
+    let text = &raw const __text_start as u64;
+    let rodata = &raw const __rodata_start as u64;
+    let data = &raw const __data_start as u64;
+    let end = (&raw const __kernel_end as u64).next_multiple_of(PAGE);
+
+    let mut pages = 0;
+    let mut failed = 0;
+    for (start, end, flags) in [(text, rodata, 0), (rodata, data, nx), (data, end, WRITABLE | nx)] {
+        for page in (start..end).step_by(PAGE_SIZE) {
+            match paging::set_kernel_flags(VirtAddr::new(page), flags) {
+                Ok(()) => pages += 1,
+                Err(_) => failed += 1,
+            }
+        }
+    }
+    if failed > 0 {
+        warn!("protect: {} kernel image pages not mapped by 4KB pages, left as the bootloader set them", failed);
+    }
+
+    let mut hhdm_entries = 0;
+    if nx != 0 {
+        for index in hhdm_pml4_entries() {
+            if paging::restrict_top_level(index, nx) {
+                hhdm_entries += 1;
+            }
+        }
+        // Limine's mappings aren't global, so reloading CR3 flushes them
+        unsafe { Arch::switch_table(Arch::active_table()) };
+    }
+    info!("protect: kernel image {} pages W^X, HHDM no-execute in {} PML4 entries", pages, hhdm_entries);
+}
+
+/// PML4 entries the HHDM spans, which map nothing else
+fn hhdm_pml4_entries() -> core::ops::RangeInclusive<usize> {
+    let top = bootinfo::get().memory_map().iter().map(|entry| entry.end()).max().unwrap_or(0).max(HHDM_MIN_SIZE);
+    let first = hhdm_offset() / PML4_ENTRY_SPAN % 512;
+    let last = (hhdm_offset() + top - 1) / PML4_ENTRY_SPAN % 512;
+    first as usize..=last as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::{Arch, Mmu};
+    use crate::memory::pagecheck;
+
+    #[test_case]
+    fn test_no_writable_executable_kernel_pages() {
+        let report = pagecheck::check(Arch::active_table());
+        assert!(report.nx_enforced);
+        assert_eq!(report.writable_executable.count, 0, "first at {:#x?}", report.writable_executable.first);
+    }
+}
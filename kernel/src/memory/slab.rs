@@ -0,0 +1,251 @@
+//! Slab caches for small kernel objects
+//! A `SlabCache` hands out objects of one size, carved from frames taken
+//! from the frame allocator. Each frame (a slab) starts with a header holding
+//! its free list and a count of objects in use; the rest of it is objects.
+//! Slabs with free objects are kept on a list, a full slab is found again
+//! from the address of an object freed into it, and a slab that empties goes
+//! back to the frame allocator once its cache already holds an empty one.
+//! The heap's small size classes are served from `SIZE_CACHES`, behind the
+//! per-CPU magazines, so small allocations neither fragment the heap nor
+//! count against its size. Each cache counts its live objects: a count that
+//! keeps growing while the system does the same work over again is a leak.
+
+use super::frame_allocator::{self, FrameOwner};
+use super::magazine::{CLASS_COUNT, SIZE_CLASSES};
+use super::paging::PAGE_SIZE;
+use super::{hhdm_offset, phys_to_virt, PhysAddr};
+use crate::sync::spinlock::Spinlock;
+use core::ptr;
+use shared::data_structures::intrusive::{Link, List, SLink, SList};
+use shared::linked;
+
+/// Header at the start of every slab
+#[repr(C)]
+struct Slab {
+    /// On the cache's list of slabs with free objects
+    link: Link,
+    free: SList<FreeObject>,
+    in_use: usize,
+}
+
+/// A free object, linked through its first word
+struct FreeObject {
+    link: SLink,
+}
+
+linked!(Slab, link: Link);
+linked!(FreeObject, link: SLink);
+
+const OBJECT_ALIGN: usize = 16;
+/// Objects start past the header, aligned like every object after them
+const OBJECTS_OFFSET: usize = size_of::<Slab>().next_multiple_of(OBJECT_ALIGN);
+/// Largest object a cache can hold; three of them fit a slab
+pub const MAX_OBJECT_SIZE: usize = 1024;
+/// Empty slabs a cache holds on to as objects are freed, so allocating and
+/// freeing one object in turn does not take and return a frame each time
+const KEEP_EMPTY: usize = 1;
+
+/// The caches behind the heap's size classes (see `magazine`)
+pub static SIZE_CACHES: [SlabCache; CLASS_COUNT] = [
+    SlabCache::new("size-16", SIZE_CLASSES[0]),
+    SlabCache::new("size-32", SIZE_CLASSES[1]),
+    SlabCache::new("size-64", SIZE_CLASSES[2]),
+    SlabCache::new("size-128", SIZE_CLASSES[3]),
+    SlabCache::new("size-256", SIZE_CLASSES[4]),
+    SlabCache::new("size-512", SIZE_CLASSES[5]),
+];
+
+struct CacheState {
+    /// Slabs with at least one free object, empty ones included
+    partial: List<Slab>,
+    slabs: usize,
+    empty: usize,
+    live: usize,
+    peak: usize,
+    allocs: u64,
+    frees: u64,
+}
+
+// Slabs are reached only through the cache that owns them, under its lock
+unsafe impl Send for CacheState {}
+
+impl CacheState {
+    /// Take `slab` off the partial list and give its frame back
+    unsafe fn release(&mut self, slab: *mut Slab) {
+        self.partial.remove(slab);
+        self.slabs -= 1;
+        self.empty -= 1;
+        frame_allocator::deallocate_frame(PhysAddr::new(slab as u64 - hhdm_offset()));
+    }
+}
+
+/// Counters of one cache, for `slabinfo`
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    /// Objects allocated and not yet freed
+    pub live: usize,
+    /// Most objects live at once
+    pub peak: usize,
+    pub slabs: usize,
+    pub empty_slabs: usize,
+    pub allocs: u64,
+    pub frees: u64,
+}
+
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    state: Spinlock<CacheState>,
+}
+
+impl SlabCache {
+    /// A cache of `object_size`-byte objects, rounded up to a multiple of
+    /// 16 bytes and aligned to 16
+    pub const fn new(name: &'static str, object_size: usize) -> Self {
+        assert!(object_size > 0 && object_size <= MAX_OBJECT_SIZE);
+        SlabCache {
+            name,
+            object_size: object_size.next_multiple_of(OBJECT_ALIGN),
+            state: Spinlock::new(CacheState {
+                partial: List::new(),
+                slabs: 0,
+                empty: 0,
+                live: 0,
+                peak: 0,
+                allocs: 0,
+                frees: 0,
+            }),
+        }
+    }
+
+    /// One object, or null if no frame is left for a new slab
+    pub fn alloc(&self) -> *mut u8 {
+        let mut object = ptr::null_mut();
+        self.alloc_batch(1, &mut |allocated| object = allocated);
+        object
+    }
+
+    /// Allocate up to `count` objects under one lock acquisition, handing
+    /// each to `take`; returns how many there were
+    pub fn alloc_batch(&self, count: usize, take: &mut dyn FnMut(*mut u8)) -> usize {
+        let mut state = self.state.lock();
+        let mut allocated = 0;
+        while allocated < count {
+            if state.partial.is_empty() && !self.grow(&mut state) {
+                break;
+            }
+            unsafe {
+                // Every slab on the partial list has a free object
+                let slab = state.partial.front().unwrap();
+                let object = (*slab).free.pop_front().unwrap();
+                if (*slab).in_use == 0 {
+                    state.empty -= 1;
+                }
+                (*slab).in_use += 1;
+                if (*slab).free.is_empty() {
+                    state.partial.remove(slab);
+                }
+                take(object.cast());
+            }
+            allocated += 1;
+        }
+        state.allocs += allocated as u64;
+        state.live += allocated;
+        state.peak = state.peak.max(state.live);
+        allocated
+    }
+
+    /// Return `object`, which must have come from this cache
+    pub fn free(&self, object: *mut u8) {
+        self.free_batch(core::iter::once(object));
+    }
+
+    /// Return every object `objects` yields under one lock acquisition
+    pub fn free_batch(&self, objects: impl Iterator<Item = *mut u8>) {
+        let mut state = self.state.lock();
+        for object in objects {
+            let object = object.cast::<FreeObject>();
+            // Slabs are frame-aligned, so the header is at the frame's start
+            let slab = (object as usize & !(PAGE_SIZE - 1)) as *mut Slab;
+            unsafe {
+                if (*slab).free.is_empty() {
+                    state.partial.push_front(slab);
+                }
+                (*slab).free.push_front(object);
+                (*slab).in_use -= 1;
+                if (*slab).in_use == 0 {
+                    state.empty += 1;
+                    if state.empty > KEEP_EMPTY {
+                        state.release(slab);
+                    }
+                }
+            }
+            state.frees += 1;
+            state.live -= 1;
+        }
+    }
+
+    /// Give every empty slab back to the frame allocator; returns the frames freed
+    /// Used while reclaiming, so a cache in use is skipped.
+    fn shrink(&self) -> usize {
+        let Some(mut state) = self.state.try_lock() else {
+            return 0;
+        };
+        let mut freed = 0;
+        for slab in state.partial.iter() {
+            unsafe {
+                if (*slab).in_use == 0 {
+                    state.release(slab);
+                    freed += 1;
+                }
+            }
+        }
+        freed
+    }
+
+    /// Carve a new frame into objects and put it on the partial list
+    fn grow(&self, state: &mut CacheState) -> bool {
+        let Some(phys) = frame_allocator::allocate_frame(FrameOwner::Slab) else {
+            return false;
+        };
+        let base: *mut u8 = phys_to_virt(phys).as_mut_ptr();
+        let count = (PAGE_SIZE - OBJECTS_OFFSET) / self.object_size;
+        unsafe {
+            let slab = base.cast::<Slab>();
+            slab.write(Slab { link: Link::new(), free: SList::new(), in_use: 0 });
+            // Push in reverse so the objects are handed out in address order
+            for i in (0..count).rev() {
+                (*slab).free.push_front(base.add(OBJECTS_OFFSET + i * self.object_size).cast());
+            }
+            state.partial.push_front(slab);
+        }
+        state.slabs += 1;
+        state.empty += 1;
+        true
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let state = self.state.lock();
+        SlabStats {
+            name: self.name,
+            object_size: self.object_size,
+            live: state.live,
+            peak: state.peak,
+            slabs: state.slabs,
+            empty_slabs: state.empty,
+            allocs: state.allocs,
+            frees: state.frees,
+        }
+    }
+}
+
+/// Give the empty slabs of every size cache back to the frame allocator;
+/// returns the frames freed
+pub fn shrink() -> usize {
+    SIZE_CACHES.iter().map(SlabCache::shrink).sum()
+}
+
+pub fn stats() -> impl Iterator<Item = SlabStats> {
+    SIZE_CACHES.iter().map(SlabCache::stats)
+}
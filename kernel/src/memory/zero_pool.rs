@@ -0,0 +1,98 @@
+//! Pre-zeroed frame pool
+//! Frames are zeroed ahead of time while the system is idle, so allocations
+//! that must not leak old contents (user pages, DMA buffers) can usually take
+//! a clean frame without clearing 4 KB on the spot. There is no scheduler to
+//! run a zeroing thread yet, so the shell's input loop calls `idle_work()`.
+
+use super::frame_allocator::{self, FrameOwner, FRAME_SIZE};
+use super::{phys_to_virt, PhysAddr};
+use crate::sync::spinlock::Spinlock;
+use core::ptr;
+
+const POOL_FRAMES: usize = 32;
+
+struct ZeroPool {
+    frames: [PhysAddr; POOL_FRAMES],
+    count: usize,
+}
+
+static POOL: Spinlock<ZeroPool> = Spinlock::new(ZeroPool {
+    frames: [PhysAddr::zero(); POOL_FRAMES],
+    count: 0,
+});
+
+fn zero_frame(phys: PhysAddr) {
+    unsafe { ptr::write_bytes(phys_to_virt(phys).as_mut_ptr::<u8>(), 0, FRAME_SIZE) };
+}
+
+/// Allocate a frame whose contents are all zero
+pub fn allocate_zeroed_frame(owner: FrameOwner) -> Option<PhysAddr> {
+    let pooled = {
+        let mut pool = POOL.lock();
+        if pool.count > 0 {
+            pool.count -= 1;
+            Some(pool.frames[pool.count])
+        } else {
+            None
+        }
+    };
+
+    match pooled {
+        Some(phys) => {
+            frame_allocator::set_owner(phys, owner);
+            Some(phys)
+        }
+        None => {
+            // Pool drained: pay for the clear on this path instead
+            let phys = frame_allocator::allocate_frame(owner)?;
+            zero_frame(phys);
+            Some(phys)
+        }
+    }
+}
+
+/// Zero one more frame into the pool; returns false once the pool is full
+pub fn idle_work() -> bool {
+    if POOL.lock().count >= POOL_FRAMES {
+        return false;
+    }
+
+    // Filling the pool is never worth reclaiming memory for
+    let phys = match frame_allocator::try_allocate_frame(FrameOwner::ZeroPool) {
+        Some(phys) => phys,
+        None => return false,
+    };
+    // Clear outside the lock so allocations are never held up behind a memset
+    zero_frame(phys);
+
+    let mut pool = POOL.lock();
+    if pool.count < POOL_FRAMES {
+        let index = pool.count;
+        pool.frames[index] = phys;
+        pool.count += 1;
+        true
+    } else {
+        drop(pool);
+        frame_allocator::deallocate_frame(phys);
+        false
+    }
+}
+
+/// Give every pooled frame back to the frame allocator, returning how many
+/// there were; used when frames run out
+pub fn drain() -> usize {
+    let Some(mut pool) = POOL.try_lock() else {
+        return 0;
+    };
+    let count = pool.count;
+    for &phys in &pool.frames[..count] {
+        frame_allocator::deallocate_frame(phys);
+    }
+    pool.count = 0;
+    count
+}
+
+/// Number of pre-zeroed frames waiting in the pool
+pub fn pooled_frames() -> usize {
+    POOL.lock().count
+}
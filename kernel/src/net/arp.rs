@@ -0,0 +1,120 @@
+//! ARP resolution and cache
+//! Replies to requests for `LOCAL_IP` and remembers every sender that talks
+//! to us. The cache is small and replaced round-robin; entries never expire,
+//! which is fine for the static QEMU network.
+
+use super::LOCAL_IP;
+use crate::sync::rwlock::RwLock;
+use alloc::vec::Vec;
+use shared::net::arp::{self, ArpPacket};
+use shared::net::ethernet::ETHERTYPE_ARP;
+use shared::net::{Ipv4Addr, MacAddr};
+
+const CACHE_SIZE: usize = 16;
+const REQUEST_ATTEMPTS: u64 = 4;
+const REQUEST_INTERVAL_MS: u64 = 250;
+
+struct ArpCache {
+    entries: [Option<(Ipv4Addr, MacAddr)>; CACHE_SIZE],
+    /// Slot overwritten when the cache is full
+    next: usize,
+}
+
+/// Looked up for every packet sent, written only by incoming ARP
+static CACHE: RwLock<ArpCache> = RwLock::new(ArpCache {
+    entries: [None; CACHE_SIZE],
+    next: 0,
+});
+
+impl ArpCache {
+    fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+        self.entries.iter().flatten().find(|(entry_ip, _)| *entry_ip == ip).map(|&(_, mac)| mac)
+    }
+
+    /// Update an existing entry; returns false if `ip` is not cached
+    fn update(&mut self, ip: Ipv4Addr, mac: MacAddr) -> bool {
+        match self.entries.iter_mut().flatten().find(|(entry_ip, _)| *entry_ip == ip) {
+            Some(entry) => {
+                entry.1 = mac;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr) {
+        if self.update(ip, mac) {
+            return;
+        }
+        let slot = match self.entries.iter().position(|e| e.is_none()) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.next;
+                self.next = (self.next + 1) % CACHE_SIZE;
+                slot
+            }
+        };
+        self.entries[slot] = Some((ip, mac));
+    }
+}
+
+/// Handle an incoming ARP packet (RFC 826 "packet reception")
+pub fn receive(payload: &[u8]) {
+    let packet = match ArpPacket::parse(payload) {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    let for_us = packet.target_ip == LOCAL_IP;
+    {
+        let mut cache = CACHE.write();
+        // Refresh senders we already know; only learn new ones that address us
+        if !cache.update(packet.sender_ip, packet.sender_mac) && for_us {
+            cache.insert(packet.sender_ip, packet.sender_mac);
+        }
+    }
+
+    if for_us && packet.op == arp::OP_REQUEST {
+        let _ = send(arp::OP_REPLY, packet.sender_mac, packet.sender_mac, packet.sender_ip);
+    }
+}
+
+fn send(op: u16, dst: MacAddr, target_mac: MacAddr, target_ip: Ipv4Addr) -> Result<(), &'static str> {
+    let sender_mac = super::mac().ok_or("No network device")?;
+    let packet = ArpPacket {
+        op,
+        sender_mac,
+        sender_ip: LOCAL_IP,
+        target_mac,
+        target_ip,
+    };
+    super::send_frame(dst, ETHERTYPE_ARP, |buf| packet.write(buf))
+}
+
+/// Cached MAC address for `ip`
+pub fn lookup(ip: Ipv4Addr) -> Option<MacAddr> {
+    if ip == Ipv4Addr::BROADCAST {
+        return Some(MacAddr::BROADCAST);
+    }
+    CACHE.read().lookup(ip)
+}
+
+/// MAC address for `ip`, broadcasting requests until it answers
+/// Waits up to a second; must not be called while handling a received frame.
+pub fn resolve(ip: Ipv4Addr) -> Result<MacAddr, &'static str> {
+    if let Some(mac) = lookup(ip) {
+        return Ok(mac);
+    }
+    for _ in 0..REQUEST_ATTEMPTS {
+        send(arp::OP_REQUEST, MacAddr::BROADCAST, MacAddr::ZERO, ip)?;
+        if super::poll_until(REQUEST_INTERVAL_MS, || lookup(ip).is_some()) {
+            return lookup(ip).ok_or("ARP entry evicted");
+        }
+    }
+    Err("No ARP reply")
+}
+
+/// Snapshot of the cache as (IP, MAC) pairs
+pub fn entries() -> Vec<(Ipv4Addr, MacAddr)> {
+    CACHE.read().entries.iter().flatten().copied().collect()
+}
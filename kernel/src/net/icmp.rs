@@ -0,0 +1,64 @@
+//! ICMP echo
+//! Answers echo requests and sends our own for the `ping` command.
+
+use super::ipv4;
+use crate::sync::spinlock::Spinlock;
+use core::sync::atomic::{AtomicU64, Ordering};
+use shared::net::icmp::{self, IcmpHeader};
+use shared::net::ipv4::{Ipv4Header, PROTOCOL_ICMP};
+use shared::net::{Ipv4Addr, MacAddr};
+
+/// Identifier carried in our echo requests
+const PING_ID: u16 = 0x7766;
+const PING_PAYLOAD: &[u8] = b"wflos ping";
+
+static ECHO_REPLIES_SENT: AtomicU64 = AtomicU64::new(0);
+
+/// (source, sequence) of the last echo reply addressed to `PING_ID`
+static LAST_REPLY: Spinlock<Option<(Ipv4Addr, u16)>> = Spinlock::new(None);
+
+/// Handle an ICMP message that arrived from `src_mac`
+pub fn receive(src_mac: MacAddr, header: &Ipv4Header, message: &[u8]) {
+    let (icmp_header, body) = match IcmpHeader::parse(message) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    match icmp_header.icmp_type {
+        icmp::TYPE_ECHO_REQUEST => {
+            let reply = IcmpHeader { icmp_type: icmp::TYPE_ECHO_REPLY, code: 0, rest: icmp_header.rest };
+            if ipv4::send_via(src_mac, header.src, PROTOCOL_ICMP, |buf| reply.write(buf, body)).is_ok() {
+                ECHO_REPLIES_SENT.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        icmp::TYPE_ECHO_REPLY => {
+            let (id, seq) = icmp_header.echo_id_seq();
+            if id == PING_ID {
+                *LAST_REPLY.lock() = Some((header.src, seq));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Send one echo request to `dst` and wait up to `timeout_ms` for the reply
+/// Returns the round-trip time in milliseconds (tick resolution).
+pub fn ping(dst: Ipv4Addr, seq: u16, timeout_ms: u64) -> Result<u64, &'static str> {
+    let mut rest = [0u8; 4];
+    rest[..2].copy_from_slice(&PING_ID.to_be_bytes());
+    rest[2..].copy_from_slice(&seq.to_be_bytes());
+    let request = IcmpHeader { icmp_type: icmp::TYPE_ECHO_REQUEST, code: 0, rest };
+
+    *LAST_REPLY.lock() = None;
+    let start = crate::time::uptime_ms();
+    ipv4::send(dst, PROTOCOL_ICMP, |buf| request.write(buf, PING_PAYLOAD))?;
+    if super::poll_until(timeout_ms, || *LAST_REPLY.lock() == Some((dst, seq))) {
+        Ok(crate::time::uptime_ms() - start)
+    } else {
+        Err("Request timed out")
+    }
+}
+
+/// Echo requests answered since boot
+pub fn echo_replies_sent() -> u64 {
+    ECHO_REPLIES_SENT.load(Ordering::Relaxed)
+}
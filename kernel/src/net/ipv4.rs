@@ -0,0 +1,65 @@
+//! IPv4 input and output
+//! One interface with a static address. Packets off the local subnet go to
+//! the gateway; fragments are dropped.
+
+use super::{arp, icmp, udp, GATEWAY, LOCAL_IP, NETMASK};
+use core::sync::atomic::{AtomicU16, Ordering};
+use shared::net::ethernet::{self, EthernetHeader, ETHERTYPE_IPV4};
+use shared::net::ipv4::{self, Ipv4Header};
+use shared::net::{Ipv4Addr, MacAddr};
+
+/// Largest payload that fits in one unfragmented packet
+pub const MAX_PAYLOAD: usize =
+    crate::drivers::virtio::net::MAX_FRAME_LEN - ethernet::HEADER_LEN - ipv4::HEADER_LEN;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Handle an incoming IPv4 packet carried in `eth`
+pub fn receive(eth: &EthernetHeader, packet: &[u8]) {
+    let (header, payload) = match Ipv4Header::parse(packet) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    if header.dst != LOCAL_IP && header.dst != Ipv4Addr::BROADCAST {
+        return;
+    }
+    match header.protocol {
+        ipv4::PROTOCOL_ICMP => icmp::receive(eth.src, &header, payload),
+        ipv4::PROTOCOL_UDP => udp::receive(&header, payload),
+        _ => {}
+    }
+}
+
+/// Send a packet to `dst`, resolving the next hop with ARP
+/// `build` writes the payload (at most `MAX_PAYLOAD` bytes) and returns its length.
+pub fn send(dst: Ipv4Addr, protocol: u8, build: impl FnOnce(&mut [u8]) -> usize) -> Result<(), &'static str> {
+    let next_hop = if dst == Ipv4Addr::BROADCAST || dst.same_subnet(LOCAL_IP, NETMASK) {
+        dst
+    } else {
+        GATEWAY
+    };
+    let mac = arp::resolve(next_hop)?;
+    send_via(mac, dst, protocol, build)
+}
+
+/// Send a packet to `dst` through the host at `mac`, skipping ARP
+/// Used for replies, which go back to the MAC address the request came from.
+pub fn send_via(
+    mac: MacAddr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    build: impl FnOnce(&mut [u8]) -> usize,
+) -> Result<(), &'static str> {
+    let header = Ipv4Header {
+        src: LOCAL_IP,
+        dst,
+        protocol,
+        ttl: ipv4::DEFAULT_TTL,
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    };
+    super::send_frame(mac, ETHERTYPE_IPV4, |buf| {
+        let (head, body) = buf.split_at_mut(ipv4::HEADER_LEN);
+        let payload_len = build(&mut body[..MAX_PAYLOAD]);
+        header.write(head, payload_len) + payload_len
+    })
+}
@@ -0,0 +1,72 @@
+//! Network stack
+//! Ethernet, ARP, IPv4, ICMP echo and UDP on top of the virtio-net driver.
+//! Frames arrive through `receive`, which the driver calls from `poll()`, so
+//! nothing here runs in interrupt context. Addressing is static and matches
+//! QEMU's user-mode network (`-netdev user`).
+
+pub mod arp;
+pub mod icmp;
+pub mod ipv4;
+pub mod udp;
+
+use crate::drivers::virtio::net as nic;
+use crate::time;
+use shared::net::ethernet::{self, EthernetHeader};
+use shared::net::{Ipv4Addr, MacAddr};
+
+pub const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+pub const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+/// Start handling frames from the network device
+pub fn init() {
+    nic::set_receive_callback(receive);
+}
+
+/// MAC address of the network device, if there is one
+pub fn mac() -> Option<MacAddr> {
+    nic::mac_address().map(MacAddr)
+}
+
+fn receive(frame: &[u8]) {
+    let (eth, payload) = match EthernetHeader::parse(frame) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    match eth.ethertype {
+        ethernet::ETHERTYPE_ARP => arp::receive(payload),
+        ethernet::ETHERTYPE_IPV4 => ipv4::receive(&eth, payload),
+        _ => {}
+    }
+}
+
+/// Send one Ethernet frame to `dst`
+/// `build` writes the payload into the buffer it is given and returns its length.
+pub fn send_frame(
+    dst: MacAddr,
+    ethertype: u16,
+    build: impl FnOnce(&mut [u8]) -> usize,
+) -> Result<(), &'static str> {
+    let src = mac().ok_or("No network device")?;
+    let mut frame = [0u8; nic::MAX_FRAME_LEN];
+    let header_len = EthernetHeader { dst, src, ethertype }.write(&mut frame);
+    let payload_len = build(&mut frame[header_len..]);
+    nic::transmit(&frame[..header_len + payload_len])
+}
+
+/// Process incoming frames until `done` returns true or `timeout_ms` passes
+/// Returns whether `done` was satisfied. Must not be called from `receive`,
+/// and needs interrupts enabled for the tick to advance.
+pub fn poll_until(timeout_ms: u64, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = time::uptime_ms() + timeout_ms;
+    loop {
+        nic::poll();
+        if done() {
+            return true;
+        }
+        if time::uptime_ms() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
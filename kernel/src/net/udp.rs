@@ -0,0 +1,104 @@
+//! UDP sockets
+//! `bind` claims a local port; datagrams for it are queued until the owner
+//! calls `recv_from`. Datagrams for unbound ports, or arriving while a queue
+//! is full, are dropped.
+
+use super::{ipv4, LOCAL_IP};
+use crate::sync::spinlock::Spinlock;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use shared::net::ipv4::{Ipv4Header, PROTOCOL_UDP};
+use shared::net::udp::{self, UdpHeader};
+use shared::net::Ipv4Addr;
+
+/// Largest payload `send_to` accepts
+pub const MAX_PAYLOAD: usize = ipv4::MAX_PAYLOAD - udp::HEADER_LEN;
+
+/// Datagrams held per socket (the kernel heap is small)
+const QUEUE_LEN: usize = 4;
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+struct Datagram {
+    src: Ipv4Addr,
+    src_port: u16,
+    data: Vec<u8>,
+}
+
+struct Binding {
+    port: u16,
+    queue: VecDeque<Datagram>,
+}
+
+static BINDINGS: Spinlock<Vec<Binding>> = Spinlock::new(Vec::new());
+
+/// A bound UDP port; unbound when dropped
+pub struct UdpSocket {
+    port: u16,
+}
+
+/// Bind a local port, or any free ephemeral port if `port` is 0
+pub fn bind(port: u16) -> Result<UdpSocket, &'static str> {
+    let mut bindings = BINDINGS.lock();
+    let in_use = |port: u16| bindings.iter().any(|b| b.port == port);
+    let port = if port == 0 {
+        EPHEMERAL_PORTS.clone().find(|&p| !in_use(p)).ok_or("No free ports")?
+    } else if in_use(port) {
+        return Err("Port already in use");
+    } else {
+        port
+    };
+    bindings.push(Binding { port, queue: VecDeque::new() });
+    Ok(UdpSocket { port })
+}
+
+impl UdpSocket {
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Send `data` as one datagram to `dst`:`port`
+    pub fn send_to(&self, data: &[u8], dst: Ipv4Addr, port: u16) -> Result<(), &'static str> {
+        if data.len() > MAX_PAYLOAD {
+            return Err("Datagram too large");
+        }
+        let header = UdpHeader { src_port: self.port, dst_port: port };
+        ipv4::send(dst, PROTOCOL_UDP, |buf| header.write(buf, LOCAL_IP, dst, data))
+    }
+
+    /// Take the oldest queued datagram without waiting
+    /// Copies as much as fits in `buf` and returns (length, source, source port).
+    pub fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, Ipv4Addr, u16)> {
+        let datagram = BINDINGS
+            .lock()
+            .iter_mut()
+            .find(|b| b.port == self.port)
+            .and_then(|b| b.queue.pop_front())?;
+        let len = datagram.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        Some((len, datagram.src, datagram.src_port))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        BINDINGS.lock().retain(|b| b.port != self.port);
+    }
+}
+
+/// Queue an incoming datagram on the socket bound to its destination port
+pub fn receive(header: &Ipv4Header, datagram: &[u8]) {
+    let (udp_header, payload) = match UdpHeader::parse(header.src, header.dst, datagram) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let mut bindings = BINDINGS.lock();
+    if let Some(binding) = bindings.iter_mut().find(|b| b.port == udp_header.dst_port) {
+        if binding.queue.len() < QUEUE_LEN {
+            binding.queue.push_back(Datagram {
+                src: header.src,
+                src_port: udp_header.src_port,
+                data: payload.to_vec(),
+            });
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Power off and reset
+//! `shutdown` enters ACPI sleep state S5 (soft off): it writes the sleep
+//! type from the DSDT's `\_S5` object to the FADT's PM1 control registers.
+//! Without ACPI, or with the machine still running after that, it writes
+//! QEMU's isa-debug-exit device, which ends the emulator where it is
+//! attached. `reboot` writes the FADT reset register, then pulses the reset
+//! line through the keyboard controller. If nothing works, the CPU halts.
+
+use crate::arch::{Arch, Cpu, PortIo};
+use crate::drivers::keyboard;
+use crate::memory::{self, PhysAddr};
+use crate::{acpi, warn, watchdog};
+use shared::acpi::{s5_sleep_types, sleep_command, Fadt, ResetRegister, PM1_SCI_EN, SPACE_SYSTEM_IO};
+
+/// Port of QEMU's `-device isa-debug-exit,iobase=0xf4,iosize=0x04`
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+/// Generic address space of memory
+const SPACE_SYSTEM_MEMORY: u8 = 0;
+
+/// PM1 control reads while waiting for the firmware to enter ACPI mode
+const ACPI_ENABLE_POLLS: usize = 1_000_000;
+/// Spins to give the chipset after a power or reset write before giving up
+const SETTLE_SPINS: usize = 10_000_000;
+
+/// Written to the exit device; QEMU exits with `(code << 1) | 1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    /// Exit status 33
+    Success = 0x10,
+    /// Exit status 35
+    #[cfg_attr(not(test), allow(dead_code))]
+    Failed = 0x11,
+}
+
+/// End QEMU with `code`; only returns when not running under QEMU (or the
+/// exit device is missing)
+pub fn exit_qemu(code: QemuExitCode) {
+    unsafe { Arch::outl(DEBUG_EXIT_PORT, code as u32) };
+}
+
+/// Turn the machine off
+pub fn shutdown() -> ! {
+    Arch::disable_interrupts();
+    watchdog::stop();
+    if let Err(e) = acpi_shutdown() {
+        warn!("ACPI power off failed: {}", e);
+    }
+    exit_qemu(QemuExitCode::Success);
+    warn!("Could not power off; halting");
+    Arch::halt()
+}
+
+/// Reset the machine
+pub fn reboot() -> ! {
+    Arch::disable_interrupts();
+    watchdog::stop();
+    if let Some(reset) = acpi::find(b"FACP").and_then(Fadt::parse).and_then(|fadt| fadt.reset) {
+        write_reset_register(reset);
+        settle();
+    }
+    keyboard::pulse_reset();
+    settle();
+    warn!("Could not reset; halting");
+    Arch::halt()
+}
+
+fn acpi_shutdown() -> Result<(), &'static str> {
+    let fadt = acpi::find(b"FACP").and_then(Fadt::parse).ok_or("no FADT")?;
+    let dsdt = acpi::table_at(fadt.dsdt).ok_or("no DSDT")?;
+    let (slp_typ_a, slp_typ_b) = s5_sleep_types(dsdt).ok_or("no \\_S5 object in the DSDT")?;
+    if fadt.pm1a_control == 0 {
+        return Err("no PM1 control register");
+    }
+    enable_acpi_mode(&fadt)?;
+    unsafe {
+        Arch::outw(fadt.pm1a_control as u16, sleep_command(slp_typ_a));
+        if fadt.pm1b_control != 0 {
+            Arch::outw(fadt.pm1b_control as u16, sleep_command(slp_typ_b));
+        }
+    }
+    settle();
+    Err("still running after entering S5")
+}
+
+/// Take power management over from the firmware, if it still has it
+fn enable_acpi_mode(fadt: &Fadt) -> Result<(), &'static str> {
+    let enabled = || unsafe { Arch::inw(fadt.pm1a_control as u16) } & PM1_SCI_EN != 0;
+    if enabled() || fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        return Ok(());
+    }
+    unsafe { Arch::outb(fadt.smi_cmd as u16, fadt.acpi_enable) };
+    if (0..ACPI_ENABLE_POLLS).any(|_| enabled()) {
+        Ok(())
+    } else {
+        Err("firmware did not switch to ACPI mode")
+    }
+}
+
+fn write_reset_register(reset: ResetRegister) {
+    match reset.space {
+        SPACE_SYSTEM_IO => unsafe { Arch::outb(reset.address as u16, reset.value) },
+        SPACE_SYSTEM_MEMORY => unsafe {
+            Arch::mmio_write(memory::phys_to_virt(PhysAddr::new(reset.address)), reset.value)
+        },
+        // PCI configuration space: the keyboard controller will have to do
+        _ => {}
+    }
+}
+
+fn settle() {
+    for _ in 0..SETTLE_SPINS {
+        core::hint::spin_loop();
+    }
+}
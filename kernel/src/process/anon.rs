@@ -0,0 +1,119 @@
+//! Memory a program asks for as it runs: its heap (`brk`) and `mmap`
+//! Neither is backed by anything until it is touched: the areas are kept in
+//! an `AreaMap` (shared::vma), and a fault in one maps a zeroed page
+//! (`demand::handle_fault`). The heap runs from the page after the program's
+//! image up to the break that `brk` moves, never past `USER_MMAP_BASE`;
+//! `mmap` places mappings from there up. Pages given back by `munmap` or a
+//! lower break are freed if they were ever touched.
+
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::paging::{self, AddressSpace, PAGE_SIZE, USER, WRITABLE};
+use crate::memory::{zero_pool, VirtAddr};
+use abi::{EINVAL, ENOMEM, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE, USER_MMAP_BASE};
+use shared::vma::{Area, AreaMap};
+
+/// End of the range `mmap` places mappings in, well clear of the stack
+const MMAP_END: u64 = 0x7000_0000_0000;
+
+const PAGE: u64 = PAGE_SIZE as u64;
+
+fn page_up(addr: u64) -> Option<u64> {
+    addr.checked_next_multiple_of(PAGE)
+}
+
+/// A program's heap and `mmap` areas
+#[derive(Clone)]
+pub struct Anonymous {
+    areas: AreaMap,
+    heap_start: u64,
+    /// End of the heap, as `brk` last set it
+    brk: u64,
+}
+
+impl Anonymous {
+    /// No mappings, and an empty heap after an image ending at `image_end`
+    pub fn new(image_end: u64) -> Self {
+        let heap_start = page_up(image_end).unwrap_or(USER_MMAP_BASE).min(USER_MMAP_BASE);
+        Anonymous { areas: AreaMap::new(), heap_start, brk: heap_start }
+    }
+
+    /// Move the break to `addr` and return where it is: unchanged if `addr`
+    /// is 0 (a query) or the heap cannot reach it
+    pub fn brk(&mut self, space: &mut AddressSpace, addr: u64) -> u64 {
+        if addr < self.heap_start || addr > USER_MMAP_BASE {
+            return self.brk;
+        }
+        let (old_end, new_end) = (self.brk.next_multiple_of(PAGE), addr.next_multiple_of(PAGE));
+        if new_end > old_end {
+            let grown = Area { start: old_end, end: new_end, prot: PROT_READ | PROT_WRITE };
+            if self.areas.insert(grown).is_err() {
+                return self.brk;
+            }
+        } else if new_end < old_end {
+            if self.areas.remove(new_end, old_end).is_err() {
+                return self.brk;
+            }
+            space.unmap_range(VirtAddr::new(new_end), VirtAddr::new(old_end));
+        }
+        self.brk = addr;
+        addr
+    }
+
+    /// Set aside `len` bytes with protection `prot`, at `hint` if that is
+    /// free and page-aligned, returning the address or an errno
+    pub fn mmap(&mut self, hint: u64, len: u64, prot: u64) -> Result<u64, i64> {
+        if len == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return Err(EINVAL);
+        }
+        let len = page_up(len).ok_or(ENOMEM)?;
+        let usable = |start: u64| {
+            start.is_multiple_of(PAGE)
+                && start >= USER_MMAP_BASE
+                && start.checked_add(len).is_some_and(|end| end <= MMAP_END && self.areas.is_free(start, end))
+        };
+        let start = if usable(hint) {
+            hint
+        } else {
+            self.areas.find_free(len, USER_MMAP_BASE, MMAP_END).ok_or(ENOMEM)?
+        };
+        self.areas.insert(Area { start, end: start + len, prot }).map_err(|_| ENOMEM)?;
+        Ok(start)
+    }
+
+    /// Give back the pages in `addr..addr + len`, which must lie where
+    /// `mmap` places mappings; parts that aren't mapped are skipped
+    pub fn munmap(&mut self, space: &mut AddressSpace, addr: u64, len: u64) -> Result<(), i64> {
+        let end = addr.checked_add(len).and_then(page_up).ok_or(EINVAL)?;
+        if len == 0 || !addr.is_multiple_of(PAGE) || addr < USER_MMAP_BASE || end > MMAP_END {
+            return Err(EINVAL);
+        }
+        // Splitting an area needs a free slot
+        self.areas.remove(addr, end).map_err(|_| ENOMEM)?;
+        space.unmap_range(VirtAddr::new(addr), VirtAddr::new(end));
+        Ok(())
+    }
+
+    /// Map a zeroed page at `page` if one of the areas covers it
+    /// Ok(false) if none does, or its protection allows no access.
+    pub fn fill(&self, space: &mut AddressSpace, page: VirtAddr) -> Result<bool, &'static str> {
+        let Some(area) = self.areas.find(page.as_u64()) else {
+            return Ok(false);
+        };
+        if area.prot == PROT_NONE {
+            return Ok(false);
+        }
+        let mut flags = USER;
+        if area.prot & PROT_WRITE != 0 {
+            flags |= WRITABLE;
+        }
+        if area.prot & PROT_EXEC == 0 {
+            flags |= paging::no_execute();
+        }
+        let phys = zero_pool::allocate_zeroed_frame(FrameOwner::User).ok_or("Out of memory for an anonymous page")?;
+        if let Err(e) = space.map_page(page, phys, flags) {
+            frame_allocator::deallocate_frame(phys);
+            return Err(e);
+        }
+        Ok(true)
+    }
+}
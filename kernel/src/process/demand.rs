@@ -0,0 +1,249 @@
+//! Demand paging for executables run from a file
+//! Instead of copying the whole program in before it starts, `run_file`
+//! maps only the stack and records where each segment's bytes live in the
+//! file. The first touch of a segment page faults, and `handle_fault` reads
+//! that page (plus a readahead window after it) through the filesystem,
+//! whose sector cache keeps repeated runs off the disk.
+//! The running program's address space is kept here, so the fault handler
+//! also fills its heap and `mmap` areas (`anon`) with zeroed pages, and gives
+//! it private copies of pages it shares with a fork (`handle_write_fault`).
+//! System calls fault in a program's buffers before touching them
+//! (`prepare_user`), so a bad pointer fails the call instead of faulting in
+//! the kernel.
+
+use super::anon::Anonymous;
+use super::elf::merge_flags;
+use crate::fs::vfs::Inode;
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::paging::{AddressSpace, COPY_ON_WRITE, PAGE_SIZE, USER, WRITABLE};
+use crate::memory::zero_pool;
+use crate::memory::{phys_to_virt, VirtAddr};
+use crate::warn;
+use crate::sync::kref::KRef;
+use crate::sync::spinlock::Spinlock;
+use alloc::vec::Vec;
+use core::slice;
+use shared::vma::{FileArea, Readahead};
+
+/// Most pages one fault fills
+const MAX_READAHEAD_PAGES: u64 = 16;
+
+/// A PT_LOAD segment waiting to be paged in
+#[derive(Clone)]
+pub struct Segment {
+    pub area: FileArea,
+    pub flags: u64,
+}
+
+/// An executable whose pages are read from its file on first touch
+pub struct LazyImage {
+    inode: KRef<dyn Inode>,
+    segments: Vec<Segment>,
+    readahead: Readahead,
+    faults: u64,
+    pages: u64,
+}
+
+impl LazyImage {
+    pub fn new(inode: KRef<dyn Inode>, segments: Vec<Segment>) -> Self {
+        LazyImage { inode, segments, readahead: Readahead::new(MAX_READAHEAD_PAGES), faults: 0, pages: 0 }
+    }
+
+    /// The same image for a forked copy of the program, which fills its own
+    /// pages from here on
+    pub fn fork(&self) -> Self {
+        LazyImage::new(self.inode.clone(), self.segments.clone())
+    }
+
+    /// Faults taken and pages filled so far
+    pub fn stats(&self) -> (u64, u64) {
+        (self.faults, self.pages)
+    }
+
+    fn covers(&self, page: VirtAddr) -> bool {
+        self.segments.iter().any(|s| s.area.overlaps_page(page.as_u64(), PAGE_SIZE as u64))
+    }
+
+    /// Read the page at `page` from the file and map it into `space`
+    fn fill(&mut self, space: &mut AddressSpace, page: VirtAddr) -> Result<(), &'static str> {
+        let phys = zero_pool::allocate_zeroed_frame(FrameOwner::User).ok_or("Out of memory for program page")?;
+        let frame = unsafe { slice::from_raw_parts_mut(phys_to_virt(phys).as_mut_ptr::<u8>(), PAGE_SIZE) };
+
+        // Two segments can share a boundary page; it gets both their bytes and
+        // the union of their permissions
+        let mut flags = None;
+        for segment in self.segments.iter().filter(|s| s.area.overlaps_page(page.as_u64(), PAGE_SIZE as u64)) {
+            if let Some((at, offset, len)) = segment.area.page_source(page.as_u64(), PAGE_SIZE as u64) {
+                if let Err(e) = read_exact(&*self.inode, offset, &mut frame[at..at + len]) {
+                    frame_allocator::deallocate_frame(phys);
+                    return Err(e);
+                }
+            }
+            flags = Some(match flags {
+                Some(existing) => merge_flags(existing, segment.flags),
+                None => segment.flags,
+            });
+        }
+
+        let result = match flags {
+            Some(flags) => space.map_page(page, phys, flags),
+            None => Err("Page is outside every segment"),
+        };
+        if result.is_err() {
+            frame_allocator::deallocate_frame(phys);
+        }
+        result?;
+        self.pages += 1;
+        Ok(())
+    }
+}
+
+/// Fill `buf` from `inode` starting at `offset`
+pub fn read_exact(inode: &dyn Inode, offset: u64, mut buf: &mut [u8]) -> Result<(), &'static str> {
+    let mut offset = offset as usize;
+    while !buf.is_empty() {
+        match inode.read_at(offset, buf) {
+            Ok(0) => return Err("Executable is shorter than its headers say"),
+            Ok(n) => {
+                offset += n;
+                buf = &mut buf[n..];
+            }
+            Err(e) => return Err(e.as_str()),
+        }
+    }
+    Ok(())
+}
+
+/// The running program's address space, its image if it is demand-paged,
+/// and the memory it has asked for
+pub struct Current {
+    pub space: AddressSpace,
+    pub image: Option<LazyImage>,
+    pub anon: Anonymous,
+}
+
+static CURRENT: Spinlock<Option<Current>> = Spinlock::new(None);
+
+/// Hand the program about to run to the fault handler
+pub fn enter(current: Current) {
+    *CURRENT.lock() = Some(current);
+}
+
+/// Take the program back once it has exited
+pub fn leave() -> Current {
+    CURRENT.lock().take().expect("demand::leave with no running program")
+}
+
+/// A copy of the running program for a forked child (see
+/// `AddressSpace::fork`)
+pub fn fork() -> Result<Current, &'static str> {
+    let mut current = CURRENT.lock();
+    let current = current.as_mut().ok_or("No program is running")?;
+    Ok(Current {
+        space: current.space.fork()?,
+        image: current.image.as_ref().map(LazyImage::fork),
+        anon: current.anon.clone(),
+    })
+}
+
+/// Run `f` on the running program's heap and `mmap` areas and its address
+/// space; None if no program is running
+pub fn with_anon<R>(f: impl FnOnce(&mut Anonymous, &mut AddressSpace) -> R) -> Option<R> {
+    let mut current = CURRENT.lock();
+    let current = current.as_mut()?;
+    Some(f(&mut current.anon, &mut current.space))
+}
+
+/// Give the running program a writable copy of the shared page holding
+/// `addr`, after a write to it faulted
+/// Returns false if the page isn't shared copy-on-write or no copy could be
+/// made, in which case the fault is a real one.
+pub fn handle_write_fault(addr: VirtAddr) -> bool {
+    let mut current = CURRENT.lock();
+    let Some(current) = current.as_mut() else {
+        return false;
+    };
+    match current.space.copy_on_write(addr) {
+        Ok(copied) => copied,
+        Err(e) => {
+            warn!("demand: cannot copy {:#x}: {}", addr, e);
+            false
+        }
+    }
+}
+
+/// Make every page of [start, start + len) present in the running program
+/// and open to it for reading, or with `write` for writing, faulting pages
+/// in and copying shared ones as its own accesses would
+/// Returns false if any page is one the program could not access itself.
+/// The range must already lie in user space.
+pub fn prepare_user(start: u64, len: u64, write: bool) -> bool {
+    let end = start + len;
+    let mut page = VirtAddr::new(start).align_down(PAGE_SIZE as u64);
+    while page.as_u64() < end {
+        if mapped_flags(page).is_none() && !handle_fault(page) {
+            return false;
+        }
+        let Some(flags) = mapped_flags(page) else {
+            return false;
+        };
+        if flags & USER == 0 {
+            return false;
+        }
+        if write && flags & WRITABLE == 0 && (flags & COPY_ON_WRITE == 0 || !handle_write_fault(page)) {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+/// Flags of the running program's mapping at `page`, if it has one
+fn mapped_flags(page: VirtAddr) -> Option<u64> {
+    CURRENT.lock().as_ref()?.space.lookup(page).map(|(_, flags)| flags)
+}
+
+/// Page in the program page holding `addr`, plus any readahead after it,
+/// or map a zeroed page if `addr` is in its heap or an `mmap` area
+/// Returns false if the running program has no business touching `addr`
+/// or the page could not be read, in which case the fault is a real one.
+pub fn handle_fault(addr: VirtAddr) -> bool {
+    let mut current = CURRENT.lock();
+    let Some(Current { space, image, anon }) = current.as_mut() else {
+        return false;
+    };
+
+    let page = addr.align_down(PAGE_SIZE as u64);
+    if space.lookup(page).is_some() {
+        return false;
+    }
+    match anon.fill(space, page) {
+        Ok(true) => return true,
+        Ok(false) => {}
+        Err(e) => {
+            warn!("demand: cannot map {:#x}: {}", page, e);
+            return false;
+        }
+    }
+    let Some(image) = image else {
+        return false;
+    };
+    if !image.covers(page) {
+        return false;
+    }
+    image.faults += 1;
+
+    let window = image.readahead.on_fault(page.as_u64() / PAGE_SIZE as u64);
+    for index in 0..window {
+        let page = page + (index as usize) * PAGE_SIZE;
+        if index > 0 && (!image.covers(page) || space.lookup(page).is_some()) {
+            break;
+        }
+        if let Err(e) = image.fill(space, page) {
+            warn!("demand: cannot fill {:#x}: {}", page, e);
+            // Readahead is best effort; only the faulting page must succeed
+            return index > 0;
+        }
+    }
+    true
+}
@@ -0,0 +1,156 @@
+//! ELF64 executable loader
+//! Parsing and bounds checks live in `shared::elf`. This checks the image
+//! suits this kernel (static, right machine, inside user space), maps each
+//! PT_LOAD segment into a user address space with the permissions it asks
+//! for, and zero-fills the BSS tail. Executables run from a file are only
+//! checked here; `demand` fills their pages as they are touched.
+
+use super::demand::Segment;
+use super::USER_STACK_TOP;
+use crate::memory::paging::{self, AddressSpace, NO_EXECUTE, PAGE_SIZE, USER, WRITABLE};
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::zero_pool;
+use crate::memory::{phys_to_virt, VirtAddr};
+use alloc::vec::Vec;
+use core::ptr;
+use shared::elf::{self, Elf, ProgramHeader};
+use shared::vma::FileArea;
+
+const MACHINE: u16 = elf::EM_X86_64;
+
+pub use elf::is_elf;
+
+/// Parse `image` and check it is a static executable for this machine
+fn parse(image: &[u8]) -> Result<Elf<'_>, &'static str> {
+    check(Elf::parse(image)?)
+}
+
+fn check(elf: Elf) -> Result<Elf, &'static str> {
+    let header = elf.header();
+    if header.elf_type != elf::ET_EXEC {
+        return Err("Not a static executable (ET_EXEC)");
+    }
+    if header.machine != MACHINE {
+        return Err("Executable is for another architecture");
+    }
+    if header.entry >= USER_STACK_TOP.as_u64() {
+        return Err("Entry point outside user space");
+    }
+    Ok(elf)
+}
+
+/// Page flags for a segment: always user-accessible, W and NX as requested
+fn segment_flags(ph: &ProgramHeader) -> u64 {
+    let mut flags = USER;
+    if ph.writable() {
+        flags |= WRITABLE;
+    }
+    if !ph.executable() {
+        flags |= paging::no_execute();
+    }
+    flags
+}
+
+/// Combine permissions of two segments that share a page
+pub fn merge_flags(a: u64, b: u64) -> u64 {
+    let executable = a & NO_EXECUTE == 0 || b & NO_EXECUTE == 0;
+    let merged = (a | b) & !NO_EXECUTE;
+    if executable {
+        merged
+    } else {
+        merged | NO_EXECUTE
+    }
+}
+
+fn load_segment(space: &mut AddressSpace, elf: &Elf, ph: &ProgramHeader) -> Result<(), &'static str> {
+    let file_data = elf.segment_data(ph)?;
+    let mem_end = VirtAddr::new(user_end(ph)?);
+
+    let flags = segment_flags(ph);
+    let seg_start = VirtAddr::new(ph.vaddr);
+    let seg_file_end = seg_start + ph.filesz;
+
+    let mut page = seg_start.align_down(PAGE_SIZE as u64);
+    while page < mem_end {
+        // Segments may share a boundary page; reuse it and widen its permissions
+        let phys = match space.lookup(page) {
+            Some((phys, existing)) => {
+                space.set_flags(page, merge_flags(existing, flags))?;
+                phys
+            }
+            None => {
+                let phys = zero_pool::allocate_zeroed_frame(FrameOwner::User).ok_or("Out of memory for segment")?;
+                if let Err(e) = space.map_page(page, phys, flags) {
+                    frame_allocator::deallocate_frame(phys);
+                    return Err(e);
+                }
+                phys
+            }
+        };
+
+        // Copy the part of the file image that lands in this page; the rest
+        // (including BSS) stays zero from the fresh frame
+        let copy_start = page.max(seg_start);
+        let copy_end = (page + PAGE_SIZE).min(seg_file_end);
+        if copy_start < copy_end {
+            let src = &file_data[(copy_start - seg_start) as usize..(copy_end - seg_start) as usize];
+            let dest = phys_to_virt(phys) + (copy_start - page);
+            unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dest.as_mut_ptr::<u8>(), src.len()) };
+        }
+
+        page += PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+/// End address of a segment, which must lie in user space
+fn user_end(ph: &ProgramHeader) -> Result<u64, &'static str> {
+    let mem_end = ph.vaddr_end()?;
+    if mem_end > USER_STACK_TOP.as_u64() {
+        return Err("Segment outside user space");
+    }
+    Ok(mem_end)
+}
+
+/// Check an executable from its first bytes `start` and its length, returning
+/// the entry point and the segments to page in from the file
+pub fn plan_lazy(start: &[u8], file_len: u64) -> Result<(VirtAddr, Vec<Segment>), &'static str> {
+    let elf = check(Elf::parse_headers(start)?)?;
+
+    let mut segments = Vec::new();
+    for ph in elf.program_headers().filter(|ph| ph.is_load() && ph.memsz > 0) {
+        ph.file_end(file_len)?;
+        let area = FileArea {
+            start: ph.vaddr,
+            end: user_end(&ph)?,
+            file_offset: ph.offset,
+            file_size: ph.filesz,
+        };
+        segments.push(Segment { area, flags: segment_flags(&ph) });
+    }
+
+    if segments.is_empty() {
+        return Err("ELF file has no loadable segments");
+    }
+    Ok((VirtAddr::new(elf.header().entry), segments))
+}
+
+/// Map every PT_LOAD segment of `image` into `space`, returning the entry
+/// point and the end of the highest segment
+pub fn load(space: &mut AddressSpace, image: &[u8]) -> Result<(VirtAddr, u64), &'static str> {
+    let elf = parse(image)?;
+
+    let mut loaded = 0;
+    let mut end = 0;
+    for ph in elf.program_headers().filter(|ph| ph.is_load() && ph.memsz > 0) {
+        load_segment(space, &elf, &ph)?;
+        loaded += 1;
+        end = end.max(user_end(&ph)?);
+    }
+
+    if loaded == 0 {
+        return Err("ELF file has no loadable segments");
+    }
+    Ok((VirtAddr::new(elf.header().entry), end))
+}
@@ -0,0 +1,75 @@
+//! The running program's open files
+//! Descriptors 0, 1 and 2 start out naming the console, and `open` adds
+//! files from the VFS. Descriptors copied by `dup2`, or into a forked child's
+//! table, share one open file and its cursor, as on POSIX. Like the address
+//! space, the table belongs to whichever program is running: `fork` hands
+//! the child a copy and puts the parent's back once the child has exited.
+
+use crate::fs::vfs::File;
+use crate::sync::kref::{KRef, ObjectKind};
+use crate::sync::mutex::Mutex;
+use crate::sync::spinlock::Spinlock;
+use shared::fdtable::{FdError, FdTable};
+
+/// What a descriptor names
+#[derive(Clone)]
+pub enum Descriptor {
+    /// Keyboard in, screen and serial out
+    Console,
+    File(KRef<Mutex<File>>),
+}
+
+impl Descriptor {
+    pub fn file(file: File) -> Self {
+        Descriptor::File(KRef::new(ObjectKind::OpenFile, Mutex::new(file)))
+    }
+}
+
+static FILES: Spinlock<FdTable<Descriptor>> = Spinlock::new(FdTable::new());
+
+/// Give a program about to start the console on descriptors 0, 1 and 2
+pub fn open_console() {
+    let mut files = FdTable::new();
+    for fd in [abi::STDIN, abi::STDOUT, abi::STDERR] {
+        let opened = files.insert(Descriptor::Console);
+        debug_assert_eq!(opened, Ok(fd));
+    }
+    replace(files);
+}
+
+/// Close everything the exited program left open
+pub fn close_all() {
+    replace(FdTable::new());
+}
+
+/// Put `files` in place of the running program's table, returning that
+pub fn replace(files: FdTable<Descriptor>) -> FdTable<Descriptor> {
+    core::mem::replace(&mut *FILES.lock(), files)
+}
+
+/// A copy of the running program's table, for a forked child
+pub fn copy() -> FdTable<Descriptor> {
+    FILES.lock().clone()
+}
+
+/// What `fd` names; the table isn't locked while the caller uses it
+pub fn get(fd: u64) -> Result<Descriptor, FdError> {
+    FILES.lock().get(fd).cloned()
+}
+
+pub fn insert(descriptor: Descriptor) -> Result<u64, FdError> {
+    FILES.lock().insert(descriptor)
+}
+
+pub fn close(fd: u64) -> Result<(), FdError> {
+    // The file (and maybe its inode) is dropped after the lock is released
+    let closed = FILES.lock().remove(fd)?;
+    drop(closed);
+    Ok(())
+}
+
+pub fn dup2(old: u64, new: u64) -> Result<u64, FdError> {
+    let replaced = FILES.lock().dup2(old, new)?;
+    drop(replaced);
+    Ok(new)
+}
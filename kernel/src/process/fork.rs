@@ -0,0 +1,75 @@
+//! fork(): a second copy of the running program
+//! The child starts with a copy-on-write duplicate of the parent's memory
+//! (`AddressSpace::fork`), the same heap and `mmap` areas and image, and a
+//! copy of its descriptor table that shares the parent's open files. There
+//! is no scheduler yet, so the child runs first, to completion, while the
+//! parent waits inside its `fork` call, which then returns the child's id.
+//! A child may fork in turn, up to `MAX_DEPTH` deep, each one running on the
+//! kernel stack below its parent's system call. Its FPU and SSE registers
+//! start as the parent's (see `arch::x86_64::fpu`).
+
+use super::{demand, files, KILL_REQUESTED};
+use crate::arch::{Arch, Mmu};
+use crate::memory::{cow, pagecheck};
+use crate::{debug, info, warn};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Most forks waiting on their child at once
+pub const MAX_DEPTH: usize = 4;
+
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// Id of the next child; the program the shell started counts as 0
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkError {
+    TooDeep,
+    OutOfMemory,
+}
+
+impl ForkError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ForkError::TooDeep => "Too many nested forks",
+            ForkError::OutOfMemory => "Out of memory for the child's page tables",
+        }
+    }
+}
+
+/// Run a copy of the running program with `run_child`, which starts it in
+/// user mode and returns its exit code, and return the copy's id
+pub fn fork(run_child: impl FnOnce() -> i64) -> Result<u64, ForkError> {
+    if DEPTH.load(Ordering::Relaxed) >= MAX_DEPTH {
+        return Err(ForkError::TooDeep);
+    }
+    let child = demand::fork().map_err(|e| {
+        warn!("process: cannot fork: {}", e);
+        ForkError::OutOfMemory
+    })?;
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    let root = child.space.pml4_phys();
+    pagecheck::debug_check(root, "forked address space");
+    debug!("process: forked child {} ({} frames shared)", pid, cow::shared_frames());
+
+    let parent = demand::leave();
+    let parent_root = parent.space.pml4_phys();
+    demand::enter(child);
+    let parent_files = files::replace(files::copy());
+    DEPTH.fetch_add(1, Ordering::Relaxed);
+    let code = unsafe {
+        Arch::switch_table(root);
+        let code = run_child();
+        Arch::switch_table(parent_root);
+        code
+    };
+    DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+    let child = demand::leave();
+    demand::enter(parent);
+    child.space.destroy();
+    files::replace(parent_files);
+    // Asked for while the child ran, so it was the one ended
+    KILL_REQUESTED.store(false, Ordering::Relaxed);
+    info!("process: child {} exited with code {}", pid, code);
+    Ok(pid)
+}
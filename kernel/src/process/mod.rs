@@ -0,0 +1,231 @@
+//! User-mode processes
+//! Loads a flat binary or ELF executable into a fresh address space, enters
+//! user mode, and returns to the caller when the program invokes `exit`.
+//! Executables run from a file are paged in on demand (see `demand`), and a
+//! program can `fork` a copy-on-write copy of itself.
+//! Its heap and `mmap` areas (`anon`) get zeroed pages as it touches them.
+//! A program starts with the console on descriptors 0-2 (`files`); files
+//! and message ports (`ipc`) it leaves open are closed when it exits.
+//! Only one process runs at a time, on the BSP, until a scheduler exists.
+
+pub mod anon;
+pub mod demand;
+pub mod elf;
+pub mod files;
+pub mod fork;
+
+use crate::arch::{Arch, ContextSwitch, Cpu, Mmu};
+use crate::fs::vfs::{self, InodeKind};
+use crate::memory::paging::{self, AddressSpace, PAGE_SIZE, USER, WRITABLE};
+use crate::memory::frame_allocator::{self, FrameOwner};
+use crate::memory::{pagecheck, zero_pool};
+use crate::memory::{phys_to_virt, VirtAddr};
+use crate::{debug, info};
+use alloc::vec;
+use shared::ipc::Owner;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Load address of flat binaries (entry point is the first byte)
+pub const USER_CODE_BASE: VirtAddr = VirtAddr::new(abi::USER_CODE_BASE);
+/// Top of the initial user stack (grows down)
+pub const USER_STACK_TOP: VirtAddr = VirtAddr::new(abi::USER_STACK_TOP);
+const USER_STACK_PAGES: usize = 4;
+const MAX_IMAGE_SIZE: usize = 4 * 1024 * 1024;
+
+static PROCESS_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Set by the out-of-memory policy to end the running program
+static KILL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Exit code of a program ended to free its memory
+pub const OOM_EXIT_CODE: i64 = -abi::ENOMEM;
+
+/// Map `pages` freshly zeroed frames starting at `virt`
+fn map_fresh_pages(
+    space: &mut AddressSpace,
+    virt: VirtAddr,
+    pages: usize,
+    flags: u64,
+) -> Result<(), &'static str> {
+    for page in 0..pages {
+        let phys = zero_pool::allocate_zeroed_frame(FrameOwner::User).ok_or("Out of memory for user pages")?;
+        if let Err(e) = space.map_page(virt + page * PAGE_SIZE, phys, flags) {
+            frame_allocator::deallocate_frame(phys);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Copy a flat binary into user pages at USER_CODE_BASE
+fn load_flat(space: &mut AddressSpace, image: &[u8]) -> Result<(), &'static str> {
+    for (page, chunk) in image.chunks(PAGE_SIZE).enumerate() {
+        let phys = zero_pool::allocate_zeroed_frame(FrameOwner::User).ok_or("Out of memory for program image")?;
+        let dest = phys_to_virt(phys).as_mut_ptr::<u8>();
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), dest, chunk.len()) };
+        // Flat binaries carry no section info, so code and data share RWX pages
+        if let Err(e) = space.map_page(USER_CODE_BASE + page * PAGE_SIZE, phys, USER | WRITABLE) {
+            frame_allocator::deallocate_frame(phys);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Run a flat binary in ring 3 and return its exit code
+pub fn run_flat(image: &[u8]) -> Result<i64, &'static str> {
+    if image.is_empty() {
+        return Err("Empty program image");
+    }
+    if image.len() > MAX_IMAGE_SIZE {
+        return Err("Program image too large");
+    }
+
+    let mut space = AddressSpace::new_user().ok_or("Out of memory for page tables")?;
+    let setup = load_flat(&mut space, image).and_then(|_| map_user_stack(&mut space));
+    if let Err(e) = setup {
+        space.destroy();
+        return Err(e);
+    }
+
+    let image_end = USER_CODE_BASE.as_u64() + image.len() as u64;
+    run_in(space, None, image_end, USER_CODE_BASE, USER_STACK_TOP)
+}
+
+/// Run an ELF64 executable in ring 3 and return its exit code
+pub fn run_elf(image: &[u8]) -> Result<i64, &'static str> {
+    let mut space = AddressSpace::new_user().ok_or("Out of memory for page tables")?;
+    let loaded = elf::load(&mut space, image).and_then(|loaded| {
+        map_user_stack(&mut space)?;
+        Ok(loaded)
+    });
+    match loaded {
+        Ok((entry, image_end)) => run_in(space, None, image_end, entry, USER_STACK_TOP),
+        Err(e) => {
+            space.destroy();
+            Err(e)
+        }
+    }
+}
+
+/// Run a program image, picking the loader from its contents
+pub fn run_image(image: &[u8]) -> Result<i64, &'static str> {
+    if elf::is_elf(image) {
+        run_elf(image)
+    } else {
+        run_flat(image)
+    }
+}
+
+/// Run the program at `path` in ring 3 and return its exit code
+/// ELF segments are left unmapped and read from the file as they fault in;
+/// flat binaries have no layout to page against and are read whole.
+pub fn run_file(path: &str) -> Result<i64, &'static str> {
+    let inode = vfs::resolve(path).map_err(|e| e.as_str())?;
+    if inode.kind() != InodeKind::File {
+        return Err("Not a file");
+    }
+    let size = inode.size();
+
+    let mut start = vec![0u8; size.min(PAGE_SIZE)];
+    demand::read_exact(&*inode, 0, &mut start)?;
+    if !elf::is_elf(&start) {
+        if size > MAX_IMAGE_SIZE {
+            return Err("Program image too large");
+        }
+        let mut image = vec![0u8; size];
+        demand::read_exact(&*inode, 0, &mut image)?;
+        return run_flat(&image);
+    }
+
+    let (entry, segments) = elf::plan_lazy(&start, size as u64)?;
+    let mut space = AddressSpace::new_user().ok_or("Out of memory for page tables")?;
+    if let Err(e) = map_user_stack(&mut space) {
+        space.destroy();
+        return Err(e);
+    }
+    let image_end = segments.iter().map(|segment| segment.area.end).max().unwrap_or(0);
+    run_in(space, Some(demand::LazyImage::new(inode, segments)), image_end, entry, USER_STACK_TOP)
+}
+
+fn map_user_stack(space: &mut AddressSpace) -> Result<(), &'static str> {
+    let stack_base = VirtAddr::new(USER_STACK_TOP.as_u64() - (USER_STACK_PAGES * PAGE_SIZE) as u64);
+    map_fresh_pages(space, stack_base, USER_STACK_PAGES, USER | WRITABLE | paging::no_execute())
+}
+
+/// Enter ring 3 at `entry` inside `space`, tearing the space down afterwards
+/// With an `image`, page faults on its segments are filled from its file.
+/// The program's heap starts after `image_end`.
+pub fn run_in(
+    space: AddressSpace,
+    image: Option<demand::LazyImage>,
+    image_end: u64,
+    entry: VirtAddr,
+    user_rsp: VirtAddr,
+) -> Result<i64, &'static str> {
+    if PROCESS_RUNNING.swap(true, Ordering::AcqRel) {
+        space.destroy();
+        return Err("A process is already running");
+    }
+
+    let kernel_pml4 = Arch::active_table();
+    pagecheck::debug_check(space.pml4_phys(), "user address space");
+    debug!("process: entering user mode at {:#x}", entry);
+
+    let root = space.pml4_phys();
+    demand::enter(demand::Current { space, image, anon: anon::Anonymous::new(image_end) });
+    files::open_console();
+    let code = unsafe {
+        Arch::switch_table(root);
+        let code = Arch::enter_user(entry, user_rsp);
+        Arch::switch_table(kernel_pml4);
+        code
+    };
+
+    let program = demand::leave();
+    if let Some(image) = program.image {
+        let (faults, pages) = image.stats();
+        debug!("process: {} page faults filled {} pages", faults, pages);
+    }
+    program.space.destroy();
+    files::close_all();
+    let ports = crate::ipc::release(Owner::Process);
+    if ports > 0 {
+        debug!("process: destroyed {} message ports", ports);
+    }
+    KILL_REQUESTED.store(false, Ordering::Relaxed);
+    PROCESS_RUNNING.store(false, Ordering::Release);
+    info!("process: exited with code {}", code);
+    Ok(code)
+}
+
+/// Ask for the running program to be ended the next time it enters the
+/// kernel (syscall, timer tick, or fault); false if no program is running
+pub fn request_kill() -> bool {
+    if !PROCESS_RUNNING.load(Ordering::Acquire) {
+        return false;
+    }
+    KILL_REQUESTED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Whether the running program has been asked to end
+pub fn kill_pending() -> bool {
+    KILL_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// End the running program from a trap taken in user mode
+/// The kernel side of the program resumes with interrupts on, as after a
+/// syscall.
+pub fn exit_from_trap(code: i64) -> ! {
+    Arch::enable_interrupts();
+    exit_current(code)
+}
+
+/// Terminate the running process, resuming the kernel in `run_in`
+/// Called from the `exit` syscall; never returns to user mode.
+pub fn exit_current(code: i64) -> ! {
+    if !PROCESS_RUNNING.load(Ordering::Acquire) {
+        panic!("exit() with no running process");
+    }
+    unsafe { Arch::exit_user(code) }
+}
@@ -0,0 +1,92 @@
+//! Random numbers
+//! `fill` hands out bytes from a ChaCha20 generator (`shared::chacha`). It is
+//! seeded from the CPU's generator (RDSEED/RDRAND) where there is one, and
+//! from jitter in the cycle counter otherwise. Every request first mixes in
+//! what has arrived since the last: another word from the CPU generator, the
+//! cycle count, and the timing of device interrupts.
+//! Interrupt handlers feed `add_event`, which folds into an atomic pool and
+//! takes no lock; the generator itself is only used outside interrupt context.
+
+use crate::arch::{Arch, Cpu, Timer};
+use crate::info;
+use crate::sync::spinlock::Spinlock;
+use core::sync::atomic::{AtomicU64, Ordering};
+use shared::chacha::{ChaChaRng, KEY_BYTES};
+
+static RNG: Spinlock<Option<ChaChaRng>> = Spinlock::new(None);
+
+/// Interrupt timings folded together since the last request
+static POOL: AtomicU64 = AtomicU64::new(0);
+
+/// Seed the generator at boot, saying what from
+/// `fill` seeds it on first use anyway; this just gets it done early.
+pub fn init() {
+    let source = if Arch::hardware_random().is_some() { "the CPU's generator" } else { "cycle counter jitter" };
+    RNG.lock().get_or_insert_with(seeded);
+    info!("Random numbers seeded from {}", source);
+}
+
+/// Fill `buf` with random bytes
+pub fn fill(buf: &mut [u8]) {
+    let mut rng = RNG.lock();
+    let rng = rng.get_or_insert_with(seeded);
+    rng.reseed(&fresh());
+    rng.fill(buf);
+}
+
+/// Fold the time of an interrupt, and `data` that came with it, into the pool
+pub fn add_event(data: u64) {
+    let sample = Arch::read_cycles() ^ data.rotate_left(32);
+    // Rotate so successive samples' low bits, the unpredictable ones, spread
+    // over the whole word
+    let _ = POOL.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pool| Some(pool.rotate_left(7) ^ sample));
+}
+
+fn seeded() -> ChaChaRng {
+    let mut seed = [0u8; KEY_BYTES];
+    for chunk in seed.chunks_exact_mut(8) {
+        let word = Arch::hardware_random().unwrap_or_else(jitter);
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    ChaChaRng::new(&seed)
+}
+
+/// Entropy that arrived since the last request
+fn fresh() -> [u8; KEY_BYTES] {
+    let words = [POOL.swap(0, Ordering::Relaxed), Arch::hardware_random().unwrap_or(0), Arch::read_cycles(), 0];
+    let mut seed = [0u8; KEY_BYTES];
+    for (chunk, word) in seed.chunks_exact_mut(8).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    seed
+}
+
+/// A word from timing jitter
+/// How long a short loop takes varies with cache, pipeline and interrupt
+/// state; the low bits of many such timings are folded together.
+fn jitter() -> u64 {
+    let mut word = 0u64;
+    for _ in 0..64 {
+        let start = Arch::read_cycles();
+        for _ in 0..16 {
+            core::hint::spin_loop();
+        }
+        word = word.rotate_left(7) ^ Arch::read_cycles().wrapping_sub(start);
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_fill_gives_fresh_bytes() {
+        let mut first = [0u8; 32];
+        let mut second = [0u8; 32];
+        fill(&mut first);
+        fill(&mut second);
+        assert_ne!(first, [0; 32]);
+        assert_ne!(first, second);
+    }
+}
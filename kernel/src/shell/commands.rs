@@ -1,92 +1,928 @@
 //! Built-in shell commands
 //! Implements command execution
 
-use crate::{println, drivers, memory};
+use super::output::Output;
+use crate::arch::x86_64::cpuid;
+use crate::arch::{Arch, Cpu, Mmu};
+use crate::fs::vfs::{self, InodeKind, OpenFlags};
+use crate::log::{self, Level};
+use crate::sync::kref;
+use crate::{println, bootinfo, drivers, features, hwinfo, input, interrupts, ipc, limine, memory, power, process, rand, symbols, time, watchdog};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use shared::backtrace::Demangled;
+use shared::clock::ClockSource;
+use shared::log::Timestamp;
+use shared::replay;
+use shared::shell::{help, parse_pipeline, script_lines, Command, Pipeline, COMMANDS};
 
-#[derive(Debug, PartialEq)]
-pub enum Command<'a> {
-    Empty,
-    Help,
-    Clear,
-    Echo(&'a str),
-    Version,
-    MemInfo,
-    Halt,
+/// Scripts may run scripts, but not without end
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Scripts currently running inside one another
+static SCRIPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Run a parsed line: each stage's output feeds the next, and the last one
+/// goes to `out` or the redirect file
+/// Returns false if any stage failed.
+pub fn run(pipeline: &Pipeline, out: &mut Output) -> bool {
+    let errors = out.errors();
+    let mut failed = false;
+    let stages = pipeline.stages();
+    let mut input: Option<String> = None;
+    for (index, &cmd) in stages.iter().enumerate() {
+        let last = index + 1 == stages.len();
+        if last && pipeline.redirect.is_none() {
+            execute(cmd, input.as_deref(), out);
+            input = None;
+            break;
+        }
+
+        let mut capture = Output::capture();
+        execute(cmd, input.as_deref(), &mut capture);
+        failed |= capture.errors() > 0;
+        input = capture.into_capture().map(|capture| {
+            if capture.truncated {
+                println!("(output of stage {} cut off at {} bytes)", index + 1, capture.text.len());
+            }
+            capture.text
+        });
+    }
+
+    if let (Some(redirect), Some(text)) = (pipeline.redirect, input) {
+        let flags = if redirect.append { OpenFlags::APPEND } else { OpenFlags::WRITE };
+        let result = vfs::open(redirect.path, flags).and_then(|mut file| vfs::write(&mut file, text.as_bytes()));
+        if let Err(e) = result {
+            out.error(format_args!("{}: {}", redirect.path, e.as_str()));
+        }
+    }
+    !failed && out.errors() == errors
 }
 
-pub fn execute(cmd: Command) {
+/// Run each line of the script at `path`, stopping at the first one that
+/// fails unless `keep_going`
+/// Returns false if the script couldn't be read or any line failed.
+pub fn run_script(path: &str, keep_going: bool, out: &mut Output) -> bool {
+    let text = match read_file(path) {
+        Ok(text) => text,
+        Err(e) => {
+            out.error(format_args!("run: {}: {}", path, e));
+            return false;
+        }
+    };
+    if SCRIPT_DEPTH.fetch_add(1, Ordering::Relaxed) >= MAX_SCRIPT_DEPTH {
+        SCRIPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+        out.error(format_args!("run: {}: scripts nested too deeply", path));
+        return false;
+    }
+
+    let mut ok = true;
+    for (number, line) in script_lines(&text) {
+        let succeeded = match parse_pipeline(line) {
+            Ok(pipeline) => run(&pipeline, out),
+            Err(e) => {
+                out.error(format_args!("Error: {}", e));
+                false
+            }
+        };
+        if !succeeded {
+            ok = false;
+            if !keep_going {
+                out.error(format_args!("run: {}:{}: stopped at failing line '{}'", path, number, line));
+                break;
+            }
+        }
+    }
+
+    SCRIPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    ok
+}
+
+/// Whole contents of a text file
+fn read_file(path: &str) -> Result<String, &'static str> {
+    let mut file = vfs::open(path, OpenFlags::READ).map_err(|e| e.as_str())?;
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        match vfs::read(&mut file, &mut buf).map_err(|e| e.as_str())? {
+            0 => break,
+            count => bytes.extend_from_slice(&buf[..count]),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| "not a text file")
+}
+
+/// Run one command; `input` is the previous pipeline stage's output
+pub fn execute(cmd: Command, input: Option<&str>, out: &mut Output) {
     match cmd {
         Command::Empty => {
             // Do nothing
         }
-        Command::Help => cmd_help(),
-        Command::Clear => cmd_clear(),
-        Command::Echo(text) => cmd_echo(text),
-        Command::Version => cmd_version(),
-        Command::MemInfo => cmd_meminfo(),
-        Command::Halt => cmd_halt(),
+        Command::Help(name) => cmd_help(name, out),
+        Command::Clear => cmd_clear(out),
+        Command::Echo(text) => cmd_echo(text, out),
+        Command::Version => cmd_version(out),
+        Command::Features => cmd_features(out),
+        Command::HwInfo => cmd_hwinfo(out),
+        Command::CpuInfo => cmd_cpuinfo(out),
+        Command::Date => cmd_date(out),
+        Command::Random(count) => cmd_random(count, out),
+        Command::MemInfo => cmd_meminfo(out),
+        Command::MemMap => cmd_memmap(out),
+        Command::FrameStats => cmd_framestats(out),
+        Command::SlabInfo => cmd_slabinfo(out),
+        Command::Krefs => cmd_krefs(out),
+        Command::PageCheck => cmd_pagecheck(out),
+        Command::Ksym(addr) => cmd_ksym(addr, out),
+        Command::IrqStats => cmd_irqstats(out),
+        Command::IrqStat => cmd_irqstat(out),
+        Command::KbdRate(delay, rate) => cmd_kbdrate(delay, rate, out),
+        Command::SetKeymap(name) => cmd_setkeymap(name, out),
+        Command::Lspci => cmd_lspci(out),
+        #[cfg(feature = "net")]
+        Command::NetInfo => cmd_netinfo(out),
+        #[cfg(feature = "net")]
+        Command::Ping(ip) => cmd_ping(ip, out),
+        #[cfg(feature = "net")]
+        Command::UdpSend(ip, port, text) => cmd_udpsend(ip, port, text, out),
+        #[cfg(feature = "net")]
+        Command::UdpListen(port) => cmd_udplisten(port, out),
+        #[cfg(not(feature = "net"))]
+        Command::NetInfo | Command::Ping(_) | Command::UdpSend(..) | Command::UdpListen(_) => {
+            out.error(format_args!("Networking is not built into this kernel"));
+        }
+        Command::LatStat(arg) => cmd_latstat(arg, out),
+        Command::Dmesg(level) => cmd_dmesg(level, out),
+        Command::Ls(path) => cmd_ls(path, out),
+        Command::Cat(path) => cmd_cat(path, out),
+        Command::Write(path, text) => cmd_write(path, text, out),
+        Command::Mkdir(path) => cmd_mkdir(path, out),
+        Command::Rm(path) => cmd_rm(path, out),
+        Command::Sync => cmd_sync(out),
+        Command::Exec(name) => cmd_exec(name, out),
+        Command::Ports => cmd_ports(out),
+        Command::Grep(pattern) => cmd_grep(pattern, input, out),
+        Command::Run(path, keep_going) => cmd_run(path, keep_going, out),
+        Command::Replay(path) => cmd_replay(path, out),
+        Command::Shutdown => cmd_shutdown(out),
+        Command::Reboot => cmd_reboot(out),
+        Command::Halt => cmd_halt(out),
     }
 }
 
-fn cmd_help() {
-    println!("Available commands:");
-    println!("  help      - Show this help message");
-    println!("  clear     - Clear the screen");
-    println!("  echo TEXT - Print text to screen");
-    println!("  version   - Show kernel version");
-    println!("  meminfo   - Display memory information");
-    println!("  halt      - Halt the system");
+fn cmd_help(name: &str, out: &mut Output) {
+    if name.is_empty() {
+        out.paged(|out| {
+            writeln!(out, "Available commands:");
+            for command in COMMANDS {
+                writeln!(out, "  {:<9} - {}", command.usage, command.summary);
+            }
+            writeln!(out);
+            writeln!(out, "CMD1 | CMD2 feeds CMD1's output to CMD2; CMD > FILE writes it to");
+            writeln!(out, "FILE and CMD >> FILE appends. 'help COMMAND' shows examples.");
+        });
+        return;
+    }
+
+    let Some(command) = help::find(name) else {
+        out.error(format_args!("help: {}: no such command", name));
+        return;
+    };
+    writeln!(out, "Usage: {}", command.usage);
+    writeln!(out, "{}", command.summary);
+    if !command.examples.is_empty() {
+        writeln!(out);
+        writeln!(out, "Examples:");
+        for example in command.examples {
+            writeln!(out, "  {}", example);
+        }
+    }
 }
 
-fn cmd_clear() {
+fn cmd_clear(_out: &mut Output) {
     drivers::vga::clear_screen();
 }
 
-fn cmd_echo(text: &str) {
-    println!("{}", text);
+fn cmd_echo(text: &str, out: &mut Output) {
+    writeln!(out, "{}", text);
+}
+
+fn cmd_version(out: &mut Output) {
+    writeln!(out, "wflos - Rust Microkernel OS");
+    writeln!(out, "Version 0.4.0 (Phase 4: Command-Line Interface)");
+    writeln!(out, "Built with Rust on Apple Silicon M1 for x86_64");
+    writeln!(out, "Syscall ABI version {}", abi::ABI_VERSION);
+    writeln!(out);
+    writeln!(out, "Features:");
+    writeln!(out, "  - Cross-compilation (ARM64 -> x86_64)");
+    writeln!(out, "  - Limine bootloader protocol");
+    writeln!(out, "  - VGA text mode driver");
+    writeln!(out, "  - Serial port debugging");
+    writeln!(out, "  - GDT and IDT configured");
+    writeln!(out, "  - Physical frame allocator");
+    writeln!(out, "  - PS/2 keyboard input");
+    writeln!(out, "  - Interactive shell");
+}
+
+fn cmd_features(out: &mut Output) {
+    for &(name, description, enabled) in features::FEATURES {
+        writeln!(out, "  [{}] {:<10} {}", if enabled { "x" } else { " " }, name, description);
+    }
+}
+
+fn cmd_hwinfo(out: &mut Output) {
+    if hwinfo::with_summary(|summary| write!(out, "{}", summary)).is_none() {
+        out.error(format_args!("No hardware summary was taken at boot"));
+    }
+}
+
+fn cmd_cpuinfo(out: &mut Output) {
+    let cpu = cpuid::info();
+    writeln!(out, "CPU:    {}", cpu.brand().unwrap_or("(no brand string)"));
+    writeln!(out, "        {} family {} model {} stepping {}", cpu.vendor(), cpu.family(), cpu.model(), cpu.stepping());
+    match cpu.tsc_hz() {
+        Some(hz) => writeln!(out, "TSC:    {} MHz (stated by CPUID)", hz / 1_000_000),
+        None => writeln!(out, "TSC:    frequency not stated"),
+    }
+    writeln!(out, "Checked features:");
+    for (name, supported) in cpu.cpu_features().named() {
+        writeln!(out, "  {:<8} {}", name, if supported { "yes" } else { "no" });
+    }
+    // The MSR says whether paging actually uses it, not just the CPU
+    writeln!(out, "NX enforced: {}", if Arch::nx_enabled() { "yes" } else { "no" });
 }
 
-fn cmd_version() {
-    println!("wflos - Rust Microkernel OS");
-    println!("Version 0.4.0 (Phase 4: Command-Line Interface)");
-    println!("Built with Rust on Apple Silicon M1 for x86_64");
-    println!();
-    println!("Features:");
-    println!("  - Cross-compilation (ARM64 -> x86_64)");
-    println!("  - Limine bootloader protocol");
-    println!("  - VGA text mode driver");
-    println!("  - Serial port debugging");
-    println!("  - GDT and IDT configured");
-    println!("  - Physical frame allocator");
-    println!("  - PS/2 keyboard input");
-    println!("  - Interactive shell");
+fn cmd_date(out: &mut Output) {
+    match time::now() {
+        Some(now) => writeln!(out, "{} {} UTC", now.weekday(), now),
+        None => out.error(format_args!("date: wall-clock time unknown (RTC unreadable at boot)")),
+    }
 }
 
-fn cmd_meminfo() {
+fn cmd_random(count: &str, out: &mut Output) {
+    const DEFAULT: usize = 16;
+    const MAX: usize = 256;
+    const PER_LINE: usize = 32;
+
+    let count = match count {
+        "" => DEFAULT,
+        _ => match count.parse::<usize>() {
+            Ok(count) if (1..=MAX).contains(&count) => count,
+            _ => {
+                out.error(format_args!("Usage: random [BYTES] (1 to {})", MAX));
+                return;
+            }
+        },
+    };
+    let mut bytes = [0u8; MAX];
+    let bytes = &mut bytes[..count];
+    rand::fill(bytes);
+    for line in bytes.chunks(PER_LINE) {
+        for byte in line {
+            write!(out, "{:02x}", byte);
+        }
+        writeln!(out);
+    }
+}
+
+fn cmd_meminfo(out: &mut Output) {
     let (total, used, free) = memory::frame_allocator::stats();
 
-    println!("Physical Memory:");
-    println!("  Total frames: {} ({} KB)", total, total * 4);
-    println!("  Used frames:  {} ({} KB)", used, used * 4);
-    println!("  Free frames:  {} ({} KB)", free, free * 4);
-    println!("  Frame size: 4 KB");
+    writeln!(out, "Physical Memory:");
+    writeln!(out, "  Total frames: {} ({} KB)", total, total * 4);
+    writeln!(out, "  Used frames:  {} ({} KB)", used, used * 4);
+    writeln!(out, "  Free frames:  {} ({} KB)", free, free * 4);
+    writeln!(out, "  Frame size: 4 KB");
+    writeln!(out, "  Pre-zeroed:   {}", memory::zero_pool::pooled_frames());
+    for (zone, frames, free) in memory::frame_allocator::zone_stats() {
+        if frames > 0 {
+            writeln!(out, "  Zone {:<16} {} frames, {} free", zone.name(), frames, free);
+        }
+    }
 
     if let Some((heap_total, heap_used, heap_free)) = memory::heap::stats() {
-        println!();
-        println!("Heap:");
-        println!("  Total: {} bytes ({} KB)", heap_total, heap_total / 1024);
-        println!("  Used:  {} bytes", heap_used);
-        println!("  Free:  {} bytes", heap_free);
+        writeln!(out);
+        writeln!(out, "Heap:");
+        writeln!(out, "  Total: {} bytes ({} KB)", heap_total, heap_total / 1024);
+        writeln!(out, "  Used:  {} bytes", heap_used);
+        writeln!(out, "  Free:  {} bytes", heap_free);
+
+        let mags = memory::heap::magazine_stats();
+        writeln!(out, "  Magazine hits:    {}", mags.hits);
+        writeln!(out, "  Magazine refills: {}", mags.refills);
+        writeln!(out, "  Magazine flushes: {}", mags.flushes);
+        writeln!(out, "  Magazine cached:  {} bytes", mags.cached_bytes);
+        writeln!(out, "  Heap lock: {} acquisitions, {} contended",
+            mags.lock_acquisitions, mags.lock_contended);
     }
+
+    let oom = memory::oom::stats();
+    writeln!(out);
+    writeln!(out, "Out of memory:");
+    writeln!(out, "  Heap reclaims:  {}", oom.heap_reclaims);
+    writeln!(out, "  Frame reclaims: {}", oom.frame_reclaims);
+    writeln!(out, "  Programs ended: {}", oom.kills);
 }
 
-fn cmd_halt() {
-    println!("Halting system...");
-    println!("You can close QEMU or press Ctrl+A then X to exit.");
+fn cmd_memmap(out: &mut Output) {
+    let memory_map = bootinfo::get().memory_map();
+    if memory_map.is_empty() {
+        out.error(format_args!("No memory map from bootloader"));
+        return;
+    }
+
+    writeln!(out, "Range                       Size       Type");
+    let mut totals = [0u64; 9];
+    for entry in memory_map {
+        let end = entry.end();
+        write!(out, "{:#012x}-{:#012x} {:>8} KB {}",
+            entry.base, end, entry.length / 1024, limine::memmap_type_name(entry.entry_type));
+
+        let usage = memory::frame_allocator::range_usage(memory::PhysAddr::new(entry.base), memory::PhysAddr::new(end));
+        match (entry.entry_type, usage) {
+            (_, Some((frames, used))) => {
+                write!(out, " ({}/{} frames used", used, frames);
+                let unmanaged = (entry.length / 4096) as usize - frames;
+                if unmanaged > 0 {
+                    write!(out, ", {} reserved", unmanaged);
+                }
+                write!(out, ")");
+            }
+            (limine::LIMINE_MEMMAP_USABLE, None) => write!(out, " (not managed)"),
+            (limine::LIMINE_MEMMAP_BOOTLOADER_RECLAIMABLE, None) => write!(out, " (not reclaimed)"),
+            _ => {}
+        }
+        writeln!(out);
+
+        totals[(entry.entry_type as usize).min(totals.len() - 1)] += entry.length;
+    }
+
+    writeln!(out);
+    for (entry_type, &bytes) in totals.iter().enumerate() {
+        if bytes > 0 {
+            writeln!(out, "  {:<24} {} KB", limine::memmap_type_name(entry_type as u64), bytes / 1024);
+        }
+    }
+
+    let (total, used, free) = memory::frame_allocator::stats();
+    writeln!(out);
+    writeln!(out, "Frame allocator: {} frames managed, {} used, {} free", total, used, free);
+    let reserved = memory::frame_allocator::reserved_frames();
+    if reserved > 0 {
+        writeln!(out, "  {} usable frames reserved: another entry overlaps them", reserved);
+    }
+}
+
+fn cmd_framestats(out: &mut Output) {
+    let stats = match memory::frame_allocator::owner_stats() {
+        Some(stats) => stats,
+        None => {
+            out.error(format_args!("Frame ownership tracking needs the `debugging` feature"));
+            return;
+        }
+    };
+
+    let (_, used, _) = memory::frame_allocator::stats();
+    let mut tagged = 0;
+    for (owner, frames) in stats {
+        writeln!(out, "  {:<12} {:>6} frames ({} KB)", owner.name(), frames, frames * 4);
+        tagged += frames;
+    }
+    writeln!(out, "  {:<12} {:>6} frames ({} KB)", "untagged", used - tagged, (used - tagged) * 4);
+}
+
+fn cmd_slabinfo(out: &mut Output) {
+    writeln!(out, "Cache      Size   Live   Peak  Slabs  Empty    Allocs     Frees");
+    for cache in memory::slab::stats() {
+        writeln!(out, "{:<9} {:>5} {:>6} {:>6} {:>6} {:>6} {:>9} {:>9}",
+            cache.name, cache.object_size, cache.live, cache.peak,
+            cache.slabs, cache.empty_slabs, cache.allocs, cache.frees);
+    }
+}
+
+fn cmd_krefs(out: &mut Output) {
+    match kref::live_counts() {
+        Some(counts) => {
+            for (kind, count) in counts {
+                writeln!(out, "  {:<12} {:>6}", kind.name(), count);
+            }
+        }
+        None => out.error(format_args!("Object counting needs the `debugging` feature")),
+    }
+}
+
+fn cmd_pagecheck(out: &mut Output) {
+    let report = memory::pagecheck::check(Arch::active_table());
+
+    writeln!(out, "Walked {} tables, {} mappings", report.tables, report.mappings);
+    if report.nx_enforced {
+        print_finding("Writable+executable pages", report.writable_executable, out);
+    } else {
+        writeln!(out, "  NX is disabled, W^X not checked");
+    }
+    print_finding("Dangling entries", report.dangling, out);
+    print_finding("HHDM gaps (physical)", report.hhdm_missing, out);
+    if report.is_clean() {
+        writeln!(out, "OK");
+    } else {
+        out.error(format_args!("FAILED"));
+    }
+}
+
+fn print_finding(label: &str, finding: memory::pagecheck::Finding, out: &mut Output) {
+    match finding.first {
+        Some(addr) => writeln!(out, "  {:<26} {} (first at {:#x})", label, finding.count, addr),
+        None => writeln!(out, "  {:<26} 0", label),
+    }
+}
+
+fn cmd_ksym(addr: &str, out: &mut Output) {
+    let hex = addr.strip_prefix("0x").unwrap_or(addr);
+    let Ok(addr) = u64::from_str_radix(hex, 16) else {
+        out.error(format_args!("Usage: ksym ADDR"));
+        return;
+    };
+    if symbols::table().is_none() {
+        out.error(format_args!("No kernel symbol table"));
+        return;
+    }
+    match symbols::lookup(addr) {
+        Some((name, offset)) => writeln!(out, "{:#x} = {}+{:#x}", addr, Demangled(name), offset),
+        None => out.error(format_args!("No kernel symbol at {:#x}", addr)),
+    }
+}
+
+fn cmd_irqstats(out: &mut Output) {
+    writeln!(out, "IRQ lines:");
+    for line in interrupts::stats() {
+        writeln!(
+            out,
+            "  IRQ {:<2}  {} interrupts, {} unclaimed, {} handler(s)",
+            line.irq, line.interrupts, line.unclaimed, line.handlers
+        );
+    }
+
+    let kbd = drivers::keyboard::stats();
+
+    writeln!(out, "Keyboard (IRQ 1, scan code set {}):", kbd.scan_code_set);
+    writeln!(out, "  Interrupts: {}", kbd.interrupts);
+    writeln!(out, "  Dropped:    {}", kbd.dropped);
+    writeln!(out, "  Buffered:   {} / {} (peak {})", kbd.buffered, kbd.capacity, kbd.high_water);
+
+    let serial = drivers::serial::stats();
+    match serial.settings {
+        Some(settings) => writeln!(out, "Serial COM{} (IRQ {}, {}):", serial.port, serial.irq, settings),
+        None => writeln!(out, "Serial COM{} (IRQ {}, not answering):", serial.port, serial.irq),
+    }
+    writeln!(out, "  Interrupts: {}", serial.interrupts);
+    writeln!(out, "  Dropped:    {}", serial.dropped);
+    writeln!(out, "  Buffered:   {} / {}", serial.buffered, serial.capacity);
+    writeln!(out, "  TX refills: {} ({} waits for room)", serial.tx_interrupts, serial.tx_waits);
+    writeln!(out, "  TX queued:  {} / {}", serial.tx_queued, serial.tx_capacity);
+
+    let (timer, idle) = time::wakeup_stats();
+    writeln!(out, "Timer ({} Hz tick, stretched when idle):", time::TICK_HZ);
+    writeln!(out, "  Interrupts:   {} ({} in the last second)", timer.total(), timer.last_window());
+    writeln!(out, "  Idle wakeups: {} ({} in the last second)", idle.total(), idle.last_window());
+}
+
+fn cmd_irqstat(out: &mut Output) {
+    use crate::arch::x86_64::interrupts::{self, VectorUse};
+
+    writeln!(out, "Vector  {:>10}  Use", "Count");
+    for (vector, count) in interrupts::vector_counts() {
+        writeln!(out, "  {:>4}  {:>10}  {}", vector, count, VectorUse::of(vector));
+    }
+    let (pic, lapic) = interrupts::spurious_counts();
+    writeln!(out, "Spurious: {} from the PIC, {} from the LAPIC", pic, lapic);
+}
+
+fn cmd_kbdrate(delay: &str, rate: &str, out: &mut Output) {
+    if delay.is_empty() {
+        writeln!(out, "{}", drivers::keyboard::typematic());
+        return;
+    }
+    let (Ok(delay), Ok(rate)) = (delay.parse(), rate.parse()) else {
+        out.error(format_args!("Usage: kbdrate [DELAY RATE]"));
+        return;
+    };
+    let result = shared::keyboard::Typematic::new(delay, rate).and_then(drivers::keyboard::set_typematic);
+    match result {
+        Ok(()) => writeln!(out, "{}", drivers::keyboard::typematic()),
+        Err(e) => out.error(format_args!("kbdrate: {}", e)),
+    }
+}
+
+fn cmd_setkeymap(name: &str, out: &mut Output) {
+    if name.is_empty() {
+        let current = drivers::keyboard::keymap();
+        for keymap in shared::keymap::KEYMAPS {
+            let mark = if keymap == current { '*' } else { ' ' };
+            writeln!(out, "{} {:<8} {}", mark, keymap.name, keymap.description);
+        }
+        return;
+    }
+    match shared::keymap::by_name(name) {
+        Some(keymap) => {
+            drivers::keyboard::set_keymap(keymap);
+            writeln!(out, "Keyboard layout: {}", keymap.description);
+        }
+        None => out.error(format_args!("setkeymap: unknown layout '{}' (see setkeymap)", name)),
+    }
+}
 
+fn cmd_lspci(out: &mut Output) {
+    for dev in drivers::pci::devices() {
+        write!(out, "  {:02x}:{:02x}.{} {:04x}:{:04x} {}", dev.bus, dev.device, dev.function, dev.vendor_id, dev.device_id, dev.class_name());
+        match dev.driver() {
+            Some(driver) => writeln!(out, " [{}]", driver),
+            None => writeln!(out),
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn cmd_netinfo(out: &mut Output) {
+    let mac = match crate::net::mac() {
+        Some(mac) => mac,
+        None => {
+            out.error(format_args!("No network device"));
+            return;
+        }
+    };
+    writeln!(out, "MAC:     {}", mac);
+    writeln!(out, "IP:      {}/{}", crate::net::LOCAL_IP, crate::net::NETMASK.to_u32().count_ones());
+    writeln!(out, "Gateway: {}", crate::net::GATEWAY);
+    writeln!(out, "Echo replies sent: {}", crate::net::icmp::echo_replies_sent());
+    writeln!(out, "ARP cache:");
+    for (ip, mac) in crate::net::arp::entries() {
+        writeln!(out, "  {:<15} {}", ip, mac);
+    }
+}
+
+#[cfg(feature = "net")]
+fn cmd_ping(ip: &str, out: &mut Output) {
+    const COUNT: u16 = 4;
+    const TIMEOUT_MS: u64 = 1000;
+
+    let dst = match shared::net::Ipv4Addr::parse(ip) {
+        Some(dst) => dst,
+        None => {
+            out.error(format_args!("Usage: ping IP"));
+            return;
+        }
+    };
+    let mut received = 0;
+    for seq in 1..=COUNT {
+        match crate::net::icmp::ping(dst, seq, TIMEOUT_MS) {
+            Ok(ms) => {
+                writeln!(out, "Reply from {}: seq={} time={} ms", dst, seq, ms);
+                received += 1;
+            }
+            Err(e) => writeln!(out, "{}: seq={} {}", dst, seq, e),
+        }
+    }
+    if received > 0 {
+        writeln!(out, "{} sent, {} received", COUNT, received);
+    } else {
+        out.error(format_args!("{} sent, 0 received", COUNT));
+    }
+}
+
+#[cfg(feature = "net")]
+fn cmd_udpsend(ip: &str, port: &str, text: &str, out: &mut Output) {
+    let (dst, port) = match (shared::net::Ipv4Addr::parse(ip), port.parse::<u16>()) {
+        (Some(dst), Ok(port)) if port != 0 => (dst, port),
+        _ => {
+            out.error(format_args!("Usage: udpsend IP PORT TEXT"));
+            return;
+        }
+    };
+    let result = crate::net::udp::bind(0).and_then(|socket| socket.send_to(text.as_bytes(), dst, port));
+    match result {
+        Ok(()) => writeln!(out, "Sent {} bytes to {}:{}", text.len(), dst, port),
+        Err(e) => out.error(format_args!("Error: {}", e)),
+    }
+}
+
+#[cfg(feature = "net")]
+fn cmd_udplisten(port: &str, out: &mut Output) {
+    let socket = match port.parse::<u16>() {
+        Ok(port) if port != 0 => match crate::net::udp::bind(port) {
+            Ok(socket) => socket,
+            Err(e) => {
+                out.error(format_args!("Error: {}", e));
+                return;
+            }
+        },
+        _ => {
+            out.error(format_args!("Usage: udplisten PORT"));
+            return;
+        }
+    };
+    writeln!(out, "Listening on UDP port {}, press any key to stop", socket.local_port());
+
+    let mut buf = [0u8; crate::net::udp::MAX_PAYLOAD];
+    while !drivers::console::try_read_key().is_some_and(|key| key.pressed) {
+        drivers::virtio::net::poll();
+        while let Some((len, src, src_port)) = socket.recv_from(&mut buf) {
+            let text = core::str::from_utf8(&buf[..len]).unwrap_or("<binary>");
+            writeln!(out, "{}:{} ({} bytes): {}", src, src_port, len, text);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn cmd_latstat(arg: &str, out: &mut Output) {
+    match arg {
+        "" => {}
+        "reset" => {
+            time::reset_latency();
+            writeln!(out, "Latency statistics cleared");
+            return;
+        }
+        _ => {
+            out.error(format_args!("Usage: latstat [reset]"));
+            return;
+        }
+    }
+
+    let hist = time::tick_latency();
+    writeln!(out, "Timer tick latency ({} ticks, {} Hz, uptime {} ms):", time::ticks(), time::TICK_HZ, time::uptime_ms());
+    match time::clock_source() {
+        (ClockSource::Cycles, hz, stated) => {
+            let how = if stated { "stated" } else { "measured" };
+            writeln!(out, "  Clock: cycle counter at {} MHz ({}), {} ns", hz / 1_000_000, how, time::monotonic_ns())
+        }
+        (ClockSource::Tick, _, _) => writeln!(out, "  Clock: tick timer, {} ns", time::monotonic_ns()),
+    }
+    let (min, max, mean, p99) = match (hist.min(), hist.max(), hist.mean(), hist.percentile(99)) {
+        (Some(min), Some(max), Some(mean), Some(p99)) => (min, max, mean, p99),
+        _ => {
+            writeln!(out, "  No samples yet");
+            return;
+        }
+    };
+    writeln!(out, "  min {} us, mean {} us, p99 <= {} us, max {} us", min, mean, p99, max);
+
+    const BAR_WIDTH: u64 = 40;
+    let peak = hist.buckets().map(|(_, count)| count).max().unwrap_or(1);
+    for (floor, count) in hist.buckets() {
+        let bar = (count * BAR_WIDTH).div_ceil(peak) as usize;
+        writeln!(out, "  >= {:>7} us {:>8} {}", floor, count, "#".repeat(bar));
+    }
+}
+
+fn cmd_dmesg(level: &str, out: &mut Output) {
+    let min = if level.is_empty() {
+        Level::Trace
+    } else {
+        match Level::parse(level) {
+            Some(level) => level,
+            None => {
+                out.error(format_args!("Usage: dmesg [trace|debug|info|warn|error]"));
+                return;
+            }
+        }
+    };
+
+    let (mut seq, end) = log::bounds();
+    if seq > 0 {
+        writeln!(out, "({} older messages overwritten)", seq);
+    }
+    // Messages logged while this prints are left for the next dmesg
+    while seq < end {
+        match log::message(seq) {
+            Some(message) if message.level >= min => out.colored(log::color(message.level), |out| {
+                writeln!(
+                    out,
+                    "{} {:<5} {}",
+                    Timestamp(message.timestamp_ns),
+                    message.level.as_str(),
+                    message.text.as_str()
+                )
+            }),
+            Some(_) => {}
+            // Overwritten since: skip ahead to the oldest one left
+            None => seq = seq.max(log::bounds().0.saturating_sub(1)),
+        }
+        seq += 1;
+    }
+}
+
+fn cmd_ls(path: &str, out: &mut Output) {
+    let path = if path.is_empty() { "/" } else { path };
+    match vfs::readdir(path) {
+        Ok(entries) => {
+            for entry in entries {
+                match entry.kind {
+                    InodeKind::Directory => writeln!(out, "  {}/", entry.name),
+                    InodeKind::File => writeln!(out, "  {} ({} bytes)", entry.name, entry.size),
+                }
+            }
+        }
+        Err(e) => out.error(format_args!("ls: {}: {}", path, e.as_str())),
+    }
+}
+
+fn cmd_cat(path: &str, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: cat PATH"));
+        return;
+    }
+
+    let mut file = match vfs::open(path, OpenFlags::READ) {
+        Ok(file) => file,
+        Err(e) => {
+            out.error(format_args!("cat: {}: {}", path, e.as_str()));
+            return;
+        }
+    };
+
+    let mut buf = [0u8; 256];
     loop {
-        unsafe {
-            core::arch::asm!("hlt");
+        match vfs::read(&mut file, &mut buf) {
+            Ok(0) => break,
+            Ok(count) => {
+                for chunk in buf[..count].utf8_chunks() {
+                    write!(out, "{}", chunk.valid());
+                }
+            }
+            Err(e) => {
+                out.error(format_args!("cat: {}: {}", path, e.as_str()));
+                return;
+            }
+        }
+    }
+}
+
+fn cmd_write(path: &str, text: &str, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: write PATH TEXT"));
+        return;
+    }
+
+    let result = vfs::open(path, OpenFlags::WRITE).and_then(|mut file| {
+        vfs::write(&mut file, text.as_bytes())?;
+        vfs::write(&mut file, b"\n")
+    });
+    if let Err(e) = result {
+        out.error(format_args!("write: {}: {}", path, e.as_str()));
+    }
+}
+
+fn cmd_mkdir(path: &str, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: mkdir PATH"));
+        return;
+    }
+    if let Err(e) = vfs::mkdir(path) {
+        out.error(format_args!("mkdir: {}: {}", path, e.as_str()));
+    }
+}
+
+fn cmd_rm(path: &str, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: rm PATH"));
+        return;
+    }
+    if let Err(e) = vfs::remove(path) {
+        out.error(format_args!("rm: {}: {}", path, e.as_str()));
+    }
+}
+
+fn cmd_sync(out: &mut Output) {
+    if let Err(e) = vfs::sync() {
+        out.error(format_args!("sync: {}", e.as_str()));
+    }
+}
+
+fn cmd_exec(name: &str, out: &mut Output) {
+    // A file on a mounted filesystem, paged in as it runs
+    if name.starts_with('/') && vfs::resolve(name).is_ok() {
+        let leaks = kref::LeakCheck::start();
+        report_exit(name, process::run_file(name), out);
+        leaks.finish("exec");
+        return;
+    }
+
+    let modules = bootinfo::get().modules();
+    if modules.is_empty() {
+        out.error(format_args!("No boot modules loaded"));
+        return;
+    }
+
+    if name.is_empty() {
+        writeln!(out, "Boot modules:");
+        for module in modules {
+            writeln!(out, "  {} ({} bytes)", module.path(), module.size);
         }
+        return;
+    }
+
+    match bootinfo::get().module(name) {
+        Some(module) => report_exit(name, process::run_image(module.data()), out),
+        None => out.error(format_args!("exec: no boot module named '{}'", name)),
+    }
+}
+
+fn cmd_ports(out: &mut Output) {
+    let ports = ipc::ports();
+    if ports.is_empty() {
+        writeln!(out, "No message ports");
+        return;
     }
+    writeln!(out, "  {:>6}  {:<20} {:<8} {:>6} {:>8} {:>8}", "ID", "NAME", "OWNER", "QUEUED", "SENT", "RECEIVED");
+    for port in ports {
+        writeln!(
+            out,
+            "  {:>6}  {:<20} {:<8} {:>6} {:>8} {:>8}",
+            port.id,
+            port.name(),
+            port.owner.as_str(),
+            port.queued,
+            port.sent,
+            port.received
+        );
+    }
+}
+
+fn report_exit(name: &str, result: Result<i64, &'static str>, out: &mut Output) {
+    match result {
+        Ok(0) => writeln!(out, "[{} exited with code 0]", name),
+        Ok(code) => out.error(format_args!("[{} exited with code {}]", name, code)),
+        Err(e) => out.error(format_args!("exec: {}", e)),
+    }
+}
+
+fn cmd_grep(pattern: &str, input: Option<&str>, out: &mut Output) {
+    let input = match input {
+        Some(input) if !pattern.is_empty() => input,
+        _ => {
+            out.error(format_args!("Usage: COMMAND | grep TEXT"));
+            return;
+        }
+    };
+    let mut matched = false;
+    for line in input.lines().filter(|line| line.contains(pattern)) {
+        writeln!(out, "{}", line);
+        matched = true;
+    }
+    if !matched {
+        // No output, but a script checking for the text should stop
+        out.error(format_args!("grep: no lines contain '{}'", pattern));
+    }
+}
+
+fn cmd_run(path: &str, keep_going: bool, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: run [-k] PATH"));
+        return;
+    }
+    if !run_script(path, keep_going, out) && keep_going {
+        // Each failing line already reported itself; this makes `run` fail too
+        out.error(format_args!("run: {}: some lines failed", path));
+    }
+}
+
+fn cmd_replay(path: &str, out: &mut Output) {
+    if path.is_empty() {
+        out.error(format_args!("Usage: replay PATH"));
+        return;
+    }
+    let script = match read_file(path) {
+        Ok(script) => script,
+        Err(e) => {
+            out.error(format_args!("replay: {}: {}", path, e));
+            return;
+        }
+    };
+
+    // Encode the whole script first, so a bad line types nothing
+    let mut scancodes = Vec::new();
+    match replay::encode(&script, drivers::keyboard::keymap(), &mut |byte| scancodes.push(byte)) {
+        Ok(()) => input::inject(&scancodes),
+        Err((line, e)) => out.error(format_args!("replay: {}:{}: {}", path, line, e)),
+    }
+}
+
+fn cmd_shutdown(out: &mut Output) {
+    cmd_sync(out);
+    writeln!(out, "Powering off...");
+    power::shutdown();
+}
+
+fn cmd_reboot(out: &mut Output) {
+    cmd_sync(out);
+    writeln!(out, "Rebooting...");
+    power::reboot();
+}
+
+fn cmd_halt(out: &mut Output) {
+    writeln!(out, "Halting system...");
+    writeln!(out, "You can close QEMU or press Ctrl+A then X to exit.");
+    watchdog::stop();
+
+    Arch::halt();
 }
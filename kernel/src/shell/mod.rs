@@ -1,17 +1,41 @@
 //! Shell REPL (Read-Eval-Print Loop)
 //! Provides interactive command-line interface
 
-pub mod parser;
 pub mod commands;
+pub mod output;
 
-use crate::drivers;
+use crate::drivers::{self, console::Console};
+use crate::fs::vfs::OpenFlags;
+use crate::fs::vfs::{self, InodeKind};
+use crate::{memory, time};
 use crate::{print, println};
+use output::Output;
+use shared::shell::{parse_pipeline, Command, Completer, LineEditor, LineEvent};
 
 const PROMPT: &str = "wflos> ";
 const MAX_LINE_LENGTH: usize = 128;
+/// How often an idle shell polls the network device, and how late it may be
+#[cfg(feature = "net")]
+const NET_POLL_MS: u64 = 10;
+#[cfg(feature = "net")]
+const NET_POLL_SLACK_MS: u64 = 40;
+/// Script run once when the shell starts, if it exists
+const RC_PATH: &str = "/etc/rc";
+/// Key replay script typed at the first prompt, if it exists (see `replay`)
+const KEYS_PATH: &str = "/etc/keys";
 
-// Static line buffer to avoid stack overflow
-static mut LINE_BUFFER: [u8; MAX_LINE_LENGTH] = [0; MAX_LINE_LENGTH];
+/// Completes paths from the mounted filesystems
+struct VfsPaths;
+
+impl Completer for VfsPaths {
+    fn list_dir(&mut self, dir: &str, each: &mut dyn FnMut(&str, bool)) {
+        if let Ok(entries) = vfs::readdir(dir) {
+            for entry in entries {
+                each(&entry.name, entry.kind == InodeKind::Directory);
+            }
+        }
+    }
+}
 
 /// Run the shell REPL
 pub fn run() -> ! {
@@ -20,64 +44,61 @@ pub fn run() -> ! {
     println!("Type 'help' for available commands");
     println!();
 
+    if vfs::open(RC_PATH, OpenFlags::READ).is_ok() {
+        println!("Running {}", RC_PATH);
+        commands::run_script(RC_PATH, false, &mut Output::console());
+    }
+    if vfs::open(KEYS_PATH, OpenFlags::READ).is_ok() {
+        println!("Replaying {}", KEYS_PATH);
+        commands::execute(Command::Replay(KEYS_PATH), None, &mut Output::console());
+    }
+
+    let mut editor = LineEditor::<MAX_LINE_LENGTH>::new();
+
     loop {
         // Display prompt
         print!("{}", PROMPT);
 
         // Read line
-        let mut line_pos = 0;
+        editor.clear();
         loop {
-            if let Some(key) = drivers::keyboard::read_key() {
-                match key {
-                    '\n' => {
-                        // Enter pressed
-                        println!();
-                        break;
-                    }
-                    '\x08' => {
-                        // Backspace
-                        if line_pos > 0 {
-                            line_pos -= 1;
-                            // Erase character: backspace, space, backspace
-                            print!("\x08 \x08");
+            if let Some(key) = drivers::console::try_read_key() {
+                match editor.feed(key, &mut Console) {
+                    LineEvent::Submit | LineEvent::Cancel => break,
+                    LineEvent::Complete => {
+                        let listed = editor.complete(&mut VfsPaths, &mut Console);
+                        if listed {
+                            // Candidates were listed below: redraw the prompt
+                            print!("{}", PROMPT);
+                            editor.redraw(&mut Console);
                         }
                     }
-                    '\x1B' => {
-                        // ESC - clear line
-                        while line_pos > 0 {
-                            print!("\x08 \x08");
-                            line_pos -= 1;
-                        }
-                    }
-                    '\t' => {
-                        // Tab - ignore for now
-                    }
-                    c if c.is_ascii_graphic() || c == ' ' => {
-                        // Printable character
-                        if line_pos < MAX_LINE_LENGTH {
-                            unsafe {
-                                LINE_BUFFER[line_pos] = c as u8;
-                            }
-                            line_pos += 1;
-                            print!("{}", c);
-                        }
-                    }
-                    _ => {
-                        // Ignore other characters
-                    }
+                    LineEvent::Pending => {}
+                }
+            } else {
+                // Nothing typed: use the time to top up the zeroed frame pool
+                if memory::zero_pool::idle_work() {
+                    continue;
+                }
+                #[cfg(feature = "net")]
+                {
+                    drivers::virtio::net::poll();
+                    // The NIC is polled, so come back for it even with nothing typed
+                    let poll = time::deadline_in(NET_POLL_MS, NET_POLL_SLACK_MS);
+                    time::idle(&[poll], drivers::console::input_pending);
                 }
+                #[cfg(not(feature = "net"))]
+                time::idle(&[], drivers::console::input_pending);
             }
         }
 
         // Parse and execute command
-        if line_pos > 0 {
-            let line = unsafe {
-                core::str::from_utf8(&LINE_BUFFER[..line_pos])
-                    .unwrap_or("")
-            };
-
-            match parser::parse(line) {
-                Ok(cmd) => commands::execute(cmd),
+        let line = editor.line();
+        if !line.is_empty() {
+            match parse_pipeline(line) {
+                Ok(pipeline) => {
+                    commands::run(&pipeline, &mut Output::console());
+                }
                 Err(e) => println!("Error: {}", e),
             }
         }
@@ -0,0 +1,145 @@
+//! Command output
+//! Commands write to an `Output` instead of calling `println!`, so the shell
+//! can send what they print to the screen, into the next command of a
+//! pipeline, or to a file. Failures are reported through `error`, so scripts
+//! can tell a command that failed from one that merely printed something.
+//! Long console output can be shown a screenful at a time with `paged`, and
+//! in color with `colored`.
+
+use crate::drivers::console::{self, ColorGuard};
+use crate::drivers::vga::Color;
+use crate::print;
+use alloc::string::String;
+use core::fmt;
+use shared::shell::{Pager, PagerKey};
+
+/// Most text one command may hand to the next stage or a file
+const MAX_CAPTURE: usize = 64 * 1024;
+/// Lines per page: the 25-row text screen, less the row for the prompt
+const PAGE_LINES: usize = 24;
+const PAGER_PROMPT: &str = "-- More -- (Space: page, Enter: line, q: quit)";
+
+pub struct Output {
+    sink: Sink,
+    /// Messages written with `error`
+    errors: usize,
+}
+
+enum Sink {
+    Console,
+    Paged(Pager),
+    Capture(Capture),
+}
+
+/// Text kept for the next pipeline stage, cut off at `MAX_CAPTURE`
+#[derive(Default)]
+pub struct Capture {
+    pub text: String,
+    pub truncated: bool,
+}
+
+impl fmt::Write for Capture {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_CAPTURE - self.text.len();
+        if s.len() <= room {
+            self.text.push_str(s);
+        } else {
+            let mut end = room;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.text.push_str(&s[..end]);
+            self.truncated = true;
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    pub fn console() -> Self {
+        Output { sink: Sink::Console, errors: 0 }
+    }
+
+    pub fn capture() -> Self {
+        Output { sink: Sink::Capture(Capture::default()), errors: 0 }
+    }
+
+    /// Target of `write!` and `writeln!`
+    /// An inherent method rather than `fmt::Write`, so callers don't have a
+    /// `Result` to discard: output can't fail, only be truncated.
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        match &mut self.sink {
+            Sink::Console => console::_print(args),
+            Sink::Paged(pager) => {
+                let _ = fmt::Write::write_fmt(&mut PagedConsole(pager), args);
+            }
+            Sink::Capture(capture) => {
+                let _ = fmt::Write::write_fmt(capture, args);
+            }
+        }
+    }
+
+    /// Run `f` with console output shown a page at a time
+    /// Output going to a pipe or a file is not paged.
+    pub fn paged(&mut self, f: impl FnOnce(&mut Output)) {
+        if !matches!(self.sink, Sink::Console) {
+            return f(self);
+        }
+        self.sink = Sink::Paged(Pager::new(PAGE_LINES));
+        f(self);
+        self.sink = Sink::Console;
+    }
+
+    /// Run `f` with console output in the `color` foreground (None: unchanged)
+    /// Output going to a pipe or a file stays plain text.
+    pub fn colored(&mut self, color: Option<Color>, f: impl FnOnce(&mut Output)) {
+        let _guard = match (color, &self.sink) {
+            (Some(color), Sink::Console | Sink::Paged(_)) => Some(ColorGuard::foreground(color)),
+            _ => None,
+        };
+        f(self);
+    }
+
+    /// Report that the command failed, with `args` as a line of output
+    pub fn error(&mut self, args: fmt::Arguments) {
+        self.errors += 1;
+        self.write_fmt(format_args!("{}\n", args));
+    }
+
+    /// Failures reported so far; a command failed if this grew while it ran
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// The captured text, if this output was a capture
+    pub fn into_capture(self) -> Option<Capture> {
+        match self.sink {
+            Sink::Console | Sink::Paged(_) => None,
+            Sink::Capture(capture) => Some(capture),
+        }
+    }
+}
+
+/// The console behind a pager
+struct PagedConsole<'a>(&'a mut Pager);
+
+impl fmt::Write for PagedConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s, &mut |line| print!("{}", line), &mut wait_for_key);
+        Ok(())
+    }
+}
+
+/// Show the pager prompt until a key answers it, then erase it
+fn wait_for_key() -> PagerKey {
+    print!("{}", PAGER_PROMPT);
+    let key = loop {
+        if let Some(answer) = PagerKey::from_key(&console::read_key()) {
+            break answer;
+        }
+    };
+    // '\x08' only moves the cursor: back up, blank the prompt, back up again
+    let back = "\x08".repeat(PAGER_PROMPT.len());
+    print!("{0}{1:2$}{0}", back, "", PAGER_PROMPT.len());
+    key
+}
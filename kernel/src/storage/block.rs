@@ -0,0 +1,41 @@
+//! Block device interface
+//! Filesystems address storage in fixed-size blocks through this trait and
+//! never talk to a controller driver directly.
+
+/// A device addressed in fixed-size blocks (sectors)
+pub trait BlockDevice: Send {
+    /// Size of one block in bytes
+    fn block_size(&self) -> usize;
+
+    /// Total number of blocks on the device
+    fn block_count(&self) -> u64;
+
+    /// Read whole blocks starting at `lba`; `buf.len()` must be a multiple of the block size
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    /// Write whole blocks starting at `lba`; `buf.len()` must be a multiple of the block size
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), &'static str>;
+
+    /// Make every completed write durable; devices without a write cache need do nothing
+    fn sync(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Drop cached data that can be read back from the device, returning the
+    /// bytes freed; called when the heap runs out, so it must not allocate
+    fn reclaim(&mut self) -> usize {
+        0
+    }
+}
+
+/// Check that a transfer of `len` bytes at `lba` stays on the device
+pub fn check_range(device: &dyn BlockDevice, lba: u64, len: usize) -> Result<(), &'static str> {
+    if !len.is_multiple_of(device.block_size()) {
+        return Err("Transfer is not a whole number of blocks");
+    }
+    let blocks = (len / device.block_size()) as u64;
+    match lba.checked_add(blocks) {
+        Some(end) if end <= device.block_count() => Ok(()),
+        _ => Err("Block address out of range"),
+    }
+}
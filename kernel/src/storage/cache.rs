@@ -0,0 +1,135 @@
+//! Sector cache
+//! Write-back LRU cache that sits between a filesystem and its block device.
+//! Dirty blocks reach the device when they are evicted or on `sync()`.
+
+use super::block::{self, BlockDevice};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+struct CachedBlock {
+    lba: u64,
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+pub struct SectorCache {
+    device: Box<dyn BlockDevice>,
+    blocks: Vec<CachedBlock>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl SectorCache {
+    /// Cache up to `capacity` blocks of `device`
+    pub fn new(device: Box<dyn BlockDevice>, capacity: usize) -> Self {
+        SectorCache {
+            device,
+            blocks: Vec::with_capacity(capacity),
+            capacity: capacity.max(1),
+            clock: 0,
+        }
+    }
+
+    /// Index of the cached copy of `lba`, marking it most recently used
+    fn find(&mut self, lba: u64) -> Option<usize> {
+        let index = self.blocks.iter().position(|b| b.lba == lba)?;
+        self.clock += 1;
+        self.blocks[index].last_used = self.clock;
+        Some(index)
+    }
+
+    /// Get a slot for `lba`, evicting the least recently used block if full.
+    /// The slot's data is only read from the device when `fill` is set.
+    fn insert(&mut self, lba: u64, fill: bool) -> Result<usize, &'static str> {
+        let index = if self.blocks.len() < self.capacity {
+            let data = vec![0u8; self.device.block_size()].into_boxed_slice();
+            self.blocks.push(CachedBlock { lba, data, dirty: false, last_used: 0 });
+            self.blocks.len() - 1
+        } else {
+            let (index, _) = self.blocks.iter().enumerate()
+                .min_by_key(|(_, b)| b.last_used)
+                .ok_or("Sector cache is empty")?;
+            self.write_back(index)?;
+            index
+        };
+
+        let entry = &mut self.blocks[index];
+        entry.lba = lba;
+        if fill {
+            if let Err(e) = self.device.read_blocks(lba, &mut entry.data) {
+                self.blocks.swap_remove(index);
+                return Err(e);
+            }
+        }
+        self.clock += 1;
+        entry.last_used = self.clock;
+        Ok(index)
+    }
+
+    fn write_back(&mut self, index: usize) -> Result<(), &'static str> {
+        let entry = &mut self.blocks[index];
+        if entry.dirty {
+            self.device.write_blocks(entry.lba, &entry.data)?;
+            entry.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for SectorCache {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        block::check_range(self, lba, buf.len())?;
+        let block_size = self.block_size();
+        for (i, chunk) in buf.chunks_exact_mut(block_size).enumerate() {
+            let lba = lba + i as u64;
+            let index = match self.find(lba) {
+                Some(index) => index,
+                None => self.insert(lba, true)?,
+            };
+            chunk.copy_from_slice(&self.blocks[index].data);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        block::check_range(self, lba, buf.len())?;
+        let block_size = self.block_size();
+        for (i, chunk) in buf.chunks_exact(block_size).enumerate() {
+            let lba = lba + i as u64;
+            // Whole blocks are overwritten, so a miss needs no read first
+            let index = match self.find(lba) {
+                Some(index) => index,
+                None => self.insert(lba, false)?,
+            };
+            let entry = &mut self.blocks[index];
+            entry.data.copy_from_slice(chunk);
+            entry.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Write every dirty block back, then flush the device itself
+    fn sync(&mut self) -> Result<(), &'static str> {
+        for index in 0..self.blocks.len() {
+            self.write_back(index)?;
+        }
+        self.device.sync()
+    }
+
+    /// Drop every clean block; dirty ones stay until they are written back
+    fn reclaim(&mut self) -> usize {
+        let before = self.blocks.len();
+        self.blocks.retain(|block| block.dirty);
+        (before - self.blocks.len()) * self.device.block_size() + self.device.reclaim()
+    }
+}
@@ -0,0 +1,6 @@
+//! Storage devices
+//! Block device interface, the sector cache, and the devices behind them
+
+pub mod block;
+pub mod cache;
+pub mod ramdisk;
@@ -0,0 +1,53 @@
+//! RAM-backed block device
+//! Serves a disk image that already sits in memory, such as a boot module.
+//! Writes change the in-memory copy only and are lost on reboot.
+
+use super::block::{self, BlockDevice};
+
+const SECTOR_SIZE: usize = 512;
+
+pub struct RamDisk {
+    data: &'static mut [u8],
+}
+
+impl RamDisk {
+    /// Wrap `len` bytes at `base` as a disk
+    ///
+    /// # Safety
+    /// The memory must be valid, writable, and used by nothing else for the
+    /// kernel's lifetime.
+    pub unsafe fn from_raw(base: *mut u8, len: usize) -> Self {
+        let len = len - len % SECTOR_SIZE;
+        RamDisk {
+            data: core::slice::from_raw_parts_mut(base, len),
+        }
+    }
+
+    fn byte_range(&self, lba: u64, len: usize) -> Result<core::ops::Range<usize>, &'static str> {
+        block::check_range(self, lba, len)?;
+        let start = lba as usize * SECTOR_SIZE;
+        Ok(start..start + len)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let range = self.byte_range(lba, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let range = self.byte_range(lba, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Names for kernel addresses
+//! The symbol table is the one in the kernel's own ELF file, which Limine
+//! loads alongside it (`bootinfo`'s `kernel_file`), so nothing has to be generated
+//! or linked in at build time and it always matches the running kernel.
+//! Lookups only read that file, and take no locks, so they are safe from a
+//! panic or exception handler.
+//! The table holds link-time addresses; with KASLR the kernel runs `slide`
+//! bytes away from them, which lookups take off first.
+
+use crate::bootinfo;
+use shared::elf::Elf;
+
+/// Where kernel/linker.ld places the kernel
+const LINK_BASE: u64 = 0xffff_ffff_8000_0000;
+
+/// How far from its link address the bootloader loaded the kernel
+/// 0 without KASLR, or if the bootloader doesn't say.
+pub fn slide() -> u64 {
+    match bootinfo::get().kernel_virtual_base {
+        0 => 0,
+        base => base.wrapping_sub(LINK_BASE),
+    }
+}
+
+/// The kernel's symbol table, if the bootloader passed the kernel file
+pub fn table() -> Option<Elf<'static>> {
+    Elf::parse(bootinfo::get().kernel_file?).ok()
+}
+
+/// Name of the symbol `addr` falls in, still mangled
+#[allow(dead_code)]
+pub fn resolve(addr: u64) -> Option<&'static str> {
+    lookup(addr).map(|(name, _)| name)
+}
+
+/// Name of the symbol `addr` falls in and how far into it `addr` is
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    table()?.symbol_at(addr.wrapping_sub(slide())).map(|(symbol, offset)| (symbol.name, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use shared::backtrace::Demangled;
+
+    #[test_case]
+    fn test_resolve_own_address() {
+        let addr = lookup as fn(u64) -> Option<(&'static str, u64)> as usize as u64;
+        let name = resolve(addr + 1).expect("no symbol table");
+        assert_eq!(format!("{}", Demangled(name)), "kernel::symbols::lookup");
+        assert_eq!(lookup(addr).map(|(_, offset)| offset), Some(0));
+    }
+}
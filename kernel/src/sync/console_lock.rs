@@ -0,0 +1,92 @@
+//! Console lock that detects re-entry from the CPU already holding it
+//! An exception or NMI that fires while this CPU is mid-print would otherwise
+//! spin forever on its own lock; callers get `Reentered` instead and can fall
+//! back to a lock-free output path.
+
+use super::irq_spinlock::IrqRestore;
+use super::spinlock::{Spinlock, SpinlockGuard};
+use crate::arch::{Arch, Cpu};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const NO_OWNER: usize = usize::MAX;
+
+/// The lock is already held by the calling CPU
+pub struct Reentered;
+
+pub struct ConsoleLock<T> {
+    lock: Spinlock<T>,
+    owner: AtomicUsize,
+}
+
+impl<T> ConsoleLock<T> {
+    pub const fn new(data: T) -> Self {
+        ConsoleLock {
+            lock: Spinlock::new(data),
+            owner: AtomicUsize::new(NO_OWNER),
+        }
+    }
+
+    /// Acquire the lock with interrupts disabled, or fail if this CPU owns it
+    ///
+    /// Interrupts stay disabled until the guard drops, so ordinary IRQs can
+    /// never observe the lock half-acquired on their own CPU.
+    pub fn lock_or_reentered(&self) -> Result<ConsoleGuard<'_, T>, Reentered> {
+        let irq = IrqRestore::save_and_disable();
+        let cpu = Arch::cpu_id();
+
+        loop {
+            if let Some(guard) = self.lock.try_lock() {
+                self.owner.store(cpu, Ordering::Relaxed);
+                return Ok(ConsoleGuard {
+                    guard,
+                    owner: &self.owner,
+                    _irq: irq,
+                });
+            }
+            if self.owner.load(Ordering::Relaxed) == cpu {
+                return Err(Reentered);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Acquire the lock only if nobody holds it
+    pub fn try_lock(&self) -> Option<ConsoleGuard<'_, T>> {
+        let irq = IrqRestore::save_and_disable();
+        let guard = self.lock.try_lock()?;
+        self.owner.store(Arch::cpu_id(), Ordering::Relaxed);
+        Some(ConsoleGuard {
+            guard,
+            owner: &self.owner,
+            _irq: irq,
+        })
+    }
+}
+
+// Field order matters: the spinlock is released before interrupts come back
+pub struct ConsoleGuard<'a, T> {
+    guard: SpinlockGuard<'a, T>,
+    owner: &'a AtomicUsize,
+    _irq: IrqRestore,
+}
+
+impl<'a, T> Deref for ConsoleGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for ConsoleGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, T> Drop for ConsoleGuard<'a, T> {
+    fn drop(&mut self) {
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,75 @@
+//! Spinlock that keeps interrupts disabled while held
+//! Data an interrupt handler also touches must not be locked with interrupts
+//! enabled: the handler would fire on the CPU holding the lock and spin on it
+//! forever. `IrqSafeSpinlock` disables interrupts before taking the lock and
+//! restores them after releasing it, so callers cannot forget to. Handlers
+//! themselves run with interrupts off and lock it the same way.
+
+use super::spinlock::{Spinlock, SpinlockGuard};
+use crate::arch::{Arch, Cpu};
+use core::ops::{Deref, DerefMut};
+
+pub struct IrqSafeSpinlock<T> {
+    lock: Spinlock<T>,
+}
+
+impl<T> IrqSafeSpinlock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSafeSpinlock { lock: Spinlock::new(data) }
+    }
+
+    pub fn lock(&self) -> IrqSafeGuard<'_, T> {
+        let irq = IrqRestore::save_and_disable();
+        IrqSafeGuard { guard: self.lock.lock(), _irq: irq }
+    }
+
+    /// Acquire the lock only if it is currently free
+    #[allow(dead_code)]
+    pub fn try_lock(&self) -> Option<IrqSafeGuard<'_, T>> {
+        let irq = IrqRestore::save_and_disable();
+        Some(IrqSafeGuard { guard: self.lock.try_lock()?, _irq: irq })
+    }
+}
+
+/// Re-enables interrupts on drop if they were enabled when it was created
+pub struct IrqRestore {
+    were_enabled: bool,
+}
+
+impl IrqRestore {
+    pub fn save_and_disable() -> Self {
+        let were_enabled = Arch::interrupts_enabled();
+        if were_enabled {
+            Arch::disable_interrupts();
+        }
+        IrqRestore { were_enabled }
+    }
+}
+
+impl Drop for IrqRestore {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            Arch::enable_interrupts();
+        }
+    }
+}
+
+// Field order matters: the spinlock is released before interrupts come back
+pub struct IrqSafeGuard<'a, T> {
+    guard: SpinlockGuard<'a, T>,
+    _irq: IrqRestore,
+}
+
+impl<'a, T> Deref for IrqSafeGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T> DerefMut for IrqSafeGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -0,0 +1,162 @@
+//! Reference-counted kernel objects
+//! `KRef<T>` is how kernel objects with more than one owner are kept alive:
+//! an `Arc` tagged with the kind of object it holds. With the `debugging`
+//! feature each kind keeps a count of live objects, so `krefs` can show what
+//! exists and a `LeakCheck` can catch objects that outlive the operation that
+//! made them. Without it the tag costs a byte and the counts are compiled out.
+
+use crate::warn;
+use alloc::sync::{Arc, Weak};
+use core::marker::Unsize;
+use core::ops::{CoerceUnsized, Deref};
+#[cfg(feature = "debugging")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// What a `KRef` points at, for the live counts
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ObjectKind {
+    FileSystem,
+    Volume,
+    Inode,
+    OpenFile,
+}
+
+impl ObjectKind {
+    pub const ALL: [ObjectKind; 4] =
+        [ObjectKind::FileSystem, ObjectKind::Volume, ObjectKind::Inode, ObjectKind::OpenFile];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectKind::FileSystem => "filesystems",
+            ObjectKind::Volume => "volumes",
+            ObjectKind::Inode => "inodes",
+            ObjectKind::OpenFile => "open files",
+        }
+    }
+}
+
+const KIND_COUNT: usize = ObjectKind::ALL.len();
+
+#[cfg(feature = "debugging")]
+static LIVE: [AtomicUsize; KIND_COUNT] = [const { AtomicUsize::new(0) }; KIND_COUNT];
+
+#[cfg(feature = "debugging")]
+fn created(kind: ObjectKind) {
+    LIVE[kind as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "debugging"))]
+fn created(_kind: ObjectKind) {}
+
+#[cfg(feature = "debugging")]
+fn destroyed(kind: ObjectKind) {
+    LIVE[kind as usize].fetch_sub(1, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "debugging"))]
+fn destroyed(_kind: ObjectKind) {}
+
+/// The shared allocation: the object and its kind, counted down when the
+/// last reference goes
+struct Counted<T: ?Sized> {
+    kind: ObjectKind,
+    value: T,
+}
+
+impl<T: ?Sized> Drop for Counted<T> {
+    fn drop(&mut self) {
+        destroyed(self.kind);
+    }
+}
+
+/// A counted reference to a shared kernel object
+/// Clones share the object, which is dropped with the last of them.
+/// `KRef<Concrete>` converts to `KRef<dyn Trait>` like an `Arc` does.
+pub struct KRef<T: ?Sized> {
+    inner: Arc<Counted<T>>,
+}
+
+impl<T> KRef<T> {
+    pub fn new(kind: ObjectKind, value: T) -> Self {
+        created(kind);
+        KRef { inner: Arc::new(Counted { kind, value }) }
+    }
+}
+
+impl<T: ?Sized> KRef<T> {
+    /// A reference that does not keep the object alive
+    #[cfg_attr(not(feature = "storage"), allow(dead_code))]
+    pub fn downgrade(this: &Self) -> KWeak<T> {
+        KWeak { inner: Arc::downgrade(&this.inner) }
+    }
+}
+
+impl<T: ?Sized> Clone for KRef<T> {
+    fn clone(&self) -> Self {
+        KRef { inner: self.inner.clone() }
+    }
+}
+
+impl<T: ?Sized> Deref for KRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.value
+    }
+}
+
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<KRef<U>> for KRef<T> {}
+
+/// A `KRef` that lets its object go, for caches
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+pub struct KWeak<T: ?Sized> {
+    inner: Weak<Counted<T>>,
+}
+
+#[cfg_attr(not(feature = "storage"), allow(dead_code))]
+impl<T: ?Sized> KWeak<T> {
+    /// A `KRef` to the object, if it is still alive
+    pub fn upgrade(&self) -> Option<KRef<T>> {
+        self.inner.upgrade().map(|inner| KRef { inner })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.inner.strong_count() > 0
+    }
+}
+
+impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<KWeak<U>> for KWeak<T> {}
+
+/// Live objects of each kind as (kind, count); None without the `debugging` feature
+pub fn live_counts() -> Option<[(ObjectKind, usize); KIND_COUNT]> {
+    #[cfg(feature = "debugging")]
+    return Some(ObjectKind::ALL.map(|kind| (kind, LIVE[kind as usize].load(Ordering::Relaxed))));
+    #[cfg(not(feature = "debugging"))]
+    return None;
+}
+
+/// Live counts taken before an operation, to compare with after it
+/// Anything the operation made that is still alive once it is over is
+/// reported as a leak. Does nothing without the `debugging` feature.
+pub struct LeakCheck {
+    before: Option<[(ObjectKind, usize); KIND_COUNT]>,
+}
+
+impl LeakCheck {
+    pub fn start() -> Self {
+        LeakCheck { before: live_counts() }
+    }
+
+    /// Warn about every kind with more live objects than at `start`
+    pub fn finish(self, operation: &str) {
+        let (Some(before), Some(after)) = (self.before, live_counts()) else {
+            return;
+        };
+        for ((kind, was), (_, now)) in before.into_iter().zip(after) {
+            if now > was {
+                warn!("kref: {} {} outlived {}", now - was, kind.name(), operation);
+            }
+        }
+    }
+}
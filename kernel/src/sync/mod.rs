@@ -1 +1,9 @@
+pub mod console_lock;
+pub mod irq_spinlock;
+pub mod kref;
+pub mod mutex;
+pub mod once;
+pub mod rwlock;
+pub mod semaphore;
 pub mod spinlock;
+pub mod wait_queue;
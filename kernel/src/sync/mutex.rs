@@ -0,0 +1,126 @@
+//! Mutex for long critical sections
+//! A `Spinlock` suits a few instructions; a caller that holds one for long
+//! keeps every other CPU wanting it spinning, and with interrupts off if it
+//! had to disable them. A `Mutex` is held with interrupts enabled. Only its
+//! own bookkeeping (who holds it, how many wait) runs with them off, under an
+//! `IrqSafeSpinlock`, so taking and releasing it cannot be torn by an
+//! interrupt. A caller that finds it held waits in `wait`, and a release with
+//! waiters calls `wake`: there is no scheduler yet, so waiting means spinning
+//! with interrupts enabled, but those two are where a task will block and be
+//! woken once there is one.
+//! Interrupt handlers must not take a `Mutex`, and nothing may take one it
+//! already holds. With one task per CPU, a CPU asking for a mutex it holds
+//! could only wait forever, so that panics instead.
+
+use super::irq_spinlock::IrqSafeSpinlock;
+use crate::arch::{Arch, Cpu};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const NO_OWNER: usize = usize::MAX;
+
+struct MutexState {
+    /// CPU holding the mutex, or `NO_OWNER`
+    owner: usize,
+    waiters: usize,
+}
+
+pub struct Mutex<T> {
+    state: IrqSafeSpinlock<MutexState>,
+    /// Mirrors `owner != NO_OWNER`, for waiters to poll without the state lock
+    held: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: IrqSafeSpinlock::new(MutexState { owner: NO_OWNER, waiters: 0 }),
+            held: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let cpu = Arch::cpu_id();
+        let mut waiting = false;
+        loop {
+            {
+                let mut state = self.state.lock();
+                if state.owner == NO_OWNER {
+                    state.owner = cpu;
+                    if waiting {
+                        state.waiters -= 1;
+                    }
+                    self.held.store(true, Ordering::Relaxed);
+                    return MutexGuard { mutex: self };
+                }
+                assert!(state.owner != cpu, "Mutex taken again by the CPU holding it");
+                if !waiting {
+                    state.waiters += 1;
+                    waiting = true;
+                }
+            }
+            self.wait();
+        }
+    }
+
+    /// Acquire the mutex only if nobody holds it
+    #[allow(dead_code)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.owner != NO_OWNER {
+            return None;
+        }
+        state.owner = Arch::cpu_id();
+        self.held.store(true, Ordering::Relaxed);
+        Some(MutexGuard { mutex: self })
+    }
+
+    /// Wait until the mutex may be free; the caller checks again
+    fn wait(&self) {
+        while self.held.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Let waiters know the mutex was released; they are already polling
+    fn wake(&self) {}
+
+    fn unlock(&self) {
+        let mut state = self.state.lock();
+        state.owner = NO_OWNER;
+        self.held.store(false, Ordering::Release);
+        if state.waiters > 0 {
+            self.wake();
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,88 @@
+//! One-time initialization of globals
+//! A `Once<T>` starts empty and is filled by the first `call_once`; every
+//! later call, on any CPU, gets the same value without running its closure.
+//! A global that can only be built at run time (from boot information, or by
+//! code that is not `const`) goes in a `Once` instead of starting out as a
+//! placeholder that every use must check for, or a `static mut`.
+//! `Lazy<T>` is a `Once` that carries its initializer, for globals that need
+//! nothing from their first user: the value is built on first dereference.
+//! A CPU that finds another one running the initializer spins until it is
+//! done, so an initializer must not wait on its own `Once`.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const EMPTY: u8 = 0;
+const RUNNING: u8 = 1;
+const READY: u8 = 2;
+
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Once { state: AtomicU8::new(EMPTY), value: UnsafeCell::new(MaybeUninit::uninit()) }
+    }
+
+    /// The value, built by `init` if this is the first call
+    pub fn call_once(&self, init: impl FnOnce() -> T) -> &T {
+        if self.state.compare_exchange(EMPTY, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            unsafe { (*self.value.get()).write(init()) };
+            self.state.store(READY, Ordering::Release);
+        }
+        while self.state.load(Ordering::Acquire) != READY {
+            core::hint::spin_loop();
+        }
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+
+    /// The value, if `call_once` has finished building it
+    pub fn get(&self) -> Option<&T> {
+        (self.state.load(Ordering::Acquire) == READY).then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Self {
+        Lazy { once: Once::new(), init }
+    }
+
+    /// Build the value now if it has not been yet
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(&this.init)
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
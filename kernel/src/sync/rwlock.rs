@@ -0,0 +1,122 @@
+//! Reader-writer spinlock
+//! Any number of readers may hold an `RwLock` at once, or one writer alone,
+//! so read-mostly tables (the mount table, the ARP cache) do not make their
+//! readers queue behind each other. Writers are preferred: once a writer is
+//! waiting, new readers wait too, so a steady stream of readers cannot keep
+//! it out forever. Like `Spinlock`, waiting spins and interrupts are left
+//! alone, so an `RwLock` must not be shared with interrupt handlers.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `state` value while a writer holds the lock; otherwise it counts readers
+const WRITER: usize = usize::MAX;
+
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    /// Writers waiting for the lock, which holds back new readers
+    writers_waiting: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Share the lock with other readers, once no writer holds or wants it
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Take the lock for reading only if that needs no waiting
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let readers = self.state.load(Ordering::Relaxed);
+        // One short of WRITER is as many readers as there can be
+        if readers >= WRITER - 1 {
+            return None;
+        }
+        self.state
+            .compare_exchange(readers, readers + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    /// Take the lock alone, once the readers holding it are done
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        loop {
+            if let Some(guard) = self.try_write() {
+                self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Take the lock for writing only if nobody holds it
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
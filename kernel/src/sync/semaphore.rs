@@ -0,0 +1,46 @@
+//! Counting semaphore
+//! Holds a count of available units: `acquire` takes one, blocking on a
+//! `WaitQueue` while there are none, and `release` gives one back and wakes
+//! a waiter. A driver can count completed requests this way, releasing from
+//! its interrupt handler while the caller that issued the request acquires.
+//! `release` never blocks, so interrupt handlers may call it; `acquire` may
+//! only be called where `WaitQueue::wait_until` may.
+
+use super::wait_queue::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[allow(dead_code)]
+pub struct Semaphore {
+    count: AtomicUsize,
+    queue: WaitQueue,
+}
+
+#[allow(dead_code)]
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore { count: AtomicUsize::new(count), queue: WaitQueue::new() }
+    }
+
+    /// Take a unit, blocking until one is available
+    pub fn acquire(&self) {
+        self.queue.wait_until(|| self.try_acquire().then_some(()));
+    }
+
+    /// Take a unit if one is available now
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| count.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Give back a unit and wake a waiter
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.queue.wake_one();
+    }
+
+    /// Units available right now
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
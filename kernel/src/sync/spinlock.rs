@@ -33,6 +33,14 @@ impl<T> Spinlock<T> {
 
         SpinlockGuard { lock: self }
     }
+
+    /// Acquire the lock only if it is currently free
+    pub fn try_lock(&self) -> Option<SpinlockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinlockGuard { lock: self })
+    }
 }
 
 pub struct SpinlockGuard<'a, T> {
@@ -0,0 +1,68 @@
+//! Waiting for a condition that an interrupt handler (or another CPU) makes true
+//! A caller blocks in `wait_until` until its condition holds; whoever makes
+//! it hold calls `wake_one` or `wake_all`. There is no scheduler yet, so a
+//! blocked caller halts its CPU through `time::idle` and rechecks its
+//! condition after every wakeup, instead of spinning on it. A wake raised by
+//! an interrupt on the waiting CPU ends the halt at once; one from another
+//! CPU is noticed on the waiter's next interrupt at the latest, since nothing
+//! sends it an IPI. Once there are tasks, waiters will sleep on the queue and
+//! `wake_one` will hand the CPU to a single one of them.
+//! Waiting needs interrupts enabled, so interrupt handlers can wake but never wait.
+
+use crate::time;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+pub struct WaitQueue {
+    /// Bumped by every wake, so a waiter can tell one happened since it looked
+    wakeups: AtomicU64,
+    waiters: AtomicUsize,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { wakeups: AtomicU64::new(0), waiters: AtomicUsize::new(0) }
+    }
+
+    /// Block until `condition` returns a value, and return it
+    /// The condition is evaluated with interrupts enabled and may run any
+    /// number of times.
+    pub fn wait_until<R>(&self, mut condition: impl FnMut() -> Option<R>) -> R {
+        self.waiters.fetch_add(1, Ordering::Relaxed);
+        let result = loop {
+            // Read before checking: a wake after the check changes it, and
+            // `idle` checks it again with interrupts off before halting
+            let seen = self.wakeups.load(Ordering::Acquire);
+            if let Some(result) = condition() {
+                break result;
+            }
+            time::idle(&[], || self.wakeups.load(Ordering::Acquire) != seen);
+        };
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    /// Wake a waiter to recheck its condition
+    /// Without a scheduler every waiter rechecks; a condition that only one
+    /// of them may act on (such as taking a `Semaphore` count) must be
+    /// claimed atomically inside `wait_until`.
+    pub fn wake_one(&self) {
+        self.wake_all();
+    }
+
+    /// Wake every waiter to recheck its condition
+    pub fn wake_all(&self) {
+        self.wakeups.fetch_add(1, Ordering::Release);
+    }
+
+    /// Callers blocked in `wait_until`
+    #[allow(dead_code)]
+    pub fn waiters(&self) -> usize {
+        self.waiters.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,265 @@
+//! File descriptor system calls
+//! `read` and `write` go to whatever the descriptor names in the running
+//! program's table (`process::files`): the console, or a file opened from
+//! the VFS. Data passes through a buffer on the kernel stack, so the
+//! program's memory is never touched with a filesystem or the console
+//! locked: faulting in a page of a demand-paged program may itself need to
+//! read the disk, or log a warning.
+
+use super::{check_user_range, copy_from_user, copy_to_user, exit_if_killed};
+use crate::drivers::console;
+use crate::fs::vfs::{self, File, FsError, OpenFlags};
+use crate::input;
+use crate::process::files::{self, Descriptor};
+use crate::sync::kref::KRef;
+use crate::sync::mutex::Mutex;
+use crate::print;
+use abi::{error, EBADF, EBUSY, EEXIST, EFAULT, EINVAL, EIO, EISDIR, EMFILE, ENOENT, ENOSPC, ENOTDIR};
+use abi::{ENOTEMPTY, EROFS, ESPIPE, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+use abi::{SEEK_CUR, SEEK_END, SEEK_SET};
+use alloc::string::String;
+use shared::fdtable::{self, FdError, Whence};
+
+/// Bytes moved between a file or the console and the program at a time
+const IO_CHUNK: usize = 512;
+
+fn fs_errno(e: FsError) -> u64 {
+    error(match e {
+        FsError::NotFound | FsError::NotMounted => ENOENT,
+        FsError::AlreadyExists => EEXIST,
+        FsError::NotADirectory => ENOTDIR,
+        FsError::IsADirectory => EISDIR,
+        FsError::DirectoryNotEmpty => ENOTEMPTY,
+        FsError::InvalidPath => EINVAL,
+        FsError::ReadOnly => EROFS,
+        // Reading a file opened only for writing, or the reverse
+        FsError::Unsupported => EBADF,
+        FsError::NoSpace => ENOSPC,
+        FsError::Busy => EBUSY,
+        FsError::Io => EIO,
+    })
+}
+
+fn fd_errno(e: FdError) -> u64 {
+    error(match e {
+        FdError::BadFd => EBADF,
+        FdError::TableFull => EMFILE,
+    })
+}
+
+/// Path passed at [ptr, ptr + len), copied into the kernel
+fn user_path(ptr: u64, len: u64) -> Result<String, u64> {
+    if len == 0 || len > abi::PATH_MAX {
+        return Err(error(EINVAL));
+    }
+    let mut bytes = [0u8; abi::PATH_MAX as usize];
+    let bytes = &mut bytes[..len as usize];
+    copy_from_user(ptr, bytes)?;
+    core::str::from_utf8(bytes).map(String::from).map_err(|_| error(EINVAL))
+}
+
+pub fn sys_open(path: u64, len: u64, flags: u64) -> u64 {
+    let path = match user_path(path, len) {
+        Ok(path) => path,
+        Err(e) => return e,
+    };
+    let access = flags & 3;
+    if access != O_RDONLY && access != O_WRONLY && access != O_RDWR {
+        return error(EINVAL);
+    }
+    let flags = OpenFlags {
+        read: access != O_WRONLY,
+        write: access != O_RDONLY,
+        create: flags & O_CREAT != 0,
+        truncate: flags & O_TRUNC != 0,
+        append: flags & O_APPEND != 0,
+    };
+    match vfs::open(&path, flags) {
+        Ok(file) => files::insert(Descriptor::file(file)).unwrap_or_else(fd_errno),
+        Err(e) => fs_errno(e),
+    }
+}
+
+pub fn sys_close(fd: u64) -> u64 {
+    files::close(fd).map_or_else(fd_errno, |()| 0)
+}
+
+pub fn sys_dup2(old: u64, new: u64) -> u64 {
+    files::dup2(old, new).unwrap_or_else(fd_errno)
+}
+
+pub fn sys_lseek(fd: u64, offset: u64, whence: u64) -> u64 {
+    let file = match files::get(fd) {
+        Ok(Descriptor::File(file)) => file,
+        Ok(Descriptor::Console) => return error(ESPIPE),
+        Err(e) => return fd_errno(e),
+    };
+    let whence = match whence {
+        SEEK_SET => Whence::Start,
+        SEEK_CUR => Whence::Current,
+        SEEK_END => Whence::End,
+        _ => return error(EINVAL),
+    };
+    let mut file = file.lock();
+    match fdtable::seek_target(file.offset(), file.size(), offset as i64, whence) {
+        Some(target) => {
+            file.seek(target);
+            target as u64
+        }
+        None => error(EINVAL),
+    }
+}
+
+pub fn sys_read(fd: u64, buf: u64, len: u64) -> u64 {
+    let descriptor = match files::get(fd) {
+        Ok(descriptor) => descriptor,
+        Err(e) => return fd_errno(e),
+    };
+    if len == 0 {
+        return 0;
+    }
+    if !check_user_range(buf, len) {
+        return error(EFAULT);
+    }
+    match descriptor {
+        Descriptor::Console => read_console(buf, len),
+        Descriptor::File(file) => read_file(&file, buf, len as usize),
+    }
+}
+
+pub fn sys_write(fd: u64, buf: u64, len: u64) -> u64 {
+    let descriptor = match files::get(fd) {
+        Ok(descriptor) => descriptor,
+        Err(e) => return fd_errno(e),
+    };
+    if len == 0 {
+        return 0;
+    }
+    if !check_user_range(buf, len) {
+        return error(EFAULT);
+    }
+    match descriptor {
+        Descriptor::Console => write_console(buf, len),
+        Descriptor::File(file) => write_file(&file, buf, len as usize),
+    }
+}
+
+/// Up to `IO_CHUNK` bytes per call
+fn read_console(buf: u64, len: u64) -> u64 {
+    let mut line = [0u8; IO_CHUNK];
+    let out = &mut line[..(len as usize).min(IO_CHUNK)];
+
+    // Block until at least one key arrives, then drain whatever is buffered
+    let mut count = 0;
+    while count < out.len() {
+        let key = if count == 0 {
+            input::wait_for(|| {
+                exit_if_killed();
+                console::try_read_key()
+            })
+        } else {
+            match console::try_read_key() {
+                Some(key) => key,
+                None => break,
+            }
+        };
+        if let Some(byte) = key.to_ascii() {
+            out[count] = byte;
+            count += 1;
+        }
+    }
+    match copy_to_user(buf, &out[..count]) {
+        Ok(()) => count as u64,
+        Err(e) => e,
+    }
+}
+
+fn write_console(buf: u64, len: u64) -> u64 {
+    let len = len as usize;
+    let mut chunk = [0u8; IO_CHUNK];
+    // Start of a character the previous chunk ended in the middle of
+    let mut carried = 0;
+    let mut done = 0;
+    while done < len {
+        let count = (len - done).min(IO_CHUNK - carried);
+        if let Err(e) = copy_from_user(buf + done as u64, &mut chunk[carried..carried + count]) {
+            return if done > 0 { done as u64 } else { e };
+        }
+        done += count;
+        let filled = carried + count;
+        carried = if done < len { split_char_len(&chunk[..filled]) } else { 0 };
+        for text in chunk[..filled - carried].utf8_chunks() {
+            print!("{}", text.valid());
+            if !text.invalid().is_empty() {
+                print!("?");
+            }
+        }
+        chunk.copy_within(filled - carried..filled, 0);
+    }
+    len as u64
+}
+
+/// Bytes at the end of `bytes` that begin a UTF-8 character without
+/// finishing it
+fn split_char_len(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        // Skip continuation bytes back to the one that starts the character
+        if byte & 0xC0 != 0x80 {
+            let width = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if width > back { back } else { 0 };
+        }
+    }
+    0
+}
+
+/// Read up to `len` bytes into the program at `buf`, stopping early at the
+/// end of the file
+fn read_file(file: &KRef<Mutex<File>>, buf: u64, len: usize) -> u64 {
+    let mut chunk = [0u8; IO_CHUNK];
+    let mut done = 0;
+    while done < len {
+        let want = (len - done).min(IO_CHUNK);
+        let read = file.lock().read(&mut chunk[..want]);
+        let count = match read {
+            Ok(count) => count,
+            // What was read before the error still counts
+            Err(_) if done > 0 => break,
+            Err(e) => return fs_errno(e),
+        };
+        if let Err(e) = copy_to_user(buf + done as u64, &chunk[..count]) {
+            return if done > 0 { done as u64 } else { e };
+        }
+        done += count;
+        if count < want {
+            break;
+        }
+    }
+    done as u64
+}
+
+fn write_file(file: &KRef<Mutex<File>>, buf: u64, len: usize) -> u64 {
+    let mut chunk = [0u8; IO_CHUNK];
+    let mut done = 0;
+    while done < len {
+        let count = (len - done).min(IO_CHUNK);
+        if let Err(e) = copy_from_user(buf + done as u64, &mut chunk[..count]) {
+            return if done > 0 { done as u64 } else { e };
+        }
+        let written = match file.lock().write(&chunk[..count]) {
+            Ok(written) => written,
+            Err(_) if done > 0 => break,
+            Err(e) => return fs_errno(e),
+        };
+        done += written;
+        if written < count {
+            break;
+        }
+    }
+    done as u64
+}
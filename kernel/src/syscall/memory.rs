@@ -0,0 +1,27 @@
+//! Memory system calls: the program break and anonymous mappings
+//! The bookkeeping is in `process::anon`; these only check arguments and
+//! encode results.
+
+use crate::process::demand;
+use abi::error;
+
+pub fn sys_brk(addr: u64) -> u64 {
+    // A system call always comes from a running program
+    demand::with_anon(|anon, space| anon.brk(space, addr)).unwrap_or(0)
+}
+
+pub fn sys_mmap(hint: u64, len: u64, prot: u64) -> u64 {
+    match demand::with_anon(|anon, _| anon.mmap(hint, len, prot)) {
+        Some(Ok(addr)) => addr,
+        Some(Err(errno)) => error(errno),
+        None => error(abi::ENOMEM),
+    }
+}
+
+pub fn sys_munmap(addr: u64, len: u64) -> u64 {
+    match demand::with_anon(|anon, space| anon.munmap(space, addr, len)) {
+        Some(Ok(())) => 0,
+        Some(Err(errno)) => error(errno),
+        None => error(abi::EINVAL),
+    }
+}
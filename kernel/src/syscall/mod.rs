@@ -0,0 +1,211 @@
+//! System call dispatch and handlers
+//! The numbers, error codes, and layouts user programs see are defined in
+//! the `abi` crate; this module only implements them.
+
+mod file;
+mod memory;
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::x86_64::syscall::SyscallFrame;
+use crate::ipc;
+use crate::process::demand;
+use crate::warn;
+use abi::{error, EFAULT, ENOSYS, SYS_CLOSE, SYS_DUP2, SYS_EXIT, SYS_LSEEK, SYS_OPEN, SYS_READ, SYS_WRITE, USER_SPACE_END};
+use abi::{EEXIST, EINVAL, EMSGSIZE, ENOENT, ENOSPC, EPERM, SYS_BRK, SYS_MMAP, SYS_MUNMAP};
+#[cfg(target_arch = "x86_64")]
+use abi::{EAGAIN, ENOMEM, SYS_FORK};
+use abi::{SYS_PORT_CREATE, SYS_PORT_DESTROY, SYS_PORT_LOOKUP, SYS_PORT_RECEIVE, SYS_PORT_SEND};
+use shared::ipc::{Owner, PortError, PortId, MAX_MESSAGE, MAX_NAME};
+
+/// Validate that [ptr, ptr + len) lies entirely in user space
+fn check_user_range(ptr: u64, len: u64) -> bool {
+    match ptr.checked_add(len) {
+        Some(end) => ptr != 0 && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// Copy the program's bytes at [ptr, ptr + out.len()) into `out`
+/// Every page is checked and faulted in first (`demand::prepare_user`), so
+/// an unmapped address fails with EFAULT rather than faulting in the kernel.
+fn copy_from_user(ptr: u64, out: &mut [u8]) -> Result<(), u64> {
+    if out.is_empty() {
+        return Ok(());
+    }
+    let len = out.len() as u64;
+    if !check_user_range(ptr, len) || !demand::prepare_user(ptr, len, false) {
+        return Err(error(EFAULT));
+    }
+    out.copy_from_slice(unsafe { core::slice::from_raw_parts(ptr as *const u8, out.len()) });
+    Ok(())
+}
+
+/// Copy `bytes` to the program at [ptr, ptr + bytes.len()), which must be
+/// writable by the program itself
+fn copy_to_user(ptr: u64, bytes: &[u8]) -> Result<(), u64> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    let len = bytes.len() as u64;
+    if !check_user_range(ptr, len) || !demand::prepare_user(ptr, len, true) {
+        return Err(error(EFAULT));
+    }
+    unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, bytes.len()) }.copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Called from the entry stub with interrupts enabled on the kernel stack
+#[cfg(target_arch = "x86_64")]
+pub extern "C" fn dispatch(frame: &mut SyscallFrame) {
+    frame.rax = match frame.rax {
+        // The child starts from the caller's registers, not just arguments
+        SYS_FORK => {
+            exit_if_killed();
+            sys_fork(frame)
+        }
+        number => handle(number, frame.rdi, frame.rsi, frame.rdx),
+    };
+}
+
+/// Run system call `number`, returning its result or a negated errno
+pub fn handle(number: u64, arg0: u64, arg1: u64, arg2: u64) -> u64 {
+    exit_if_killed();
+    match number {
+        SYS_READ => file::sys_read(arg0, arg1, arg2),
+        SYS_WRITE => file::sys_write(arg0, arg1, arg2),
+        SYS_OPEN => file::sys_open(arg0, arg1, arg2),
+        SYS_CLOSE => file::sys_close(arg0),
+        SYS_LSEEK => file::sys_lseek(arg0, arg1, arg2),
+        SYS_DUP2 => file::sys_dup2(arg0, arg1),
+        SYS_BRK => memory::sys_brk(arg0),
+        SYS_MMAP => memory::sys_mmap(arg0, arg1, arg2),
+        SYS_MUNMAP => memory::sys_munmap(arg0, arg1),
+        SYS_EXIT => sys_exit(arg0 as i32),
+        SYS_PORT_CREATE => sys_port_create(arg0, arg1),
+        SYS_PORT_LOOKUP => sys_port_lookup(arg0, arg1),
+        SYS_PORT_SEND => sys_port_send(arg0, arg1, arg2),
+        SYS_PORT_RECEIVE => sys_port_receive(arg0, arg1, arg2),
+        SYS_PORT_DESTROY => sys_port_destroy(arg0),
+        number => {
+            warn!("syscall: unknown number {}", number);
+            error(ENOSYS)
+        }
+    }
+}
+
+/// Port name passed at [ptr, ptr + len), copied into `buf`
+/// The name is read before the port table is locked, like a message.
+fn user_port_name(ptr: u64, len: u64, buf: &mut [u8; MAX_NAME]) -> Result<&str, u64> {
+    if len == 0 || len > abi::PORT_NAME_MAX {
+        return Err(error(EINVAL));
+    }
+    let bytes = &mut buf[..len as usize];
+    copy_from_user(ptr, bytes)?;
+    core::str::from_utf8(bytes).map_err(|_| error(EINVAL))
+}
+
+fn port_errno(e: PortError) -> u64 {
+    error(match e {
+        PortError::NotFound => ENOENT,
+        PortError::Exists => EEXIST,
+        PortError::TableFull => ENOSPC,
+        PortError::BadName => EINVAL,
+        PortError::TooLarge | PortError::BufferTooSmall(_) => EMSGSIZE,
+        // Only from the non-blocking calls, which programs can't make
+        PortError::WouldBlock => EINVAL,
+    })
+}
+
+fn sys_port_create(name: u64, len: u64) -> u64 {
+    let mut buf = [0u8; MAX_NAME];
+    let name = match user_port_name(name, len, &mut buf) {
+        Ok(name) => name,
+        Err(e) => return e,
+    };
+    ipc::create(name, Owner::Process).map_or_else(port_errno, |port| port.0)
+}
+
+fn sys_port_lookup(name: u64, len: u64) -> u64 {
+    let mut buf = [0u8; MAX_NAME];
+    let name = match user_port_name(name, len, &mut buf) {
+        Ok(name) => name,
+        Err(e) => return e,
+    };
+    ipc::lookup(name).map_or_else(port_errno, |port| port.0)
+}
+
+fn sys_port_send(port: u64, buf: u64, len: u64) -> u64 {
+    if len > abi::PORT_MESSAGE_MAX {
+        return error(EMSGSIZE);
+    }
+    // Copied out of user memory first: a fault on it must not happen with
+    // the port table locked
+    let mut message = [0u8; MAX_MESSAGE];
+    let message = &mut message[..len as usize];
+    if let Err(e) = copy_from_user(buf, message) {
+        return e;
+    }
+    let sent = ipc::blocking(|| {
+        exit_if_killed();
+        ipc::try_send(PortId(port), message)
+    });
+    sent.map_or_else(port_errno, |()| 0)
+}
+
+/// A buffer too small for the next message fails with EMSGSIZE and leaves
+/// the message queued
+fn sys_port_receive(port: u64, buf: u64, len: u64) -> u64 {
+    if len > 0 && !check_user_range(buf, len) {
+        return error(EFAULT);
+    }
+    // Received into the kernel, then copied out with the table unlocked
+    let mut message = [0u8; MAX_MESSAGE];
+    let buffer = &mut message[..len.min(abi::PORT_MESSAGE_MAX) as usize];
+    let received = ipc::blocking(|| {
+        exit_if_killed();
+        ipc::try_receive(PortId(port), buffer)
+    });
+    match received {
+        Ok(received) => match copy_to_user(buf, &buffer[..received]) {
+            Ok(()) => received as u64,
+            Err(e) => e,
+        },
+        Err(e) => port_errno(e),
+    }
+}
+
+/// Only ports the program created itself
+fn sys_port_destroy(port: u64) -> u64 {
+    let port = PortId(port);
+    match ipc::owner(port) {
+        Ok(Owner::Process) => ipc::destroy(port).map_or_else(port_errno, |()| 0),
+        Ok(Owner::Kernel) => error(EPERM),
+        Err(e) => port_errno(e),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sys_fork(frame: &SyscallFrame) -> u64 {
+    use crate::process::fork::{self, ForkError};
+    match fork::fork(|| unsafe { crate::arch::x86_64::context::enter_fork(frame) }) {
+        Ok(pid) => pid,
+        Err(e) => {
+            warn!("syscall: fork: {}", e.as_str());
+            match e {
+                ForkError::TooDeep => error(EAGAIN),
+                ForkError::OutOfMemory => error(ENOMEM),
+            }
+        }
+    }
+}
+
+/// End the calling program here if the out-of-memory policy picked it
+fn exit_if_killed() {
+    if crate::process::kill_pending() {
+        crate::process::exit_current(crate::process::OOM_EXIT_CODE);
+    }
+}
+
+fn sys_exit(code: i32) -> u64 {
+    crate::process::exit_current(code as i64)
+}
@@ -0,0 +1,105 @@
+//! In-kernel test harness, built only by `cargo test` (`make test-integration`)
+//! Functions marked `#[test_case]` anywhere in the kernel are collected by
+//! the compiler's custom test framework and handed to `runner`, which `_start`
+//! calls once the kernel is fully up, in place of the shell. Results are
+//! printed like any console output, and reach the runner over serial; QEMU
+//! is then ended through its isa-debug-exit device, so the exit status of
+//! `qemu-test.sh` (the cargo runner) says whether every test passed.
+//! A failing test panics, and the test panic handler reports it and exits.
+//! Tests can also raise an exception on purpose with `catch_exception!`: the
+//! handler records it and resumes after the faulting instruction instead of
+//! halting, so the test can check what was reported.
+
+use crate::arch::x86_64::interrupts::InterruptStackFrame;
+use crate::power::{exit_qemu, QemuExitCode};
+use crate::sync::spinlock::Spinlock;
+use crate::{backtrace, print, println};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Where the exception `catch_exception!` expects should resume; 0 when no
+/// exception is expected
+pub static RESUME: AtomicU64 = AtomicU64::new(0);
+
+static CAUGHT: Spinlock<Option<Caught>> = Spinlock::new(None);
+
+/// An exception taken while one was expected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caught {
+    pub vector: u64,
+    pub error_code: Option<u64>,
+}
+
+/// Run one instruction (an `asm!` template and its operands, which must be
+/// named) that should raise an exception, and return the exception taken
+/// Execution carries on after the instruction either way. Like `asm!`, it
+/// must be used in an `unsafe` block.
+#[macro_export]
+macro_rules! catch_exception {
+    ($instruction:literal $(, $($operands:tt)*)?) => {{
+        core::arch::asm!(
+            "lea {resume_at}, [rip + 2f]",
+            "mov [{resume}], {resume_at}",
+            $instruction,
+            "2:",
+            "mov qword ptr [{resume}], 0",
+            resume_at = out(reg) _,
+            resume = in(reg) $crate::testing::RESUME.as_ptr(),
+            $($($operands)*)?
+        );
+        $crate::testing::take_caught()
+    }};
+}
+
+/// Called by the exception handlers: if a test expects an exception in the
+/// kernel, record this one and resume where it asked
+pub fn catch(vector: u64, error_code: Option<u64>, frame: &mut InterruptStackFrame) -> bool {
+    if frame.in_user_mode() {
+        return false;
+    }
+    let resume = RESUME.swap(0, Ordering::SeqCst);
+    if resume == 0 {
+        return false;
+    }
+    *CAUGHT.lock() = Some(Caught { vector, error_code });
+    frame.rip = resume;
+    true
+}
+
+/// The exception caught since the last call
+pub fn take_caught() -> Option<Caught> {
+    CAUGHT.lock().take()
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print!("{} ... ", core::any::type_name::<T>());
+        self();
+        println!("ok");
+    }
+}
+
+pub fn runner(tests: &[&dyn Testable]) {
+    println!("running {} tests", tests.len());
+    for test in tests {
+        // The run never idles; each test gets the watchdog's full timeout
+        crate::watchdog::pet();
+        test.run();
+    }
+    println!("test result: ok. {} passed", tests.len());
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Report the panicking test and end the run
+pub fn panic(info: &PanicInfo) -> ! {
+    println!("FAILED\n{}", info);
+    backtrace::print_here();
+    exit_qemu(QemuExitCode::Failed);
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,203 @@
+//! System tick
+//! The architecture's timer interrupts at `TICK_HZ`. Every tick first asks the
+//! timer how long ago it fired, which is exactly how long the interrupt waited
+//! to be handled (interrupts disabled, a lock held, another IRQ running).
+//! Wakeup-to-dispatch latency will be recorded the same way once there is a
+//! scheduler to dispatch tasks.
+//!
+//! `monotonic_ns` refines the tick count with the CPU cycle counter when it
+//! runs at a constant rate, and otherwise with the timer's sub-tick count
+//! (see `shared::clock`). The counter's frequency comes from the hardware
+//! where it states it, so nanosecond timing is available from `init` on;
+//! otherwise it is measured over the first `clock::CALIBRATION_TICKS` ticks.
+//!
+//! When the kernel goes idle with nothing due soon, `idle` stretches the tick
+//! so one interrupt stands for several ticks, and the first tick after waking
+//! restores the periodic rate.
+//!
+//! Wall-clock time is read from the RTC once, at `init`, and carried forward
+//! from there by the monotonic clock, so it advances smoothly and never
+//! jumps; it is only as exact as the RTC's whole seconds.
+
+use crate::arch::{Arch, Cpu, Timer};
+use crate::drivers::rtc;
+use crate::{watchdog, workqueue};
+use crate::{info, warn};
+use crate::sync::spinlock::Spinlock;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use shared::clock::{self, ClockSource, Deadline, MonotonicClock, RateCounter};
+use shared::data_structures::histogram::Histogram;
+use shared::datetime::DateTime;
+
+pub const TICK_HZ: u32 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Microseconds between the tick firing and its handler running
+static TICK_LATENCY: Spinlock<Histogram> = Spinlock::new(Histogram::new());
+
+static CLOCK: Spinlock<MonotonicClock> = Spinlock::new(MonotonicClock::new(TICK_HZ));
+
+/// Longest the tick may be stretched to while idle
+const MAX_IDLE_TICKS: u64 = TICK_HZ as u64;
+
+/// Set while `idle` has the CPU halted
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+/// Unix time in nanoseconds when `monotonic_ns` read zero; 0 if the RTC
+/// could not be read
+static BOOT_UNIX_NS: AtomicU64 = AtomicU64::new(0);
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Timer interrupts and wakeups from idle, per second
+struct WakeupStats {
+    timer: RateCounter,
+    idle: RateCounter,
+}
+
+static WAKEUPS: Spinlock<WakeupStats> = Spinlock::new(WakeupStats {
+    timer: RateCounter::new(TICK_HZ as u64),
+    idle: RateCounter::new(TICK_HZ as u64),
+});
+
+/// Start the periodic tick
+pub fn init() {
+    let invariant = Arch::cycles_invariant();
+    if !invariant {
+        warn!("Cycle counter is not invariant, timing from the tick alone");
+    }
+    let stated_hz = Arch::cycles_hz();
+    if let Some(hz) = stated_hz {
+        info!("Cycle counter: {} MHz, as stated by the hardware", hz / 1_000_000);
+    }
+    Arch::without_interrupts(|| CLOCK.lock().start(invariant, Arch::read_cycles(), stated_hz));
+    Arch::start_timer(TICK_HZ);
+
+    match rtc::read().and_then(|now| Some((now, now.to_unix()?))) {
+        Some((now, seconds)) => {
+            BOOT_UNIX_NS.store((seconds * NANOS_PER_SEC).saturating_sub(monotonic_ns()), Ordering::Relaxed);
+            info!("Wall clock: {} UTC", now);
+        }
+        None => warn!("RTC unreadable, no wall-clock time"),
+    }
+}
+
+/// Handle the timer interrupt (called from the interrupt handler)
+pub fn handle_tick() {
+    // Read first: every instruction before this adds to the measured latency
+    let elapsed = Arch::ticks_elapsed();
+    let latency = Arch::nanos_since_tick() / 1000;
+
+    TICKS.fetch_add(elapsed, Ordering::Relaxed);
+    TICK_LATENCY.lock().record(latency);
+    CLOCK.lock().on_tick(Arch::read_cycles(), elapsed);
+    {
+        let mut wakeups = WAKEUPS.lock();
+        wakeups.timer.record();
+        wakeups.timer.advance(elapsed);
+        wakeups.idle.advance(elapsed);
+    }
+    if !IDLE.load(Ordering::Relaxed) {
+        // Awake again: tick periodically from the next interrupt on
+        Arch::set_tick_period(1);
+    }
+
+    Arch::end_of_tick();
+}
+
+/// Ticks since `init`
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Milliseconds since `init`
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICK_HZ as u64
+}
+
+/// A deadline `ms` from now that may be served up to `slack_ms` late
+#[cfg_attr(not(feature = "net"), allow(dead_code))]
+pub fn deadline_in(ms: u64, slack_ms: u64) -> Deadline {
+    let ms_to_ticks = |ms: u64| (ms * TICK_HZ as u64).div_ceil(1000);
+    Deadline { at: ticks() + ms_to_ticks(ms), slack: ms_to_ticks(slack_ms) }
+}
+
+/// Halt until an interrupt arrives, unless `ready` says there is work already
+/// Deferred work (`workqueue`) runs first, and the CPU does not halt while
+/// more is queued. The tick is stretched up to the nearest of `deadlines`,
+/// or a second if there are none, so an idle system isn't woken `TICK_HZ`
+/// times a second.
+/// Called with interrupts enabled.
+pub fn idle(deadlines: &[Deadline], ready: impl Fn() -> bool) {
+    // Waiting for work is progress as far as the watchdog is concerned
+    watchdog::pet();
+    workqueue::run_pending();
+    // Checked with interrupts off, so work arriving after the check still ends the halt
+    Arch::disable_interrupts();
+    if ready() || workqueue::pending() {
+        Arch::enable_interrupts();
+        return;
+    }
+    IDLE.store(true, Ordering::Relaxed);
+    Arch::set_tick_period(clock::idle_period(ticks(), deadlines, MAX_IDLE_TICKS));
+    Arch::enable_interrupts_and_wait();
+    IDLE.store(false, Ordering::Relaxed);
+
+    Arch::without_interrupts(|| WAKEUPS.lock().idle.record());
+}
+
+/// Timer interrupt and idle wakeup counters
+pub fn wakeup_stats() -> (RateCounter, RateCounter) {
+    // The tick handler takes this lock too
+    Arch::without_interrupts(|| {
+        let wakeups = WAKEUPS.lock();
+        (wakeups.timer, wakeups.idle)
+    })
+}
+
+/// Nanoseconds since `init`; never goes backwards
+pub fn monotonic_ns() -> u64 {
+    // The tick handler takes this lock too
+    Arch::without_interrupts(|| {
+        CLOCK.lock().now(Arch::nanos_since_tick(), Arch::read_cycles())
+    })
+}
+
+/// Nanoseconds since the Unix epoch, if the wall-clock time is known
+pub fn unix_time_ns() -> Option<u64> {
+    match BOOT_UNIX_NS.load(Ordering::Relaxed) {
+        0 => None,
+        boot => Some(boot + monotonic_ns()),
+    }
+}
+
+/// The current UTC date and time, if known
+pub fn now() -> Option<DateTime> {
+    unix_time_ns().map(|ns| DateTime::from_unix(ns / NANOS_PER_SEC))
+}
+
+/// Re-anchor the cycle counter after it may have been reset (resume from suspend)
+#[allow(dead_code)]
+pub fn resync() {
+    Arch::without_interrupts(|| CLOCK.lock().resync(Arch::read_cycles()));
+}
+
+/// Current clock source, the cycle counter frequency (0 until calibrated),
+/// and whether the hardware stated that frequency rather than it being measured
+pub fn clock_source() -> (ClockSource, u64, bool) {
+    Arch::without_interrupts(|| {
+        let clock = CLOCK.lock();
+        (clock.source(), clock.cycles_hz(), clock.cycles_hz_stated())
+    })
+}
+
+/// Snapshot of the tick latency histogram
+pub fn tick_latency() -> Histogram {
+    // The tick handler takes this lock too
+    Arch::without_interrupts(|| *TICK_LATENCY.lock())
+}
+
+pub fn reset_latency() {
+    Arch::without_interrupts(|| TICK_LATENCY.lock().clear());
+}
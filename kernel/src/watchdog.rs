@@ -0,0 +1,74 @@
+//! Watchdog for kernel hangs
+//! The kernel shows it is getting somewhere by going idle (`time::idle`,
+//! where every wait ends up) or by running user code. Two checks catch it
+//! stopping, and the CPU found stuck dumps its registers and a backtrace to
+//! serial, then resets the machine:
+//! - The tick: interrupting the kernel with no progress for `TIMEOUT_MS`
+//!   means a loop or deadlock with interrupts enabled.
+//! - A watch CPU (the first AP, with `smp` and more than one CPU): the boot
+//!   CPU's tick count standing still for `TIMEOUT_MS` means it is stuck with
+//!   interrupts disabled, typically in an interrupt handler, where the tick
+//!   can't see it. The watch CPU sends it an NMI.
+//!
+//! Halting on purpose (`halt`, a panic, a fatal exception) calls `stop` first.
+
+use crate::sync::spinlock::Spinlock;
+use crate::time;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use shared::watchdog::StallDetector;
+
+/// Longest the kernel may go without progress
+pub const TIMEOUT_MS: u64 = 10_000;
+const TIMEOUT_TICKS: u64 = TIMEOUT_MS * time::TICK_HZ as u64 / 1000;
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Tick count at the last sign of progress
+static LAST_PROGRESS: AtomicU64 = AtomicU64::new(0);
+
+/// Set by the watch CPU just before its NMI, so the boot CPU can tell it
+/// from a hardware NMI
+static NMI_SENT: AtomicBool = AtomicBool::new(false);
+
+/// Only the watch CPU touches it
+static DETECTOR: Spinlock<StallDetector> = Spinlock::new(StallDetector::new(TIMEOUT_TICKS));
+
+/// Start watching; the tick must be running
+pub fn start() {
+    pet();
+    RUNNING.store(true, Ordering::Relaxed);
+}
+
+/// Stop watching for good, before halting on purpose
+pub fn stop() {
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+/// Record progress
+pub fn pet() {
+    LAST_PROGRESS.store(time::ticks(), Ordering::Relaxed);
+}
+
+/// Tick-side check, given whether the tick interrupted user code
+/// True if the kernel has made no progress for the timeout.
+pub fn tick(in_user_mode: bool) -> bool {
+    if in_user_mode {
+        pet();
+        return false;
+    }
+    RUNNING.load(Ordering::Relaxed)
+        && time::ticks().saturating_sub(LAST_PROGRESS.load(Ordering::Relaxed)) >= TIMEOUT_TICKS
+}
+
+/// Watch-CPU check, from its own timer
+/// True if the boot CPU's tick has stopped and it should get an NMI; only
+/// once, as the NMI handler resets the machine.
+pub fn watch() -> bool {
+    let stalled = DETECTOR.lock().sample(time::ticks());
+    stalled && RUNNING.load(Ordering::Relaxed) && !NMI_SENT.swap(true, Ordering::Relaxed)
+}
+
+/// True if an NMI came from the watch CPU
+pub fn nmi_sent() -> bool {
+    NMI_SENT.load(Ordering::Relaxed)
+}
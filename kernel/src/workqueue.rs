@@ -0,0 +1,74 @@
+//! Deferred work
+//! An interrupt handler does only what cannot wait (taking data off the
+//! device) and `schedule`s a `Work` item for the rest, which then runs with
+//! interrupts enabled. Pending items run where the interrupted code cannot
+//! be holding a lock: when the kernel goes idle (`time::idle`), and on return
+//! from an interrupt that arrived in user mode. Work can take ordinary locks
+//! as long as nothing holding them waits for the work itself.
+//! An item scheduled again before it runs runs once; one scheduled while it
+//! runs runs again afterwards. Items run one at a time, in no set order.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+pub struct Work {
+    func: fn(),
+    /// Set from `schedule` until the item is taken off the queue to run
+    queued: AtomicBool,
+    next: AtomicPtr<Work>,
+}
+
+impl Work {
+    pub const fn new(func: fn()) -> Self {
+        Work { func, queued: AtomicBool::new(false), next: AtomicPtr::new(ptr::null_mut()) }
+    }
+}
+
+/// Queued items, most recent first, linked through `Work::next`
+static QUEUE: AtomicPtr<Work> = AtomicPtr::new(ptr::null_mut());
+
+/// Set while `run_pending` is emptying the queue
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Queue `work` to run; safe in interrupt context, and takes no locks
+pub fn schedule(work: &'static Work) {
+    if work.queued.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let item = work as *const Work as *mut Work;
+    let mut head = QUEUE.load(Ordering::Relaxed);
+    loop {
+        work.next.store(head, Ordering::Relaxed);
+        match QUEUE.compare_exchange_weak(head, item, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(current) => head = current,
+        }
+    }
+}
+
+/// True if work is queued
+pub fn pending() -> bool {
+    !QUEUE.load(Ordering::Acquire).is_null()
+}
+
+/// Run every queued item, and any queued while they run
+/// Called with interrupts enabled and no locks held; returns at once if
+/// another caller is already at it.
+pub fn run_pending() {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    loop {
+        let mut next = QUEUE.swap(ptr::null_mut(), Ordering::Acquire);
+        if next.is_null() {
+            break;
+        }
+        // Items are `&'static` and only relinked once `queued` is cleared
+        while let Some(work) = unsafe { next.as_ref() } {
+            next = work.next.load(Ordering::Relaxed);
+            work.queued.store(false, Ordering::Release);
+            (work.func)();
+        }
+    }
+    RUNNING.store(false, Ordering::Release);
+}
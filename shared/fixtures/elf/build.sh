@@ -0,0 +1,10 @@
+#!/bin/sh
+# Regenerate the ELF fixtures from hello.S with GNU binutils
+set -e
+cd "$(dirname "$0")"
+as --64 hello.S -o hello.o
+ld -static -z noseparate-code --build-id=none -Ttext=0x400000 hello.o -o hello.elf
+ld -pie -z noseparate-code --build-id=none --no-dynamic-linker hello.o -o hello-pie.elf
+as --32 hello32.S -o hello32.o
+ld -m elf_i386 -static --build-id=none hello32.o -o hello32.elf
+rm -f hello.o hello32.o
@@ -0,0 +1,56 @@
+//! Feed arbitrary bytes through the shell's input path: both key decoders,
+//! the line editor, and the parser. Even bytes go to the serial decoder and
+//! odd ones to the PS/2 decoder so one input exercises both.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shared::bootfmt::BootBuffer;
+use shared::keyboard::{Decoder, TerminalDecoder};
+use shared::shell::{parse, parse_pipeline, Completer, LineEditor, LineEvent};
+
+const LINE_LENGTH: usize = 32;
+
+/// Offers the same two entries in every directory
+struct AnyDir;
+
+impl Completer for AnyDir {
+    fn list_dir(&mut self, _dir: &str, each: &mut dyn FnMut(&str, bool)) {
+        each("file", false);
+        each("folder", true);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut serial = TerminalDecoder::new();
+    let mut ps2 = Decoder::new();
+    let mut editor = LineEditor::<LINE_LENGTH>::new();
+    let mut echo = BootBuffer::<64>::new();
+
+    for (i, &byte) in data.iter().enumerate() {
+        let key = if i % 2 == 0 { serial.feed(byte) } else { ps2.feed(byte) };
+        let Some(key) = key else { continue };
+        echo.clear();
+        let event = editor.feed(key, &mut echo);
+
+        let line = editor.line();
+        assert!(line.len() <= LINE_LENGTH);
+        assert!(line.bytes().all(|b| b == b' ' || b.is_ascii_graphic()));
+        match event {
+            LineEvent::Submit => {
+                let _ = parse(line);
+                editor.clear();
+            }
+            LineEvent::Complete => {
+                editor.complete(&mut AnyDir, &mut echo);
+            }
+            _ => {}
+        }
+    }
+
+    // Arbitrary text straight into the parser, past what the editor allows
+    if let Ok(text) = core::str::from_utf8(data) {
+        let _ = parse(text);
+        let _ = parse_pipeline(text);
+    }
+});
@@ -0,0 +1,267 @@
+//! ACPI table parsing
+//! The RSDP points at the RSDT (32-bit table addresses) or, from ACPI 2.0,
+//! the XSDT (64-bit); either lists the other tables by physical address.
+//! Every table starts with the same 36-byte header and sums to zero
+//! bytewise. Only what power management needs is decoded: the FADT's PM1
+//! control and reset registers, and the sleep type of S5 (soft off) from
+//! the DSDT. The caller reads the tables out of memory.
+
+pub const HEADER_LEN: usize = 36;
+const RSDP_V1_LEN: usize = 20;
+const RSDP_V2_LEN: usize = 36;
+
+/// FADT flag: the reset register is valid
+const RESET_REG_SUP: u32 = 1 << 10;
+/// Generic address space of I/O ports
+pub const SPACE_SYSTEM_IO: u8 = 1;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u32_at(bytes, offset) as u64 | (u32_at(bytes, offset + 4) as u64) << 32
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Root System Description Pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub rsdt: u32,
+    /// 0 before ACPI 2.0
+    pub xsdt: u64,
+}
+
+impl Rsdp {
+    /// Bytes a valid RSDP occupies, for a caller reading it from memory
+    pub const MAX_LEN: usize = RSDP_V2_LEN;
+
+    /// Parse and checksum an RSDP; `bytes` may run past its end
+    pub fn parse(bytes: &[u8]) -> Option<Rsdp> {
+        if bytes.len() < RSDP_V1_LEN || &bytes[..8] != b"RSD PTR " || !checksum_ok(&bytes[..RSDP_V1_LEN]) {
+            return None;
+        }
+        let revision = bytes[15];
+        let rsdt = u32_at(bytes, 16);
+        if revision < 2 {
+            return Some(Rsdp { revision, rsdt, xsdt: 0 });
+        }
+        if bytes.len() < RSDP_V2_LEN || !checksum_ok(&bytes[..RSDP_V2_LEN]) {
+            return None;
+        }
+        Some(Rsdp { revision, rsdt, xsdt: u64_at(bytes, 24) })
+    }
+}
+
+/// Signature and total length from a table header
+pub fn header(bytes: &[u8]) -> Option<([u8; 4], usize)> {
+    if bytes.len() < HEADER_LEN {
+        return None;
+    }
+    Some(([bytes[0], bytes[1], bytes[2], bytes[3]], u32_at(bytes, 4) as usize))
+}
+
+/// True if `table` is as long as its header says and checksums
+pub fn valid(table: &[u8]) -> bool {
+    header(table).is_some_and(|(_, length)| length == table.len()) && checksum_ok(table)
+}
+
+/// Physical addresses of the tables an RSDT (`wide` false) or XSDT lists
+pub fn entries(table: &[u8], wide: bool) -> impl Iterator<Item = u64> + '_ {
+    let size = if wide { 8 } else { 4 };
+    table[HEADER_LEN.min(table.len())..]
+        .chunks_exact(size)
+        .map(move |entry| if wide { u64_at(entry, 0) } else { u32_at(entry, 0) as u64 })
+}
+
+/// The reset register and the value that resets the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetRegister {
+    /// `SPACE_SYSTEM_IO`, memory, or PCI configuration space
+    pub space: u8,
+    pub address: u64,
+    pub value: u8,
+}
+
+/// What power management needs from the FADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// Physical address of the DSDT
+    pub dsdt: u64,
+    /// Port that switches the firmware into ACPI mode, 0 if always in it
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    /// PM1 control register ports; b is 0 when there is only one
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub reset: Option<ResetRegister>,
+}
+
+impl Fadt {
+    pub fn parse(table: &[u8]) -> Option<Fadt> {
+        let (signature, _) = header(table)?;
+        if &signature != b"FACP" || table.len() < 72 {
+            return None;
+        }
+        let flags = if table.len() >= 116 { u32_at(table, 112) } else { 0 };
+        // The reset register arrived with FADT revision 2
+        let reset = (flags & RESET_REG_SUP != 0 && table.len() >= 129).then(|| ResetRegister {
+            space: table[116],
+            address: u64_at(table, 120),
+            value: table[128],
+        });
+        // X_DSDT supersedes DSDT when set
+        let x_dsdt = if table.len() >= 148 { u64_at(table, 140) } else { 0 };
+        Some(Fadt {
+            dsdt: if x_dsdt != 0 { x_dsdt } else { u32_at(table, 40) as u64 },
+            smi_cmd: u32_at(table, 48),
+            acpi_enable: table[52],
+            pm1a_control: u32_at(table, 64),
+            pm1b_control: u32_at(table, 68),
+            reset,
+        })
+    }
+}
+
+/// PM1 control: SCI_EN, set once the firmware is in ACPI mode
+pub const PM1_SCI_EN: u16 = 1;
+/// PM1 control: enter the sleep state in SLP_TYP
+pub const PM1_SLP_EN: u16 = 1 << 13;
+
+/// PM1 control value that enters sleep type `slp_typ`
+pub fn sleep_command(slp_typ: u8) -> u16 {
+    ((slp_typ as u16 & 0x7) << 10) | PM1_SLP_EN
+}
+
+/// SLP_TYP values for PM1a and PM1b that enter S5, from the DSDT
+/// Looks for the AML `Name (\_S5, Package () { a, b, ... })` rather than
+/// interpreting the DSDT: the object is static on every known firmware.
+pub fn s5_sleep_types(dsdt: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0a;
+
+    let body = dsdt.get(HEADER_LEN..)?;
+    let at = body.windows(4).enumerate().find_map(|(at, name)| {
+        let named = (at >= 1 && body[at - 1] == NAME_OP)
+            || (at >= 2 && body[at - 2] == NAME_OP && body[at - 1] == b'\\');
+        (name == b"_S5_" && named).then_some(at + 4)
+    })?;
+    let rest = body.get(at..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // PkgLength: its first byte's top two bits count the bytes after it;
+    // then NumElements
+    let mut pos = 1 + 1 + (*rest.get(1)? >> 6) as usize + 1;
+    let mut element = || {
+        if *rest.get(pos)? == BYTE_PREFIX {
+            pos += 1;
+        }
+        // ZeroOp and OneOp are their own values
+        let value = *rest.get(pos)?;
+        pos += 1;
+        Some(value)
+    };
+    let a = element()?;
+    let b = element()?;
+    Some((a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Set the checksum byte at `at` so `bytes[..len]` sums to zero
+    fn fix_checksum(bytes: &mut [u8], len: usize, at: usize) {
+        bytes[at] = 0;
+        let sum = bytes[..len].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        bytes[at] = sum.wrapping_neg();
+    }
+
+    fn table(signature: &[u8; 4], length: usize) -> [u8; 256] {
+        let mut bytes = [0u8; 256];
+        bytes[..4].copy_from_slice(signature);
+        bytes[4..8].copy_from_slice(&(length as u32).to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_rsdp_versions() {
+        let mut rsdp = [0u8; 36];
+        rsdp[..8].copy_from_slice(b"RSD PTR ");
+        rsdp[16..20].copy_from_slice(&0x7fe_1000u32.to_le_bytes());
+        fix_checksum(&mut rsdp, 20, 8);
+        assert_eq!(Rsdp::parse(&rsdp), Some(Rsdp { revision: 0, rsdt: 0x7fe_1000, xsdt: 0 }));
+
+        rsdp[15] = 2;
+        rsdp[24..32].copy_from_slice(&0x7fe_2000u64.to_le_bytes());
+        fix_checksum(&mut rsdp, 20, 8);
+        fix_checksum(&mut rsdp, 36, 32);
+        assert_eq!(Rsdp::parse(&rsdp), Some(Rsdp { revision: 2, rsdt: 0x7fe_1000, xsdt: 0x7fe_2000 }));
+
+        rsdp[30] ^= 1;
+        assert_eq!(Rsdp::parse(&rsdp), None);
+        assert_eq!(Rsdp::parse(b"RSD PTX                 "), None);
+    }
+
+    #[test]
+    fn test_root_table_entries() {
+        let mut rsdt = table(b"RSDT", HEADER_LEN + 8);
+        rsdt[36..40].copy_from_slice(&0x1000u32.to_le_bytes());
+        rsdt[40..44].copy_from_slice(&0x2000u32.to_le_bytes());
+        fix_checksum(&mut rsdt, HEADER_LEN + 8, 9);
+        let rsdt = &rsdt[..HEADER_LEN + 8];
+        assert!(valid(rsdt));
+        assert!(entries(rsdt, false).eq([0x1000, 0x2000]));
+        assert!(entries(rsdt, true).eq([0x2000_0000_1000]));
+        assert!(!valid(&rsdt[..HEADER_LEN]));
+    }
+
+    #[test]
+    fn test_fadt_fields() {
+        let mut fadt = table(b"FACP", 244);
+        fadt[40..44].copy_from_slice(&0x7fe_0040u32.to_le_bytes());
+        fadt[48..52].copy_from_slice(&0xb2u32.to_le_bytes());
+        fadt[52] = 0xf1;
+        fadt[64..68].copy_from_slice(&0x604u32.to_le_bytes());
+        let parsed = Fadt::parse(&fadt[..244]).unwrap();
+        assert_eq!((parsed.dsdt, parsed.smi_cmd, parsed.acpi_enable), (0x7fe_0040, 0xb2, 0xf1));
+        assert_eq!((parsed.pm1a_control, parsed.pm1b_control, parsed.reset), (0x604, 0, None));
+
+        // Reset register in I/O space, and an X_DSDT that overrides DSDT
+        fadt[112..116].copy_from_slice(&RESET_REG_SUP.to_le_bytes());
+        fadt[116] = SPACE_SYSTEM_IO;
+        fadt[120..128].copy_from_slice(&0xcf9u64.to_le_bytes());
+        fadt[128] = 0x06;
+        fadt[140..148].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        let parsed = Fadt::parse(&fadt[..244]).unwrap();
+        assert_eq!(parsed.reset, Some(ResetRegister { space: SPACE_SYSTEM_IO, address: 0xcf9, value: 6 }));
+        assert_eq!(parsed.dsdt, 0x1_0000_0000);
+
+        assert_eq!(Fadt::parse(&table(b"APIC", 244)), None);
+    }
+
+    #[test]
+    fn test_s5_sleep_types() {
+        let mut dsdt = [0u8; HEADER_LEN + 16];
+        // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero })
+        let aml = [0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x07, 0x04, 0x0a, 0x05, 0x00, 0x00, 0x00];
+        dsdt[HEADER_LEN..HEADER_LEN + aml.len()].copy_from_slice(&aml);
+        assert_eq!(s5_sleep_types(&dsdt), Some((5, 0)));
+
+        // Without the root prefix, both values byte-prefixed
+        let aml = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x04, 0x0a, 0x07, 0x0a, 0x07, 0x00, 0x00];
+        dsdt[HEADER_LEN..HEADER_LEN + aml.len()].copy_from_slice(&aml);
+        assert_eq!(s5_sleep_types(&dsdt), Some((7, 7)));
+
+        // A reference to _S5_ rather than its definition
+        dsdt[HEADER_LEN] = 0x70;
+        assert_eq!(s5_sleep_types(&dsdt), None);
+        assert_eq!(sleep_command(5), 0x3400);
+    }
+}
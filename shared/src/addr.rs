@@ -0,0 +1,313 @@
+//! Typed physical and virtual addresses
+//! `PhysAddr` and `VirtAddr` keep the two address spaces from being mixed up;
+//! crossing between them is always an explicit HHDM conversion.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub};
+
+/// Highest physical address width supported by x86_64 paging
+const PHYS_ADDR_BITS: u32 = 52;
+/// Virtual addresses are sign-extended from bit 47 (4-level paging)
+const VIRT_ADDR_BITS: u32 = 48;
+
+/// A physical memory address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct PhysAddr(u64);
+
+/// A canonical virtual memory address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct VirtAddr(u64);
+
+const fn align_down(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    addr & !(align - 1)
+}
+
+const fn align_up(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    (addr + align - 1) & !(align - 1)
+}
+
+impl PhysAddr {
+    /// Create a physical address, panicking if it exceeds 52 bits
+    pub const fn new(addr: u64) -> Self {
+        match Self::try_new(addr) {
+            Some(phys) => phys,
+            None => panic!("physical address exceeds 52 bits"),
+        }
+    }
+
+    pub const fn try_new(addr: u64) -> Option<Self> {
+        if addr >> PHYS_ADDR_BITS == 0 {
+            Some(PhysAddr(addr))
+        } else {
+            None
+        }
+    }
+
+    pub const fn zero() -> Self {
+        PhysAddr(0)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn align_down(self, align: u64) -> Self {
+        PhysAddr(align_down(self.0, align))
+    }
+
+    pub const fn align_up(self, align: u64) -> Self {
+        PhysAddr(align_up(self.0, align))
+    }
+
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Address of this physical location inside a direct map at `hhdm_offset`
+    pub const fn to_virt(self, hhdm_offset: u64) -> VirtAddr {
+        VirtAddr::new(hhdm_offset + self.0)
+    }
+}
+
+impl VirtAddr {
+    /// Create a virtual address, panicking if it is not canonical
+    pub const fn new(addr: u64) -> Self {
+        match Self::try_new(addr) {
+            Some(virt) => virt,
+            None => panic!("virtual address is not canonical"),
+        }
+    }
+
+    pub const fn try_new(addr: u64) -> Option<Self> {
+        if Self::is_canonical(addr) {
+            Some(VirtAddr(addr))
+        } else {
+            None
+        }
+    }
+
+    /// Create a virtual address, sign-extending bit 47 into the upper bits
+    pub const fn new_truncate(addr: u64) -> Self {
+        let shift = 64 - VIRT_ADDR_BITS;
+        VirtAddr((((addr << shift) as i64) >> shift) as u64)
+    }
+
+    /// True if bits 48-63 are copies of bit 47
+    pub const fn is_canonical(addr: u64) -> bool {
+        let upper = addr >> (VIRT_ADDR_BITS - 1);
+        upper == 0 || upper == (1 << (64 - VIRT_ADDR_BITS + 1)) - 1
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self::new(ptr as u64)
+    }
+
+    pub const fn zero() -> Self {
+        VirtAddr(0)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    pub const fn align_down(self, align: u64) -> Self {
+        VirtAddr(align_down(self.0, align))
+    }
+
+    pub const fn align_up(self, align: u64) -> Self {
+        VirtAddr::new_truncate(align_up(self.0, align))
+    }
+
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// True for addresses in the lower (user) half
+    pub const fn is_lower_half(self) -> bool {
+        self.0 >> (VIRT_ADDR_BITS - 1) == 0
+    }
+
+    /// PML4, PDPT, PD and PT indices for 4-level paging
+    pub const fn page_table_indices(self) -> [usize; 4] {
+        [
+            ((self.0 >> 39) & 0x1FF) as usize,
+            ((self.0 >> 30) & 0x1FF) as usize,
+            ((self.0 >> 21) & 0x1FF) as usize,
+            ((self.0 >> 12) & 0x1FF) as usize,
+        ]
+    }
+
+    /// Byte offset within a 4KB page
+    pub const fn page_offset(self) -> usize {
+        (self.0 & 0xFFF) as usize
+    }
+}
+
+impl Add<u64> for PhysAddr {
+    type Output = PhysAddr;
+
+    fn add(self, rhs: u64) -> PhysAddr {
+        PhysAddr::new(self.0 + rhs)
+    }
+}
+
+impl Add<usize> for PhysAddr {
+    type Output = PhysAddr;
+
+    fn add(self, rhs: usize) -> PhysAddr {
+        self + rhs as u64
+    }
+}
+
+impl AddAssign<usize> for PhysAddr {
+    fn add_assign(&mut self, rhs: usize) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<PhysAddr> for PhysAddr {
+    type Output = u64;
+
+    fn sub(self, rhs: PhysAddr) -> u64 {
+        self.0 - rhs.0
+    }
+}
+
+impl Add<u64> for VirtAddr {
+    type Output = VirtAddr;
+
+    fn add(self, rhs: u64) -> VirtAddr {
+        VirtAddr::new(self.0 + rhs)
+    }
+}
+
+impl Add<usize> for VirtAddr {
+    type Output = VirtAddr;
+
+    fn add(self, rhs: usize) -> VirtAddr {
+        self + rhs as u64
+    }
+}
+
+impl AddAssign<usize> for VirtAddr {
+    fn add_assign(&mut self, rhs: usize) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<VirtAddr> for VirtAddr {
+    type Output = u64;
+
+    fn sub(self, rhs: VirtAddr) -> u64 {
+        self.0 - rhs.0
+    }
+}
+
+impl fmt::Debug for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PhysAddr({:#x})", self.0)
+    }
+}
+
+impl fmt::Debug for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VirtAddr({:#x})", self.0)
+    }
+}
+
+impl fmt::LowerHex for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phys_rejects_too_wide() {
+        assert!(PhysAddr::try_new(0x000F_FFFF_FFFF_F000).is_some());
+        assert!(PhysAddr::try_new(1 << 52).is_none());
+    }
+
+    #[test]
+    fn test_phys_alignment() {
+        let phys = PhysAddr::new(0x1234);
+        assert_eq!(phys.align_down(0x1000), PhysAddr::new(0x1000));
+        assert_eq!(phys.align_up(0x1000), PhysAddr::new(0x2000));
+        assert!(!phys.is_aligned(0x1000));
+        assert!(PhysAddr::new(0x3000).is_aligned(0x1000));
+    }
+
+    #[test]
+    fn test_phys_to_virt() {
+        let hhdm = 0xFFFF_8000_0000_0000;
+        let virt = PhysAddr::new(0xB8000).to_virt(hhdm);
+        assert_eq!(virt.as_u64(), 0xFFFF_8000_000B_8000);
+    }
+
+    #[test]
+    fn test_canonical() {
+        assert!(VirtAddr::is_canonical(0x0000_7FFF_FFFF_FFFF));
+        assert!(VirtAddr::is_canonical(0xFFFF_8000_0000_0000));
+        assert!(!VirtAddr::is_canonical(0x0000_8000_0000_0000));
+        assert!(!VirtAddr::is_canonical(0xFFFF_7FFF_FFFF_FFFF));
+        assert!(VirtAddr::try_new(0x0000_8000_0000_0000).is_none());
+    }
+
+    #[test]
+    fn test_new_truncate_sign_extends() {
+        assert_eq!(VirtAddr::new_truncate(0x0000_8000_0000_0000).as_u64(), 0xFFFF_8000_0000_0000);
+        assert_eq!(VirtAddr::new_truncate(0x1234).as_u64(), 0x1234);
+    }
+
+    #[test]
+    fn test_lower_half() {
+        assert!(VirtAddr::new(0x40_0000).is_lower_half());
+        assert!(!VirtAddr::new(0xFFFF_8000_0000_0000).is_lower_half());
+    }
+
+    #[test]
+    fn test_page_table_indices() {
+        let virt = VirtAddr::new(0x0000_7FFF_FFFF_F123);
+        assert_eq!(virt.page_table_indices(), [255, 511, 511, 511]);
+        assert_eq!(virt.page_offset(), 0x123);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let base = VirtAddr::new(0x40_0000);
+        assert_eq!((base + 0x1000usize).as_u64(), 0x40_1000);
+        assert_eq!(VirtAddr::new(0x40_2000) - base, 0x2000);
+        let mut phys = PhysAddr::new(0x1000);
+        phys += 0x1000usize;
+        assert_eq!(phys, PhysAddr::new(0x2000));
+    }
+}
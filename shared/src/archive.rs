@@ -0,0 +1,299 @@
+//! Initial ramdisk archives: cpio ("newc") and tar (ustar)
+//! Both are what `cpio -H newc` and `tar --format=ustar` write, the two
+//! formats an initrd is commonly packed in. `Archive` detects which one it
+//! was given and walks its members in order, checking every offset against
+//! the image so a truncated archive yields an error instead of an
+//! out-of-bounds read. Paths come back relative ("./etc/rc" and "/etc/rc"
+//! are both "etc/rc"); unpacking them is left to the caller.
+
+use crate::bytes::ByteView;
+
+const CPIO_MAGIC: &[u8; 6] = b"070701";
+/// The same layout with a checksum field, which is not checked
+const CPIO_CRC_MAGIC: &[u8; 6] = b"070702";
+const CPIO_HEADER_SIZE: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+const TAR_BLOCK: usize = 512;
+const TAR_MAGIC: &[u8; 5] = b"ustar";
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_REGULAR: u32 = 0o100000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Cpio,
+    Tar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    /// Links, devices and the like, which an initrd has no use for
+    Other,
+}
+
+/// One member of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// Relative path, without a leading "./" or "/"
+    pub path: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+pub struct Archive<'a> {
+    image: ByteView<'a>,
+    format: Format,
+}
+
+impl<'a> Archive<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Self, &'static str> {
+        let view = ByteView::new(image);
+        let format = if matches!(view.bytes(0, 6), Some(magic) if magic == CPIO_MAGIC || magic == CPIO_CRC_MAGIC) {
+            Format::Cpio
+        } else if view.bytes(257, TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+            Format::Tar
+        } else {
+            return Err("Not a cpio or tar archive");
+        };
+        Ok(Archive { image: view, format })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Members in archive order; an error ends the walk
+    pub fn entries(&self) -> Entries<'a> {
+        Entries { image: self.image, format: self.format, offset: 0, done: false }
+    }
+}
+
+pub struct Entries<'a> {
+    image: ByteView<'a>,
+    format: Format,
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let next = match self.format {
+                Format::Cpio => self.next_cpio(),
+                Format::Tar => self.next_tar(),
+            };
+            match next {
+                // The archive's own "." is nothing to unpack
+                Ok(Some(entry)) if entry.path.is_empty() => continue,
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Entries<'a> {
+    fn next_cpio(&mut self) -> Result<Option<Entry<'a>>, &'static str> {
+        let header = self.image.bytes(self.offset, CPIO_HEADER_SIZE).ok_or("Truncated cpio header")?;
+        if &header[..6] != CPIO_MAGIC && &header[..6] != CPIO_CRC_MAGIC {
+            return Err("Bad cpio header magic");
+        }
+        // Thirteen 8-digit hex fields follow the magic
+        let field = |index: usize| parse_number(&header[6 + index * 8..14 + index * 8], 16).ok_or("Bad cpio header field");
+        let mode = field(1)? as u32;
+        let size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_offset = self.offset + CPIO_HEADER_SIZE;
+        let name = self.image.bytes(name_offset, name_size).ok_or("Truncated cpio name")?;
+        let name = core::str::from_utf8(name.strip_suffix(&[0]).unwrap_or(name)).map_err(|_| "cpio name is not UTF-8")?;
+        if name == CPIO_TRAILER {
+            return Ok(None);
+        }
+
+        // Name and data are each padded to 4 bytes
+        let data_offset = (name_offset + name_size).next_multiple_of(4);
+        let data = self.image.bytes(data_offset, size).ok_or("Truncated cpio data")?;
+        self.offset = (data_offset + size).next_multiple_of(4);
+
+        let kind = match mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => EntryKind::Directory,
+            MODE_REGULAR => EntryKind::File,
+            _ => EntryKind::Other,
+        };
+        Ok(Some(Entry { path: relative(name), kind, data }))
+    }
+
+    fn next_tar(&mut self) -> Result<Option<Entry<'a>>, &'static str> {
+        // Two zero blocks end the archive, though the end of the image will do
+        let header = match self.image.bytes(self.offset, TAR_BLOCK) {
+            Some(header) if header.iter().any(|&byte| byte != 0) => header,
+            _ => return Ok(None),
+        };
+        if &header[257..262] != TAR_MAGIC {
+            return Err("Bad tar header magic");
+        }
+        let checksum = parse_number(&header[148..156], 8).ok_or("Bad tar checksum field")?;
+        // The checksum is over the header with its own field read as spaces
+        let sum: u64 = header.iter().enumerate().map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as u64).sum();
+        if sum != checksum {
+            return Err("Bad tar header checksum");
+        }
+        let size = parse_number(&header[124..136], 8).ok_or("Bad tar size field")? as usize;
+
+        let name = c_field(&header[..100])?;
+        let prefix = c_field(&header[345..500])?;
+        let data_offset = self.offset + TAR_BLOCK;
+        let data = self.image.bytes(data_offset, size).ok_or("Truncated tar data")?;
+        self.offset = data_offset + size.next_multiple_of(TAR_BLOCK);
+
+        if !prefix.is_empty() {
+            // Splitting a long path across prefix and name would need an
+            // allocation to put back together
+            return Err("tar paths over 100 bytes are not supported");
+        }
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            _ => EntryKind::Other,
+        };
+        Ok(Some(Entry { path: relative(name), kind, data }))
+    }
+}
+
+/// An ASCII number in `radix`, as cpio (hex) and tar (octal, NUL or space
+/// terminated) store them
+fn parse_number(field: &[u8], radix: u32) -> Option<u64> {
+    let digits = field.split(|&byte| byte == 0 || byte == b' ').find(|digits| !digits.is_empty())?;
+    u64::from_str_radix(core::str::from_utf8(digits).ok()?, radix).ok()
+}
+
+/// A NUL-padded text field
+fn c_field(field: &[u8]) -> Result<&str, &'static str> {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..end]).map_err(|_| "tar name is not UTF-8")
+}
+
+/// `path` without leading "./" or "/", or trailing "/"
+fn relative(path: &str) -> &str {
+    let mut path = path;
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+    let path = path.trim_end_matches('/');
+    if path == "." {
+        ""
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::format;
+    use std::vec::Vec;
+
+    fn cpio_member(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let fields = [0, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+        archive.extend_from_slice(CPIO_MAGIC);
+        for field in fields {
+            archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    fn tar_member(archive: &mut Vec<u8>, name: &str, typeflag: u8, data: &[u8]) {
+        let mut header = [0u8; TAR_BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(TAR_BLOCK), 0);
+    }
+
+    fn collect<'a>(archive: &Archive<'a>) -> Vec<Entry<'a>> {
+        archive.entries().collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_cpio_members() {
+        let mut image = Vec::new();
+        cpio_member(&mut image, ".", MODE_DIRECTORY | 0o755, b"");
+        cpio_member(&mut image, "etc", MODE_DIRECTORY | 0o755, b"");
+        cpio_member(&mut image, "etc/rc", MODE_REGULAR | 0o644, b"echo hi\n");
+        cpio_member(&mut image, "bin/sh", 0o120777, b"busybox");
+        cpio_member(&mut image, CPIO_TRAILER, 0, b"");
+
+        let archive = Archive::parse(&image).unwrap();
+        assert_eq!(archive.format(), Format::Cpio);
+        let entries = collect(&archive);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], Entry { path: "etc", kind: EntryKind::Directory, data: b"" });
+        assert_eq!(entries[1], Entry { path: "etc/rc", kind: EntryKind::File, data: b"echo hi\n" });
+        assert_eq!(entries[2].kind, EntryKind::Other);
+    }
+
+    #[test]
+    fn test_tar_members() {
+        let mut image = Vec::new();
+        tar_member(&mut image, "./", b'5', b"");
+        tar_member(&mut image, "./etc/", b'5', b"");
+        tar_member(&mut image, "./etc/keys", b'0', &[b'k'; 600]);
+        image.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+
+        let archive = Archive::parse(&image).unwrap();
+        assert_eq!(archive.format(), Format::Tar);
+        let entries = collect(&archive);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], Entry { path: "etc", kind: EntryKind::Directory, data: b"" });
+        assert_eq!(entries[1].path, "etc/keys");
+        assert_eq!(entries[1].data.len(), 600);
+    }
+
+    #[test]
+    fn test_damaged_archives_are_errors() {
+        assert!(Archive::parse(b"hello").is_err());
+
+        let mut image = Vec::new();
+        cpio_member(&mut image, "big", MODE_REGULAR, &[1; 64]);
+        image.truncate(image.len() - 32);
+        let archive = Archive::parse(&image).unwrap();
+        assert_eq!(archive.entries().next(), Some(Err("Truncated cpio data")));
+
+        let mut image = Vec::new();
+        tar_member(&mut image, "file", b'0', b"data");
+        image[0] = b'F';
+        let archive = Archive::parse(&image).unwrap();
+        assert_eq!(archive.entries().collect::<Vec<_>>(), [Err("Bad tar header checksum")]);
+    }
+}
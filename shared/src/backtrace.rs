@@ -0,0 +1,209 @@
+//! Stack traces
+//! The kernel is built with frame pointers forced on, so every frame saves
+//! its caller's frame pointer and its return address at fixed offsets from
+//! its own (`FrameLayout`). `Frames` follows that chain through a
+//! caller-supplied reader, which decides what memory is safe to touch.
+//! `Demangled` turns the symbol names found for the return addresses back
+//! into Rust paths.
+
+use core::fmt;
+
+/// Deepest trace `Frames` yields, in case the chain loops
+pub const MAX_FRAMES: usize = 32;
+
+/// Where a frame keeps its caller's frame pointer and its return address,
+/// as offsets from its own frame pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    pub caller_fp: i64,
+    pub return_addr: i64,
+}
+
+impl FrameLayout {
+    /// `push rbp; mov rbp, rsp`: the saved rbp at [rbp], the return address above it
+    pub const X86_64: FrameLayout = FrameLayout { caller_fp: 0, return_addr: 8 };
+    /// The frame pointer points just past the saved ra and fp
+    pub const RISCV: FrameLayout = FrameLayout { caller_fp: -16, return_addr: -8 };
+}
+
+/// Return addresses up the stack, innermost first
+pub struct Frames<F> {
+    fp: u64,
+    layout: FrameLayout,
+    read: F,
+    depth: usize,
+}
+
+impl<F: FnMut(u64) -> Option<u64>> Frames<F> {
+    /// Walk from frame pointer `fp`; `read` returns the u64 at an address, or
+    /// `None` if it is not safe to read
+    pub fn new(fp: u64, layout: FrameLayout, read: F) -> Self {
+        Frames { fp, layout, read, depth: 0 }
+    }
+}
+
+impl<F: FnMut(u64) -> Option<u64>> Iterator for Frames<F> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.fp == 0 || !self.fp.is_multiple_of(8) || self.depth == MAX_FRAMES {
+            return None;
+        }
+        let caller_fp = (self.read)(self.fp.checked_add_signed(self.layout.caller_fp)?)?;
+        let return_addr = (self.read)(self.fp.checked_add_signed(self.layout.return_addr)?)?;
+        if return_addr == 0 {
+            return None;
+        }
+
+        // Callers' frames sit higher on the stack; anything else is garbage
+        self.fp = if caller_fp > self.fp { caller_fp } else { 0 };
+        self.depth += 1;
+        Some(return_addr)
+    }
+}
+
+/// A symbol name, printed as a Rust path if it uses the legacy mangling
+/// (`_ZN` ... `E`) and as-is otherwise. The trailing hash is left out.
+pub struct Demangled<'a>(pub &'a str);
+
+impl fmt::Display for Demangled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match components(self.0) {
+            Some(parts) => {
+                for (i, part) in parts.enumerate() {
+                    if i > 0 {
+                        f.write_str("::")?;
+                    }
+                    write_unescaped(f, part)?;
+                }
+                Ok(())
+            }
+            None => f.write_str(self.0),
+        }
+    }
+}
+
+/// Path components of a legacy-mangled name, without the hash
+fn components(name: &str) -> Option<impl Iterator<Item = &str>> {
+    let mut rest = name.strip_prefix("_ZN")?.strip_suffix('E')?;
+    // Check the whole name parses before printing any of it
+    let mut count = 0;
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        rest = rest.get(digits + len..)?;
+        count += 1;
+    }
+
+    let mut rest = &name[3..name.len() - 1];
+    let parts = (0..count).map(move |_| {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().unwrap_or(0);
+        let part = &rest[digits..digits + len];
+        rest = &rest[digits + len..];
+        part
+    });
+    Some(parts.filter(|part| !is_hash(part)))
+}
+
+/// The `h` + 16 hex digits rustc appends to keep symbols unique
+fn is_hash(part: &str) -> bool {
+    part.len() == 17 && part.starts_with('h') && part[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn write_unescaped(f: &mut fmt::Formatter<'_>, part: &str) -> fmt::Result {
+    // A leading underscore only keeps an escape from starting the identifier
+    let mut rest = match part.strip_prefix('_') {
+        Some(stripped) if stripped.starts_with('$') => stripped,
+        _ => part,
+    };
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = after;
+        } else if let Some((escape, after)) = rest.strip_prefix('$').and_then(|r| r.split_once('$')) {
+            match unescape(escape) {
+                Some(c) => write!(f, "{}", c)?,
+                None => write!(f, "${}$", escape)?,
+            }
+            rest = after;
+        } else {
+            let end = rest.char_indices().skip(1).find(|&(_, c)| c == '$' || c == '.').map_or(rest.len(), |(i, _)| i);
+            f.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
+
+fn unescape(escape: &str) -> Option<char> {
+    Some(match escape {
+        "SP" => '@',
+        "BP" => '*',
+        "RF" => '&',
+        "LT" => '<',
+        "GT" => '>',
+        "LP" => '(',
+        "RP" => ')',
+        "C" => ',',
+        _ => char::from_u32(u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn test_walks_frame_chain() {
+        // Three frames at 0x1000 -> 0x1040 -> 0x1080, the last with no caller
+        let memory = [(0x1000, 0x1040), (0x1008, 0xAAA), (0x1040, 0x1080), (0x1048, 0xBBB), (0x1080, 0), (0x1088, 0xCCC)];
+        let read = |addr| memory.iter().find(|&&(a, _)| a == addr).map(|&(_, v)| v);
+        assert!(Frames::new(0x1000, FrameLayout::X86_64, read).eq([0xAAA, 0xBBB, 0xCCC]));
+    }
+
+    #[test]
+    fn test_riscv_layout() {
+        // Frames at 0x1010 -> 0x1050, each with ra at fp - 8 and fp at fp - 16
+        let memory = [(0x1000, 0x1050), (0x1008, 0xAAA), (0x1040, 0), (0x1048, 0xBBB)];
+        let read = |addr| memory.iter().find(|&&(a, _)| a == addr).map(|&(_, v)| v);
+        assert!(Frames::new(0x1010, FrameLayout::RISCV, read).eq([0xAAA, 0xBBB]));
+    }
+
+    #[test]
+    fn test_stops_on_bad_frames() {
+        // Unreadable memory ends the trace
+        assert_eq!(Frames::new(0x1000, FrameLayout::X86_64, |_| None).count(), 0);
+        // A caller frame below the current one is not followed
+        let memory = [(0x1000, 0x800), (0x1008, 0xAAA)];
+        let read = |addr| memory.iter().find(|&&(a, _)| a == addr).map(|&(_, v)| v);
+        assert!(Frames::new(0x1000, FrameLayout::X86_64, read).eq([0xAAA]));
+        // Misaligned and null frame pointers are never read
+        assert_eq!(Frames::new(0x1004, FrameLayout::X86_64, |_| Some(0x2000)).count(), 0);
+        assert_eq!(Frames::new(0, FrameLayout::X86_64, |_| Some(0x2000)).count(), 0);
+        // A loop is cut off
+        let mut fp = 0x1000;
+        let read = |addr: u64| Some(if addr.is_multiple_of(16) { fp += 16; fp } else { 0xAAA });
+        assert_eq!(Frames::new(0x1000, FrameLayout::X86_64, read).count(), MAX_FRAMES);
+    }
+
+    #[test]
+    fn test_demangle() {
+        let show = |name| format!("{}", Demangled(name));
+        assert_eq!(show("_ZN6kernel5panic17h0123456789abcdefE"), "kernel::panic");
+        assert_eq!(
+            show("_ZN4core3ptr42drop_in_place$LT$alloc..string..String$GT$17h0123456789abcdefE"),
+            "core::ptr::drop_in_place<alloc::string::String>"
+        );
+        assert_eq!(
+            show("_ZN55_$LT$kernel..log..Level$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE"),
+            "<kernel::log::Level as core::fmt::Debug>::fmt"
+        );
+        // Not mangled, or mangled some other way: left alone
+        assert_eq!(show("_start"), "_start");
+        assert_eq!(show("_ZN5brokenE"), "_ZN5brokenE");
+        assert_eq!(show("_RNvCs1234_6kernel5panic"), "_RNvCs1234_6kernel5panic");
+    }
+}
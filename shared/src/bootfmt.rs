@@ -0,0 +1,145 @@
+//! Fixed-capacity formatting buffer
+//! Formats into a stack array and silently truncates on overflow, so callers
+//! on the early-boot and panic paths can format without allocating or failing
+
+use core::fmt;
+
+pub struct BootBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> Default for BootBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BootBuffer<N> {
+    pub const fn new() -> Self {
+        BootBuffer {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Formatted text so far (always valid UTF-8, cut at a char boundary)
+    pub fn as_str(&self) -> &str {
+        // Only whole UTF-8 sequences are ever copied in
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// True if some output was dropped because the buffer filled up
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.truncated = false;
+    }
+}
+
+impl<const N: usize> fmt::Write for BootBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+
+        let space = N - self.len;
+        let mut take = s.len().min(space);
+        if take < s.len() {
+            // Back off to a char boundary so the buffer stays valid UTF-8
+            while !s.is_char_boundary(take) {
+                take -= 1;
+            }
+            self.truncated = true;
+        }
+
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+
+        // Never report an error: a failing Write would make `write_fmt` bail
+        // out (or panic in callers that unwrap), which is exactly what the
+        // panic path must avoid.
+        Ok(())
+    }
+}
+
+/// Format `args` into a new buffer of capacity `N`
+pub fn format<const N: usize>(args: fmt::Arguments) -> BootBuffer<N> {
+    use fmt::Write;
+    let mut buffer = BootBuffer::new();
+    let _ = buffer.write_fmt(args);
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_new_buffer_is_empty() {
+        let buffer: BootBuffer<16> = BootBuffer::new();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_str(), "");
+        assert!(!buffer.is_truncated());
+    }
+
+    #[test]
+    fn test_format_fits() {
+        let buffer: BootBuffer<32> = format(format_args!("x = {}", 42));
+        assert_eq!(buffer.as_str(), "x = 42");
+        assert!(!buffer.is_truncated());
+    }
+
+    #[test]
+    fn test_format_truncates() {
+        let buffer: BootBuffer<8> = format(format_args!("{}", "hello world"));
+        assert_eq!(buffer.as_str(), "hello wo");
+        assert_eq!(buffer.len(), 8);
+        assert!(buffer.is_truncated());
+    }
+
+    #[test]
+    fn test_truncation_respects_char_boundary() {
+        let mut buffer: BootBuffer<4> = BootBuffer::new();
+        // 'é' is two bytes; only one byte of space remains after "abc"
+        assert!(write!(buffer, "abcé").is_ok());
+        assert_eq!(buffer.as_str(), "abc");
+        assert!(buffer.is_truncated());
+    }
+
+    #[test]
+    fn test_no_writes_after_truncation() {
+        let mut buffer: BootBuffer<4> = BootBuffer::new();
+        assert!(write!(buffer, "abcé").is_ok());
+        assert!(write!(buffer, "d").is_ok());
+        assert_eq!(buffer.as_str(), "abc");
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut buffer: BootBuffer<4> = format(format_args!("overflow"));
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert!(!buffer.is_truncated());
+        assert!(write!(buffer, "ok").is_ok());
+        assert_eq!(buffer.as_bytes(), b"ok");
+    }
+}
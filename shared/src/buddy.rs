@@ -0,0 +1,218 @@
+//! Buddy allocator bookkeeping
+//! Keeps free blocks of 2^order consecutive indices (frames, for the frame
+//! allocator) on one free list per order. Allocating splits the smallest free
+//! block that is big enough; freeing merges a block with its buddy, the other
+//! half of the block the two were split from, for as long as the buddy is
+//! free as well. Both take at most `MAX_ORDER` steps. Blocks are aligned to
+//! their size, so the buddy of the block at `index` is at `index ^ size`.
+//! The lists are linked through arrays indexed by a block's first index, so
+//! nothing is written to the memory being managed. What is in use is the
+//! caller's to track: freeing an index that is already free corrupts the lists.
+//! Indices can be split into pools (memory zones, for the frame allocator),
+//! each with its own lists, so a caller can ask for a block from a
+//! particular pool. The caller says which pool each index belongs to, and
+//! must keep pools from sharing any `MAX_BLOCK`-aligned stretch of indices,
+//! so a block never merges with a buddy from another pool.
+
+/// Largest block: 2^10 indices (4 MB of frames)
+pub const MAX_ORDER: usize = 10;
+pub const MAX_BLOCK: usize = 1 << MAX_ORDER;
+
+/// Links and orders are stored plus one, so that zero means none and a new
+/// allocator is all zeros
+const NONE: u32 = 0;
+
+pub struct Buddy<const N: usize, const POOLS: usize = 1> {
+    /// First block of each order's free list, per pool
+    heads: [[u32; MAX_ORDER + 1]; POOLS],
+    next: [u32; N],
+    prev: [u32; N],
+    /// Order of the free block starting at each index, or none
+    order: [u8; N],
+    free: [usize; POOLS],
+}
+
+impl<const N: usize, const POOLS: usize> Default for Buddy<N, POOLS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const POOLS: usize> Buddy<N, POOLS> {
+    /// An allocator with nothing free; see `free_range`
+    pub const fn new() -> Self {
+        Buddy {
+            heads: [[NONE; MAX_ORDER + 1]; POOLS],
+            next: [NONE; N],
+            prev: [NONE; N],
+            order: [NONE as u8; N],
+            free: [0; POOLS],
+        }
+    }
+
+    /// Indices currently free in `pool`
+    pub fn free_count(&self, pool: usize) -> usize {
+        self.free[pool]
+    }
+
+    /// Take a block of 2^`order` indices from `pool` and return its first index
+    pub fn alloc(&mut self, pool: usize, order: usize) -> Option<usize> {
+        let found = (order..=MAX_ORDER).find(|&o| self.heads[pool][o] != NONE)?;
+        let index = self.heads[pool][found] as usize - 1;
+        self.remove(pool, index, found);
+        // Give back the upper half at each split down to the wanted order
+        for split in (order..found).rev() {
+            self.push(pool, index + (1 << split), split);
+        }
+        self.free[pool] -= 1 << order;
+        Some(index)
+    }
+
+    /// Take `count` consecutive indices from `pool`, at most `MAX_BLOCK`, and
+    /// return the first; the rest of the block they come from stays free
+    pub fn alloc_run(&mut self, pool: usize, count: usize) -> Option<usize> {
+        if count == 0 || count > MAX_BLOCK {
+            return None;
+        }
+        let order = count.next_power_of_two().trailing_zeros() as usize;
+        let index = self.alloc(pool, order)?;
+        self.free_range(pool, index + count, (1 << order) - count);
+        Some(index)
+    }
+
+    /// Return the block of 2^`order` indices at `index` to `pool`; the block
+    /// must be aligned to its size. It is merged with free buddies.
+    pub fn free(&mut self, pool: usize, index: usize, order: usize) {
+        self.free[pool] += 1 << order;
+        let (mut index, mut order) = (index, order);
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if buddy >= N || self.order[buddy] != order as u8 + 1 {
+                break;
+            }
+            self.remove(pool, buddy, order);
+            index &= !(1 << order);
+            order += 1;
+        }
+        self.push(pool, index, order);
+    }
+
+    /// Return `count` indices from `start` to `pool`, in the largest aligned
+    /// blocks that fit
+    pub fn free_range(&mut self, pool: usize, start: usize, count: usize) {
+        let end = start + count;
+        let mut index = start;
+        while index < end {
+            let align = if index == 0 { MAX_ORDER } else { index.trailing_zeros() as usize };
+            let fits = (end - index).ilog2() as usize;
+            let order = align.min(fits).min(MAX_ORDER);
+            self.free(pool, index, order);
+            index += 1 << order;
+        }
+    }
+
+    fn push(&mut self, pool: usize, index: usize, order: usize) {
+        let head = self.heads[pool][order];
+        if head != NONE {
+            self.prev[head as usize - 1] = index as u32 + 1;
+        }
+        self.next[index] = head;
+        self.prev[index] = NONE;
+        self.heads[pool][order] = index as u32 + 1;
+        self.order[index] = order as u8 + 1;
+    }
+
+    fn remove(&mut self, pool: usize, index: usize, order: usize) {
+        let (prev, next) = (self.prev[index], self.next[index]);
+        if prev == NONE {
+            self.heads[pool][order] = next;
+        } else {
+            self.next[prev as usize - 1] = next;
+        }
+        if next != NONE {
+            self.prev[next as usize - 1] = prev;
+        }
+        self.order[index] = NONE as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_split_and_merge() {
+        let mut buddy = Buddy::<MAX_BLOCK>::new();
+        buddy.free_range(0, 0, MAX_BLOCK);
+        assert_eq!(buddy.free_count(0), MAX_BLOCK);
+
+        // Splitting the block hands out its lowest indices first
+        assert_eq!(buddy.alloc(0, 0), Some(0));
+        assert_eq!(buddy.alloc(0, 0), Some(1));
+        assert_eq!(buddy.alloc(0, 2), Some(4));
+        assert_eq!(buddy.free_count(0), MAX_BLOCK - 6);
+
+        // Freed in any order, the pieces merge back into the whole block
+        buddy.free(0, 4, 2);
+        buddy.free(0, 1, 0);
+        buddy.free(0, 0, 0);
+        assert_eq!(buddy.free_count(0), MAX_BLOCK);
+        assert_eq!(buddy.alloc(0, MAX_ORDER), Some(0));
+        assert_eq!(buddy.alloc(0, 0), None);
+    }
+
+    #[test]
+    fn test_run_keeps_the_tail_free() {
+        let mut buddy = Buddy::<64>::new();
+        buddy.free_range(0, 0, 64);
+        assert_eq!(buddy.alloc_run(0, 5), Some(0));
+        assert_eq!(buddy.free_count(0), 59);
+        // Indices 5..8 were the tail of the 8-block and are handed out next
+        assert_eq!(buddy.alloc(0, 0), Some(5));
+        assert_eq!(buddy.alloc_run(0, 2), Some(6));
+        assert_eq!(buddy.alloc_run(0, 0), None);
+        assert_eq!(buddy.alloc_run(0, MAX_BLOCK + 1), None);
+    }
+
+    #[test]
+    fn test_frames_freed_one_by_one_merge() {
+        let mut buddy = Buddy::<16>::new();
+        buddy.free_range(0, 0, 16);
+        assert_eq!(buddy.alloc_run(0, 16), Some(0));
+        for index in (0..16).rev() {
+            buddy.free(0, index, 0);
+        }
+        assert_eq!(buddy.alloc(0, 4), Some(0));
+    }
+
+    #[test]
+    fn test_unaligned_ranges_stay_apart() {
+        // Two ranges with a gap: blocks never cover the gap
+        let mut buddy = Buddy::<64>::new();
+        buddy.free_range(0, 3, 10);
+        buddy.free_range(0, 14, 18);
+        assert_eq!(buddy.free_count(0), 28);
+        assert_eq!(buddy.alloc(0, 4), Some(16));
+        assert_eq!(buddy.alloc(0, 3), None);
+        let singles: Vec<usize> = core::iter::from_fn(|| buddy.alloc(0, 0)).collect();
+        assert_eq!(singles.len(), 12);
+        assert!(!singles.contains(&13) && singles.iter().all(|index| (3..32).contains(index)));
+    }
+
+    #[test]
+    fn test_pools_are_separate() {
+        let mut buddy = Buddy::<{ 2 * MAX_BLOCK }, 2>::new();
+        buddy.free_range(0, 0, 8);
+        buddy.free_range(1, MAX_BLOCK, 16);
+        assert_eq!((buddy.free_count(0), buddy.free_count(1)), (8, 16));
+        // Pool 0 has no 16-block; pool 1 has, and its blocks come from its range
+        assert_eq!(buddy.alloc(0, 4), None);
+        assert_eq!(buddy.alloc(1, 0), Some(MAX_BLOCK));
+        assert_eq!(buddy.alloc_run(0, 8), Some(0));
+        assert_eq!(buddy.alloc(0, 0), None);
+        buddy.free(1, MAX_BLOCK, 0);
+        assert_eq!(buddy.alloc(1, 4), Some(MAX_BLOCK));
+    }
+}
@@ -0,0 +1,185 @@
+//! Byte-order aware field access for on-wire and on-disk formats
+//! `le` and `be` read and write integers at fixed offsets; like slice
+//! indexing they panic when out of range, for callers that have already
+//! checked the length. `ByteView` is the checked form for untrusted input: a
+//! borrowed view whose getters return None instead of reading past the end.
+
+macro_rules! byte_order {
+    ($(#[$attr:meta])* $order:ident, $from:ident, $to:ident) => {
+        $(#[$attr])*
+        pub mod $order {
+            pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+                u16::$from(super::array(bytes, offset))
+            }
+
+            pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+                u32::$from(super::array(bytes, offset))
+            }
+
+            pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+                u64::$from(super::array(bytes, offset))
+            }
+
+            pub fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+                bytes[offset..offset + 2].copy_from_slice(&value.$to());
+            }
+
+            pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+                bytes[offset..offset + 4].copy_from_slice(&value.$to());
+            }
+
+            pub fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+                bytes[offset..offset + 8].copy_from_slice(&value.$to());
+            }
+        }
+    };
+}
+
+byte_order!(
+    /// Little-endian fields (FAT, GPT, ELF on x86_64 and RISC-V)
+    le, from_le_bytes, to_le_bytes
+);
+byte_order!(
+    /// Big-endian fields (network byte order)
+    be, from_be_bytes, to_be_bytes
+);
+
+fn array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+/// Bounds-checked, zero-copy reads from a byte slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteView<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteView<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        ByteView { bytes }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub const fn as_slice(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// `len` bytes at `offset`
+    pub fn bytes(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.bytes.get(offset..offset.checked_add(len)?)
+    }
+
+    /// A view of `len` bytes at `offset`, for nested structures
+    pub fn sub(&self, offset: usize, len: usize) -> Option<ByteView<'a>> {
+        self.bytes(offset, len).map(ByteView::new)
+    }
+
+    /// `N` bytes at `offset`, copied out (magic numbers, identifiers)
+    pub fn array<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.bytes(offset, N).map(|bytes| bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(offset).copied()
+    }
+
+    pub fn u16_le(&self, offset: usize) -> Option<u16> {
+        self.array(offset).map(u16::from_le_bytes)
+    }
+
+    pub fn u32_le(&self, offset: usize) -> Option<u32> {
+        self.array(offset).map(u32::from_le_bytes)
+    }
+
+    pub fn u64_le(&self, offset: usize) -> Option<u64> {
+        self.array(offset).map(u64::from_le_bytes)
+    }
+
+    pub fn u16_be(&self, offset: usize) -> Option<u16> {
+        self.array(offset).map(u16::from_be_bytes)
+    }
+
+    pub fn u32_be(&self, offset: usize) -> Option<u32> {
+        self.array(offset).map(u32::from_be_bytes)
+    }
+
+    pub fn u64_be(&self, offset: usize) -> Option<u64> {
+        self.array(offset).map(u64::from_be_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: [u8; 8] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+
+    #[test]
+    fn test_little_endian() {
+        assert_eq!(le::read_u16(&DATA, 1), 0x0302);
+        assert_eq!(le::read_u32(&DATA, 0), 0x0403_0201);
+        assert_eq!(le::read_u64(&DATA, 0), 0x0807_0605_0403_0201);
+    }
+
+    #[test]
+    fn test_big_endian() {
+        assert_eq!(be::read_u16(&DATA, 1), 0x0203);
+        assert_eq!(be::read_u32(&DATA, 4), 0x0506_0708);
+        assert_eq!(be::read_u64(&DATA, 0), 0x0102_0304_0506_0708);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let mut buf = [0u8; 8];
+        le::write_u32(&mut buf, 2, 0xAABB_CCDD);
+        assert_eq!(buf, [0, 0, 0xDD, 0xCC, 0xBB, 0xAA, 0, 0]);
+        assert_eq!(le::read_u32(&buf, 2), 0xAABB_CCDD);
+
+        be::write_u16(&mut buf, 0, 0x1234);
+        assert_eq!(&buf[..2], &[0x12, 0x34]);
+        be::write_u64(&mut buf, 0, 0x0102_0304_0506_0708);
+        assert_eq!(buf, DATA);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_read_past_end_panics() {
+        le::read_u32(&DATA, 6);
+    }
+
+    #[test]
+    fn test_view_reads() {
+        let view = ByteView::new(&DATA);
+        assert_eq!(view.u8(7), Some(0x08));
+        assert_eq!(view.u16_be(0), Some(0x0102));
+        assert_eq!(view.u32_le(4), Some(0x0807_0605));
+        assert_eq!(view.u64_be(0), Some(0x0102_0304_0506_0708));
+        assert_eq!(view.array::<3>(1), Some([0x02, 0x03, 0x04]));
+    }
+
+    #[test]
+    fn test_view_out_of_range() {
+        let view = ByteView::new(&DATA);
+        assert_eq!(view.u8(8), None);
+        assert_eq!(view.u32_le(5), None);
+        assert_eq!(view.u64_le(1), None);
+        assert_eq!(view.bytes(usize::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_sub_view() {
+        let view = ByteView::new(&DATA);
+        let sub = view.sub(4, 4).unwrap();
+        assert_eq!(sub.len(), 4);
+        assert_eq!(sub.u16_le(2), Some(0x0807));
+        assert_eq!(sub.u16_le(3), None);
+        assert!(view.sub(6, 4).is_none());
+    }
+}
@@ -0,0 +1,430 @@
+//! Monotonic clock
+//! Time is the periodic tick count plus how far the current tick has run,
+//! measured either by a free-running cycle counter (TSC, `time` CSR) or, if
+//! that can't be trusted, by the tick timer's own sub-tick count.
+//!
+//! The cycle counter is only ever used to interpolate within one tick, so
+//! calibration error can't accumulate. Its frequency is measured against the
+//! tick unless the hardware states it (CPUID on x86, the timebase on RISC-V),
+//! in which case the counter is used from the start. It is dropped for the tick source when
+//! the CPU doesn't promise a constant rate, or when it is seen to stall
+//! across a tick (counters that stop in deep C-states). A counter that jumps
+//! backwards, as the TSC does when reset across suspend, is re-anchored at
+//! the next tick. Readings never go backwards in any case.
+//!
+//! An idle kernel may stretch the tick so one interrupt stands for several
+//! ticks; `idle_period` picks how far, and `RateCounter` shows the effect.
+
+/// Ticks used to measure the cycle counter's frequency
+pub const CALIBRATION_TICKS: u64 = 25;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// Ticks plus the tick timer's sub-tick count
+    Tick,
+    /// Ticks plus calibrated cycle counter progress since the last tick
+    Cycles,
+}
+
+pub struct MonotonicClock {
+    tick_ns: u64,
+    ticks: u64,
+    /// Whether the counter claims a constant rate; cleared if it stalls
+    invariant: bool,
+    source: ClockSource,
+    cycles_hz: u64,
+    /// The frequency was given to `start` rather than measured
+    stated_hz: bool,
+    /// Cycle counter reading at the last tick
+    tick_cycles: u64,
+    /// Tick count and cycle reading when calibration started
+    calibration: Option<(u64, u64)>,
+    resyncs: u64,
+    last_ns: u64,
+}
+
+impl MonotonicClock {
+    pub const fn new(tick_hz: u32) -> Self {
+        MonotonicClock {
+            tick_ns: NANOS_PER_SEC / tick_hz as u64,
+            ticks: 0,
+            invariant: false,
+            source: ClockSource::Tick,
+            cycles_hz: 0,
+            stated_hz: false,
+            tick_cycles: 0,
+            calibration: None,
+            resyncs: 0,
+            last_ns: 0,
+        }
+    }
+
+    /// Start counting; `invariant` says whether the cycle counter may be used
+    /// and `stated_hz` is its frequency if the hardware reports it, which
+    /// skips measuring it
+    pub fn start(&mut self, invariant: bool, cycles: u64, stated_hz: Option<u64>) {
+        self.invariant = invariant;
+        self.tick_cycles = cycles;
+        match stated_hz.filter(|&hz| hz > 0) {
+            Some(hz) => {
+                self.cycles_hz = hz;
+                self.stated_hz = true;
+                self.calibration = None;
+                if invariant {
+                    self.source = ClockSource::Cycles;
+                }
+            }
+            None => self.calibration = Some((self.ticks, cycles)),
+        }
+    }
+
+    /// Account for a tick interrupt standing for `ticks` ticks, `cycles` read in its handler
+    pub fn on_tick(&mut self, cycles: u64, ticks: u64) {
+        let previous = self.tick_cycles;
+        self.ticks += ticks;
+        self.tick_cycles = cycles;
+
+        if cycles < previous {
+            // Counter reset (suspend) or went backwards
+            self.resync(cycles);
+            return;
+        }
+
+        if let Some((start_tick, start_cycles)) = self.calibration {
+            let elapsed = self.ticks - start_tick;
+            if elapsed >= CALIBRATION_TICKS {
+                let hz = (cycles - start_cycles) as u128 * NANOS_PER_SEC as u128
+                    / (elapsed * self.tick_ns) as u128;
+                self.cycles_hz = hz as u64;
+                self.calibration = None;
+                if self.invariant && self.cycles_hz > 0 {
+                    self.source = ClockSource::Cycles;
+                }
+            }
+            return;
+        }
+
+        // A counter advancing less than half the period has stopped for a while
+        if self.source == ClockSource::Cycles && self.cycles_to_ns(cycles - previous) < ticks * self.tick_ns / 2 {
+            self.invariant = false;
+            self.source = ClockSource::Tick;
+        }
+    }
+
+    /// Current time in nanoseconds
+    /// `sub_tick_ns` is the tick timer's progress since the last tick and
+    /// `cycles` the cycle counter, both read just now.
+    pub fn now(&mut self, sub_tick_ns: u64, cycles: u64) -> u64 {
+        let into_tick = match self.source {
+            // Can exceed a tick while the tick is stretched
+            ClockSource::Tick => sub_tick_ns,
+            ClockSource::Cycles => self.cycles_to_ns(cycles.saturating_sub(self.tick_cycles)),
+        };
+        let ns = (self.ticks * self.tick_ns + into_tick).max(self.last_ns);
+        self.last_ns = ns;
+        ns
+    }
+
+    /// Forget the cycle counter's anchor, e.g. after resuming from suspend
+    /// Time continues from the tick count until the counter is recalibrated;
+    /// a stated frequency still holds, so the counter is only re-anchored.
+    pub fn resync(&mut self, cycles: u64) {
+        self.resyncs += 1;
+        self.tick_cycles = cycles;
+        if !self.stated_hz {
+            self.source = ClockSource::Tick;
+            self.calibration = Some((self.ticks, cycles));
+        }
+    }
+
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    /// Cycle counter frequency, 0 until calibrated
+    pub fn cycles_hz(&self) -> u64 {
+        self.cycles_hz
+    }
+
+    /// True if the frequency was stated by the hardware, not measured
+    pub fn cycles_hz_stated(&self) -> bool {
+        self.stated_hz
+    }
+
+    /// Times the cycle counter had to be re-anchored
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    fn cycles_to_ns(&self, cycles: u64) -> u64 {
+        if self.cycles_hz == 0 {
+            return 0;
+        }
+        (cycles as u128 * NANOS_PER_SEC as u128 / self.cycles_hz as u128) as u64
+    }
+}
+
+/// A timer the idle loop has to wake up for: due at tick `at`, and fine to
+/// run up to `slack` ticks late
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    pub at: u64,
+    pub slack: u64,
+}
+
+/// Ticks per timer interrupt to sleep with at tick `now`, at most `max`
+/// A new period starts at the next tick. It ends at the latest point every
+/// deadline's slack allows, so deadlines with overlapping windows are all
+/// served by that one wakeup.
+pub fn idle_period(now: u64, deadlines: &[Deadline], max: u64) -> u64 {
+    let next_tick = now + 1;
+    deadlines
+        .iter()
+        .map(|deadline| (deadline.at + deadline.slack).saturating_sub(next_tick))
+        .min()
+        .unwrap_or(max)
+        .clamp(1, max.max(1))
+}
+
+/// Events per window (a second's worth of ticks), for wakeup statistics
+#[derive(Debug, Clone, Copy)]
+pub struct RateCounter {
+    window: u64,
+    elapsed: u64,
+    count: u64,
+    last: u64,
+    total: u64,
+}
+
+impl RateCounter {
+    pub const fn new(window_ticks: u64) -> Self {
+        RateCounter { window: window_ticks, elapsed: 0, count: 0, last: 0, total: 0 }
+    }
+
+    pub fn record(&mut self) {
+        self.count += 1;
+        self.total += 1;
+    }
+
+    /// Move time on by `ticks`, closing the window once it is full
+    pub fn advance(&mut self, ticks: u64) {
+        self.elapsed += ticks;
+        if self.elapsed >= self.window {
+            // A stretched tick can overshoot the window: scale back to one window
+            self.last = self.count * self.window / self.elapsed;
+            self.count = 0;
+            self.elapsed = 0;
+        }
+    }
+
+    /// Events in the last complete window
+    pub fn last_window(&self) -> u64 {
+        self.last
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 100;
+    const TICK_NS: u64 = 10_000_000;
+    // 1 GHz: one cycle per nanosecond keeps expectations readable
+    const CYCLES_PER_TICK: u64 = 10_000_000;
+
+    fn calibrated(invariant: bool) -> (MonotonicClock, u64) {
+        let mut clock = MonotonicClock::new(HZ);
+        let mut cycles = 5_000;
+        clock.start(invariant, cycles, None);
+        for _ in 0..CALIBRATION_TICKS {
+            cycles += CYCLES_PER_TICK;
+            clock.on_tick(cycles, 1);
+        }
+        (clock, cycles)
+    }
+
+    #[test]
+    fn test_calibration_picks_cycles_only_if_invariant() {
+        let (clock, _) = calibrated(true);
+        assert_eq!(clock.source(), ClockSource::Cycles);
+        assert_eq!(clock.cycles_hz(), 1_000_000_000);
+
+        let (clock, _) = calibrated(false);
+        assert_eq!(clock.source(), ClockSource::Tick);
+        assert_eq!(clock.cycles_hz(), 1_000_000_000);
+    }
+
+    #[test]
+    fn test_tick_source_uses_sub_tick_count() {
+        let mut clock = MonotonicClock::new(HZ);
+        clock.start(false, 0, None);
+        assert_eq!(clock.now(1_000, 0), 1_000);
+        clock.on_tick(0, 1);
+        assert_eq!(clock.now(2_000, 0), TICK_NS + 2_000);
+        // A stretched tick: the sub-tick count runs past a whole tick
+        assert_eq!(clock.now(3 * TICK_NS, 0), 4 * TICK_NS);
+        // The interrupt ending it doesn't pull time back
+        clock.on_tick(0, 4);
+        assert_eq!(clock.now(0, 0), 5 * TICK_NS);
+        assert_eq!(clock.now(1_000, 0), 5 * TICK_NS + 1_000);
+    }
+
+    #[test]
+    fn test_stretched_tick_is_not_a_stall() {
+        let (mut clock, mut cycles) = calibrated(true);
+        let base = CALIBRATION_TICKS * TICK_NS;
+        cycles += 5 * CYCLES_PER_TICK;
+        clock.on_tick(cycles, 5);
+        assert_eq!(clock.source(), ClockSource::Cycles);
+        assert_eq!(clock.now(0, cycles + 7), base + 5 * TICK_NS + 7);
+
+        // One tick's worth of cycles over five ticks: the counter stopped
+        cycles += CYCLES_PER_TICK;
+        clock.on_tick(cycles, 5);
+        assert_eq!(clock.source(), ClockSource::Tick);
+    }
+
+    #[test]
+    fn test_idle_period() {
+        let timer = |at, slack| Deadline { at, slack };
+        assert_eq!(idle_period(10, &[], 5), 5);
+        assert_eq!(idle_period(10, &[timer(14, 0)], 50), 3);
+        // Already due, or due at the next tick: the shortest period
+        assert_eq!(idle_period(10, &[timer(3, 0)], 50), 1);
+        assert_eq!(idle_period(10, &[timer(11, 0)], 50), 1);
+        // The tighter window decides, and the other timer shares its wakeup
+        assert_eq!(idle_period(10, &[timer(12, 20), timer(20, 5)], 50), 14);
+        assert_eq!(idle_period(10, &[timer(12, 100)], 50), 50);
+    }
+
+    #[test]
+    fn test_rate_counter() {
+        let mut rate = RateCounter::new(100);
+        for _ in 0..100 {
+            rate.record();
+            rate.advance(1);
+        }
+        assert_eq!(rate.last_window(), 100);
+
+        // Six-tick interrupts: the 17th overshoots the window by two ticks
+        for _ in 0..17 {
+            rate.record();
+            rate.advance(6);
+        }
+        assert_eq!(rate.last_window(), 16);
+        assert_eq!(rate.total(), 117);
+    }
+
+    #[test]
+    fn test_cycles_interpolate_within_tick() {
+        let (mut clock, cycles) = calibrated(true);
+        let base = CALIBRATION_TICKS * TICK_NS;
+        assert_eq!(clock.now(0, cycles + 1_234), base + 1_234);
+        assert_eq!(clock.now(0, cycles + 5_000_000), base + 5_000_000);
+    }
+
+    #[test]
+    fn test_never_goes_backwards() {
+        let mut clock = MonotonicClock::new(HZ);
+        clock.start(false, 0, None);
+        let late = clock.now(9_000_000, 0);
+        // The timer reloaded but the tick hasn't been handled yet
+        assert_eq!(clock.now(100, 0), late);
+        clock.on_tick(0, 1);
+        assert!(clock.now(100, 0) > late);
+    }
+
+    #[test]
+    fn test_stalled_counter_falls_back_to_ticks() {
+        let (mut clock, mut cycles) = calibrated(true);
+        // Stopped for most of a tick in a deep C-state
+        cycles += CYCLES_PER_TICK / 10;
+        clock.on_tick(cycles, 1);
+        assert_eq!(clock.source(), ClockSource::Tick);
+
+        // Even a well-behaved counter isn't trusted again
+        clock.resync(cycles);
+        for _ in 0..CALIBRATION_TICKS {
+            cycles += CYCLES_PER_TICK;
+            clock.on_tick(cycles, 1);
+        }
+        assert_eq!(clock.source(), ClockSource::Tick);
+    }
+
+    #[test]
+    fn test_counter_reset_recalibrates() {
+        let (mut clock, cycles) = calibrated(true);
+        let before = clock.now(0, cycles + 9_000_000);
+
+        // Resume from suspend: the counter restarted near zero
+        let mut cycles = 100;
+        clock.on_tick(cycles, 1);
+        assert_eq!(clock.resyncs(), 1);
+        assert_eq!(clock.source(), ClockSource::Tick);
+        let after = clock.now(0, cycles);
+        assert!(after >= before);
+
+        for _ in 0..CALIBRATION_TICKS {
+            cycles += CYCLES_PER_TICK;
+            clock.on_tick(cycles, 1);
+        }
+        assert_eq!(clock.source(), ClockSource::Cycles);
+        assert!(clock.now(0, cycles + 10) > after);
+    }
+
+    #[test]
+    fn test_stated_frequency_needs_no_calibration() {
+        let mut clock = MonotonicClock::new(HZ);
+        clock.start(true, 5_000, Some(1_000_000_000));
+        assert_eq!((clock.source(), clock.cycles_hz()), (ClockSource::Cycles, 1_000_000_000));
+        assert!(clock.cycles_hz_stated());
+        assert_eq!(clock.now(0, 6_234), 1_234);
+
+        // After a counter reset it is re-anchored and used right away
+        clock.on_tick(5_000 + CYCLES_PER_TICK, 1);
+        clock.on_tick(100, 1);
+        assert_eq!(clock.resyncs(), 1);
+        assert_eq!(clock.source(), ClockSource::Cycles);
+        assert_eq!(clock.now(0, 600), 2 * TICK_NS + 500);
+
+        // Not invariant: the tick source, though the frequency is known
+        let mut clock = MonotonicClock::new(HZ);
+        clock.start(false, 0, Some(1_000_000_000));
+        assert_eq!(clock.source(), ClockSource::Tick);
+    }
+
+    #[test]
+    fn test_random_readings_are_monotonic() {
+        let (mut clock, mut cycles) = calibrated(true);
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut last = 0;
+        for _ in 0..10_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            match seed % 8 {
+                0 => {
+                    let ticks = 1 + seed % 5;
+                    cycles += seed % (2 * ticks * CYCLES_PER_TICK);
+                    clock.on_tick(cycles, ticks);
+                }
+                1 => {
+                    cycles = seed % 1_000;
+                    clock.on_tick(cycles, 1);
+                }
+                2 => clock.resync(cycles),
+                _ => {
+                    let now = clock.now(seed % (2 * TICK_NS), cycles + seed % (3 * CYCLES_PER_TICK));
+                    assert!(now >= last);
+                    last = now;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Kernel command line parsing
+//! The command line is a list of options separated by spaces, each either
+//! `key=value` or a bare flag (`no-framebuffer`). Values can't contain
+//! spaces; there is no quoting. A key given twice takes its last value, as
+//! with Linux, so a setting can be overridden by appending to the line.
+
+#[derive(Clone, Copy)]
+pub struct Cmdline<'a> {
+    line: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    pub const fn new(line: &'a str) -> Self {
+        Cmdline { line }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.line
+    }
+
+    /// Every option in order, as (key, value); flags have no value
+    pub fn options(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.line.split_ascii_whitespace().map(|option| match option.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (option, None),
+        })
+    }
+
+    /// Value of the last `key=value`; `Some("")` for a bare `key`
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.options().filter(|&(name, _)| name == key).last().map(|(_, value)| value.unwrap_or(""))
+    }
+
+    /// Whether `name` is given, bare or with any value
+    pub fn flag(&self, name: &str) -> bool {
+        self.options().any(|(key, _)| key == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_and_flags() {
+        let cmdline = Cmdline::new("  loglevel=debug no-framebuffer  console=serial ");
+        assert_eq!(cmdline.get("loglevel"), Some("debug"));
+        assert_eq!(cmdline.get("console"), Some("serial"));
+        assert_eq!(cmdline.get("no-framebuffer"), Some(""));
+        assert!(cmdline.flag("no-framebuffer"));
+        assert!(!cmdline.flag("quiet"));
+        assert_eq!(cmdline.get("quiet"), None);
+    }
+
+    #[test]
+    fn test_last_value_wins() {
+        let cmdline = Cmdline::new("loglevel=warn root=/dev/a=b loglevel=trace");
+        assert_eq!(cmdline.get("loglevel"), Some("trace"));
+        // Only the first '=' separates key and value
+        assert_eq!(cmdline.get("root"), Some("/dev/a=b"));
+        assert_eq!(cmdline.options().count(), 3);
+    }
+
+    #[test]
+    fn test_empty_line() {
+        let cmdline = Cmdline::new("");
+        assert_eq!(cmdline.options().count(), 0);
+        assert!(!cmdline.flag(""));
+    }
+}
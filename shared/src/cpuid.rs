@@ -0,0 +1,385 @@
+//! CPUID decoding
+//! `CpuInfo` reads the leaves that identify an x86 processor (vendor, brand
+//! string, family/model/stepping) and the feature bits worth reporting,
+//! through a caller-supplied function that executes CPUID. Decoding is kept
+//! apart from the instruction so it can be tested with recorded values.
+//! It also reads the TSC frequency where the CPU (or hypervisor) states it,
+//! which saves measuring it. `CpuFeatures` has the bits the kernel acts on
+//! as fields, for code that must check one before using it.
+
+/// The feature-bit registers `CpuInfo` keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Word {
+    /// Leaf 1 ECX and EDX
+    Leaf1Ecx,
+    Leaf1Edx,
+    /// Leaf 7 (subleaf 0) EBX and ECX
+    Leaf7Ebx,
+    Leaf7Ecx,
+    /// Leaf 0x8000_0001 EDX
+    ExtEdx,
+    /// Leaf 0x8000_0007 EDX
+    PowerEdx,
+}
+
+const WORDS: usize = 6;
+
+/// Reported features as (register, bit, name), in the order they are listed
+const FEATURES: &[(Word, u32, &str)] = &[
+    (Word::Leaf1Edx, 0, "fpu"),
+    (Word::Leaf1Edx, 4, "tsc"),
+    (Word::Leaf1Edx, 5, "msr"),
+    (Word::Leaf1Edx, 6, "pae"),
+    (Word::Leaf1Edx, 9, "apic"),
+    (Word::Leaf1Edx, 13, "pge"),
+    (Word::Leaf1Edx, 15, "cmov"),
+    (Word::Leaf1Edx, 16, "pat"),
+    (Word::Leaf1Edx, 19, "clflush"),
+    (Word::Leaf1Edx, 23, "mmx"),
+    (Word::Leaf1Edx, 24, "fxsr"),
+    (Word::Leaf1Edx, 25, "sse"),
+    (Word::Leaf1Edx, 26, "sse2"),
+    (Word::Leaf1Edx, 28, "htt"),
+    (Word::Leaf1Ecx, 0, "sse3"),
+    (Word::Leaf1Ecx, 1, "pclmulqdq"),
+    (Word::Leaf1Ecx, 9, "ssse3"),
+    (Word::Leaf1Ecx, 12, "fma"),
+    (Word::Leaf1Ecx, 13, "cx16"),
+    (Word::Leaf1Ecx, 19, "sse4.1"),
+    (Word::Leaf1Ecx, 20, "sse4.2"),
+    (Word::Leaf1Ecx, 21, "x2apic"),
+    (Word::Leaf1Ecx, 22, "movbe"),
+    (Word::Leaf1Ecx, 23, "popcnt"),
+    (Word::Leaf1Ecx, 24, "tsc-deadline"),
+    (Word::Leaf1Ecx, 25, "aes"),
+    (Word::Leaf1Ecx, 26, "xsave"),
+    (Word::Leaf1Ecx, 28, "avx"),
+    (Word::Leaf1Ecx, 30, "rdrand"),
+    (Word::Leaf1Ecx, 31, "hypervisor"),
+    (Word::Leaf7Ebx, 0, "fsgsbase"),
+    (Word::Leaf7Ebx, 3, "bmi1"),
+    (Word::Leaf7Ebx, 5, "avx2"),
+    (Word::Leaf7Ebx, 7, "smep"),
+    (Word::Leaf7Ebx, 8, "bmi2"),
+    (Word::Leaf7Ebx, 9, "erms"),
+    (Word::Leaf7Ebx, 10, "invpcid"),
+    (Word::Leaf7Ebx, 16, "avx512f"),
+    (Word::Leaf7Ebx, 18, "rdseed"),
+    (Word::Leaf7Ebx, 20, "smap"),
+    (Word::Leaf7Ebx, 29, "sha"),
+    (Word::Leaf7Ecx, 2, "umip"),
+    (Word::Leaf7Ecx, 3, "pku"),
+    (Word::ExtEdx, 11, "syscall"),
+    (Word::ExtEdx, 20, "nx"),
+    (Word::ExtEdx, 26, "pdpe1gb"),
+    (Word::ExtEdx, 27, "rdtscp"),
+    (Word::ExtEdx, 29, "lm"),
+    (Word::PowerEdx, 8, "invtsc"),
+];
+
+const LEAF_VENDOR: u32 = 0;
+const LEAF_SIGNATURE: u32 = 1;
+const LEAF_EXTENDED_FEATURES: u32 = 7;
+/// TSC to core crystal clock ratio, and the crystal frequency
+const LEAF_TSC: u32 = 0x15;
+/// Processor base frequency in MHz
+const LEAF_FREQUENCY: u32 = 0x16;
+const LEAF_HYPERVISOR_MAX: u32 = 0x4000_0000;
+/// Hypervisor timing information (VMware's, also offered by KVM and QEMU): TSC kHz
+const LEAF_HYPERVISOR_TIMING: u32 = 0x4000_0010;
+const LEAF_EXT_MAX: u32 = 0x8000_0000;
+const LEAF_EXT_FEATURES: u32 = 0x8000_0001;
+const LEAF_BRAND: u32 = 0x8000_0002;
+const LEAF_POWER: u32 = 0x8000_0007;
+
+/// Features the kernel checks before relying on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuFeatures {
+    pub sse: bool,
+    pub sse2: bool,
+    pub avx: bool,
+    /// No-execute page table bit
+    pub nx: bool,
+    /// 1 GiB pages in the PDPT
+    pub huge_pages_1g: bool,
+    pub rdrand: bool,
+    pub rdseed: bool,
+    /// Local APIC, and its x2APIC (MSR) mode
+    pub apic: bool,
+    pub x2apic: bool,
+    /// TSC ticks at a constant rate in every power state
+    pub invariant_tsc: bool,
+}
+
+impl CpuFeatures {
+    /// Each feature with its name as `CpuInfo::features` lists it
+    pub fn named(&self) -> [(&'static str, bool); 10] {
+        [
+            ("sse", self.sse),
+            ("sse2", self.sse2),
+            ("avx", self.avx),
+            ("nx", self.nx),
+            ("pdpe1gb", self.huge_pages_1g),
+            ("rdrand", self.rdrand),
+            ("rdseed", self.rdseed),
+            ("apic", self.apic),
+            ("x2apic", self.x2apic),
+            ("invtsc", self.invariant_tsc),
+        ]
+    }
+}
+
+/// What CPUID says about the processor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    /// All zeros if the brand string leaves are missing
+    brand: [u8; 48],
+    /// Leaf 1 EAX: stepping, model, and family fields
+    signature: u32,
+    words: [u32; WORDS],
+    /// 0 if the CPU doesn't say
+    tsc_hz: u64,
+}
+
+impl CpuInfo {
+    /// Decode the processor through `cpuid(leaf, subleaf)`, which returns
+    /// [EAX, EBX, ECX, EDX]; leaves past the maximum the CPU reports are not asked for
+    pub fn read(mut cpuid: impl FnMut(u32, u32) -> [u32; 4]) -> Self {
+        let mut info = CpuInfo { vendor: [0; 12], brand: [0; 48], signature: 0, words: [0; WORDS], tsc_hz: 0 };
+
+        let [max_leaf, ebx, ecx, edx] = cpuid(LEAF_VENDOR, 0);
+        // The vendor string is spread over EBX, EDX, ECX in that order
+        for (chunk, reg) in info.vendor.chunks_mut(4).zip([ebx, edx, ecx]) {
+            chunk.copy_from_slice(&reg.to_le_bytes());
+        }
+        if max_leaf >= LEAF_SIGNATURE {
+            let [eax, _, ecx, edx] = cpuid(LEAF_SIGNATURE, 0);
+            info.signature = eax;
+            info.words[Word::Leaf1Ecx as usize] = ecx;
+            info.words[Word::Leaf1Edx as usize] = edx;
+        }
+        if max_leaf >= LEAF_EXTENDED_FEATURES {
+            let [_, ebx, ecx, _] = cpuid(LEAF_EXTENDED_FEATURES, 0);
+            info.words[Word::Leaf7Ebx as usize] = ebx;
+            info.words[Word::Leaf7Ecx as usize] = ecx;
+        }
+        info.tsc_hz = read_tsc_hz(&mut cpuid, max_leaf, info.has("hypervisor"));
+
+        let max_ext = cpuid(LEAF_EXT_MAX, 0)[0];
+        if max_ext >= LEAF_EXT_FEATURES {
+            info.words[Word::ExtEdx as usize] = cpuid(LEAF_EXT_FEATURES, 0)[3];
+        }
+        if max_ext >= LEAF_BRAND + 2 {
+            for (i, chunk) in info.brand.chunks_mut(16).enumerate() {
+                let regs = cpuid(LEAF_BRAND + i as u32, 0);
+                for (bytes, reg) in chunk.chunks_mut(4).zip(regs) {
+                    bytes.copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+        if max_ext >= LEAF_POWER {
+            info.words[Word::PowerEdx as usize] = cpuid(LEAF_POWER, 0)[3];
+        }
+        info
+    }
+
+    /// "GenuineIntel", "AuthenticAMD", "TCGTCGTCGTCG" (QEMU TCG), ...
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    /// The marketing name, padding trimmed; None if the CPU has none
+    pub fn brand(&self) -> Option<&str> {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        let brand = core::str::from_utf8(&self.brand[..len]).ok()?.trim();
+        (!brand.is_empty()).then_some(brand)
+    }
+
+    /// Family, with the extended family added for family 0xF
+    pub fn family(&self) -> u32 {
+        let base = (self.signature >> 8) & 0xF;
+        if base == 0xF {
+            base + ((self.signature >> 20) & 0xFF)
+        } else {
+            base
+        }
+    }
+
+    /// Model, with the extended model bits for families 6 and 0xF
+    pub fn model(&self) -> u32 {
+        let base = (self.signature >> 4) & 0xF;
+        let base_family = (self.signature >> 8) & 0xF;
+        if base_family == 6 || base_family == 0xF {
+            base | ((self.signature >> 16) & 0xF) << 4
+        } else {
+            base
+        }
+    }
+
+    pub fn stepping(&self) -> u32 {
+        self.signature & 0xF
+    }
+
+    /// Names of the supported features, from the list this module knows
+    pub fn features(&self) -> impl Iterator<Item = &'static str> + '_ {
+        FEATURES
+            .iter()
+            .filter(|&&(word, bit, _)| self.words[word as usize] & (1 << bit) != 0)
+            .map(|&(_, _, name)| name)
+    }
+
+    /// True if the feature called `name` (as listed by `features`) is supported
+    pub fn has(&self, name: &str) -> bool {
+        self.features().any(|feature| feature == name)
+    }
+
+    pub fn cpu_features(&self) -> CpuFeatures {
+        let bit = |word: Word, bit: u32| self.words[word as usize] & (1 << bit) != 0;
+        CpuFeatures {
+            sse: bit(Word::Leaf1Edx, 25),
+            sse2: bit(Word::Leaf1Edx, 26),
+            avx: bit(Word::Leaf1Ecx, 28),
+            nx: bit(Word::ExtEdx, 20),
+            huge_pages_1g: bit(Word::ExtEdx, 26),
+            rdrand: bit(Word::Leaf1Ecx, 30),
+            rdseed: bit(Word::Leaf7Ebx, 18),
+            apic: bit(Word::Leaf1Edx, 9),
+            x2apic: bit(Word::Leaf1Ecx, 21),
+            invariant_tsc: bit(Word::PowerEdx, 8),
+        }
+    }
+
+    /// TSC frequency in Hz, if the CPU or the hypervisor states it
+    pub fn tsc_hz(&self) -> Option<u64> {
+        (self.tsc_hz != 0).then_some(self.tsc_hz)
+    }
+}
+
+/// The stated TSC frequency, or 0
+/// A hypervisor's figure comes first: it is what the guest's TSC runs at,
+/// whatever the host CPU's leaves say. Leaf 0x15 gives the TSC as a ratio of
+/// the crystal clock; CPUs that leave the crystal frequency out run the TSC
+/// at the base frequency of leaf 0x16.
+fn read_tsc_hz(cpuid: &mut impl FnMut(u32, u32) -> [u32; 4], max_leaf: u32, hypervisor: bool) -> u64 {
+    if hypervisor && cpuid(LEAF_HYPERVISOR_MAX, 0)[0] >= LEAF_HYPERVISOR_TIMING {
+        let khz = cpuid(LEAF_HYPERVISOR_TIMING, 0)[0];
+        if khz != 0 {
+            return u64::from(khz) * 1000;
+        }
+    }
+    if max_leaf < LEAF_TSC {
+        return 0;
+    }
+    let [denominator, numerator, crystal_hz, _] = cpuid(LEAF_TSC, 0);
+    if denominator == 0 || numerator == 0 {
+        return 0;
+    }
+    if crystal_hz != 0 {
+        return u64::from(crystal_hz) * u64::from(numerator) / u64::from(denominator);
+    }
+    if max_leaf >= LEAF_FREQUENCY {
+        return u64::from(cpuid(LEAF_FREQUENCY, 0)[0] & 0xFFFF) * 1_000_000;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    /// Leaves of a QEMU-style virtual CPU
+    fn qemu64(leaf: u32, _subleaf: u32) -> [u32; 4] {
+        match leaf {
+            0 => [0xd, 0x6874_7541, 0x444d_4163, 0x6974_6e65],
+            1 => [0x0000_0663, 0x0000_0800, 0x8020_2001, 0x078b_fbfd],
+            7 => [0, 0, 0, 0],
+            0x8000_0000 => [0x8000_000a, 0, 0, 0],
+            0x8000_0001 => [0x0000_0663, 0, 0x0000_0001, 0x2010_0800],
+            // "QEMU Virtual CPU version 2.5+"
+            0x8000_0002 => [0x554d_4551, 0x7269_5620, 0x6c61_7574, 0x5550_4320],
+            0x8000_0003 => [0x7265_7620, 0x6e6f_6973, 0x352e_3220, 0x0000_002b],
+            0x8000_0004 => [0, 0, 0, 0],
+            _ => [0, 0, 0, 0],
+        }
+    }
+
+    #[test]
+    fn test_identifies_cpu() {
+        let cpu = CpuInfo::read(qemu64);
+        assert_eq!(cpu.vendor(), "AuthenticAMD");
+        assert_eq!(cpu.brand(), Some("QEMU Virtual CPU version 2.5+"));
+        assert_eq!((cpu.family(), cpu.model(), cpu.stepping()), (6, 6, 3));
+    }
+
+    #[test]
+    fn test_features() {
+        let cpu = CpuInfo::read(qemu64);
+        let features: Vec<&str> = cpu.features().collect();
+        assert!(features.starts_with(&["fpu", "tsc", "msr", "pae", "apic"]));
+        assert!(cpu.has("sse2") && cpu.has("hypervisor") && cpu.has("nx") && cpu.has("lm"));
+        assert!(!cpu.has("avx") && !cpu.has("invtsc") && !cpu.has("no-such-feature"));
+    }
+
+    #[test]
+    fn test_cpu_features_match_names() {
+        let cpu = CpuInfo::read(qemu64);
+        let features = cpu.cpu_features();
+        assert!(features.sse2 && features.nx && features.x2apic && !features.avx && !features.invariant_tsc);
+        for (name, supported) in features.named() {
+            assert_eq!(cpu.has(name), supported, "{}", name);
+        }
+        assert_eq!(CpuInfo::read(|_, _| [0; 4]).cpu_features(), CpuFeatures::default());
+    }
+
+    #[test]
+    fn test_extended_family_and_model() {
+        // Family 6 model 0x9E (Coffee Lake) and family 0x17 (Zen)
+        let intel = |leaf, _| if leaf == 1 { [0x0009_06ea, 0, 0, 0] } else { [1, 0, 0, 0] };
+        let cpu = CpuInfo::read(intel);
+        assert_eq!((cpu.family(), cpu.model(), cpu.stepping()), (6, 0x9E, 0xA));
+        let amd = |leaf, _| if leaf == 1 { [0x0080_0f82, 0, 0, 0] } else { [1, 0, 0, 0] };
+        assert_eq!(CpuInfo::read(amd).family(), 0x17);
+    }
+
+    #[test]
+    fn test_missing_leaves() {
+        // Only leaf 0: nothing else is asked for or reported
+        let cpu = CpuInfo::read(|leaf, _| {
+            assert!(leaf == 0 || leaf == 0x8000_0000, "leaf {:#x} read", leaf);
+            [0; 4]
+        });
+        assert_eq!(cpu.brand(), None);
+        assert_eq!(cpu.features().count(), 0);
+        assert_eq!(cpu.tsc_hz(), None);
+    }
+
+    #[test]
+    fn test_tsc_frequency() {
+        assert_eq!(CpuInfo::read(qemu64).tsc_hz(), None);
+
+        // Crystal 24 MHz, TSC ratio 250/2: 3 GHz
+        let crystal = |leaf, _| match leaf {
+            0 => [0x16, 0, 0, 0],
+            0x15 => [2, 250, 24_000_000, 0],
+            0x16 => [2900, 0, 0, 0],
+            _ => [0; 4],
+        };
+        assert_eq!(CpuInfo::read(crystal).tsc_hz(), Some(3_000_000_000));
+
+        // No crystal frequency: the base frequency, from leaf 0x16
+        let base = |leaf, subleaf| if leaf == 0x15 { [2, 250, 0, 0] } else { crystal(leaf, subleaf) };
+        assert_eq!(CpuInfo::read(base).tsc_hz(), Some(2_900_000_000));
+
+        // A hypervisor's timing leaf wins
+        let guest = |leaf, subleaf| match leaf {
+            1 => [0, 0, 1 << 31, 0],
+            0x4000_0000 => [0x4000_0010, 0, 0, 0],
+            0x4000_0010 => [2_500_000, 0, 0, 0],
+            _ => crystal(leaf, subleaf),
+        };
+        assert_eq!(CpuInfo::read(guest).tsc_hz(), Some(2_500_000_000));
+    }
+}
@@ -0,0 +1,138 @@
+//! String with a fixed capacity in bytes, for code that runs before the heap
+//! Text is only ever added whole, so the contents stay valid UTF-8: a
+//! `push_str` that does not fit fails and leaves the string as it was. Use
+//! `shared::bootfmt::BootBuffer` instead to format text that may be cut.
+
+use core::fmt;
+use core::ops::Deref;
+
+#[derive(Clone, Copy)]
+pub struct ArrayString<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ArrayString<N> {
+    pub const fn new() -> Self {
+        ArrayString { bytes: [0; N], len: 0 }
+    }
+
+    /// A copy of `s`, if it fits
+    pub fn from(s: &str) -> Result<Self, &'static str> {
+        let mut string = Self::new();
+        string.push_str(s)?;
+        Ok(string)
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole strings are copied in
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+
+    /// Append `s` whole, or nothing if it does not fit
+    pub fn push_str(&mut self, s: &str) -> Result<(), &'static str> {
+        let end = self.len + s.len();
+        if end > N {
+            return Err("String full");
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn push(&mut self, c: char) -> Result<(), &'static str> {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Shorten to `len` bytes, which must fall on a char boundary
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            assert!(self.as_str().is_char_boundary(len), "ArrayString::truncate inside a char");
+            self.len = len;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for ArrayString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for ArrayString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Formatting fails once the string is full, keeping what fit so far
+impl<const N: usize> fmt::Write for ArrayString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Display for ArrayString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for ArrayString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> PartialEq for ArrayString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> PartialEq<str> for ArrayString<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_push_whole_or_nothing() {
+        let mut string: ArrayString<8> = ArrayString::from("boot").unwrap();
+        assert_eq!(string.push_str("/initrd"), Err("String full"));
+        assert_eq!(string, *"boot");
+        string.push('/').unwrap();
+        // 'é' is two bytes: it fits, then nothing more does
+        string.push_str("ré").unwrap();
+        assert_eq!((string.len(), string.as_str()), (8, "boot/ré"));
+        assert!(string.push('x').is_err());
+        assert!(ArrayString::<2>::from("abc").is_err());
+    }
+
+    #[test]
+    fn test_format_and_truncate() {
+        let mut string: ArrayString<16> = ArrayString::new();
+        write!(string, "{} KB", 640).unwrap();
+        assert_eq!(string.as_str(), "640 KB");
+        assert!(write!(string, " is enough for {} years", 40).is_err());
+        string.truncate(3);
+        assert_eq!(&*string, "640");
+        string.clear();
+        assert!(string.is_empty());
+    }
+}
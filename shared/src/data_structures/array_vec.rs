@@ -0,0 +1,154 @@
+//! Vector with a fixed capacity, for code that runs before the heap
+//! Elements live inline, so an `ArrayVec` can sit in a static or on the
+//! stack; pushing past `N` hands the element back instead of growing. It
+//! dereferences to a slice for everything else.
+
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::{fmt, ptr, slice};
+
+pub struct ArrayVec<T, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    pub const fn new() -> Self {
+        ArrayVec { items: [const { MaybeUninit::uninit() }; N], len: 0 }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Append `item`, or give it back if the vector is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        match self.items.get_mut(self.len) {
+            Some(slot) => {
+                slot.write(item);
+                self.len += 1;
+                Ok(())
+            }
+            None => Err(item),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+        Some(unsafe { self.items[self.len].assume_init_read() })
+    }
+
+    /// Drop the elements from `len` on
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            drop(self.pop());
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // The first `len` items are initialized
+        unsafe { slice::from_raw_parts(self.items.as_ptr().cast(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.items.as_mut_ptr().cast(), self.len) }
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) }
+    }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for ArrayVec<T, N> {
+    fn clone(&self) -> Self {
+        let mut copy = ArrayVec::new();
+        for item in self.iter() {
+            let _ = copy.push(item.clone());
+        }
+        copy
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for ArrayVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_push_until_full() {
+        let mut vec: ArrayVec<u32, 3> = ArrayVec::new();
+        assert!(vec.is_empty() && vec.capacity() == 3);
+        for value in 1..=3 {
+            assert_eq!(vec.push(value), Ok(()));
+        }
+        assert!(vec.is_full());
+        assert_eq!(vec.push(4), Err(4));
+        assert_eq!(vec.as_slice(), [1, 2, 3]);
+
+        vec[1] = 20;
+        vec.sort_unstable_by(|a, b| b.cmp(a));
+        assert_eq!(vec.pop(), Some(1));
+        assert_eq!(&*vec, [20, 3]);
+        vec.clear();
+        assert_eq!(vec.pop(), None);
+    }
+
+    #[test]
+    fn test_elements_are_dropped() {
+        let counted = Rc::new(());
+        let mut vec: ArrayVec<Rc<()>, 4> = ArrayVec::new();
+        for _ in 0..3 {
+            vec.push(Rc::clone(&counted)).unwrap();
+        }
+        let copy = vec.clone();
+        assert_eq!(Rc::strong_count(&counted), 7);
+        vec.truncate(1);
+        assert_eq!(Rc::strong_count(&counted), 5);
+        drop(vec);
+        drop(copy);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+}
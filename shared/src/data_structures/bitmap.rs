@@ -0,0 +1,190 @@
+//! Fixed-size bitmap
+//! One bit per index, stored in 64-bit words so ranges are set, cleared,
+//! counted and searched a word at a time. The size is given in words
+//! (`words(bits)` computes it), since the array length can't be derived from
+//! a generic bit count. Indices past the end panic, like slice indexing.
+
+use core::ops::Range;
+
+/// Words needed for `bits` bits
+pub const fn words(bits: usize) -> usize {
+    bits.div_ceil(64)
+}
+
+#[derive(Clone)]
+pub struct Bitmap<const WORDS: usize> {
+    words: [u64; WORDS],
+}
+
+impl<const WORDS: usize> Default for Bitmap<WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bits of `word` that fall in [start, end), both relative to the word
+fn mask(start: usize, end: usize) -> u64 {
+    let high = if end >= 64 { !0 } else { (1 << end) - 1 };
+    high & !((1u64 << start) - 1)
+}
+
+impl<const WORDS: usize> Bitmap<WORDS> {
+    /// All bits clear
+    pub const fn new() -> Self {
+        Bitmap { words: [0; WORDS] }
+    }
+
+    /// Number of bits
+    pub const fn len(&self) -> usize {
+        WORDS * 64
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        WORDS == 0
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.words[index / 64] & 1 << (index % 64) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        self.words[index / 64] &= !(1 << (index % 64));
+    }
+
+    /// Call `f` on each word the range touches, with the mask of its bits
+    /// that are in the range
+    fn for_words(&mut self, range: Range<usize>, mut f: impl FnMut(&mut u64, u64)) {
+        let mut index = range.start;
+        while index < range.end {
+            let word = index / 64;
+            let end = range.end.min((word + 1) * 64);
+            f(&mut self.words[word], mask(index % 64, end - word * 64));
+            index = end;
+        }
+    }
+
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.for_words(range, |word, mask| *word |= mask);
+    }
+
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        self.for_words(range, |word, mask| *word &= !mask);
+    }
+
+    /// Set bits in `range`
+    pub fn count_ones(&self, range: Range<usize>) -> usize {
+        let mut count = 0;
+        let mut index = range.start;
+        while index < range.end {
+            let word = index / 64;
+            let end = range.end.min((word + 1) * 64);
+            count += (self.words[word] & mask(index % 64, end - word * 64)).count_ones() as usize;
+            index = end;
+        }
+        count
+    }
+
+    /// First index in `range` whose bit is `value`
+    fn find(&self, range: Range<usize>, value: bool) -> Option<usize> {
+        let mut index = range.start;
+        while index < range.end {
+            let word = index / 64;
+            let end = range.end.min((word + 1) * 64);
+            let bits = if value { self.words[word] } else { !self.words[word] };
+            let found = bits & mask(index % 64, end - word * 64);
+            if found != 0 {
+                return Some(word * 64 + found.trailing_zeros() as usize);
+            }
+            index = end;
+        }
+        None
+    }
+
+    /// First clear bit in `range`
+    pub fn first_zero(&self, range: Range<usize>) -> Option<usize> {
+        self.find(range, false)
+    }
+
+    /// First set bit in `range`
+    pub fn first_one(&self, range: Range<usize>) -> Option<usize> {
+        self.find(range, true)
+    }
+
+    /// Start of the first run of `count` clear bits within `range`
+    pub fn zero_run(&self, range: Range<usize>, count: usize) -> Option<usize> {
+        let mut start = range.start;
+        loop {
+            start = self.first_zero(start..range.end)?;
+            if range.end - start < count {
+                return None;
+            }
+            match self.first_one(start..start + count) {
+                Some(one) => start = one + 1,
+                None => return Some(start),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 192 bits, so ranges can cross two word boundaries
+    type Bits = Bitmap<{ words(192) }>;
+
+    #[test]
+    fn test_single_bits() {
+        let mut bits = Bits::new();
+        assert_eq!((words(192), words(193), bits.len()), (3, 4, 192));
+        for index in [0, 63, 64, 191] {
+            assert!(!bits.get(index));
+            bits.set(index);
+            assert!(bits.get(index));
+        }
+        assert_eq!(bits.count_ones(0..192), 4);
+        bits.clear(63);
+        assert!(!bits.get(63) && bits.get(64));
+        assert_eq!(bits.count_ones(0..192), 3);
+    }
+
+    #[test]
+    fn test_ranges_across_words() {
+        let mut bits = Bits::new();
+        bits.set_range(60..130);
+        assert_eq!(bits.count_ones(0..192), 70);
+        assert!(!bits.get(59) && bits.get(60) && bits.get(129) && !bits.get(130));
+        assert_eq!(bits.count_ones(64..128), 64);
+        assert_eq!(bits.count_ones(100..100), 0);
+
+        bits.clear_range(62..66);
+        assert_eq!(bits.count_ones(60..70), 6);
+        // Whole words and a range ending on a word boundary
+        bits.set_range(0..192);
+        bits.clear_range(128..192);
+        assert_eq!(bits.count_ones(0..192), 128);
+    }
+
+    #[test]
+    fn test_searches() {
+        let mut bits = Bits::new();
+        bits.set_range(0..70);
+        assert_eq!(bits.first_zero(0..192), Some(70));
+        assert_eq!(bits.first_one(70..192), None);
+        assert_eq!(bits.first_one(10..20), Some(10));
+        assert_eq!(bits.first_zero(0..70), None);
+
+        // Runs: 70..100 free, 100 used, 101..192 free
+        bits.set(100);
+        assert_eq!(bits.zero_run(0..192, 30), Some(70));
+        assert_eq!(bits.zero_run(0..192, 31), Some(101));
+        assert_eq!(bits.zero_run(0..192, 91), Some(101));
+        assert_eq!(bits.zero_run(0..192, 92), None);
+        assert_eq!(bits.zero_run(0..150, 50), None);
+        assert_eq!(bits.zero_run(75..192, 0), Some(75));
+    }
+}
@@ -0,0 +1,174 @@
+//! Log2 histogram
+//! Fixed-size, allocation-free histogram for latency measurements. Bucket 0
+//! holds zero; bucket `i` holds values in `[2^(i-1), 2^i)`, and the last
+//! bucket also takes everything larger.
+
+pub const BUCKETS: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Histogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Bucket index a value lands in
+    pub fn bucket_of(value: u64) -> usize {
+        let bits = (u64::BITS - value.leading_zeros()) as usize;
+        bits.min(BUCKETS - 1)
+    }
+
+    /// Inclusive lower bound of values counted in bucket `index`
+    pub fn bucket_floor(index: usize) -> u64 {
+        if index == 0 {
+            0
+        } else {
+            1 << (index - 1)
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.buckets[Self::bucket_of(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.min)
+        }
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        self.sum.checked_div(self.count)
+    }
+
+    /// Upper bound of the bucket holding the `percent`th percentile value
+    /// Bucketing makes this up to 2x high; it is capped at the observed maximum.
+    pub fn percentile(&self, percent: u64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (self.count * percent.min(100)).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let ceiling = Self::bucket_floor(index + 1).saturating_sub(1);
+                return Some(ceiling.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Non-empty buckets as (lowest value, count)
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| (Self::bucket_floor(index), count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_boundaries() {
+        assert_eq!(Histogram::bucket_of(0), 0);
+        assert_eq!(Histogram::bucket_of(1), 1);
+        assert_eq!(Histogram::bucket_of(2), 2);
+        assert_eq!(Histogram::bucket_of(3), 2);
+        assert_eq!(Histogram::bucket_of(4), 3);
+        assert_eq!(Histogram::bucket_of(u64::MAX), BUCKETS - 1);
+        for index in 1..BUCKETS {
+            assert_eq!(Histogram::bucket_of(Histogram::bucket_floor(index)), index);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let hist = Histogram::new();
+        assert_eq!(hist.count(), 0);
+        assert_eq!(hist.min(), None);
+        assert_eq!(hist.mean(), None);
+        assert_eq!(hist.percentile(99), None);
+        assert_eq!(hist.buckets().count(), 0);
+    }
+
+    #[test]
+    fn test_record_stats() {
+        let mut hist = Histogram::new();
+        for value in [5, 10, 15] {
+            hist.record(value);
+        }
+        assert_eq!(hist.count(), 3);
+        assert_eq!(hist.min(), Some(5));
+        assert_eq!(hist.max(), Some(15));
+        assert_eq!(hist.mean(), Some(10));
+        let buckets: [(u64, u64); 2] = [(4, 1), (8, 2)];
+        assert!(hist.buckets().eq(buckets.iter().copied()));
+    }
+
+    #[test]
+    fn test_percentile_is_bucket_ceiling() {
+        let mut hist = Histogram::new();
+        for _ in 0..99 {
+            hist.record(3);
+        }
+        hist.record(1000);
+        assert_eq!(hist.percentile(50), Some(3));
+        assert_eq!(hist.percentile(99), Some(3));
+        assert_eq!(hist.percentile(100), Some(1000));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut hist = Histogram::new();
+        hist.record(42);
+        hist.clear();
+        assert_eq!(hist.count(), 0);
+        assert_eq!(hist.max(), None);
+    }
+}
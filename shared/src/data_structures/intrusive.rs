@@ -0,0 +1,330 @@
+//! Intrusive linked lists
+//! The links live inside the elements, so putting an element on a list
+//! never allocates, which is what the allocators themselves (a slab's free
+//! objects, a cache's slabs) and anything that runs with a lock held or in
+//! an interrupt need. An element type says where its link is by
+//! implementing `Linked`, usually with `linked!`; one with two links can be
+//! on two lists at once.
+//! Lists hold raw pointers and never own their elements: keeping an element
+//! alive, in place and on at most one list per link is up to the caller,
+//! which is why the methods that take an element are unsafe.
+//! `SList` is singly linked and works at its front only; `List` is doubly
+//! linked, so an element can also be removed from the middle in O(1).
+
+use core::marker::PhantomData;
+use core::ptr;
+
+/// The link of an element on an `SList`
+pub struct SLink {
+    next: *mut SLink,
+}
+
+/// The link of an element on a `List`
+pub struct Link {
+    next: *mut Link,
+    prev: *mut Link,
+}
+
+impl SLink {
+    pub const fn new() -> Self {
+        SLink { next: ptr::null_mut() }
+    }
+}
+
+impl Link {
+    pub const fn new() -> Self {
+        Link { next: ptr::null_mut(), prev: ptr::null_mut() }
+    }
+}
+
+impl Default for SLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An element type with a link of type `L` (`SLink` or `Link`)
+///
+/// # Safety
+/// `link` and `from_link` must convert between an element and one and the
+/// same link field inside it.
+pub unsafe trait Linked<L> {
+    fn link(element: *mut Self) -> *mut L;
+    fn from_link(link: *mut L) -> *mut Self;
+}
+
+/// Implement `Linked` for a struct by naming its link field
+///
+/// ```text
+/// struct Slab { link: Link, ... }
+/// linked!(Slab, link: Link);
+/// ```
+#[macro_export]
+macro_rules! linked {
+    ($ty:ty, $field:ident: $link:ty) => {
+        unsafe impl $crate::data_structures::intrusive::Linked<$link> for $ty {
+            fn link(element: *mut Self) -> *mut $link {
+                unsafe { core::ptr::addr_of_mut!((*element).$field) }
+            }
+
+            fn from_link(link: *mut $link) -> *mut Self {
+                unsafe { link.byte_sub(core::mem::offset_of!($ty, $field)).cast() }
+            }
+        }
+    };
+}
+
+/// Singly linked list: push and pop at the front, last in first out
+pub struct SList<T: Linked<SLink>> {
+    head: *mut SLink,
+    _elements: PhantomData<*mut T>,
+}
+
+impl<T: Linked<SLink>> SList<T> {
+    pub const fn new() -> Self {
+        SList { head: ptr::null_mut(), _elements: PhantomData }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn front(&self) -> Option<*mut T> {
+        (!self.head.is_null()).then(|| T::from_link(self.head))
+    }
+
+    /// # Safety
+    /// `element` must stay valid while on the list, and not be on another
+    /// list through the same link.
+    pub unsafe fn push_front(&mut self, element: *mut T) {
+        let link = T::link(element);
+        (*link).next = self.head;
+        self.head = link;
+    }
+
+    pub fn pop_front(&mut self) -> Option<*mut T> {
+        let element = self.front()?;
+        // Valid by `push_front`'s contract
+        self.head = unsafe { (*self.head).next };
+        Some(element)
+    }
+}
+
+impl<T: Linked<SLink>> Default for SList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Doubly linked list
+pub struct List<T: Linked<Link>> {
+    head: *mut Link,
+    tail: *mut Link,
+    len: usize,
+    _elements: PhantomData<*mut T>,
+}
+
+impl<T: Linked<Link>> List<T> {
+    pub const fn new() -> Self {
+        List { head: ptr::null_mut(), tail: ptr::null_mut(), len: 0, _elements: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn front(&self) -> Option<*mut T> {
+        (!self.head.is_null()).then(|| T::from_link(self.head))
+    }
+
+    pub fn back(&self) -> Option<*mut T> {
+        (!self.tail.is_null()).then(|| T::from_link(self.tail))
+    }
+
+    /// # Safety
+    /// `element` must stay valid while on the list, and not be on another
+    /// list through the same link.
+    pub unsafe fn push_front(&mut self, element: *mut T) {
+        let link = T::link(element);
+        (*link).prev = ptr::null_mut();
+        (*link).next = self.head;
+        if self.head.is_null() {
+            self.tail = link;
+        } else {
+            (*self.head).prev = link;
+        }
+        self.head = link;
+        self.len += 1;
+    }
+
+    /// # Safety
+    /// As for `push_front`.
+    pub unsafe fn push_back(&mut self, element: *mut T) {
+        let link = T::link(element);
+        (*link).next = ptr::null_mut();
+        (*link).prev = self.tail;
+        if self.tail.is_null() {
+            self.head = link;
+        } else {
+            (*self.tail).next = link;
+        }
+        self.tail = link;
+        self.len += 1;
+    }
+
+    /// Take `element` off the list
+    ///
+    /// # Safety
+    /// `element` must be on this list.
+    pub unsafe fn remove(&mut self, element: *mut T) {
+        let link = T::link(element);
+        let (prev, next) = ((*link).prev, (*link).next);
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            (*next).prev = prev;
+        }
+        (*link).next = ptr::null_mut();
+        (*link).prev = ptr::null_mut();
+        self.len -= 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<*mut T> {
+        let element = self.front()?;
+        unsafe { self.remove(element) };
+        Some(element)
+    }
+
+    pub fn pop_back(&mut self) -> Option<*mut T> {
+        let element = self.back()?;
+        unsafe { self.remove(element) };
+        Some(element)
+    }
+
+    /// Elements front to back
+    /// Each one's successor is read before it is yielded, so the caller may
+    /// `remove` the element it was just given, but no other.
+    pub fn iter(&self) -> Iter<T> {
+        Iter { next: self.head, _elements: PhantomData }
+    }
+}
+
+impl<T: Linked<Link>> Default for List<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Iter<T: Linked<Link>> {
+    next: *mut Link,
+    _elements: PhantomData<*mut T>,
+}
+
+impl<T: Linked<Link>> Iterator for Iter<T> {
+    type Item = *mut T;
+
+    fn next(&mut self) -> Option<*mut T> {
+        if self.next.is_null() {
+            return None;
+        }
+        let link = self.next;
+        self.next = unsafe { (*link).next };
+        Some(T::from_link(link))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    /// On two lists at once, through different links
+    struct Node {
+        value: u32,
+        free: SLink,
+        link: Link,
+    }
+
+    linked!(Node, free: SLink);
+    linked!(Node, link: Link);
+
+    fn nodes(count: u32) -> Vec<Node> {
+        (0..count).map(|value| Node { value, free: SLink::new(), link: Link::new() }).collect()
+    }
+
+    fn values(list: &List<Node>) -> Vec<u32> {
+        list.iter().map(|node| unsafe { (*node).value }).collect()
+    }
+
+    #[test]
+    fn test_slist_is_lifo() {
+        let mut nodes = nodes(3);
+        let mut list: SList<Node> = SList::new();
+        assert!(list.is_empty() && list.pop_front().is_none());
+        for node in nodes.iter_mut() {
+            unsafe { list.push_front(node) };
+        }
+        let popped: Vec<u32> = core::iter::from_fn(|| list.pop_front()).map(|node| unsafe { (*node).value }).collect();
+        assert_eq!(popped, [2, 1, 0]);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_list_ends_and_remove() {
+        let mut nodes = nodes(4);
+        let pointers: Vec<*mut Node> = nodes.iter_mut().map(|node| node as *mut Node).collect();
+        let mut list: List<Node> = List::new();
+        unsafe {
+            list.push_back(pointers[1]);
+            list.push_back(pointers[2]);
+            list.push_front(pointers[0]);
+            list.push_back(pointers[3]);
+        }
+        assert_eq!(values(&list), [0, 1, 2, 3]);
+
+        // Middle, then both ends
+        unsafe { list.remove(pointers[2]) };
+        assert_eq!(values(&list), [0, 1, 3]);
+        assert_eq!(list.pop_front(), Some(pointers[0]));
+        assert_eq!(list.pop_back(), Some(pointers[3]));
+        assert_eq!((list.len(), list.front(), list.back()), (1, Some(pointers[1]), Some(pointers[1])));
+        unsafe { list.remove(pointers[1]) };
+        assert!(list.is_empty() && list.front().is_none() && list.back().is_none());
+    }
+
+    #[test]
+    fn test_remove_while_iterating_and_two_lists() {
+        let mut nodes = nodes(6);
+        let mut list: List<Node> = List::new();
+        let mut free: SList<Node> = SList::new();
+        for node in nodes.iter_mut() {
+            unsafe { list.push_back(node) };
+        }
+        for node in list.iter() {
+            if unsafe { (*node).value } % 2 == 0 {
+                unsafe {
+                    list.remove(node);
+                    free.push_front(node);
+                }
+            }
+        }
+        assert_eq!(values(&list), [1, 3, 5]);
+        assert_eq!(free.pop_front().map(|node| unsafe { (*node).value }), Some(4));
+    }
+}
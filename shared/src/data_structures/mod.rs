@@ -1,2 +1,9 @@
 // Hardware-agnostic data structures
+pub mod array_string;
+pub mod array_vec;
+pub mod bitmap;
+pub mod histogram;
+pub mod intrusive;
+pub mod range_map;
 pub mod ring_buffer;
+pub mod spsc;
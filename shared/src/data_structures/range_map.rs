@@ -0,0 +1,173 @@
+//! Ordered map of disjoint address ranges
+//! Each range `start..end` carries a value and is kept in a `BTreeMap` keyed
+//! by its start, so the range holding an address is the last one starting at
+//! or below it: lookups, inserts and removals are O(log n) however many
+//! ranges there are. Ranges never overlap; `insert` refuses a range that
+//! would, and `remove` trims or splits the ranges it covers part of.
+//! Joining neighbours is left to the caller, which knows when two values
+//! are alike.
+
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry<V> {
+    end: u64,
+    value: V,
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeMap<V> {
+    entries: BTreeMap<u64, Entry<V>>,
+}
+
+impl<V> RangeMap<V> {
+    pub const fn new() -> Self {
+        RangeMap { entries: BTreeMap::new() }
+    }
+
+    /// Number of ranges
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The range holding `addr`, and its value
+    pub fn get(&self, addr: u64) -> Option<(Range<u64>, &V)> {
+        let (&start, entry) = self.entries.range(..=addr).next_back()?;
+        (addr < entry.end).then_some((start..entry.end, &entry.value))
+    }
+
+    /// Ranges sharing at least one address with `range`, in address order
+    pub fn overlapping(&self, range: Range<u64>) -> impl Iterator<Item = (Range<u64>, &V)> {
+        // One that starts below `range` can still reach into it
+        let from = self.get(range.start).filter(|_| !range.is_empty()).map_or(range.start, |(found, _)| found.start);
+        self.entries.range(from..range.end.max(from)).map(|(&start, entry)| (start..entry.end, &entry.value))
+    }
+
+    /// True if no range overlaps `range`
+    pub fn is_free(&self, range: Range<u64>) -> bool {
+        self.overlapping(range).next().is_none()
+    }
+
+    /// Every range in address order
+    pub fn iter(&self) -> impl Iterator<Item = (Range<u64>, &V)> {
+        self.entries.iter().map(|(&start, entry)| (start..entry.end, &entry.value))
+    }
+
+    /// Lowest `start` in `within` with `start..start + len` free
+    pub fn find_free(&self, len: u64, within: Range<u64>) -> Option<u64> {
+        let mut start = within.start;
+        for (range, _) in self.overlapping(within.clone()) {
+            if range.start >= start.checked_add(len)? {
+                break;
+            }
+            start = start.max(range.end);
+        }
+        (start.checked_add(len)? <= within.end).then_some(start)
+    }
+
+    /// Add `range` with `value`; fails if it is empty or overlaps another
+    pub fn insert(&mut self, range: Range<u64>, value: V) -> Result<(), &'static str> {
+        if range.is_empty() {
+            return Err("Empty range");
+        }
+        if !self.is_free(range.clone()) {
+            return Err("Range overlaps another");
+        }
+        self.entries.insert(range.start, Entry { end: range.end, value });
+        Ok(())
+    }
+}
+
+impl<V: Clone> RangeMap<V> {
+    /// Take `range` out of every range, shrinking or splitting those it
+    /// covers part of
+    pub fn remove(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        // A range starting below `range` keeps its front, and its back too
+        // if it reaches past `range`
+        if let Some((found, _)) = self.get(range.start).filter(|(found, _)| found.start < range.start) {
+            let entry = self.entries.get_mut(&found.start).unwrap();
+            entry.end = range.start;
+            if range.end < found.end {
+                let value = entry.value.clone();
+                self.entries.insert(range.end, Entry { end: found.end, value });
+                return;
+            }
+        }
+        while let Some((&start, _)) = self.entries.range(range.start..range.end).next() {
+            let entry = self.entries.remove(&start).unwrap();
+            if entry.end > range.end {
+                self.entries.insert(range.end, entry);
+            }
+        }
+    }
+}
+
+impl<V> Default for RangeMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    fn ranges(map: &RangeMap<char>) -> Vec<(Range<u64>, char)> {
+        map.iter().map(|(range, &value)| (range, value)).collect()
+    }
+
+    #[test]
+    fn test_get_and_overlapping() {
+        let mut map = RangeMap::new();
+        map.insert(0x1000..0x3000, 'a').unwrap();
+        map.insert(0x5000..0x6000, 'b').unwrap();
+        assert_eq!(map.insert(0x2000..0x5800, 'c'), Err("Range overlaps another"));
+        assert_eq!(map.insert(0x4000..0x4000, 'c'), Err("Empty range"));
+        map.insert(0x3000..0x5000, 'c').unwrap();
+
+        assert_eq!(map.get(0x1000), Some((0x1000..0x3000, &'a')));
+        assert_eq!(map.get(0x2fff), Some((0x1000..0x3000, &'a')));
+        assert_eq!(map.get(0x6000), None);
+        assert_eq!(map.get(0), None);
+        // The first range starts below the one asked about
+        let found: Vec<char> = map.overlapping(0x2000..0x5001).map(|(_, &value)| value).collect();
+        assert_eq!(found, ['a', 'c', 'b']);
+        assert!(map.is_free(0x6000..0x7000) && map.is_free(0x2000..0x2000));
+        assert!(!map.is_free(0x5fff..0x7000));
+    }
+
+    #[test]
+    fn test_remove_trims_and_splits() {
+        let mut map = RangeMap::new();
+        map.insert(0x1000..0x9000, 'a').unwrap();
+        map.insert(0xa000..0xc000, 'b').unwrap();
+        map.remove(0x3000..0x5000);
+        assert_eq!(ranges(&map), [(0x1000..0x3000, 'a'), (0x5000..0x9000, 'a'), (0xa000..0xc000, 'b')]);
+        map.remove(0x8000..0xb000);
+        assert_eq!(ranges(&map), [(0x1000..0x3000, 'a'), (0x5000..0x8000, 'a'), (0xb000..0xc000, 'b')]);
+        map.remove(0..0x10000);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_find_free() {
+        let mut map = RangeMap::new();
+        map.insert(0x2000..0x3000, 'a').unwrap();
+        map.insert(0x5000..0x6000, 'b').unwrap();
+        assert_eq!(map.find_free(0x1000, 0x1000..0x10000), Some(0x1000));
+        assert_eq!(map.find_free(0x2000, 0x1000..0x10000), Some(0x3000));
+        assert_eq!(map.find_free(0x1000, 0x2800..0x10000), Some(0x3000));
+        assert_eq!(map.find_free(0x3000, 0x1000..0x8000), None);
+        assert_eq!(map.find_free(u64::MAX, 0x1000..u64::MAX), None);
+    }
+}
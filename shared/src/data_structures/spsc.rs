@@ -0,0 +1,138 @@
+//! Lock-free single-producer, single-consumer queue
+//! For handing bytes from an interrupt handler to the code that reads them:
+//! `push` and `pop` take `&self` and never wait, so a handler can push into
+//! a queue whose reader it interrupted mid-`pop`. Each side only writes its
+//! own index; the producer publishes a slot with a release store of `tail`
+//! and the consumer frees one with a release store of `head`, each side
+//! reading the other's index with acquire. One slot is always kept free, so
+//! the queue holds `N - 1` items.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct SpscQueue<T: Copy, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    /// Next slot to pop; written only by the consumer
+    head: AtomicUsize,
+    /// Next slot to push; written only by the producer
+    tail: AtomicUsize,
+}
+
+// Slots are only touched by the one side that owns them at the time
+unsafe impl<T: Copy + Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T: Copy, const N: usize> Default for SpscQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        SpscQueue {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Append `item`; false if the queue is full
+    ///
+    /// # Safety
+    /// No other `push` on this queue may run at the same time.
+    pub unsafe fn push(&self, item: T) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return false;
+        }
+        (*self.slots[tail].get()).write(item);
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    /// Take the oldest item, if any
+    ///
+    /// # Safety
+    /// No other `pop` on this queue may run at the same time.
+    pub unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = (*self.slots[head].get()).assume_init();
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+
+    /// Items queued; only a snapshot while the other side is running
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + N - head) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Most items the queue holds at once
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_fifo_and_full() {
+        let queue: SpscQueue<u8, 4> = SpscQueue::new();
+        assert_eq!(queue.capacity(), 3);
+        unsafe {
+            assert_eq!(queue.pop(), None);
+            assert!(queue.push(1) && queue.push(2) && queue.push(3));
+            assert!(!queue.push(4));
+            assert_eq!(queue.len(), 3);
+            assert_eq!(queue.pop(), Some(1));
+            // The freed slot is reused past the end of the array
+            assert!(queue.push(5));
+            assert_eq!([queue.pop(), queue.pop(), queue.pop(), queue.pop()], [Some(2), Some(3), Some(5), None]);
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_producer_and_consumer_threads() {
+        const COUNT: u32 = 100_000;
+        let queue: Arc<SpscQueue<u32, 16>> = Arc::new(SpscQueue::new());
+        let producer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                for value in 0..COUNT {
+                    while !unsafe { queue.push(value) } {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+
+        let mut expected = 0;
+        while expected < COUNT {
+            match unsafe { queue.pop() } {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert!(queue.is_empty());
+    }
+}
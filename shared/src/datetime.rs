@@ -0,0 +1,247 @@
+//! Calendar dates and times
+//! `DateTime` is a UTC date and time of day in the Gregorian calendar. It
+//! converts to and from Unix time (seconds since 1970-01-01 00:00:00 UTC).
+//! `RtcRegisters` holds the registers of a PC's CMOS real-time clock as read,
+//! and `decode` turns them into a `DateTime`: the clock may count in BCD or
+//! binary and in 12- or 24-hour time, as its status register B says.
+
+use core::fmt;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+/// Days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar
+const UNIX_EPOCH_DAYS: u64 = 719_468;
+/// Days in each 400-year cycle of the Gregorian calendar
+const DAYS_PER_ERA: u64 = 146_097;
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date and time `seconds` after the Unix epoch
+    pub fn from_unix(seconds: u64) -> DateTime {
+        let (days, time) = (seconds / SECONDS_PER_DAY, seconds % SECONDS_PER_DAY);
+
+        // Count from 0000-03-01, so the leap day ends each year
+        let days = days + UNIX_EPOCH_DAYS;
+        let era = days / DAYS_PER_ERA;
+        let day_of_era = days % DAYS_PER_ERA;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let march_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * march_month + 2) / 5 + 1;
+        let month = if march_month < 10 { march_month + 3 } else { march_month - 9 };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds since the Unix epoch, or None for an invalid date or one
+    /// before 1970
+    pub fn to_unix(&self) -> Option<u64> {
+        if !self.is_valid() || self.year < 1970 {
+            return None;
+        }
+        let (month, day) = (u64::from(self.month), u64::from(self.day));
+        let year = u64::from(self.year) - u64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let march_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * march_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * DAYS_PER_ERA + day_of_era - UNIX_EPOCH_DAYS;
+
+        let time = u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        Some(days * SECONDS_PER_DAY + time)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Three-letter day of the week, e.g. "Mon"
+    pub fn weekday(&self) -> &'static str {
+        let days = self.to_unix().unwrap_or(0) / SECONDS_PER_DAY;
+        WEEKDAYS[(days % 7) as usize]
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// ISO 8601 without the 'T', e.g. "2024-02-29 13:05:09"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Status register B: values are binary rather than BCD
+pub const STATUS_B_BINARY: u8 = 0x04;
+/// Status register B: hours run 0-23 rather than 1-12 with a PM flag
+pub const STATUS_B_24_HOUR: u8 = 0x02;
+/// In 12-hour time, set in the hour register after noon
+const HOUR_PM: u8 = 0x80;
+
+/// CMOS clock registers as read, before decoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    /// Year within the century
+    pub year: u8,
+    /// Century register, where the machine has one
+    pub century: Option<u8>,
+    pub status_b: u8,
+}
+
+impl RtcRegisters {
+    /// The date and time the registers hold, or None if they make no sense
+    /// Without a century register, the year is taken to be in 2000-2099.
+    pub fn decode(&self) -> Option<DateTime> {
+        let binary = self.status_b & STATUS_B_BINARY != 0;
+        let value = |raw: u8| if binary { Some(raw) } else { from_bcd(raw) };
+
+        let pm = self.status_b & STATUS_B_24_HOUR == 0 && self.hour & HOUR_PM != 0;
+        let mut hour = value(self.hour & !HOUR_PM)?;
+        if self.status_b & STATUS_B_24_HOUR == 0 {
+            // 12 AM is midnight and 12 PM is noon
+            if !(1..=12).contains(&hour) {
+                return None;
+            }
+            hour = hour % 12 + if pm { 12 } else { 0 };
+        }
+
+        let century = match self.century {
+            Some(raw) => value(raw)?,
+            None => 20,
+        };
+        let year = value(self.year)?;
+        if year > 99 {
+            return None;
+        }
+
+        let date = DateTime {
+            year: u16::from(century) * 100 + u16::from(year),
+            month: value(self.month)?,
+            day: value(self.day)?,
+            hour,
+            minute: value(self.minute)?,
+            second: value(self.second)?,
+        };
+        date.is_valid().then_some(date)
+    }
+}
+
+/// Decode a two-digit BCD value
+fn from_bcd(raw: u8) -> Option<u8> {
+    let (tens, ones) = (raw >> 4, raw & 0x0F);
+    (tens < 10 && ones < 10).then_some(tens * 10 + ones)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    #[test]
+    fn test_unix_round_trip() {
+        assert_eq!(DateTime::from_unix(0), date(1970, 1, 1, 0, 0, 0));
+        assert_eq!(date(2000, 3, 1, 0, 0, 0).to_unix(), Some(951_868_800));
+        assert_eq!(date(2024, 2, 29, 13, 5, 9).to_unix(), Some(1_709_211_909));
+        assert_eq!(DateTime::from_unix(1_709_211_909), date(2024, 2, 29, 13, 5, 9));
+        // The last second of every seventh day for two centuries converts back
+        for day in (0..80_000u64).step_by(7) {
+            let seconds = day * SECONDS_PER_DAY + 86_399;
+            assert_eq!(DateTime::from_unix(seconds).to_unix(), Some(seconds));
+        }
+    }
+
+    #[test]
+    fn test_invalid_dates() {
+        assert_eq!(date(2023, 2, 29, 0, 0, 0).to_unix(), None);
+        assert!(date(2000, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(1900, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(2024, 13, 1, 0, 0, 0).is_valid());
+        assert!(!date(2024, 4, 31, 0, 0, 0).is_valid());
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).to_unix(), None);
+    }
+
+    #[test]
+    fn test_weekday_and_display() {
+        assert_eq!(DateTime::from_unix(0).weekday(), "Thu");
+        let leap_day = date(2024, 2, 29, 13, 5, 9);
+        assert_eq!(leap_day.weekday(), "Thu");
+        assert_eq!(date(2026, 10, 17, 0, 0, 0).weekday(), "Sat");
+        assert_eq!(std::format!("{}", leap_day), "2024-02-29 13:05:09");
+    }
+
+    #[test]
+    fn test_rtc_decode() {
+        let bcd = RtcRegisters {
+            second: 0x09,
+            minute: 0x05,
+            hour: 0x13,
+            day: 0x29,
+            month: 0x02,
+            year: 0x24,
+            century: Some(0x20),
+            status_b: STATUS_B_24_HOUR,
+        };
+        assert_eq!(bcd.decode(), Some(date(2024, 2, 29, 13, 5, 9)));
+
+        // Binary, 12-hour: 1 PM, and 12 AM is midnight
+        let binary = RtcRegisters { hour: HOUR_PM | 1, day: 29, month: 2, year: 24, century: None, status_b: STATUS_B_BINARY, ..bcd };
+        assert_eq!(binary.decode().map(|d| (d.hour, d.second)), Some((13, 0x09)));
+        assert_eq!(RtcRegisters { hour: 12, ..binary }.decode().map(|d| d.hour), Some(0));
+        assert_eq!(RtcRegisters { hour: HOUR_PM | 12, ..binary }.decode().map(|d| d.hour), Some(12));
+
+        // Digits past 9, hours outside 1-12, and impossible dates are rejected
+        assert_eq!(RtcRegisters { minute: 0x5A, ..bcd }.decode(), None);
+        assert_eq!(RtcRegisters { hour: 0, ..binary }.decode(), None);
+        assert_eq!(RtcRegisters { day: 0x30, ..bcd }.decode(), None);
+    }
+}
@@ -0,0 +1,501 @@
+//! ELF64 parsing
+//! Reads the file header, program headers, and section headers of a
+//! little-endian ELF64 image, checking every offset against the image so a
+//! malformed file yields an error instead of an out-of-bounds read. Deciding
+//! whether an image is runnable (type, machine, address range) is left to
+//! the loader.
+
+use crate::bytes::{le, ByteView};
+
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+pub const ELFCLASS64: u8 = 2;
+pub const ELFDATA2LSB: u8 = 1;
+pub const EV_CURRENT: u8 = 1;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const EM_X86_64: u16 = 62;
+pub const EM_RISCV: u16 = 243;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_ABS: u16 = 0xfff1;
+
+pub const HEADER_SIZE: usize = 64;
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+pub const SECTION_HEADER_SIZE: usize = 64;
+pub const SYMBOL_SIZE: usize = 24;
+
+/// The ELF file header, minus the identification bytes already validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub elf_type: u16,
+    pub machine: u16,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub phnum: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// One program header (segment)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(view: ByteView) -> Option<Self> {
+        Some(ProgramHeader {
+            p_type: view.u32_le(0)?,
+            flags: view.u32_le(4)?,
+            offset: view.u64_le(8)?,
+            vaddr: view.u64_le(16)?,
+            paddr: view.u64_le(24)?,
+            filesz: view.u64_le(32)?,
+            memsz: view.u64_le(40)?,
+            align: view.u64_le(48)?,
+        })
+    }
+
+    pub fn is_load(&self) -> bool {
+        self.p_type == PT_LOAD
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    /// End of the segment in memory (`vaddr + memsz`), checked for overflow
+    pub fn vaddr_end(&self) -> Result<u64, &'static str> {
+        self.vaddr.checked_add(self.memsz).ok_or("Segment address overflow")
+    }
+
+    /// End of the segment's bytes in a file of `file_len` bytes
+    /// Fails if the segment claims more file bytes than memory or runs past
+    /// the end of the file.
+    pub fn file_end(&self, file_len: u64) -> Result<u64, &'static str> {
+        if self.filesz > self.memsz {
+            return Err("Segment file size exceeds memory size");
+        }
+        let end = self.offset.checked_add(self.filesz).ok_or("Segment offset overflow")?;
+        if end > file_len {
+            return Err("Segment extends past end of file");
+        }
+        Ok(end)
+    }
+}
+
+/// One section header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionHeader {
+    /// Offset of the name in the section name string table
+    pub name: u32,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entsize: u64,
+}
+
+impl SectionHeader {
+    fn parse(view: ByteView) -> Option<Self> {
+        Some(SectionHeader {
+            name: view.u32_le(0)?,
+            sh_type: view.u32_le(4)?,
+            flags: view.u64_le(8)?,
+            addr: view.u64_le(16)?,
+            offset: view.u64_le(24)?,
+            size: view.u64_le(32)?,
+            link: view.u32_le(40)?,
+            info: view.u32_le(44)?,
+            addralign: view.u64_le(48)?,
+            entsize: view.u64_le(56)?,
+        })
+    }
+}
+
+/// One entry of the symbol table that names an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub value: u64,
+    /// Zero when the assembler did not record one
+    pub size: u64,
+}
+
+/// A validated ELF64 image
+#[derive(Debug, Clone, Copy)]
+pub struct Elf<'a> {
+    data: ByteView<'a>,
+    header: ElfHeader,
+}
+
+/// True if `image` starts with the ELF magic number
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(&ELF_MAGIC)
+}
+
+/// `count` entries of `entry_size` bytes at `offset`, if all inside `data`
+fn table(data: ByteView, offset: u64, count: u16, entry_size: usize) -> Option<ByteView> {
+    let offset = usize::try_from(offset).ok()?;
+    data.sub(offset, (count as usize).checked_mul(entry_size)?)
+}
+
+impl<'a> Elf<'a> {
+    /// Check the identification bytes and that the header tables fit in `image`
+    pub fn parse(image: &'a [u8]) -> Result<Self, &'static str> {
+        Self::parse_tables(image, true)
+    }
+
+    /// Like `parse`, for when `image` is only the start of the file: section
+    /// headers (usually at the end) are ignored, leaving what loading needs
+    pub fn parse_headers(image: &'a [u8]) -> Result<Self, &'static str> {
+        Self::parse_tables(image, false)
+    }
+
+    fn parse_tables(image: &'a [u8], sections: bool) -> Result<Self, &'static str> {
+        let data = ByteView::new(image);
+        let bytes = data.bytes(0, HEADER_SIZE).ok_or("ELF header extends past end of file")?;
+        if !is_elf(bytes) {
+            return Err("Not an ELF file");
+        }
+        if bytes[4] != ELFCLASS64 {
+            return Err("Not a 64-bit ELF file");
+        }
+        if bytes[5] != ELFDATA2LSB {
+            return Err("Not a little-endian ELF file");
+        }
+        if bytes[6] != EV_CURRENT {
+            return Err("Unsupported ELF version");
+        }
+
+        let mut header = ElfHeader {
+            elf_type: le::read_u16(bytes, 16),
+            machine: le::read_u16(bytes, 18),
+            entry: le::read_u64(bytes, 24),
+            phoff: le::read_u64(bytes, 32),
+            shoff: le::read_u64(bytes, 40),
+            flags: le::read_u32(bytes, 48),
+            phnum: le::read_u16(bytes, 56),
+            shnum: le::read_u16(bytes, 60),
+            shstrndx: le::read_u16(bytes, 62),
+        };
+        let phentsize = le::read_u16(bytes, 54) as usize;
+        let shentsize = le::read_u16(bytes, 58) as usize;
+
+        if header.phnum > 0 {
+            if phentsize != PROGRAM_HEADER_SIZE {
+                return Err("Unexpected program header size");
+            }
+            table(data, header.phoff, header.phnum, PROGRAM_HEADER_SIZE)
+                .ok_or("Program headers extend past end of file")?;
+        }
+        if !sections {
+            header.shnum = 0;
+        }
+        if header.shnum > 0 {
+            if shentsize != SECTION_HEADER_SIZE {
+                return Err("Unexpected section header size");
+            }
+            table(data, header.shoff, header.shnum, SECTION_HEADER_SIZE)
+                .ok_or("Section headers extend past end of file")?;
+            if header.shstrndx >= header.shnum {
+                return Err("Section name table index out of range");
+            }
+        }
+
+        Ok(Elf { data, header })
+    }
+
+    pub fn header(&self) -> &ElfHeader {
+        &self.header
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        // Bounds were checked in `parse`, so the per-entry reads can't fail
+        let table = table(self.data, self.header.phoff, self.header.phnum, PROGRAM_HEADER_SIZE);
+        let count = if table.is_some() { self.header.phnum as usize } else { 0 };
+        (0..count).filter_map(move |i| ProgramHeader::parse(table?.sub(i * PROGRAM_HEADER_SIZE, PROGRAM_HEADER_SIZE)?))
+    }
+
+    pub fn section_headers(&self) -> impl Iterator<Item = SectionHeader> + 'a {
+        let table = table(self.data, self.header.shoff, self.header.shnum, SECTION_HEADER_SIZE);
+        let count = if table.is_some() { self.header.shnum as usize } else { 0 };
+        (0..count).filter_map(move |i| SectionHeader::parse(table?.sub(i * SECTION_HEADER_SIZE, SECTION_HEADER_SIZE)?))
+    }
+
+    /// The bytes a segment loads from the file (`filesz` of them)
+    /// Fails if the segment claims more file bytes than memory or runs past
+    /// the end of the image.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Result<&'a [u8], &'static str> {
+        let end = ph.file_end(self.data.len() as u64)?;
+        Ok(&self.data.as_slice()[ph.offset as usize..end as usize])
+    }
+
+    /// Contents of a section; empty for SHT_NOBITS (e.g. .bss)
+    pub fn section_data(&self, sh: &SectionHeader) -> Result<&'a [u8], &'static str> {
+        if sh.sh_type == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        let offset = usize::try_from(sh.offset).map_err(|_| "Section offset overflow")?;
+        let size = usize::try_from(sh.size).map_err(|_| "Section size overflow")?;
+        self.data.bytes(offset, size).ok_or("Section extends past end of file")
+    }
+
+    /// Name of a section from the section name string table
+    pub fn section_name(&self, sh: &SectionHeader) -> Option<&'a str> {
+        let names = self.section_headers().nth(self.header.shstrndx as usize)?;
+        let names = self.section_data(&names).ok()?;
+        let start = names.get(sh.name as usize..)?;
+        let len = start.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&start[..len]).ok()
+    }
+
+    /// First section called `name`
+    pub fn section_by_name(&self, name: &str) -> Option<SectionHeader> {
+        self.section_headers().find(|sh| self.section_name(sh) == Some(name))
+    }
+
+    /// Named code and data symbols from `.symtab`; nothing if it was stripped
+    /// Undefined, absolute, section, and file symbols are left out, since
+    /// none of them name a place in the image.
+    pub fn symbols(&self) -> impl Iterator<Item = Symbol<'a>> + 'a {
+        let symtab = self.section_headers().find(|sh| sh.sh_type == SHT_SYMTAB);
+        let table = symtab.and_then(|sh| self.section_data(&sh).ok()).unwrap_or(&[]);
+        let names = symtab
+            .and_then(|sh| self.section_headers().nth(sh.link as usize))
+            .and_then(|sh| self.section_data(&sh).ok())
+            .unwrap_or(&[]);
+
+        table.chunks_exact(SYMBOL_SIZE).filter_map(move |entry| {
+            let kind = entry[4] & 0xF;
+            let section = le::read_u16(entry, 6);
+            if kind == STT_SECTION || kind == STT_FILE || section == SHN_UNDEF || section == SHN_ABS {
+                return None;
+            }
+            let name = names.get(le::read_u32(entry, 0) as usize..)?;
+            let name = core::str::from_utf8(&name[..name.iter().position(|&b| b == 0)?]).ok()?;
+            if name.is_empty() {
+                return None;
+            }
+            Some(Symbol { name, value: le::read_u64(entry, 8), size: le::read_u64(entry, 16) })
+        })
+    }
+
+    /// The symbol `addr` falls in, and how far into it
+    /// That is the closest symbol at or below `addr`, unless it has a size
+    /// and `addr` lies past its end.
+    pub fn symbol_at(&self, addr: u64) -> Option<(Symbol<'a>, u64)> {
+        self.symbols()
+            .filter(|sym| sym.value <= addr && (sym.size == 0 || addr - sym.value < sym.size))
+            .max_by_key(|sym| sym.value)
+            .map(|sym| (sym, addr - sym.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static HELLO: &[u8] = include_bytes!("../fixtures/elf/hello.elf");
+    static HELLO_PIE: &[u8] = include_bytes!("../fixtures/elf/hello-pie.elf");
+    static HELLO_32: &[u8] = include_bytes!("../fixtures/elf/hello32.elf");
+
+    fn patched(offset: usize, bytes: &[u8]) -> [u8; 8192] {
+        let mut image = [0u8; 8192];
+        image[..HELLO.len()].copy_from_slice(HELLO);
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+        image
+    }
+
+    fn loads(elf: &Elf) -> [ProgramHeader; 2] {
+        let mut segments = elf.program_headers().filter(|ph| ph.is_load());
+        [segments.next().unwrap(), segments.next().unwrap()]
+    }
+
+    #[test]
+    fn test_header() {
+        let elf = Elf::parse(HELLO).unwrap();
+        let header = elf.header();
+        assert_eq!(header.elf_type, ET_EXEC);
+        assert_eq!(header.machine, EM_X86_64);
+        assert_eq!(header.entry, 0x400000);
+        assert_eq!(header.phnum, 2);
+        assert_eq!(header.shnum, 7);
+    }
+
+    #[test]
+    fn test_is_elf() {
+        assert!(is_elf(HELLO));
+        assert!(!is_elf(b"\x7fEL"));
+        assert!(!is_elf(b"\x48\xc7\xc0\x01\x00\x00\x00"));
+    }
+
+    #[test]
+    fn test_load_segments() {
+        let elf = Elf::parse(HELLO).unwrap();
+        let [text, data] = loads(&elf);
+
+        assert!(text.executable() && !text.writable());
+        assert!(text.vaddr <= elf.header().entry && elf.header().entry < text.vaddr_end().unwrap());
+
+        assert!(data.writable() && !data.executable());
+        // .data is in the file, .bss only in memory
+        assert_eq!(data.filesz, 16);
+        assert_eq!(data.memsz, 16 + 4096);
+        assert_eq!(elf.segment_data(&data).unwrap(), b"Hello from ELF!\n");
+    }
+
+    #[test]
+    fn test_sections() {
+        let elf = Elf::parse(HELLO).unwrap();
+        let names: [Option<&str>; 4] = {
+            let mut sections = elf.section_headers();
+            core::array::from_fn(|_| sections.next().and_then(|sh| elf.section_name(&sh)))
+        };
+        assert_eq!(names, [Some(""), Some(".text"), Some(".data"), Some(".bss")]);
+
+        let text = elf.section_by_name(".text").unwrap();
+        assert_eq!(text.sh_type, SHT_PROGBITS);
+        assert_eq!(text.addr, 0x400000);
+        // mov $SYS_WRITE, %eax
+        assert_eq!(&elf.section_data(&text).unwrap()[..5], &[0xB8, 0x01, 0x00, 0x00, 0x00]);
+
+        let bss = elf.section_by_name(".bss").unwrap();
+        assert_eq!(bss.sh_type, SHT_NOBITS);
+        assert_eq!(bss.size, 4096);
+        assert_eq!(elf.section_data(&bss).unwrap(), &[] as &[u8]);
+
+        assert_eq!(elf.section_by_name(".symtab").unwrap().sh_type, SHT_SYMTAB);
+        assert!(elf.section_by_name(".nope").is_none());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let elf = Elf::parse(HELLO).unwrap();
+        // SYS_WRITE and friends are absolute; hello.o is a file symbol
+        assert!(elf.symbols().all(|sym| !sym.name.starts_with("SYS_") && sym.name != "hello.o"));
+        let start = elf.symbols().find(|sym| sym.name == "_start").unwrap();
+        assert_eq!(start, Symbol { name: "_start", value: 0x400000, size: 0 });
+
+        assert_eq!(elf.symbol_at(0x400005), Some((start, 5)));
+        let (message, offset) = elf.symbol_at(0x401030).unwrap();
+        assert_eq!((message.name, offset), ("message", 8));
+        assert_eq!(elf.symbol_at(0x3FFFFF), None);
+
+        // Stripped of sections, there is nothing to look up
+        assert_eq!(Elf::parse_headers(HELLO).unwrap().symbol_at(0x400005), None);
+    }
+
+    #[test]
+    fn test_position_independent() {
+        let elf = Elf::parse(HELLO_PIE).unwrap();
+        assert_eq!(elf.header().elf_type, ET_DYN);
+        assert_eq!(elf.program_headers().filter(|ph| ph.is_load()).count(), 2);
+    }
+
+    #[test]
+    fn test_rejects_32_bit() {
+        assert_eq!(Elf::parse(HELLO_32).unwrap_err(), "Not a 64-bit ELF file");
+    }
+
+    #[test]
+    fn test_rejects_bad_identification() {
+        assert_eq!(Elf::parse(&patched(0, b"\x7fELG")).unwrap_err(), "Not an ELF file");
+        assert_eq!(Elf::parse(&patched(5, &[2])).unwrap_err(), "Not a little-endian ELF file");
+        assert_eq!(Elf::parse(&patched(6, &[0])).unwrap_err(), "Unsupported ELF version");
+    }
+
+    #[test]
+    fn test_rejects_truncated() {
+        assert!(Elf::parse(&HELLO[..HEADER_SIZE - 1]).is_err());
+        // Section headers sit at the end of the file
+        assert_eq!(
+            Elf::parse(&HELLO[..HELLO.len() - 1]).unwrap_err(),
+            "Section headers extend past end of file"
+        );
+        assert_eq!(Elf::parse(&patched(32, &u64::MAX.to_le_bytes())).unwrap_err(), "Program headers extend past end of file");
+    }
+
+    #[test]
+    fn test_rejects_bad_table_sizes() {
+        assert_eq!(Elf::parse(&patched(54, &[32, 0])).unwrap_err(), "Unexpected program header size");
+        assert_eq!(Elf::parse(&patched(58, &[40, 0])).unwrap_err(), "Unexpected section header size");
+        assert_eq!(Elf::parse(&patched(62, &[7, 0])).unwrap_err(), "Section name table index out of range");
+    }
+
+    #[test]
+    fn test_segment_bounds() {
+        let elf = Elf::parse(HELLO).unwrap();
+        let [_, data] = loads(&elf);
+
+        let past_end = ProgramHeader { offset: HELLO.len() as u64 - 8, ..data };
+        assert_eq!(elf.segment_data(&past_end).unwrap_err(), "Segment extends past end of file");
+
+        let overflow = ProgramHeader { offset: u64::MAX, ..data };
+        assert_eq!(elf.segment_data(&overflow).unwrap_err(), "Segment offset overflow");
+
+        let oversized = ProgramHeader { filesz: data.memsz + 1, ..data };
+        assert_eq!(elf.segment_data(&oversized).unwrap_err(), "Segment file size exceeds memory size");
+
+        let wrapping = ProgramHeader { vaddr: u64::MAX, ..data };
+        assert_eq!(wrapping.vaddr_end().unwrap_err(), "Segment address overflow");
+    }
+
+    #[test]
+    fn test_parse_headers_of_file_start() {
+        // The first page holds the header and program headers, not the section headers
+        let start = &HELLO[..256];
+        assert!(Elf::parse(start).is_err());
+        let elf = Elf::parse_headers(start).unwrap();
+        assert_eq!(elf.header().entry, 0x400000);
+        assert_eq!(elf.header().shnum, 0);
+        assert_eq!(elf.section_headers().count(), 0);
+
+        let [text, data] = loads(&elf);
+        assert_eq!(loads(&Elf::parse(HELLO).unwrap()), [text, data]);
+        assert_eq!(data.file_end(HELLO.len() as u64), Ok(data.offset + 16));
+        assert_eq!(data.file_end(data.offset + 15).unwrap_err(), "Segment extends past end of file");
+    }
+
+    #[test]
+    fn test_no_tables() {
+        // phnum = shnum = 0: nothing to iterate, nothing to bounds-check
+        let mut image = patched(56, &[0, 0]);
+        image[60..62].copy_from_slice(&[0, 0]);
+        let elf = Elf::parse(&image).unwrap();
+        assert_eq!(elf.program_headers().count(), 0);
+        assert_eq!(elf.section_headers().count(), 0);
+    }
+}
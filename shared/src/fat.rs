@@ -0,0 +1,470 @@
+//! FAT32 on-disk structures
+//! Parsing and encoding of the boot sector, directory entries and long file
+//! name (LFN) entries. No I/O happens here, so it can be tested on the host.
+
+use crate::bytes::le::{read_u16, read_u32, write_u16, write_u32};
+
+pub const DIR_ENTRY_SIZE: usize = 32;
+
+// Directory entry attributes
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// First name byte of a deleted entry
+pub const ENTRY_DELETED: u8 = 0xE5;
+/// First name byte marking the end of a directory
+pub const ENTRY_END: u8 = 0x00;
+
+// FAT entry values (only the low 28 bits are meaningful)
+pub const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+pub const CLUSTER_FREE: u32 = 0;
+pub const CLUSTER_BAD: u32 = 0x0FFF_FFF7;
+pub const CLUSTER_END: u32 = 0x0FFF_FFFF;
+/// Smallest value that marks the end of a cluster chain
+pub const CLUSTER_END_MIN: u32 = 0x0FFF_FFF8;
+/// Cluster numbers 0 and 1 are reserved; data starts at cluster 2
+pub const FIRST_DATA_CLUSTER: u32 = 2;
+
+/// UCS-2 characters stored in one LFN entry
+pub const LFN_CHARS_PER_ENTRY: usize = 13;
+/// Longest name an LFN sequence can hold (20 entries)
+pub const LFN_MAX_CHARS: usize = 255;
+const LFN_LAST_ENTRY: u8 = 0x40;
+const LFN_SEQUENCE_MASK: u8 = 0x1F;
+// Byte offsets of the three name pieces inside an LFN entry
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// The FAT32 BIOS Parameter Block from the boot sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BiosParameterBlock {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    pub root_cluster: u32,
+    pub fs_info_sector: u16,
+}
+
+impl BiosParameterBlock {
+    /// Parse and sanity-check the boot sector of a FAT32 volume
+    pub fn parse(sector: &[u8]) -> Result<Self, &'static str> {
+        if sector.len() < 512 {
+            return Err("Boot sector too short");
+        }
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            return Err("Missing boot sector signature");
+        }
+
+        let bpb = BiosParameterBlock {
+            bytes_per_sector: read_u16(sector, 11),
+            sectors_per_cluster: sector[13],
+            reserved_sectors: read_u16(sector, 14),
+            fat_count: sector[16],
+            total_sectors: match read_u16(sector, 19) {
+                0 => read_u32(sector, 32),
+                small => small as u32,
+            },
+            fat_size: read_u32(sector, 36),
+            root_cluster: read_u32(sector, 44),
+            fs_info_sector: read_u16(sector, 48),
+        };
+
+        if !matches!(bpb.bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+            return Err("Unsupported sector size");
+        }
+        if !bpb.sectors_per_cluster.is_power_of_two() {
+            return Err("Invalid sectors per cluster");
+        }
+        if bpb.reserved_sectors == 0 || bpb.fat_count == 0 {
+            return Err("Invalid reserved sector or FAT count");
+        }
+        // FAT12/16 keep the FAT size in the 16-bit field and have no root cluster
+        if read_u16(sector, 22) != 0 || bpb.fat_size == 0 {
+            return Err("Not a FAT32 volume");
+        }
+        if bpb.root_cluster < FIRST_DATA_CLUSTER {
+            return Err("Invalid root cluster");
+        }
+        if bpb.data_start_sector() >= bpb.total_sectors {
+            return Err("Volume too small");
+        }
+
+        Ok(bpb)
+    }
+
+    pub fn fat_start_sector(&self) -> u32 {
+        self.reserved_sectors as u32
+    }
+
+    pub fn data_start_sector(&self) -> u32 {
+        self.fat_start_sector() + self.fat_count as u32 * self.fat_size
+    }
+
+    /// Number of data clusters (valid cluster numbers are 2..cluster_count + 2)
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.data_start_sector()) / self.sectors_per_cluster as u32
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// First sector of a data cluster
+    pub fn cluster_to_sector(&self, cluster: u32) -> u32 {
+        self.data_start_sector() + (cluster - FIRST_DATA_CLUSTER) * self.sectors_per_cluster as u32
+    }
+
+    /// Sector and byte offset holding the FAT entry for `cluster` in FAT copy `fat`
+    pub fn fat_entry_location(&self, cluster: u32, fat: u8) -> (u32, usize) {
+        let byte_offset = cluster as usize * 4;
+        let sector = self.fat_start_sector()
+            + fat as u32 * self.fat_size
+            + (byte_offset / self.bytes_per_sector as usize) as u32;
+        (sector, byte_offset % self.bytes_per_sector as usize)
+    }
+}
+
+/// True if a FAT entry value ends a cluster chain
+pub fn is_end_of_chain(entry: u32) -> bool {
+    entry & FAT_ENTRY_MASK >= CLUSTER_END_MIN
+}
+
+/// An 8.3 directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortEntry {
+    pub name: [u8; 11],
+    pub attr: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn parse(raw: &[u8]) -> Self {
+        let mut name = [0; 11];
+        name.copy_from_slice(&raw[..11]);
+        ShortEntry {
+            name,
+            attr: raw[11],
+            first_cluster: ((read_u16(raw, 20) as u32) << 16) | read_u16(raw, 26) as u32,
+            size: read_u32(raw, 28),
+        }
+    }
+
+    /// Write this entry into a 32-byte slot, stamping `date` on all timestamps
+    pub fn encode(&self, raw: &mut [u8], date: u16) {
+        raw[..DIR_ENTRY_SIZE].fill(0);
+        raw[..11].copy_from_slice(&self.name);
+        raw[11] = self.attr;
+        write_u16(raw, 16, date); // Creation date
+        write_u16(raw, 18, date); // Last access date
+        write_u16(raw, 20, (self.first_cluster >> 16) as u16);
+        write_u16(raw, 24, date); // Write date
+        write_u16(raw, 26, self.first_cluster as u16);
+        write_u32(raw, 28, self.size);
+    }
+
+    /// Update the cluster and size fields of an encoded entry in place
+    pub fn patch(raw: &mut [u8], first_cluster: u32, size: u32) {
+        write_u16(raw, 20, (first_cluster >> 16) as u16);
+        write_u16(raw, 26, first_cluster as u16);
+        write_u32(raw, 28, size);
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    /// True for "." and ".."
+    pub fn is_dot_entry(&self) -> bool {
+        self.name[0] == b'.'
+    }
+
+    /// Display form of the 8.3 name ("README.TXT"), written into `out`
+    pub fn display_name<'a>(&self, out: &'a mut [u8; 12]) -> &'a str {
+        let base = trim_padding(&self.name[..8]);
+        let ext = trim_padding(&self.name[8..]);
+        let mut len = base.len();
+        out[..len].copy_from_slice(base);
+        // 0x05 stands in for a real leading 0xE5 byte
+        if len > 0 && out[0] == 0x05 {
+            out[0] = ENTRY_DELETED;
+        }
+        if !ext.is_empty() {
+            out[len] = b'.';
+            out[len + 1..len + 1 + ext.len()].copy_from_slice(ext);
+            len += 1 + ext.len();
+        }
+        for byte in &mut out[..len] {
+            if !byte.is_ascii() {
+                *byte = b'?';
+            }
+        }
+        core::str::from_utf8(&out[..len]).unwrap_or("?")
+    }
+}
+
+fn trim_padding(field: &[u8]) -> &[u8] {
+    let len = field.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &field[..len]
+}
+
+/// Checksum of an 8.3 name, stored in each of its LFN entries
+pub fn lfn_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Sequence number (1-based) of an LFN entry and whether it is the last one
+pub fn lfn_sequence(raw: &[u8]) -> (u8, bool) {
+    (raw[0] & LFN_SEQUENCE_MASK, raw[0] & LFN_LAST_ENTRY != 0)
+}
+
+pub fn lfn_entry_checksum(raw: &[u8]) -> u8 {
+    raw[13]
+}
+
+/// The 13 UCS-2 units held by one LFN entry (0x0000 / 0xFFFF pad the tail)
+pub fn lfn_chars(raw: &[u8]) -> [u16; LFN_CHARS_PER_ENTRY] {
+    let mut chars = [0; LFN_CHARS_PER_ENTRY];
+    for (slot, &offset) in chars.iter_mut().zip(LFN_CHAR_OFFSETS.iter()) {
+        *slot = read_u16(raw, offset);
+    }
+    chars
+}
+
+/// Encode one LFN entry holding `chars` (already padded)
+pub fn encode_lfn(raw: &mut [u8], sequence: u8, last: bool, checksum: u8, chars: &[u16; LFN_CHARS_PER_ENTRY]) {
+    raw[..DIR_ENTRY_SIZE].fill(0);
+    raw[0] = sequence | if last { LFN_LAST_ENTRY } else { 0 };
+    raw[11] = ATTR_LONG_NAME;
+    raw[13] = checksum;
+    for (&ch, &offset) in chars.iter().zip(LFN_CHAR_OFFSETS.iter()) {
+        write_u16(raw, offset, ch);
+    }
+}
+
+/// Number of LFN entries needed for a name of `units` UTF-16 code units
+pub fn lfn_entries_needed(units: usize) -> usize {
+    units.div_ceil(LFN_CHARS_PER_ENTRY)
+}
+
+/// Characters allowed in an 8.3 name besides letters and digits
+fn is_short_name_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase()
+        || byte.is_ascii_digit()
+        || b"$%'-_@~`!(){}^#&".contains(&byte)
+}
+
+/// The 8.3 form of `name` if it can be stored without an LFN
+/// Only names that are already upper case and fit 8.3 qualify, so the
+/// on-disk name round-trips exactly.
+pub fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (name.contains('.') && ext.is_empty()) {
+        return None;
+    }
+    if !base.bytes().chain(ext.bytes()).all(is_short_name_char) {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+/// Generate the numbered 8.3 alias ("LONGFI~1.TXT") for a long name
+pub fn short_alias(name: &str, number: u32) -> [u8; 11] {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+
+    let mut short = [b' '; 11];
+    for (slot, byte) in short[8..].iter_mut().zip(sanitize(ext)) {
+        *slot = byte;
+    }
+
+    // Format "~N" and keep the basis short enough for it to fit in 8 bytes
+    let mut suffix = [0u8; 11];
+    let mut suffix_len = 0;
+    let mut n = number.max(1);
+    while n > 0 {
+        suffix[suffix_len] = b'0' + (n % 10) as u8;
+        suffix_len += 1;
+        n /= 10;
+    }
+    suffix[suffix_len] = b'~';
+    suffix_len += 1;
+    suffix[..suffix_len].reverse();
+    let suffix_len = suffix_len.min(8);
+
+    let mut base_len = 0;
+    for (slot, byte) in short[..8 - suffix_len].iter_mut().zip(sanitize(base)) {
+        *slot = byte;
+        base_len += 1;
+    }
+    if base_len == 0 {
+        short[0] = b'_';
+        base_len = 1;
+    }
+    short[base_len..base_len + suffix_len].copy_from_slice(&suffix[..suffix_len]);
+    short
+}
+
+/// Upper-case a name piece and replace characters 8.3 names can't hold
+fn sanitize(piece: &str) -> impl Iterator<Item = u8> + '_ {
+    piece
+        .bytes()
+        .filter(|&b| b != b' ' && b != b'.')
+        .map(|b| {
+            let upper = b.to_ascii_uppercase();
+            if is_short_name_char(upper) { upper } else { b'_' }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot_sector() -> [u8; 512] {
+        let mut sector = [0u8; 512];
+        write_u16(&mut sector, 11, 512); // Bytes per sector
+        sector[13] = 8; // Sectors per cluster
+        write_u16(&mut sector, 14, 32); // Reserved sectors
+        sector[16] = 2; // FAT count
+        write_u32(&mut sector, 32, 131072); // Total sectors (64 MB)
+        write_u32(&mut sector, 36, 128); // Sectors per FAT
+        write_u32(&mut sector, 44, 2); // Root cluster
+        write_u16(&mut sector, 48, 1); // FSInfo sector
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        sector
+    }
+
+    #[test]
+    fn test_parse_bpb() {
+        let bpb = BiosParameterBlock::parse(&boot_sector()).unwrap();
+        assert_eq!(bpb.cluster_size(), 4096);
+        assert_eq!(bpb.fat_start_sector(), 32);
+        assert_eq!(bpb.data_start_sector(), 32 + 2 * 128);
+        assert_eq!(bpb.cluster_to_sector(2), bpb.data_start_sector());
+        assert_eq!(bpb.cluster_to_sector(3), bpb.data_start_sector() + 8);
+    }
+
+    #[test]
+    fn test_parse_bpb_rejects_fat16() {
+        let mut sector = boot_sector();
+        write_u16(&mut sector, 22, 64);
+        assert!(BiosParameterBlock::parse(&sector).is_err());
+    }
+
+    #[test]
+    fn test_parse_bpb_rejects_missing_signature() {
+        let mut sector = boot_sector();
+        sector[511] = 0;
+        assert!(BiosParameterBlock::parse(&sector).is_err());
+    }
+
+    #[test]
+    fn test_fat_entry_location() {
+        let bpb = BiosParameterBlock::parse(&boot_sector()).unwrap();
+        assert_eq!(bpb.fat_entry_location(5, 0), (32, 20));
+        assert_eq!(bpb.fat_entry_location(128, 0), (33, 0));
+        assert_eq!(bpb.fat_entry_location(5, 1), (32 + 128, 20));
+    }
+
+    #[test]
+    fn test_end_of_chain() {
+        assert!(is_end_of_chain(CLUSTER_END));
+        assert!(is_end_of_chain(0xFFFF_FFF8));
+        assert!(!is_end_of_chain(3));
+    }
+
+    #[test]
+    fn test_short_entry_round_trip() {
+        let entry = ShortEntry {
+            name: *b"README  TXT",
+            attr: ATTR_ARCHIVE,
+            first_cluster: 0x0001_0005,
+            size: 1234,
+        };
+        let mut raw = [0u8; 32];
+        entry.encode(&mut raw, 0);
+        assert_eq!(ShortEntry::parse(&raw), entry);
+
+        ShortEntry::patch(&mut raw, 9, 10);
+        let patched = ShortEntry::parse(&raw);
+        assert_eq!((patched.first_cluster, patched.size), (9, 10));
+    }
+
+    #[test]
+    fn test_display_name() {
+        let mut out = [0u8; 12];
+        let entry = ShortEntry { name: *b"README  TXT", attr: 0, first_cluster: 0, size: 0 };
+        assert_eq!(entry.display_name(&mut out), "README.TXT");
+        let entry = ShortEntry { name: *b"BOOT       ", attr: 0, first_cluster: 0, size: 0 };
+        assert_eq!(entry.display_name(&mut out), "BOOT");
+    }
+
+    #[test]
+    fn test_lfn_checksum() {
+        // Reference value computed with the algorithm from the FAT specification
+        let mut expected = 0u8;
+        for &byte in b"LONGFI~1TXT" {
+            expected = (if expected & 1 != 0 { 0x80 } else { 0 }) + (expected >> 1);
+            expected = expected.wrapping_add(byte);
+        }
+        assert_eq!(lfn_checksum(b"LONGFI~1TXT"), expected);
+    }
+
+    #[test]
+    fn test_lfn_round_trip() {
+        let mut chars = [0xFFFF; LFN_CHARS_PER_ENTRY];
+        for (slot, unit) in chars.iter_mut().zip("hello.txt".encode_utf16()) {
+            *slot = unit;
+        }
+        chars[9] = 0;
+
+        let mut raw = [0u8; 32];
+        encode_lfn(&mut raw, 1, true, 0xAB, &chars);
+        assert_eq!(lfn_sequence(&raw), (1, true));
+        assert_eq!(lfn_entry_checksum(&raw), 0xAB);
+        assert_eq!(raw[11], ATTR_LONG_NAME);
+        assert_eq!(lfn_chars(&raw), chars);
+    }
+
+    #[test]
+    fn test_lfn_entries_needed() {
+        assert_eq!(lfn_entries_needed(1), 1);
+        assert_eq!(lfn_entries_needed(13), 1);
+        assert_eq!(lfn_entries_needed(14), 2);
+    }
+
+    #[test]
+    fn test_exact_short_name() {
+        assert_eq!(exact_short_name("README.TXT"), Some(*b"README  TXT"));
+        assert_eq!(exact_short_name("BOOT"), Some(*b"BOOT       "));
+        assert_eq!(exact_short_name("readme.txt"), None);
+        assert_eq!(exact_short_name("TOOLONGNAME.TXT"), None);
+        assert_eq!(exact_short_name("A.B.C"), None);
+    }
+
+    #[test]
+    fn test_short_alias() {
+        assert_eq!(&short_alias("long file name.txt", 1), b"LONGFI~1TXT");
+        assert_eq!(&short_alias("notes", 12), b"NOTES~12   ");
+        assert_eq!(&short_alias(".hidden", 1), b"HIDDEN~1   ");
+        assert_eq!(&short_alias("a+b.c", 1), b"A_B~1   C  ");
+    }
+}
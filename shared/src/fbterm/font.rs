@@ -0,0 +1,155 @@
+//! Built-in 8x16 bitmap font
+//! Covers ASCII, the Latin-1 letters and symbols, and common punctuation
+//! such as curly quotes and dashes; each glyph is 16 rows with the leftmost
+//! pixel in bit 7. Accented letters are composed from their base letter and
+//! a mark, capitals losing two rows to make room above them.
+//! `to_cp437` maps characters for VGA text mode, whose font is code page 437.
+
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = 16;
+
+type Glyph = [u8; GLYPH_HEIGHT];
+
+static FONT_DATA: [Glyph; 128] = include!("font8x16.rs");
+
+static SYMBOLS: [(char, Glyph); 45] = include!("symbols8x16.rs");
+
+/// What composes a letter onto its base
+#[derive(Clone, Copy)]
+enum Mark {
+    /// Rows drawn above the letter, ending one blank row over it
+    Above([u8; 3]),
+    Cedilla,
+    Stroke,
+}
+
+const GRAVE: Mark = Mark::Above([0x00, 0x30, 0x18]);
+const ACUTE: Mark = Mark::Above([0x00, 0x0C, 0x18]);
+const CIRCUMFLEX: Mark = Mark::Above([0x00, 0x1C, 0x36]);
+const TILDE: Mark = Mark::Above([0x00, 0x3B, 0x6E]);
+const DIAERESIS: Mark = Mark::Above([0x00, 0x66, 0x66]);
+const RING: Mark = Mark::Above([0x1C, 0x36, 0x1C]);
+
+/// Accented letters, and the base letters they are drawn from, by mark
+const ACCENTED: [(&str, &str, Mark); 8] = [
+    ("ÀÈÌÒÙàèìòù", "AEIOUaeiou", GRAVE),
+    ("ÁÉÍÓÚÝáéíóúý", "AEIOUYaeiouy", ACUTE),
+    ("ÂÊÎÔÛâêîôû", "AEIOUaeiou", CIRCUMFLEX),
+    ("ÃÑÕãñõ", "ANOano", TILDE),
+    ("ÄËÏÖÜäëïöüÿ", "AEIOUaeiouy", DIAERESIS),
+    ("Åå", "Aa", RING),
+    ("Çç", "Cc", Mark::Cedilla),
+    ("Øø", "Oo", Mark::Stroke),
+];
+
+/// Bitmap for `c`, if the font has one
+pub fn glyph(c: char) -> Option<Glyph> {
+    if c.is_ascii() {
+        return Some(FONT_DATA[c as usize]);
+    }
+    if let Some(&(_, glyph)) = SYMBOLS.iter().find(|&&(symbol, _)| symbol == c) {
+        return Some(glyph);
+    }
+    ACCENTED.iter().find_map(|&(letters, bases, mark)| {
+        let base = bases.chars().nth(letters.chars().position(|letter| letter == c)?)?;
+        Some(compose(base, mark))
+    })
+}
+
+/// `base` with `mark` added
+fn compose(base: char, mark: Mark) -> Glyph {
+    let mut glyph = FONT_DATA[base as usize];
+    let Mark::Above(rows) = mark else {
+        let body = glyph.iter().position(|&bits| bits != 0).unwrap_or(0)..12;
+        match mark {
+            Mark::Cedilla => glyph[12..14].copy_from_slice(&[0x0C, 0x38]),
+            // A line from top right to bottom left through the letter
+            _ => {
+                let span = body.len().max(2) - 1;
+                for (step, row) in body.enumerate() {
+                    glyph[row] |= 0x01 << (step * 6 / span);
+                }
+            }
+        }
+        return glyph;
+    };
+    if base.is_ascii_uppercase() {
+        squash(&mut glyph);
+    } else {
+        // Lowercase letters start at row 5; clear the dot of i
+        glyph[..5].fill(0);
+    }
+    let top = glyph.iter().position(|&bits| bits != 0).unwrap_or(GLYPH_HEIGHT);
+    for (offset, bits) in rows.into_iter().enumerate() {
+        if let Some(row) = (top + offset).checked_sub(4) {
+            glyph[row] |= bits;
+        }
+    }
+    glyph
+}
+
+/// Drop two rows that add nothing to the one above, from the bottom of the
+/// letter, so it sits two rows lower with its baseline in place
+fn squash(glyph: &mut Glyph) {
+    let mut squashed = [0; GLYPH_HEIGHT];
+    let mut to = GLYPH_HEIGHT;
+    let mut drop = 2;
+    for row in (0..GLYPH_HEIGHT).rev() {
+        if drop > 0 && (3..12).contains(&row) && glyph[row] != 0 && glyph[row] & !glyph[row - 1] == 0 {
+            drop -= 1;
+            continue;
+        }
+        to -= 1;
+        squashed[to] = glyph[row];
+        if to == 0 {
+            break;
+        }
+    }
+    *glyph = squashed;
+}
+
+/// Code page 437 from 0x80 on: accented letters, box drawing, Greek and math
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// The code page 437 byte that shows `c`, if it has one
+pub fn to_cp437(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        _ => CP437_HIGH.chars().position(|high| high == c).map(|index| 0x80 + index as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cp437() {
+        assert_eq!(CP437_HIGH.chars().count(), 128);
+        assert_eq!(to_cp437('a'), Some(b'a'));
+        assert_eq!(to_cp437('ä'), Some(0x84));
+        assert_eq!(to_cp437('ß'), Some(0xE1));
+        assert_eq!(to_cp437('°'), Some(0xF8));
+        assert_eq!(to_cp437('€'), None);
+        assert_eq!(to_cp437('\n'), None);
+    }
+
+    #[test]
+    fn test_accented_letters() {
+        for (letters, bases, _) in ACCENTED {
+            assert_eq!(letters.chars().count(), bases.chars().count());
+            for (letter, base) in letters.chars().zip(bases.chars()) {
+                let glyph = glyph(letter).unwrap();
+                assert_ne!(glyph, FONT_DATA[base as usize], "{}", letter);
+                // Marks above capitals stay clear of the letter
+                if base.is_ascii_uppercase() && !matches!(letter, 'Ç' | 'Ø') {
+                    assert_eq!(glyph[3], 0, "{}", letter);
+                }
+            }
+        }
+        assert!(glyph('€').is_some() && glyph('ß').is_some());
+        assert_eq!(glyph('Ж'), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Character cells kept in memory
+//! A `TextGrid` is a `Screen` that stores its cells instead of drawing them,
+//! so several terminals can share one display: each writes to its own grid,
+//! and the one on show is copied out with `render`. Lines scrolled off the
+//! top are kept as scrollback, as many as the buffer has room for, and
+//! `scroll_view` shows them. `render` only redraws what changed since it last
+//! ran, moving the target's pixels for whole-screen scrolls.
+
+use super::{Cell, Screen};
+
+pub struct TextGrid<'a> {
+    /// Lines of `cols` cells, used as a ring: the screen's rows, and the
+    /// scrollback above them
+    cells: &'a mut [Cell],
+    cols: usize,
+    rows: usize,
+    /// Lines `cells` holds
+    lines: usize,
+    /// Ring line of the screen's top row
+    top: usize,
+    /// Scrollback lines filled so far
+    history: usize,
+    /// Lines back from the bottom that the view is scrolled
+    view: usize,
+    damage: Damage,
+}
+
+/// What `render` has to redraw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Damage {
+    /// The target shows this grid as it is
+    None,
+    /// Lines scrolled since the last render, then rows `from..to` changed
+    Rows { scrolled: usize, from: usize, to: usize },
+    All,
+}
+
+impl<'a> TextGrid<'a> {
+    /// A blank `cols` x `rows` grid keeping scrollback in whatever of `cells`
+    /// the screen doesn't need; None if `cells` can't hold the screen
+    pub fn new(cells: &'a mut [Cell], cols: usize, rows: usize) -> Option<Self> {
+        if cols == 0 || rows == 0 || cells.len() / cols < rows {
+            return None;
+        }
+        let lines = cells.len() / cols;
+        cells.fill(Cell::BLANK);
+        Some(TextGrid { cells, cols, rows, lines, top: 0, history: 0, view: 0, damage: Damage::All })
+    }
+
+    /// Lines of scrollback there are to look at
+    pub fn history(&self) -> usize {
+        self.history
+    }
+
+    /// How many lines back the view is scrolled (0: the live screen)
+    pub fn view(&self) -> usize {
+        self.view
+    }
+
+    /// Scroll the view `lines` back into the scrollback (negative: forward),
+    /// staying between the oldest line kept and the live screen
+    pub fn scroll_view(&mut self, lines: isize) {
+        let view = self.view.saturating_add_signed(lines).min(self.history);
+        if view != self.view {
+            self.view = view;
+            self.damage = Damage::All;
+        }
+    }
+
+    /// Cell `col` of row `row` as the view shows it
+    pub fn cell(&self, col: usize, row: usize) -> Cell {
+        self.cells[self.view_line(row) * self.cols + col]
+    }
+
+    /// Make the next `render` draw everything, e.g. when this grid is put
+    /// back on the display
+    pub fn redraw_all(&mut self) {
+        self.damage = Damage::All;
+    }
+
+    /// Bring `target`, which shows this grid, up to date
+    pub fn render<S: Screen + ?Sized>(&mut self, target: &mut S) {
+        let (from, to) = match core::mem::replace(&mut self.damage, Damage::None) {
+            Damage::None => return,
+            Damage::Rows { scrolled, from, to } if scrolled < self.rows => {
+                for _ in 0..scrolled {
+                    target.scroll_line(0);
+                }
+                (from, to)
+            }
+            _ => (0, self.rows),
+        };
+        let (cols, rows) = target.dimensions();
+        for row in from..to.min(rows) {
+            for col in 0..self.cols.min(cols) {
+                target.put_cell(col, row, self.cell(col, row));
+            }
+        }
+    }
+
+    /// Ring line showing screen row `row` with the view scrolled back
+    fn view_line(&self, row: usize) -> usize {
+        (self.top + self.lines - self.view + row) % self.lines
+    }
+
+    fn line_mut(&mut self, row: usize) -> &mut [Cell] {
+        let start = (self.top + row) % self.lines * self.cols;
+        &mut self.cells[start..start + self.cols]
+    }
+
+    /// Note a change to screen row `row`; output always returns the view to
+    /// the live screen
+    fn touch(&mut self, row: usize) {
+        if self.view != 0 {
+            self.view = 0;
+            self.damage = Damage::All;
+        }
+        self.damage = match self.damage {
+            Damage::None => Damage::Rows { scrolled: 0, from: row, to: row + 1 },
+            Damage::Rows { scrolled, from, to } => Damage::Rows { scrolled, from: from.min(row), to: to.max(row + 1) },
+            Damage::All => Damage::All,
+        };
+    }
+}
+
+impl Screen for TextGrid<'_> {
+    fn dimensions(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    fn put_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        if col < self.cols && row < self.rows {
+            self.line_mut(row)[col] = cell;
+            self.touch(row);
+        }
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize, bg: u8) {
+        let to = to.min(self.cols);
+        if from < to && row < self.rows {
+            self.line_mut(row)[from..to].fill(Cell { bg, ..Cell::BLANK });
+            self.touch(row);
+        }
+    }
+
+    fn scroll_line(&mut self, bg: u8) {
+        // The old top row becomes the newest line of scrollback
+        self.top = (self.top + 1) % self.lines;
+        self.history = (self.history + 1).min(self.lines - self.rows);
+        self.line_mut(self.rows - 1).fill(Cell { bg, ..Cell::BLANK });
+        if self.view != 0 {
+            self.view = 0;
+            self.damage = Damage::All;
+        }
+        // Rows already changed moved up with the text
+        self.damage = match self.damage {
+            Damage::None => Damage::Rows { scrolled: 1, from: self.rows - 1, to: self.rows },
+            Damage::Rows { scrolled, from, .. } => {
+                Damage::Rows { scrolled: scrolled + 1, from: from.saturating_sub(1), to: self.rows }
+            }
+            Damage::All => Damage::All,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::super::Terminal;
+    use super::*;
+    use std::string::String;
+    use std::vec;
+
+    /// The grid as its view shows it, one line per row
+    fn text(grid: &TextGrid) -> String {
+        let (cols, rows) = grid.dimensions();
+        let mut text = String::new();
+        for row in 0..rows {
+            text.extend((0..cols).map(|col| grid.cell(col, row).ch));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// A screen that records which cells were drawn and how often it scrolled
+    struct Recorder {
+        cells: std::vec::Vec<Cell>,
+        cols: usize,
+        drawn: usize,
+        scrolls: usize,
+    }
+
+    impl Screen for Recorder {
+        fn dimensions(&self) -> (usize, usize) {
+            (self.cols, self.cells.len() / self.cols)
+        }
+
+        fn put_cell(&mut self, col: usize, row: usize, cell: Cell) {
+            self.cells[row * self.cols + col] = cell;
+            self.drawn += 1;
+        }
+
+        fn erase_cells(&mut self, _row: usize, _from: usize, _to: usize, _bg: u8) {
+            unreachable!("render draws cells");
+        }
+
+        fn scroll_line(&mut self, _bg: u8) {
+            self.cells.rotate_left(self.cols);
+            self.scrolls += 1;
+        }
+    }
+
+    #[test]
+    fn test_terminal_on_grid_keeps_scrollback() {
+        let mut cells = vec![Cell::default(); 3 * 5];
+        let mut term = Terminal::new(TextGrid::new(&mut cells, 3, 2).unwrap());
+        term.write_str("one\ntwo\nsix\nten");
+        let grid = term.screen_mut();
+        assert_eq!(text(grid), "six\nten\n");
+        assert_eq!(grid.history(), 2);
+
+        grid.scroll_view(1);
+        assert_eq!(text(grid), "two\nsix\n");
+        // Clamped to the oldest line kept, then back to the live screen
+        grid.scroll_view(10);
+        assert_eq!((grid.view(), text(grid).as_str()), (2, "one\ntwo\n"));
+        grid.scroll_view(-10);
+        assert_eq!(text(grid), "six\nten\n");
+
+        // Only the three lines beyond the screen are kept
+        term.write_str("\na\nb\nc");
+        let grid = term.screen_mut();
+        assert_eq!(grid.history(), 3);
+        grid.scroll_view(3);
+        assert_eq!(text(grid), "six\nten\n");
+    }
+
+    #[test]
+    fn test_output_returns_to_live_screen() {
+        let mut cells = vec![Cell::default(); 2 * 4];
+        let mut term = Terminal::new(TextGrid::new(&mut cells, 2, 2).unwrap());
+        term.write_str("a\nb\nc");
+        term.screen_mut().scroll_view(1);
+        term.write_str("d");
+        assert_eq!(term.screen().view(), 0);
+        assert_eq!(text(term.screen()), "b \ncd\n");
+    }
+
+    #[test]
+    fn test_render_draws_only_changes() {
+        let mut cells = vec![Cell::default(); 4 * 6];
+        let mut term = Terminal::new(TextGrid::new(&mut cells, 4, 3).unwrap());
+        let mut display = Recorder { cells: vec![Cell::default(); 4 * 3], cols: 4, drawn: 0, scrolls: 0 };
+
+        // Everything the first time
+        term.screen_mut().render(&mut display);
+        assert_eq!(display.drawn, 12);
+
+        term.write_str("ab");
+        term.screen_mut().render(&mut display);
+        assert_eq!(display.drawn, 12 + 4);
+        term.screen_mut().render(&mut display);
+        assert_eq!(display.drawn, 16);
+
+        // A scroll moves the display's rows, then draws the changed ones
+        term.write_str("\n\n\nxy");
+        term.screen_mut().render(&mut display);
+        assert_eq!(display.scrolls, 1);
+        assert_eq!(display.drawn, 16 + 4);
+        let shown: String = display.cells.iter().map(|cell| cell.ch).collect();
+        assert_eq!(shown, "        xy  ");
+        assert!(display.cells.iter().zip(0..).all(|(&cell, i)| cell == term.screen().cell(i % 4, i / 4)));
+
+        // Scrolling a whole screen or more redraws without scrolling
+        term.write_str("\n1\n2\n3\n4");
+        term.screen_mut().render(&mut display);
+        assert_eq!(display.scrolls, 1);
+        let shown: String = display.cells.iter().map(|cell| cell.ch).collect();
+        assert_eq!(shown, "2   3   4   ");
+    }
+
+    #[test]
+    fn test_grid_size_checks() {
+        let mut cells = vec![Cell::default(); 6];
+        assert!(TextGrid::new(&mut cells, 3, 2).is_some());
+        assert!(TextGrid::new(&mut cells, 3, 3).is_none());
+        assert!(TextGrid::new(&mut cells, 0, 1).is_none());
+        assert_eq!(TextGrid::new(&mut cells, 3, 2).unwrap().history(), 0);
+    }
+}
@@ -0,0 +1,611 @@
+//! Framebuffer text terminal
+//! Turns UTF-8 text into character cells on a `Screen`, handling control
+//! characters and the ANSI escape sequences the kernel emits (SGR colors,
+//! cursor positioning, erase). Each character takes one cell. Every
+//! `Surface` is a screen, drawing cells as glyphs of the built-in 8x16 font,
+//! '?' for characters the font lacks; a `FontScreen` draws them in a PSF
+//! font instead. The kernel renders into the Limine framebuffer, and tests
+//! render into a `MemSurface` and compare the pixels against golden images
+//! in `fixtures/console/`. A `TextGrid` keeps the cells instead, with
+//! scrollback, and draws them onto another screen when asked.
+
+pub mod font;
+pub mod grid;
+pub mod psf;
+pub mod surface;
+
+pub use grid::TextGrid;
+pub use psf::Psf;
+pub use surface::{MemSurface, Surface};
+
+use font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// The 16 console colors in ANSI order (black, red, green, yellow, blue,
+/// magenta, cyan, white, then the bright variants), with VGA RGB values
+pub const PALETTE: [u32; 16] = [
+    0x000000, 0xAA0000, 0x00AA00, 0xAA5500, 0x0000AA, 0xAA00AA, 0x00AAAA, 0xAAAAAA,
+    0x555555, 0xFF5555, 0x55FF55, 0xFFFF55, 0x5555FF, 0xFF55FF, 0x55FFFF, 0xFFFFFF,
+];
+
+const DEFAULT_FG: u8 = 15;
+const DEFAULT_BG: u8 = 0;
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMS: usize = 4;
+
+/// One character cell: a character and its colors, as `PALETTE` indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: u8,
+    pub bg: u8,
+}
+
+impl Cell {
+    /// An empty cell in the default colors
+    pub const BLANK: Cell = Cell { ch: ' ', fg: DEFAULT_FG, bg: DEFAULT_BG };
+}
+
+/// Where a `Terminal` puts its character cells
+pub trait Screen {
+    /// Size in cells (columns, rows)
+    fn dimensions(&self) -> (usize, usize);
+
+    fn put_cell(&mut self, col: usize, row: usize, cell: Cell);
+
+    /// Blank cells `from..to` of `row` in background color `bg`
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize, bg: u8);
+
+    /// Move every row up one, blanking the bottom row in `bg`
+    fn scroll_line(&mut self, bg: u8);
+
+    /// Blank the whole screen in `bg`
+    fn clear(&mut self, bg: u8) {
+        let (cols, rows) = self.dimensions();
+        for row in 0..rows {
+            self.erase_cells(row, 0, cols, bg);
+        }
+    }
+}
+
+/// Glyphs to draw cells with
+pub enum Font<'a> {
+    /// The 8x16 font in `font`
+    Builtin,
+    Psf(&'a Psf<'a>),
+}
+
+impl Font<'_> {
+    /// Size of a cell in pixels (width, height)
+    pub fn cell_size(&self) -> (usize, usize) {
+        match self {
+            Font::Builtin => (GLYPH_WIDTH, GLYPH_HEIGHT),
+            Font::Psf(psf) => psf.size(),
+        }
+    }
+
+    /// Draw `cell` with its top left corner at (x, y)
+    fn draw<S: Surface + ?Sized>(&self, surface: &mut S, x: usize, y: usize, cell: Cell) {
+        let (fg, bg) = (PALETTE[cell.fg as usize % 16], PALETTE[cell.bg as usize % 16]);
+        let (width, height) = self.cell_size();
+        let builtin;
+        let (bitmap, row_bytes) = match self {
+            Font::Builtin => {
+                builtin = glyph(cell.ch).or_else(|| glyph('?')).unwrap_or_default();
+                (&builtin[..], 1)
+            }
+            Font::Psf(psf) => (psf.glyph(cell.ch).or_else(|| psf.glyph('?')).unwrap_or_default(), psf.row_bytes()),
+        };
+        if bitmap.is_empty() {
+            surface.fill_rect(x, y, width, height, bg);
+            return;
+        }
+        for (dy, bits) in bitmap.chunks_exact(row_bytes).enumerate() {
+            for dx in 0..width {
+                let on = bits[dx / 8] & (0x80 >> (dx % 8)) != 0;
+                surface.set_pixel(x + dx, y + dy, if on { fg } else { bg });
+            }
+        }
+    }
+}
+
+/// Cells of `font` on `surface`, as many whole ones as fit
+fn dimensions<S: Surface + ?Sized>(surface: &S, font: &Font) -> (usize, usize) {
+    let (width, height) = font.cell_size();
+    (surface.width() / width, surface.height() / height)
+}
+
+fn erase_cells<S: Surface + ?Sized>(surface: &mut S, font: &Font, row: usize, from: usize, to: usize, bg: u8) {
+    if from < to {
+        let (width, height) = font.cell_size();
+        surface.fill_rect(from * width, row * height, (to - from) * width, height, PALETTE[bg as usize % 16]);
+    }
+}
+
+/// Cells drawn in the built-in font
+impl<S: Surface> Screen for S {
+    fn dimensions(&self) -> (usize, usize) {
+        dimensions(self, &Font::Builtin)
+    }
+
+    fn put_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        Font::Builtin.draw(self, col * GLYPH_WIDTH, row * GLYPH_HEIGHT, cell);
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize, bg: u8) {
+        erase_cells(self, &Font::Builtin, row, from, to, bg);
+    }
+
+    fn scroll_line(&mut self, bg: u8) {
+        self.scroll_up(GLYPH_HEIGHT, PALETTE[bg as usize % 16]);
+    }
+
+    /// The margin past the last whole cell too
+    fn clear(&mut self, bg: u8) {
+        let (width, height) = (self.width(), self.height());
+        self.fill_rect(0, 0, width, height, PALETTE[bg as usize % 16]);
+    }
+}
+
+/// A surface whose cells are drawn in a chosen font
+pub struct FontScreen<'a, S: Surface> {
+    surface: S,
+    font: Font<'a>,
+}
+
+impl<'a, S: Surface> FontScreen<'a, S> {
+    pub fn new(surface: S, font: Font<'a>) -> Self {
+        FontScreen { surface, font }
+    }
+
+    pub fn font(&self) -> &Font<'a> {
+        &self.font
+    }
+
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+
+    pub fn surface_mut(&mut self) -> &mut S {
+        &mut self.surface
+    }
+}
+
+impl<S: Surface> Screen for FontScreen<'_, S> {
+    fn dimensions(&self) -> (usize, usize) {
+        dimensions(&self.surface, &self.font)
+    }
+
+    fn put_cell(&mut self, col: usize, row: usize, cell: Cell) {
+        let (width, height) = self.font.cell_size();
+        self.font.draw(&mut self.surface, col * width, row * height, cell);
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize, bg: u8) {
+        erase_cells(&mut self.surface, &self.font, row, from, to, bg);
+    }
+
+    fn scroll_line(&mut self, bg: u8) {
+        self.surface.scroll_up(self.font.cell_size().1, PALETTE[bg as usize % 16]);
+    }
+
+    /// The margin past the last whole cell too
+    fn clear(&mut self, bg: u8) {
+        self.surface.clear(bg);
+    }
+}
+
+/// Escape sequence parser state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After ESC
+    Escape,
+    /// After ESC [, collecting numeric parameters
+    Csi,
+}
+
+pub struct Terminal<S: Screen> {
+    screen: S,
+    cols: usize,
+    rows: usize,
+    col: usize,
+    row: usize,
+    fg: u8,
+    bg: u8,
+    bold: bool,
+    state: State,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+}
+
+impl<S: Screen> Terminal<S> {
+    /// A terminal covering the whole of `screen`
+    /// The screen is left as it is; call `clear` for a blank one.
+    pub fn new(screen: S) -> Self {
+        let (cols, rows) = screen.dimensions();
+        Terminal {
+            screen,
+            cols,
+            rows,
+            col: 0,
+            row: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+        }
+    }
+
+    pub fn screen(&self) -> &S {
+        &self.screen
+    }
+
+    /// The screen, to draw on directly (see `shared::graphics`)
+    /// The terminal doesn't know about such drawing: text written later
+    /// may cover it, and scrolling moves it up with the text.
+    pub fn screen_mut(&mut self) -> &mut S {
+        &mut self.screen
+    }
+
+    /// Size in character cells (columns, rows)
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Cursor cell (column, row)
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    /// Blank the whole screen in the background color and home the cursor
+    pub fn clear(&mut self) {
+        self.screen.clear(self.bg);
+        self.col = 0;
+        self.row = 0;
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        if self.rows == 0 || self.cols == 0 {
+            return;
+        }
+        match self.state {
+            State::Ground => self.ground(c),
+            State::Escape => {
+                self.state = match c {
+                    '[' => {
+                        self.params = [0; MAX_PARAMS];
+                        self.param_count = 0;
+                        State::Csi
+                    }
+                    // Intermediate bytes, as in charset selection (ESC ( B)
+                    ' '..='/' => State::Escape,
+                    // Any other escape is complete and dropped
+                    _ => State::Ground,
+                };
+            }
+            // Sequences are ASCII; anything else ends one unfinished
+            State::Csi => match u8::try_from(c) {
+                Ok(byte) => self.csi(byte),
+                Err(_) => self.state = State::Ground,
+            },
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1B' => self.state = State::Escape,
+            '\n' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.saturating_sub(1),
+            '\t' => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            // C0 and C1 controls draw nothing
+            _ if c.is_control() => {}
+            c => {
+                if self.col >= self.cols {
+                    self.col = 0;
+                    self.line_feed();
+                }
+                self.draw_cell(c, self.col, self.row);
+                self.col += 1;
+            }
+        }
+    }
+
+    fn csi(&mut self, byte: u8) {
+        match byte {
+            b'0'..=b'9' => {
+                let index = self.param_count.max(1) - 1;
+                if index < MAX_PARAMS {
+                    let param = &mut self.params[index];
+                    *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                }
+                self.param_count = self.param_count.max(1);
+            }
+            b';' => self.param_count = self.param_count.max(1) + 1,
+            0x40..=0x7E => {
+                self.state = State::Ground;
+                self.dispatch(byte);
+            }
+            // Intermediate and private-marker bytes are accepted and ignored
+            0x20..=0x3F => {}
+            _ => self.state = State::Ground,
+        }
+    }
+
+    /// Parameter `index`, or `default` if it was omitted or zero
+    fn param(&self, index: usize, default: u16) -> usize {
+        match self.params.get(index) {
+            Some(&value) if index < self.param_count && value != 0 => value as usize,
+            _ => default as usize,
+        }
+    }
+
+    fn dispatch(&mut self, command: u8) {
+        match command {
+            b'm' => self.select_graphic_rendition(),
+            b'H' | b'f' => {
+                self.row = (self.param(0, 1) - 1).min(self.rows - 1);
+                self.col = (self.param(1, 1) - 1).min(self.cols - 1);
+            }
+            b'A' => self.row = self.row.saturating_sub(self.param(0, 1)),
+            b'B' => self.row = (self.row + self.param(0, 1)).min(self.rows - 1),
+            b'C' => self.col = (self.col + self.param(0, 1)).min(self.cols - 1),
+            b'D' => self.col = self.col.saturating_sub(self.param(0, 1)),
+            b'J' => match self.param(0, 0) {
+                0 => {
+                    self.erase_line_from(self.col.min(self.cols));
+                    self.erase_rows(self.row + 1, self.rows);
+                }
+                1 => {
+                    self.erase_rows(0, self.row);
+                    self.erase_cells(self.row, 0, (self.col + 1).min(self.cols));
+                }
+                _ => self.erase_rows(0, self.rows),
+            },
+            b'K' => match self.param(0, 0) {
+                0 => self.erase_line_from(self.col.min(self.cols)),
+                1 => self.erase_cells(self.row, 0, (self.col + 1).min(self.cols)),
+                _ => self.erase_cells(self.row, 0, self.cols),
+            },
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        // ESC [ m is the same as ESC [ 0 m
+        for index in 0..self.param_count.clamp(1, MAX_PARAMS) {
+            let value = self.params[index];
+            match value {
+                0 => {
+                    self.fg = DEFAULT_FG;
+                    self.bg = DEFAULT_BG;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.fg = (value - 30) as u8,
+                39 => self.fg = DEFAULT_FG,
+                40..=47 => self.bg = (value - 40) as u8,
+                49 => self.bg = DEFAULT_BG,
+                90..=97 => self.fg = (value - 90) as u8 + 8,
+                100..=107 => self.bg = (value - 100) as u8 + 8,
+                _ => {}
+            }
+        }
+    }
+
+    /// Move down a row, scrolling the screen at the bottom
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.screen.scroll_line(self.bg);
+        }
+    }
+
+    fn draw_cell(&mut self, ch: char, col: usize, row: usize) {
+        // Bold brightens the eight basic colors, as on the VGA console
+        let fg = if self.bold && self.fg < 8 { self.fg + 8 } else { self.fg };
+        self.screen.put_cell(col, row, Cell { ch, fg, bg: self.bg });
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        self.screen.erase_cells(row, from, to, self.bg);
+    }
+
+    fn erase_line_from(&mut self, col: usize) {
+        self.erase_cells(self.row, col, self.cols);
+    }
+
+    fn erase_rows(&mut self, from: usize, to: usize) {
+        for row in from..to {
+            self.erase_cells(row, 0, self.cols);
+        }
+    }
+}
+
+impl<S: Screen> core::fmt::Write for Terminal<S> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        Terminal::write_str(self, s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+    use std::vec;
+
+    /// Render `input` on a `cols` x `rows` cell screen and return it in the
+    /// golden text format: one line per pixel row, one character per pixel,
+    /// '.' for black and the palette index in hex for other colors
+    fn render(cols: usize, rows: usize, input: &str) -> String {
+        let (width, height) = (cols * GLYPH_WIDTH, rows * GLYPH_HEIGHT);
+        let mut pixels = vec![0u32; width * height];
+        let mut term = Terminal::new(MemSurface::new(&mut pixels, width, height).unwrap());
+        term.clear();
+        term.write_str(input);
+
+        let mut text = String::new();
+        for y in 0..height {
+            for x in 0..width {
+                let color = term.screen().pixel(x, y);
+                text.push(match PALETTE.iter().position(|&c| c == color) {
+                    Some(0) => '.',
+                    Some(index) => char::from_digit(index as u32, 16).unwrap(),
+                    None => '?',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Compare against `fixtures/console/<name>.txt`
+    /// Run with UPDATE_GOLDEN=1 to rewrite the file after an intended change.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = std::format!("{}/fixtures/console/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            expected == actual,
+            "{} does not match; rerun with UPDATE_GOLDEN=1 if the change is intended\n{}",
+            path,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_golden_glyphs() {
+        assert_golden("glyphs", &render(4, 2, "Hi!\n:)"));
+    }
+
+    #[test]
+    fn test_golden_colors() {
+        assert_golden("colors", &render(4, 1, "\x1B[31mR\x1B[42mG\x1B[0mN\x1B[1;34mB"));
+    }
+
+    #[test]
+    fn test_golden_scrolling() {
+        assert_golden("scrolling", &render(2, 2, "ab\ncd\nef"));
+    }
+
+    #[test]
+    fn test_golden_escapes() {
+        // Draw over a full screen, then home, erase a line end, move, and
+        // overwrite with backspace and carriage return
+        let input = "xxxx\nxxxx\nxxxx\x1B[H\x1B[2;2H\x1B[K\x1B[3;1H\x1B[1K\x1B[1;1Hab\x08c\rd";
+        assert_golden("escapes", &render(4, 3, input));
+    }
+
+    #[test]
+    fn test_clear_screen_sequence() {
+        // What `clear` sends to Limine's terminal works here too
+        assert_eq!(render(2, 1, "ab\x1B[2J\x1B[H"), render(2, 1, ""));
+    }
+
+    fn terminal(pixels: &mut [u32], cols: usize, rows: usize) -> Terminal<MemSurface<'_>> {
+        let surface = MemSurface::new(pixels, cols * GLYPH_WIDTH, rows * GLYPH_HEIGHT).unwrap();
+        Terminal::new(surface)
+    }
+
+    #[test]
+    fn test_cursor_tracking() {
+        let mut pixels = vec![0u32; 10 * 8 * 3 * 16];
+        let mut term = terminal(&mut pixels, 10, 3);
+        assert_eq!(term.size(), (10, 3));
+        term.write_str("abc\tx");
+        assert_eq!(term.cursor(), (9, 0));
+        term.write_str("\x1B[2;5H");
+        assert_eq!(term.cursor(), (4, 1));
+        term.write_str("\x1B[A\x1B[3C\x1B[10D\x1B[99B");
+        assert_eq!(term.cursor(), (0, 2));
+        // Out-of-range positions clamp to the screen
+        term.write_str("\x1B[99;99H");
+        assert_eq!(term.cursor(), (9, 2));
+    }
+
+    #[test]
+    fn test_wrap_and_scroll_at_last_column() {
+        let mut pixels = vec![0u32; 2 * 8 * 2 * 16];
+        let mut term = terminal(&mut pixels, 2, 2);
+        term.write_str("abcde");
+        assert_eq!(term.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn test_malformed_escapes_are_harmless() {
+        let mut pixels = vec![0u32; 4 * 8 * 16];
+        let mut term = terminal(&mut pixels, 4, 1);
+        term.write_str("\x1B[99999999999999;;;;;;;;;9m\x1B(B\x1B[?25l\x1B[\x07");
+        assert_eq!(term.cursor(), (0, 0));
+        // Parsing resumes normally afterwards
+        term.write_str("\x1B[0mok");
+        assert_eq!(term.cursor(), (2, 0));
+    }
+
+    #[test]
+    fn test_unicode_cells() {
+        let mut pixels = vec![0u32; 4 * 8 * 16];
+        let mut term = terminal(&mut pixels, 4, 1);
+        term.write_str("ä€\u{85}Ж");
+        assert_eq!(term.cursor(), (3, 0));
+        // Characters the font lacks show as '?'
+        assert_eq!(render(2, 1, "Жx"), render(2, 1, "?x"));
+        assert_ne!(render(1, 1, "ä"), render(1, 1, "a"));
+        assert_golden("latin1", &render(4, 1, "äÉß€"));
+    }
+
+    #[test]
+    fn test_psf_font() {
+        // A PSF1 font of 8x2 glyphs where only 'x' is drawn: a bar on top
+        let mut data = vec![0x36, 0x04, 0, 2];
+        data.resize(4 + 256 * 2, 0);
+        data[4 + 2 * usize::from(b'x')] = 0xFF;
+        let psf = Psf::parse(&data).unwrap();
+        let font = Font::Psf(&psf);
+        assert_eq!(font.cell_size(), (8, 2));
+
+        let mut pixels = vec![0u32; 20 * 5];
+        let mut term = Terminal::new(FontScreen::new(MemSurface::new(&mut pixels, 20, 5).unwrap(), font));
+        assert_eq!(term.size(), (2, 2));
+        term.write_str("\x1B[31m\nxЖ");
+        let surface = term.screen().surface();
+        assert!((8..16).all(|x| surface.pixel(x, 2) == 0) && surface.pixel(0, 2) == PALETTE[1]);
+        assert!((0..16).all(|x| surface.pixel(x, 3) == 0));
+    }
+
+    #[test]
+    fn test_surface_too_small_for_a_cell() {
+        let mut pixels = vec![0u32; 4 * 4];
+        let mut term = Terminal::new(MemSurface::new(&mut pixels, 4, 4).unwrap());
+        assert_eq!(term.size(), (0, 0));
+        term.write_str("hello\n");
+        assert!(pixels.iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn test_mem_surface_size_check() {
+        let mut pixels = [0u32; 6];
+        assert!(MemSurface::new(&mut pixels, 2, 3).is_some());
+        assert!(MemSurface::new(&mut pixels, 2, 2).is_none());
+        assert!(MemSurface::new(&mut pixels, usize::MAX, 2).is_none());
+    }
+}
@@ -0,0 +1,257 @@
+//! PC Screen Font (PSF1 and PSF2) files
+//! The console font format of Linux and the BSDs, loaded at boot as a module
+//! in place of the built-in 8x16 font. Glyphs are bitmaps of `height` rows,
+//! each row `(width + 7) / 8` bytes with the leftmost pixel in the top bit.
+//! A font with a Unicode table says which characters each glyph shows;
+//! without one, glyph N shows character N. Multi-character sequences in the
+//! table are skipped, since a cell holds one character. Lookups below U+0100
+//! use an index built by `parse`; the rest search the table.
+
+use crate::bytes::ByteView;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+/// 512 glyphs instead of 256
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_MODESEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQ: u8 = 0xFE;
+
+/// Largest glyphs drawn, in pixels
+pub const MAX_WIDTH: usize = 32;
+pub const MAX_HEIGHT: usize = 64;
+
+/// No glyph in `Psf::latin1`
+const NO_GLYPH: u16 = u16::MAX;
+
+/// Where a font's Unicode table is, and how it is encoded
+#[derive(Clone, Copy)]
+enum Table<'a> {
+    /// None: glyph N shows character N
+    Identity,
+    /// UCS-2, little-endian
+    Psf1(&'a [u8]),
+    /// UTF-8
+    Psf2(&'a [u8]),
+}
+
+pub struct Psf<'a> {
+    glyphs: &'a [u8],
+    count: usize,
+    width: usize,
+    height: usize,
+    /// Bytes per glyph
+    size: usize,
+    table: Table<'a>,
+    /// Glyph for each character up to U+00FF
+    latin1: [u16; 256],
+}
+
+impl<'a> Psf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        let view = ByteView::new(data);
+        let truncated = "Font is truncated";
+        let psf1 = view.array(0) == Some(PSF1_MAGIC);
+        // Where the glyphs start, how many there are, their size, and
+        // whether a table follows them
+        let (start, count, (width, height), size, has_table) = if psf1 {
+            let mode = view.u8(2).ok_or(truncated)?;
+            let height = usize::from(view.u8(3).ok_or(truncated)?);
+            let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+            (PSF1_HEADER_SIZE, count, (8, height), height, mode & (PSF1_MODEHASTAB | PSF1_MODESEQ) != 0)
+        } else if view.array(0) == Some(PSF2_MAGIC) {
+            let field = |offset| view.u32_le(offset).map(|value| value as usize).ok_or(truncated);
+            let has_table = field(12)? as u32 & PSF2_HAS_UNICODE_TABLE != 0;
+            (field(8)?.max(PSF2_HEADER_SIZE), field(16)?, (field(28)?, field(24)?), field(20)?, has_table)
+        } else {
+            return Err("Not a PSF font");
+        };
+        if width == 0 || height == 0 || count == 0 {
+            return Err("Font has no glyphs");
+        }
+        if width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err("Glyphs larger than 32x64 are not supported");
+        }
+        if size != height * width.div_ceil(8) {
+            return Err("Glyph size does not match the glyph dimensions");
+        }
+        if count >= usize::from(NO_GLYPH) {
+            return Err("Font has too many glyphs");
+        }
+        let glyphs = view.bytes(start, count * size).ok_or(truncated)?;
+        let rest = &data[start + glyphs.len()..];
+        let table = match (has_table, psf1) {
+            (false, _) => Table::Identity,
+            (true, true) => Table::Psf1(rest),
+            (true, false) => Table::Psf2(rest),
+        };
+
+        let mut font = Psf { glyphs, count, width, height, size, table, latin1: [NO_GLYPH; 256] };
+        // The first glyph listed for a character wins
+        let mut latin1 = [NO_GLYPH; 256];
+        font.walk(|index, c| {
+            if let Some(slot) = latin1.get_mut(c as usize) {
+                if *slot == NO_GLYPH {
+                    *slot = index as u16;
+                }
+            }
+            false
+        });
+        font.latin1 = latin1;
+        Ok(font)
+    }
+
+    /// Glyph size in pixels (width, height)
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.count
+    }
+
+    /// Bytes in each row of a glyph
+    pub fn row_bytes(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    /// Bitmap of the glyph that shows `c`, `height` rows of `row_bytes`
+    pub fn glyph(&self, c: char) -> Option<&'a [u8]> {
+        let index = match self.latin1.get(c as usize) {
+            Some(&NO_GLYPH) => return None,
+            Some(&index) => usize::from(index),
+            None => {
+                let mut found = None;
+                self.walk(|index, mapped| {
+                    found = (mapped == c).then_some(index);
+                    found.is_some()
+                });
+                found?
+            }
+        };
+        self.glyphs.get(index * self.size..(index + 1) * self.size)
+    }
+
+    /// Call `f` with each glyph index and a character it shows, until it
+    /// returns true
+    fn walk(&self, mut f: impl FnMut(usize, char) -> bool) {
+        match self.table {
+            Table::Identity => {
+                for (index, c) in (0..self.count).filter_map(|index| Some((index, char::from_u32(index as u32)?))) {
+                    if f(index, c) {
+                        return;
+                    }
+                }
+            }
+            Table::Psf1(table) => {
+                let mut units = table.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+                for index in 0..self.count {
+                    let mut in_sequence = false;
+                    for unit in units.by_ref() {
+                        match unit {
+                            PSF1_SEPARATOR => break,
+                            PSF1_START_SEQ => in_sequence = true,
+                            _ if in_sequence => {}
+                            unit => {
+                                if char::from_u32(unit.into()).is_some_and(|c| f(index, c)) {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Table::Psf2(table) => {
+                for (index, entry) in (0..self.count).zip(table.split(|&byte| byte == PSF2_SEPARATOR)) {
+                    // Single characters come before the first sequence
+                    let singles = entry.split(|&byte| byte == PSF2_START_SEQ).next().unwrap_or_default();
+                    for c in core::str::from_utf8(singles).unwrap_or_default().chars() {
+                        if f(index, c) {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// A PSF2 font of `count` 10x3 glyphs, glyph N filled with byte N,
+    /// followed by `table`
+    fn psf2(count: u32, table: &[u8]) -> Vec<u8> {
+        let mut font = PSF2_MAGIC.to_vec();
+        let flags = if table.is_empty() { 0 } else { PSF2_HAS_UNICODE_TABLE };
+        for field in [0, PSF2_HEADER_SIZE as u32, flags, count, 6, 3, 10] {
+            font.extend_from_slice(&field.to_le_bytes());
+        }
+        for index in 0..count {
+            font.extend_from_slice(&[index as u8; 6]);
+        }
+        font.extend_from_slice(table);
+        font
+    }
+
+    #[test]
+    fn test_psf2() {
+        // Glyph 0: 'A' and 'Á' then a sequence; glyph 1: '€'; glyph 2: none
+        let table = ["AÁ".as_bytes(), &[PSF2_START_SEQ], "A\u{301}".as_bytes(), &[PSF2_SEPARATOR]].concat();
+        let data = psf2(3, &[&table[..], "€".as_bytes(), &[PSF2_SEPARATOR; 2]].concat());
+        let font = Psf::parse(&data).unwrap();
+        assert_eq!((font.size(), font.glyph_count(), font.row_bytes()), ((10, 3), 3, 2));
+        assert_eq!(font.glyph('A'), Some(&[0u8; 6][..]));
+        assert_eq!(font.glyph('Á'), Some(&[0u8; 6][..]));
+        assert_eq!(font.glyph('€'), Some(&[1u8; 6][..]));
+        assert_eq!(font.glyph('\u{301}'), None);
+        assert_eq!(font.glyph('B'), None);
+
+        // Without a table glyph N is character N
+        let data = psf2(3, &[]);
+        let font = Psf::parse(&data).unwrap();
+        assert_eq!(font.glyph('\u{2}'), Some(&[2u8; 6][..]));
+        assert_eq!(font.glyph('\u{3}'), None);
+    }
+
+    #[test]
+    fn test_psf1() {
+        let mut data = std::vec![PSF1_MAGIC[0], PSF1_MAGIC[1], 0, 16];
+        for index in 0..=255u8 {
+            data.extend_from_slice(&[index; 16]);
+        }
+        assert_eq!(Psf::parse(&data).unwrap().glyph('a'), Some(&[b'a'; 16][..]));
+        // Glyph 1 shows '€' and a sequence; the rest show nothing
+        data[2] |= PSF1_MODEHASTAB;
+        for unit in [PSF1_SEPARATOR, 0x20AC, PSF1_START_SEQ, 0x41, 0x301, PSF1_SEPARATOR] {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        let font = Psf::parse(&data).unwrap();
+        assert_eq!(font.glyph('€'), Some(&[1u8; 16][..]));
+        assert_eq!(font.glyph('a'), None);
+        assert_eq!(font.glyph('A'), None);
+    }
+
+    #[test]
+    fn test_rejects_bad_fonts() {
+        assert_eq!(Psf::parse(b"hello").err(), Some("Not a PSF font"));
+        let data = psf2(3, &[]);
+        assert_eq!(Psf::parse(&data[..40]).err(), Some("Font is truncated"));
+        let mut data = psf2(1, &[]);
+        data[20] = 5;
+        assert_eq!(Psf::parse(&data).err(), Some("Glyph size does not match the glyph dimensions"));
+        data[28] = 33;
+        assert_eq!(Psf::parse(&data).err(), Some("Glyphs larger than 32x64 are not supported"));
+    }
+}
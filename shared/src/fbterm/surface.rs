@@ -0,0 +1,74 @@
+//! Pixel targets for the terminal renderer
+
+/// Somewhere to draw 0x00RRGGBB pixels
+pub trait Surface {
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    /// Set one pixel; out-of-range coordinates are ignored
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32);
+
+    /// Move everything up by `lines` pixel rows and fill the rows uncovered
+    /// at the bottom with `color`
+    fn scroll_up(&mut self, lines: usize, color: u32);
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let x_end = (x + width).min(self.width());
+        let y_end = (y + height).min(self.height());
+        for py in y..y_end {
+            for px in x..x_end {
+                self.set_pixel(px, py, color);
+            }
+        }
+    }
+}
+
+/// A surface in ordinary memory, one u32 per pixel in row-major order
+/// Used to render on the host (tests) or off-screen.
+pub struct MemSurface<'a> {
+    pixels: &'a mut [u32],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> MemSurface<'a> {
+    /// Wrap `pixels`, which must hold exactly `width * height` entries
+    pub fn new(pixels: &'a mut [u32], width: usize, height: usize) -> Option<Self> {
+        if pixels.len() != width.checked_mul(height)? {
+            return None;
+        }
+        Some(MemSurface { pixels, width, height })
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        self.pixels
+    }
+}
+
+impl Surface for MemSurface<'_> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    fn scroll_up(&mut self, lines: usize, color: u32) {
+        let shift = (lines * self.width).min(self.pixels.len());
+        self.pixels.copy_within(shift.., 0);
+        let len = self.pixels.len();
+        self.pixels[len - shift..].fill(color);
+    }
+}
@@ -0,0 +1,49 @@
+// 8x16 glyphs beyond ASCII: Latin-1 symbols and common punctuation
+// Same layout as font8x16.rs; accented letters are composed in font.rs
+[
+    ('\u{a0}', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¡', [0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x1E, 0x1E, 0x1E, 0x0C, 0x00, 0x00, 0x00, 0x00]),
+    ('¢', [0x00, 0x00, 0x00, 0x0C, 0x3E, 0x63, 0x60, 0x60, 0x63, 0x3E, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('£', [0x00, 0x00, 0x1C, 0x36, 0x32, 0x30, 0x7C, 0x30, 0x30, 0x30, 0x73, 0x7E, 0x00, 0x00, 0x00, 0x00]),
+    ('¤', [0x00, 0x00, 0x00, 0x00, 0x63, 0x3E, 0x36, 0x36, 0x3E, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¥', [0x00, 0x00, 0x63, 0x63, 0x36, 0x1C, 0x7F, 0x0C, 0x7F, 0x0C, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x00]),
+    ('¦', [0x00, 0x00, 0x0C, 0x0C, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x00]),
+    ('§', [0x00, 0x3C, 0x66, 0x60, 0x38, 0x6C, 0x66, 0x36, 0x1C, 0x06, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00]),
+    ('¨', [0x00, 0x00, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('©', [0x00, 0x00, 0x3C, 0x42, 0x99, 0xA5, 0xA1, 0xA5, 0x99, 0x42, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('ª', [0x00, 0x00, 0x3C, 0x06, 0x3E, 0x66, 0x3B, 0x00, 0x7F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('«', [0x00, 0x00, 0x00, 0x00, 0x1B, 0x36, 0x6C, 0xD8, 0x6C, 0x36, 0x1B, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¬', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7F, 0x03, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('®', [0x00, 0x00, 0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA9, 0xA5, 0x42, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¯', [0x00, 0x7F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('°', [0x00, 0x00, 0x1C, 0x36, 0x36, 0x1C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('±', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x7E, 0x0C, 0x0C, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('²', [0x00, 0x38, 0x6C, 0x0C, 0x18, 0x30, 0x7C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('³', [0x00, 0x38, 0x6C, 0x18, 0x0C, 0x6C, 0x38, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('´', [0x00, 0x00, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('µ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x66, 0x7C, 0x60, 0x60, 0xC0, 0x00, 0x00]),
+    ('¶', [0x00, 0x00, 0x7F, 0xDB, 0xDB, 0xDB, 0x7B, 0x1B, 0x1B, 0x1B, 0x1B, 0x1B, 0x00, 0x00, 0x00, 0x00]),
+    ('·', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¸', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x38, 0x00, 0x00]),
+    ('¹', [0x00, 0x18, 0x38, 0x18, 0x18, 0x18, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('º', [0x00, 0x00, 0x3C, 0x66, 0x66, 0x66, 0x3C, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('»', [0x00, 0x00, 0x00, 0x00, 0xD8, 0x6C, 0x36, 0x1B, 0x36, 0x6C, 0xD8, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('¼', [0x00, 0x60, 0xE0, 0x62, 0x66, 0x06, 0x0C, 0x18, 0x32, 0x66, 0xCA, 0x1F, 0x02, 0x00, 0x00, 0x00]),
+    ('½', [0x00, 0x60, 0xE0, 0x62, 0x66, 0x06, 0x0C, 0x18, 0x36, 0x69, 0xC1, 0x02, 0x07, 0x00, 0x00, 0x00]),
+    ('¾', [0x00, 0xE0, 0x30, 0x62, 0x36, 0xE6, 0x0C, 0x18, 0x32, 0x66, 0xCA, 0x1F, 0x02, 0x00, 0x00, 0x00]),
+    ('¿', [0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x18, 0x30, 0x63, 0x63, 0x3E, 0x00, 0x00, 0x00]),
+    ('Æ', [0x00, 0x00, 0x3F, 0x6C, 0xCC, 0xCC, 0xFE, 0xCC, 0xCC, 0xCC, 0xCC, 0xCF, 0x00, 0x00, 0x00, 0x00]),
+    ('×', [0x00, 0x00, 0x00, 0x00, 0x00, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('ß', [0x00, 0x00, 0x3C, 0x66, 0x66, 0x6C, 0x6E, 0x63, 0x63, 0x63, 0x63, 0x6E, 0x00, 0x00, 0x00, 0x00]),
+    ('æ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x76, 0x0D, 0x3F, 0x6C, 0x6C, 0x6D, 0x36, 0x00, 0x00, 0x00, 0x00]),
+    ('÷', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00, 0x7E, 0x00, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('–', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('—', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('‘', [0x00, 0x00, 0x0C, 0x18, 0x1C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('’', [0x00, 0x00, 0x1C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('“', [0x00, 0x00, 0x36, 0x6C, 0x77, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('”', [0x00, 0x00, 0x77, 0x36, 0x6C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('•', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x3C, 0x3C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('…', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xDB, 0xDB, 0x00, 0x00, 0x00, 0x00]),
+    ('€', [0x00, 0x00, 0x1E, 0x33, 0x60, 0xF8, 0x60, 0xF8, 0x60, 0x60, 0x33, 0x1E, 0x00, 0x00, 0x00, 0x00]),
+]
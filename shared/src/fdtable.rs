@@ -0,0 +1,156 @@
+//! File descriptor tables
+//! A program names what it has open by small integers. `insert` hands out
+//! the lowest free one, as POSIX requires, so closing descriptor 1 and
+//! opening a file makes that file standard output; `dup2` makes a second
+//! descriptor name the same thing. The table only keeps the `T`s: what they
+//! are (open files, the console) is up to the kernel.
+
+/// Descriptors one program can have open
+pub const MAX_FDS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdError {
+    /// No such descriptor is open
+    BadFd,
+    /// Every descriptor is in use
+    TableFull,
+}
+
+impl FdError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FdError::BadFd => "Bad file descriptor",
+            FdError::TableFull => "Too many open files",
+        }
+    }
+}
+
+/// Where `lseek` measures an offset from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whence {
+    Start,
+    Current,
+    End,
+}
+
+#[derive(Clone)]
+pub struct FdTable<T> {
+    slots: [Option<T>; MAX_FDS],
+}
+
+impl<T> FdTable<T> {
+    pub const fn new() -> Self {
+        FdTable { slots: [const { None }; MAX_FDS] }
+    }
+
+    /// Give `item` the lowest free descriptor
+    pub fn insert(&mut self, item: T) -> Result<u64, FdError> {
+        let fd = self.slots.iter().position(Option::is_none).ok_or(FdError::TableFull)?;
+        self.slots[fd] = Some(item);
+        Ok(fd as u64)
+    }
+
+    pub fn get(&self, fd: u64) -> Result<&T, FdError> {
+        self.slot(fd)?.as_ref().ok_or(FdError::BadFd)
+    }
+
+    /// Close `fd`, returning what it named
+    pub fn remove(&mut self, fd: u64) -> Result<T, FdError> {
+        self.slot_mut(fd)?.take().ok_or(FdError::BadFd)
+    }
+
+    /// Descriptors open, lowest first
+    pub fn fds(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..).zip(&self.slots).filter(|(_, slot)| slot.is_some()).map(|(fd, _)| fd)
+    }
+
+    fn slot(&self, fd: u64) -> Result<&Option<T>, FdError> {
+        usize::try_from(fd).ok().and_then(|fd| self.slots.get(fd)).ok_or(FdError::BadFd)
+    }
+
+    fn slot_mut(&mut self, fd: u64) -> Result<&mut Option<T>, FdError> {
+        usize::try_from(fd).ok().and_then(|fd| self.slots.get_mut(fd)).ok_or(FdError::BadFd)
+    }
+}
+
+impl<T: Clone> FdTable<T> {
+    /// Make `new` name what `old` does, returning what `new` named before
+    /// (for the caller to close); nothing changes if they are the same
+    pub fn dup2(&mut self, old: u64, new: u64) -> Result<Option<T>, FdError> {
+        let item = self.get(old)?.clone();
+        self.slot(new)?;
+        if old == new {
+            return Ok(None);
+        }
+        Ok(self.slot_mut(new)?.replace(item))
+    }
+}
+
+impl<T> Default for FdTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The cursor `lseek` moves to, `offset` from `whence` in a file of `size`
+/// bytes with its cursor at `cursor`; None if that is before the start
+/// A cursor past the end is allowed: reads there return nothing, and a
+/// write fills the gap.
+pub fn seek_target(cursor: usize, size: usize, offset: i64, whence: Whence) -> Option<usize> {
+    let base = match whence {
+        Whence::Start => 0,
+        Whence::Current => cursor,
+        Whence::End => size,
+    };
+    usize::try_from(i128::try_from(base).ok()? + i128::from(offset)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lowest_free_descriptor() {
+        let mut table = FdTable::new();
+        assert_eq!((table.insert('a'), table.insert('b'), table.insert('c')), (Ok(0), Ok(1), Ok(2)));
+        assert_eq!(table.remove(1), Ok('b'));
+        assert_eq!(table.remove(1), Err(FdError::BadFd));
+        assert_eq!(table.insert('d'), Ok(1));
+        assert!(table.fds().eq([0, 1, 2]));
+
+        for fd in 3..MAX_FDS as u64 {
+            assert_eq!(table.insert('x'), Ok(fd));
+        }
+        assert_eq!(table.insert('y'), Err(FdError::TableFull));
+        assert_eq!(table.get(MAX_FDS as u64), Err(FdError::BadFd));
+        assert_eq!(table.get(u64::MAX), Err(FdError::BadFd));
+    }
+
+    #[test]
+    fn test_dup2() {
+        let mut table = FdTable::new();
+        table.insert("console").unwrap();
+        table.insert("console").unwrap();
+        let file = table.insert("file").unwrap();
+
+        // Replacing an open descriptor hands back what it named
+        assert_eq!(table.dup2(file, 1), Ok(Some("console")));
+        assert_eq!(table.get(1), Ok(&"file"));
+        assert_eq!(table.dup2(file, 7), Ok(None));
+        assert!(table.fds().eq([0, 1, 2, 7]));
+        assert_eq!(table.dup2(file, file), Ok(None));
+
+        assert_eq!(table.dup2(5, 1), Err(FdError::BadFd));
+        assert_eq!(table.dup2(0, MAX_FDS as u64), Err(FdError::BadFd));
+        assert_eq!(table.get(1), Ok(&"file"));
+    }
+
+    #[test]
+    fn test_seek_target() {
+        assert_eq!(seek_target(10, 100, 5, Whence::Start), Some(5));
+        assert_eq!(seek_target(10, 100, -4, Whence::Current), Some(6));
+        assert_eq!(seek_target(10, 100, 20, Whence::End), Some(120));
+        assert_eq!(seek_target(10, 100, -11, Whence::Current), None);
+        assert_eq!(seek_target(0, 0, i64::MIN, Whence::End), None);
+    }
+}
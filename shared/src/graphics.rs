@@ -0,0 +1,187 @@
+//! 2D drawing on a `Surface`
+//! Pixel primitives for drawing apart from the text console (a boot splash,
+//! status bars): rectangles, lines, copying in images, and text at any pixel
+//! position in the console's 8x16 font. Positions are signed so shapes may
+//! hang off an edge; whatever falls outside the surface is clipped.
+
+use crate::fbterm::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::fbterm::Surface;
+
+/// Set one pixel, if it is on the surface
+pub fn put_pixel<S: Surface + ?Sized>(surface: &mut S, x: i32, y: i32, color: u32) {
+    plot(surface, x.into(), y.into(), color);
+}
+
+/// Fill a `width` x `height` rectangle with its top left corner at (x, y)
+pub fn fill_rect<S: Surface + ?Sized>(surface: &mut S, x: i32, y: i32, width: usize, height: usize, color: u32) {
+    let columns = clip(x, width, surface.width());
+    let rows = clip(y, height, surface.height());
+    if let (Some((x, width)), Some((y, height))) = (columns, rows) {
+        surface.fill_rect(x, y, width, height, color);
+    }
+}
+
+/// Draw a one-pixel line from `from` to `to`, both ends included
+pub fn draw_line<S: Surface + ?Sized>(surface: &mut S, from: (i32, i32), to: (i32, i32), color: u32) {
+    // Bresenham: step along both axes, tracking the error against the true line
+    let (mut x, mut y) = (i64::from(from.0), i64::from(from.1));
+    let (x_end, y_end) = (i64::from(to.0), i64::from(to.1));
+    let (dx, dy) = ((x_end - x).abs(), -(y_end - y).abs());
+    let (step_x, step_y) = ((x_end - x).signum(), (y_end - y).signum());
+    let mut error = dx + dy;
+    loop {
+        plot(surface, x, y, color);
+        if x == x_end && y == y_end {
+            break;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// Copy an image `width` pixels wide, its rows one after another in
+/// `pixels`, with its top left corner at (x, y)
+/// A last row shorter than `width` is drawn as far as it goes.
+pub fn blit<S: Surface + ?Sized>(surface: &mut S, x: i32, y: i32, width: usize, pixels: &[u32]) {
+    if width == 0 {
+        return;
+    }
+    for (row, line) in (i64::from(y)..).zip(pixels.chunks(width)) {
+        for (column, &color) in (i64::from(x)..).zip(line) {
+            plot(surface, column, row, color);
+        }
+    }
+}
+
+/// Draw `text` on one line with its top left corner at (x, y), returning the
+/// x just past the last character
+/// Pixels outside the glyphs are set to `background`, or left alone if it is
+/// None. Characters the font lacks are drawn as '?'.
+pub fn draw_text<S: Surface + ?Sized>(
+    surface: &mut S,
+    x: i32,
+    y: i32,
+    text: &str,
+    foreground: u32,
+    background: Option<u32>,
+) -> i32 {
+    let mut left = i64::from(x);
+    for c in text.chars() {
+        let bitmap = glyph(c).or_else(|| glyph('?')).unwrap_or_default();
+        for (row, bits) in (i64::from(y)..).zip(bitmap) {
+            for (column, dx) in (left..).zip(0..GLYPH_WIDTH) {
+                match (bits & (0x80 >> dx) != 0, background) {
+                    (true, _) => plot(surface, column, row, foreground),
+                    (false, Some(background)) => plot(surface, column, row, background),
+                    (false, None) => {}
+                }
+            }
+        }
+        left += GLYPH_WIDTH as i64;
+    }
+    left.clamp(i32::MIN.into(), i32::MAX.into()) as i32
+}
+
+/// Size of `text` in pixels (width, height) as `draw_text` draws it
+pub fn text_size(text: &str) -> (usize, usize) {
+    (text.chars().count() * GLYPH_WIDTH, GLYPH_HEIGHT)
+}
+
+fn plot<S: Surface + ?Sized>(surface: &mut S, x: i64, y: i64, color: u32) {
+    if let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) {
+        surface.set_pixel(x, y, color);
+    }
+}
+
+/// The part of `start..start + len` inside `0..limit`, as (start, len)
+fn clip(start: i32, len: usize, limit: usize) -> Option<(usize, usize)> {
+    let end = (i64::from(start) + len as i64).min(limit as i64);
+    let start = i64::from(start).max(0);
+    (start < end).then(|| (start as usize, (end - start) as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::fbterm::MemSurface;
+    use std::string::String;
+    use std::vec;
+
+    /// Draw on a blank `width` x `height` surface and return it one line per
+    /// pixel row: '.' for 0, '#' for anything else
+    fn draw(width: usize, height: usize, f: impl FnOnce(&mut MemSurface)) -> String {
+        let mut pixels = vec![0u32; width * height];
+        let mut surface = MemSurface::new(&mut pixels, width, height).unwrap();
+        f(&mut surface);
+        let mut text = String::new();
+        for row in surface.pixels().chunks(width) {
+            text.extend(row.iter().map(|&pixel| if pixel == 0 { '.' } else { '#' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn test_fill_rect_clips() {
+        let inside = draw(4, 3, |s| fill_rect(s, 1, 1, 2, 1, 7));
+        assert_eq!(inside, "....\n.##.\n....\n");
+        let hanging = draw(4, 3, |s| fill_rect(s, -2, 2, 4, 5, 7));
+        assert_eq!(hanging, "....\n....\n##..\n");
+        // Entirely off the surface, and empty
+        assert_eq!(draw(2, 2, |s| fill_rect(s, -5, 0, 5, 2, 7)), "..\n..\n");
+        assert_eq!(draw(2, 2, |s| fill_rect(s, 0, 0, 0, 2, 7)), "..\n..\n");
+        assert_eq!(draw(2, 2, |s| fill_rect(s, i32::MAX, 0, usize::MAX, 2, 7)), "..\n..\n");
+    }
+
+    #[test]
+    fn test_lines() {
+        let diagonal = draw(4, 4, |s| draw_line(s, (3, 3), (0, 0), 1));
+        assert_eq!(diagonal, "#...\n.#..\n..#.\n...#\n");
+        let shallow = draw(5, 2, |s| draw_line(s, (0, 0), (4, 1), 1));
+        assert_eq!(shallow, "##...\n..###\n");
+        let single = draw(2, 2, |s| draw_line(s, (1, 0), (1, 0), 1));
+        assert_eq!(single, ".#\n..\n");
+        // Clipped where it leaves the surface
+        let clipped = draw(3, 3, |s| draw_line(s, (-2, 1), (5, 1), 1));
+        assert_eq!(clipped, "...\n###\n...\n");
+    }
+
+    #[test]
+    fn test_blit() {
+        let image = [1, 0, 1, 1, 1, 1, 1];
+        let placed = draw(4, 4, |s| blit(s, 1, 1, 3, &image));
+        assert_eq!(placed, "....\n.#.#\n.###\n.#..\n");
+        let clipped = draw(2, 2, |s| blit(s, -1, -1, 3, &image));
+        assert_eq!(clipped, "##\n..\n");
+        assert_eq!(draw(1, 1, |s| blit(s, 0, 0, 0, &image)), ".\n");
+    }
+
+    #[test]
+    fn test_text() {
+        let (width, height) = text_size("Hi");
+        assert_eq!((width, height), (16, 16));
+        let mut pixels = vec![0u32; (width + 3) * (height + 2)];
+        let mut surface = MemSurface::new(&mut pixels, width + 3, height + 2).unwrap();
+        assert_eq!(draw_text(&mut surface, 3, 2, "Hi", 0xFFFFFF, None), 19);
+
+        // Glyph pixels in the foreground, the rest untouched
+        for (dy, bits) in glyph('H').unwrap().iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let expected = if bits & (0x80 >> dx) != 0 { 0xFFFFFF } else { 0 };
+                assert_eq!(surface.pixel(3 + dx, 2 + dy), expected);
+            }
+        }
+        // A background fills the whole cell
+        draw_text(&mut surface, -4, 0, "\x7f", 0xFFFFFF, Some(0x0000AA));
+        assert!((0..4).all(|x| surface.pixel(x, 0) != 0));
+        assert_eq!(surface.pixel(4, 0), 0);
+    }
+}
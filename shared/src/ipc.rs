@@ -0,0 +1,376 @@
+//! Message ports
+//! A port is a named queue of up to `QUEUE_DEPTH` messages, each up to
+//! `MAX_MESSAGE` bytes, copied in by the sender and out by the receiver in
+//! the order they were sent. A port is created under a name, and anyone who
+//! knows the name can look it up and send to it. Port ids carry a
+//! generation, so an id kept after its port is destroyed never reaches a
+//! later port in the same slot.
+//! This is only the bookkeeping: the kernel (`kernel::ipc`) locks the table
+//! and blocks callers while a queue is full or empty.
+
+use core::fmt;
+
+/// Ports that can exist at once
+pub const MAX_PORTS: usize = 16;
+/// Messages a port holds before senders have to wait
+pub const QUEUE_DEPTH: usize = 8;
+/// Longest message in bytes (`abi::PORT_MESSAGE_MAX`)
+pub const MAX_MESSAGE: usize = 256;
+/// Longest port name in bytes (`abi::PORT_NAME_MAX`)
+pub const MAX_NAME: usize = 32;
+
+/// Port handle: the slot in the low byte, its generation above
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortId(pub u64);
+
+impl PortId {
+    fn new(slot: usize, generation: u32) -> Self {
+        PortId(u64::from(generation) << 8 | slot as u64)
+    }
+
+    fn slot(self) -> usize {
+        (self.0 & 0xFF) as usize
+    }
+
+    fn generation(self) -> u64 {
+        self.0 >> 8
+    }
+}
+
+impl fmt::Display for PortId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Who created a port, and so who may destroy it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Kernel,
+    /// The running user program; its ports go when it exits
+    Process,
+}
+
+impl Owner {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Owner::Kernel => "kernel",
+            Owner::Process => "process",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError {
+    /// No port by that name, or the id's port has been destroyed
+    NotFound,
+    /// Another port has the name
+    Exists,
+    /// Every port slot is in use
+    TableFull,
+    /// The name is empty or longer than `MAX_NAME`
+    BadName,
+    /// The message is longer than `MAX_MESSAGE`
+    TooLarge,
+    /// The buffer is shorter than the next message, which stays queued; the
+    /// message's length
+    BufferTooSmall(usize),
+    /// The queue is full (send) or empty (receive)
+    WouldBlock,
+}
+
+impl PortError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PortError::NotFound => "no such port",
+            PortError::Exists => "port name already in use",
+            PortError::TableFull => "too many ports",
+            PortError::BadName => "bad port name",
+            PortError::TooLarge => "message too large",
+            PortError::BufferTooSmall(_) => "buffer too small for message",
+            PortError::WouldBlock => "port queue full or empty",
+        }
+    }
+}
+
+struct Port {
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    owner: Owner,
+    messages: [[u8; MAX_MESSAGE]; QUEUE_DEPTH],
+    lengths: [usize; QUEUE_DEPTH],
+    /// Queue index of the oldest message
+    head: usize,
+    queued: usize,
+    sent: u64,
+    received: u64,
+}
+
+impl Port {
+    fn name(&self) -> &str {
+        // Only ever copied from a `&str`, at a char boundary
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+}
+
+struct Slot {
+    /// Bumped each time the slot's port is destroyed
+    generation: u32,
+    port: Option<Port>,
+}
+
+/// A port as `PortTable::ports` reports it
+#[derive(Debug, Clone, Copy)]
+pub struct PortInfo {
+    pub id: PortId,
+    name: [u8; MAX_NAME],
+    name_len: usize,
+    pub owner: Owner,
+    /// Messages waiting to be received
+    pub queued: usize,
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl PortInfo {
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+}
+
+pub struct PortTable {
+    slots: [Slot; MAX_PORTS],
+}
+
+impl Default for PortTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortTable {
+    pub const fn new() -> Self {
+        PortTable { slots: [const { Slot { generation: 0, port: None } }; MAX_PORTS] }
+    }
+
+    /// Create an empty port called `name`
+    pub fn create(&mut self, name: &str, owner: Owner) -> Result<PortId, PortError> {
+        if name.is_empty() || name.len() > MAX_NAME {
+            return Err(PortError::BadName);
+        }
+        if self.lookup(name).is_ok() {
+            return Err(PortError::Exists);
+        }
+        let index = self.slots.iter().position(|slot| slot.port.is_none()).ok_or(PortError::TableFull)?;
+        let mut port = Port {
+            name: [0; MAX_NAME],
+            name_len: name.len(),
+            owner,
+            messages: [[0; MAX_MESSAGE]; QUEUE_DEPTH],
+            lengths: [0; QUEUE_DEPTH],
+            head: 0,
+            queued: 0,
+            sent: 0,
+            received: 0,
+        };
+        port.name[..name.len()].copy_from_slice(name.as_bytes());
+        let slot = &mut self.slots[index];
+        slot.port = Some(port);
+        Ok(PortId::new(index, slot.generation))
+    }
+
+    /// The port called `name`
+    pub fn lookup(&self, name: &str) -> Result<PortId, PortError> {
+        self.slots
+            .iter()
+            .enumerate()
+            .find_map(|(index, slot)| match &slot.port {
+                Some(port) if port.name() == name => Some(PortId::new(index, slot.generation)),
+                _ => None,
+            })
+            .ok_or(PortError::NotFound)
+    }
+
+    pub fn owner(&self, id: PortId) -> Result<Owner, PortError> {
+        self.port(id).map(|port| port.owner)
+    }
+
+    /// Destroy a port, dropping any messages still queued
+    pub fn destroy(&mut self, id: PortId) -> Result<(), PortError> {
+        self.port(id)?;
+        let slot = &mut self.slots[id.slot()];
+        slot.port = None;
+        // Wrapping only matters after 2^32 ports in one slot
+        slot.generation = slot.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Destroy every port `owner` created, returning how many there were
+    pub fn destroy_owned_by(&mut self, owner: Owner) -> usize {
+        let ids: [Option<PortId>; MAX_PORTS] = core::array::from_fn(|index| {
+            let slot = &self.slots[index];
+            match &slot.port {
+                Some(port) if port.owner == owner => Some(PortId::new(index, slot.generation)),
+                _ => None,
+            }
+        });
+        ids.into_iter().flatten().filter(|&id| self.destroy(id).is_ok()).count()
+    }
+
+    /// Queue a copy of `message` on the port
+    pub fn send(&mut self, id: PortId, message: &[u8]) -> Result<(), PortError> {
+        if message.len() > MAX_MESSAGE {
+            return Err(PortError::TooLarge);
+        }
+        let port = self.port_mut(id)?;
+        if port.queued == QUEUE_DEPTH {
+            return Err(PortError::WouldBlock);
+        }
+        let tail = (port.head + port.queued) % QUEUE_DEPTH;
+        port.messages[tail][..message.len()].copy_from_slice(message);
+        port.lengths[tail] = message.len();
+        port.queued += 1;
+        port.sent += 1;
+        Ok(())
+    }
+
+    /// Move the oldest message into `buffer`, returning its length
+    pub fn receive(&mut self, id: PortId, buffer: &mut [u8]) -> Result<usize, PortError> {
+        let port = self.port_mut(id)?;
+        if port.queued == 0 {
+            return Err(PortError::WouldBlock);
+        }
+        let len = port.lengths[port.head];
+        if buffer.len() < len {
+            return Err(PortError::BufferTooSmall(len));
+        }
+        buffer[..len].copy_from_slice(&port.messages[port.head][..len]);
+        port.head = (port.head + 1) % QUEUE_DEPTH;
+        port.queued -= 1;
+        port.received += 1;
+        Ok(len)
+    }
+
+    /// Every port, in slot order
+    pub fn ports(&self) -> impl Iterator<Item = PortInfo> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let port = slot.port.as_ref()?;
+            Some(PortInfo {
+                id: PortId::new(index, slot.generation),
+                name: port.name,
+                name_len: port.name_len,
+                owner: port.owner,
+                queued: port.queued,
+                sent: port.sent,
+                received: port.received,
+            })
+        })
+    }
+
+    fn port(&self, id: PortId) -> Result<&Port, PortError> {
+        match self.slots.get(id.slot()) {
+            Some(slot) if u64::from(slot.generation) == id.generation() => slot.port.as_ref().ok_or(PortError::NotFound),
+            _ => Err(PortError::NotFound),
+        }
+    }
+
+    fn port_mut(&mut self, id: PortId) -> Result<&mut Port, PortError> {
+        match self.slots.get_mut(id.slot()) {
+            Some(slot) if u64::from(slot.generation) == id.generation() => slot.port.as_mut().ok_or(PortError::NotFound),
+            _ => Err(PortError::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::boxed::Box;
+
+    fn table() -> Box<PortTable> {
+        Box::new(PortTable::new())
+    }
+
+    #[test]
+    fn test_create_and_lookup() {
+        let mut ports = table();
+        let log = ports.create("log", Owner::Kernel).unwrap();
+        let echo = ports.create("echo", Owner::Process).unwrap();
+        assert_ne!(log, echo);
+        assert_eq!(ports.lookup("echo"), Ok(echo));
+        assert_eq!(ports.lookup("ech"), Err(PortError::NotFound));
+        assert_eq!(ports.create("log", Owner::Process), Err(PortError::Exists));
+        assert_eq!(ports.create("", Owner::Kernel), Err(PortError::BadName));
+        assert_eq!(ports.create(&"x".repeat(MAX_NAME + 1), Owner::Kernel), Err(PortError::BadName));
+
+        assert!(ports.ports().map(|info| (info.id, info.owner)).eq([(log, Owner::Kernel), (echo, Owner::Process)]));
+        assert!(ports.ports().any(|info| info.name() == "echo"));
+    }
+
+    #[test]
+    fn test_messages_in_order() {
+        let mut ports = table();
+        let id = ports.create("queue", Owner::Kernel).unwrap();
+        let mut buffer = [0u8; MAX_MESSAGE];
+        assert_eq!(ports.receive(id, &mut buffer), Err(PortError::WouldBlock));
+
+        for n in 0..QUEUE_DEPTH as u8 {
+            ports.send(id, &[n; 3]).unwrap();
+        }
+        assert_eq!(ports.send(id, b"one too many"), Err(PortError::WouldBlock));
+        assert_eq!(ports.receive(id, &mut buffer), Ok(3));
+        assert_eq!(buffer[..3], [0; 3]);
+
+        // Wraps around the queue; empty and full-size messages both fit
+        ports.send(id, b"").unwrap();
+        for n in 1..QUEUE_DEPTH as u8 {
+            assert_eq!(ports.receive(id, &mut buffer), Ok(3));
+            assert_eq!(buffer[..3], [n; 3]);
+        }
+        assert_eq!(ports.receive(id, &mut buffer), Ok(0));
+        ports.send(id, &[7; MAX_MESSAGE]).unwrap();
+        assert_eq!(ports.send(id, &[7; MAX_MESSAGE + 1]), Err(PortError::TooLarge));
+
+        let info = ports.ports().next().unwrap();
+        assert_eq!((info.queued, info.sent, info.received), (1, QUEUE_DEPTH as u64 + 2, QUEUE_DEPTH as u64 + 1));
+    }
+
+    #[test]
+    fn test_short_buffer_keeps_message() {
+        let mut ports = table();
+        let id = ports.create("queue", Owner::Kernel).unwrap();
+        ports.send(id, b"hello").unwrap();
+        let mut small = [0u8; 4];
+        assert_eq!(ports.receive(id, &mut small), Err(PortError::BufferTooSmall(5)));
+        let mut buffer = [0u8; 8];
+        assert_eq!(ports.receive(id, &mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"hello");
+    }
+
+    #[test]
+    fn test_destroyed_ids_stay_dead() {
+        let mut ports = table();
+        let old = ports.create("service", Owner::Process).unwrap();
+        ports.send(old, b"stale").unwrap();
+        ports.destroy(old).unwrap();
+        assert_eq!(ports.destroy(old), Err(PortError::NotFound));
+
+        // The slot is reused, but the old id doesn't reach the new port
+        let new = ports.create("service", Owner::Process).unwrap();
+        assert_ne!(old, new);
+        assert_eq!(ports.send(old, b"x"), Err(PortError::NotFound));
+        assert_eq!(ports.receive(new, &mut [0u8; 8]), Err(PortError::WouldBlock));
+
+        ports.create("kernel", Owner::Kernel).unwrap();
+        assert_eq!(ports.destroy_owned_by(Owner::Process), 1);
+        assert_eq!(ports.lookup("service"), Err(PortError::NotFound));
+        assert!(ports.lookup("kernel").is_ok());
+
+        // Every slot in use
+        for n in 0..MAX_PORTS - 1 {
+            ports.create(&std::format!("p{}", n), Owner::Kernel).unwrap();
+        }
+        assert_eq!(ports.create("last", Owner::Kernel), Err(PortError::TableFull));
+    }
+}
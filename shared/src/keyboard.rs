@@ -0,0 +1,902 @@
+//! Keyboard input decoding
+//! `Decoder` turns PS/2 scan code set 1 bytes into key events for a
+//! `Keymap` (US to start with), tracking Shift, Ctrl, Alt, and the three locks from the make (press) and
+//! break (release) codes. Set 2, what keyboards send untranslated, is first
+//! turned into set 1 the way the controller's translation would. `TerminalDecoder` does the same for the bytes a serial
+//! terminal sends, including its escape sequences. `ScanKey` goes the other
+//! way, from a key to the scan codes that type it. All of it is independent
+//! of the hardware, so it runs on the host for testing, as is `Typematic`,
+//! the repeat setting the keyboard takes.
+
+use crate::keymap::{self, Keymap, Level};
+
+/// Prefix of the extended (0xE0) scan codes, e.g. arrows and right Ctrl
+const EXTENDED_PREFIX: u8 = 0xE0;
+/// Prefix of Pause, the one key sent with 0xE1; two bytes follow
+const PAUSE_PREFIX: u8 = 0xE1;
+/// Set in break codes
+const RELEASED: u8 = 0x80;
+/// Set 2 sends this before the make code of a released key
+const SET2_BREAK_PREFIX: u8 = 0xF0;
+
+const LEFT_SHIFT: u8 = 0x2A;
+const RIGHT_SHIFT: u8 = 0x36;
+const CTRL: u8 = 0x1D;
+const ALT: u8 = 0x38;
+const CAPS_LOCK: u8 = 0x3A;
+const NUM_LOCK: u8 = 0x45;
+const SCROLL_LOCK: u8 = 0x46;
+/// F1 to F10 are consecutive from here; F11 and F12 come later
+const F1: u8 = 0x3B;
+const F11: u8 = 0x57;
+
+/// Which key an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    /// A character key, with Shift, AltGr and Caps Lock applied
+    Char(char),
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function key F1 to F12
+    F(u8),
+    Shift,
+    Ctrl,
+    Alt,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+}
+
+/// Modifier state when an event happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    /// The locks as the keyboard's Set LEDs command takes them: bit 0
+    /// Scroll Lock, bit 1 Num Lock, bit 2 Caps Lock
+    pub fn leds(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+/// A key going down (or repeating) or coming back up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// State after this event, so pressing Shift reports `shift: true`
+    pub modifiers: Modifiers,
+}
+
+impl KeyEvent {
+    pub const fn press(code: KeyCode, modifiers: Modifiers) -> Self {
+        KeyEvent { code, pressed: true, modifiers }
+    }
+
+    /// The letter of a Ctrl+letter press, in lowercase
+    pub fn ctrl_letter(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if self.pressed && self.modifiers.ctrl && c.is_ascii_alphabetic() => {
+                Some(c.to_ascii_lowercase())
+            }
+            _ => None,
+        }
+    }
+
+    /// The byte a terminal would send for this press
+    /// Ctrl+letter becomes its control code; releases, Alt combinations,
+    /// navigation keys, and non-ASCII characters have none.
+    pub fn to_ascii(&self) -> Option<u8> {
+        if !self.pressed || self.modifiers.alt {
+            return None;
+        }
+        if let Some(letter) = self.ctrl_letter() {
+            return Some(letter as u8 - b'a' + 1);
+        }
+        match self.code {
+            KeyCode::Char(c) if c.is_ascii() && !self.modifiers.ctrl => Some(c as u8),
+            KeyCode::Enter => Some(b'\n'),
+            KeyCode::Backspace => Some(0x08),
+            KeyCode::Tab => Some(b'\t'),
+            KeyCode::Escape => Some(0x1B),
+            _ => None,
+        }
+    }
+}
+
+/// Scan code set 1 decoder
+#[derive(Debug)]
+pub struct Decoder {
+    keymap: &'static Keymap,
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    /// A lock key is down; typematic repeats must not toggle it again
+    caps_held: bool,
+    num_held: bool,
+    scroll_held: bool,
+    /// The previous byte was `EXTENDED_PREFIX`
+    extended: bool,
+    /// Bytes of a Pause sequence still to skip
+    pause_left: u8,
+    /// The previous set 2 byte was `SET2_BREAK_PREFIX`
+    set2_break: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            keymap: &keymap::US,
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            left_alt: false,
+            right_alt: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+            caps_held: false,
+            num_held: false,
+            scroll_held: false,
+            extended: false,
+            pause_left: 0,
+            set2_break: false,
+        }
+    }
+
+    pub fn keymap(&self) -> &'static Keymap {
+        self.keymap
+    }
+
+    /// Decode character keys with `keymap` from now on
+    pub fn set_keymap(&mut self, keymap: &'static Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Right Alt is held as AltGr
+    fn altgr(&self) -> bool {
+        self.right_alt && self.keymap.has_altgr()
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.left_ctrl || self.right_ctrl,
+            alt: self.left_alt || (self.right_alt && !self.altgr()),
+            caps_lock: self.caps_lock,
+            num_lock: self.num_lock,
+            scroll_lock: self.scroll_lock,
+        }
+    }
+
+    /// Feed one byte from the controller
+    /// Returns the event it completes, if any; prefixes and unmapped keys
+    /// produce none.
+    pub fn feed(&mut self, scan_code: u8) -> Option<KeyEvent> {
+        // Pause looks like Ctrl and Num Lock behind its prefix; it has no
+        // break code and is not reported
+        if self.pause_left > 0 {
+            self.pause_left -= 1;
+            return None;
+        }
+        if scan_code == PAUSE_PREFIX {
+            self.pause_left = 2;
+            return None;
+        }
+        if scan_code == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+        let extended = core::mem::replace(&mut self.extended, false);
+        let pressed = scan_code & RELEASED == 0;
+        let key = scan_code & !RELEASED;
+
+        let code = match (extended, key) {
+            (false, LEFT_SHIFT) => {
+                self.left_shift = pressed;
+                KeyCode::Shift
+            }
+            (false, RIGHT_SHIFT) => {
+                self.right_shift = pressed;
+                KeyCode::Shift
+            }
+            (false, CTRL) => {
+                self.left_ctrl = pressed;
+                KeyCode::Ctrl
+            }
+            (true, CTRL) => {
+                self.right_ctrl = pressed;
+                KeyCode::Ctrl
+            }
+            (false, ALT) => {
+                self.left_alt = pressed;
+                KeyCode::Alt
+            }
+            (true, ALT) => {
+                self.right_alt = pressed;
+                KeyCode::Alt
+            }
+            (false, CAPS_LOCK) => {
+                toggle(&mut self.caps_lock, &mut self.caps_held, pressed);
+                KeyCode::CapsLock
+            }
+            (false, NUM_LOCK) => {
+                toggle(&mut self.num_lock, &mut self.num_held, pressed);
+                KeyCode::NumLock
+            }
+            (false, SCROLL_LOCK) => {
+                toggle(&mut self.scroll_lock, &mut self.scroll_held, pressed);
+                KeyCode::ScrollLock
+            }
+            (true, _) => extended_key(key)?,
+            (false, _) => self.main_key(key)?,
+        };
+        Some(KeyEvent { code, pressed, modifiers: self.modifiers() })
+    }
+
+    /// Feed one scan code set 2 byte from a keyboard the controller does
+    /// not translate
+    pub fn feed_set2(&mut self, scan_code: u8) -> Option<KeyEvent> {
+        match scan_code {
+            SET2_BREAK_PREFIX => {
+                self.set2_break = true;
+                None
+            }
+            EXTENDED_PREFIX | PAUSE_PREFIX => self.feed(scan_code),
+            _ => {
+                let released = core::mem::replace(&mut self.set2_break, false);
+                // Keys set 1 has no code for, and replies like the 0xAA
+                // self-test pass, are dropped along with a pending prefix
+                let Some(key) = set2_to_set1(scan_code) else {
+                    self.extended = false;
+                    return None;
+                };
+                self.feed(if released { key | RELEASED } else { key })
+            }
+        }
+    }
+
+    fn main_key(&self, key: u8) -> Option<KeyCode> {
+        let code = match key {
+            0x01 => KeyCode::Escape,
+            0x0E => KeyCode::Backspace,
+            0x0F => KeyCode::Tab,
+            0x1C => KeyCode::Enter,
+            F1..=0x44 => KeyCode::F(key - F1 + 1),
+            F11 | 0x58 => KeyCode::F(key - F11 + 11),
+            _ => {
+                let base = self.keymap.char_at(key, Level::Plain)?;
+                // A key with nothing on AltGr types what it would without
+                if let Some(c) = self.keymap.char_at(key, Level::AltGr).filter(|_| self.altgr()) {
+                    return Some(KeyCode::Char(c));
+                }
+                let modifiers = self.modifiers();
+                // Caps Lock only affects letters, and Shift reverses it
+                let upper = if base.is_alphabetic() {
+                    modifiers.shift != modifiers.caps_lock
+                } else {
+                    modifiers.shift
+                };
+                let level = if upper { Level::Shift } else { Level::Plain };
+                KeyCode::Char(self.keymap.char_at(key, level)?)
+            }
+        };
+        Some(code)
+    }
+}
+
+/// Set 1 make code of a set 2 one, as the controller translates it
+/// Extended keys share the table: after 0xE0 the same byte means the same
+/// thing in both sets.
+pub fn set2_to_set1(code: u8) -> Option<u8> {
+    const TABLE: [u8; 0x84] = [
+        0x00, 0x43, 0x00, 0x3F, 0x3D, 0x3B, 0x3C, 0x58, 0x00, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29, 0x00, // 0x00
+        0x00, 0x38, 0x2A, 0x00, 0x1D, 0x10, 0x02, 0x00, 0x00, 0x00, 0x2C, 0x1F, 0x1E, 0x11, 0x03, 0x5B, // 0x10
+        0x00, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x5C, 0x00, 0x39, 0x2F, 0x21, 0x14, 0x13, 0x06, 0x5D, // 0x20
+        0x00, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x00, 0x00, 0x00, 0x32, 0x24, 0x16, 0x08, 0x09, 0x00, // 0x30
+        0x00, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x00, 0x00, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0C, 0x00, // 0x40
+        0x00, 0x00, 0x28, 0x00, 0x1A, 0x0D, 0x00, 0x00, 0x3A, 0x36, 0x1C, 0x1B, 0x00, 0x2B, 0x00, 0x00, // 0x50
+        0x00, 0x56, 0x00, 0x00, 0x00, 0x00, 0x0E, 0x00, 0x00, 0x4F, 0x00, 0x4B, 0x47, 0x00, 0x00, 0x00, // 0x60
+        0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45, 0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x00, // 0x70
+        0x00, 0x00, 0x00, 0x41, // 0x80
+    ];
+    TABLE.get(code as usize).copied().filter(|&key| key != 0)
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Flip a lock on a fresh press, not on the repeats while it is held
+fn toggle(lock: &mut bool, held: &mut bool, pressed: bool) {
+    if pressed && !*held {
+        *lock = !*lock;
+    }
+    *held = pressed;
+}
+
+/// Keys sent with the 0xE0 prefix
+/// Returns None for the rest, including the fake Shift presses some
+/// keyboards wrap around navigation keys.
+fn extended_key(key: u8) -> Option<KeyCode> {
+    let code = match key {
+        0x1C => KeyCode::Enter, // Keypad Enter
+        0x35 => KeyCode::Char('/'), // Keypad '/'
+        0x47 => KeyCode::Home,
+        0x48 => KeyCode::Up,
+        0x49 => KeyCode::PageUp,
+        0x4B => KeyCode::Left,
+        0x4D => KeyCode::Right,
+        0x4F => KeyCode::End,
+        0x50 => KeyCode::Down,
+        0x51 => KeyCode::PageDown,
+        0x52 => KeyCode::Insert,
+        0x53 => KeyCode::Delete,
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// How a key is typed in scan code set 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanKey {
+    /// Make code; the break code has `RELEASED` set
+    pub make: u8,
+    /// Sent after the 0xE0 prefix
+    pub extended: bool,
+    /// Shift or AltGr to hold for the character
+    pub level: Level,
+}
+
+impl ScanKey {
+    /// AltGr, the right Alt key
+    pub const ALTGR: ScanKey = ScanKey { make: ALT, extended: true, level: Level::Plain };
+
+    /// The key that `Decoder` reports as `code`; characters are looked up
+    /// in `keymap`, so only ones it has can be typed
+    pub fn for_code(code: KeyCode, keymap: &Keymap) -> Option<ScanKey> {
+        let key = |make, extended| Some(ScanKey { make, extended, level: Level::Plain });
+        match code {
+            KeyCode::Char(c) => keymap.find(c).map(|(make, level)| ScanKey { make, extended: false, level }),
+            KeyCode::Enter => key(0x1C, false),
+            KeyCode::Backspace => key(0x0E, false),
+            KeyCode::Tab => key(0x0F, false),
+            KeyCode::Escape => key(0x01, false),
+            KeyCode::Up => key(0x48, true),
+            KeyCode::Down => key(0x50, true),
+            KeyCode::Left => key(0x4B, true),
+            KeyCode::Right => key(0x4D, true),
+            KeyCode::Home => key(0x47, true),
+            KeyCode::End => key(0x4F, true),
+            KeyCode::Insert => key(0x52, true),
+            KeyCode::Delete => key(0x53, true),
+            KeyCode::PageUp => key(0x49, true),
+            KeyCode::PageDown => key(0x51, true),
+            KeyCode::F(n @ 1..=10) => key(F1 + n - 1, false),
+            KeyCode::F(n @ 11..=12) => key(F11 + n - 11, false),
+            KeyCode::F(_) => None,
+            KeyCode::Shift => key(LEFT_SHIFT, false),
+            KeyCode::Ctrl => key(CTRL, false),
+            KeyCode::Alt => key(ALT, false),
+            KeyCode::CapsLock => key(CAPS_LOCK, false),
+            KeyCode::NumLock => key(NUM_LOCK, false),
+            KeyCode::ScrollLock => key(SCROLL_LOCK, false),
+        }
+    }
+
+    /// Pass the bytes that press (or release) the key to `emit`; Shift and
+    /// AltGr are left to the caller
+    pub fn emit(&self, pressed: bool, emit: &mut dyn FnMut(u8)) {
+        if self.extended {
+            emit(EXTENDED_PREFIX);
+        }
+        emit(if pressed { self.make } else { self.make | RELEASED });
+    }
+}
+
+/// Key repeat setting, as the keyboard's Set Typematic command takes it
+/// Bits 5-6 are the delay before a held key repeats, (n + 1) * 250 ms;
+/// bits 0-4 the rate it then repeats at, from 30 (0) down to 2 (31) keys
+/// per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic(pub u8);
+
+impl Typematic {
+    /// What keyboards start with: 500 ms, then 10.9 keys per second
+    pub const DEFAULT: Typematic = Typematic(0x2B);
+
+    /// The setting closest to `rate` keys per second after `delay_ms`
+    pub fn new(delay_ms: u32, rate: u32) -> Result<Self, &'static str> {
+        if !matches!(delay_ms, 250 | 500 | 750 | 1000) {
+            return Err("Delay must be 250, 500, 750 or 1000 ms");
+        }
+        if !(2..=30).contains(&rate) {
+            return Err("Rate must be 2 to 30 keys per second");
+        }
+        let code = (0..32).min_by_key(|&code| Typematic(code).rate_millihz().abs_diff(rate * 1000)).unwrap_or(0);
+        Ok(Typematic(((delay_ms / 250 - 1) as u8) << 5 | code))
+    }
+
+    pub fn delay_ms(&self) -> u32 {
+        ((self.0 >> 5 & 3) as u32 + 1) * 250
+    }
+
+    /// Keys per second, in thousandths
+    pub fn rate_millihz(&self) -> u32 {
+        let (a, b) = ((self.0 & 7) as u32, (self.0 >> 3 & 3) as u32);
+        // The period is (8 + A) * 2^B * 4.17 ms
+        1_000_000_000 / ((8 + a) << b) / 4170
+    }
+}
+
+impl core::fmt::Display for Typematic {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let tenths = (self.rate_millihz() + 50) / 100;
+        write!(f, "{} ms delay, {}.{} keys/s", self.delay_ms(), tenths / 10, tenths % 10)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum EscapeState {
+    #[default]
+    None,
+    /// After ESC
+    Start,
+    /// After ESC O; one final byte follows
+    Ss3,
+    /// After ESC [; parameters, then a final byte in 0x40..=0x7E
+    Csi,
+}
+
+/// Decoder for the bytes a VT100-style terminal sends
+/// Terminals report presses only, so every event has `pressed` set. ESC is
+/// indistinguishable from the start of an escape sequence without a
+/// timeout, so the Escape key itself is never reported.
+#[derive(Debug, Default)]
+pub struct TerminalDecoder {
+    state: EscapeState,
+    /// First numeric parameter of the current CSI sequence
+    param: u8,
+    /// A ';' has been seen; later parameters (modifiers) are ignored
+    param_done: bool,
+    /// The previous byte was CR, so a following LF belongs to the same Enter
+    after_cr: bool,
+}
+
+impl TerminalDecoder {
+    pub const fn new() -> Self {
+        TerminalDecoder { state: EscapeState::None, param: 0, param_done: false, after_cr: false }
+    }
+
+    /// Feed one received byte, returning the key it completes
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        let plain = Modifiers::default();
+        match self.state {
+            EscapeState::Start => {
+                self.state = match byte {
+                    b'[' => EscapeState::Csi,
+                    b'O' => EscapeState::Ss3,
+                    _ => EscapeState::None,
+                };
+                self.param = 0;
+                self.param_done = false;
+                // ESC before a character is how terminals send Alt+key
+                if self.state == EscapeState::None && byte.is_ascii_graphic() {
+                    let alt = Modifiers { alt: true, ..plain };
+                    return Some(KeyEvent::press(KeyCode::Char(byte as char), alt));
+                }
+                return None;
+            }
+            EscapeState::Ss3 => {
+                self.state = EscapeState::None;
+                return csi_final(byte, 0).map(|code| KeyEvent::press(code, plain));
+            }
+            EscapeState::Csi => {
+                match byte {
+                    b'0'..=b'9' if !self.param_done => {
+                        self.param = self.param.saturating_mul(10).saturating_add(byte - b'0');
+                    }
+                    b';' => self.param_done = true,
+                    0x40..=0x7E => {
+                        self.state = EscapeState::None;
+                        return csi_final(byte, self.param).map(|code| KeyEvent::press(code, plain));
+                    }
+                    _ => {}
+                }
+                return None;
+            }
+            EscapeState::None => {}
+        }
+
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        let (code, modifiers) = match byte {
+            0x1B => {
+                self.state = EscapeState::Start;
+                return None;
+            }
+            b'\n' if after_cr => return None,
+            b'\r' | b'\n' => (KeyCode::Enter, plain),
+            0x7F | 0x08 => (KeyCode::Backspace, plain),
+            b'\t' => (KeyCode::Tab, plain),
+            0x01..=0x1A => (KeyCode::Char((b'a' + byte - 1) as char), Modifiers { ctrl: true, ..plain }),
+            0x20..=0x7E => (KeyCode::Char(byte as char), plain),
+            _ => return None,
+        };
+        Some(KeyEvent::press(code, modifiers))
+    }
+}
+
+/// Key named by the final byte of an escape sequence and its first parameter
+fn csi_final(byte: u8, param: u8) -> Option<KeyCode> {
+    let code = match (byte, param) {
+        (b'A', _) => KeyCode::Up,
+        (b'B', _) => KeyCode::Down,
+        (b'C', _) => KeyCode::Right,
+        (b'D', _) => KeyCode::Left,
+        (b'H', _) | (b'~', 1) | (b'~', 7) => KeyCode::Home,
+        (b'F', _) | (b'~', 4) | (b'~', 8) => KeyCode::End,
+        (b'~', 2) => KeyCode::Insert,
+        (b'~', 3) => KeyCode::Delete,
+        (b'~', 5) => KeyCode::PageUp,
+        (b'~', 6) => KeyCode::PageDown,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    fn feed_all(decoder: &mut Decoder, codes: &[u8]) -> Option<KeyEvent> {
+        codes.iter().fold(None, |_, &code| decoder.feed(code))
+    }
+
+    fn code(event: Option<KeyEvent>) -> Option<KeyCode> {
+        event.map(|e| e.code)
+    }
+
+    fn terminal(bytes: &[u8]) -> [Option<KeyEvent>; 8] {
+        let mut decoder = TerminalDecoder::new();
+        let mut events = [None; 8];
+        let mut count = 0;
+        for &byte in bytes {
+            if let Some(event) = decoder.feed(byte) {
+                events[count] = Some(event);
+                count += 1;
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_plain_keys() {
+        let mut decoder = Decoder::new();
+        assert_eq!(code(decoder.feed(0x1E)), Some(KeyCode::Char('a')));
+        assert_eq!(code(decoder.feed(0x02)), Some(KeyCode::Char('1')));
+        assert_eq!(code(decoder.feed(0x1C)), Some(KeyCode::Enter));
+        assert_eq!(code(decoder.feed(0x0E)), Some(KeyCode::Backspace));
+        assert_eq!(code(decoder.feed(0x01)), Some(KeyCode::Escape));
+        assert_eq!(decoder.feed(0x54), None); // SysRq is unmapped
+    }
+
+    #[test]
+    fn test_release_events() {
+        let mut decoder = Decoder::new();
+        let press = decoder.feed(0x1E).unwrap();
+        assert!(press.pressed);
+        let release = decoder.feed(0x1E | RELEASED).unwrap();
+        assert!(!release.pressed);
+        assert_eq!(release.code, KeyCode::Char('a'));
+        assert_eq!(release.to_ascii(), None);
+    }
+
+    #[test]
+    fn test_shift_until_released() {
+        let mut decoder = Decoder::new();
+        let shift = decoder.feed(LEFT_SHIFT).unwrap();
+        assert_eq!(shift.code, KeyCode::Shift);
+        assert!(shift.modifiers.shift);
+        assert_eq!(code(decoder.feed(0x1E)), Some(KeyCode::Char('A')));
+        assert_eq!(code(decoder.feed(0x02)), Some(KeyCode::Char('!')));
+        assert_eq!(code(decoder.feed(0x0C)), Some(KeyCode::Char('_')));
+        assert!(!decoder.feed(LEFT_SHIFT | RELEASED).unwrap().modifiers.shift);
+        assert_eq!(code(decoder.feed(0x1E)), Some(KeyCode::Char('a')));
+    }
+
+    #[test]
+    fn test_both_shifts_tracked_separately() {
+        let mut decoder = Decoder::new();
+        feed_all(&mut decoder, &[LEFT_SHIFT, RIGHT_SHIFT, LEFT_SHIFT | RELEASED]);
+        assert_eq!(code(decoder.feed(0x35)), Some(KeyCode::Char('?')));
+    }
+
+    #[test]
+    fn test_caps_lock_letters_only() {
+        let mut decoder = Decoder::new();
+        feed_all(&mut decoder, &[CAPS_LOCK, CAPS_LOCK | RELEASED]);
+        assert!(decoder.modifiers().caps_lock);
+        assert_eq!(code(decoder.feed(0x10)), Some(KeyCode::Char('Q')));
+        assert_eq!(code(decoder.feed(0x03)), Some(KeyCode::Char('2')));
+
+        // Shift reverses Caps Lock for letters
+        decoder.feed(LEFT_SHIFT);
+        assert_eq!(code(decoder.feed(0x10)), Some(KeyCode::Char('q')));
+        assert_eq!(code(decoder.feed(0x03)), Some(KeyCode::Char('@')));
+    }
+
+    #[test]
+    fn test_caps_lock_ignores_repeat() {
+        let mut decoder = Decoder::new();
+        // Holding the key sends repeated make codes
+        feed_all(&mut decoder, &[CAPS_LOCK, CAPS_LOCK, CAPS_LOCK, CAPS_LOCK | RELEASED]);
+        assert!(decoder.modifiers().caps_lock);
+        feed_all(&mut decoder, &[CAPS_LOCK, CAPS_LOCK | RELEASED]);
+        assert!(!decoder.modifiers().caps_lock);
+    }
+
+    #[test]
+    fn test_locks_and_leds() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.modifiers().leds(), 0);
+        feed_all(&mut decoder, &[NUM_LOCK, NUM_LOCK, NUM_LOCK | RELEASED, SCROLL_LOCK]);
+        assert_eq!(decoder.modifiers().leds(), 0b011);
+        feed_all(&mut decoder, &[SCROLL_LOCK | RELEASED, CAPS_LOCK]);
+        assert_eq!(decoder.modifiers().leds(), 0b111);
+        // Pause is Ctrl and Num Lock behind 0xE1, and changes neither
+        feed_all(&mut decoder, &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5]);
+        assert_eq!(decoder.modifiers(), Modifiers { caps_lock: true, num_lock: true, scroll_lock: true, ..Modifiers::default() });
+    }
+
+    #[test]
+    fn test_set2_matches_set1() {
+        let mut set1 = Decoder::new();
+        let mut set2 = Decoder::new();
+        // Shift+a, then Left, then the keypad Enter, each pressed and released
+        let events1 = [LEFT_SHIFT, 0x1E, 0x9E, 0xAA, 0xE0, 0x4B, 0xE0, 0xCB, 0xE0, 0x1C, 0xE0, 0x9C].map(|b| set1.feed(b));
+        let bytes2: [&[u8]; 12] = [&[0x12], &[0x1C], &[0xF0, 0x1C], &[0xF0, 0x12], &[0xE0], &[0x6B], &[0xE0], &[0xF0, 0x6B], &[0xE0], &[0x5A], &[0xE0], &[0xF0, 0x5A]];
+        let events2 = bytes2.map(|bytes| bytes.iter().fold(None, |_, &b| set2.feed_set2(b)));
+        assert_eq!(events1, events2);
+        assert_eq!(code(events2[1]), Some(KeyCode::Char('A')));
+        // A self-test pass and an unmapped key are dropped; Pause still is
+        assert_eq!(set2.feed_set2(0xAA), None);
+        assert_eq!(set2.feed_set2(0x84), None);
+        for byte in [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77] {
+            assert_eq!(set2.feed_set2(byte), None);
+        }
+        assert_eq!(set2.modifiers(), Modifiers::default());
+    }
+
+    #[test]
+    fn test_typematic_settings() {
+        assert_eq!(Typematic::new(250, 30), Ok(Typematic(0x00)));
+        assert_eq!(Typematic::new(1000, 2), Ok(Typematic(0x7F)));
+        assert_eq!(Typematic::new(500, 10), Ok(Typematic(0x2C)));
+        assert_eq!(Typematic::new(300, 10), Err("Delay must be 250, 500, 750 or 1000 ms"));
+        assert_eq!(Typematic::new(500, 31), Err("Rate must be 2 to 30 keys per second"));
+        assert_eq!(std::format!("{}", Typematic::DEFAULT), "500 ms delay, 10.9 keys/s");
+        assert_eq!(std::format!("{}", Typematic(0)), "250 ms delay, 30.0 keys/s");
+    }
+
+    #[test]
+    fn test_ctrl_combinations() {
+        let mut decoder = Decoder::new();
+        decoder.feed(CTRL);
+        let ctrl_c = decoder.feed(0x2E).unwrap();
+        assert_eq!(ctrl_c.ctrl_letter(), Some('c'));
+        assert_eq!(ctrl_c.to_ascii(), Some(0x03));
+        // Shift doesn't change the letter
+        decoder.feed(LEFT_SHIFT);
+        assert_eq!(decoder.feed(0x16).unwrap().ctrl_letter(), Some('u'));
+        // Ctrl with a non-letter has no terminal byte
+        assert_eq!(decoder.feed(0x02).unwrap().to_ascii(), None);
+        decoder.feed(CTRL | RELEASED);
+        let plain = decoder.feed(0x2E).unwrap();
+        assert_eq!(plain.ctrl_letter(), None);
+        assert_eq!(plain.to_ascii(), Some(b'C'));
+    }
+
+    #[test]
+    fn test_right_ctrl_and_alt() {
+        let mut decoder = Decoder::new();
+        feed_all(&mut decoder, &[EXTENDED_PREFIX, CTRL]);
+        assert!(decoder.modifiers().ctrl);
+        assert_eq!(decoder.feed(0x20).unwrap().ctrl_letter(), Some('d'));
+        feed_all(&mut decoder, &[EXTENDED_PREFIX, CTRL | RELEASED]);
+        assert!(!decoder.modifiers().ctrl);
+
+        feed_all(&mut decoder, &[EXTENDED_PREFIX, ALT]);
+        let alt_n = decoder.feed(0x31).unwrap();
+        assert!(alt_n.modifiers.alt);
+        assert_eq!(alt_n.to_ascii(), None);
+        feed_all(&mut decoder, &[EXTENDED_PREFIX, ALT | RELEASED]);
+        assert_eq!(decoder.modifiers(), Modifiers::default());
+    }
+
+    #[test]
+    fn test_navigation_keys() {
+        let mut decoder = Decoder::new();
+        let keys = [
+            (0x48, KeyCode::Up),
+            (0x50, KeyCode::Down),
+            (0x4B, KeyCode::Left),
+            (0x4D, KeyCode::Right),
+            (0x47, KeyCode::Home),
+            (0x4F, KeyCode::End),
+            (0x52, KeyCode::Insert),
+            (0x53, KeyCode::Delete),
+            (0x49, KeyCode::PageUp),
+            (0x51, KeyCode::PageDown),
+        ];
+        for (scan_code, key) in keys {
+            assert_eq!(code(feed_all(&mut decoder, &[EXTENDED_PREFIX, scan_code])), Some(key));
+            let release = feed_all(&mut decoder, &[EXTENDED_PREFIX, scan_code | RELEASED]).unwrap();
+            assert_eq!((release.code, release.pressed), (key, false));
+        }
+        // Without the prefix these are keypad keys, which aren't mapped
+        assert_eq!(decoder.feed(0x48), None);
+    }
+
+    #[test]
+    fn test_function_keys() {
+        let mut decoder = Decoder::new();
+        assert_eq!(code(decoder.feed(0x3B)), Some(KeyCode::F(1)));
+        assert_eq!(code(decoder.feed(0x44)), Some(KeyCode::F(10)));
+        assert_eq!(code(decoder.feed(0x58)), Some(KeyCode::F(12)));
+        decoder.feed(ALT);
+        let alt_f2 = decoder.feed(0x3C).unwrap();
+        assert_eq!((alt_f2.code, alt_f2.modifiers.alt, alt_f2.to_ascii()), (KeyCode::F(2), true, None));
+        for n in 1..=12 {
+            assert_eq!(type_key(&mut decoder, KeyCode::F(n)), Some(KeyCode::F(n)));
+        }
+        assert_eq!(ScanKey::for_code(KeyCode::F(13), &keymap::US), None);
+    }
+
+    #[test]
+    fn test_extended_keypad_and_fake_shift() {
+        let mut decoder = Decoder::new();
+        assert_eq!(code(feed_all(&mut decoder, &[EXTENDED_PREFIX, 0x1C])), Some(KeyCode::Enter));
+        assert_eq!(code(feed_all(&mut decoder, &[EXTENDED_PREFIX, 0x35])), Some(KeyCode::Char('/')));
+        // Fake shifts around extended keys don't latch Shift
+        assert_eq!(feed_all(&mut decoder, &[EXTENDED_PREFIX, LEFT_SHIFT]), None);
+        assert!(!decoder.modifiers().shift);
+        assert_eq!(code(decoder.feed(0x1E)), Some(KeyCode::Char('a')));
+    }
+
+    /// Press and release the key for `key_code` in the decoder's layout,
+    /// Shift or AltGr around it if needed; returns what the press decoded to
+    fn type_key(decoder: &mut Decoder, key_code: KeyCode) -> Option<KeyCode> {
+        let key = ScanKey::for_code(key_code, decoder.keymap())?;
+        let modifier = match key.level {
+            Level::Plain => None,
+            Level::Shift => ScanKey::for_code(KeyCode::Shift, decoder.keymap()),
+            Level::AltGr => Some(ScanKey::ALTGR),
+        };
+        let mut press = None;
+        let mut bytes = std::vec::Vec::new();
+        modifier.iter().for_each(|modifier| modifier.emit(true, &mut |byte| bytes.push(byte)));
+        key.emit(true, &mut |byte| bytes.push(byte));
+        for byte in bytes.drain(..) {
+            press = decoder.feed(byte);
+        }
+        key.emit(false, &mut |byte| bytes.push(byte));
+        modifier.iter().for_each(|modifier| modifier.emit(false, &mut |byte| bytes.push(byte)));
+        for byte in bytes {
+            decoder.feed(byte);
+        }
+        code(press)
+    }
+
+    #[test]
+    fn test_scan_keys_decode_back() {
+        let mut decoder = Decoder::new();
+        for byte in 0x20u8..0x7F {
+            let c = KeyCode::Char(byte as char);
+            assert_eq!(type_key(&mut decoder, c), Some(c), "{:?}", byte as char);
+        }
+        for named in [KeyCode::Enter, KeyCode::Tab, KeyCode::Up, KeyCode::Delete, KeyCode::PageDown] {
+            assert_eq!(type_key(&mut decoder, named), Some(named));
+        }
+        assert_eq!(ScanKey::for_code(KeyCode::Char('é'), &keymap::US), None);
+    }
+
+    #[test]
+    fn test_other_layouts() {
+        let mut decoder = Decoder::new();
+        for keymap in keymap::KEYMAPS {
+            decoder.set_keymap(keymap);
+            for c in (0x20u8..0x7F).map(char::from).chain(['ä', 'é', '€', '§']) {
+                if keymap.find(c).is_some() {
+                    let c = KeyCode::Char(c);
+                    assert_eq!(type_key(&mut decoder, c), Some(c), "{} {:?}", keymap.name, c);
+                }
+            }
+        }
+        // Every ASCII character is somewhere on the German layout
+        assert!((0x20u8..0x7F).all(|byte| keymap::DE.find(byte as char).is_some()));
+        assert_eq!(code(decoder.feed(0x14)), Some(KeyCode::Char('y'))); // Dvorak
+        decoder.set_keymap(&keymap::DE);
+        assert_eq!(code(decoder.feed(0x15)), Some(KeyCode::Char('z')));
+        // AltGr is not Alt there, and keeps its other keys plain
+        let at = feed_all(&mut decoder, &[EXTENDED_PREFIX, ALT, 0x10]).unwrap();
+        assert_eq!((at.code, at.to_ascii()), (KeyCode::Char('@'), Some(b'@')));
+        assert_eq!(code(decoder.feed(0x11)), Some(KeyCode::Char('w')));
+    }
+
+    #[test]
+    fn test_terminal_plain_bytes() {
+        let events = terminal(b"a\r\nB\x7f\t");
+        assert_eq!(code(events[0]), Some(KeyCode::Char('a')));
+        // CR LF is a single Enter
+        assert_eq!(code(events[1]), Some(KeyCode::Enter));
+        assert_eq!(code(events[2]), Some(KeyCode::Char('B')));
+        assert_eq!(code(events[3]), Some(KeyCode::Backspace));
+        assert_eq!(code(events[4]), Some(KeyCode::Tab));
+        assert_eq!(events[5], None);
+    }
+
+    #[test]
+    fn test_terminal_control_and_alt() {
+        let events = terminal(b"\x03\x15\x1bx");
+        assert_eq!(events[0].unwrap().ctrl_letter(), Some('c'));
+        assert_eq!(events[1].unwrap().to_ascii(), Some(0x15));
+        let alt_x = events[2].unwrap();
+        assert_eq!((alt_x.code, alt_x.modifiers.alt), (KeyCode::Char('x'), true));
+    }
+
+    #[test]
+    fn test_terminal_escape_sequences() {
+        let events = terminal(b"\x1b[A\x1b[D\x1bOH\x1b[3~\x1b[5~\x1b[1;5C\x1b[4~\x1b[F");
+        let codes = events.map(code);
+        assert_eq!(
+            codes,
+            [
+                Some(KeyCode::Up),
+                Some(KeyCode::Left),
+                Some(KeyCode::Home),
+                Some(KeyCode::Delete),
+                Some(KeyCode::PageUp),
+                Some(KeyCode::Right),
+                Some(KeyCode::End),
+                Some(KeyCode::End),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_terminal_unknown_sequence_swallowed() {
+        // F5 (ESC [ 1 5 ~) has no key code; the following byte still arrives
+        let events = terminal(b"\x1b[15~q");
+        assert_eq!(code(events[0]), Some(KeyCode::Char('q')));
+        assert_eq!(events[1], None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Keyboard layouts
+//! A `Keymap` says which characters the character keys type, as rows of
+//! set 1 make codes with a string per level: unshifted, with Shift, and with
+//! AltGr (right Alt). A space in the AltGr string means the key has nothing
+//! there. Layouts with an AltGr level give up right Alt as a modifier; the
+//! rest keep it as Alt. Dead keys type their accent as it is.
+
+/// Shift level of a character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Plain,
+    Shift,
+    AltGr,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Keymap {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// First make code of each row, and the row's characters at each level
+    rows: [(u8, [&'static str; 3]); 6],
+}
+
+/// Make code of the space bar, the same on every layout
+const SPACE: u8 = 0x39;
+
+impl Keymap {
+    /// The character key `make` types at `level`, if it types one there
+    pub fn char_at(&self, make: u8, level: Level) -> Option<char> {
+        if make == SPACE {
+            return (level != Level::AltGr).then_some(' ');
+        }
+        self.rows.iter().find_map(|&(first, levels)| {
+            let c = levels[level as usize].chars().nth(make.checked_sub(first)? as usize)?;
+            (c != ' ').then_some(c)
+        })
+    }
+
+    /// The key and level that type `c`, preferring the lowest level
+    pub fn find(&self, c: char) -> Option<(u8, Level)> {
+        if c == ' ' {
+            return Some((SPACE, Level::Plain));
+        }
+        [Level::Plain, Level::Shift, Level::AltGr].into_iter().find_map(|level| {
+            self.rows.iter().find_map(|&(first, levels)| {
+                let index = levels[level as usize].chars().position(|key| key == c)?;
+                Some((first + index as u8, level))
+            })
+        })
+    }
+
+    /// True if right Alt is AltGr rather than Alt
+    pub fn has_altgr(&self) -> bool {
+        self.rows.iter().any(|(_, levels)| levels[Level::AltGr as usize].chars().any(|c| c != ' '))
+    }
+}
+
+/// US QWERTY, what the kernel starts with
+pub const US: Keymap = Keymap {
+    name: "us",
+    description: "US QWERTY",
+    rows: [
+        (0x02, ["1234567890-=", "!@#$%^&*()_+", ""]),
+        (0x10, ["qwertyuiop[]", "QWERTYUIOP{}", ""]),
+        (0x1E, ["asdfghjkl;'`", "ASDFGHJKL:\"~", ""]),
+        (0x2B, ["\\", "|", ""]),
+        (0x2C, ["zxcvbnm,./", "ZXCVBNM<>?", ""]),
+        (0x56, ["\\", "|", ""]),
+    ],
+};
+
+/// German QWERTZ
+pub const DE: Keymap = Keymap {
+    name: "de",
+    description: "German QWERTZ",
+    rows: [
+        (0x02, ["1234567890ß´", "!\"§$%&/()=?`", " ²³   {[]}\\ "]),
+        (0x10, ["qwertzuiopü+", "QWERTZUIOPÜ*", "@ €        ~"]),
+        (0x1E, ["asdfghjklöä^", "ASDFGHJKLÖÄ°", ""]),
+        (0x2B, ["#", "'", ""]),
+        (0x2C, ["yxcvbnm,.-", "YXCVBNM;:_", "      µ   "]),
+        (0x56, ["<", ">", "|"]),
+    ],
+};
+
+/// French AZERTY
+pub const FR: Keymap = Keymap {
+    name: "fr",
+    description: "French AZERTY",
+    rows: [
+        (0x02, ["&é\"'(-è_çà)=", "1234567890°+", " ~#{[|`\\^@]}"]),
+        (0x10, ["azertyuiop^$", "AZERTYUIOP¨£", "  €        ¤"]),
+        (0x1E, ["qsdfghjklmù²", "QSDFGHJKLM%²", ""]),
+        (0x2B, ["*", "µ", ""]),
+        (0x2C, ["wxcvbn,;:!", "WXCVBN?./§", ""]),
+        (0x56, ["<", ">", ""]),
+    ],
+};
+
+/// Dvorak on a US keyboard
+pub const DVORAK: Keymap = Keymap {
+    name: "dvorak",
+    description: "US Dvorak",
+    rows: [
+        (0x02, ["1234567890[]", "!@#$%^&*(){}", ""]),
+        (0x10, ["',.pyfgcrl/=", "\"<>PYFGCRL?+", ""]),
+        (0x1E, ["aoeuidhtns-`", "AOEUIDHTNS_~", ""]),
+        (0x2B, ["\\", "|", ""]),
+        (0x2C, [";qjkxbmwvz", ":QJKXBMWVZ", ""]),
+        (0x56, ["\\", "|", ""]),
+    ],
+};
+
+/// Every layout, the default first
+pub const KEYMAPS: [&Keymap; 4] = [&US, &DE, &FR, &DVORAK];
+
+/// The layout called `name`
+pub fn by_name(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS.into_iter().find(|keymap| keymap.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_line_up() {
+        for keymap in KEYMAPS {
+            for (first, levels) in keymap.rows {
+                let keys = levels[0].chars().count();
+                assert_eq!(levels[1].chars().count(), keys, "{} {:#x}", keymap.name, first);
+                let altgr = levels[2].chars().count();
+                assert!(altgr == 0 || altgr == keys, "{} {:#x}", keymap.name, first);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookups() {
+        assert_eq!(US.char_at(0x15, Level::Plain), Some('y'));
+        assert_eq!(DE.char_at(0x15, Level::Plain), Some('z'));
+        assert_eq!(DE.char_at(0x10, Level::AltGr), Some('@'));
+        assert_eq!(DE.char_at(0x11, Level::AltGr), None);
+        assert_eq!(FR.char_at(0x03, Level::Shift), Some('2'));
+        assert_eq!(DE.find('{'), Some((0x08, Level::AltGr)));
+        assert_eq!(DVORAK.find('S'), Some((0x27, Level::Shift)));
+        assert_eq!(US.find(' '), Some((SPACE, Level::Plain)));
+        assert!(DE.has_altgr() && !US.has_altgr());
+        assert_eq!(by_name("DE"), Some(&DE));
+        assert_eq!(by_name("sv"), None);
+    }
+}
@@ -2,5 +2,39 @@
 
 // Shared library for hardware-agnostic data structures and utilities
 // Can be tested on host system (macOS ARM64) without cross-compilation
+// Collections that grow use `alloc`; the kernel, the only user, has a heap
 
+extern crate alloc;
+
+pub mod acpi;
+pub mod addr;
+pub mod archive;
+pub mod backtrace;
+pub mod bootfmt;
+pub mod buddy;
+pub mod bytes;
+pub mod chacha;
+pub mod clock;
+pub mod cmdline;
+pub mod cpuid;
 pub mod data_structures;
+pub mod datetime;
+pub mod elf;
+pub mod fat;
+pub mod fbterm;
+pub mod fdtable;
+pub mod graphics;
+pub mod ipc;
+pub mod keyboard;
+pub mod keymap;
+pub mod log;
+pub mod memrange;
+pub mod mmio;
+pub mod net;
+pub mod page_fault;
+pub mod path;
+pub mod replay;
+pub mod serial;
+pub mod shell;
+pub mod vma;
+pub mod watchdog;
@@ -0,0 +1,200 @@
+//! Kernel message log
+//! A fixed ring of records, each a level, a timestamp, and one line of text
+//! cut to `TEXT_CAPACITY` bytes. Once the ring is full every new record
+//! replaces the oldest, so it always holds the latest messages without
+//! allocating, from the first line of boot onward.
+
+use core::fmt;
+
+/// Longest message kept; the rest of a longer one is dropped
+pub const TEXT_CAPACITY: usize = 120;
+
+/// How important a message is, least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+
+    /// Level from its name as printed by `as_str`
+    pub fn parse(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|level| level.as_str() == name)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    level: Level,
+    timestamp_ns: u64,
+    len: u8,
+    text: [u8; TEXT_CAPACITY],
+}
+
+const EMPTY: Entry = Entry { level: Level::Trace, timestamp_ns: 0, len: 0, text: [0; TEXT_CAPACITY] };
+
+/// One logged message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub level: Level,
+    pub timestamp_ns: u64,
+    pub text: &'a str,
+}
+
+/// The last `N` messages
+pub struct LogRing<const N: usize> {
+    entries: [Entry; N],
+    /// Slot the next record goes into
+    next: usize,
+    len: usize,
+    /// Records ever pushed, including the ones since overwritten
+    total: u64,
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        LogRing { entries: [EMPTY; N], next: 0, len: 0, total: 0 }
+    }
+
+    /// Append a message, replacing the oldest if the ring is full
+    /// Trailing newlines are dropped, and text past `TEXT_CAPACITY` is cut
+    /// at a char boundary.
+    pub fn push(&mut self, level: Level, timestamp_ns: u64, text: &str) {
+        let text = text.trim_end_matches('\n');
+        let mut len = text.len().min(TEXT_CAPACITY);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let entry = &mut self.entries[self.next];
+        entry.level = level;
+        entry.timestamp_ns = timestamp_ns;
+        entry.len = len as u8;
+        entry.text[..len].copy_from_slice(&text.as_bytes()[..len]);
+
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        self.total += 1;
+    }
+
+    /// Number of the oldest record held, counting every record pushed
+    pub fn first(&self) -> u64 {
+        self.total - self.len as u64
+    }
+
+    /// Number the next record pushed will get
+    pub fn end(&self) -> u64 {
+        self.total
+    }
+
+    /// Record number `seq`, if it has been pushed and not yet replaced
+    pub fn get(&self, seq: u64) -> Option<Record<'_>> {
+        if seq < self.first() || seq >= self.total {
+            return None;
+        }
+        let back = (self.total - seq) as usize;
+        let entry = &self.entries[(self.next + N - back) % N];
+        Some(Record {
+            level: entry.level,
+            timestamp_ns: entry.timestamp_ns,
+            // Only whole UTF-8 sequences are ever copied in
+            text: unsafe { core::str::from_utf8_unchecked(&entry.text[..entry.len as usize]) },
+        })
+    }
+
+    /// Records held, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = Record<'_>> {
+        (self.first()..self.total).filter_map(move |seq| self.get(seq))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Time since boot as `[seconds.micros]`, padded to line up in a column
+pub struct Timestamp(pub u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:5}.{:06}]", self.0 / 1_000_000_000, self.0 % 1_000_000_000 / 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn test_level_names() {
+        for level in Level::ALL {
+            assert_eq!(Level::parse(level.as_str()), Some(level));
+        }
+        assert_eq!(Level::parse("loud"), None);
+        assert!(Level::Warn > Level::Info);
+    }
+
+    #[test]
+    fn test_oldest_are_replaced() {
+        let mut ring: LogRing<3> = LogRing::new();
+        assert!(ring.is_empty());
+        for (i, text) in ["one", "two", "three", "four"].iter().enumerate() {
+            ring.push(Level::Info, i as u64, text);
+        }
+
+        assert_eq!(ring.len(), 3);
+        assert_eq!((ring.first(), ring.end()), (1, 4));
+        assert!(ring.iter().map(|r| r.text).eq(["two", "three", "four"]));
+        assert_eq!(ring.get(0), None);
+        assert_eq!(ring.get(1).unwrap().timestamp_ns, 1);
+        assert_eq!(ring.get(3).unwrap().text, "four");
+        assert_eq!(ring.get(4), None);
+    }
+
+    #[test]
+    fn test_long_text_is_cut() {
+        let mut ring: LogRing<2> = LogRing::new();
+        // 'é' is two bytes, so the limit falls inside a character
+        let text = format!("a{}", "é".repeat(TEXT_CAPACITY));
+        ring.push(Level::Warn, 0, &text);
+        ring.push(Level::Error, 0, "done\n");
+
+        let records: std::vec::Vec<Record> = ring.iter().collect();
+        assert_eq!(records[0].text.len(), TEXT_CAPACITY - 1);
+        assert!(text.starts_with(records[0].text));
+        assert_eq!(records[1], Record { level: Level::Error, timestamp_ns: 0, text: "done" });
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(format!("{}", Timestamp(0)), "[    0.000000]");
+        assert_eq!(format!("{}", Timestamp(12_345_678_901)), "[   12.345678]");
+    }
+}
@@ -0,0 +1,84 @@
+//! Physical address range arithmetic for the memory map
+//! Bootloader memory maps are not guaranteed to be tidy: an entry the frame
+//! allocator may use can overlap one it must not touch (a framebuffer, or a
+//! reserved hole firmware reported twice), and non-usable entries need not be
+//! page aligned. `subtract` cuts the pages that are safe to manage out of a
+//! range, rounding the range in and the holes out.
+
+/// Call `f(start, end)` for each page-aligned piece of `[start, end)` that
+/// overlaps none of `holes`, lowest first
+pub fn subtract<I>(start: u64, end: u64, holes: I, page: u64, mut f: impl FnMut(u64, u64))
+where
+    I: Iterator<Item = (u64, u64)> + Clone,
+{
+    let end = end / page * page;
+    let mut cursor = start.next_multiple_of(page);
+    while cursor < end {
+        // The lowest hole that still reaches past the cursor
+        let next_hole = holes
+            .clone()
+            .filter(|&(hole_start, hole_end)| hole_start < hole_end && hole_end > cursor && hole_start < end)
+            .map(|(hole_start, hole_end)| (hole_start / page * page, hole_end.next_multiple_of(page)))
+            .min();
+        match next_hole {
+            Some((hole_start, hole_end)) => {
+                if hole_start > cursor {
+                    f(cursor, hole_start);
+                }
+                cursor = cursor.max(hole_end);
+            }
+            None => {
+                f(cursor, end);
+                return;
+            }
+        }
+    }
+}
+
+/// Bytes of `[start, end)` that `holes` cover, counted in whole pages as
+/// `subtract` leaves them out
+pub fn overlap<I>(start: u64, end: u64, holes: I, page: u64) -> u64
+where
+    I: Iterator<Item = (u64, u64)> + Clone,
+{
+    let mut kept = 0;
+    subtract(start, end, holes, page, |piece_start, piece_end| kept += piece_end - piece_start);
+    (end / page * page).saturating_sub(start.next_multiple_of(page)) - kept
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    fn pieces(start: u64, end: u64, holes: &[(u64, u64)]) -> Vec<(u64, u64)> {
+        let mut out = Vec::new();
+        subtract(start, end, holes.iter().copied(), 0x1000, |s, e| out.push((s, e)));
+        out
+    }
+
+    #[test]
+    fn test_subtract_without_holes_keeps_range() {
+        assert_eq!(pieces(0x1000, 0x9000, &[]), [(0x1000, 0x9000)]);
+        // Holes elsewhere don't matter
+        assert_eq!(pieces(0x1000, 0x9000, &[(0, 0x1000), (0x9000, 0xa000)]), [(0x1000, 0x9000)]);
+    }
+
+    #[test]
+    fn test_subtract_rounds_holes_out_and_range_in() {
+        // An unaligned hole takes every page it touches
+        assert_eq!(pieces(0, 0x10000, &[(0x3800, 0x4001)]), [(0, 0x3000), (0x5000, 0x10000)]);
+        // An unaligned range loses its partial pages
+        assert_eq!(pieces(0x800, 0x9fc00, &[]), [(0x1000, 0x9f000)]);
+        assert_eq!(overlap(0, 0x10000, [(0x3800, 0x4001)].into_iter(), 0x1000), 0x2000);
+    }
+
+    #[test]
+    fn test_subtract_overlapping_and_covering_holes() {
+        let holes = [(0x6000, 0x8000), (0x2000, 0x3000), (0x7000, 0xa000)];
+        assert_eq!(pieces(0, 0xc000, &holes), [(0, 0x2000), (0x3000, 0x6000), (0xa000, 0xc000)]);
+        assert!(pieces(0x2000, 0x3000, &holes).is_empty());
+        assert_eq!(overlap(0x2000, 0x3000, holes.into_iter(), 0x1000), 0x1000);
+    }
+}
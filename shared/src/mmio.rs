@@ -0,0 +1,208 @@
+//! Memory-mapped device registers
+//! `ReadOnly`, `WriteOnly`, and `ReadWrite` wrap a register so every access is
+//! volatile and only the permitted direction compiles. `register_block!`
+//! describes a device's registers by offset from a base address, which suits
+//! sparse layouts (a PLIC spans megabytes) better than a padded struct.
+//! `Volatile` is for memory a device shares rather than a register: a VGA
+//! text buffer, framebuffer pixels, a virtqueue ring. It can sit in a struct
+//! describing that memory, or be laid over a pointer with `from_ptr`.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+
+/// Memory that a device also reads or writes
+#[repr(transparent)]
+pub struct Volatile<T: Copy>(UnsafeCell<T>);
+
+/// A register that can only be read
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy>(UnsafeCell<T>);
+
+/// A register that can only be written
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy>(UnsafeCell<T>);
+
+/// A register that can be read and written
+#[repr(transparent)]
+pub struct ReadWrite<T: Copy>(UnsafeCell<T>);
+
+impl<T: Copy> Volatile<T> {
+    pub const fn new(value: T) -> Self {
+        Volatile(UnsafeCell::new(value))
+    }
+
+    /// The `T` at `ptr`, accessed volatile from now on
+    ///
+    /// # Safety
+    /// `ptr` must be valid and aligned for `T` for as long as `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *mut T) -> &'a Volatile<T> {
+        &*(ptr as *const Volatile<T>)
+    }
+
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+
+    /// Read, transform, and write back (not atomic with respect to the device)
+    pub fn modify(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
+
+impl<T: Copy> ReadOnly<T> {
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+}
+
+impl<T: Copy> WriteOnly<T> {
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+}
+
+impl<T: Copy> ReadWrite<T> {
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+
+    /// Read, transform, and write back (not atomic with respect to the device)
+    pub fn modify(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
+
+/// Declare a register block: a base address plus one accessor per register
+///
+/// ```text
+/// register_block! {
+///     /// Per-context PLIC registers
+///     pub struct PlicContext {
+///         0x0 => pub threshold: ReadWrite<u32>,
+///         0x4 => pub claim: ReadWrite<u32>,
+///     }
+/// }
+/// let context = unsafe { PlicContext::new(base) };
+/// context.threshold().write(0);
+/// ```
+///
+/// A register type may also be an array, e.g. `[ReadWrite<u32>; 32]`.
+#[macro_export]
+macro_rules! register_block {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $offset:literal => $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            base: $crate::addr::VirtAddr,
+        }
+
+        impl $name {
+            /// Registers of the device mapped at `base`
+            ///
+            /// # Safety
+            /// `base` must map the whole block for as long as the value is
+            /// used, and nothing else may treat that memory as ordinary data.
+            #[allow(dead_code)]
+            $vis const unsafe fn new(base: $crate::addr::VirtAddr) -> Self {
+                $name { base }
+            }
+
+            #[allow(dead_code)]
+            $vis fn base(&self) -> $crate::addr::VirtAddr {
+                self.base
+            }
+
+            $(
+                $(#[$field_attr])*
+                #[allow(dead_code)]
+                $field_vis fn $field(&self) -> &$ty {
+                    let register = (self.base.as_u64() as usize + $offset) as *const $ty;
+                    unsafe { &*register }
+                }
+            )*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::addr::VirtAddr;
+
+    register_block! {
+        struct TestDevice {
+            0x0 => status: ReadOnly<u32>,
+            0x4 => command: WriteOnly<u32>,
+            0xA => control: ReadWrite<u16>,
+            0x10 => table: [ReadWrite<u32>; 2],
+        }
+    }
+
+    fn device(memory: &mut [u32; 8]) -> TestDevice {
+        unsafe { TestDevice::new(VirtAddr::new(memory.as_mut_ptr() as u64)) }
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut memory = [0u32; 8];
+        memory[0] = 0xDEAD_BEEF;
+        assert_eq!(device(&mut memory).status().read(), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn test_write_at_offset() {
+        let mut memory = [0u32; 8];
+        device(&mut memory).command().write(7);
+        assert_eq!(memory, [0, 7, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_read_write_and_modify() {
+        let mut memory = [0u32; 8];
+        let dev = device(&mut memory);
+        dev.control().write(0x00F0);
+        dev.control().modify(|value| value | 0x0001);
+        assert_eq!(dev.control().read(), 0x00F1);
+        // Upper half of the word at 0x8 on a little-endian host
+        assert_eq!(memory[2], 0x00F1_0000);
+    }
+
+    #[test]
+    fn test_volatile_memory() {
+        let mut memory = [0u16; 4];
+        let cell = unsafe { Volatile::from_ptr(memory.as_mut_ptr().add(2)) };
+        cell.write(5);
+        cell.modify(|value| value * 3);
+        assert_eq!(cell.read(), 15);
+        assert_eq!(memory, [0, 0, 15, 0]);
+
+        let owned = [Volatile::new(1u8), Volatile::new(2)];
+        owned[1].write(owned[0].read() + 8);
+        assert_eq!(owned[1].read(), 9);
+    }
+
+    #[test]
+    fn test_register_array() {
+        let mut memory = [0u32; 8];
+        let dev = device(&mut memory);
+        dev.table()[1].write(42);
+        assert_eq!(dev.table()[0].read(), 0);
+        assert_eq!(memory[5], 42);
+    }
+}
@@ -0,0 +1,98 @@
+//! ARP for IPv4 over Ethernet (RFC 826)
+
+use crate::bytes::be::{read_u16, write_u16};
+use super::{Ipv4Addr, MacAddr};
+
+pub const PACKET_LEN: usize = 28;
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+
+const HTYPE_ETHERNET: u16 = 1;
+const PTYPE_IPV4: u16 = 0x0800;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Parse an Ethernet/IPv4 ARP packet; other hardware or protocol types are rejected
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_LEN
+            || read_u16(data, 0) != HTYPE_ETHERNET
+            || read_u16(data, 2) != PTYPE_IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        let mut packet = ArpPacket {
+            op: read_u16(data, 6),
+            sender_mac: MacAddr::ZERO,
+            sender_ip: Ipv4Addr::UNSPECIFIED,
+            target_mac: MacAddr::ZERO,
+            target_ip: Ipv4Addr::UNSPECIFIED,
+        };
+        packet.sender_mac.0.copy_from_slice(&data[8..14]);
+        packet.sender_ip.0.copy_from_slice(&data[14..18]);
+        packet.target_mac.0.copy_from_slice(&data[18..24]);
+        packet.target_ip.0.copy_from_slice(&data[24..28]);
+        Some(packet)
+    }
+
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        write_u16(buf, 0, HTYPE_ETHERNET);
+        write_u16(buf, 2, PTYPE_IPV4);
+        buf[4] = 6;
+        buf[5] = 4;
+        write_u16(buf, 6, self.op);
+        buf[8..14].copy_from_slice(&self.sender_mac.0);
+        buf[14..18].copy_from_slice(&self.sender_ip.0);
+        buf[18..24].copy_from_slice(&self.target_mac.0);
+        buf[24..28].copy_from_slice(&self.target_ip.0);
+        PACKET_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        // Who has 10.0.2.15? Tell 10.0.2.2 (as sent by QEMU's user network)
+        let raw = [
+            0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x52, 0x55, 0x0a, 0x00, 0x02, 0x02,
+            10, 0, 2, 2, 0, 0, 0, 0, 0, 0, 10, 0, 2, 15,
+        ];
+        let packet = ArpPacket::parse(&raw).unwrap();
+        assert_eq!(packet.op, OP_REQUEST);
+        assert_eq!(packet.sender_mac, MacAddr([0x52, 0x55, 0x0a, 0x00, 0x02, 0x02]));
+        assert_eq!(packet.sender_ip, Ipv4Addr::new(10, 0, 2, 2));
+        assert_eq!(packet.target_ip, Ipv4Addr::new(10, 0, 2, 15));
+
+        let mut out = [0u8; PACKET_LEN];
+        assert_eq!(packet.write(&mut out), PACKET_LEN);
+        assert_eq!(out, raw);
+    }
+
+    #[test]
+    fn test_reject_non_ipv4() {
+        let mut raw = [0u8; PACKET_LEN];
+        ArpPacket {
+            op: OP_REPLY,
+            sender_mac: MacAddr::ZERO,
+            sender_ip: Ipv4Addr::UNSPECIFIED,
+            target_mac: MacAddr::ZERO,
+            target_ip: Ipv4Addr::UNSPECIFIED,
+        }
+        .write(&mut raw);
+        raw[3] = 0xDD; // Protocol type 0x08DD
+        assert!(ArpPacket::parse(&raw).is_none());
+        assert!(ArpPacket::parse(&raw[..PACKET_LEN - 1]).is_none());
+    }
+}
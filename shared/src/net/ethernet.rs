@@ -0,0 +1,63 @@
+//! Ethernet II frames
+
+use crate::bytes::be::{read_u16, write_u16};
+use super::MacAddr;
+
+pub const HEADER_LEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EthernetHeader {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    /// Split a frame into its header and payload
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < HEADER_LEN {
+            return None;
+        }
+        let mut dst = [0u8; 6];
+        let mut src = [0u8; 6];
+        dst.copy_from_slice(&frame[0..6]);
+        src.copy_from_slice(&frame[6..12]);
+        let header = EthernetHeader {
+            dst: MacAddr(dst),
+            src: MacAddr(src),
+            ethertype: read_u16(frame, 12),
+        };
+        Some((header, &frame[HEADER_LEN..]))
+    }
+
+    /// Write the header to the start of `buf`, returning its length
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        buf[0..6].copy_from_slice(&self.dst.0);
+        buf[6..12].copy_from_slice(&self.src.0);
+        write_u16(buf, 12, self.ethertype);
+        HEADER_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let header = EthernetHeader {
+            dst: MacAddr::BROADCAST,
+            src: MacAddr([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+            ethertype: ETHERTYPE_ARP,
+        };
+        let mut frame = [0u8; HEADER_LEN + 2];
+        assert_eq!(header.write(&mut frame), HEADER_LEN);
+        frame[HEADER_LEN] = 0xAB;
+        let (parsed, payload) = EthernetHeader::parse(&frame).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(payload, &[0xAB, 0]);
+        assert!(EthernetHeader::parse(&frame[..HEADER_LEN - 1]).is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! ICMP messages (RFC 792)
+
+use crate::bytes::be::{read_u16, write_u16};
+use super::checksum;
+
+pub const HEADER_LEN: usize = 8;
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IcmpHeader {
+    pub icmp_type: u8,
+    pub code: u8,
+    /// Type-specific word; identifier and sequence number for echo messages
+    pub rest: [u8; 4],
+}
+
+impl IcmpHeader {
+    /// Validate the checksum and split a message into header and body
+    pub fn parse(message: &[u8]) -> Option<(Self, &[u8])> {
+        if message.len() < HEADER_LEN || checksum(message) != 0 {
+            return None;
+        }
+        let mut rest = [0u8; 4];
+        rest.copy_from_slice(&message[4..8]);
+        let header = IcmpHeader { icmp_type: message[0], code: message[1], rest };
+        Some((header, &message[HEADER_LEN..]))
+    }
+
+    /// Write the header followed by `body`, returning the message length
+    pub fn write(&self, buf: &mut [u8], body: &[u8]) -> usize {
+        let len = HEADER_LEN + body.len();
+        buf[0] = self.icmp_type;
+        buf[1] = self.code;
+        write_u16(buf, 2, 0);
+        buf[4..8].copy_from_slice(&self.rest);
+        buf[HEADER_LEN..len].copy_from_slice(body);
+        let sum = checksum(&buf[..len]);
+        write_u16(buf, 2, sum);
+        len
+    }
+
+    /// Identifier and sequence number of an echo request or reply
+    pub fn echo_id_seq(&self) -> (u16, u16) {
+        (read_u16(&self.rest, 0), read_u16(&self.rest, 2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_roundtrip() {
+        let request = IcmpHeader { icmp_type: TYPE_ECHO_REQUEST, code: 0, rest: [0, 1, 0, 7] };
+        let mut buf = [0u8; 32];
+        let len = request.write(&mut buf, b"ping!");
+        assert_eq!(len, HEADER_LEN + 5);
+        let (parsed, body) = IcmpHeader::parse(&buf[..len]).unwrap();
+        assert_eq!(parsed, request);
+        assert_eq!(parsed.echo_id_seq(), (1, 7));
+        assert_eq!(body, b"ping!");
+
+        buf[HEADER_LEN] ^= 0xFF;
+        assert!(IcmpHeader::parse(&buf[..len]).is_none());
+    }
+}
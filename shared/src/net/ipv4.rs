@@ -0,0 +1,119 @@
+//! IPv4 headers (RFC 791)
+//! Options are skipped on receive and never sent; fragments are not reassembled.
+
+use crate::bytes::be::{read_u16, write_u16};
+use super::{checksum, Ipv4Addr};
+
+pub const HEADER_LEN: usize = 20;
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const DEFAULT_TTL: u8 = 64;
+
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub id: u16,
+}
+
+impl Ipv4Header {
+    /// Validate a packet and split it into header and payload
+    /// Trailing Ethernet padding beyond the total length is cut off.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (packet[0] & 0x0F) as usize * 4;
+        let total_len = read_u16(packet, 2) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+        if checksum(&packet[..header_len]) != 0 {
+            return None;
+        }
+        let flags = read_u16(packet, 6);
+        if flags & FLAG_MORE_FRAGMENTS != 0 || flags & FRAGMENT_OFFSET_MASK != 0 {
+            return None;
+        }
+
+        let mut header = Ipv4Header {
+            src: Ipv4Addr::UNSPECIFIED,
+            dst: Ipv4Addr::UNSPECIFIED,
+            protocol: packet[9],
+            ttl: packet[8],
+            id: read_u16(packet, 4),
+        };
+        header.src.0.copy_from_slice(&packet[12..16]);
+        header.dst.0.copy_from_slice(&packet[16..20]);
+        Some((header, &packet[header_len..total_len]))
+    }
+
+    /// Write a 20-byte header for `payload_len` bytes of payload, returning its length
+    pub fn write(&self, buf: &mut [u8], payload_len: usize) -> usize {
+        buf[0] = 0x45; // Version 4, 5 words
+        buf[1] = 0;
+        write_u16(buf, 2, (HEADER_LEN + payload_len) as u16);
+        write_u16(buf, 4, self.id);
+        write_u16(buf, 6, FLAG_DONT_FRAGMENT);
+        buf[8] = self.ttl;
+        buf[9] = self.protocol;
+        write_u16(buf, 10, 0);
+        buf[12..16].copy_from_slice(&self.src.0);
+        buf[16..20].copy_from_slice(&self.dst.0);
+        let sum = checksum(&buf[..HEADER_LEN]);
+        write_u16(buf, 10, sum);
+        HEADER_LEN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Ipv4Header {
+        Ipv4Header {
+            src: Ipv4Addr::new(10, 0, 2, 15),
+            dst: Ipv4Addr::new(10, 0, 2, 2),
+            protocol: PROTOCOL_UDP,
+            ttl: DEFAULT_TTL,
+            id: 0x1234,
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_strips_padding() {
+        let mut packet = [0u8; 64];
+        let len = sample().write(&mut packet, 4);
+        packet[len..len + 4].copy_from_slice(b"data");
+        let (header, payload) = Ipv4Header::parse(&packet).unwrap();
+        assert_eq!(header, sample());
+        assert_eq!(payload, b"data");
+    }
+
+    #[test]
+    fn test_reject_bad_checksum() {
+        let mut packet = [0u8; HEADER_LEN];
+        sample().write(&mut packet, 0);
+        packet[8] ^= 1;
+        assert!(Ipv4Header::parse(&packet).is_none());
+    }
+
+    #[test]
+    fn test_reject_fragment_and_truncation() {
+        let mut packet = [0u8; HEADER_LEN + 8];
+        sample().write(&mut packet, 8);
+        assert!(Ipv4Header::parse(&packet[..HEADER_LEN + 7]).is_none());
+
+        write_u16(&mut packet, 6, FLAG_MORE_FRAGMENTS);
+        write_u16(&mut packet, 10, 0);
+        let sum = checksum(&packet[..HEADER_LEN]);
+        write_u16(&mut packet, 10, sum);
+        assert!(Ipv4Header::parse(&packet).is_none());
+    }
+}
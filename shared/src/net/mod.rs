@@ -0,0 +1,134 @@
+//! Network protocol formats
+//! Parsing and building of Ethernet, ARP, IPv4, ICMP and UDP headers. Pure
+//! byte manipulation with no I/O, so the kernel's network stack and host
+//! tests share the same code.
+//! Parsers return None for anything malformed; callers just drop the packet.
+
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod udp;
+
+use core::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xFF; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255; 4]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4Addr([a, b, c, d])
+    }
+
+    /// Parse dotted-quad notation ("10.0.2.15")
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut octets = [0u8; 4];
+        let mut parts = s.split('.');
+        for octet in octets.iter_mut() {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            *octet = part.parse().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Ipv4Addr(octets))
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// True if `self` and `other` share the network part selected by `netmask`
+    pub fn same_subnet(self, other: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+        self.to_u32() & netmask.to_u32() == other.to_u32() & netmask.to_u32()
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{}.{}.{}.{}", b[0], b[1], b[2], b[3])
+    }
+}
+
+/// Running one's-complement sum of big-endian 16-bit words (RFC 1071)
+pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for pair in &mut chunks {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Fold a running sum into the final Internet checksum
+pub fn checksum_finish(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Internet checksum of `data`; a buffer containing a correct checksum sums to 0
+pub fn checksum(data: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4_parse() {
+        assert_eq!(Ipv4Addr::parse("10.0.2.15"), Some(Ipv4Addr::new(10, 0, 2, 15)));
+        assert_eq!(Ipv4Addr::parse("255.255.255.255"), Some(Ipv4Addr::BROADCAST));
+        assert_eq!(Ipv4Addr::parse("10.0.2"), None);
+        assert_eq!(Ipv4Addr::parse("10.0.2.15.1"), None);
+        assert_eq!(Ipv4Addr::parse("10.0.2.256"), None);
+        assert_eq!(Ipv4Addr::parse("10..2.15"), None);
+        assert_eq!(Ipv4Addr::parse("+1.0.2.15"), None);
+    }
+
+    #[test]
+    fn test_same_subnet() {
+        let mask = Ipv4Addr::new(255, 255, 255, 0);
+        let host = Ipv4Addr::new(10, 0, 2, 15);
+        assert!(host.same_subnet(Ipv4Addr::new(10, 0, 2, 2), mask));
+        assert!(!host.same_subnet(Ipv4Addr::new(10, 0, 3, 2), mask));
+    }
+
+    #[test]
+    fn test_checksum_rfc1071_example() {
+        // Example from RFC 1071 section 3: sum is 0xddf2, checksum its complement
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+    }
+
+    #[test]
+    fn test_checksum_odd_length() {
+        assert_eq!(checksum(&[0x12, 0x34, 0x56]), !(0x1234u16 + 0x5600));
+    }
+}
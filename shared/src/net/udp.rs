@@ -0,0 +1,98 @@
+//! UDP datagrams (RFC 768)
+
+use super::ipv4::PROTOCOL_UDP;
+use crate::bytes::be::{read_u16, write_u16};
+use super::{checksum_add, checksum_finish, Ipv4Addr};
+
+pub const HEADER_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+/// Checksum over the IPv4 pseudo-header and the whole datagram
+fn datagram_checksum(src: Ipv4Addr, dst: Ipv4Addr, datagram: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, &src.0);
+    sum = checksum_add(sum, &dst.0);
+    sum += PROTOCOL_UDP as u32 + datagram.len() as u32;
+    checksum_finish(checksum_add(sum, datagram))
+}
+
+impl UdpHeader {
+    /// Validate a datagram sent from `src` to `dst` and split off its payload
+    pub fn parse(src: Ipv4Addr, dst: Ipv4Addr, datagram: &[u8]) -> Option<(Self, &[u8])> {
+        if datagram.len() < HEADER_LEN {
+            return None;
+        }
+        let len = read_u16(datagram, 4) as usize;
+        if len < HEADER_LEN || len > datagram.len() {
+            return None;
+        }
+        // A zero checksum means the sender didn't compute one
+        if read_u16(datagram, 6) != 0 && datagram_checksum(src, dst, &datagram[..len]) != 0 {
+            return None;
+        }
+        let header = UdpHeader {
+            src_port: read_u16(datagram, 0),
+            dst_port: read_u16(datagram, 2),
+        };
+        Some((header, &datagram[HEADER_LEN..len]))
+    }
+
+    /// Write the header and `payload`, returning the datagram length
+    pub fn write(&self, buf: &mut [u8], src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> usize {
+        let len = HEADER_LEN + payload.len();
+        write_u16(buf, 0, self.src_port);
+        write_u16(buf, 2, self.dst_port);
+        write_u16(buf, 4, len as u16);
+        write_u16(buf, 6, 0);
+        buf[HEADER_LEN..len].copy_from_slice(payload);
+        // A computed zero is sent as all ones, since zero means "no checksum"
+        let sum = match datagram_checksum(src, dst, &buf[..len]) {
+            0 => 0xFFFF,
+            sum => sum,
+        };
+        write_u16(buf, 6, sum);
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+    #[test]
+    fn test_roundtrip() {
+        let header = UdpHeader { src_port: 49152, dst_port: 7 };
+        let mut buf = [0u8; 32];
+        let len = header.write(&mut buf, SRC, DST, b"hello");
+        let (parsed, payload) = UdpHeader::parse(SRC, DST, &buf[..len]).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(payload, b"hello");
+
+        // The pseudo-header covers the addresses
+        assert!(UdpHeader::parse(SRC, Ipv4Addr::new(10, 0, 2, 3), &buf[..len]).is_none());
+    }
+
+    #[test]
+    fn test_zero_checksum_accepted() {
+        let mut buf = [0u8; 16];
+        let len = UdpHeader { src_port: 1, dst_port: 2 }.write(&mut buf, SRC, DST, b"x");
+        write_u16(&mut buf, 6, 0);
+        assert!(UdpHeader::parse(SRC, DST, &buf[..len]).is_some());
+    }
+
+    #[test]
+    fn test_reject_bad_length() {
+        let mut buf = [0u8; 16];
+        let len = UdpHeader { src_port: 1, dst_port: 2 }.write(&mut buf, SRC, DST, b"xyz");
+        assert!(UdpHeader::parse(SRC, DST, &buf[..len - 1]).is_none());
+        write_u16(&mut buf, 4, 4);
+        assert!(UdpHeader::parse(SRC, DST, &buf[..len]).is_none());
+    }
+}
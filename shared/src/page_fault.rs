@@ -0,0 +1,94 @@
+//! x86 page fault error codes
+//! The CPU pushes a bit set describing the access that faulted: whether the
+//! page was there at all, read or write, user or kernel, and a few rarer
+//! causes. `PageFaultError` names those bits and prints them as a phrase
+//! like "user write to a non-present page".
+
+use core::fmt;
+
+/// The error code of a page fault (vector 14)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFaultError(pub u64);
+
+impl PageFaultError {
+    pub const PRESENT: u64 = 1 << 0;
+    pub const WRITE: u64 = 1 << 1;
+    pub const USER: u64 = 1 << 2;
+    pub const RESERVED_BIT: u64 = 1 << 3;
+    pub const INSTRUCTION_FETCH: u64 = 1 << 4;
+    pub const PROTECTION_KEY: u64 = 1 << 5;
+    pub const SHADOW_STACK: u64 = 1 << 6;
+
+    /// The page was mapped, so the access broke its permissions
+    pub fn present(self) -> bool {
+        self.0 & Self::PRESENT != 0
+    }
+
+    pub fn write(self) -> bool {
+        self.0 & Self::WRITE != 0
+    }
+
+    /// The access came from ring 3
+    pub fn user(self) -> bool {
+        self.0 & Self::USER != 0
+    }
+
+    /// A page table entry had a reserved bit set
+    pub fn reserved_bit(self) -> bool {
+        self.0 & Self::RESERVED_BIT != 0
+    }
+
+    pub fn instruction_fetch(self) -> bool {
+        self.0 & Self::INSTRUCTION_FETCH != 0
+    }
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.user() { "user" } else { "kernel" };
+        let access = if self.instruction_fetch() {
+            "instruction fetch from"
+        } else if self.write() {
+            "write to"
+        } else {
+            "read from"
+        };
+        let page = if self.present() { "a protected page" } else { "a non-present page" };
+        write!(f, "{} {} {}", mode, access, page)?;
+
+        if self.reserved_bit() {
+            f.write_str(", reserved bit set")?;
+        }
+        if self.0 & Self::PROTECTION_KEY != 0 {
+            f.write_str(", protection key")?;
+        }
+        if self.0 & Self::SHADOW_STACK != 0 {
+            f.write_str(", shadow stack")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn test_describes_access() {
+        let show = |code| format!("{}", PageFaultError(code));
+        assert_eq!(show(0), "kernel read from a non-present page");
+        assert_eq!(show(0x6), "user write to a non-present page");
+        assert_eq!(show(0x11), "kernel instruction fetch from a protected page");
+        assert_eq!(show(0x9), "kernel read from a protected page, reserved bit set");
+        assert_eq!(show(0x27), "user write to a protected page, protection key");
+    }
+
+    #[test]
+    fn test_bits() {
+        let error = PageFaultError(PageFaultError::PRESENT | PageFaultError::USER);
+        assert!(error.present() && error.user());
+        assert!(!error.write() && !error.instruction_fetch() && !error.reserved_bit());
+    }
+}
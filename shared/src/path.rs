@@ -0,0 +1,94 @@
+//! Path string helpers
+//! Pure string manipulation for '/'-separated paths, shared by the VFS and
+//! anything else that needs to split paths without allocating
+
+/// Longest file or directory name accepted by the VFS
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Iterate over the meaningful components of `path`
+/// Empty components (from "//" or a trailing '/') and "." are skipped;
+/// ".." is passed through for the caller to resolve.
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+/// True if `path` starts at the root
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with('/')
+}
+
+/// Split `path` into its parent directory and final component
+/// Returns None for paths without a final component such as "/" or "".
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return None;
+    }
+    match trimmed.rfind('/') {
+        Some(0) => Some(("/", &trimmed[1..])),
+        Some(index) => Some((&trimmed[..index], &trimmed[index + 1..])),
+        None => Some(("", trimmed)),
+    }
+}
+
+/// True if `name` can be used as a single directory entry name
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.contains('\0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_components_skip_empty_and_dot() {
+        let mut parts = components("//usr/./bin//ls/");
+        assert_eq!(parts.next(), Some("usr"));
+        assert_eq!(parts.next(), Some("bin"));
+        assert_eq!(parts.next(), Some("ls"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn test_components_keep_dotdot() {
+        let mut parts = components("/a/../b");
+        assert_eq!(parts.next(), Some("a"));
+        assert_eq!(parts.next(), Some(".."));
+        assert_eq!(parts.next(), Some("b"));
+        assert_eq!(parts.next(), None);
+    }
+
+    #[test]
+    fn test_components_root() {
+        assert_eq!(components("/").next(), None);
+    }
+
+    #[test]
+    fn test_is_absolute() {
+        assert!(is_absolute("/etc"));
+        assert!(!is_absolute("etc"));
+    }
+
+    #[test]
+    fn test_split_parent() {
+        assert_eq!(split_parent("/etc/motd"), Some(("/etc", "motd")));
+        assert_eq!(split_parent("/motd"), Some(("/", "motd")));
+        assert_eq!(split_parent("/etc/"), Some(("/", "etc")));
+        assert_eq!(split_parent("motd"), Some(("", "motd")));
+        assert_eq!(split_parent("/"), None);
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("hello.txt"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("."));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+    }
+}
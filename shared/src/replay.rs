@@ -0,0 +1,233 @@
+//! Keyboard replay scripts
+//! A replay script spells out key presses as text, one step per line, and
+//! `encode` turns it into the scan codes (set 1) a PS/2 keyboard would send,
+//! so a replayed session goes through the same decoding as typing does:
+//!
+//! ```text
+//! # Blank lines and lines starting with '#' are skipped
+//! type ls /disk        each character, with Shift or AltGr held where the layout needs it
+//! key Enter            one key, pressed and released
+//! key Ctrl+Shift+Tab   modifiers (Ctrl, Shift, Alt) held around the key
+//! scancodes e0 48 e0 c8   raw bytes in hex
+//! ```
+//!
+//! Lines are trimmed, so spaces at either end of a `type` line are not
+//! typed; use `key Space` for those. A single letter after `key` names the
+//! key rather than the character, so `key Ctrl+C` does not add Shift.
+//! Characters are found in the `Keymap` the keyboard is decoded with, so
+//! the replayed bytes type the same text whatever the layout.
+
+use crate::keyboard::{KeyCode, ScanKey};
+use crate::keymap::{Keymap, Level};
+use crate::shell::parser::split_word;
+use crate::shell::script::script_lines;
+
+/// Keys `key` knows by name; anything else must be a single character
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Enter", KeyCode::Enter),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Escape", KeyCode::Escape),
+    ("Space", KeyCode::Char(' ')),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("CapsLock", KeyCode::CapsLock),
+    ("NumLock", KeyCode::NumLock),
+    ("ScrollLock", KeyCode::ScrollLock),
+    ("F1", KeyCode::F(1)),
+    ("F2", KeyCode::F(2)),
+    ("F3", KeyCode::F(3)),
+    ("F4", KeyCode::F(4)),
+    ("F5", KeyCode::F(5)),
+    ("F6", KeyCode::F(6)),
+    ("F7", KeyCode::F(7)),
+    ("F8", KeyCode::F(8)),
+    ("F9", KeyCode::F(9)),
+    ("F10", KeyCode::F(10)),
+    ("F11", KeyCode::F(11)),
+    ("F12", KeyCode::F(12)),
+];
+
+const MODIFIERS: &[(&str, KeyCode)] = &[("Ctrl", KeyCode::Ctrl), ("Shift", KeyCode::Shift), ("Alt", KeyCode::Alt)];
+
+/// Most modifiers one `key` step can hold
+const MAX_MODIFIERS: usize = 3;
+
+/// Pass the scan codes `script` describes to `emit`, in order
+/// On error, returns the 1-based line number and what is wrong with it;
+/// the lines before it have been emitted.
+pub fn encode(script: &str, keymap: &Keymap, emit: &mut dyn FnMut(u8)) -> Result<(), (usize, &'static str)> {
+    for (number, line) in script_lines(script) {
+        let (step, rest) = split_word(line);
+        let encoded = match step {
+            "type" => type_text(rest, keymap, emit),
+            "key" => key_chord(rest, keymap, emit),
+            "scancodes" => scan_codes(rest, emit),
+            _ => Err("unknown step (expected type, key, or scancodes)"),
+        };
+        encoded.map_err(|e| (number, e))?;
+    }
+    Ok(())
+}
+
+/// Press and release `key`, holding Shift or AltGr if it needs it
+fn tap(key: ScanKey, keymap: &Keymap, emit: &mut dyn FnMut(u8)) {
+    let modifier = match key.level {
+        Level::Plain => None,
+        Level::Shift => ScanKey::for_code(KeyCode::Shift, keymap),
+        Level::AltGr => Some(ScanKey::ALTGR),
+    };
+    if let Some(modifier) = modifier {
+        modifier.emit(true, emit);
+    }
+    key.emit(true, emit);
+    key.emit(false, emit);
+    if let Some(modifier) = modifier {
+        modifier.emit(false, emit);
+    }
+}
+
+fn type_text(text: &str, keymap: &Keymap, emit: &mut dyn FnMut(u8)) -> Result<(), &'static str> {
+    // Check every character first, so a bad one types nothing
+    let keys = || text.chars().map(|c| ScanKey::for_code(KeyCode::Char(c), keymap));
+    if keys().any(|key| key.is_none()) {
+        return Err("text has a character the keyboard cannot type");
+    }
+    keys().flatten().for_each(|key| tap(key, keymap, emit));
+    Ok(())
+}
+
+fn key_chord(chord: &str, keymap: &Keymap, emit: &mut dyn FnMut(u8)) -> Result<(), &'static str> {
+    let mut parts = chord.split('+');
+    let name = parts.next_back().filter(|name| !name.is_empty()).ok_or("key needs a key name")?;
+
+    let mut held = [None; MAX_MODIFIERS];
+    for (slot, part) in parts.enumerate() {
+        let &(_, code) = MODIFIERS
+            .iter()
+            .find(|(modifier, _)| modifier.eq_ignore_ascii_case(part))
+            .ok_or("unknown modifier (expected Ctrl, Shift, or Alt)")?;
+        *held.get_mut(slot).ok_or("too many modifiers")? = ScanKey::for_code(code, keymap);
+    }
+
+    let code = match KEY_NAMES.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)) {
+        Some(&(_, code)) => code,
+        None => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c.to_ascii_lowercase()),
+                _ => return Err("unknown key name"),
+            }
+        }
+    };
+    let key = ScanKey::for_code(code, keymap).ok_or("key is not on the keyboard")?;
+
+    held.iter().flatten().for_each(|modifier| modifier.emit(true, emit));
+    tap(key, keymap, emit);
+    held.iter().rev().flatten().for_each(|modifier| modifier.emit(false, emit));
+    Ok(())
+}
+
+fn scan_codes(bytes: &str, emit: &mut dyn FnMut(u8)) -> Result<(), &'static str> {
+    let parse = |word| u8::from_str_radix(word, 16).map_err(|_| "scan codes must be hex bytes");
+    // As with `type`, a bad byte anywhere emits nothing
+    for word in bytes.split_whitespace() {
+        parse(word)?;
+    }
+    for word in bytes.split_whitespace() {
+        emit(parse(word)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::keyboard::{Decoder, KeyEvent};
+    use crate::keymap;
+    use std::vec::Vec;
+
+    fn encoded(script: &str) -> Result<Vec<u8>, (usize, &'static str)> {
+        let mut bytes = Vec::new();
+        encode(script, &keymap::US, &mut |byte| bytes.push(byte))?;
+        Ok(bytes)
+    }
+
+    /// The key presses a script decodes to
+    fn presses(script: &str) -> Vec<KeyEvent> {
+        let mut decoder = Decoder::new();
+        let bytes = encoded(script).unwrap();
+        bytes.iter().filter_map(|&byte| decoder.feed(byte)).filter(|event| event.pressed).collect()
+    }
+
+    fn typed(script: &str) -> std::string::String {
+        presses(script).iter().filter_map(|event| event.to_ascii()).map(char::from).collect()
+    }
+
+    #[test]
+    fn test_type_and_keys() {
+        let script = "# list the disk\ntype ls /disk\nkey Enter\n\ntype Echo \"Hi!\"\nkey enter\n";
+        assert_eq!(typed(script), "ls /disk\nEcho \"Hi!\"\n");
+    }
+
+    #[test]
+    fn test_shift_released_after_each_character() {
+        // Shift press, 'a' press and release, Shift release
+        assert_eq!(encoded("type A").unwrap(), [0x2A, 0x1E, 0x9E, 0xAA]);
+        assert_eq!(typed("type Ab"), "Ab");
+    }
+
+    #[test]
+    fn test_chords() {
+        let ctrl_c = presses("key Ctrl+C");
+        let last = ctrl_c.last().unwrap();
+        assert_eq!(last.ctrl_letter(), Some('c'));
+        assert!(!last.modifiers.shift);
+        assert_eq!(encoded("key Up").unwrap(), [0xE0, 0x48, 0xE0, 0xC8]);
+        assert_eq!(typed("key Space\nkey Shift+1"), " !");
+        assert_eq!(encoded("key Alt+F2").unwrap(), [0x38, 0x3C, 0xBC, 0xB8]);
+        // Modifiers come up in reverse order, leaving nothing held
+        let mut decoder = Decoder::new();
+        encoded("key Ctrl+Alt+Delete").unwrap().iter().for_each(|&byte| {
+            decoder.feed(byte);
+        });
+        assert_eq!(decoder.modifiers(), Default::default());
+    }
+
+    #[test]
+    fn test_follows_the_layout() {
+        let mut bytes = Vec::new();
+        encode("type z@", &keymap::DE, &mut |byte| bytes.push(byte)).unwrap();
+        // 'z' is where US has 'y', and '@' is AltGr+Q
+        assert_eq!(bytes, [0x15, 0x95, 0xE0, 0x38, 0x10, 0x90, 0xE0, 0xB8]);
+        let mut decoder = Decoder::new();
+        decoder.set_keymap(&keymap::DE);
+        let text: std::string::String = bytes.iter().filter_map(|&byte| decoder.feed(byte)?.to_ascii()).map(char::from).collect();
+        assert_eq!(text, "z@");
+    }
+
+    #[test]
+    fn test_raw_scan_codes() {
+        assert_eq!(encoded("scancodes 1e 9E\nscancodes").unwrap(), [0x1E, 0x9E]);
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(encoded("type ok\nfrobnicate"), Err((2, "unknown step (expected type, key, or scancodes)")));
+        assert_eq!(encoded("\nkey Hyper+A"), Err((2, "unknown modifier (expected Ctrl, Shift, or Alt)")));
+        assert_eq!(encoded("key Return"), Err((1, "unknown key name")));
+        assert_eq!(encoded("key Ctrl+"), Err((1, "key needs a key name")));
+        assert_eq!(encoded("key Ctrl+Ctrl+Ctrl+Ctrl+a"), Err((1, "too many modifiers")));
+        assert_eq!(encoded("scancodes 1e zz"), Err((1, "scan codes must be hex bytes")));
+        assert_eq!(encoded("type café"), Err((1, "text has a character the keyboard cannot type")));
+    }
+}
@@ -0,0 +1,155 @@
+//! Serial port settings
+//! The PC's four COM ports and the line settings of a 16550 UART, written
+//! as on the Linux command line: `115200` or `9600,7e1` (baud rate, then
+//! data bits, parity and stop bits). The UART divides a 115200 Hz clock, so
+//! the baud rate must divide it evenly.
+
+use core::fmt;
+
+/// I/O port base and IRQ line of COM1 to COM4
+pub const PORTS: [(u16, u8); 4] = [(0x3F8, 4), (0x2F8, 3), (0x3E8, 4), (0x2E8, 3)];
+
+/// The UART clock divided by the divisor latch
+const BASE_BAUD: u32 = 115_200;
+
+/// Line control register bits
+const LCR_TWO_STOP_BITS: u8 = 0x04;
+const LCR_PARITY_ENABLE: u8 = 0x08;
+const LCR_EVEN_PARITY: u8 = 0x10;
+const LCR_STICK_PARITY: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// Always 1
+    Mark,
+    /// Always 0
+    Space,
+}
+
+impl Parity {
+    fn letter(self) -> char {
+        match self {
+            Parity::None => 'n',
+            Parity::Odd => 'o',
+            Parity::Even => 'e',
+            Parity::Mark => 'm',
+            Parity::Space => 's',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+}
+
+impl LineSettings {
+    /// 38400 baud, 8 data bits, no parity, one stop bit
+    pub const DEFAULT: LineSettings = LineSettings { baud: 38400, data_bits: 8, parity: Parity::None, stop_bits: 1 };
+
+    /// `BAUD` or `BAUD,DPS` (e.g. `115200,8n1`)
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let (baud, framing) = text.split_once(',').unwrap_or((text, "8n1"));
+        let baud: u32 = baud.parse().map_err(|_| "Baud rate must be a number")?;
+        if !BASE_BAUD.is_multiple_of(baud) {
+            return Err("Baud rate must divide 115200");
+        }
+        let &[data, parity, stop] = framing.as_bytes() else {
+            return Err("Framing must be data bits, parity and stop bits, like 8n1");
+        };
+        let data_bits = match data {
+            b'5'..=b'8' => data - b'0',
+            _ => return Err("Data bits must be 5 to 8"),
+        };
+        let parity = match parity.to_ascii_lowercase() {
+            b'n' => Parity::None,
+            b'o' => Parity::Odd,
+            b'e' => Parity::Even,
+            b'm' => Parity::Mark,
+            b's' => Parity::Space,
+            _ => return Err("Parity must be n, o, e, m or s"),
+        };
+        let stop_bits = match stop {
+            b'1' | b'2' => stop - b'0',
+            _ => return Err("Stop bits must be 1 or 2"),
+        };
+        Ok(LineSettings { baud, data_bits, parity, stop_bits })
+    }
+
+    /// Value for the divisor latch
+    pub fn divisor(&self) -> u16 {
+        (BASE_BAUD / self.baud) as u16
+    }
+
+    /// Value for the line control register, divisor latch access off
+    pub fn line_control(&self) -> u8 {
+        let mut bits = self.data_bits - 5;
+        if self.stop_bits == 2 {
+            bits |= LCR_TWO_STOP_BITS;
+        }
+        bits | match self.parity {
+            Parity::None => 0,
+            Parity::Odd => LCR_PARITY_ENABLE,
+            Parity::Even => LCR_PARITY_ENABLE | LCR_EVEN_PARITY,
+            Parity::Mark => LCR_PARITY_ENABLE | LCR_STICK_PARITY,
+            Parity::Space => LCR_PARITY_ENABLE | LCR_EVEN_PARITY | LCR_STICK_PARITY,
+        }
+    }
+}
+
+impl fmt::Display for LineSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{}{}{}", self.baud, self.data_bits, self.parity.letter(), self.stop_bits)
+    }
+}
+
+/// Index into `PORTS` of `com1` to `com4`
+pub fn port_index(name: &str) -> Option<usize> {
+    let number = name.strip_prefix("com").or_else(|| name.strip_prefix("COM"))?;
+    match number {
+        "1" | "2" | "3" | "4" => Some(usize::from(number.as_bytes()[0] - b'1')),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let settings = LineSettings::parse("115200").unwrap();
+        assert_eq!(settings, LineSettings { baud: 115200, ..LineSettings::DEFAULT });
+        assert_eq!((settings.divisor(), settings.line_control()), (1, 0x03));
+        let settings = LineSettings::parse("9600,7E2").unwrap();
+        assert_eq!((settings.divisor(), settings.line_control()), (12, 0x1E));
+        assert_eq!(std::format!("{}", settings), "9600,7e2");
+        assert_eq!(LineSettings::DEFAULT.divisor(), 3);
+
+        assert_eq!(LineSettings::parse("fast"), Err("Baud rate must be a number"));
+        assert_eq!(LineSettings::parse("1000"), Err("Baud rate must divide 115200"));
+        assert_eq!(LineSettings::parse("0"), Err("Baud rate must divide 115200"));
+        assert_eq!(LineSettings::parse("9600,9n1"), Err("Data bits must be 5 to 8"));
+        assert_eq!(LineSettings::parse("9600,8x1"), Err("Parity must be n, o, e, m or s"));
+        assert_eq!(LineSettings::parse("9600,8n3"), Err("Stop bits must be 1 or 2"));
+        assert!(LineSettings::parse("9600,8n").is_err());
+    }
+
+    #[test]
+    fn test_port_names() {
+        assert_eq!(port_index("com1"), Some(0));
+        assert_eq!(port_index("COM4"), Some(3));
+        assert_eq!(port_index("com5"), None);
+        assert_eq!(port_index("ttyS0"), None);
+    }
+}
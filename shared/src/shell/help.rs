@@ -0,0 +1,118 @@
+//! Command registry
+//! One entry per shell command: its name (for `parse` and completion), a
+//! usage line, a one-line summary for the `help` listing, and examples for
+//! `help COMMAND`. A command added to the parser goes here too, and the
+//! tests check that the two agree.
+
+/// What `help` knows about a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandHelp {
+    pub name: &'static str,
+    /// The name followed by its arguments, `[OPTIONAL]` ones bracketed
+    pub usage: &'static str,
+    pub summary: &'static str,
+    /// Complete shell lines, pipes and redirections allowed
+    pub examples: &'static [&'static str],
+}
+
+const fn command(
+    name: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+    examples: &'static [&'static str],
+) -> CommandHelp {
+    CommandHelp { name, usage, summary, examples }
+}
+
+/// Every command `parse` accepts, in the order `help` lists them
+pub const COMMANDS: &[CommandHelp] = &[
+    command("help", "help [COMMAND]", "List commands, or show how to use one", &["help", "help ls"]),
+    command("clear", "clear", "Clear the screen", &[]),
+    command("echo", "echo TEXT", "Print text to screen", &["echo hello world", "echo hello > /greeting"]),
+    command("version", "version", "Show kernel version", &[]),
+    command("date", "date", "Show the date and time (UTC)", &[]),
+    command("random", "random [BYTES]", "Print random bytes in hex (default 16)", &["random", "random 64"]),
+    command("features", "features", "List subsystems compiled into this kernel", &[]),
+    command("hwinfo", "hwinfo", "Show the hardware found at boot", &["hwinfo", "hwinfo > /hw.txt"]),
+    command("cpuinfo", "cpuinfo", "Identify the CPU and the features the kernel checks", &[]),
+    command("meminfo", "meminfo", "Display memory information", &["meminfo | grep frames"]),
+    command("memmap", "memmap", "Show the bootloader memory map and frame usage", &[]),
+    command("framestats", "framestats", "Show frames held per subsystem", &[]),
+    command("slabinfo", "slabinfo", "Show slab cache objects and slabs", &["slabinfo | grep size-64"]),
+    command("krefs", "krefs", "Count live shared kernel objects by kind", &[]),
+    command("pagecheck", "pagecheck", "Check the active page tables for inconsistencies", &[]),
+    command("ksym", "ksym ADDR", "Name the kernel symbol at an address", &["ksym 0xffffffff80001234"]),
+    command("irqstats", "irqstats", "Show interrupt and input buffer counters", &["irqstats > /irq.txt"]),
+    command("irqstat", "irqstat", "Count exceptions and interrupts by vector", &["irqstat | grep IRQ"]),
+    command("kbdrate", "kbdrate [DELAY RATE]", "Show or set the key repeat delay (ms) and rate (keys/s)", &["kbdrate", "kbdrate 250 30"]),
+    command("setkeymap", "setkeymap [NAME]", "List keyboard layouts, or switch to one", &["setkeymap", "setkeymap de"]),
+    command("lspci", "lspci", "List PCI devices", &[]),
+    command("netinfo", "netinfo", "Show network addresses and the ARP cache", &[]),
+    command("ping", "ping IP", "Send ICMP echo requests", &["ping 10.0.2.2"]),
+    command("udpsend", "udpsend IP PORT TEXT", "Send TEXT in a UDP datagram", &["udpsend 10.0.2.2 5555 hello there"]),
+    command("udplisten", "udplisten PORT", "Print UDP datagrams until a key is pressed", &["udplisten 5555"]),
+    command("latstat", "latstat [reset]", "Show (or clear) timer interrupt latency", &["latstat", "latstat reset"]),
+    command(
+        "dmesg",
+        "dmesg [LEVEL]",
+        "Show kernel messages (trace, debug, info, warn, error)",
+        &["dmesg", "dmesg warn", "dmesg | grep fat32"],
+    ),
+    command("ls", "ls [PATH]", "List a directory", &["ls", "ls /disk"]),
+    command("cat", "cat PATH", "Print a file", &["cat /etc/motd"]),
+    command("write", "write PATH TEXT", "Replace a file's contents with TEXT", &["write /etc/motd Welcome to wflos!"]),
+    command("mkdir", "mkdir PATH", "Create a directory", &["mkdir /etc"]),
+    command("rm", "rm PATH", "Remove a file or empty directory", &["rm /etc/motd"]),
+    command("sync", "sync", "Write cached disk blocks back to their devices", &[]),
+    command("exec", "exec NAME|PATH", "Run a boot module or program file", &["exec", "exec hello.bin", "exec /disk/bin/hello"]),
+    command("ports", "ports", "List IPC message ports", &[]),
+    command("grep", "grep TEXT", "Show input lines containing TEXT (after '|')", &["dmesg | grep error"]),
+    command(
+        "run",
+        "run [-k] PATH",
+        "Run the commands in a script (-k: keep going after errors)",
+        &["run /etc/rc", "run -k /setup.rc"],
+    ),
+    command("replay", "replay PATH", "Type the keys in a replay script", &["replay /disk/tests/ls.keys"]),
+    command("shutdown", "shutdown", "Sync disks and power off", &[]),
+    command("reboot", "reboot", "Sync disks and restart the machine", &[]),
+    command("halt", "halt", "Halt the system", &[]),
+];
+
+/// The registry entry for `name`
+pub fn find(name: &str) -> Option<&'static CommandHelp> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::parser::{parse, split_word};
+    use crate::shell::parse_pipeline;
+
+    #[test]
+    fn test_registry_matches_parser() {
+        for command in COMMANDS {
+            assert!(parse(command.name).is_ok(), "{} is not parsed", command.name);
+            assert_eq!(split_word(command.usage).0, command.name, "usage of {} names another command", command.name);
+        }
+    }
+
+    #[test]
+    fn test_examples_parse() {
+        for command in COMMANDS {
+            for example in command.examples {
+                assert!(parse_pipeline(example).is_ok(), "example '{}' does not parse", example);
+                let uses = example.split(['|', '>']).any(|stage| split_word(stage).0 == command.name);
+                assert!(uses, "example '{}' does not run {}", example, command.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("ls").map(|command| command.usage), Some("ls [PATH]"));
+        assert!(find("nonsense").is_none());
+        assert!(find("").is_none());
+    }
+}
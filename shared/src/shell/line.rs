@@ -0,0 +1,689 @@
+//! Line editor
+//! Collects key presses into a fixed-size line and writes the terminal echo
+//! for each one, so the kernel shell only moves keys in and text out.
+//!
+//! The echo assumes only that printing a character advances the terminal
+//! cursor and that '\x08' moves it one cell left without erasing, so it works
+//! the same on the VGA console and a serial terminal.
+
+use core::fmt::Write;
+
+use super::help::COMMANDS;
+use crate::keyboard::{KeyCode, KeyEvent};
+use crate::path;
+
+/// What a key did to the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEvent {
+    /// Still editing
+    Pending,
+    /// Enter: `line()` holds the finished line
+    Submit,
+    /// Ctrl+C: the line was abandoned and cleared
+    Cancel,
+    /// Tab: the caller should run `complete`
+    Complete,
+}
+
+/// Source of path completions
+pub trait Completer {
+    /// Call `each(name, is_dir)` for every entry of the directory `dir`
+    /// `dir` is absolute and ends in '/'. Unreadable directories list nothing.
+    fn list_dir(&mut self, dir: &str, each: &mut dyn FnMut(&str, bool));
+}
+
+/// Matches seen so far during one completion
+struct Matches<const N: usize> {
+    common: [u8; N],
+    common_len: usize,
+    count: usize,
+    is_dir: bool,
+}
+
+impl<const N: usize> Matches<N> {
+    fn add(&mut self, name: &str, is_dir: bool) {
+        if self.count == 0 {
+            self.common_len = name.len().min(N);
+            self.common[..self.common_len].copy_from_slice(&name.as_bytes()[..self.common_len]);
+            self.is_dir = is_dir;
+        } else {
+            self.common_len = self.common[..self.common_len]
+                .iter()
+                .zip(name.bytes())
+                .take_while(|(a, b)| **a == *b)
+                .count();
+        }
+        self.count += 1;
+    }
+}
+
+/// Editing state for one line of at most `N` printable ASCII characters
+pub struct LineEditor<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// Insertion point, 0..=len
+    cursor: usize,
+}
+
+impl<const N: usize> Default for LineEditor<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LineEditor<N> {
+    pub const fn new() -> Self {
+        LineEditor { buf: [0; N], len: 0, cursor: 0 }
+    }
+
+    /// The line typed so far
+    pub fn line(&self) -> &str {
+        // Only printable ASCII is ever stored
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Insertion point within `line()`
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.cursor = 0;
+    }
+
+    /// Print the whole line and put the terminal cursor back at the insertion
+    /// point, after the caller has reprinted the prompt
+    pub fn redraw(&self, out: &mut impl Write) {
+        let _ = out.write_str(self.line());
+        back(out, self.len - self.cursor);
+    }
+
+    /// Complete the word before the cursor
+    /// A command word completes against `COMMANDS` and other words starting
+    /// with '/' against `completer`. A unique match is inserted with a
+    /// trailing space, or '/' for a directory; several matches are extended
+    /// to their common prefix, and listed on a new line when that adds
+    /// nothing. Returns true after listing: the caller must redraw the prompt
+    /// and the line.
+    pub fn complete(&mut self, completer: &mut impl Completer, out: &mut impl Write) -> bool {
+        let before = &self.line()[..self.cursor];
+        let word_start = before.rfind([' ', '|']).map_or(0, |i| i + 1);
+        let word = &before[word_start..];
+        // Commands start the line and follow each '|'
+        let leading = before[..word_start].trim_end();
+        let is_command = leading.is_empty() || leading.ends_with('|');
+        if !is_command && !path::is_absolute(word) {
+            return false;
+        }
+        let name_start = word_start + word.rfind('/').map_or(0, |i| i + 1);
+        let prefix_len = self.cursor - name_start;
+
+        let mut matches = Matches::<N> { common: [0; N], common_len: 0, count: 0, is_dir: false };
+        self.for_each_match(completer, is_command, word_start, name_start, &mut |name, is_dir| {
+            matches.add(name, is_dir)
+        });
+
+        if matches.count == 0 {
+            return false;
+        }
+        for &byte in &matches.common[prefix_len..matches.common_len] {
+            self.insert(byte as char, out);
+        }
+        if matches.count == 1 {
+            self.insert(if matches.is_dir { '/' } else { ' ' }, out);
+            return false;
+        }
+        if matches.common_len > prefix_len {
+            return false;
+        }
+
+        let _ = out.write_str("\n");
+        self.for_each_match(completer, is_command, word_start, name_start, &mut |name, is_dir| {
+            let _ = write!(out, "{}{}  ", name, if is_dir { "/" } else { "" });
+        });
+        let _ = out.write_str("\n");
+        true
+    }
+
+    /// Call `each` for every candidate that extends the word being completed
+    /// Names that could not be typed into the line are skipped.
+    fn for_each_match(
+        &self,
+        completer: &mut impl Completer,
+        is_command: bool,
+        word_start: usize,
+        name_start: usize,
+        each: &mut dyn FnMut(&str, bool),
+    ) {
+        let line = self.line();
+        let prefix = &line[name_start..self.cursor];
+        let mut filter = |name: &str, is_dir: bool| {
+            if name.starts_with(prefix) && name.bytes().all(|b| b.is_ascii_graphic()) {
+                each(name, is_dir);
+            }
+        };
+        if is_command {
+            for command in COMMANDS {
+                filter(command.name, false);
+            }
+        } else {
+            completer.list_dir(&line[word_start..name_start], &mut filter);
+        }
+    }
+
+    /// Insert a printable character at the cursor, if there is room
+    fn insert(&mut self, c: char, out: &mut impl Write) {
+        if self.len < N {
+            self.buf.copy_within(self.cursor..self.len, self.cursor + 1);
+            self.buf[self.cursor] = c as u8;
+            self.len += 1;
+            self.cursor += 1;
+            let _ = out.write_char(c);
+            self.redraw_tail(0, out);
+        }
+    }
+
+    /// Remove `count` characters starting at `start`, which must not be
+    /// after the cursor; the terminal cursor must already be at `start`
+    fn remove(&mut self, start: usize, count: usize, out: &mut impl Write) {
+        self.buf.copy_within(start + count..self.len, start);
+        self.len -= count;
+        self.cursor = start;
+        self.redraw_tail(count, out);
+    }
+
+    /// Reprint the line after the cursor, blank the `erased` cells that
+    /// follow it, and return the terminal cursor to the insertion point
+    fn redraw_tail(&self, erased: usize, out: &mut impl Write) {
+        let _ = out.write_str(&self.line()[self.cursor..]);
+        for _ in 0..erased {
+            let _ = out.write_char(' ');
+        }
+        back(out, self.len - self.cursor + erased);
+    }
+
+    fn move_to_start(&mut self, out: &mut impl Write) {
+        back(out, self.cursor);
+        self.cursor = 0;
+    }
+
+    fn move_to_end(&mut self, out: &mut impl Write) {
+        let _ = out.write_str(&self.line()[self.cursor..]);
+        self.cursor = self.len;
+    }
+
+    /// Apply one key, writing its echo to `out`
+    /// Releases and keys without a line-editing meaning are ignored. Echo
+    /// errors are dropped: a lost echo must not lose the keystroke.
+    pub fn feed(&mut self, key: KeyEvent, out: &mut impl Write) -> LineEvent {
+        if !key.pressed {
+            return LineEvent::Pending;
+        }
+        match (key.code, key.ctrl_letter()) {
+            (KeyCode::Enter, _) => {
+                let _ = out.write_str("\n");
+                return LineEvent::Submit;
+            }
+            (KeyCode::Backspace, _) if self.cursor > 0 => {
+                back(out, 1);
+                self.remove(self.cursor - 1, 1, out);
+            }
+            (KeyCode::Delete, _) if self.cursor < self.len => self.remove(self.cursor, 1, out),
+            (KeyCode::Left, _) if self.cursor > 0 => {
+                back(out, 1);
+                self.cursor -= 1;
+            }
+            (KeyCode::Right, _) if self.cursor < self.len => {
+                let _ = out.write_char(self.buf[self.cursor] as char);
+                self.cursor += 1;
+            }
+            (KeyCode::Home, _) | (_, Some('a')) => self.move_to_start(out),
+            (KeyCode::End, _) | (_, Some('e')) => self.move_to_end(out),
+            (KeyCode::Escape, _) => {
+                // ESC - clear line
+                self.move_to_end(out);
+                while self.len > 0 {
+                    let _ = out.write_str("\x08 \x08");
+                    self.len -= 1;
+                }
+                self.cursor = 0;
+            }
+            (_, Some('u')) => {
+                // Ctrl+U - kill from the start of the line to the cursor
+                let count = self.cursor;
+                self.move_to_start(out);
+                self.remove(0, count, out);
+            }
+            (_, Some('k')) => {
+                // Ctrl+K - kill from the cursor to the end of the line
+                self.remove(self.cursor, self.len - self.cursor, out);
+            }
+            (KeyCode::Tab, _) => return LineEvent::Complete,
+            (_, Some('c')) => {
+                // Ctrl+C - abandon the line
+                let _ = out.write_str("^C\n");
+                self.clear();
+                return LineEvent::Cancel;
+            }
+            (KeyCode::Char(c), None) if !key.modifiers.alt && (c.is_ascii_graphic() || c == ' ') => {
+                self.insert(c, out);
+            }
+            _ => {
+                // Ignore other keys (Up/Down, Alt combinations...)
+            }
+        }
+        LineEvent::Pending
+    }
+}
+
+/// Move the terminal cursor `count` cells left
+fn back(out: &mut impl Write, count: usize) {
+    for _ in 0..count {
+        let _ = out.write_char('\x08');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootfmt::BootBuffer;
+    use crate::keyboard::{Decoder, Modifiers, TerminalDecoder};
+    use crate::shell::parse;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::press(code, Modifiers::default())
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::press(KeyCode::Char(c), Modifiers { ctrl: true, ..Modifiers::default() })
+    }
+
+    fn type_str<const N: usize>(editor: &mut LineEditor<N>, text: &str, out: &mut BootBuffer<256>) {
+        for c in text.chars() {
+            assert_eq!(editor.feed(press(KeyCode::Char(c)), out), LineEvent::Pending);
+        }
+    }
+
+    #[test]
+    fn test_type_and_submit() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "ls /", &mut out);
+        assert_eq!(editor.feed(press(KeyCode::Enter), &mut out), LineEvent::Submit);
+        assert_eq!(editor.line(), "ls /");
+        assert_eq!(out.as_str(), "ls /\n");
+    }
+
+    #[test]
+    fn test_backspace() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        // Backspace on an empty line echoes nothing
+        editor.feed(press(KeyCode::Backspace), &mut out);
+        type_str(&mut editor, "ab", &mut out);
+        editor.feed(press(KeyCode::Backspace), &mut out);
+        assert_eq!(editor.line(), "a");
+        assert_eq!(out.as_str(), "ab\x08 \x08");
+    }
+
+    #[test]
+    fn test_clear_line_keys() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "xy", &mut out);
+        editor.feed(ctrl('u'), &mut out);
+        assert_eq!(editor.line(), "");
+        assert_eq!(out.as_str(), "xy\x08\x08  \x08\x08");
+
+        out.clear();
+        type_str(&mut editor, "z", &mut out);
+        editor.feed(press(KeyCode::Escape), &mut out);
+        assert_eq!(editor.line(), "");
+        assert_eq!(out.as_str(), "z\x08 \x08");
+    }
+
+    /// A one-row terminal: characters overwrite and advance, '\x08' moves
+    /// left without erasing, '\n' ends the row
+    struct Screen {
+        cells: [u8; 128],
+        col: usize,
+    }
+
+    impl Screen {
+        fn new() -> Self {
+            Screen { cells: [b' '; 128], col: 0 }
+        }
+
+        fn apply(&mut self, output: &str) {
+            for byte in output.bytes() {
+                match byte {
+                    b'\x08' => self.col = self.col.saturating_sub(1),
+                    b'\n' => *self = Screen::new(),
+                    byte => {
+                        // Completion listings may run past the edge
+                        if let Some(cell) = self.cells.get_mut(self.col) {
+                            *cell = byte;
+                        }
+                        self.col += 1;
+                    }
+                }
+            }
+        }
+
+        /// The screen shows exactly the line, with the cursor in place
+        fn assert_shows<const N: usize>(&self, editor: &LineEditor<N>) {
+            let line = editor.line().as_bytes();
+            assert_eq!(&self.cells[..line.len()], line);
+            assert!(self.cells[line.len()..].iter().all(|&b| b == b' '));
+            assert_eq!(self.col, editor.cursor());
+        }
+    }
+
+    /// Feed keys, checking the screen after each one
+    fn edit<const N: usize>(editor: &mut LineEditor<N>, screen: &mut Screen, keys: &[KeyEvent]) {
+        for &key in keys {
+            let mut out = BootBuffer::<256>::new();
+            editor.feed(key, &mut out);
+            screen.apply(out.as_str());
+            screen.assert_shows(editor);
+        }
+    }
+
+    fn key(c: char) -> KeyEvent {
+        press(KeyCode::Char(c))
+    }
+
+    #[test]
+    fn test_cursor_movement_and_insert() {
+        let mut editor = LineEditor::<16>::new();
+        let mut screen = Screen::new();
+        let left = press(KeyCode::Left);
+        edit(&mut editor, &mut screen, &[key('c'), key('a'), key('t'), left, left, key('h')]);
+        assert_eq!(editor.line(), "chat");
+        assert_eq!(editor.cursor(), 2);
+
+        edit(&mut editor, &mut screen, &[press(KeyCode::Home), key('s')]);
+        assert_eq!(editor.line(), "schat");
+        assert_eq!(editor.cursor(), 1);
+
+        edit(&mut editor, &mut screen, &[press(KeyCode::End), press(KeyCode::Right)]);
+        assert_eq!(editor.cursor(), 5);
+        edit(&mut editor, &mut screen, &[ctrl('a'), left]);
+        assert_eq!(editor.cursor(), 0);
+        edit(&mut editor, &mut screen, &[press(KeyCode::Right), ctrl('e')]);
+        assert_eq!(editor.cursor(), 5);
+    }
+
+    #[test]
+    fn test_mid_line_deletion() {
+        let mut editor = LineEditor::<16>::new();
+        let mut screen = Screen::new();
+        let left = press(KeyCode::Left);
+        edit(&mut editor, &mut screen, &[key('a'), key('b'), key('c'), key('d'), key('e'), key('f')]);
+        edit(&mut editor, &mut screen, &[left, left, press(KeyCode::Backspace)]);
+        assert_eq!(editor.line(), "abcef");
+        edit(&mut editor, &mut screen, &[press(KeyCode::Delete)]);
+        assert_eq!(editor.line(), "abcf");
+        assert_eq!(editor.cursor(), 3);
+        // Delete at the end and Backspace at the start do nothing
+        edit(&mut editor, &mut screen, &[press(KeyCode::End), press(KeyCode::Delete)]);
+        edit(&mut editor, &mut screen, &[press(KeyCode::Home), press(KeyCode::Backspace)]);
+        assert_eq!(editor.line(), "abcf");
+    }
+
+    #[test]
+    fn test_kill_operations() {
+        let mut editor = LineEditor::<32>::new();
+        let mut screen = Screen::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "echo hello world", &mut out);
+        screen.apply(out.as_str());
+
+        let lefts = [press(KeyCode::Left); 6];
+        edit(&mut editor, &mut screen, &lefts);
+        edit(&mut editor, &mut screen, &[ctrl('k')]);
+        assert_eq!(editor.line(), "echo hello");
+        assert_eq!(editor.cursor(), 10);
+
+        edit(&mut editor, &mut screen, &lefts[..5]);
+        edit(&mut editor, &mut screen, &[ctrl('u')]);
+        assert_eq!(editor.line(), "hello");
+        assert_eq!(editor.cursor(), 0);
+
+        edit(&mut editor, &mut screen, &[press(KeyCode::Right), press(KeyCode::Escape)]);
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn test_insert_into_full_line() {
+        let mut editor = LineEditor::<4>::new();
+        let mut screen = Screen::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "abcd", &mut out);
+        screen.apply(out.as_str());
+        edit(&mut editor, &mut screen, &[press(KeyCode::Home), key('x')]);
+        assert_eq!(editor.line(), "abcd");
+        assert_eq!(editor.cursor(), 0);
+    }
+
+    #[test]
+    fn test_redraw() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "ls /b", &mut out);
+        editor.feed(press(KeyCode::Left), &mut out);
+        let mut screen = Screen::new();
+        out.clear();
+        editor.redraw(&mut out);
+        screen.apply(out.as_str());
+        screen.assert_shows(&editor);
+    }
+
+    #[test]
+    fn test_ctrl_c_cancels() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "halt", &mut out);
+        assert_eq!(editor.feed(ctrl('c'), &mut out), LineEvent::Cancel);
+        assert_eq!(editor.line(), "");
+        assert_eq!(out.as_str(), "halt^C\n");
+    }
+
+    #[test]
+    fn test_full_line_drops_input() {
+        let mut editor = LineEditor::<4>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "abcdef", &mut out);
+        assert_eq!(editor.line(), "abcd");
+        assert_eq!(out.as_str(), "abcd");
+    }
+
+    #[test]
+    fn test_ignores_releases_and_other_keys() {
+        let mut editor = LineEditor::<16>::new();
+        let mut out = BootBuffer::<256>::new();
+        let mut release = press(KeyCode::Char('a'));
+        release.pressed = false;
+        editor.feed(release, &mut out);
+        editor.feed(press(KeyCode::Left), &mut out);
+        editor.feed(press(KeyCode::Char('é')), &mut out);
+        let alt = KeyEvent::press(KeyCode::Char('x'), Modifiers { alt: true, ..Modifiers::default() });
+        editor.feed(alt, &mut out);
+        assert_eq!(editor.line(), "");
+        assert!(out.is_empty());
+    }
+
+    /// A fixed tree: /bin/{sh,shell}, /boot/, /etc/motd
+    struct FakeFs;
+
+    impl Completer for FakeFs {
+        fn list_dir(&mut self, dir: &str, each: &mut dyn FnMut(&str, bool)) {
+            let entries: &[(&str, bool)] = match dir {
+                "/" => &[("bin", true), ("boot", true), ("etc", true)],
+                "/bin/" => &[("sh", false), ("shell", false), ("bad name", false)],
+                "/etc/" => &[("motd", false)],
+                _ => &[],
+            };
+            for &(name, is_dir) in entries {
+                each(name, is_dir);
+            }
+        }
+    }
+
+    fn complete(text: &str) -> (BootBuffer<256>, bool, BootBuffer<256>) {
+        let mut editor = LineEditor::<32>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, text, &mut out);
+        assert_eq!(editor.feed(press(KeyCode::Tab), &mut out), LineEvent::Complete);
+        out.clear();
+        let listed = editor.complete(&mut FakeFs, &mut out);
+        let mut line = BootBuffer::<256>::new();
+        line.write_str(editor.line()).unwrap();
+        (line, listed, out)
+    }
+
+    #[test]
+    fn test_complete_unique_command() {
+        let (line, listed, out) = complete("ver");
+        assert_eq!(line.as_str(), "version ");
+        assert!(!listed);
+        assert_eq!(out.as_str(), "sion ");
+    }
+
+    #[test]
+    fn test_complete_common_prefix_then_list() {
+        let (line, listed, _) = complete("me");
+        assert_eq!(line.as_str(), "mem");
+        assert!(!listed);
+
+        let (line, listed, out) = complete("mem");
+        assert_eq!(line.as_str(), "mem");
+        assert!(listed);
+        assert_eq!(out.as_str(), "\nmeminfo  memmap  \n");
+    }
+
+    #[test]
+    fn test_complete_after_pipe() {
+        assert_eq!(complete("meminfo | gr").0.as_str(), "meminfo | grep ");
+        assert_eq!(complete("meminfo |gr").0.as_str(), "meminfo |grep ");
+    }
+
+    #[test]
+    fn test_complete_no_match() {
+        let (line, listed, out) = complete("xyz");
+        assert_eq!(line.as_str(), "xyz");
+        assert!(!listed);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_complete_paths() {
+        assert_eq!(complete("cat /e").0.as_str(), "cat /etc/");
+        assert_eq!(complete("cat /etc/m").0.as_str(), "cat /etc/motd ");
+        assert_eq!(complete("exec /bin/").0.as_str(), "exec /bin/sh");
+        // Untypeable names are never offered
+        let (_, listed, out) = complete("exec /bin/sh");
+        assert!(listed);
+        assert_eq!(out.as_str(), "\nsh  shell  \n");
+        let (_, listed, out) = complete("ls /b");
+        assert!(listed);
+        assert_eq!(out.as_str(), "\nbin/  boot/  \n");
+    }
+
+    #[test]
+    fn test_complete_ignores_relative_paths() {
+        let (line, listed, out) = complete("cat et");
+        assert_eq!(line.as_str(), "cat et");
+        assert!(!listed);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_complete_mid_line() {
+        let mut editor = LineEditor::<32>::new();
+        let mut screen = Screen::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "cat /e x", &mut out);
+        screen.apply(out.as_str());
+        edit(&mut editor, &mut screen, &[press(KeyCode::Left), press(KeyCode::Left)]);
+        out.clear();
+        editor.complete(&mut FakeFs, &mut out);
+        screen.apply(out.as_str());
+        screen.assert_shows(&editor);
+        assert_eq!(editor.line(), "cat /etc/ x");
+        assert_eq!(editor.cursor(), 9);
+    }
+
+    #[test]
+    fn test_complete_respects_capacity() {
+        let mut editor = LineEditor::<4>::new();
+        let mut out = BootBuffer::<256>::new();
+        type_str(&mut editor, "fr", &mut out);
+        editor.complete(&mut FakeFs, &mut out);
+        assert_eq!(editor.line(), "fram");
+    }
+
+    /// Deterministic xorshift64 so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn check_line<const N: usize>(editor: &LineEditor<N>) {
+        let line = editor.line();
+        assert!(line.len() <= N);
+        assert!(line.bytes().all(|b| b == b' ' || b.is_ascii_graphic()));
+        // Any line the editor can produce parses or is rejected cleanly
+        let _ = parse(line);
+    }
+
+    /// Drive the editor with whatever keys `next_key` decodes, keeping a
+    /// screen model in step and checking both after every key
+    fn run_random(mut next_key: impl FnMut() -> Option<KeyEvent>) {
+        let mut editor = LineEditor::<32>::new();
+        let mut screen = Screen::new();
+        let mut out = BootBuffer::<1024>::new();
+        for _ in 0..200_000 {
+            let Some(key) = next_key() else { continue };
+            out.clear();
+            match editor.feed(key, &mut out) {
+                LineEvent::Submit => {
+                    check_line(&editor);
+                    editor.clear();
+                }
+                LineEvent::Complete => {
+                    let listed = editor.complete(&mut FakeFs, &mut out);
+                    if listed {
+                        editor.redraw(&mut out);
+                    }
+                }
+                _ => {}
+            }
+            assert!(!out.is_truncated());
+            screen.apply(out.as_str());
+            screen.assert_shows(&editor);
+            check_line(&editor);
+        }
+    }
+
+    #[test]
+    fn test_random_serial_bytes() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let mut decoder = TerminalDecoder::new();
+        run_random(|| decoder.feed(rng.next() as u8));
+    }
+
+    #[test]
+    fn test_random_scan_codes() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        let mut decoder = Decoder::new();
+        run_random(|| decoder.feed(rng.next() as u8));
+    }
+}
@@ -0,0 +1,18 @@
+//! Shell input handling
+//! The parts of the kernel shell that need no hardware: turning a line into a
+//! `Command`, editing the line as keys arrive, and paging long output. The
+//! kernel supplies the keys and runs the commands.
+
+pub mod help;
+pub mod line;
+pub mod pager;
+pub mod parser;
+pub mod pipeline;
+pub mod script;
+
+pub use help::{CommandHelp, COMMANDS};
+pub use line::{Completer, LineEditor, LineEvent};
+pub use pager::{Pager, PagerKey};
+pub use parser::{parse, Command};
+pub use pipeline::{parse_pipeline, Pipeline, Redirect};
+pub use script::script_lines;
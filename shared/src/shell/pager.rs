@@ -0,0 +1,140 @@
+//! Pager
+//! Shows long output a screenful at a time. `Pager` counts the lines it
+//! passes on and, once a page is full, asks for a key before starting the
+//! next line: Space shows another page, Enter one more line, and q stops,
+//! dropping the rest of the output. Lines are counted by '\n', so a line
+//! wider than the screen counts once.
+
+use crate::keyboard::{KeyCode, KeyEvent};
+
+/// What the reader asked for at a full page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagerKey {
+    NextPage,
+    NextLine,
+    Quit,
+}
+
+impl PagerKey {
+    /// Space or PageDown, Enter or Down, and q, Escape or Ctrl+C; None for
+    /// any other key, which is ignored
+    pub fn from_key(key: &KeyEvent) -> Option<PagerKey> {
+        if !key.pressed {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char(' ') | KeyCode::PageDown => Some(PagerKey::NextPage),
+            KeyCode::Enter | KeyCode::Down => Some(PagerKey::NextLine),
+            KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Escape => Some(PagerKey::Quit),
+            _ => key.ctrl_letter().filter(|&c| c == 'c').map(|_| PagerKey::Quit),
+        }
+    }
+}
+
+pub struct Pager {
+    page_lines: usize,
+    /// Lines that may still start before the next prompt
+    left: usize,
+    at_line_start: bool,
+    quit: bool,
+}
+
+impl Pager {
+    /// `page_lines` lines per page: the screen height, less a row for the prompt
+    pub fn new(page_lines: usize) -> Self {
+        let page_lines = page_lines.max(1);
+        Pager { page_lines, left: page_lines, at_line_start: true, quit: false }
+    }
+
+    /// True once the reader has asked to stop
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Pass `text` on to `show`, calling `wait` for a key before each line
+    /// that would start past a full page
+    /// Text written after the reader quits is dropped.
+    pub fn write(&mut self, text: &str, show: &mut dyn FnMut(&str), wait: &mut dyn FnMut() -> PagerKey) {
+        let mut rest = text;
+        while !rest.is_empty() && !self.quit {
+            if self.at_line_start {
+                if self.left == 0 {
+                    match wait() {
+                        PagerKey::NextPage => self.left = self.page_lines,
+                        PagerKey::NextLine => self.left = 1,
+                        PagerKey::Quit => {
+                            self.quit = true;
+                            return;
+                        }
+                    }
+                }
+                self.left -= 1;
+            }
+
+            let (line, after) = match rest.find('\n') {
+                Some(end) => rest.split_at(end + 1),
+                None => (rest, ""),
+            };
+            show(line);
+            self.at_line_start = line.ends_with('\n');
+            rest = after;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::keyboard::Modifiers;
+    use std::cell::RefCell;
+    use std::string::String;
+
+    /// Feed `writes` through a pager, answering prompts with `keys` in turn;
+    /// returns what was shown, with each prompt marked as '#'
+    fn page(page_lines: usize, writes: &[&str], keys: &[PagerKey]) -> String {
+        let mut pager = Pager::new(page_lines);
+        let shown = RefCell::new(String::new());
+        let mut keys = keys.iter().copied();
+        for text in writes {
+            pager.write(text, &mut |line| shown.borrow_mut().push_str(line), &mut || {
+                shown.borrow_mut().push('#');
+                keys.next().expect("more prompts than keys")
+            });
+        }
+        shown.into_inner()
+    }
+
+    #[test]
+    fn test_short_output_never_waits() {
+        assert_eq!(page(3, &["a\nb\nc\n"], &[]), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_pages_and_lines() {
+        let keys = [PagerKey::NextLine, PagerKey::NextPage];
+        assert_eq!(page(2, &["1\n2\n3\n4\n5\n"], &keys), "1\n2\n#3\n#4\n5\n");
+    }
+
+    #[test]
+    fn test_quit_drops_the_rest() {
+        assert_eq!(page(1, &["1\n2\n", "3\n"], &[PagerKey::Quit]), "1\n#");
+    }
+
+    #[test]
+    fn test_lines_split_across_writes() {
+        // "ab\n" arrives in pieces and counts once; the prompt comes before "c"
+        assert_eq!(page(1, &["a", "b\n", "c\n"], &[PagerKey::NextPage]), "ab\n#c\n");
+    }
+
+    #[test]
+    fn test_keys() {
+        let key = |code| PagerKey::from_key(&KeyEvent::press(code, Modifiers::default()));
+        assert_eq!(key(KeyCode::Char(' ')), Some(PagerKey::NextPage));
+        assert_eq!(key(KeyCode::Enter), Some(PagerKey::NextLine));
+        assert_eq!(key(KeyCode::Char('q')), Some(PagerKey::Quit));
+        assert_eq!(key(KeyCode::Char('x')), None);
+        let ctrl_c = KeyEvent::press(KeyCode::Char('c'), Modifiers { ctrl: true, ..Modifiers::default() });
+        assert_eq!(PagerKey::from_key(&ctrl_c), Some(PagerKey::Quit));
+    }
+}
@@ -0,0 +1,393 @@
+//! Command parser
+//! Parses a shell line into a `Command` borrowing its arguments from the
+//! line. Pure string handling, so it is tested on the host.
+
+/// A parsed shell command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command<'a> {
+    Empty,
+    /// Command to explain, empty to list them all
+    Help(&'a str),
+    Clear,
+    Echo(&'a str),
+    Version,
+    Features,
+    HwInfo,
+    CpuInfo,
+    Date,
+    /// Number of bytes, empty for the default
+    Random(&'a str),
+    MemInfo,
+    MemMap,
+    FrameStats,
+    SlabInfo,
+    Krefs,
+    PageCheck,
+    Ksym(&'a str),
+    IrqStats,
+    IrqStat,
+    /// Repeat delay (ms) and rate (keys per second), empty to show them
+    KbdRate(&'a str, &'a str),
+    /// Layout to switch to, empty to list them
+    SetKeymap(&'a str),
+    Lspci,
+    NetInfo,
+    Ping(&'a str),
+    UdpSend(&'a str, &'a str, &'a str),
+    UdpListen(&'a str),
+    LatStat(&'a str),
+    /// Least important level to show, empty for all
+    Dmesg(&'a str),
+    Ls(&'a str),
+    Cat(&'a str),
+    Write(&'a str, &'a str),
+    Mkdir(&'a str),
+    Rm(&'a str),
+    Sync,
+    Exec(&'a str),
+    Ports,
+    Grep(&'a str),
+    /// Script path, and whether to keep going after a failing line (`-k`)
+    Run(&'a str, bool),
+    Replay(&'a str),
+    Shutdown,
+    Reboot,
+    Halt,
+}
+
+/// Split off the first whitespace-separated word
+/// Returns (word, rest) with the whitespace between them dropped, so the rest
+/// keeps its inner spacing (for free-text arguments).
+pub fn split_word(input: &str) -> (&str, &str) {
+    let input = input.trim_start();
+    match input.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (input, ""),
+    }
+}
+
+pub fn parse(input: &str) -> Result<Command<'_>, &'static str> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return Ok(Command::Empty);
+    }
+
+    let (cmd, args) = split_word(input);
+    // First argument; missing ones are empty and rejected by the command
+    let arg = split_word(args).0;
+
+    match cmd {
+        "help" => Ok(Command::Help(arg)),
+        "clear" => Ok(Command::Clear),
+        "version" => Ok(Command::Version),
+        "features" => Ok(Command::Features),
+        "hwinfo" => Ok(Command::HwInfo),
+        "cpuinfo" => Ok(Command::CpuInfo),
+        "date" => Ok(Command::Date),
+        "random" => Ok(Command::Random(arg)),
+        "shutdown" => Ok(Command::Shutdown),
+        "reboot" => Ok(Command::Reboot),
+        "halt" => Ok(Command::Halt),
+        "meminfo" => Ok(Command::MemInfo),
+        "memmap" => Ok(Command::MemMap),
+        "framestats" => Ok(Command::FrameStats),
+        "slabinfo" => Ok(Command::SlabInfo),
+        "krefs" => Ok(Command::Krefs),
+        "pagecheck" => Ok(Command::PageCheck),
+        "ksym" => Ok(Command::Ksym(arg)),
+        "irqstats" => Ok(Command::IrqStats),
+        "irqstat" => Ok(Command::IrqStat),
+        "kbdrate" => {
+            let (delay, rest) = split_word(args);
+            Ok(Command::KbdRate(delay, split_word(rest).0))
+        }
+        "setkeymap" => Ok(Command::SetKeymap(arg)),
+        "lspci" => Ok(Command::Lspci),
+        "netinfo" => Ok(Command::NetInfo),
+        "ping" => Ok(Command::Ping(arg)),
+        "udpsend" => {
+            // Everything after the port is the payload, spacing preserved
+            let (ip, rest) = split_word(args);
+            let (port, text) = split_word(rest);
+            Ok(Command::UdpSend(ip, port, text))
+        }
+        "udplisten" => Ok(Command::UdpListen(arg)),
+        "latstat" => Ok(Command::LatStat(arg)),
+        "dmesg" => Ok(Command::Dmesg(arg)),
+        "ls" => Ok(Command::Ls(arg)),
+        "cat" => Ok(Command::Cat(arg)),
+        "mkdir" => Ok(Command::Mkdir(arg)),
+        "rm" => Ok(Command::Rm(arg)),
+        "sync" => Ok(Command::Sync),
+        "write" => {
+            // Everything after the path is the file contents, spacing preserved
+            let (path, text) = split_word(args);
+            Ok(Command::Write(path, text))
+        }
+        "exec" => Ok(Command::Exec(arg)),
+        "ports" => Ok(Command::Ports),
+        "echo" => Ok(Command::Echo(args)),
+        // The whole rest of the line is the pattern, spacing preserved
+        "grep" => Ok(Command::Grep(args)),
+        "run" => match split_word(args) {
+            ("-k", rest) => Ok(Command::Run(split_word(rest).0, true)),
+            (path, _) => Ok(Command::Run(path, false)),
+        },
+        "replay" => Ok(Command::Replay(args)),
+        _ => Err("Unknown command. Type 'help' for available commands."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_help() {
+        let result = parse("help");
+        assert!(matches!(result, Ok(Command::Help(""))));
+        assert_eq!(parse("help ls"), Ok(Command::Help("ls")));
+    }
+
+    #[test]
+    fn test_parse_clear() {
+        let result = parse("clear");
+        assert!(matches!(result, Ok(Command::Clear)));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let result = parse("version");
+        assert!(matches!(result, Ok(Command::Version)));
+    }
+
+    #[test]
+    fn test_parse_echo() {
+        let result = parse("echo hello world");
+        if let Ok(Command::Echo(text)) = result {
+            assert_eq!(text, "hello world");
+        } else {
+            panic!("Expected Echo command");
+        }
+    }
+
+    #[test]
+    fn test_parse_features() {
+        assert!(matches!(parse("features"), Ok(Command::Features)));
+    }
+
+    #[test]
+    fn test_parse_hwinfo() {
+        assert_eq!(parse("hwinfo"), Ok(Command::HwInfo));
+    }
+
+    #[test]
+    fn test_parse_cpuinfo() {
+        assert_eq!(parse("cpuinfo"), Ok(Command::CpuInfo));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse("date"), Ok(Command::Date));
+    }
+
+    #[test]
+    fn test_parse_random() {
+        assert_eq!(parse("random"), Ok(Command::Random("")));
+        assert_eq!(parse("random 64"), Ok(Command::Random("64")));
+    }
+
+    #[test]
+    fn test_parse_power() {
+        assert_eq!(parse("shutdown"), Ok(Command::Shutdown));
+        assert_eq!(parse("reboot"), Ok(Command::Reboot));
+        assert_eq!(parse("halt"), Ok(Command::Halt));
+    }
+
+    #[test]
+    fn test_parse_memmap() {
+        assert!(matches!(parse("memmap"), Ok(Command::MemMap)));
+    }
+
+    #[test]
+    fn test_parse_framestats() {
+        assert!(matches!(parse("framestats"), Ok(Command::FrameStats)));
+    }
+
+    #[test]
+    fn test_parse_slabinfo() {
+        assert!(matches!(parse("slabinfo"), Ok(Command::SlabInfo)));
+    }
+
+    #[test]
+    fn test_parse_krefs() {
+        assert!(matches!(parse("krefs"), Ok(Command::Krefs)));
+    }
+
+    #[test]
+    fn test_parse_pagecheck() {
+        assert!(matches!(parse("pagecheck"), Ok(Command::PageCheck)));
+    }
+
+    #[test]
+    fn test_parse_ksym() {
+        assert_eq!(parse("ksym 0xffffffff80001234"), Ok(Command::Ksym("0xffffffff80001234")));
+        assert_eq!(parse("ksym"), Ok(Command::Ksym("")));
+    }
+
+    #[test]
+    fn test_parse_irqstats() {
+        assert!(matches!(parse("irqstats"), Ok(Command::IrqStats)));
+        assert!(matches!(parse("irqstat"), Ok(Command::IrqStat)));
+    }
+
+    #[test]
+    fn test_parse_kbdrate() {
+        assert_eq!(parse("kbdrate"), Ok(Command::KbdRate("", "")));
+        assert_eq!(parse("kbdrate 250 30"), Ok(Command::KbdRate("250", "30")));
+        assert_eq!(parse("setkeymap de"), Ok(Command::SetKeymap("de")));
+    }
+
+    #[test]
+    fn test_parse_lspci() {
+        assert!(matches!(parse("lspci"), Ok(Command::Lspci)));
+    }
+
+    #[test]
+    fn test_parse_netinfo_ping() {
+        assert!(matches!(parse("netinfo"), Ok(Command::NetInfo)));
+        assert!(matches!(parse("ping 10.0.2.2"), Ok(Command::Ping("10.0.2.2"))));
+    }
+
+    #[test]
+    fn test_parse_udpsend() {
+        assert!(matches!(
+            parse("udpsend 10.0.2.2 5555 hello  there"),
+            Ok(Command::UdpSend("10.0.2.2", "5555", "hello  there"))
+        ));
+        assert!(matches!(parse("udpsend 10.0.2.2"), Ok(Command::UdpSend("10.0.2.2", "", ""))));
+    }
+
+    #[test]
+    fn test_parse_udplisten() {
+        assert!(matches!(parse("udplisten 7"), Ok(Command::UdpListen("7"))));
+    }
+
+    #[test]
+    fn test_parse_latstat() {
+        assert!(matches!(parse("latstat"), Ok(Command::LatStat(""))));
+        assert!(matches!(parse("latstat reset"), Ok(Command::LatStat("reset"))));
+    }
+
+    #[test]
+    fn test_parse_dmesg() {
+        assert!(matches!(parse("dmesg"), Ok(Command::Dmesg(""))));
+        assert!(matches!(parse("dmesg warn"), Ok(Command::Dmesg("warn"))));
+    }
+
+    #[test]
+    fn test_parse_ls() {
+        assert!(matches!(parse("ls /etc"), Ok(Command::Ls("/etc"))));
+        assert!(matches!(parse("ls"), Ok(Command::Ls(""))));
+    }
+
+    #[test]
+    fn test_parse_cat() {
+        assert!(matches!(parse("cat /etc/motd"), Ok(Command::Cat("/etc/motd"))));
+    }
+
+    #[test]
+    fn test_parse_write() {
+        assert!(matches!(
+            parse("write /notes hello  world"),
+            Ok(Command::Write("/notes", "hello  world"))
+        ));
+        assert!(matches!(parse("write /empty"), Ok(Command::Write("/empty", ""))));
+    }
+
+    #[test]
+    fn test_parse_mkdir_rm() {
+        assert!(matches!(parse("mkdir /tmp"), Ok(Command::Mkdir("/tmp"))));
+        assert!(matches!(parse("rm /tmp"), Ok(Command::Rm("/tmp"))));
+    }
+
+    #[test]
+    fn test_parse_sync() {
+        assert!(matches!(parse("sync"), Ok(Command::Sync)));
+    }
+
+    #[test]
+    fn test_parse_exec() {
+        assert!(matches!(parse("exec hello.bin"), Ok(Command::Exec("hello.bin"))));
+        assert!(matches!(parse("exec"), Ok(Command::Exec(""))));
+    }
+
+    #[test]
+    fn test_parse_ports() {
+        assert!(matches!(parse("ports"), Ok(Command::Ports)));
+    }
+
+    #[test]
+    fn test_parse_grep() {
+        assert_eq!(parse("grep frames"), Ok(Command::Grep("frames")));
+        assert_eq!(parse("grep Used  frames"), Ok(Command::Grep("Used  frames")));
+    }
+
+    #[test]
+    fn test_parse_run() {
+        assert_eq!(parse("run /etc/rc"), Ok(Command::Run("/etc/rc", false)));
+        assert_eq!(parse("run -k /etc/rc"), Ok(Command::Run("/etc/rc", true)));
+        assert_eq!(parse("run"), Ok(Command::Run("", false)));
+    }
+
+    #[test]
+    fn test_parse_replay() {
+        assert_eq!(parse("replay /disk/tests/ls.keys"), Ok(Command::Replay("/disk/tests/ls.keys")));
+        assert_eq!(parse("replay"), Ok(Command::Replay("")));
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let result = parse("");
+        assert!(matches!(result, Ok(Command::Empty)));
+    }
+
+    #[test]
+    fn test_parse_whitespace() {
+        let result = parse("   ");
+        assert!(matches!(result, Ok(Command::Empty)));
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        let result = parse("unknown");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_split_word() {
+        assert_eq!(split_word("  one  two three"), ("one", "two three"));
+        assert_eq!(split_word("one"), ("one", ""));
+        assert_eq!(split_word("one\ttwo"), ("one", "two"));
+        assert_eq!(split_word(""), ("", ""));
+    }
+
+    #[test]
+    fn test_parse_echo_preserves_spacing() {
+        assert_eq!(parse("echo  a   b "), Ok(Command::Echo("a   b")));
+        assert_eq!(parse("echo"), Ok(Command::Echo("")));
+    }
+
+    #[test]
+    fn test_parse_command_needs_whole_word() {
+        assert!(parse("helpme").is_err());
+        assert!(parse("echohello").is_err());
+    }
+
+    #[test]
+    fn test_parse_with_extra_whitespace() {
+        let result = parse("  help  ");
+        assert!(matches!(result, Ok(Command::Help(""))));
+    }
+}
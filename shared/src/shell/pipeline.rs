@@ -0,0 +1,116 @@
+//! Pipelines and redirection
+//! Splits a line like `meminfo | grep Used > /tmp/used` into its commands and
+//! an optional output file. Every stage is parsed before anything runs, so a
+//! typo in the last stage doesn't leave the first one half done.
+
+use super::parser::{parse, split_word, Command};
+
+/// Most commands joined with '|' in one line
+pub const MAX_STAGES: usize = 4;
+
+/// Where the last stage's output goes instead of the console
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redirect<'a> {
+    pub path: &'a str,
+    /// `>>` appends; `>` replaces the file
+    pub append: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Pipeline<'a> {
+    stages: [Command<'a>; MAX_STAGES],
+    len: usize,
+    pub redirect: Option<Redirect<'a>>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// The commands in order; each one's output is the next one's input
+    pub fn stages(&self) -> &[Command<'a>] {
+        &self.stages[..self.len]
+    }
+}
+
+/// Parse a full shell line
+/// A line without '|' or '>' gives a single stage, exactly as `parse` would.
+pub fn parse_pipeline(input: &str) -> Result<Pipeline<'_>, &'static str> {
+    let (commands, redirect) = match input.split_once('>') {
+        Some((commands, target)) => {
+            let (append, target) = match target.strip_prefix('>') {
+                Some(target) => (true, target),
+                None => (false, target),
+            };
+            let (path, rest) = split_word(target);
+            if path.is_empty() || path.starts_with('>') {
+                return Err("Missing file name after '>'");
+            }
+            if !rest.is_empty() {
+                return Err("Only one file name may follow '>'");
+            }
+            (commands, Some(Redirect { path, append }))
+        }
+        None => (input, None),
+    };
+
+    let mut pipeline = Pipeline { stages: [Command::Empty; MAX_STAGES], len: 0, redirect };
+    let piped = commands.contains('|') || redirect.is_some();
+    for stage in commands.split('|') {
+        let command = parse(stage)?;
+        if piped && command == Command::Empty {
+            return Err("Missing command in pipeline");
+        }
+        if pipeline.len == MAX_STAGES {
+            return Err("Too many commands in pipeline");
+        }
+        pipeline.stages[pipeline.len] = command;
+        pipeline.len += 1;
+    }
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_command() {
+        let pipeline = parse_pipeline("echo hi").unwrap();
+        assert_eq!(pipeline.stages(), &[Command::Echo("hi")]);
+        assert_eq!(pipeline.redirect, None);
+        assert_eq!(parse_pipeline("").unwrap().stages(), &[Command::Empty]);
+    }
+
+    #[test]
+    fn test_pipe() {
+        let pipeline = parse_pipeline("meminfo | grep Used  KB|grep 4").unwrap();
+        assert_eq!(
+            pipeline.stages(),
+            &[Command::MemInfo, Command::Grep("Used  KB"), Command::Grep("4")]
+        );
+    }
+
+    #[test]
+    fn test_redirect() {
+        let pipeline = parse_pipeline("echo a b > /tmp/out").unwrap();
+        assert_eq!(pipeline.stages(), &[Command::Echo("a b")]);
+        assert_eq!(pipeline.redirect, Some(Redirect { path: "/tmp/out", append: false }));
+
+        let pipeline = parse_pipeline("ls / | grep bin >>/log").unwrap();
+        assert_eq!(pipeline.stages().len(), 2);
+        assert_eq!(pipeline.redirect, Some(Redirect { path: "/log", append: true }));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_pipeline("echo hi >").is_err());
+        assert!(parse_pipeline("echo hi > a b").is_err());
+        assert!(parse_pipeline("echo hi > a > b").is_err());
+        assert!(parse_pipeline("echo hi >>> a").is_err());
+        assert!(parse_pipeline("echo hi >>>a").is_err());
+        assert!(parse_pipeline("| grep x").is_err());
+        assert!(parse_pipeline("meminfo |").is_err());
+        assert!(parse_pipeline("> /out").is_err());
+        assert!(parse_pipeline("meminfo | bogus").is_err());
+        assert!(parse_pipeline("help|help|help|help|help").is_err());
+        assert!(parse_pipeline("help|help|help|help").is_ok());
+    }
+}
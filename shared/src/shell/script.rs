@@ -0,0 +1,29 @@
+//! Shell scripts
+//! A script is a text file of shell lines run in order. Blank lines and lines
+//! starting with '#' are skipped; '#' anywhere else is ordinary text, so
+//! `echo #1` still prints it.
+
+/// The lines of `text` worth running, with their 1-based line numbers
+pub fn script_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skips_comments_and_blank_lines() {
+        let text = "# boot script\n\nmkdir /tmp\n   # indented comment\n  echo #1  \r\n\t\nls /tmp";
+        assert!(script_lines(text).eq([(3, "mkdir /tmp"), (5, "echo #1"), (7, "ls /tmp")]));
+    }
+
+    #[test]
+    fn test_empty_script() {
+        assert_eq!(script_lines("").count(), 0);
+        assert_eq!(script_lines("#!/bin/wflos\n\n").count(), 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! File-backed and anonymous memory areas
+//! A program segment is a run of memory whose first `file_size` bytes come
+//! from a file and the rest read as zero. When pages are filled on first
+//! touch, `FileArea` says which bytes of the file land in a given page, and
+//! `Readahead` how many pages to fill at once.
+//! Memory a program asks for at run time (its heap, `mmap`) has no file
+//! behind it: `AreaMap` keeps those areas in a `RangeMap` ordered by address,
+//! merging neighbours with the same protection and splitting areas that are
+//! partly unmapped.
+
+use crate::data_structures::range_map::RangeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileArea {
+    /// Virtual address range, `start` inclusive and `end` exclusive
+    pub start: u64,
+    pub end: u64,
+    /// Where the area's bytes begin in the file, and how many there are
+    pub file_offset: u64,
+    pub file_size: u64,
+}
+
+impl FileArea {
+    /// True if any byte of the page at `page` lies in the area
+    pub fn overlaps_page(&self, page: u64, page_size: u64) -> bool {
+        self.start < page + page_size && page < self.end
+    }
+
+    /// The file bytes that belong in the page at `page`, as (offset in the
+    /// page, offset in the file, length); `None` if the area leaves the page
+    /// all zeros
+    pub fn page_source(&self, page: u64, page_size: u64) -> Option<(usize, u64, usize)> {
+        let from = page.max(self.start);
+        let to = (page + page_size).min(self.start + self.file_size);
+        if from >= to {
+            return None;
+        }
+        Some(((from - page) as usize, self.file_offset + (from - self.start), (to - from) as usize))
+    }
+}
+
+/// Readahead window for one file
+/// A fault on the page right after the previous batch doubles the window,
+/// up to `max` pages; a fault anywhere else drops it back to one page.
+#[derive(Debug, Clone, Copy)]
+pub struct Readahead {
+    next: u64,
+    window: u64,
+    max: u64,
+}
+
+impl Readahead {
+    pub const fn new(max: u64) -> Self {
+        Readahead { next: u64::MAX, window: 1, max }
+    }
+
+    /// Pages to fill starting at page number `page`, which just faulted
+    pub fn on_fault(&mut self, page: u64) -> u64 {
+        self.window = if page == self.next { (self.window * 2).min(self.max) } else { 1 };
+        self.next = page + self.window;
+        self.window
+    }
+}
+
+/// Most anonymous areas one program can have
+pub const MAX_AREAS: usize = 32;
+
+/// Addresses `start..end` a program asked for, with protection bits the
+/// caller interprets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub start: u64,
+    pub end: u64,
+    pub prot: u64,
+}
+
+impl Area {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaError {
+    /// Part of the range is already in use
+    Overlap,
+    /// No room for another area
+    TableFull,
+}
+
+#[derive(Debug, Clone)]
+pub struct AreaMap {
+    /// Protection of each area, keyed by its range
+    areas: RangeMap<u64>,
+}
+
+impl AreaMap {
+    pub const fn new() -> Self {
+        AreaMap { areas: RangeMap::new() }
+    }
+
+    /// Every area in address order
+    pub fn areas(&self) -> impl Iterator<Item = Area> + '_ {
+        self.areas.iter().map(|(range, &prot)| Area { start: range.start, end: range.end, prot })
+    }
+
+    /// The area holding `addr`
+    pub fn find(&self, addr: u64) -> Option<Area> {
+        self.areas.get(addr).map(|(range, &prot)| Area { start: range.start, end: range.end, prot })
+    }
+
+    /// True if no area overlaps `start..end`
+    pub fn is_free(&self, start: u64, end: u64) -> bool {
+        self.areas.is_free(start..end)
+    }
+
+    /// Lowest `start` in `from..to` with `start..start + len` free
+    pub fn find_free(&self, len: u64, from: u64, to: u64) -> Option<u64> {
+        self.areas.find_free(len, from..to)
+    }
+
+    /// Add `area`, which must not overlap any other, joining it to
+    /// neighbours it touches that have the same protection
+    pub fn insert(&mut self, area: Area) -> Result<(), AreaError> {
+        if area.start >= area.end || !self.is_free(area.start, area.end) {
+            return Err(AreaError::Overlap);
+        }
+        let before = area.start.checked_sub(1).and_then(|last| self.find(last)).filter(|before| before.prot == area.prot);
+        let after = self.find(area.end).filter(|after| after.prot == area.prot);
+        if before.is_none() && after.is_none() && self.areas.len() == MAX_AREAS {
+            return Err(AreaError::TableFull);
+        }
+        let start = before.map_or(area.start, |before| before.start);
+        let end = after.map_or(area.end, |after| after.end);
+        self.areas.remove(start..end);
+        self.areas.insert(start..end, area.prot).map_err(|_| AreaError::Overlap)
+    }
+
+    /// Take `start..end` out of every area, shrinking or splitting those it
+    /// covers part of; fails only if a split would need one area too many
+    pub fn remove(&mut self, start: u64, end: u64) -> Result<(), AreaError> {
+        // Only an area reaching past both ends of the range is split
+        let splits = self.find(start).is_some_and(|area| area.start < start && end < area.end);
+        if splits && self.areas.len() == MAX_AREAS {
+            return Err(AreaError::TableFull);
+        }
+        self.areas.remove(start..end);
+        Ok(())
+    }
+}
+
+impl Default for AreaMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::vec::Vec;
+
+    const PAGE: u64 = 4096;
+
+    // 0x1800 bytes of file data starting mid-page, then BSS to 0x405000
+    const AREA: FileArea = FileArea { start: 0x400800, end: 0x405000, file_offset: 0x2800, file_size: 0x1800 };
+
+    #[test]
+    fn test_page_source() {
+        // First page: starts mid-page
+        assert_eq!(AREA.page_source(0x400000, PAGE), Some((0x800, 0x2800, 0x800)));
+        // A whole page of file data
+        assert_eq!(AREA.page_source(0x401000, PAGE), Some((0, 0x3000, 0x1000)));
+        // File data ends exactly at the page boundary: the rest is BSS
+        assert_eq!(AREA.page_source(0x402000, PAGE), None);
+        assert_eq!(AREA.page_source(0x404000, PAGE), None);
+    }
+
+    #[test]
+    fn test_partial_last_page() {
+        let area = FileArea { file_size: 0x1810, ..AREA };
+        assert_eq!(area.page_source(0x402000, PAGE), Some((0, 0x4000, 0x10)));
+    }
+
+    #[test]
+    fn test_overlaps_page() {
+        assert!(AREA.overlaps_page(0x400000, PAGE));
+        assert!(AREA.overlaps_page(0x404000, PAGE));
+        assert!(!AREA.overlaps_page(0x3ff000, PAGE));
+        assert!(!AREA.overlaps_page(0x405000, PAGE));
+    }
+
+    #[test]
+    fn test_readahead_grows_on_sequential_faults() {
+        let mut readahead = Readahead::new(8);
+        assert_eq!(readahead.on_fault(10), 1);
+        assert_eq!(readahead.on_fault(11), 2);
+        assert_eq!(readahead.on_fault(13), 4);
+        assert_eq!(readahead.on_fault(17), 8);
+        assert_eq!(readahead.on_fault(25), 8);
+        // A jump elsewhere starts over
+        assert_eq!(readahead.on_fault(3), 1);
+        assert_eq!(readahead.on_fault(4), 2);
+    }
+
+    fn area(start: u64, end: u64, prot: u64) -> Area {
+        Area { start, end, prot }
+    }
+
+    #[test]
+    fn test_area_map_joins_neighbours() {
+        let mut map = AreaMap::new();
+        map.insert(area(0x3000, 0x4000, 3)).unwrap();
+        map.insert(area(0x1000, 0x2000, 3)).unwrap();
+        assert_eq!(map.insert(area(0x1800, 0x3800, 3)), Err(AreaError::Overlap));
+        // Filling the gap joins all three; a different protection stays apart
+        map.insert(area(0x2000, 0x3000, 3)).unwrap();
+        map.insert(area(0x4000, 0x5000, 1)).unwrap();
+        assert_eq!(map.areas().collect::<Vec<_>>(), [area(0x1000, 0x4000, 3), area(0x4000, 0x5000, 1)]);
+        assert_eq!(map.find(0x3fff), Some(area(0x1000, 0x4000, 3)));
+        assert_eq!(map.find(0x5000), None);
+    }
+
+    #[test]
+    fn test_area_map_remove_splits() {
+        let mut map = AreaMap::new();
+        map.insert(area(0x1000, 0x9000, 3)).unwrap();
+        map.insert(area(0xa000, 0xc000, 1)).unwrap();
+        map.remove(0x3000, 0x5000).unwrap();
+        map.remove(0x8000, 0xb000).unwrap();
+        assert_eq!(map.areas().collect::<Vec<_>>(), [area(0x1000, 0x3000, 3), area(0x5000, 0x8000, 3), area(0xb000, 0xc000, 1)]);
+
+        // A split with every slot taken has nowhere to go
+        let mut full = AreaMap::new();
+        for i in 0..MAX_AREAS as u64 {
+            full.insert(area(i * 0x2000, i * 0x2000 + 0x1000, 3)).unwrap();
+        }
+        assert_eq!(full.insert(area(0x100000, 0x101000, 3)), Err(AreaError::TableFull));
+        assert_eq!(full.remove(0x400, 0x800), Err(AreaError::TableFull));
+        assert_eq!(full.areas().count(), MAX_AREAS);
+        full.remove(0, 0x1000).unwrap();
+        assert_eq!(full.areas().count(), MAX_AREAS - 1);
+    }
+
+    #[test]
+    fn test_area_map_find_free() {
+        let mut map = AreaMap::new();
+        map.insert(area(0x2000, 0x3000, 3)).unwrap();
+        map.insert(area(0x5000, 0x6000, 3)).unwrap();
+        assert_eq!(map.find_free(0x1000, 0x1000, 0x10000), Some(0x1000));
+        assert_eq!(map.find_free(0x2000, 0x1000, 0x10000), Some(0x3000));
+        assert_eq!(map.find_free(0x3000, 0x1000, 0x10000), Some(0x6000));
+        assert_eq!(map.find_free(0x3000, 0x1000, 0x8000), None);
+        assert_eq!(map.find_free(u64::MAX, 0x1000, u64::MAX), None);
+    }
+}
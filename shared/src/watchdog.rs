@@ -0,0 +1,83 @@
+//! Stall detection for a watchdog CPU
+//! The watching CPU samples the watched one's tick count from a timer of its
+//! own, whose rate nobody has measured. `StallDetector` learns that rate from
+//! the samples (ticks gone by per sample, up to the last time the count
+//! moved) and reports a stall once the count has stood still for as many
+//! samples as the timeout spans. It reads no clock and takes no lock, so it
+//! keeps working however the watched CPU is stuck.
+
+/// Ticks to see go by before trusting the sample rate
+const CALIBRATION_TICKS: u64 = 100;
+
+pub struct StallDetector {
+    timeout_ticks: u64,
+    /// Tick count at the first sample
+    start: Option<u64>,
+    samples: u64,
+    /// Tick count when it last moved, and the samples taken until then
+    last: u64,
+    samples_to_last: u64,
+    /// Samples in a row that saw `last`
+    unchanged: u64,
+}
+
+impl StallDetector {
+    pub const fn new(timeout_ticks: u64) -> Self {
+        StallDetector { timeout_ticks, start: None, samples: 0, last: 0, samples_to_last: 0, unchanged: 0 }
+    }
+
+    /// Record the latest tick count; true once it has not moved for the timeout
+    pub fn sample(&mut self, ticks: u64) -> bool {
+        self.samples += 1;
+        let start = *self.start.get_or_insert(ticks);
+        if ticks != self.last {
+            self.last = ticks;
+            self.samples_to_last = self.samples;
+            self.unchanged = 0;
+            return false;
+        }
+        self.unchanged += 1;
+        // unchanged / samples_to_last * elapsed is how many ticks should have
+        // gone by since the last move
+        let elapsed = self.last - start;
+        elapsed >= CALIBRATION_TICKS && self.unchanged * elapsed >= self.timeout_ticks * self.samples_to_last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_reported_after_timeout() {
+        let mut detector = StallDetector::new(1000);
+        // Two ticks per sample: the timeout spans 500 samples
+        for sample in 1..=1000 {
+            assert!(!detector.sample(2 * sample));
+        }
+        let stalled = (1..=1000).position(|_| detector.sample(2000)).map(|n| n + 1);
+        assert_eq!(stalled, Some(501));
+    }
+
+    #[test]
+    fn test_no_stall_before_ticks_start() {
+        let mut detector = StallDetector::new(1000);
+        // A tick that never started is not a hang: nothing to calibrate from
+        assert!((0..100_000).all(|_| !detector.sample(0)));
+        // Once it runs, the samples before it only make the watchdog slower
+        assert!((1..=100).all(|sample| !detector.sample(10 * sample)));
+    }
+
+    #[test]
+    fn test_stretched_idle_tick_is_not_a_stall() {
+        let mut detector = StallDetector::new(1000);
+        // Idle: the tick comes once a second, covering 100 ticks, while the
+        // watcher samples 10 times a second
+        for second in 1..=600 {
+            assert!(!detector.sample(100 * second));
+            for _ in 0..9 {
+                assert!(!detector.sample(100 * second));
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+; hello.asm - first wflos userspace program (flat binary)
+; Loaded at 0x400000 by the kernel's process loader; entry is the first byte.
+; Build: make user (nasm -f bin -i user/ user/hello.asm -o user/hello.bin)
+
+%include "abi.inc"
+
+bits 64
+org USER_CODE_BASE
+
+start:
+    mov rax, SYS_WRITE
+    mov rdi, STDOUT
+    lea rsi, [rel message]
+    mov rdx, message_len
+    syscall
+
+    mov rax, SYS_EXIT
+    xor rdi, rdi                ; exit code 0
+    syscall
+
+message:    db "Hello from ring 3!", 10
+message_len equ $ - message